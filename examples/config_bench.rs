@@ -0,0 +1,65 @@
+//! Times parsing a synthetic config with thousands of targets, the size generated configs
+//! reach. Run with `cargo run --release --example config_bench`, the parsed config has to come
+//! back the same from what `write_config` writes of it.
+
+use std::time::{Duration, Instant};
+
+use tran::config::{parse_config_contents, write_config_to_string};
+
+const TARGETS: usize = 3000;
+const ROWS: usize = 200;
+const RUNS: u32 = 50;
+
+fn fixture() -> String {
+    let mut contents = String::from("[mode]\nmap\n[overwrite]\ntrue\n[colors]\n");
+    for row in 0..ROWS {
+        if row % 7 == 0 {
+            contents.push('3');
+        }
+        for slot in 0..16 {
+            let value = (row * 16 + slot) * 2654435761 % 0xffffff;
+            contents.push_str(&format!("#{:06x}", value));
+        }
+        contents.push('\n');
+    }
+    contents.push_str("[current_color]\n@0\n[target_files]\n");
+    for target in 0..TARGETS {
+        contents.push_str(&format!(
+            "/home/user/.config/theme/generated/part-{:04}/colors.conf",
+            target
+        ));
+        if target % 5 == 0 {
+            contents.push_str("|format=text|literals=true");
+        }
+        contents.push('\n');
+    }
+    contents
+}
+
+fn main() {
+    let contents = fixture();
+
+    // The fastest run, the others mostly measure what else the machine was doing
+    let mut fastest = Duration::MAX;
+    let mut config = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let parsed = parse_config_contents(&contents).unwrap();
+        fastest = fastest.min(start.elapsed());
+        // Dropped outside the timing, freeing every target is the allocator's business
+        config = Some(parsed);
+    }
+    let config = config.unwrap();
+    println!(
+        "parse:   {:?} for a config of {} bytes",
+        fastest,
+        contents.len()
+    );
+
+    let written = write_config_to_string(config.clone());
+    assert!(
+        parse_config_contents(&written).unwrap() == config,
+        "the written config parses differently"
+    );
+    println!("targets: {}", config.get_targets().len());
+}
@@ -0,0 +1,87 @@
+//! Times recoloring a synthetic 4K RGBA image against the per-entry scan the palette path used
+//! to do. Run with `cargo run --release --example pixel_bench [--features parallel]`, the
+//! checksum must be the same either way.
+
+use std::time::Instant;
+
+use tran::{
+    config::Color,
+    pixel::{pixel_map, recolor_pixels},
+    ColorMap,
+};
+
+const WIDTH: usize = 3840;
+const HEIGHT: usize = 2160;
+const COLORS: usize = 64;
+
+fn palette() -> Vec<Color> {
+    (0..COLORS)
+        .map(|i| Color::from_bytes((i * 4) as u8, (255 - i * 3) as u8, (i * 7 % 256) as u8))
+        .collect()
+}
+
+fn fixture(palette: &[Color]) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * 4);
+    for i in 0..WIDTH * HEIGHT {
+        let (red, green, blue) = palette[(i / 97) % palette.len()].bytes();
+        pixels.extend_from_slice(&[red, green, blue, 255]);
+    }
+    pixels
+}
+
+fn naive(pixels: &mut [u8], map: &[ColorMap]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for entry in map {
+            let old = Color::try_from_hex_str(entry.get_current_color())
+                .unwrap()
+                .bytes();
+            if (pixel[0], pixel[1], pixel[2]) == old {
+                let new = Color::try_from_hex_str(entry.get_new_color())
+                    .unwrap()
+                    .bytes();
+                (pixel[0], pixel[1], pixel[2]) = new;
+                break;
+            }
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn main() {
+    let palette = palette();
+    let old: Vec<String> = palette.iter().take(32).map(|c| c.to_string()).collect();
+    let new: Vec<String> = palette
+        .iter()
+        .rev()
+        .take(32)
+        .map(|c| c.to_string())
+        .collect();
+    let map: Vec<ColorMap> = new
+        .iter()
+        .zip(&old)
+        .map(|(new, old)| ColorMap::new(new, old))
+        .collect();
+
+    let mut expected = fixture(&palette);
+    let start = Instant::now();
+    naive(&mut expected, &map);
+    println!("per-entry scan: {:?}", start.elapsed());
+
+    let mut pixels = fixture(&palette);
+    let start = Instant::now();
+    let lookup = pixel_map(&map).unwrap();
+    let changes = recolor_pixels(&mut pixels, 4, &lookup);
+    println!(
+        "lookup:         {:?} ({} pixels changed)",
+        start.elapsed(),
+        changes
+    );
+
+    assert!(pixels == expected, "lookup and per-entry scan disagree");
+    println!("checksum:       {:016x}", checksum(&pixels));
+}
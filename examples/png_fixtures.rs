@@ -0,0 +1,65 @@
+//! Writes a set of small pngs into the given directory, for trying tran out or checking them
+//! with other decoders: `cargo run --example png_fixtures --features testing -- <dir>`
+
+use std::path::PathBuf;
+
+use tran::png::build::PngBuilder;
+
+const NORD: [(u8, u8, u8); 5] = [
+    (0x00, 0x00, 0x00),
+    (0xff, 0xff, 0xff),
+    (0x2e, 0x34, 0x40),
+    (0x88, 0xc0, 0xd0),
+    (0x4c, 0x56, 0x6a),
+];
+
+fn main() -> std::io::Result<()> {
+    let dir = PathBuf::from(std::env::args().nth(1).unwrap_or_else(|| ".".to_string()));
+    std::fs::create_dir_all(&dir)?;
+
+    let indices: Vec<Vec<u8>> = (0..16)
+        .map(|y| (0..16).map(|x| ((x / 4 + y / 4) % 5) as u8).collect())
+        .collect();
+    let rgb: Vec<Vec<(u8, u8, u8)>> = indices
+        .iter()
+        .map(|row| row.iter().map(|i| NORD[*i as usize]).collect())
+        .collect();
+    let rgba: Vec<Vec<(u8, u8, u8, u8)>> = rgb
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .map(|&(r, g, b)| (r, g, b, if y < 4 { 0 } else { 255 }))
+                .collect()
+        })
+        .collect();
+
+    let fixtures = [
+        ("palette.png", PngBuilder::palette(&NORD, &indices)),
+        (
+            "palette_stored.png",
+            PngBuilder::palette(&NORD, &indices).stored(),
+        ),
+        (
+            "palette_split.png",
+            PngBuilder::palette(&NORD, &indices).idat_chunks(3),
+        ),
+        (
+            "palette_trns.png",
+            PngBuilder::palette(&NORD, &indices).transparency(&[0]),
+        ),
+        (
+            "palette_interlaced.png",
+            PngBuilder::palette(&NORD, &indices).interlaced(),
+        ),
+        ("rgb.png", PngBuilder::rgb(&rgb)),
+        ("rgb_interlaced.png", PngBuilder::rgb(&rgb).interlaced()),
+        ("rgb_split.png", PngBuilder::rgb(&rgb).idat_chunks(3)),
+        ("rgba.png", PngBuilder::rgba(&rgba)),
+    ];
+    for (name, builder) in fixtures {
+        std::fs::write(dir.join(name), builder.build())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+/* C interface to tran, built with `cargo build --release --features ffi`. tests/ffi.rs checks
+ * it against the Rust side with `cargo test --features ffi`. */
+#ifndef TRAN_H
+#define TRAN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* One code per tran error, see tran_last_error_message() for the details. */
+typedef enum {
+    TRAN_OK = 0,
+    TRAN_CONFIG_ERROR = 1,
+    TRAN_FILE_READ_ERROR = 2,
+    TRAN_FILE_WRITE_ERROR = 3,
+    TRAN_FILE_NOT_FOUND_ERROR = 4,
+    TRAN_WRITING_CONFIG_ERROR = 5,
+    TRAN_INVALID_HEX_COLOR = 6,
+    TRAN_PNG_FORMAT_ERROR = 7,
+    TRAN_UNSUPPORTED_ERROR = 8,
+    TRAN_OUTSIDE_ALLOWED_ROOTS = 9,
+    /* A null pointer or a string that isn't UTF-8. */
+    TRAN_INVALID_ARGUMENT = 10,
+    /* tran panicked, which is a bug. */
+    TRAN_PANIC = 11,
+    TRAN_OUTPUT_EXISTS = 12,
+    TRAN_EXEC_ERROR = 13,
+    TRAN_MISSING_SECTION = 14,
+    TRAN_INCONSISTENT_MODE = 15,
+    TRAN_CANCELLED = 16,
+} tran_error_code;
+
+/*
+ * Ownership: everything passed in stays the caller's and is only read during the call.
+ * Buffers written to `out` belong to the caller, who releases them with tran_free().
+ */
+
+/* Recolors the png of `len` bytes at `data`, taking from_hex[i] to to_hex[i] for i < n.
+ * On TRAN_OK the new png is in *out and its length in *out_len. */
+tran_error_code tran_recolor_png_bytes(const uint8_t *data, size_t len,
+                                       const char *const *from_hex, const char *const *to_hex,
+                                       size_t n, uint8_t **out, size_t *out_len);
+
+/* Recolors the NUL terminated `text` the same way. On TRAN_OK *out is a NUL terminated
+ * string and *out_len its length without the NUL. */
+tran_error_code tran_recolor_text(const char *text,
+                                  const char *const *from_hex, const char *const *to_hex,
+                                  size_t n, char **out, size_t *out_len);
+
+/* The message of the last error on this thread, or NULL. Valid until the next failing call
+ * on the same thread, don't free it. */
+const char *tran_last_error_message(void);
+
+/* Frees a buffer from `out`, NULL is ignored. */
+void tran_free(void *ptr);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,94 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{errors::TranError, json::Json, state::State};
+
+const CACHE_FILE: &str = "targets.json";
+
+/// FNV-1a, only used to notice that a file changed.
+pub fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub hash: u64,
+    /// The colors the file was left in, as written by `Selection`'s Display.
+    pub colors: String,
+}
+
+/// Remembers which colors each target was last left in, so a run can skip targets that are
+/// already consistent. An entry only counts while the file still hashes the same.
+#[derive(Debug, Default)]
+pub struct Cache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+impl Cache {
+    /// Loads the cache of `state`, a missing or unreadable cache is treated as empty.
+    pub fn open(state: &State) -> Self {
+        let path = state.get_cache_dir().join(CACHE_FILE);
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| Json::parse(&contents).ok())
+            .and_then(|json| {
+                json.as_array()?
+                    .iter()
+                    .map(|entry| {
+                        Some((
+                            PathBuf::from(entry.get("path")?.as_str()?),
+                            CacheEntry {
+                                hash: u64::from_str_radix(entry.get("hash")?.as_str()?, 16).ok()?,
+                                colors: entry.get("colors")?.as_str()?.to_string(),
+                            },
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Cache { path, entries }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&CacheEntry> {
+        self.entries.get(path)
+    }
+
+    /// Whether `path` with contents `data` was already left in `colors`.
+    pub fn is_current(&self, path: &Path, data: &[u8], colors: &str) -> bool {
+        self.get(path)
+            .is_some_and(|entry| entry.colors == colors && entry.hash == content_hash(data))
+    }
+
+    pub fn insert(&mut self, path: PathBuf, entry: CacheEntry) {
+        self.entries.insert(path, entry);
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    pub fn save(&self) -> Result<(), TranError> {
+        if let Some(dir) = self.path.parent() {
+            crate::perms::create_dir_all(dir).map_err(|e| TranError::write(dir, e))?;
+        }
+        let mut paths: Vec<&PathBuf> = self.entries.keys().collect();
+        paths.sort();
+        let entries: Vec<Json> = paths
+            .into_iter()
+            .map(|path| {
+                let entry = &self.entries[path];
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("hash", format!("{:016x}", entry.hash))
+                    .with("colors", entry.colors.as_str())
+            })
+            .collect();
+        crate::perms::write(&self.path, Json::from(entries).to_string())
+            .map_err(|e| TranError::write(&self.path, e))
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::Config,
+    recolor::{read_head, resolve_output, DEFAULT_OUTPUT_NAME},
+    run::{target_options, with_transform},
+    RecolorOptions,
+};
+
+/// Whether `name` is `pattern` with every `{date}` standing for a `YYYY-MM-DD` date.
+fn matches_name(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split("{date}");
+    let Some(mut rest) = parts.next().and_then(|first| name.strip_prefix(first)) else {
+        return false;
+    };
+    for part in parts {
+        let Some(date) = rest.get(..10) else {
+            return false;
+        };
+        let is_date = date.bytes().enumerate().all(|(i, b)| match i {
+            4 | 7 => b == b'-',
+            _ => b.is_ascii_digit(),
+        });
+        let Some(after) = rest[10..].strip_prefix(part) else {
+            return false;
+        };
+        if !is_date {
+            return false;
+        }
+        rest = after;
+    }
+    rest.is_empty()
+}
+
+/// Files next to `output`'s name that a run on another day could have written.
+fn dated_files(output: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(pattern)) = (output.parent(), output.file_name().and_then(|n| n.to_str()))
+    else {
+        return Vec::new();
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| matches_name(pattern, name))
+        })
+        .map(|entry| dir.join(entry.file_name()))
+        .collect()
+}
+
+/// Every existing file a run in non-overwrite mode could have written for one of the configured
+/// colors, found by expanding the output name exactly. Targets themselves are never included.
+pub fn generated_files(config: &Config, options: &RecolorOptions) -> Vec<PathBuf> {
+    let template = config.get_output_name().unwrap_or(DEFAULT_OUTPUT_NAME);
+    let targets: Vec<(PathBuf, RecolorOptions)> = config
+        .get_targets()
+        .iter()
+        .map(|target| target_options(target, options))
+        .collect();
+
+    let mut found = BTreeSet::new();
+    let current = config.get_current_colors();
+    for (index, selection) in config.get_selections().iter().enumerate() {
+        with_transform(
+            selection,
+            &current,
+            config.get_gradient_space(),
+            config.get_luminance_band(),
+            config.get_stops(),
+            |transform| {
+                for (source, options) in targets.iter() {
+                    let Ok(head) = read_head(source) else {
+                        continue;
+                    };
+                    let options = RecolorOptions {
+                        overwrite: false,
+                        output_name: template.replace("{index}", &index.to_string()),
+                        ..options.clone()
+                    };
+                    let (_, output) = resolve_output(source, &head, &options, transform);
+                    if output == *source {
+                        continue;
+                    }
+                    if options.output_name.contains("{date}") {
+                        found.extend(dated_files(&output));
+                    } else if output.is_file() {
+                        found.insert(output);
+                    }
+                }
+            },
+        );
+    }
+
+    for (source, _) in targets.iter() {
+        found.remove(source);
+    }
+    found.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config_contents, test_util::TempDir};
+
+    const PNG_HEAD: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    fn config(dir: &TempDir, extra: &str) -> Config {
+        parse_config_contents(&format!(
+            "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n#bf616a\n{}[target_files]\n{}\n",
+            extra,
+            dir.path().join("icon.png").display()
+        ))
+        .unwrap()
+    }
+
+    fn names(files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn only_names_the_template_gives_are_found() {
+        let dir = TempDir::new("clean");
+        for name in [
+            "icon.png",
+            "icon_#88c0d0.png",
+            "icon_#bf616a.png",
+            "icon_#88c0d1.png",
+            "icon_#2e3440.png",
+            "icon_#88c0d0.png.bak",
+            "xicon_#bf616a.png",
+            "icon_#BF616A.png",
+            "icon_#88c0d0.PNG",
+        ] {
+            dir.write(name, PNG_HEAD);
+        }
+        let found = generated_files(&config(&dir, ""), &RecolorOptions::default());
+        assert_eq!(names(found), ["icon_#88c0d0.png", "icon_#bf616a.png"]);
+    }
+
+    #[test]
+    fn templates_fill_the_index_and_any_date() {
+        let dir = TempDir::new("clean-date");
+        for name in [
+            "icon.png",
+            "icon.0.2026-10-14.png",
+            "icon.1.1999-01-01.png",
+            "icon.2.2026-10-14.png",
+            "icon.0.2026-1x-14.png",
+            "icon.0.2026-10-145.png",
+            "icon.0..png",
+        ] {
+            dir.write(name, PNG_HEAD);
+        }
+        let config = config(&dir, "[output_name]\n{stem}.{index}.{date}.{ext}\n");
+        let found = generated_files(&config, &RecolorOptions::default());
+        assert_eq!(
+            names(found),
+            ["icon.0.2026-10-14.png", "icon.1.1999-01-01.png"]
+        );
+    }
+
+    #[test]
+    fn targets_are_never_found() {
+        let dir = TempDir::new("clean-target");
+        dir.write("icon.png", PNG_HEAD);
+        let config = config(&dir, "[output_name]\n{stem}.{ext}\n");
+        assert!(generated_files(&config, &RecolorOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn dates_are_matched_exactly() {
+        assert!(matches_name("a_{date}.png", "a_2026-10-14.png"));
+        assert!(matches_name("{date}{date}", "2026-10-142026-10-15"));
+        assert!(!matches_name("a_{date}.png", "a_2026-10-14.png.bak"));
+        assert!(!matches_name("a_{date}.png", "a_2026/10/14.png"));
+        assert!(!matches_name("a_{date}.png", "a_.png"));
+        assert!(!matches_name("a_{date}.png", "b_2026-10-14.png"));
+    }
+}
@@ -1,23 +1,48 @@
-use std::{io::Write, path::Path};
+use std::io::Write;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::path::PathBuf;
 
-use crate::errors::TranError;
+use crate::{
+    constraints::Constraints,
+    errors::{HexError, TranError},
+    exec_handler::{ExecCommand, DEFAULT_TIMEOUT},
+    ini::IniOptions,
+    pixel::LuminanceBand,
+    recolor::{CollisionPolicy, FileFormat, OutputDir, RecolorOptions},
+    schedule::Schedule,
+    selection::{pick_weighted, RngLike, Selection},
+    space::GradientSpace,
+    text::TextOptions,
+};
 
-#[derive(PartialEq)]
-enum ParseState {
-    Start,
-    BraceOpen,
-    BraceClosed,
-    NewLine,
-    Text,
-}
-
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Section {
     Mode,
     CurrentColor,
     Colors,
     TargetFiles,
     Overwrite,
+    OutputName,
+    Transition,
+    Contrast,
+    GradientSpace,
+    AllowedRoots,
+    OutputDir,
+    Collision,
+    RecencyPenalty,
+    Journal,
+    Schedule,
+    GradientOptions,
+    Stops,
+    HexCase,
+    TextOptions,
+    DiscoverOptions,
+    Constraints,
+    SequencesFile,
+    DefaultTags,
+    SyncMode,
+    Handlers,
 }
 
 impl TryFrom<&str> for Section {
@@ -30,16 +55,128 @@ impl TryFrom<&str> for Section {
             "target_files" => Ok(Self::TargetFiles),
             "current_color" => Ok(Self::CurrentColor),
             "overwrite" => Ok(Self::Overwrite),
-            _ => Err(TranError::ConfigError(format!("Unrecognized section'{}', valid sections are 'mode', 'current_color', 'colors', and 'target_files'", value)))
+            "output_name" => Ok(Self::OutputName),
+            "transition" => Ok(Self::Transition),
+            "contrast" => Ok(Self::Contrast),
+            "gradient_space" => Ok(Self::GradientSpace),
+            "allowed_roots" => Ok(Self::AllowedRoots),
+            "output_dir" => Ok(Self::OutputDir),
+            "collision" => Ok(Self::Collision),
+            "recency_penalty" => Ok(Self::RecencyPenalty),
+            "journal" => Ok(Self::Journal),
+            "schedule" => Ok(Self::Schedule),
+            "gradient_options" => Ok(Self::GradientOptions),
+            "stops" => Ok(Self::Stops),
+            "hex_case" => Ok(Self::HexCase),
+            "text_options" => Ok(Self::TextOptions),
+            "discover_options" => Ok(Self::DiscoverOptions),
+            "constraints" => Ok(Self::Constraints),
+            "sequences_file" => Ok(Self::SequencesFile),
+            "default_tags" => Ok(Self::DefaultTags),
+            "sync_mode" => Ok(Self::SyncMode),
+            "handlers" => Ok(Self::Handlers),
+            _ => Err(TranError::ConfigError(format!("Unrecognized section '{}', valid sections are 'mode', 'current_color', 'colors', 'target_files', 'overwrite', 'output_name', 'transition', 'contrast', 'gradient_space', 'allowed_roots', 'output_dir', 'collision', 'recency_penalty', 'journal', 'schedule', 'gradient_options', 'stops', 'hex_case', 'text_options', 'discover_options', 'constraints', 'sequences_file', 'default_tags', 'sync_mode' and 'handlers'", value)))
+        }
+    }
+}
+
+impl Section {
+    /// Whether the section is a list an overlay can add to, see `Config::merge`.
+    pub fn is_list(&self) -> bool {
+        matches!(
+            self,
+            Section::Colors
+                | Section::TargetFiles
+                | Section::AllowedRoots
+                | Section::Schedule
+                | Section::Stops
+        )
+    }
+}
+
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Section::Mode => "mode",
+            Section::CurrentColor => "current_color",
+            Section::Colors => "colors",
+            Section::TargetFiles => "target_files",
+            Section::Overwrite => "overwrite",
+            Section::OutputName => "output_name",
+            Section::Transition => "transition",
+            Section::Contrast => "contrast",
+            Section::GradientSpace => "gradient_space",
+            Section::AllowedRoots => "allowed_roots",
+            Section::OutputDir => "output_dir",
+            Section::Collision => "collision",
+            Section::RecencyPenalty => "recency_penalty",
+            Section::Journal => "journal",
+            Section::Schedule => "schedule",
+            Section::GradientOptions => "gradient_options",
+            Section::Stops => "stops",
+            Section::HexCase => "hex_case",
+            Section::TextOptions => "text_options",
+            Section::DiscoverOptions => "discover_options",
+            Section::Constraints => "constraints",
+            Section::SequencesFile => "sequences_file",
+            Section::DefaultTags => "default_tags",
+            Section::SyncMode => "sync_mode",
+            Section::Handlers => "handlers",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How a run tells which colors a target is in, `[sync_mode]` in the config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SyncMode {
+    /// Every target is taken to be in `current_color`.
+    #[default]
+    Assume,
+    /// Every target is scanned and taken from the configured colors it holds the most of,
+    /// see `verify::classify`, so files edited by hand or left behind catch up.
+    Detect,
+}
+
+impl TryFrom<&str> for SyncMode {
+    type Error = TranError;
+
+    fn try_from(value: &str) -> Result<Self, TranError> {
+        match value {
+            "assume" => Ok(SyncMode::Assume),
+            "detect" => Ok(SyncMode::Detect),
+            _ => Err(TranError::ConfigError(format!(
+                "Unrecognized sync mode '{}', valid modes are 'assume' and 'detect'",
+                value
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncMode::Assume => write!(f, "assume"),
+            SyncMode::Detect => write!(f, "detect"),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Mode {
     Gradient,
     Map,
 }
 
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Gradient => write!(f, "gradient"),
+            Mode::Map => write!(f, "map"),
+        }
+    }
+}
+
 impl TryFrom<&str> for Mode {
     type Error = TranError;
 
@@ -62,10 +199,10 @@ enum ColorOrMap {
 
 enum ColorOrMapVec {
     Color(Vec<Color>),
-    Map(Vec<Vec<Color>>),
+    Map(Vec<Vec<Option<Color>>>),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Color {
     red: u8,
     green: u8,
@@ -96,32 +233,214 @@ impl Color {
         (self.red, self.green, self.blue)
     }
 
-    pub fn try_from_hex_str<S: AsRef<str>>(s: S) -> Result<Self, TranError> {
-        let s = s.as_ref();
-        let (r, g, b) = if s.len() == 6 {
-            // No preceding #
-            (s.get(0..2), s.get(2..4), s.get(4..6))
-        } else if s.len() == 7 {
-            // Preceding #
-            (s.get(1..3), s.get(3..5), s.get(5..7))
-        } else {
-            return Err(TranError::ConfigError(format!(
-                "Could not interpret {} as hex color",
-                s
-            )));
+    /// `steps` colors evenly spaced from `self` to `to`, ending on `to` and leaving out `self`.
+    pub fn ramp(&self, to: &Color, steps: usize) -> Vec<Color> {
+        self.ramp_in(to, steps, GradientSpace::Srgb)
+    }
+
+    /// `self` with its hue turned by `degrees` around the HSL color wheel, saturation and
+    /// lightness kept.
+    pub fn rotate_hue(&self, degrees: f64) -> Color {
+        let [hue, saturation, lightness] = crate::space::to_hsl(self);
+        crate::space::from_hsl([(hue + degrees).rem_euclid(360.0), saturation, lightness])
+    }
+
+    /// The color across the wheel, cyan for red.
+    pub fn complementary(&self) -> Color {
+        self.rotate_hue(180.0)
+    }
+
+    /// The two colors a third of the wheel away on either side.
+    pub fn triadic(&self) -> [Color; 2] {
+        [self.rotate_hue(120.0), self.rotate_hue(240.0)]
+    }
+
+    /// `count` neighbours `spread` degrees apart on both sides of `self`, closest first and
+    /// the one turned back before the one turned forward. An odd count ends on the forward side.
+    pub fn analogous(&self, count: usize, spread: f64) -> Vec<Color> {
+        (0..count)
+            .map(|i| {
+                let steps = (i / 2 + 1) as f64;
+                match i % 2 {
+                    0 => self.rotate_hue(-steps * spread),
+                    _ => self.rotate_hue(steps * spread),
+                }
+            })
+            .collect()
+    }
+
+    /// Like `ramp`, but spaced evenly in `space`.
+    pub fn ramp_in(&self, to: &Color, steps: usize, space: GradientSpace) -> Vec<Color> {
+        if space != GradientSpace::Srgb {
+            return (1..=steps)
+                .map(|step| space.mix(self, to, step as f64 / steps as f64))
+                .collect();
+        }
+        let channel = |from: u8, to: u8, step: usize| {
+            let offset = (to as f64 - from as f64) * step as f64 / steps as f64;
+            (from as f64 + offset).round() as u8
         };
+        (1..=steps)
+            .map(|step| {
+                Color::from_bytes(
+                    channel(self.red, to.red, step),
+                    channel(self.green, to.green, step),
+                    channel(self.blue, to.blue, step),
+                )
+            })
+            .collect()
+    }
 
-        if let (Some(r), Some(g), Some(b)) = (r, g, b) {
-            Ok(Color::from_bytes(
-                u8::from_str_radix(r, 16)?,
-                u8::from_str_radix(g, 16)?,
-                u8::from_str_radix(b, 16)?,
-            ))
+    /// `#2e3440`, the same as `to_string`.
+    pub fn to_hex_lower(&self) -> String {
+        self.to_string()
+    }
+
+    /// `#2E3440`.
+    pub fn to_hex_upper(&self) -> String {
+        format!("#{:02X}{:02X}{:02X}", self.red, self.green, self.blue)
+    }
+
+    pub fn to_hex(&self, case: HexCase) -> String {
+        match case {
+            HexCase::Lower => self.to_hex_lower(),
+            HexCase::Upper => self.to_hex_upper(),
+        }
+    }
+
+    /// Sum of the per-channel differences, 0 only for the same color.
+    pub fn distance(&self, other: &Color) -> u32 {
+        self.red.abs_diff(other.red) as u32
+            + self.green.abs_diff(other.green) as u32
+            + self.blue.abs_diff(other.blue) as u32
+    }
+
+    /// WCAG relative luminance, from 0 for black to 1 for white, with the channels linearized
+    /// from sRGB first.
+    pub fn relative_luminance(&self) -> f64 {
+        let linear = |channel: u8| {
+            let c = channel as f64 / 255.0;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.red) + 0.7152 * linear(self.green) + 0.0722 * linear(self.blue)
+    }
+
+    /// WCAG contrast ratio between the two colors, from 1 for the same luminance to 21 for
+    /// black on white.
+    pub fn contrast_ratio(&self, other: &Color) -> f64 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// CIE76 color difference, the distance between the two in CIELAB. About 2.3 is the least
+    /// difference the eye notices.
+    pub fn delta_e(&self, other: &Color) -> f64 {
+        let (a, b) = (crate::space::to_lab(self), crate::space::to_lab(other));
+        (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f64>().sqrt()
+    }
+
+    /// Whether text on the color reads better in white than in black, which is the case below a
+    /// relative luminance of about 0.179, where both contrast equally.
+    pub fn is_dark(&self) -> bool {
+        self.contrast_ratio(&Color::white()) > self.contrast_ratio(&Color::black())
+    }
+
+    /// The color closest to `self` on the way to black or white that has at least `ratio`
+    /// contrast against `background`. When no color can reach `ratio` this is whichever of
+    /// black and white contrasts more.
+    pub fn with_contrast(&self, background: &Color, ratio: f64) -> Color {
+        if self.contrast_ratio(background) >= ratio {
+            return *self;
+        }
+        // Lean the way the color already does, unless only the other end can get there
+        let lighter = self.relative_luminance() >= background.relative_luminance();
+        let (near, far) = match lighter {
+            true => (Color::white(), Color::black()),
+            false => (Color::black(), Color::white()),
+        };
+        let end = if near.contrast_ratio(background) >= ratio
+            || near.contrast_ratio(background) >= far.contrast_ratio(background)
+        {
+            near
         } else {
-            Err(TranError::ConfigError(format!(
-                "Something went wrong while parsing {}",
+            far
+        };
+        if end.contrast_ratio(background) < ratio {
+            return end;
+        }
+
+        // Contrast only grows once past the background on the way to `end`, so the colors that
+        // pass form one run ending at `end` and 8 halvings pin its start
+        let ramp = self.ramp(&end, 255);
+        let (mut failing, mut passing) = (0, ramp.len() - 1);
+        while passing - failing > 1 {
+            let middle = (failing + passing) / 2;
+            if ramp[middle].contrast_ratio(background) >= ratio {
+                passing = middle;
+            } else {
+                failing = middle;
+            }
+        }
+        match ramp[0].contrast_ratio(background) >= ratio {
+            true => ramp[0],
+            false => ramp[passing],
+        }
+    }
+
+    /// Reads six hex digits, or three doubled as in `#abc`, after an optional `#` or `0x`.
+    /// Whitespace around the color is ignored.
+    pub fn try_from_hex_str<S: AsRef<str>>(s: S) -> Result<Self, TranError> {
+        let s = s.as_ref().trim();
+        let invalid = |reason| TranError::InvalidHexColor {
+            input: s.to_string(),
+            reason,
+        };
+        let digits = ["#", "0x", "0X"]
+            .iter()
+            .find_map(|prefix| s.strip_prefix(prefix))
+            .unwrap_or(s);
+        // Every byte before the first that isn't a hex digit is ASCII, so it starts a character
+        if let Some(i) = digits.bytes().position(|b| !b.is_ascii_hexdigit()) {
+            let c = digits[i..].chars().next().unwrap_or_default();
+            return Err(invalid(HexError::InvalidDigit(c)));
+        }
+        let short = match digits.len() {
+            6 => false,
+            3 => true,
+            0 => return Err(invalid(HexError::Empty)),
+            len => return Err(invalid(HexError::Length(len))),
+        };
+        // Every digit was checked above
+        let nibble = |i: usize| (digits.as_bytes()[i] as char).to_digit(16).unwrap_or(0) as u8;
+        let channel = |i: usize| match short {
+            true => nibble(i) * 17,
+            false => nibble(2 * i) * 16 + nibble(2 * i + 1),
+        };
+        Ok(Color::from_bytes(channel(0), channel(1), channel(2)))
+    }
+
+    /// Reads decimal channels as in `46,52,64` or `rgb(46, 52, 64)`.
+    pub fn try_from_triplet_str<S: AsRef<str>>(s: S) -> Result<Self, TranError> {
+        let s = s.as_ref();
+        let inner = s
+            .trim()
+            .strip_prefix("rgb(")
+            .and_then(|s| s.strip_suffix(')'))
+            .unwrap_or(s);
+        let channels = inner
+            .split(',')
+            .map(|c| c.trim().parse())
+            .collect::<Result<Vec<u8>, _>>();
+        match channels.as_deref() {
+            Ok([r, g, b]) => Ok(Color::from_bytes(*r, *g, *b)),
+            _ => Err(TranError::ConfigError(format!(
+                "Expected three numbers from 0 to 255 in '{}'",
                 s
-            )))
+            ))),
         }
     }
 }
@@ -132,6 +451,38 @@ impl std::fmt::Display for Color {
     }
 }
 
+/// Whether the hex colors tran writes use `a` to `f` or `A` to `F`, from `[hex_case]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HexCase {
+    #[default]
+    Lower,
+    Upper,
+}
+
+impl TryFrom<&str> for HexCase {
+    type Error = TranError;
+
+    fn try_from(value: &str) -> Result<Self, TranError> {
+        match value {
+            "lower" => Ok(HexCase::Lower),
+            "upper" => Ok(HexCase::Upper),
+            _ => Err(TranError::ConfigError(format!(
+                "Unrecognized hex case '{}', valid cases are 'lower' and 'upper'",
+                value
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for HexCase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HexCase::Lower => write!(f, "lower"),
+            HexCase::Upper => write!(f, "upper"),
+        }
+    }
+}
+
 impl TryFrom<&str> for Color {
     type Error = TranError;
 
@@ -146,486 +497,3882 @@ impl From<Color> for String {
     }
 }
 
-#[derive(Debug)]
-pub enum Config {
-    GradientConfig(GradientConfig),
-    MapConfig(MapConfig),
+/// Fading from one selection to the next in `steps` runs spread over `duration` seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub duration: u64,
+    pub steps: usize,
+    /// Only recolor png targets on the last step.
+    pub skip_png: bool,
 }
 
-impl Config {
-    pub fn get_target_files(&self) -> &[String] {
-        match self {
-            Config::GradientConfig(gc) => gc.get_target_files(),
-            Config::MapConfig(mc) => mc.get_target_files(),
+impl Default for Transition {
+    fn default() -> Self {
+        Transition {
+            duration: 60,
+            steps: 10,
+            skip_png: false,
         }
     }
+}
 
-    pub fn get_mode(&self) -> &str {
-        match self {
-            Config::GradientConfig(_) => "gradient",
-            Config::MapConfig(_) => "map",
+impl Transition {
+    fn push_line(&mut self, line: &str) -> Result<(), TranError> {
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            TranError::ConfigError(format!(
+                "Expected key=value in transition, found '{}'",
+                line
+            ))
+        })?;
+        let value = value.trim();
+        match key.trim() {
+            "duration" => self.duration = value.parse()?,
+            "steps" => {
+                self.steps = value.parse()?;
+                if self.steps == 0 {
+                    return Err(TranError::ConfigError(
+                        "A transition needs at least one step".to_string(),
+                    ));
+                }
+            }
+            "skip_png" => {
+                self.skip_png = value.parse().map_err(|_| {
+                    TranError::ConfigError(format!(
+                        "Expected true or false for skip_png, found '{}'",
+                        value
+                    ))
+                })?
+            }
+            key => {
+                return Err(TranError::ConfigError(format!(
+                    "Unrecognized transition setting '{}', valid settings are 'duration', 'steps' and 'skip_png'",
+                    key
+                )))
+            }
         }
+        Ok(())
+    }
+
+    /// Time to wait between two steps.
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.duration) / self.steps.max(1) as u32
     }
 }
 
-#[derive(Debug)]
-pub struct GradientConfig {
-    current_color: Color,
-    colors: Vec<Color>,
-    weights: Vec<usize>,
-    target_files: Vec<String>,
-    overwrite: bool,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContrastStrategy {
+    /// Pick again, up to `attempts` times, then adjust the last pick.
+    Repick,
+    /// Lighten or darken the pick until it contrasts enough.
+    Adjust,
 }
 
-impl GradientConfig {
-    pub fn set_current_colors(&mut self, color: Color) {
-        self.current_color = color
-    }
+/// A minimum contrast every gradient pick has to keep against `background`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contrast {
+    pub background: Color,
+    pub ratio: f64,
+    pub strategy: ContrastStrategy,
+    pub attempts: usize,
+}
 
-    pub fn get_current_color(&self) -> &Color {
-        &self.current_color
-    }
+// Parsing only accepts finite ratios
+impl Eq for Contrast {}
 
-    pub fn get_colors(&self) -> &[Color] {
-        &self.colors
+impl Contrast {
+    fn new(background: Color) -> Self {
+        Contrast {
+            background,
+            ratio: 4.5,
+            strategy: ContrastStrategy::Adjust,
+            attempts: 10,
+        }
     }
 
-    pub fn get_colors_scaled(&self) -> Vec<Color> {
-        let mut output = Vec::new();
-
-        for (i, color) in self.get_colors().iter().enumerate() {
-            if color == self.get_current_color() {
-                continue;
+    fn push_line(&mut self, line: &str) -> Result<(), TranError> {
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            TranError::ConfigError(format!("Expected key=value in contrast, found '{}'", line))
+        })?;
+        let value = value.trim();
+        match key.trim() {
+            "background" => self.background = Color::try_from_hex_str(value)?,
+            "ratio" => {
+                self.ratio = value
+                    .parse()
+                    .ok()
+                    .filter(|ratio: &f64| (1.0..=21.0).contains(ratio))
+                    .ok_or_else(|| {
+                        TranError::ConfigError(format!(
+                            "Expected a contrast ratio between 1 and 21, found '{}'",
+                            value
+                        ))
+                    })?
+            }
+            "strategy" => {
+                self.strategy = match value {
+                    "repick" => ContrastStrategy::Repick,
+                    "adjust" => ContrastStrategy::Adjust,
+                    _ => {
+                        return Err(TranError::ConfigError(format!(
+                            "Unrecognized contrast strategy '{}', valid strategies are 'repick' and 'adjust'",
+                            value
+                        )))
+                    }
+                }
             }
+            "attempts" => self.attempts = value.parse()?,
+            key => {
+                return Err(TranError::ConfigError(format!(
+                    "Unrecognized contrast setting '{}', valid settings are 'background', 'ratio', 'strategy' and 'attempts'",
+                    key
+                )))
+            }
+        }
+        Ok(())
+    }
+}
 
-            let w = match self.weights.get(i) {
-                Some(w) => *w,
-                None => 1,
-            };
+/// A line of `[stops]`, the color at `position` of a multi-stop gradient. `None` is the color
+/// the run picked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stop {
+    pub position: f64,
+    pub color: Option<Color>,
+}
 
-            for _ in 0..w {
-                output.push(*color)
-            }
+// Parsing only accepts finite positions
+impl Eq for Stop {}
+
+impl Stop {
+    /// The stop as `[stops]` reads it, its color written in `case`.
+    pub fn to_string_in(&self, case: HexCase) -> String {
+        match self.color {
+            Some(color) => format!("{}:{}", self.position, color.to_hex(case)),
+            None => format!("{}:primary", self.position),
         }
+    }
+}
 
-        output
+impl std::fmt::Display for Stop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_in(HexCase::default()))
     }
+}
 
-    pub fn get_target_files(&self) -> &[String] {
-        &self.target_files
+/// Reads `0.5:#2e3440`, `#2e3440` or `primary` with or without a position.
+fn parse_stop(line: &str) -> Result<(Option<f64>, Option<Color>), TranError> {
+    let (position, color) = match line.split_once(':') {
+        Some((position, color)) => {
+            let position = position.trim();
+            let parsed = position
+                .parse::<f64>()
+                .ok()
+                .filter(|p| (0.0..=1.0).contains(p))
+                .ok_or_else(|| {
+                    TranError::ConfigError(format!(
+                        "Expected a stop position from 0.0 to 1.0, found '{}'",
+                        position
+                    ))
+                })?;
+            (Some(parsed), color.trim())
+        }
+        None => (None, line.trim()),
+    };
+    match color {
+        "primary" => Ok((position, None)),
+        color => Ok((position, Some(Color::try_from_hex_str(color)?))),
     }
+}
 
-    pub fn get_overwrite(&self) -> bool {
-        self.overwrite
+/// Gives the stops without a position one, the first and last defaulting to 0 and 1 and those
+/// in between spread evenly between the stops around them. Positions have to rise.
+fn place_stops(stops: &[(Option<f64>, Option<Color>)]) -> Result<Vec<Stop>, TranError> {
+    let last = stops.len().saturating_sub(1);
+    let mut known: Vec<(usize, f64)> = stops
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (position, _))| position.map(|p| (i, p)))
+        .collect();
+    if known.first().is_none_or(|(i, _)| *i != 0) {
+        known.insert(0, (0, 0.0));
+    }
+    if known.last().is_none_or(|(i, _)| *i != last) {
+        known.push((last, 1.0));
+    }
+    for pair in known.windows(2) {
+        if pair[1].1 < pair[0].1 {
+            return Err(TranError::ConfigError(format!(
+                "Stop positions have to rise, but stop {} at {} comes after {}",
+                pair[1].0 + 1,
+                pair[1].1,
+                pair[0].1
+            )));
+        }
+    }
+    let mut placed = Vec::with_capacity(stops.len());
+    for (i, (_, color)) in stops.iter().enumerate() {
+        let after = known.partition_point(|(k, _)| *k < i);
+        let position = match known.get(after) {
+            Some((k, p)) if *k == i => *p,
+            Some((k, p)) => {
+                let (j, q) = known[after - 1];
+                q + (p - q) * (i - j) as f64 / (k - j) as f64
+            }
+            None => 1.0,
+        };
+        placed.push(Stop {
+            position,
+            color: *color,
+        });
     }
+    Ok(placed)
 }
 
-#[derive(Debug)]
-pub struct MapConfig {
-    current_color: Vec<Color>,
-    colors: Vec<Vec<Color>>,
-    weights: Vec<usize>,
-    target_files: Vec<String>,
-    overwrite: bool,
+/// How directories are walked looking for targets, from `[discover_options]`. The state
+/// directory, backups included, is skipped whatever these say.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Traversal {
+    /// Also walks directories whose name starts with a dot. Hidden files are always looked at.
+    pub hidden: bool,
+    /// Leaves out what the `.gitignore` and `.ignore` files along the way ignore.
+    pub respect_gitignore: bool,
+    /// Also takes files ending in `.bak`, `.orig` or `~`, when discovering as when running.
+    pub backups: bool,
 }
 
-impl MapConfig {
-    pub fn set_current_colors(&mut self, color: Vec<Color>) {
-        self.current_color = color
+impl Traversal {
+    fn push_line(&mut self, line: &str) -> Result<(), TranError> {
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            TranError::ConfigError(format!(
+                "Expected key=value in discover_options, found '{}'",
+                line
+            ))
+        })?;
+        let (key, value) = (key.trim(), value.trim());
+        let value = value.parse().map_err(|_| {
+            TranError::ConfigError(format!(
+                "Expected true or false for {}, found '{}'",
+                key, value
+            ))
+        })?;
+        match key {
+            "hidden" => self.hidden = value,
+            "respect_gitignore" => self.respect_gitignore = value,
+            "backups" => self.backups = value,
+            _ => {
+                return Err(TranError::ConfigError(format!(
+                    "Unrecognized discover_options setting '{}', valid settings are 'hidden', 'respect_gitignore' and 'backups'",
+                    key
+                )))
+            }
+        }
+        Ok(())
     }
+}
 
-    pub fn get_current_colors(&self) -> &[Color] {
-        &self.current_color
-    }
+/// Makes colors picked in the last `runs` runs less likely, scaling their weight by `factor`
+/// once for every time they were picked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecencyPenalty {
+    pub runs: usize,
+    pub factor: f32,
+}
 
-    pub fn get_colors(&self) -> &[Vec<Color>] {
-        &self.colors
-    }
+// Parsing only accepts finite factors
+impl Eq for RecencyPenalty {}
 
-    pub fn get_colors_scaled(&self) -> Vec<&Vec<Color>> {
-        let mut output = Vec::new();
+// Penalized weights are fractions of the configured ones, so all weights are scaled up first
+const PENALTY_RESOLUTION: f32 = 1000.0;
 
-        for (i, color) in self.get_colors().iter().enumerate() {
-            let w = match self.weights.get(i) {
-                Some(w) => *w,
-                None => 1,
-            };
-            for _ in 0..w {
-                output.push(color)
+impl RecencyPenalty {
+    fn push_line(&mut self, line: &str) -> Result<(), TranError> {
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            TranError::ConfigError(format!(
+                "Expected key=value in recency_penalty, found '{}'",
+                line
+            ))
+        })?;
+        let value = value.trim();
+        match key.trim() {
+            "runs" => self.runs = value.parse()?,
+            "factor" => {
+                self.factor = value
+                    .parse()
+                    .ok()
+                    .filter(|factor: &f32| (0.0..=1.0).contains(factor))
+                    .ok_or_else(|| {
+                        TranError::ConfigError(format!(
+                            "Expected a recency penalty factor between 0 and 1, found '{}'",
+                            value
+                        ))
+                    })?
+            }
+            key => {
+                return Err(TranError::ConfigError(format!(
+                "Unrecognized recency_penalty setting '{}', valid settings are 'runs' and 'factor'",
+                key
+            )))
             }
         }
-
-        output
+        Ok(())
     }
 
-    pub fn get_target_files(&self) -> &[String] {
-        &self.target_files
+    /// `weight` of `colors` after the penalty for every time they are in `recent`. A color
+    /// with any weight keeps some, so it is never ruled out.
+    pub fn weight(&self, weight: usize, colors: &[Color], recent: &[Vec<Color>]) -> usize {
+        if weight == 0 {
+            return 0;
+        }
+        let times = recent.iter().filter(|r| r.as_slice() == colors).count();
+        let scaled = weight as f32 * PENALTY_RESOLUTION * self.factor.powi(times as i32);
+        (scaled.round() as usize).max(1)
     }
+}
 
-    pub fn get_overwrite(&self) -> bool {
-        self.overwrite
+impl Default for RecencyPenalty {
+    fn default() -> Self {
+        RecencyPenalty {
+            runs: 5,
+            factor: 0.5,
+        }
     }
 }
 
-const BUFF_SIZE: usize = 50;
+/// What a gradient pick went through to meet the `[contrast]` section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContrastFix {
+    /// These picks lacked contrast before one had enough.
+    Repicked(Vec<Color>),
+    /// The pick was lightened or darkened to the new color.
+    Adjusted { picked: Color, adjusted: Color },
+}
 
-pub fn parse_config<T: AsRef<Path>>(target: T) -> Result<Config, TranError> {
-    let contents = std::fs::read_to_string(target)?;
-    let chars = contents.trim().chars();
-    let mut state = ParseState::Start;
-    let mut section = Section::Mode;
-    let mut buff = String::with_capacity(BUFF_SIZE);
-
-    let mut mode: Option<Mode> = None;
-    let mut current_color: ColorOrMap = ColorOrMap::Color(Color::black());
-    let mut colors: Option<ColorOrMapVec> = None;
-    let mut target_files: Vec<String> = Vec::new();
-    let mut overwrite: bool = false;
-    let mut weights: Vec<usize> = Vec::new();
-
-    for char in chars {
-        match state {
-            ParseState::Start => {
-                if char != '[' {
-                    return Err(TranError::ConfigError(format!(
-                        "Expected config to start with '[', found {}",
-                        char
-                    )));
-                }
-                state = ParseState::BraceOpen;
-            }
-            ParseState::BraceOpen => {
-                if char == ']' {
-                    section = buff.as_str().try_into()?;
-                    buff.clear();
-                    state = ParseState::BraceClosed;
-                } else {
-                    buff.push(char);
+impl std::fmt::Display for ContrastFix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContrastFix::Repicked(rejected) => {
+                write!(f, "Picked again, too little contrast with")?;
+                for color in rejected {
+                    write!(f, " {}", color)?;
                 }
+                Ok(())
             }
-            ParseState::BraceClosed => {
-                if char != '\n' {
-                    return Err(TranError::ConfigError(format!(
-                        "Expected newline after section declaration, found {}",
-                        char
-                    )));
-                } else {
-                    state = ParseState::NewLine;
-                }
+            ContrastFix::Adjusted { picked, adjusted } => {
+                write!(f, "Adjusted {} to {} for enough contrast", picked, adjusted)
             }
-            ParseState::Text => {
-                if char == '\n' {
-                    // Add contents from buff to propper storage
-                    match section {
-                        Section::Mode => {
-                            mode = Some(buff.as_str().try_into()?);
-                            buff.clear();
-                        }
-                        Section::Colors => {
-                            if let Some(m) = &mode {
-                                match m {
-                                    Mode::Gradient => match &mut colors {
-                                        Some(c) => {
-                                            if let ColorOrMapVec::Color(v) = c {
-                                                let mut entire = buff.split('#');
-
-                                                weights.push(
-                                                    entire
-                                                        .next()
-                                                        .and_then(|c| {
-                                                            c.parse().ok()
-                                                        })
-                                                        .unwrap_or(1),
-                                                );
-                                                v.push(Color::try_from_hex_str(
-                                                    entire.next().ok_or_else(|| {
-                                                        TranError::ConfigError(
-                                                            "Failed to parse color value"
-                                                                .to_string(),
-                                                        )
-                                                    })?,
-                                                )?);
-                                                buff.clear();
-                                            } else {
-                                                return Err(TranError::ConfigError(
-                                                    "Inconsistent state".to_string(),
-                                                ));
-                                            }
-                                        }
-                                        None => {
-                                            let mut entire = buff.split('#');
-
-                                            weights.push(
-                                                entire
-                                                    .next()
-                                                    .and_then(|c| c.parse().ok())
-                                                    .unwrap_or(1),
-                                            );
-                                            colors = Some(ColorOrMapVec::Color(vec![
-                                                Color::try_from_hex_str(
-                                                    entire.next().ok_or_else(|| {
-                                                        TranError::ConfigError(
-                                                            "Failed to parse color value"
-                                                                .to_string(),
-                                                        )
-                                                    })?,
-                                                )?,
-                                            ]));
-                                            buff.clear();
-                                        }
-                                    },
-                                    Mode::Map => {
-                                        let mut entire = buff.split('#');
-                                        weights.push(
-                                            entire
-                                                .next()
-                                                .and_then(|c| c.parse().ok())
-                                                .unwrap_or(1),
-                                        );
-                                        let color_map = entire
-                                            .map(Color::try_from_hex_str)
-                                            .collect::<Result<Vec<Color>, TranError>>(
-                                        )?;
-                                        match &mut colors {
-                                            Some(c) => {
-                                                if let ColorOrMapVec::Map(v) = c {
-                                                    v.push(color_map);
-                                                } else {
-                                                    return Err(TranError::ConfigError(
-                                                        "Inconsistent state".to_string(),
-                                                    ));
-                                                }
-                                            }
-                                            None => {
-                                                colors = Some(ColorOrMapVec::Map(vec![color_map]));
-                                            }
-                                        }
-                                        buff.clear();
-                                    }
-                                }
-                            } else {
-                                return Err(TranError::ConfigError("Found color section before mode section. Can't determine color format".to_string()));
-                            }
-                        }
-                        Section::CurrentColor => {
-                            if let Some(m) = &mode {
-                                match m {
-                                    Mode::Gradient => {
-                                        current_color =
-                                            ColorOrMap::Color(Color::try_from_hex_str(&buff)?);
-                                        buff.clear();
-                                    }
-                                    Mode::Map => {
-                                        current_color = ColorOrMap::Map(
-                                            buff.split('#')
-                                                .map(Color::try_from_hex_str)
-                                                .collect::<Result<Vec<Color>, TranError>>()?,
-                                        );
-                                    }
-                                }
-                            } else {
-                                return Err(TranError::ConfigError("Found color section before mode section. Can't determine color format".to_string()));
-                            }
-                        }
-                        Section::TargetFiles => {
-                            target_files.push(buff);
-                            buff = String::with_capacity(BUFF_SIZE);
-                        }
-                        Section::Overwrite => {
-                            if buff == "true" {
-                                overwrite = true;
-                            }
-                            buff.clear()
+        }
+    }
+}
+
+/// A line of `[target_files]`: the path and the settings appended to it, as in
+/// `gtk.css|format=gtk|literals=true`. A `|` of the path itself is written `||`, backslashes
+/// are kept as they are so Windows paths like `C:\Users\me\gtk.css` need no escaping.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub path: PathBuf,
+    pub format: Option<FileFormat>,
+    /// Whether structured handlers also rewrite literals, see `RecolorOptions::literals`.
+    pub literals: Option<bool>,
+    /// Overrides `[overwrite]` for this target.
+    pub overwrite: Option<bool>,
+    /// Positions in the map row this target takes, every one when `None`.
+    pub colors: Option<Vec<usize>>,
+    /// Overrides `[collision]` for this target.
+    pub collision: Option<CollisionPolicy>,
+    /// Recolors this target with a command instead of a handler, always the last hint so the
+    /// command can hold anything.
+    pub exec: Option<ExecCommand>,
+}
+
+impl Target {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Target {
+            path: path.into(),
+            format: None,
+            literals: None,
+            overwrite: None,
+            colors: None,
+            collision: None,
+            exec: None,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, TranError> {
+        // Most targets are a plain path, without hints to look for
+        if !line.contains('|') {
+            return Ok(Target::new(line));
+        }
+        let (path, hints) = split_target_path(line);
+        let (hints, exec) = match hints.split_once("|exec=") {
+            Some((hints, command)) => (hints, Some(ExecCommand::parse(command)?)),
+            None => (hints, None),
+        };
+        let mut target = Target::new(path);
+        target.exec = exec;
+        let boolean = |key: &str, value: &str| {
+            value.trim().parse().map_err(|_| {
+                TranError::ConfigError(format!(
+                    "Expected true or false for {}, found '{}'",
+                    key, value
+                ))
+            })
+        };
+        for hint in hints.split('|').skip(1) {
+            let (key, value) = hint.split_once('=').ok_or_else(|| {
+                TranError::ConfigError(format!("Expected key=value in target hint '{}'", hint))
+            })?;
+            match key.trim() {
+                "format" => target.format = Some(value.trim().try_into()?),
+                "literals" => target.literals = Some(boolean(key, value)?),
+                "overwrite" => target.overwrite = Some(boolean(key, value)?),
+                "collision" => target.collision = Some(value.trim().try_into()?),
+                "exec_timeout" => {
+                    let seconds = value.trim().parse()?;
+                    match &mut target.exec {
+                        Some(exec) => exec.timeout = std::time::Duration::from_secs(seconds),
+                        None => {
+                            return Err(TranError::ConfigError(
+                                "exec_timeout needs an exec hint after it".to_string(),
+                            ))
                         }
                     }
-                    state = ParseState::NewLine;
-                } else {
-                    buff.push(char);
                 }
-            }
-            ParseState::NewLine => {
-                if char == '[' {
-                    state = ParseState::BraceOpen;
-                } else {
-                    buff.push(char);
-                    state = ParseState::Text;
+                "colors" => {
+                    let colors = value
+                        .split(',')
+                        .map(|i| i.trim().parse())
+                        .collect::<Result<Vec<usize>, _>>()
+                        .map_err(|_| {
+                            TranError::ConfigError(format!(
+                                "Expected comma separated positions for colors, found '{}'",
+                                value
+                            ))
+                        })?;
+                    target.colors = Some(colors);
+                }
+                _ => {
+                    return Err(TranError::ConfigError(format!(
+                        "Unrecognized target hint '{}', valid hints are 'format', 'literals', 'overwrite', 'colors', 'collision', 'exec_timeout' and 'exec'",
+                        key
+                    )))
                 }
             }
         }
+        Ok(target)
     }
 
-    if !buff.is_empty() {
-        match section {
-            Section::Mode => {
-                mode = Some(buff.as_str().try_into()?);
-                buff.clear();
+    /// `options` with the settings of this target applied.
+    pub fn options(&self, options: &RecolorOptions) -> RecolorOptions {
+        RecolorOptions {
+            format: match self.exec {
+                Some(_) => Some(FileFormat::Exec),
+                None => self.format.or(options.format),
+            },
+            literals: self.literals.unwrap_or(options.literals),
+            overwrite: self.overwrite.unwrap_or(options.overwrite),
+            collision: self.collision.unwrap_or(options.collision),
+            exec: self.exec.clone().or_else(|| options.exec.clone()),
+            ..options.clone()
+        }
+    }
+}
+
+/// The path of a target line, with `||` read back as `|`, and the hints after it starting at
+/// their first `|`.
+fn split_target_path(line: &str) -> (String, &str) {
+    let mut path = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(i) = rest.find('|') {
+        path.push_str(&rest[..i]);
+        match rest[i + 1..].starts_with('|') {
+            true => {
+                path.push('|');
+                rest = &rest[i + 2..];
             }
-            Section::Colors => {
-                if let Some(m) = &mode {
-                    match m {
-                        Mode::Gradient => match &mut colors {
-                            Some(c) => {
-                                if let ColorOrMapVec::Color(v) = c {
-                                    v.push(Color::try_from_hex_str(&buff)?);
-                                    buff.clear();
-                                } else {
-                                    return Err(TranError::ConfigError(
-                                        "Inconsistent state".to_string(),
-                                    ));
-                                }
-                            }
-                            None => {
-                                colors = Some(ColorOrMapVec::Color(vec![Color::try_from_hex_str(
-                                    &buff,
-                                )?]));
-                                buff.clear();
-                            }
-                        },
-                        Mode::Map => {
-                            let color_map = buff
-                                .split('#')
-                                .map(Color::try_from_hex_str)
-                                .collect::<Result<Vec<Color>, TranError>>()?;
-                            match &mut colors {
-                                Some(c) => {
-                                    if let ColorOrMapVec::Map(v) = c {
-                                        v.push(color_map);
-                                    } else {
-                                        return Err(TranError::ConfigError(
-                                            "Inconsistent state".to_string(),
-                                        ));
-                                    }
-                                }
-                                None => {
-                                    colors = Some(ColorOrMapVec::Map(vec![color_map]));
-                                }
-                            }
-                            buff.clear();
-                        }
-                    }
-                } else {
-                    return Err(TranError::ConfigError(
-                        "Found color section before mode section. Can't determine color format"
-                            .to_string(),
-                    ));
+            false => return (path, &rest[i..]),
+        }
+    }
+    path.push_str(rest);
+    (path, "")
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.display().to_string().replace('|', "||"))?;
+        if let Some(format) = self.format {
+            write!(f, "|format={}", format)?;
+        }
+        if let Some(literals) = self.literals {
+            write!(f, "|literals={}", literals)?;
+        }
+        if let Some(overwrite) = self.overwrite {
+            write!(f, "|overwrite={}", overwrite)?;
+        }
+        if let Some(colors) = &self.colors {
+            let colors: Vec<String> = colors.iter().map(|i| i.to_string()).collect();
+            write!(f, "|colors={}", colors.join(","))?;
+        }
+        if let Some(collision) = self.collision {
+            write!(f, "|collision={}", collision)?;
+        }
+        if let Some(exec) = &self.exec {
+            if exec.timeout != DEFAULT_TIMEOUT {
+                write!(f, "|exec_timeout={}", exec.timeout.as_secs())?;
+            }
+            write!(f, "|exec={}", exec)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Config {
+    GradientConfig(GradientConfig),
+    MapConfig(MapConfig),
+}
+
+impl Config {
+    pub fn get_targets(&self) -> &[Target] {
+        match self {
+            Config::GradientConfig(gc) => gc.get_targets(),
+            Config::MapConfig(mc) => mc.get_targets(),
+        }
+    }
+
+    /// The paths of `[target_files]`, without their settings.
+    pub fn get_target_files(&self) -> Vec<String> {
+        self.get_targets()
+            .iter()
+            .map(|t| t.path.to_string_lossy().to_string())
+            .collect()
+    }
+
+    /// Keeps only the targets `f` returns true for.
+    pub fn retain_targets(&mut self, f: impl FnMut(&Target) -> bool) {
+        match self {
+            Config::GradientConfig(gc) => gc.targets.retain(f),
+            Config::MapConfig(mc) => mc.targets.retain(f),
+        }
+    }
+
+    pub fn push_target(&mut self, target: Target) {
+        match self {
+            Config::GradientConfig(gc) => gc.targets.push(target),
+            Config::MapConfig(mc) => mc.targets.push(target),
+        }
+    }
+
+    pub fn get_allowed_roots(&self) -> &[PathBuf] {
+        match self {
+            Config::GradientConfig(gc) => gc.get_allowed_roots(),
+            Config::MapConfig(mc) => mc.get_allowed_roots(),
+        }
+    }
+
+    pub fn get_recency_penalty(&self) -> Option<&RecencyPenalty> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_recency_penalty(),
+            Config::MapConfig(mc) => mc.get_recency_penalty(),
+        }
+    }
+
+    /// What happens to copies whose name is taken, `[collision]` in the config.
+    pub fn get_collision(&self) -> CollisionPolicy {
+        match self {
+            Config::GradientConfig(gc) => gc.get_collision(),
+            Config::MapConfig(mc) => mc.get_collision(),
+        }
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        match self {
+            Config::GradientConfig(gc) => gc.get_sync_mode(),
+            Config::MapConfig(mc) => mc.get_sync_mode(),
+        }
+    }
+
+    /// The `[output_dir]` of the config, with its base left unset unless one was configured.
+    pub fn get_output_dir(&self) -> Option<&OutputDir> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_output_dir(),
+            Config::MapConfig(mc) => mc.get_output_dir(),
+        }
+    }
+
+    pub fn get_schedule(&self) -> Option<&Schedule> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_schedule(),
+            Config::MapConfig(mc) => mc.get_schedule(),
+        }
+    }
+
+    pub fn get_constraints(&self) -> Option<&Constraints> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_constraints(),
+            Config::MapConfig(mc) => mc.get_constraints(),
+        }
+    }
+
+    /// The tags of the entry of `[colors]` at `index`, as in `dark,blue:#2e3440`.
+    pub fn get_tags(&self, index: usize) -> &[String] {
+        match self {
+            Config::GradientConfig(gc) => gc.get_tags(index),
+            Config::MapConfig(mc) => mc.get_tags(index),
+        }
+    }
+
+    /// The tags picks are narrowed to when a run is given none, from `[default_tags]`.
+    pub fn get_default_tags(&self) -> &[String] {
+        match self {
+            Config::GradientConfig(gc) => gc.get_default_tags(),
+            Config::MapConfig(mc) => mc.get_default_tags(),
+        }
+    }
+
+    /// Whether the entry at `index` can be picked with `tags`, having every one of them.
+    fn is_tagged(&self, index: usize, tags: &[String]) -> bool {
+        let entry = self.get_tags(index);
+        tags.iter().all(|tag| entry.contains(tag))
+    }
+
+    /// Where the terminal escape sequences of the current scheme are written after a run, see
+    /// `export::terminal_sequences`.
+    pub fn get_sequences_file(&self) -> Option<&std::path::Path> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_sequences_file(),
+            Config::MapConfig(mc) => mc.get_sequences_file(),
+        }
+    }
+
+    /// Whether `colors` can be picked under `[constraints]`.
+    fn allows(&self, colors: &[Color]) -> bool {
+        self.get_constraints()
+            .is_none_or(|constraints| constraints.allows(colors))
+    }
+
+    pub fn get_journal(&self) -> bool {
+        match self {
+            Config::GradientConfig(gc) => gc.get_journal(),
+            Config::MapConfig(mc) => mc.get_journal(),
+        }
+    }
+
+    pub fn get_overwrite(&self) -> bool {
+        match self {
+            Config::GradientConfig(gc) => gc.get_overwrite(),
+            Config::MapConfig(mc) => mc.get_overwrite(),
+        }
+    }
+
+    pub fn get_output_name(&self) -> Option<&str> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_output_name(),
+            Config::MapConfig(mc) => mc.get_output_name(),
+        }
+    }
+
+    pub fn get_transition(&self) -> Option<&Transition> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_transition(),
+            Config::MapConfig(mc) => mc.get_transition(),
+        }
+    }
+
+    pub fn get_contrast(&self) -> Option<&Contrast> {
+        match self {
+            Config::GradientConfig(gc) => gc.get_contrast(),
+            Config::MapConfig(_) => None,
+        }
+    }
+
+    pub fn get_gradient_space(&self) -> GradientSpace {
+        match self {
+            Config::GradientConfig(gc) => gc.get_gradient_space(),
+            Config::MapConfig(mc) => mc.get_gradient_space(),
+        }
+    }
+
+    pub fn get_stops(&self) -> &[Stop] {
+        match self {
+            Config::GradientConfig(gc) => gc.get_stops(),
+            Config::MapConfig(_) => &[],
+        }
+    }
+
+    pub fn get_luminance_band(&self) -> LuminanceBand {
+        match self {
+            Config::GradientConfig(gc) => gc.get_luminance_band(),
+            Config::MapConfig(mc) => mc.get_luminance_band(),
+        }
+    }
+
+    pub fn get_hex_case(&self) -> HexCase {
+        match self {
+            Config::GradientConfig(gc) => gc.get_hex_case(),
+            Config::MapConfig(mc) => mc.get_hex_case(),
+        }
+    }
+
+    /// Which colors text handlers take and how they write them.
+    pub fn get_text_options(&self) -> &TextOptions {
+        match self {
+            Config::GradientConfig(gc) => gc.get_text_options(),
+            Config::MapConfig(mc) => mc.get_text_options(),
+        }
+    }
+
+    /// Which keys and sections the ini handler rewrites, `[handlers]` in the config.
+    pub fn get_ini_options(&self) -> &IniOptions {
+        match self {
+            Config::GradientConfig(gc) => gc.get_ini_options(),
+            Config::MapConfig(mc) => mc.get_ini_options(),
+        }
+    }
+
+    /// How `tran discover` walks directories.
+    pub fn get_traversal(&self) -> Traversal {
+        match self {
+            Config::GradientConfig(gc) => gc.get_traversal(),
+            Config::MapConfig(mc) => mc.get_traversal(),
+        }
+    }
+
+    pub fn set_selection(&mut self, selection: Selection) -> Result<(), TranError> {
+        match (self, selection) {
+            (Config::GradientConfig(gc), Selection::Color(color)) => gc.set_current_colors(color),
+            (Config::MapConfig(mc), Selection::Row(row)) => mc.set_current_colors(row),
+            (_, _) => {
+                return Err(TranError::ConfigError(
+                    "Selected colors do not match the config mode".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_current_colors(&self) -> Vec<Color> {
+        match self {
+            Config::GradientConfig(gc) => vec![*gc.get_current_color()],
+            Config::MapConfig(mc) => mc.get_current_colors().to_vec(),
+        }
+    }
+
+    /// Every configured color, or map row, in config order.
+    pub fn get_selections(&self) -> Vec<Selection> {
+        match self {
+            Config::GradientConfig(gc) => gc
+                .get_colors()
+                .iter()
+                .copied()
+                .map(Selection::Color)
+                .collect(),
+            Config::MapConfig(mc) => mc.get_colors().into_iter().map(Selection::Row).collect(),
+        }
+    }
+
+    pub fn get_mode(&self) -> &str {
+        match self {
+            Config::GradientConfig(_) => "gradient",
+            Config::MapConfig(_) => "map",
+        }
+    }
+
+    /// `overlay`, the text of a config, layered over `base`. `[mode]` can be left out but has
+    /// to be the one of the base when given. Every other section the overlay has wins over the
+    /// one of the base, key by key for `key=value` sections like `[transition]`. The list
+    /// sections `[colors]`, `[target_files]`, `[allowed_roots]`, `[schedule]` and `[stops]`
+    /// replace the entries of the base, or are added after them when written as
+    /// `[append_colors]` and so on. Weights go with their colors, and map rows an overlay
+    /// appends must be as long as those of the base.
+    pub fn merge(base: Config, overlay: &str) -> Result<Config, TranError> {
+        let mut builder = ConfigBuilder::default();
+        read_config(&mut builder, &write_config_to_string(base))?;
+        builder.overlay = true;
+        read_config(&mut builder, overlay)?;
+        builder.build()
+    }
+
+    /// Reads `colors`, as the `TRAN_COLOR` environment variable holds them, as the next
+    /// selection: a single color in gradient mode, a row as long as the current one in map mode,
+    /// where `-` keeps the current color of a slot.
+    pub fn parse_selection(&self, colors: &str) -> Result<Selection, TranError> {
+        let (weight, parsed) = split_row(colors)?;
+        let invalid = |reason: String| {
+            TranError::UnsupportedError(format!(
+                "Can't recolor to TRAN_COLOR '{}', {}",
+                colors, reason
+            ))
+        };
+        if weight.is_some() {
+            return Err(invalid("it can't have a weight".to_string()));
+        }
+        match self {
+            Config::GradientConfig(_) => match parsed.as_slice() {
+                [Some(color)] => Ok(Selection::Color(*color)),
+                [None] => Err(invalid("'-' only keeps a color in map mode".to_string())),
+                _ => Err(invalid(format!(
+                    "it has {} colors but gradient mode takes one",
+                    parsed.len()
+                ))),
+            },
+            Config::MapConfig(mc) => {
+                let expected = match mc.get_current_colors() {
+                    [] => mc.get_rows().first().map_or(0, Vec::len),
+                    current => current.len(),
+                };
+                if parsed.is_empty() || parsed.len() != expected {
+                    return Err(invalid(format!(
+                        "it has {} colors but rows in map mode have {}",
+                        parsed.len(),
+                        expected
+                    )));
+                }
+                let current = mc.get_current_colors();
+                parsed
+                    .iter()
+                    .enumerate()
+                    .map(|(i, color)| color.or_else(|| current.get(i).copied()))
+                    .collect::<Option<Vec<_>>>()
+                    .map(Selection::Row)
+                    .ok_or_else(|| {
+                        invalid("'-' keeps a color but there is no current row".to_string())
+                    })
+            }
+        }
+    }
+
+    pub fn select_next<R: RngLike>(&self, rng: &mut R) -> Result<Selection, TranError> {
+        self.select_contrasting(rng, &[])
+            .map(|(selection, _)| selection)
+    }
+
+    /// Like `select_next`, but also tells what it took to meet the `[contrast]` section, if
+    /// anything. `recent` holds the colors of the last runs, newest last, for the
+    /// `[recency_penalty]` section.
+    pub fn select_contrasting<R: RngLike>(
+        &self,
+        rng: &mut R,
+        recent: &[Vec<Color>],
+    ) -> Result<(Selection, Option<ContrastFix>), TranError> {
+        self.select_tagged(rng, recent, &[])
+    }
+
+    /// Like `select_contrasting`, picking only among the entries of `[colors]` that have every
+    /// one of `tags`, or of `[default_tags]` when `tags` is empty. Fails when no entry has them.
+    pub fn select_tagged<R: RngLike>(
+        &self,
+        rng: &mut R,
+        recent: &[Vec<Color>],
+        tags: &[String],
+    ) -> Result<(Selection, Option<ContrastFix>), TranError> {
+        let tags = match tags {
+            [] => self.get_default_tags(),
+            tags => tags,
+        };
+        if !(0..self.get_selections().len()).any(|i| self.is_tagged(i, tags)) {
+            return Err(TranError::ConfigError(format!(
+                "No entry in [colors] is tagged {}",
+                tags.join(",")
+            )));
+        }
+        let picked = self.pick(rng, recent, tags)?;
+        let (Some(contrast), Selection::Color(color)) = (self.get_contrast(), &picked) else {
+            return Ok((picked, None));
+        };
+        let passes = |color: &Color| color.contrast_ratio(&contrast.background) >= contrast.ratio;
+        if passes(color) {
+            return Ok((picked, None));
+        }
+
+        let mut color = *color;
+        if contrast.strategy == ContrastStrategy::Repick {
+            let mut rejected = vec![color];
+            for _ in 1..contrast.attempts {
+                if let Selection::Color(next) = self.pick(rng, recent, tags)? {
+                    if passes(&next) {
+                        return Ok((
+                            Selection::Color(next),
+                            Some(ContrastFix::Repicked(rejected)),
+                        ));
+                    }
+                    rejected.push(next);
+                    color = next;
+                }
+            }
+        }
+        let adjusted = color.with_contrast(&contrast.background, contrast.ratio);
+        Ok((
+            Selection::Color(adjusted),
+            Some(ContrastFix::Adjusted {
+                picked: color,
+                adjusted,
+            }),
+        ))
+    }
+
+    /// The weight the color or row at `index` is picked with.
+    fn effective_weight(&self, index: usize, colors: &[Color], recent: &[Vec<Color>]) -> usize {
+        let weight = match self {
+            Config::GradientConfig(gc) => gc.get_weight(index),
+            Config::MapConfig(mc) => mc.get_weight(index),
+        };
+        match self.get_recency_penalty() {
+            Some(penalty) => {
+                let recent = &recent[recent.len().saturating_sub(penalty.runs)..];
+                penalty.weight(weight, colors, recent)
+            }
+            None => weight,
+        }
+    }
+
+    fn pick<R: RngLike>(
+        &self,
+        rng: &mut R,
+        recent: &[Vec<Color>],
+        tags: &[String],
+    ) -> Result<Selection, TranError> {
+        match self {
+            Config::GradientConfig(gc) => {
+                let candidates: Vec<(&Color, usize)> = gc
+                    .get_colors()
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, color)| {
+                        *color != gc.get_current_color()
+                            && self.is_tagged(*i, tags)
+                            && self.allows(std::slice::from_ref(*color))
+                    })
+                    .map(|(i, color)| {
+                        let weight = self.effective_weight(i, std::slice::from_ref(color), recent);
+                        (color, weight)
+                    })
+                    .collect();
+
+                // With only the current color left it stays, and the run has nothing to do
+                let current = gc.get_current_color();
+                let kept = || {
+                    gc.get_colors()
+                        .iter()
+                        .enumerate()
+                        .any(|(i, color)| {
+                            color == current && gc.get_weight(i) > 0 && self.is_tagged(i, tags)
+                        })
+                        .then_some(current)
+                        .filter(|current| self.allows(std::slice::from_ref(*current)))
+                };
+                pick_weighted(&candidates, rng)
+                    .or_else(kept)
+                    .map(|color| Selection::Color(*color))
+                    .ok_or_else(|| {
+                        let weighted = (0..gc.get_colors().len()).any(|i| gc.get_weight(i) > 0);
+                        TranError::ConfigError(match weighted {
+                            true => "No color to select, every color in [colors] breaks [constraints]",
+                            false => "No color to select, every entry in [colors] has weight 0",
+                        }.to_string())
+                    })
+            }
+            Config::MapConfig(mc) => {
+                let rows = mc.get_colors();
+                let candidates: Vec<(&Vec<Color>, usize)> = rows
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, row)| {
+                        row.as_slice() != mc.get_current_colors()
+                            && self.is_tagged(*i, tags)
+                            && self.allows(row)
+                    })
+                    .map(|(i, row)| (row, self.effective_weight(i, row, recent)))
+                    .collect();
+
+                let current = mc.get_current_colors();
+                let kept = || {
+                    rows.iter()
+                        .enumerate()
+                        .any(|(i, row)| {
+                            row.as_slice() == current
+                                && mc.get_weight(i) > 0
+                                && self.is_tagged(i, tags)
+                        })
+                        .then_some(current)
+                        .filter(|current| self.allows(current))
+                };
+                pick_weighted(&candidates, rng)
+                    .map(Vec::as_slice)
+                    .or_else(kept)
+                    .map(|row| Selection::Row(row.to_vec()))
+                    .ok_or_else(|| {
+                        let weighted = (0..rows.len()).any(|i| mc.get_weight(i) > 0);
+                        TranError::ConfigError(match weighted {
+                            true => "No color row to select, every row in [colors] breaks [constraints]",
+                            false => "No color row to select, every row in [colors] has weight 0",
+                        }.to_string())
+                    })
+            }
+        }
+    }
+}
+
+// Parsing records one weight per color, so derived equality compares effective weights
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GradientConfig {
+    current_color: Color,
+    colors: Vec<Color>,
+    weights: Vec<usize>,
+    tags: Vec<Vec<String>>,
+    targets: Vec<Target>,
+    allowed_roots: Vec<PathBuf>,
+    output_dir: Option<OutputDir>,
+    collision: CollisionPolicy,
+    sync_mode: SyncMode,
+    recency_penalty: Option<RecencyPenalty>,
+    journal: bool,
+    schedule: Option<Schedule>,
+    constraints: Option<Constraints>,
+    sequences_file: Option<PathBuf>,
+    default_tags: Vec<String>,
+    overwrite: bool,
+    output_name: Option<String>,
+    transition: Option<Transition>,
+    contrast: Option<Contrast>,
+    gradient_space: GradientSpace,
+    luminance_band: LuminanceBand,
+    stops: Vec<Stop>,
+    text_options: TextOptions,
+    ini_options: IniOptions,
+    traversal: Traversal,
+}
+
+impl GradientConfig {
+    pub fn set_current_colors(&mut self, color: Color) {
+        self.current_color = color
+    }
+
+    pub fn get_current_color(&self) -> &Color {
+        &self.current_color
+    }
+
+    pub fn get_colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn get_weight(&self, index: usize) -> usize {
+        self.weights.get(index).copied().unwrap_or(1)
+    }
+
+    pub fn get_tags(&self, index: usize) -> &[String] {
+        self.tags.get(index).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every color but the current one with its weight, as `pick_weighted` takes them.
+    pub fn get_weighted_colors(&self) -> Vec<(&Color, usize)> {
+        self.get_colors()
+            .iter()
+            .enumerate()
+            .filter(|(_, color)| *color != self.get_current_color())
+            .map(|(i, color)| (color, self.get_weight(i)))
+            .collect()
+    }
+
+    #[deprecated(
+        since = "0.1.0",
+        note = "repeats every color as often as its weight, use get_weighted_colors"
+    )]
+    pub fn get_colors_scaled(&self) -> Vec<Color> {
+        self.get_weighted_colors()
+            .into_iter()
+            .flat_map(|(color, weight)| std::iter::repeat_n(*color, weight))
+            .collect()
+    }
+
+    pub fn get_targets(&self) -> &[Target] {
+        &self.targets
+    }
+
+    /// Directories targets have to resolve into, empty allows any.
+    pub fn get_allowed_roots(&self) -> &[PathBuf] {
+        &self.allowed_roots
+    }
+
+    pub fn get_output_dir(&self) -> Option<&OutputDir> {
+        self.output_dir.as_ref()
+    }
+
+    pub fn get_collision(&self) -> CollisionPolicy {
+        self.collision
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn get_recency_penalty(&self) -> Option<&RecencyPenalty> {
+        self.recency_penalty.as_ref()
+    }
+
+    /// Whether every replacement of a run is logged to the state directory.
+    pub fn get_journal(&self) -> bool {
+        self.journal
+    }
+
+    /// When `tran daemon` recolors.
+    pub fn get_schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    pub fn get_constraints(&self) -> Option<&Constraints> {
+        self.constraints.as_ref()
+    }
+
+    pub fn get_sequences_file(&self) -> Option<&std::path::Path> {
+        self.sequences_file.as_deref()
+    }
+
+    pub fn get_default_tags(&self) -> &[String] {
+        &self.default_tags
+    }
+
+    pub fn get_overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    pub fn get_output_name(&self) -> Option<&str> {
+        self.output_name.as_deref()
+    }
+
+    pub fn get_transition(&self) -> Option<&Transition> {
+        self.transition.as_ref()
+    }
+
+    pub fn get_contrast(&self) -> Option<&Contrast> {
+        self.contrast.as_ref()
+    }
+
+    pub fn get_gradient_space(&self) -> GradientSpace {
+        self.gradient_space
+    }
+
+    /// The lumas gradients recolor.
+    pub fn get_luminance_band(&self) -> LuminanceBand {
+        self.luminance_band
+    }
+
+    /// Where gradients ramp across, empty for black to the color picked.
+    pub fn get_stops(&self) -> &[Stop] {
+        &self.stops
+    }
+
+    pub fn get_hex_case(&self) -> HexCase {
+        self.text_options.hex_case
+    }
+
+    pub fn get_text_options(&self) -> &TextOptions {
+        &self.text_options
+    }
+
+    pub fn get_ini_options(&self) -> &IniOptions {
+        &self.ini_options
+    }
+
+    pub fn get_traversal(&self) -> Traversal {
+        self.traversal
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapConfig {
+    current_color: Vec<Color>,
+    /// `None` where a row keeps the current color, written `-`.
+    colors: Vec<Vec<Option<Color>>>,
+    weights: Vec<usize>,
+    tags: Vec<Vec<String>>,
+    targets: Vec<Target>,
+    allowed_roots: Vec<PathBuf>,
+    output_dir: Option<OutputDir>,
+    collision: CollisionPolicy,
+    sync_mode: SyncMode,
+    recency_penalty: Option<RecencyPenalty>,
+    journal: bool,
+    schedule: Option<Schedule>,
+    constraints: Option<Constraints>,
+    sequences_file: Option<PathBuf>,
+    default_tags: Vec<String>,
+    overwrite: bool,
+    output_name: Option<String>,
+    transition: Option<Transition>,
+    gradient_space: GradientSpace,
+    luminance_band: LuminanceBand,
+    text_options: TextOptions,
+    ini_options: IniOptions,
+    traversal: Traversal,
+}
+
+impl GradientConfig {
+    /// Adds `color` to the end of `[colors]`.
+    pub fn push_color(&mut self, color: Color) {
+        self.colors.push(color);
+        self.weights.push(1);
+        self.tags.push(Vec::new());
+    }
+}
+
+impl MapConfig {
+    /// Adds `row` to the end of `[colors]`.
+    pub fn push_colors(&mut self, row: Vec<Color>) {
+        self.colors.push(row.into_iter().map(Some).collect());
+        self.weights.push(1);
+        self.tags.push(Vec::new());
+    }
+
+    pub fn set_current_colors(&mut self, color: Vec<Color>) {
+        self.current_color = color
+    }
+
+    pub fn get_current_colors(&self) -> &[Color] {
+        &self.current_color
+    }
+
+    /// The rows of `[colors]` as written, `None` in the slots that keep the current color.
+    pub fn get_rows(&self) -> &[Vec<Option<Color>>] {
+        &self.colors
+    }
+
+    /// The rows of `[colors]` with the current color in the slots written `-`.
+    pub fn get_colors(&self) -> Vec<Vec<Color>> {
+        self.colors
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(i, color)| {
+                        color
+                            .or_else(|| self.current_color.get(i).copied())
+                            .unwrap_or(Color::black())
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn get_weight(&self, index: usize) -> usize {
+        self.weights.get(index).copied().unwrap_or(1)
+    }
+
+    pub fn get_tags(&self, index: usize) -> &[String] {
+        self.tags.get(index).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every row with its weight, as `pick_weighted` takes them once borrowed.
+    pub fn get_weighted_colors(&self) -> Vec<(Vec<Color>, usize)> {
+        self.get_colors()
+            .into_iter()
+            .enumerate()
+            .map(|(i, row)| (row, self.get_weight(i)))
+            .collect()
+    }
+
+    #[deprecated(
+        since = "0.1.0",
+        note = "repeats every row as often as its weight, use get_weighted_colors"
+    )]
+    pub fn get_colors_scaled(&self) -> Vec<Vec<Color>> {
+        self.get_weighted_colors()
+            .into_iter()
+            .flat_map(|(row, weight)| std::iter::repeat_n(row, weight))
+            .collect()
+    }
+
+    pub fn get_targets(&self) -> &[Target] {
+        &self.targets
+    }
+
+    /// Directories targets have to resolve into, empty allows any.
+    pub fn get_allowed_roots(&self) -> &[PathBuf] {
+        &self.allowed_roots
+    }
+
+    pub fn get_output_dir(&self) -> Option<&OutputDir> {
+        self.output_dir.as_ref()
+    }
+
+    pub fn get_collision(&self) -> CollisionPolicy {
+        self.collision
+    }
+
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn get_recency_penalty(&self) -> Option<&RecencyPenalty> {
+        self.recency_penalty.as_ref()
+    }
+
+    /// Whether every replacement of a run is logged to the state directory.
+    pub fn get_journal(&self) -> bool {
+        self.journal
+    }
+
+    /// When `tran daemon` recolors.
+    pub fn get_schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    pub fn get_constraints(&self) -> Option<&Constraints> {
+        self.constraints.as_ref()
+    }
+
+    pub fn get_sequences_file(&self) -> Option<&std::path::Path> {
+        self.sequences_file.as_deref()
+    }
+
+    pub fn get_default_tags(&self) -> &[String] {
+        &self.default_tags
+    }
+
+    pub fn get_overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    pub fn get_output_name(&self) -> Option<&str> {
+        self.output_name.as_deref()
+    }
+
+    pub fn get_transition(&self) -> Option<&Transition> {
+        self.transition.as_ref()
+    }
+
+    pub fn get_gradient_space(&self) -> GradientSpace {
+        self.gradient_space
+    }
+
+    /// The lumas gradients recolor.
+    pub fn get_luminance_band(&self) -> LuminanceBand {
+        self.luminance_band
+    }
+
+    pub fn get_hex_case(&self) -> HexCase {
+        self.text_options.hex_case
+    }
+
+    pub fn get_text_options(&self) -> &TextOptions {
+        &self.text_options
+    }
+
+    pub fn get_ini_options(&self) -> &IniOptions {
+        &self.ini_options
+    }
+
+    pub fn get_traversal(&self) -> Traversal {
+        self.traversal
+    }
+}
+
+#[cfg(feature = "fs")]
+pub fn parse_config<T: AsRef<Path>>(target: T) -> Result<Config, TranError> {
+    let target = target.as_ref();
+    let contents = std::fs::read_to_string(target).map_err(|e| TranError::read(target, e))?;
+    parse_config_contents(&contents).map_err(|e| e.in_file(target))
+}
+
+/// Where the machine-local overlay of the config at `path` is, its name with `.local` added.
+#[cfg(feature = "fs")]
+pub fn local_config_path(path: &Path) -> PathBuf {
+    let mut local = path.as_os_str().to_os_string();
+    local.push(".local");
+    PathBuf::from(local)
+}
+
+/// The config at `target` with its local overlay merged over it when there is one, see
+/// `Config::merge`.
+#[cfg(feature = "fs")]
+pub fn parse_config_with_local<T: AsRef<Path>>(target: T) -> Result<Config, TranError> {
+    let target = target.as_ref();
+    let config = parse_config(target)?;
+    let local = local_config_path(target);
+    if !local.is_file() {
+        return Ok(config);
+    }
+    let contents = std::fs::read_to_string(&local).map_err(|e| TranError::read(&local, e))?;
+    Config::merge(config, &contents).map_err(|e| e.in_file(&local))
+}
+
+#[derive(Default)]
+struct ConfigBuilder {
+    mode: Option<Mode>,
+    current_color: Option<ColorOrMap>,
+    /// An `@index` in `[current_color]`, resolved once every entry of `[colors]` is read.
+    current_index: Option<usize>,
+    colors: Option<ColorOrMapVec>,
+    weights: Vec<usize>,
+    tags: Vec<Vec<String>>,
+    targets: Vec<Target>,
+    allowed_roots: Vec<PathBuf>,
+    output_dir: Option<OutputDir>,
+    collision: CollisionPolicy,
+    sync_mode: SyncMode,
+    recency_penalty: Option<RecencyPenalty>,
+    journal: bool,
+    schedule: Option<Schedule>,
+    constraints: Option<Constraints>,
+    sequences_file: Option<PathBuf>,
+    default_tags: Vec<String>,
+    overwrite: bool,
+    output_name: Option<String>,
+    transition: Option<Transition>,
+    contrast: Option<Contrast>,
+    gradient_space: GradientSpace,
+    luminance_band: LuminanceBand,
+    stops: Vec<(Option<f64>, Option<Color>)>,
+    text_options: TextOptions,
+    ini_options: IniOptions,
+    traversal: Traversal,
+    /// Set while an overlay is read over a base, see `Config::merge`.
+    overlay: bool,
+    /// The list sections the overlay already replaced the entries of the base of.
+    replaced: Vec<Section>,
+}
+
+/// Reads a `key=value` line of `[text_options]` into `options`, `exclude` adding a glob each
+/// time it comes up.
+fn push_text_option(options: &mut TextOptions, line: &str) -> Result<(), TranError> {
+    let (key, value) = line.split_once('=').ok_or_else(|| {
+        TranError::ConfigError(format!(
+            "Expected key=value in text_options, found '{}'",
+            line
+        ))
+    })?;
+    let (key, value) = (key.trim(), value.trim());
+    let boolean = || {
+        value.parse().map_err(|_| {
+            TranError::ConfigError(format!(
+                "Expected true or false for {}, found '{}'",
+                key, value
+            ))
+        })
+    };
+    match key {
+        "skip_urls" => options.skip_urls = boolean()?,
+        "skip_hashes" => options.skip_hashes = boolean()?,
+        "exclude" => options.exclude.push(value.to_string()),
+        _ => {
+            return Err(TranError::ConfigError(format!(
+                "Unrecognized text_options setting '{}', valid settings are 'skip_urls', 'skip_hashes' and 'exclude'",
+                key
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Reads a `key=value` line of `[handlers]` into `options`, `ini_keys` and `ini_sections`
+/// taking comma separated globs in place of the defaults.
+fn push_handler_option(options: &mut IniOptions, line: &str) -> Result<(), TranError> {
+    let (key, value) = line.split_once('=').ok_or_else(|| {
+        TranError::ConfigError(format!("Expected key=value in handlers, found '{}'", line))
+    })?;
+    let globs = value
+        .split(',')
+        .map(str::trim)
+        .filter(|glob| !glob.is_empty())
+        .map(str::to_string)
+        .collect();
+    match key.trim() {
+        "ini_keys" => options.keys = globs,
+        "ini_sections" => options.sections = globs,
+        key => {
+            return Err(TranError::ConfigError(format!(
+            "Unrecognized handlers setting '{}', valid settings are 'ini_keys' and 'ini_sections'",
+            key
+        )))
+        }
+    }
+    Ok(())
+}
+
+/// Reads a `key=value` line of `[gradient_options]` into `band`. Lumas are 0 to 255, or 0.0 to
+/// 1.0 when written with a decimal point.
+fn push_gradient_option(band: &mut LuminanceBand, line: &str) -> Result<(), TranError> {
+    let (key, value) = line.split_once('=').ok_or_else(|| {
+        TranError::ConfigError(format!(
+            "Expected key=value in gradient_options, found '{}'",
+            line
+        ))
+    })?;
+    let value = value.trim();
+    let luma = match value.contains('.') {
+        true => value
+            .parse::<f32>()
+            .ok()
+            .filter(|luma| (0.0..=1.0).contains(luma))
+            .map(|luma| (luma * 255.0).round() as u8),
+        false => value.parse::<u8>().ok(),
+    }
+    .ok_or_else(|| {
+        TranError::ConfigError(format!(
+            "Expected a luminance from 0 to 255 or 0.0 to 1.0, found '{}'",
+            value
+        ))
+    })?;
+    match key.trim() {
+        "min_luminance" => band.min = luma,
+        "max_luminance" => band.max = luma,
+        key => {
+            return Err(TranError::ConfigError(format!(
+            "Unrecognized gradient_options setting '{}', valid settings are 'min_luminance' and 'max_luminance'",
+            key
+        )))
+        }
+    }
+    Ok(())
+}
+
+/// How long the decimal triplet at the start of `s` is, stopping after its third number.
+fn triplet_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let skip_spaces = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        i
+    };
+    let mut i = 0;
+    for commas in 0..3 {
+        i = skip_spaces(i);
+        while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        let next = skip_spaces(i);
+        if commas == 2 || bytes.get(next) != Some(&b',') {
+            break;
+        }
+        i = next + 1;
+    }
+    i
+}
+
+/// The tags of a comma separated list such as `dark,blue`.
+fn tag_list(list: &str) -> impl Iterator<Item = String> + '_ {
+    list.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+}
+
+/// Splits the tags that may lead an entry of `[colors]`, as in `dark,blue:2#2e3440`, from the
+/// rest of the line.
+fn split_tags(line: &str) -> (Vec<String>, &str) {
+    let is_tag = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | ',' | ' ');
+    match line.split_once(':') {
+        Some((tags, rest)) if tags.chars().all(is_tag) && tags.chars().any(char::is_alphabetic) => {
+            (tag_list(tags).collect(), rest)
+        }
+        _ => (Vec::new(), line),
+    }
+}
+
+/// Weights above this are taken for typos, a weight of ten thousand already all but rules out
+/// the other entries.
+pub const MAX_WEIGHT: usize = 10_000;
+
+/// Splits a line of colors, hex as in `#2e3440#3b4252` or decimal as in `46,52,64` and
+/// `rgb(46, 52, 64)`, from the weight that may lead it as in `3#2e3440` or `3 46,52,64`.
+fn split_colors(line: &str) -> Result<(Option<usize>, Vec<Color>), TranError> {
+    let (weight, colors) = split_row(line)?;
+    let colors = colors
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            TranError::ConfigError(format!(
+                "'-' in '{}' only keeps a color in a map row of [colors]",
+                line
+            ))
+        })?;
+    Ok((weight, colors))
+}
+
+/// Like `split_colors`, with `-` for a slot of a map row that keeps the current color, as in
+/// `-#bf616a--`.
+fn split_row(line: &str) -> Result<(Option<usize>, Vec<Option<Color>>), TranError> {
+    let mut weight = None;
+    let mut colors = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let len = if rest.starts_with('-') {
+            colors.push(None);
+            1
+        } else if let Some(hex) = rest.strip_prefix('#') {
+            let len = 1 + hex
+                .find(|c: char| c == '#' || c == '-' || c.is_whitespace())
+                .unwrap_or(hex.len());
+            colors.push(Some(Color::try_from_hex_str(&rest[..len])?));
+            len
+        } else if rest.starts_with("0x") || rest.starts_with("0X") {
+            let len = rest
+                .find(|c: char| c == '#' || c == '-' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            colors.push(Some(Color::try_from_hex_str(&rest[..len])?));
+            len
+        } else if rest.starts_with("rgb(") {
+            let len = 1 + rest
+                .find(')')
+                .ok_or_else(|| TranError::ConfigError(format!("Unclosed rgb( in '{}'", line)))?;
+            colors.push(Some(Color::try_from_triplet_str(&rest[..len])?));
+            len
+        } else {
+            // A number is the weight, unless a comma makes it the start of a triplet
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let triplet = rest[digits..].trim_start().starts_with(',');
+            if digits > 0 && triplet {
+                let len = triplet_len(rest);
+                colors.push(Some(Color::try_from_triplet_str(&rest[..len])?));
+                len
+            } else if digits > 0 && weight.is_none() && colors.is_empty() {
+                let parsed = rest[..digits].parse().unwrap_or(usize::MAX);
+                if parsed > MAX_WEIGHT {
+                    return Err(TranError::ConfigError(format!(
+                        "The weight {} of '{}' is above the largest one, {}",
+                        &rest[..digits],
+                        line,
+                        MAX_WEIGHT
+                    )));
                 }
+                weight = Some(parsed);
+                digits
+            } else {
+                let len = rest
+                    .find(|c: char| c == '#' || c == '-' || c.is_whitespace())
+                    .unwrap_or(rest.len())
+                    .max(1);
+                let input = &rest[..len];
+                return Err(TranError::InvalidHexColor {
+                    input: input.to_string(),
+                    reason: match input.chars().find(|c| !c.is_ascii_hexdigit()) {
+                        Some(c) => HexError::InvalidDigit(c),
+                        None if matches!(input.len(), 3 | 6) => HexError::MissingPrefix,
+                        None => HexError::Length(input.len()),
+                    },
+                });
             }
-            Section::CurrentColor => {
-                if let Some(m) = &mode {
-                    match m {
-                        Mode::Gradient => {
-                            current_color = ColorOrMap::Color(Color::try_from_hex_str(&buff)?);
-                            buff.clear();
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok((weight, colors))
+}
+
+impl ConfigBuilder {
+    fn get_mode(&self) -> Result<&Mode, TranError> {
+        self.mode
+            .as_ref()
+            .ok_or(TranError::MissingSection(Section::Mode))
+    }
+
+    /// Starts reading `section`. The first time an overlay has a list section it replaces the
+    /// entries of the base, unless it is written as `[append_<section>]`.
+    fn enter_section(&mut self, section: Section, append: bool) {
+        if !self.overlay || append || self.replaced.contains(&section) {
+            return;
+        }
+        self.replaced.push(section);
+        match section {
+            Section::Colors => {
+                self.colors = None;
+                self.weights.clear();
+                self.tags.clear();
+            }
+            Section::TargetFiles => self.targets.clear(),
+            Section::AllowedRoots => self.allowed_roots.clear(),
+            Section::Schedule => self.schedule = None,
+            Section::Stops => self.stops.clear(),
+            _ => {}
+        }
+    }
+
+    /// Whether what is read of `a` and `b` comes from the same file, as an overlay can have one
+    /// of them and leave the other to the base. What doesn't is compared by `build`.
+    fn read_together(&self, a: Section, b: Section) -> bool {
+        !self.overlay || (self.replaced.contains(&a) && self.replaced.contains(&b))
+    }
+
+    fn push_line(&mut self, section: &Section, line: &str) -> Result<(), TranError> {
+        match section {
+            Section::Mode => {
+                let mode: Mode = line.try_into()?;
+                match self.mode {
+                    Some(expected) if expected != mode => {
+                        return Err(TranError::InconsistentMode {
+                            expected,
+                            found: mode,
+                        })
+                    }
+                    _ => self.mode = Some(mode),
+                }
+            }
+            Section::Colors => {
+                let (tags, line) = split_tags(line);
+                let (weight, colors) = split_row(line)?;
+                match (*self.get_mode()?, &self.colors) {
+                    (Mode::Gradient, None | Some(ColorOrMapVec::Color(_))) => {
+                        let color = colors.first().copied().flatten().ok_or_else(|| {
+                            TranError::ConfigError(match colors.first() {
+                                Some(None) => "'-' only keeps a color in map rows".to_string(),
+                                _ => "Failed to parse color value".to_string(),
+                            })
+                        })?;
+                        match &mut self.colors {
+                            Some(ColorOrMapVec::Color(v)) => v.push(color),
+                            _ => self.colors = Some(ColorOrMapVec::Color(vec![color])),
+                        }
+                    }
+                    (Mode::Map, None | Some(ColorOrMapVec::Map(_))) => {
+                        let color_map = colors;
+                        if let Some(ColorOrMap::Map(current)) = &self.current_color {
+                            if self.read_together(Section::CurrentColor, Section::Colors)
+                                && current.len() != color_map.len()
+                            {
+                                return Err(TranError::ConfigError(format!(
+                                    "This row has {} colors, but current_color has {}",
+                                    color_map.len(),
+                                    current.len()
+                                )));
+                            }
                         }
-                        Mode::Map => {
-                            let c = buff.split('#');
-                            current_color =
-                                ColorOrMap::Map(c.map(Color::try_from_hex_str).collect::<Result<
-                                    Vec<Color>,
-                                    TranError,
-                                >>(
-                                )?);
+                        match &mut self.colors {
+                            // Including rows an overlay appends to the ones of the base
+                            Some(ColorOrMapVec::Map(v))
+                                if v.first().is_some_and(|row| row.len() != color_map.len()) =>
+                            {
+                                return Err(TranError::ConfigError(format!(
+                                    "This row has {} colors, but rows in [colors] have {}",
+                                    color_map.len(),
+                                    v[0].len()
+                                )));
+                            }
+                            Some(ColorOrMapVec::Map(v)) => v.push(color_map),
+                            _ => self.colors = Some(ColorOrMapVec::Map(vec![color_map])),
                         }
                     }
-                } else {
-                    return Err(TranError::ConfigError(
-                        "Found color section before mode section. Can't determine color format"
-                            .to_string(),
-                    ));
+                    (mode, Some(ColorOrMapVec::Color(_))) => {
+                        return Err(TranError::InconsistentMode {
+                            expected: mode,
+                            found: Mode::Gradient,
+                        });
+                    }
+                    (mode, _) => {
+                        return Err(TranError::InconsistentMode {
+                            expected: mode,
+                            found: Mode::Map,
+                        });
+                    }
+                }
+                self.weights.push(weight.unwrap_or(1));
+                self.tags.push(tags);
+            }
+            Section::CurrentColor => {
+                if let Some(index) = line.trim().strip_prefix('@') {
+                    self.current_index = Some(index.trim().parse().map_err(|_| {
+                        TranError::ConfigError(format!(
+                            "Expected an index of [colors] after '@', found '{}'",
+                            index
+                        ))
+                    })?);
+                    self.current_color = None;
+                    return Ok(());
                 }
+                self.current_index = None;
+                let (weight, colors) = split_colors(line)?;
+                let invalid = || {
+                    TranError::ConfigError(format!(
+                        "Could not read '{}' as the current color",
+                        line
+                    ))
+                };
+                if weight.is_some() {
+                    return Err(invalid());
+                }
+                if let Some(ColorOrMapVec::Map(rows)) = &self.colors {
+                    if self.read_together(Section::CurrentColor, Section::Colors)
+                        && rows.first().is_some_and(|row| row.len() != colors.len())
+                    {
+                        return Err(TranError::ConfigError(format!(
+                            "current_color has {} colors, but rows in [colors] have {}",
+                            colors.len(),
+                            rows[0].len()
+                        )));
+                    }
+                }
+                self.current_color = Some(match self.get_mode()? {
+                    Mode::Gradient => match colors.as_slice() {
+                        [color] => ColorOrMap::Color(*color),
+                        _ => return Err(invalid()),
+                    },
+                    Mode::Map => ColorOrMap::Map(colors),
+                });
             }
             Section::TargetFiles => {
-                target_files.push(buff);
+                self.targets.push(Target::parse(line)?);
             }
             Section::Overwrite => {
-                if buff == "true" {
-                    overwrite = true;
+                self.overwrite = line == "true";
+            }
+            Section::Journal => {
+                self.journal = line == "true";
+            }
+            Section::Schedule => {
+                self.schedule
+                    .get_or_insert_with(Schedule::default)
+                    .push_line(line)?;
+            }
+            Section::Constraints => {
+                self.constraints
+                    .get_or_insert_with(Constraints::default)
+                    .push_line(line)?;
+            }
+            Section::SequencesFile => {
+                self.sequences_file = Some(PathBuf::from(line));
+            }
+            Section::DefaultTags => {
+                self.default_tags.extend(tag_list(line));
+            }
+            Section::OutputName => {
+                crate::recolor::validate_output_name(line)?;
+                self.output_name = Some(line.to_string());
+            }
+            Section::Transition => {
+                self.transition
+                    .get_or_insert_with(Transition::default)
+                    .push_line(line)?;
+            }
+            Section::Contrast => {
+                if *self.get_mode()? != Mode::Gradient {
+                    return Err(TranError::ConfigError(
+                        "A contrast section only applies to gradient mode".to_string(),
+                    ));
+                }
+                self.contrast
+                    .get_or_insert_with(|| Contrast::new(Color::black()))
+                    .push_line(line)?;
+            }
+            Section::GradientSpace => {
+                self.gradient_space = line.trim().try_into()?;
+            }
+            Section::GradientOptions => {
+                push_gradient_option(&mut self.luminance_band, line)?;
+            }
+            Section::Stops => {
+                self.stops.push(parse_stop(line)?);
+            }
+            Section::HexCase => {
+                self.text_options.hex_case = line.trim().try_into()?;
+            }
+            Section::TextOptions => {
+                push_text_option(&mut self.text_options, line)?;
+            }
+            Section::Handlers => {
+                push_handler_option(&mut self.ini_options, line)?;
+            }
+            Section::DiscoverOptions => {
+                self.traversal.push_line(line)?;
+            }
+            Section::AllowedRoots => {
+                self.allowed_roots.push(PathBuf::from(line));
+            }
+            Section::RecencyPenalty => {
+                self.recency_penalty
+                    .get_or_insert_with(RecencyPenalty::default)
+                    .push_line(line)?;
+            }
+            Section::Collision => {
+                self.collision = line.trim().try_into()?;
+            }
+            Section::SyncMode => {
+                self.sync_mode = line.trim().try_into()?;
+            }
+            Section::OutputDir => {
+                let (key, value) = line.split_once('=').ok_or_else(|| {
+                    TranError::ConfigError(format!(
+                        "Expected key=value in output_dir, found '{}'",
+                        line
+                    ))
+                })?;
+                let value = PathBuf::from(value.trim());
+                let output_dir = self
+                    .output_dir
+                    .get_or_insert_with(|| OutputDir::new(PathBuf::new()));
+                match key.trim() {
+                    "dir" => output_dir.dir = value,
+                    "base" => output_dir.base = Some(value),
+                    key => {
+                        return Err(TranError::ConfigError(format!(
+                        "Unrecognized output_dir setting '{}', valid settings are 'dir' and 'base'",
+                        key
+                    )))
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build(self) -> Result<Config, TranError> {
+        let mode = self.mode.ok_or(TranError::MissingSection(Section::Mode))?;
+        let colors = self
+            .colors
+            .ok_or(TranError::MissingSection(Section::Colors))?;
+        let current_color = match self.current_index {
+            Some(index) => {
+                let (entries, resolved) = match &colors {
+                    ColorOrMapVec::Color(colors) => (
+                        colors.len(),
+                        colors.get(index).map(|c| ColorOrMap::Color(*c)),
+                    ),
+                    ColorOrMapVec::Map(rows) => (
+                        rows.len(),
+                        match rows.get(index) {
+                            Some(row) => Some(ColorOrMap::Map(
+                                row.iter().copied().collect::<Option<_>>().ok_or_else(|| {
+                                    TranError::ConfigError(format!(
+                                        "current_color refers to @{}, which keeps colors with '-'",
+                                        index
+                                    ))
+                                })?,
+                            )),
+                            None => None,
+                        },
+                    ),
+                };
+                resolved.ok_or_else(|| {
+                    TranError::ConfigError(format!(
+                        "current_color refers to @{}, but [colors] has {} entries, @0 to @{}",
+                        index,
+                        entries,
+                        entries.saturating_sub(1)
+                    ))
+                })?
+            }
+            None => self.current_color.unwrap_or(match mode {
+                Mode::Gradient => ColorOrMap::Color(Color::black()),
+                Mode::Map => ColorOrMap::Map(Vec::new()),
+            }),
+        };
+
+        // A `-` keeps the color of the current row in its slot, so there has to be one
+        if let (ColorOrMap::Map(current), ColorOrMapVec::Map(rows)) = (&current_color, &colors) {
+            let short = rows
+                .iter()
+                .position(|row| row.contains(&None) && row.len() != current.len());
+            if let Some(i) = short {
+                return Err(TranError::ConfigError(format!(
+                    "Row @{} of [colors] keeps colors with '-', which needs a current_color of {} colors, found {}",
+                    i,
+                    rows[i].len(),
+                    current.len()
+                )));
+            }
+            if let Some(row) = rows.first().filter(|row| row.len() != current.len()) {
+                if !current.is_empty() {
+                    return Err(TranError::ConfigError(format!(
+                        "Rows in [colors] have {} colors, but current_color has {}",
+                        row.len(),
+                        current.len()
+                    )));
+                }
+            }
+        }
+
+        if self
+            .output_dir
+            .as_ref()
+            .is_some_and(|output_dir| output_dir.dir.as_os_str().is_empty())
+        {
+            return Err(TranError::ConfigError(
+                "An output_dir section needs a dir".to_string(),
+            ));
+        }
+        let stops = place_stops(&self.stops)?;
+        if !stops.is_empty() && mode == Mode::Map {
+            return Err(TranError::ConfigError(
+                "Stops only apply to gradient mode".to_string(),
+            ));
+        }
+        if self.luminance_band.min > self.luminance_band.max {
+            return Err(TranError::ConfigError(format!(
+                "min_luminance {} is above max_luminance {}",
+                self.luminance_band.min, self.luminance_band.max
+            )));
+        }
+
+        for target in self.targets.iter() {
+            let Some(positions) = &target.colors else {
+                continue;
+            };
+            let ColorOrMapVec::Map(rows) = &colors else {
+                return Err(TranError::ConfigError(format!(
+                    "Target {} picks colors, which only applies to map mode",
+                    target.path.display()
+                )));
+            };
+            let row_len = rows.iter().map(Vec::len).min().unwrap_or(0);
+            if let Some(position) = positions.iter().find(|&&i| i >= row_len) {
+                return Err(TranError::ConfigError(format!(
+                    "Target {} picks color {} but rows in [colors] have {} colors",
+                    target.path.display(),
+                    position,
+                    row_len
+                )));
+            }
+        }
+
+        if let Some(constraints) = &self.constraints {
+            let width = match &colors {
+                ColorOrMapVec::Color(_) => 1,
+                ColorOrMapVec::Map(rows) => rows.iter().map(Vec::len).min().unwrap_or(0),
+            };
+            let outside = constraints
+                .get_rules()
+                .iter()
+                .find(|rule| rule.last_position().is_some_and(|last| last >= width));
+            if let Some(rule) = outside {
+                return Err(TranError::ConfigError(format!(
+                    "Constraint '{}' refers to a color past the {} of a selection",
+                    rule,
+                    match width {
+                        1 => "one color".to_string(),
+                        width => format!("{} colors", width),
+                    }
+                )));
+            }
+        }
+
+        match (mode, current_color, colors) {
+            (Mode::Gradient, ColorOrMap::Color(current_color), ColorOrMapVec::Color(colors)) => {
+                Ok(Config::GradientConfig(GradientConfig {
+                    current_color,
+                    targets: self.targets,
+                    allowed_roots: self.allowed_roots,
+                    output_dir: self.output_dir,
+                    collision: self.collision,
+                    sync_mode: self.sync_mode,
+                    recency_penalty: self.recency_penalty,
+                    journal: self.journal,
+                    schedule: self.schedule,
+                    constraints: self.constraints,
+                    sequences_file: self.sequences_file,
+                    default_tags: self.default_tags,
+                    colors,
+                    weights: self.weights,
+                    tags: self.tags,
+                    overwrite: self.overwrite,
+                    output_name: self.output_name,
+                    transition: self.transition,
+                    contrast: self.contrast,
+                    gradient_space: self.gradient_space,
+                    luminance_band: self.luminance_band,
+                    stops,
+                    text_options: self.text_options,
+                    ini_options: self.ini_options,
+                    traversal: self.traversal,
+                }))
+            }
+            (Mode::Map, ColorOrMap::Map(current_color), ColorOrMapVec::Map(colors)) => {
+                Ok(Config::MapConfig(MapConfig {
+                    current_color,
+                    targets: self.targets,
+                    allowed_roots: self.allowed_roots,
+                    output_dir: self.output_dir,
+                    collision: self.collision,
+                    sync_mode: self.sync_mode,
+                    recency_penalty: self.recency_penalty,
+                    journal: self.journal,
+                    schedule: self.schedule,
+                    constraints: self.constraints,
+                    sequences_file: self.sequences_file,
+                    default_tags: self.default_tags,
+                    colors,
+                    overwrite: self.overwrite,
+                    weights: self.weights,
+                    tags: self.tags,
+                    output_name: self.output_name,
+                    transition: self.transition,
+                    gradient_space: self.gradient_space,
+                    luminance_band: self.luminance_band,
+                    text_options: self.text_options,
+                    ini_options: self.ini_options,
+                    traversal: self.traversal,
+                }))
+            }
+            (mode, ColorOrMap::Color(_), _) | (mode, _, ColorOrMapVec::Color(_)) => {
+                Err(TranError::InconsistentMode {
+                    expected: mode,
+                    found: Mode::Gradient,
+                })
+            }
+            (mode, _, _) => Err(TranError::InconsistentMode {
+                expected: mode,
+                found: Mode::Map,
+            }),
+        }
+    }
+}
+
+/// Says which line of the config a setting that didn't parse is on.
+fn at_line(e: TranError, line: usize) -> TranError {
+    match e {
+        TranError::ConfigError(reason) => {
+            TranError::ConfigError(format!("Line {}: {}", line, reason))
+        }
+        e => e,
+    }
+}
+
+pub fn parse_config_contents(contents: &str) -> Result<Config, TranError> {
+    let mut builder = ConfigBuilder::default();
+    read_config(&mut builder, contents)?;
+    builder.build()
+}
+
+/// `contents` in the form `write_config` writes: sections in a fixed order, settings left at
+/// their default left out, colors in the case of `[hex_case]` and an `@index` in
+/// `[current_color]` replaced by the colors it refers to. Canonicalizing twice changes nothing.
+pub fn canonicalize(contents: &str) -> Result<String, TranError> {
+    Ok(write_config_to_string(parse_config_contents(contents)?))
+}
+
+/// Whether `line` is a section declaration rather than a value that starts with '[', as a
+/// target path can.
+fn is_section_line(line: &str) -> bool {
+    line.starts_with('[') && line.trim_end().ends_with(']')
+}
+
+/// Starts the section `declaration`, a line as in `[colors]` or `[append_colors]`.
+fn enter_section(builder: &mut ConfigBuilder, declaration: &str) -> Result<Section, TranError> {
+    let (name, rest) = declaration[1..].split_once(']').ok_or_else(|| {
+        TranError::ConfigError(format!(
+            "Expected ']' to close the section declaration '{}'",
+            declaration
+        ))
+    })?;
+    let (section, append) = match name.strip_prefix("append_") {
+        Some(name) => (Section::try_from(name)?, true),
+        None => (Section::try_from(name)?, false),
+    };
+    if append && !section.is_list() {
+        return Err(TranError::ConfigError(format!(
+            "Only [colors], [target_files], [allowed_roots], [schedule] and [stops] can be appended to, found [{}]",
+            name
+        )));
+    }
+    if let Some(char) = rest.chars().next() {
+        return Err(TranError::ConfigError(format!(
+            "Expected newline after section declaration, found {}",
+            char
+        )));
+    }
+    builder.enter_section(section, append);
+    Ok(section)
+}
+
+fn read_config(builder: &mut ConfigBuilder, contents: &str) -> Result<(), TranError> {
+    let skipped = contents.len() - contents.trim_start().len();
+    let first_line = 1 + contents[..skipped].matches('\n').count();
+    let contents = contents.trim_start();
+    // Nothing to read, what is missing is for `build` to tell
+    if contents.is_empty() {
+        return Ok(());
+    }
+    if let Some(char) = contents.chars().next().filter(|&c| c != '[') {
+        return Err(TranError::ConfigError(format!(
+            "Expected config to start with '[', found {}",
+            char
+        )));
+    }
+
+    let lines = 1 + contents.bytes().filter(|&b| b == b'\n').count();
+    let mut section = Section::Mode;
+    for (i, line) in contents.split('\n').enumerate() {
+        // The first line can only be a declaration, it starts with '['
+        if i == 0 || is_section_line(line) {
+            section = enter_section(builder, line)?;
+            if section == Section::TargetFiles {
+                // Generated configs list thousands of targets, at most one per line left
+                builder.targets.reserve(lines - i - 1);
+            }
+        } else if !line.trim().is_empty() {
+            builder
+                .push_line(&section, line)
+                .map_err(|e| at_line(e, first_line + i))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "fs")]
+pub fn write_config<T: AsRef<Path>>(config: Config, target: T) -> Result<(), TranError> {
+    let path = target.as_ref();
+    crate::perms::create(path)
+        .and_then(|f| write_config_to(config, &mut std::io::BufWriter::new(f)))
+        .map_err(|source| TranError::WritingConfigError {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+fn write_transition<W: Write>(
+    writer: &mut W,
+    transition: Option<&Transition>,
+    space: GradientSpace,
+) -> Result<(), std::io::Error> {
+    if space != GradientSpace::default() {
+        writeln!(writer, "[gradient_space]")?;
+        writeln!(writer, "{}", space)?;
+    }
+    if let Some(transition) = transition {
+        writeln!(writer, "[transition]")?;
+        writeln!(writer, "duration={}", transition.duration)?;
+        writeln!(writer, "steps={}", transition.steps)?;
+        writeln!(writer, "skip_png={}", transition.skip_png)?;
+    }
+    Ok(())
+}
+
+fn write_gradient_options<W: Write>(
+    writer: &mut W,
+    band: LuminanceBand,
+) -> Result<(), std::io::Error> {
+    if band != LuminanceBand::default() {
+        writeln!(writer, "[gradient_options]")?;
+        writeln!(writer, "min_luminance={}", band.min)?;
+        writeln!(writer, "max_luminance={}", band.max)?;
+    }
+    Ok(())
+}
+
+fn write_text_options<W: Write>(
+    writer: &mut W,
+    options: &TextOptions,
+) -> Result<(), std::io::Error> {
+    let default = TextOptions::default();
+    if options.skip_urls != default.skip_urls
+        || options.skip_hashes != default.skip_hashes
+        || !options.exclude.is_empty()
+    {
+        writeln!(writer, "[text_options]")?;
+        writeln!(writer, "skip_urls={}", options.skip_urls)?;
+        writeln!(writer, "skip_hashes={}", options.skip_hashes)?;
+        for pattern in options.exclude.iter() {
+            writeln!(writer, "exclude={}", pattern)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_handlers<W: Write>(writer: &mut W, options: &IniOptions) -> Result<(), std::io::Error> {
+    if *options != IniOptions::default() {
+        writeln!(writer, "[handlers]")?;
+        writeln!(writer, "ini_keys={}", options.keys.join(","))?;
+        writeln!(writer, "ini_sections={}", options.sections.join(","))?;
+    }
+    Ok(())
+}
+
+fn write_tags<W: Write>(writer: &mut W, tags: &[String]) -> Result<(), std::io::Error> {
+    if !tags.is_empty() {
+        write!(writer, "{}:", tags.join(","))?;
+    }
+    Ok(())
+}
+
+fn write_traversal<W: Write>(writer: &mut W, traversal: Traversal) -> Result<(), std::io::Error> {
+    if traversal != Traversal::default() {
+        writeln!(writer, "[discover_options]")?;
+        writeln!(writer, "hidden={}", traversal.hidden)?;
+        writeln!(writer, "respect_gitignore={}", traversal.respect_gitignore)?;
+        writeln!(writer, "backups={}", traversal.backups)?;
+    }
+    Ok(())
+}
+
+fn write_allowed_roots<W: Write>(writer: &mut W, roots: &[PathBuf]) -> Result<(), std::io::Error> {
+    if !roots.is_empty() {
+        writeln!(writer, "[allowed_roots]")?;
+        for root in roots {
+            writeln!(writer, "{}", root.display())?;
+        }
+    }
+    Ok(())
+}
+
+fn write_output_dir<W: Write>(
+    writer: &mut W,
+    output_dir: Option<&OutputDir>,
+) -> Result<(), std::io::Error> {
+    if let Some(output_dir) = output_dir {
+        writeln!(writer, "[output_dir]")?;
+        writeln!(writer, "dir={}", output_dir.dir.display())?;
+        if let Some(base) = &output_dir.base {
+            writeln!(writer, "base={}", base.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// `config` as `write_config_to` writes it.
+pub fn write_config_to_string(config: Config) -> String {
+    let mut contents = Vec::new();
+    write_config_to(config, &mut contents).expect("writing to a Vec can't fail");
+    String::from_utf8(contents).expect("configs are written as UTF-8")
+}
+
+/// Writes `config` as it would be parsed back, an `@index` in `[current_color]` becoming the
+/// colors it referred to.
+pub fn write_config_to<W: Write>(config: Config, mut writer: W) -> Result<(), std::io::Error> {
+    match config {
+        Config::GradientConfig(config) => {
+            writeln!(&mut writer, "[mode]")?;
+            writeln!(&mut writer, "gradient")?;
+
+            writeln!(&mut writer, "[overwrite]")?;
+            writeln!(&mut writer, "{}", config.get_overwrite())?;
+
+            if let Some(output_name) = config.get_output_name() {
+                writeln!(&mut writer, "[output_name]")?;
+                writeln!(&mut writer, "{}", output_name)?;
+            }
+            if let Some(sequences_file) = config.get_sequences_file() {
+                writeln!(&mut writer, "[sequences_file]")?;
+                writeln!(&mut writer, "{}", sequences_file.display())?;
+            }
+            if !config.get_default_tags().is_empty() {
+                writeln!(&mut writer, "[default_tags]")?;
+                writeln!(&mut writer, "{}", config.get_default_tags().join(","))?;
+            }
+            if config.get_collision() != CollisionPolicy::default() {
+                writeln!(&mut writer, "[collision]")?;
+                writeln!(&mut writer, "{}", config.get_collision())?;
+            }
+            if config.get_sync_mode() != SyncMode::default() {
+                writeln!(&mut writer, "[sync_mode]")?;
+                writeln!(&mut writer, "{}", config.get_sync_mode())?;
+            }
+            if let Some(penalty) = config.get_recency_penalty() {
+                writeln!(&mut writer, "[recency_penalty]")?;
+                writeln!(&mut writer, "runs={}", penalty.runs)?;
+                writeln!(&mut writer, "factor={}", penalty.factor)?;
+            }
+            if config.get_journal() {
+                writeln!(&mut writer, "[journal]")?;
+                writeln!(&mut writer, "true")?;
+            }
+            if let Some(schedule) = config.get_schedule() {
+                writeln!(&mut writer, "[schedule]")?;
+                for entry in schedule.get_entries() {
+                    writeln!(&mut writer, "{}", entry)?;
+                }
+            }
+            if let Some(constraints) = config.get_constraints() {
+                writeln!(&mut writer, "[constraints]")?;
+                for rule in constraints.get_rules() {
+                    writeln!(&mut writer, "{}", rule)?;
+                }
+                if constraints.enforce {
+                    writeln!(&mut writer, "enforce=true")?;
+                }
+            }
+            write_transition(
+                &mut writer,
+                config.get_transition(),
+                config.get_gradient_space(),
+            )?;
+            write_gradient_options(&mut writer, config.get_luminance_band())?;
+            let case = config.get_hex_case();
+            if case != HexCase::default() {
+                writeln!(&mut writer, "[hex_case]")?;
+                writeln!(&mut writer, "{}", case)?;
+            }
+            write_text_options(&mut writer, config.get_text_options())?;
+            write_handlers(&mut writer, config.get_ini_options())?;
+            write_traversal(&mut writer, config.get_traversal())?;
+            if !config.get_stops().is_empty() {
+                writeln!(&mut writer, "[stops]")?;
+                for stop in config.get_stops() {
+                    writeln!(&mut writer, "{}", stop.to_string_in(case))?;
+                }
+            }
+            if let Some(contrast) = config.get_contrast() {
+                writeln!(&mut writer, "[contrast]")?;
+                writeln!(
+                    &mut writer,
+                    "background={}",
+                    contrast.background.to_hex(case)
+                )?;
+                writeln!(&mut writer, "ratio={}", contrast.ratio)?;
+                match contrast.strategy {
+                    ContrastStrategy::Repick => writeln!(&mut writer, "strategy=repick")?,
+                    ContrastStrategy::Adjust => writeln!(&mut writer, "strategy=adjust")?,
+                }
+                writeln!(&mut writer, "attempts={}", contrast.attempts)?;
+            }
+
+            writeln!(&mut writer, "[current_color]")?;
+            writeln!(&mut writer, "{}", config.get_current_color().to_hex(case))?;
+
+            writeln!(&mut writer, "[colors]")?;
+            for (i, color) in config.get_colors().iter().enumerate() {
+                write_tags(&mut writer, config.get_tags(i))?;
+                match config.get_weight(i) {
+                    1 => writeln!(&mut writer, "{}", color.to_hex(case))?,
+                    w => writeln!(&mut writer, "{}{}", w, color.to_hex(case))?,
+                }
+            }
+
+            write_allowed_roots(&mut writer, config.get_allowed_roots())?;
+            write_output_dir(&mut writer, config.get_output_dir())?;
+            writeln!(&mut writer, "[target_files]")?;
+            for target in config.get_targets() {
+                writeln!(&mut writer, "{}", target)?;
+            }
+        }
+        Config::MapConfig(config) => {
+            writeln!(&mut writer, "[mode]")?;
+            writeln!(&mut writer, "map")?;
+
+            writeln!(&mut writer, "[overwrite]")?;
+            writeln!(&mut writer, "{}", config.get_overwrite())?;
+
+            if let Some(output_name) = config.get_output_name() {
+                writeln!(&mut writer, "[output_name]")?;
+                writeln!(&mut writer, "{}", output_name)?;
+            }
+            if let Some(sequences_file) = config.get_sequences_file() {
+                writeln!(&mut writer, "[sequences_file]")?;
+                writeln!(&mut writer, "{}", sequences_file.display())?;
+            }
+            if !config.get_default_tags().is_empty() {
+                writeln!(&mut writer, "[default_tags]")?;
+                writeln!(&mut writer, "{}", config.get_default_tags().join(","))?;
+            }
+            if config.get_collision() != CollisionPolicy::default() {
+                writeln!(&mut writer, "[collision]")?;
+                writeln!(&mut writer, "{}", config.get_collision())?;
+            }
+            if config.get_sync_mode() != SyncMode::default() {
+                writeln!(&mut writer, "[sync_mode]")?;
+                writeln!(&mut writer, "{}", config.get_sync_mode())?;
+            }
+            if let Some(penalty) = config.get_recency_penalty() {
+                writeln!(&mut writer, "[recency_penalty]")?;
+                writeln!(&mut writer, "runs={}", penalty.runs)?;
+                writeln!(&mut writer, "factor={}", penalty.factor)?;
+            }
+            if config.get_journal() {
+                writeln!(&mut writer, "[journal]")?;
+                writeln!(&mut writer, "true")?;
+            }
+            if let Some(schedule) = config.get_schedule() {
+                writeln!(&mut writer, "[schedule]")?;
+                for entry in schedule.get_entries() {
+                    writeln!(&mut writer, "{}", entry)?;
+                }
+            }
+            if let Some(constraints) = config.get_constraints() {
+                writeln!(&mut writer, "[constraints]")?;
+                for rule in constraints.get_rules() {
+                    writeln!(&mut writer, "{}", rule)?;
+                }
+                if constraints.enforce {
+                    writeln!(&mut writer, "enforce=true")?;
+                }
+            }
+            write_transition(
+                &mut writer,
+                config.get_transition(),
+                config.get_gradient_space(),
+            )?;
+            write_gradient_options(&mut writer, config.get_luminance_band())?;
+            let case = config.get_hex_case();
+            if case != HexCase::default() {
+                writeln!(&mut writer, "[hex_case]")?;
+                writeln!(&mut writer, "{}", case)?;
+            }
+            write_text_options(&mut writer, config.get_text_options())?;
+            write_handlers(&mut writer, config.get_ini_options())?;
+            write_traversal(&mut writer, config.get_traversal())?;
+
+            if !config.get_current_colors().is_empty() {
+                writeln!(&mut writer, "[current_color]")?;
+                for color in config.get_current_colors() {
+                    write!(&mut writer, "{}", color.to_hex(case))?;
+                }
+                writeln!(&mut writer)?;
+            }
+
+            writeln!(&mut writer, "[colors]")?;
+            for (i, color_row) in config.get_rows().iter().enumerate() {
+                write_tags(&mut writer, config.get_tags(i))?;
+                if config.get_weight(i) != 1 {
+                    write!(&mut writer, "{}", config.get_weight(i))?;
+                }
+                for color in color_row {
+                    match color {
+                        Some(color) => write!(&mut writer, "{}", color.to_hex(case))?,
+                        None => write!(&mut writer, "-")?,
+                    }
                 }
+                writeln!(&mut writer)?;
+            }
+
+            write_allowed_roots(&mut writer, config.get_allowed_roots())?;
+            write_output_dir(&mut writer, config.get_output_dir())?;
+            writeln!(&mut writer, "[target_files]")?;
+            for target in config.get_targets() {
+                writeln!(&mut writer, "{}", target)?;
             }
         }
     }
 
-    match (
-        mode.ok_or(TranError::ConfigError("Missing mode".to_string()))?,
-        current_color,
-        colors.ok_or(TranError::ConfigError("Missing colors".to_string()))?,
-    ) {
-        (Mode::Gradient, ColorOrMap::Color(current_color), ColorOrMapVec::Color(colors)) => {
-            Ok(Config::GradientConfig(GradientConfig {
-                current_color,
-                target_files,
-                colors,
-                weights,
-                overwrite,
-            }))
-        }
-        (Mode::Map, ColorOrMap::Map(current_color), ColorOrMapVec::Map(colors)) => {
-            Ok(Config::MapConfig(MapConfig {
-                current_color,
-                target_files,
-                colors,
-                overwrite,
-                weights,
-            }))
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::{Fixed, RngLike, SystemRng};
+
+    fn map_config(current: &str, rows: &[&str]) -> String {
+        format!(
+            "[mode]\nmap\n[current_color]\n{}\n[colors]\n{}\n",
+            current,
+            rows.join("\n")
+        )
+    }
+
+    fn config_error(contents: &str) -> String {
+        match parse_config_contents(contents) {
+            Err(TranError::ConfigError(reason)) => reason,
+            other => panic!("expected a config error, got {:?}", other.map(|_| ())),
         }
-        (_, _, _) => Err(TranError::ConfigError("Inconsistent state".to_string())),
     }
-}
 
-pub fn write_config<T: AsRef<Path>>(config: Config, target: T) -> Result<(), TranError> {
-    let f = std::fs::File::create(target)?;
-    let mut writer = std::io::BufWriter::new(f);
+    fn gradient_config(current: &str, colors: &[&str]) -> Config {
+        let contents = format!(
+            "[mode]\ngradient\n[current_color]\n{}\n[colors]\n{}\n",
+            current,
+            colors.join("\n")
+        );
+        parse_config_contents(&contents).unwrap()
+    }
 
-    match config {
-        Config::GradientConfig(config) => {
-            writeln!(&mut writer, "[mode]")?;
-            writeln!(&mut writer, "gradient")?;
+    fn selected(config: &Config, value: u64) -> String {
+        config
+            .select_next(&mut Fixed(vec![value]))
+            .unwrap()
+            .to_string()
+    }
 
-            writeln!(&mut writer, "[overwrite]")?;
-            writeln!(&mut writer, "{}", config.get_overwrite())?;
+    #[test]
+    fn selection_follows_the_weights() {
+        let config = gradient_config("#000000", &["#aaaaaa", "3#bbbbbb", "2#cccccc"]);
+        let picks: Vec<String> = (0..6).map(|i| selected(&config, i)).collect();
+        assert_eq!(
+            picks,
+            ["#aaaaaa", "#bbbbbb", "#bbbbbb", "#bbbbbb", "#cccccc", "#cccccc"]
+        );
+    }
 
-            writeln!(&mut writer, "[current_color]")?;
-            writeln!(&mut writer, "{}", config.get_current_color())?;
+    #[test]
+    fn selection_skips_the_current_color() {
+        let config = gradient_config("#aaaaaa", &["5#aaaaaa", "#bbbbbb"]);
+        for i in 0..6 {
+            assert_eq!(selected(&config, i), "#bbbbbb");
+        }
+        let config = parse_config_contents(&map_config(
+            "#111111#222222",
+            &["#111111#222222", "#333333#444444"],
+        ))
+        .unwrap();
+        for i in 0..3 {
+            assert_eq!(selected(&config, i), "#333333#444444");
+        }
+    }
 
-            writeln!(&mut writer, "[colors]")?;
-            for color in config.get_colors() {
-                writeln!(&mut writer, "{}", color)?;
+    #[test]
+    fn selection_without_candidates_fails() {
+        let config = gradient_config("#aaaaaa", &["0#aaaaaa", "0#bbbbbb"]);
+        let Err(TranError::ConfigError(reason)) = config.select_next(&mut Fixed(vec![])) else {
+            panic!("expected a config error");
+        };
+        assert_eq!(
+            reason,
+            "No color to select, every entry in [colors] has weight 0"
+        );
+        // The current color can be picked again, the run then writes nothing
+        let config = gradient_config("#aaaaaa", &["#aaaaaa"]);
+        assert_eq!(
+            config.select_next(&mut Fixed(vec![])).unwrap(),
+            Selection::Color(Color::try_from_hex_str("#aaaaaa").unwrap())
+        );
+        let config =
+            parse_config_contents(&map_config("#111111", &["0#aaaaaa", "0#bbbbbb"])).unwrap();
+        assert!(config.select_next(&mut Fixed(vec![])).is_err());
+    }
+
+    #[test]
+    fn a_lone_current_color_stays() {
+        let config = gradient_config("#aaaaaa", &["#aaaaaa"]);
+        assert_eq!(selected(&config, 3), "#aaaaaa");
+    }
+
+    #[test]
+    fn a_lone_current_row_stays_unless_weighted_out() {
+        let config =
+            parse_config_contents(&map_config("#111111#222222", &["#111111#222222"])).unwrap();
+        assert_eq!(selected(&config, 3), "#111111#222222");
+
+        let config =
+            parse_config_contents(&map_config("#111111#222222", &["0#111111#222222"])).unwrap();
+        assert_eq!(
+            config
+                .select_next(&mut Fixed(vec![3]))
+                .unwrap_err()
+                .to_string(),
+            "Error reading config: No color row to select, every row in [colors] has weight 0"
+        );
+        let config = gradient_config("#aaaaaa", &["0#aaaaaa", "0#bbbbbb"]);
+        assert!(config
+            .select_next(&mut Fixed(vec![3]))
+            .unwrap_err()
+            .to_string()
+            .contains("every entry in [colors] has weight 0"));
+    }
+
+    #[test]
+    fn luminance_bounds_read_as_lumas_or_fractions() {
+        let band = |options: &str| {
+            parse_config_contents(&format!(
+                "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[gradient_options]\n{}\n",
+                options
+            ))
+            .map(|config| config.get_luminance_band())
+        };
+        assert_eq!(
+            band("min_luminance=0.5\nmax_luminance = 200").unwrap(),
+            LuminanceBand { min: 128, max: 200 }
+        );
+        let config = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[gradient_options]\nmax_luminance=1.0\nmin_luminance=1.\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_luminance_band(),
+            LuminanceBand { min: 255, max: 255 }
+        );
+        let written = write_config_to_string(config.clone());
+        assert!(written.contains("min_luminance=255\n"), "{}", written);
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let error = |options: &str| band(options).unwrap_err().to_string();
+        assert!(error("min_luminance=256").contains("from 0 to 255 or 0.0 to 1.0"));
+        assert!(error("min_luminance=1.5").contains("from 0 to 255 or 0.0 to 1.0"));
+        assert!(error("min_luminance=200\nmax_luminance=100")
+            .contains("min_luminance 200 is above max_luminance 100"));
+        assert!(error("gamma=2").contains("Unrecognized gradient_options setting 'gamma'"));
+    }
+
+    #[test]
+    fn hex_colors_take_0x_shorthand_and_padding() {
+        let nord = color("#2e3440");
+        for input in ["#2e3440", "2e3440", "0x2E3440", "0X2e3440", "  #2E3440\t"] {
+            assert_eq!(Color::try_from_hex_str(input).unwrap(), nord, "{}", input);
+        }
+        assert_eq!(color("#abc"), color("#aabbcc"));
+        assert_eq!(color("0xfff"), Color::from_bytes(255, 255, 255));
+        assert_eq!(
+            split_colors("2 0x2e3440#abc").unwrap(),
+            (Some(2), vec![nord, color("#aabbcc")])
+        );
+        // Multibyte characters are refused rather than sliced through
+        for input in ["#é2345", "#2e34", "0x", "#2e3440ff"] {
+            assert!(Color::try_from_hex_str(input).is_err(), "{}", input);
+        }
+        assert!(Color::try_from_hex_str("#abcd")
+            .unwrap_err()
+            .to_string()
+            .contains("'#abcd'"));
+    }
+
+    #[test]
+    fn current_color_can_name_an_entry_of_colors() {
+        let config = gradient_config("@1", &["#aaaaaa", "#bbbbbb"]);
+        assert_eq!(config.get_current_colors(), [color("#bbbbbb")]);
+        let written = write_config_to_string(config.clone());
+        assert!(
+            written.contains("[current_color]\n#bbbbbb\n"),
+            "{}",
+            written
+        );
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let map = parse_config_contents(&map_config("@ 0", &["#111111#222222", "#333333#444444"]))
+            .unwrap();
+        assert_eq!(
+            map.get_current_colors(),
+            [color("#111111"), color("#222222")]
+        );
+        assert_eq!(selected(&map, 0), "#333333#444444");
+
+        assert_eq!(
+            config_error(&map_config("@2", &["#111111", "#222222"])),
+            "current_color refers to @2, but [colors] has 2 entries, @0 to @1"
+        );
+        assert!(config_error(&map_config("@first", &["#111111"]))
+            .contains("Expected an index of [colors] after '@', found 'first'"));
+    }
+
+    #[test]
+    fn stops_without_positions_are_spread_between_the_others() {
+        let config = gradient_config(
+            "#000000",
+            &["#bf616a\n[stops]\n#000000\n0.2:#2e3440\n#4c566a\nprimary\n0.8:#88c0d0\n#eceff4"],
+        );
+        let positions: Vec<f64> = config.get_stops().iter().map(|s| s.position).collect();
+        for (position, expected) in positions.iter().zip([0.0, 0.2, 0.4, 0.6, 0.8, 1.0]) {
+            assert!((position - expected).abs() < 1e-9, "{:?}", positions);
+        }
+        let colors: Vec<Option<Color>> = config.get_stops().iter().map(|s| s.color).collect();
+        assert_eq!(colors.len(), 6);
+        assert_eq!(colors[2], Some(color("#4c566a")));
+        assert_eq!(colors[3], None);
+        let written = write_config_to_string(config.clone());
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let error = |stops: &str| {
+            config_error(&format!(
+                "[mode]\ngradient\n[colors]\n#bf616a\n[stops]\n{}\n",
+                stops
+            ))
+        };
+        assert!(error("0.6:#000000\n0.4:#ffffff")
+            .contains("Stop positions have to rise, but stop 2 at 0.4 comes after 0.6"));
+        assert!(error("1.5:#000000").contains("from 0.0 to 1.0, found '1.5'"));
+        assert_eq!(
+            config_error(&map_config("#000000", &["#bf616a\n[stops]\nprimary"])),
+            "Stops only apply to gradient mode"
+        );
+    }
+
+    #[test]
+    fn hues_turn_around_the_wheel() {
+        let red = color("#ff0000");
+        assert_eq!(red.complementary(), color("#00ffff"));
+        assert_eq!(red.triadic(), [color("#00ff00"), color("#0000ff")]);
+        assert_eq!(red.rotate_hue(-360.0), red);
+        assert_eq!(
+            red.analogous(3, 60.0),
+            [color("#ff00ff"), color("#ffff00"), color("#0000ff")]
+        );
+        assert_eq!(red.analogous(0, 30.0), []);
+        // Greys have no hue to turn
+        let grey = color("#808080");
+        assert_eq!(grey.complementary(), grey);
+        let nord = color("#bf616a");
+        let [hue, saturation, lightness] = crate::space::to_hsl(&nord);
+        let [turned, s, l] = crate::space::to_hsl(&nord.complementary());
+        assert!(
+            ((hue + 180.0) % 360.0 - turned).abs() < 1.0,
+            "{} {}",
+            hue,
+            turned
+        );
+        assert!((saturation - s).abs() < 0.01 && (lightness - l).abs() < 0.01);
+    }
+
+    #[test]
+    fn hex_case_sets_the_case_of_written_colors() {
+        let config = gradient_config("#2e3440", &["#bf616a\n[hex_case]\nupper"]);
+        assert_eq!(config.get_hex_case(), HexCase::Upper);
+        let written = write_config_to_string(config.clone());
+        assert!(
+            written.contains("[current_color]\n#2E3440\n"),
+            "{}",
+            written
+        );
+        assert!(written.contains("#BF616A"), "{}", written);
+        assert!(written.contains("[hex_case]\nupper\n"), "{}", written);
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let lower = write_config_to_string(gradient_config("#2E3440", &["#BF616A"]));
+        assert!(!lower.contains("[hex_case]"), "{}", lower);
+        assert!(lower.contains("#2e3440"), "{}", lower);
+        assert!(
+            config_error(&map_config("#000000", &["#ffffff\n[hex_case]\ntitle"]))
+                .contains("'title'")
+        );
+    }
+
+    #[test]
+    fn weights_are_capped_and_kept_with_their_colors() {
+        let config = gradient_config("#000000", &["10000#aaaaaa", "#000000", "0#bbbbbb"]);
+        let Config::GradientConfig(gc) = &config else {
+            panic!("expected a gradient config");
+        };
+        let aaaaaa = Color::from_bytes(0xaa, 0xaa, 0xaa);
+        let bbbbbb = Color::from_bytes(0xbb, 0xbb, 0xbb);
+        assert_eq!(gc.get_weighted_colors(), [(&aaaaaa, 10_000), (&bbbbbb, 0)]);
+        #[allow(deprecated)]
+        let scaled = gc.get_colors_scaled();
+        assert_eq!(scaled.len(), 10_000);
+
+        assert_eq!(
+            config_error(&map_config("#111111", &["10001#aaaaaa"])),
+            "Line 6: The weight 10001 of '10001#aaaaaa' is above the largest one, 10000"
+        );
+        assert!(
+            config_error(&map_config("#111111", &["99999999999999999999999#aaaaaa"]))
+                .contains("is above the largest one")
+        );
+
+        let contents = map_config("#111111", &["2#aaaaaa", "#bbbbbb"]);
+        let Config::MapConfig(mc) = parse_config_contents(&contents).unwrap() else {
+            panic!("expected a map config");
+        };
+        assert_eq!(
+            mc.get_weighted_colors(),
+            [(vec![aaaaaa], 2), (vec![bbbbbb], 1)]
+        );
+        #[allow(deprecated)]
+        let scaled = mc.get_colors_scaled();
+        assert_eq!(scaled, [vec![aaaaaa], vec![aaaaaa], vec![bbbbbb]]);
+    }
+
+    #[test]
+    fn handlers_name_the_ini_keys_and_sections() {
+        let contents = "[mode]\ngradient\n[current_color]\n#000000\n[handlers]\nini_keys=bg, fg\nini_sections=*\n[colors]\n#111111\n";
+        let config = parse_config_contents(contents).unwrap();
+        assert_eq!(config.get_ini_options().keys, ["bg", "fg"]);
+        assert_eq!(config.get_ini_options().sections, ["*"]);
+        let written = write_config_to_string(config.clone());
+        assert!(
+            written.contains("[handlers]\nini_keys=bg,fg\nini_sections=*\n"),
+            "{}",
+            written
+        );
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let config = gradient_config("#000000", &["#111111"]);
+        assert_eq!(*config.get_ini_options(), IniOptions::default());
+        assert!(!write_config_to_string(config).contains("[handlers]"));
+        assert!(config_error(&contents.replace("ini_sections", "ini_files"))
+            .ends_with("Unrecognized handlers setting 'ini_files', valid settings are 'ini_keys' and 'ini_sections'"));
+        assert!(
+            config_error(&contents.replace("ini_sections=*", "ini_sections"))
+                .ends_with("Expected key=value in handlers, found 'ini_sections'")
+        );
+    }
+
+    #[test]
+    fn sync_modes_round_trip() {
+        let contents =
+            "[mode]\ngradient\n[current_color]\n#000000\n[sync_mode]\ndetect\n[colors]\n#111111\n";
+        let config = parse_config_contents(contents).unwrap();
+        assert_eq!(config.get_sync_mode(), SyncMode::Detect);
+        let written = write_config_to_string(config.clone());
+        assert!(written.contains("[sync_mode]\ndetect\n"), "{}", written);
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let config = gradient_config("#000000", &["#111111"]);
+        assert_eq!(config.get_sync_mode(), SyncMode::Assume);
+        assert!(!write_config_to_string(config).contains("[sync_mode]"));
+        assert!(config_error(&contents.replace("detect", "guess"))
+            .ends_with("Unrecognized sync mode 'guess', valid modes are 'assume' and 'detect'"));
+    }
+
+    #[test]
+    fn tags_narrow_the_picks() {
+        let colors = [
+            "dark, blue:#111111",
+            "dark:2#222222",
+            "light:#eeeeee",
+            "#aaaaaa",
+        ];
+        let config = gradient_config("#000000", &colors);
+        assert_eq!(config.get_tags(0), ["dark", "blue"]);
+        assert_eq!(config.get_tags(1), ["dark"]);
+        assert!(config.get_tags(3).is_empty());
+
+        let tagged = |tags: &[&str], value| {
+            let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+            config
+                .select_tagged(&mut Fixed(vec![value]), &[], &tags)
+                .map(|(selection, _)| selection.to_string())
+        };
+        let dark: Vec<String> = (0..3).map(|i| tagged(&["dark"], i).unwrap()).collect();
+        assert_eq!(dark, ["#111111", "#222222", "#222222"]);
+        assert_eq!(tagged(&["blue", "dark"], 1).unwrap(), "#111111");
+        match tagged(&["dim"], 0) {
+            Err(TranError::ConfigError(reason)) => {
+                assert_eq!(reason, "No entry in [colors] is tagged dim")
+            }
+            other => panic!("expected a config error, got {:?}", other),
+        }
+
+        let contents = "[mode]\ngradient\n[current_color]\n#000000\n[default_tags]\nlight\n[colors]\ndark:#111111\nlight:#eeeeee\n";
+        let config = parse_config_contents(contents).unwrap();
+        assert_eq!(config.get_default_tags(), ["light"]);
+        assert_eq!(selected(&config, 0), "#eeeeee");
+        let written = write_config_to_string(config.clone());
+        assert!(written.contains("[default_tags]\nlight\n"), "{}", written);
+        assert!(written.contains("dark:#111111"), "{}", written);
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+    }
+
+    #[test]
+    fn tran_colors_read_as_the_next_selection() {
+        let gradient = gradient_config("#111111", &["#222222"]);
+        assert_eq!(
+            gradient.parse_selection("#abcdef").unwrap().to_string(),
+            "#abcdef"
+        );
+        let error = |config: &Config, colors: &str| config.parse_selection(colors).unwrap_err();
+        assert_eq!(
+            error(&gradient, "#abcdef#123456").to_string(),
+            "Can't recolor to TRAN_COLOR '#abcdef#123456', it has 2 colors but gradient mode takes one"
+        );
+        assert!(error(&gradient, "3#abcdef")
+            .to_string()
+            .contains("it can't have a weight"));
+        assert!(matches!(
+            error(&gradient, "abcdef"),
+            TranError::InvalidHexColor {
+                reason: HexError::MissingPrefix,
+                ..
             }
+        ));
 
-            writeln!(&mut writer, "[target_files]")?;
-            for target in config.get_target_files() {
-                writeln!(&mut writer, "{}", target)?;
+        let map =
+            parse_config_contents(&map_config("#111111#222222", &["#aaaaaa#bbbbbb"])).unwrap();
+        let Selection::Row(row) = map.parse_selection("#333333#444444").unwrap() else {
+            panic!("expected a row");
+        };
+        assert_eq!(row.len(), 2);
+        assert!(error(&map, "#333333")
+            .to_string()
+            .contains("it has 1 colors but rows in map mode have 2"));
+    }
+
+    #[test]
+    fn text_options_round_trip_and_reject_unknown_settings() {
+        let config = |section: &str| {
+            parse_config_contents(&format!(
+                "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[text_options]\n{}\n",
+                section
+            ))
+        };
+        let read = config("skip_urls=false\nexclude=icon-*\nexclude=*.svg").unwrap();
+        let options = read.get_text_options();
+        assert!(!options.skip_urls && options.skip_hashes);
+        assert_eq!(options.exclude, ["icon-*", "*.svg"]);
+        let written = write_config_to_string(read.clone());
+        assert!(
+            written.contains("exclude=icon-*\nexclude=*.svg\n"),
+            "{}",
+            written
+        );
+        assert_eq!(parse_config_contents(&written).unwrap(), read);
+
+        let defaults = write_config_to_string(config("skip_urls=true").unwrap());
+        assert!(!defaults.contains("[text_options]"), "{}", defaults);
+
+        let error = |section: &str| config(section).unwrap_err().to_string();
+        assert!(error("skip_hashes=yes").contains("Expected true or false for skip_hashes"));
+        assert!(error("skip_css=true").contains("Unrecognized text_options setting 'skip_css'"));
+        assert!(error("skip_urls").contains("Expected key=value in text_options"));
+    }
+
+    #[test]
+    fn declarations_and_line_numbers_are_read_line_by_line() {
+        assert_eq!(
+            config_error("[mode\ngradient\n"),
+            "Expected ']' to close the section declaration '[mode'"
+        );
+        assert_eq!(
+            config_error("[mode] gradient\n"),
+            "Expected newline after section declaration, found  "
+        );
+        assert_eq!(
+            config_error("mode\n"),
+            "Expected config to start with '[', found m"
+        );
+        assert_eq!(
+            config_error("\n\n[mode]\ngradient\n\n[overwrite]\ntrue\n[output_dir]\nroot=/\n"),
+            "Line 9: Unrecognized output_dir setting 'root', valid settings are 'dir' and 'base'"
+        );
+
+        // Only a line closed by ']' declares a section
+        let config = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\n[old] theme.css\n",
+        )
+        .unwrap();
+        assert_eq!(config.get_target_files(), ["[old] theme.css"]);
+    }
+
+    #[test]
+    fn discover_options_round_trip() {
+        let config = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[discover_options]\nhidden=true\n",
+        )
+        .unwrap();
+        assert!(config.get_traversal().hidden);
+        assert!(!config.get_traversal().respect_gitignore);
+        let written = write_config_to_string(config.clone());
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+        assert!(
+            !write_config_to_string(gradient_config("#111111", &["#222222"]))
+                .contains("[discover_options]")
+        );
+        assert!(config_error(
+            "[mode]\ngradient\n[colors]\n#222222\n[discover_options]\nhidden=maybe\n"
+        )
+        .contains("Expected true or false for hidden, found 'maybe'"));
+    }
+
+    #[test]
+    fn missing_sections_and_mixed_modes_can_be_matched() {
+        let error = |contents: &str| parse_config_contents(contents).unwrap_err();
+        let missing = error("[colors]\n#aaaaaa\n");
+        assert!(
+            matches!(missing, TranError::MissingSection(Section::Mode)),
+            "{:?}",
+            missing
+        );
+        assert!(missing
+            .to_string()
+            .contains("Missing a [mode] section before the colors"));
+        let missing = error("[mode]\ngradient\n[current_color]\n#aaaaaa\n");
+        assert!(
+            matches!(missing, TranError::MissingSection(Section::Colors)),
+            "{:?}",
+            missing
+        );
+        assert_eq!(
+            missing.to_string(),
+            "Error reading config: Missing a [colors] section"
+        );
+
+        let mixed =
+            error("[mode]\ngradient\n[colors]\n#aaaaaa\n[mode]\nmap\n[colors]\n#bbbbbb#cccccc\n");
+        assert!(
+            matches!(
+                mixed,
+                TranError::InconsistentMode {
+                    expected: Mode::Gradient,
+                    found: Mode::Map
+                }
+            ),
+            "{:?}",
+            mixed
+        );
+        assert_eq!(
+            mixed.to_string(),
+            "Error reading config: Found map mode settings in a config in gradient mode"
+        );
+    }
+
+    #[test]
+    fn decimal_colors_read_like_hex_ones() {
+        assert_eq!(
+            split_colors("3 46,52,64 rgb(191, 97, 106)#88c0d0").unwrap(),
+            (
+                Some(3),
+                vec![color("#2e3440"), color("#bf616a"), color("#88c0d0")]
+            )
+        );
+        assert_eq!(
+            split_colors("46, 52 ,64").unwrap(),
+            (None, vec![color("#2e3440")])
+        );
+
+        let config = gradient_config("rgb(0,0,0)", &["2 46,52,64", "#bf616a"]);
+        assert_eq!(config.get_current_colors(), [color("#000000")]);
+        let written = write_config_to_string(config.clone());
+        assert!(written.contains("#2e3440"), "{}", written);
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let map = parse_config_contents(&map_config(
+            "0,0,0 255,255,255",
+            &["rgb(46,52,64) 236,239,244"],
+        ))
+        .unwrap();
+        assert_eq!(
+            map.get_selections(),
+            [Selection::Row(vec![color("#2e3440"), color("#eceff4")])]
+        );
+
+        assert!(Color::try_from_triplet_str("256,0,0").is_err());
+        assert!(Color::try_from_triplet_str("1,2").is_err());
+        assert!(split_colors("rgb(1,2,3").is_err());
+        assert_eq!(
+            config_error(&map_config("4 1,2,3", &["#aaaaaa"])),
+            "Line 4: Could not read '4 1,2,3' as the current color"
+        );
+    }
+
+    fn color(hex: &str) -> Color {
+        Color::try_from_hex_str(hex).unwrap()
+    }
+
+    #[test]
+    fn contrast_ratios_match_wcag() {
+        let ratio = |a: &str, b: &str| color(a).contrast_ratio(&color(b));
+        assert!((ratio("#000000", "#ffffff") - 21.0).abs() < 1e-9);
+        assert!((ratio("#ffffff", "#000000") - 21.0).abs() < 1e-9);
+        assert_eq!(ratio("#88c0d0", "#88c0d0"), 1.0);
+        // The greys WCAG tools give as just under and just over 4.5 on white
+        assert!((ratio("#777777", "#ffffff") - 4.48).abs() < 0.01);
+        assert!((ratio("#767676", "#ffffff") - 4.54).abs() < 0.01);
+        assert!((ratio("#ff0000", "#ffffff") - 4.0).abs() < 0.01);
+        assert!((color("#ffffff").relative_luminance() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn colors_are_dark_below_the_luminance_where_black_and_white_tie() {
+        assert_eq!(color("#000000").relative_luminance(), 0.0);
+        // The reference luminance of pure red and green
+        assert!((color("#ff0000").relative_luminance() - 0.2126).abs() < 1e-4);
+        assert!((color("#00ff00").relative_luminance() - 0.7152).abs() < 1e-4);
+        assert!(color("#2e3440").is_dark());
+        assert!(color("#0000ff").is_dark());
+        assert!(!color("#88c0d0").is_dark());
+        assert!(!color("#ffff00").is_dark());
+        // #757575 and #767676 sit on either side of a luminance of 0.179
+        assert!(color("#757575").is_dark());
+        assert!(!color("#767676").is_dark());
+    }
+
+    #[test]
+    fn adjusting_for_contrast_always_ends() {
+        let greys = (0..=255).step_by(15).map(|v| Color::from_bytes(v, v, v));
+        let colors: Vec<Color> = greys
+            .chain([
+                color("#ff0000"),
+                color("#00ff00"),
+                color("#0000ff"),
+                color("#88c0d0"),
+            ])
+            .collect();
+        for background in colors.iter() {
+            for color in colors.iter() {
+                for ratio in [1.0, 3.0, 4.5, 7.0, 15.0, 21.0] {
+                    let adjusted = color.with_contrast(background, ratio);
+                    let best = Color::black()
+                        .contrast_ratio(background)
+                        .max(Color::white().contrast_ratio(background));
+                    if ratio <= best {
+                        assert!(
+                            adjusted.contrast_ratio(background) >= ratio,
+                            "{} on {} at {}",
+                            adjusted,
+                            background,
+                            ratio
+                        );
+                    } else {
+                        assert!([Color::black(), Color::white()].contains(&adjusted));
+                    }
+                }
             }
         }
-        Config::MapConfig(config) => {
-            writeln!(&mut writer, "[mode]")?;
-            writeln!(&mut writer, "gradient")?;
+        let passing = color("#eeeeee");
+        assert_eq!(passing.with_contrast(&Color::black(), 4.5), passing);
+    }
 
-            writeln!(&mut writer, "[overwrite]")?;
-            writeln!(&mut writer, "{}", config.get_overwrite())?;
+    #[test]
+    fn picks_lacking_contrast_are_repicked_or_adjusted() {
+        let contrast = |strategy: &str| {
+            let contents = format!(
+                "[mode]\ngradient\n[current_color]\n#000000\n[colors]\n#222222\n#eeeeee\n[contrast]\nbackground=#1e1e1e\nratio=4.5\nstrategy={}\n",
+                strategy
+            );
+            parse_config_contents(&contents).unwrap()
+        };
 
-            writeln!(&mut writer, "[current_color]")?;
-            for color in config.get_current_colors() {
-                write!(&mut writer, "{}", color)?;
+        let (picked, fix) = contrast("repick")
+            .select_contrasting(&mut Fixed(vec![0, 1]), &[])
+            .unwrap();
+        assert_eq!(picked, Selection::Color(color("#eeeeee")));
+        assert_eq!(fix, Some(ContrastFix::Repicked(vec![color("#222222")])));
+
+        let (picked, fix) = contrast("adjust")
+            .select_contrasting(&mut Fixed(vec![0]), &[])
+            .unwrap();
+        let Selection::Color(adjusted) = picked else {
+            panic!("expected a color");
+        };
+        assert!(adjusted.contrast_ratio(&color("#1e1e1e")) >= 4.5);
+        assert!(adjusted.relative_luminance() > color("#222222").relative_luminance());
+        assert_eq!(
+            fix.unwrap().to_string(),
+            format!("Adjusted #222222 to {} for enough contrast", adjusted)
+        );
+
+        let (picked, fix) = contrast("adjust")
+            .select_contrasting(&mut Fixed(vec![1]), &[])
+            .unwrap();
+        assert_eq!((picked, fix), (Selection::Color(color("#eeeeee")), None));
+    }
+
+    #[test]
+    fn recently_picked_colors_are_less_likely() {
+        let config = |penalty: &str| {
+            parse_config_contents(&format!(
+                "[mode]\ngradient\n[current_color]\n#000000\n[colors]\n#222222\n#333333\n[recency_penalty]\n{}\n",
+                penalty
+            ))
+        };
+        let pick = |config: &Config, recent: &[Vec<Color>], draw: u64| {
+            config
+                .select_contrasting(&mut Fixed(vec![draw]), recent)
+                .unwrap()
+                .0
+        };
+        let (dark, light) = (color("#222222"), color("#333333"));
+        let halving = config("runs=2\nfactor=0.5").unwrap();
+
+        // #222222 was picked twice, so it is down to a quarter of the weight of #333333
+        let twice = [vec![dark], vec![dark]];
+        assert_eq!(pick(&halving, &twice, 249), Selection::Color(dark));
+        assert_eq!(pick(&halving, &twice, 250), Selection::Color(light));
+        // Only the last `runs` runs count
+        let older = [vec![dark], vec![dark], vec![light], vec![light]];
+        assert_eq!(pick(&halving, &older, 999), Selection::Color(dark));
+        assert_eq!(pick(&halving, &older, 1000), Selection::Color(light));
+
+        let penalty = RecencyPenalty {
+            runs: 5,
+            factor: 0.0,
+        };
+        assert_eq!(penalty.weight(3, &[dark], &twice), 1);
+        assert_eq!(penalty.weight(0, &[light], &twice), 0);
+        assert_eq!(penalty.weight(3, &[light], &twice), 3000);
+
+        assert!(config("factor=1.5")
+            .unwrap_err()
+            .to_string()
+            .contains("between 0 and 1"));
+        assert!(config("decay=2")
+            .unwrap_err()
+            .to_string()
+            .contains("Unrecognized recency_penalty setting 'decay'"));
+    }
+
+    #[test]
+    fn overlays_replace_or_append_to_the_lists_of_the_base() {
+        let gradient = |config: Config| match config {
+            Config::GradientConfig(gc) => gc,
+            Config::MapConfig(_) => panic!("expected a gradient config"),
+        };
+        let hexes = |gc: &GradientConfig| -> Vec<String> {
+            gc.get_colors().iter().map(|c| c.to_hex_lower()).collect()
+        };
+        let base = gradient_config("#111111", &["#aaaaaa", "3#bbbbbb"]);
+
+        let replaced =
+            gradient(Config::merge(base.clone(), "[colors]\n#cccccc\n#dddddd\n").unwrap());
+        assert_eq!(hexes(&replaced), ["#cccccc", "#dddddd"]);
+        assert_eq!(replaced.get_current_color().to_hex_lower(), "#111111");
+
+        let appended = gradient(Config::merge(base.clone(), "[append_colors]\n#cccccc\n").unwrap());
+        assert_eq!(hexes(&appended), ["#aaaaaa", "#bbbbbb", "#cccccc"]);
+        assert_eq!(appended.get_weight(1), 3);
+
+        let Err(TranError::InconsistentMode { expected, found }) =
+            Config::merge(base.clone(), "[mode]\nmap\n")
+        else {
+            panic!("expected an inconsistent mode");
+        };
+        assert_eq!((expected, found), (Mode::Gradient, Mode::Map));
+        assert!(Config::merge(base.clone(), "[mode]\ngradient\n").is_ok());
+
+        let Err(TranError::ConfigError(reason)) =
+            Config::merge(base, "[append_current_color]\n#cccccc\n")
+        else {
+            panic!("expected a config error");
+        };
+        assert!(reason.contains("can be appended to, found [append_current_color]"));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn local_overlays_are_merged_when_next_to_the_config() {
+        let dir = crate::test_util::TempDir::new("local_overlay");
+        let config_path = dir.write(
+            "config",
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#aaaaaa\n",
+        );
+        assert_eq!(
+            local_config_path(&config_path),
+            dir.path().join("config.local")
+        );
+        let colors = |config: Config| match config {
+            Config::GradientConfig(gc) => gc.get_colors().len(),
+            Config::MapConfig(_) => panic!("expected a gradient config"),
+        };
+        assert_eq!(colors(parse_config_with_local(&config_path).unwrap()), 1);
+
+        dir.write("config.local", "[append_colors]\n#bbbbbb\n");
+        assert_eq!(colors(parse_config_with_local(&config_path).unwrap()), 2);
+
+        dir.write("config.local", "[mode]\nmap\n");
+        let error = parse_config_with_local(&config_path).unwrap_err();
+        assert!(error.to_string().contains("config.local"), "{}", error);
+    }
+
+    #[test]
+    fn gradient_configs_equal_themselves_written_and_read() {
+        let config = gradient_config("#2e3440", &["#88c0d0", "3#bf616a", "0#a3be8c"]);
+        let written = write_config_to_string(config.clone());
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+        assert_ne!(
+            config,
+            gradient_config("#2e3440", &["#88c0d0", "2#bf616a", "0#a3be8c"])
+        );
+        assert_ne!(
+            config,
+            gradient_config("#3b4252", &["#88c0d0", "3#bf616a", "0#a3be8c"])
+        );
+    }
+
+    #[test]
+    fn modes_and_sections_hash() {
+        let modes: std::collections::HashSet<Mode> =
+            [Mode::Map, Mode::Gradient, Mode::Map].into_iter().collect();
+        assert_eq!(modes.len(), 2);
+        assert_eq!(format!("{:?}", Section::Mode), "Mode");
+        let a = crate::ColorMap::new("#aaaaaa", "#bbbbbb");
+        assert_eq!(a, a.clone());
+        assert_ne!(a, crate::ColorMap::new("#bbbbbb", "#aaaaaa"));
+    }
+
+    #[test]
+    fn map_configs_equal_themselves_written_and_read() {
+        let contents = map_config("#111111#222222", &["#aaaaaa#bbbbbb", "4#cccccc#dddddd"]);
+        let config = parse_config_contents(&contents).unwrap();
+        let written = write_config_to_string(config.clone());
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+        let reordered = map_config("#111111#222222", &["4#cccccc#dddddd", "#aaaaaa#bbbbbb"]);
+        assert_ne!(parse_config_contents(&reordered).unwrap(), config);
+    }
+
+    #[test]
+    fn target_lines_read_back_as_written() {
+        for line in [
+            "/home/me/.config/gtk.css",
+            "a.conf|format=gtk",
+            "a.json|literals=false|overwrite=true",
+            "bar.css|colors=0,4,7|collision=number",
+            "odd||name.conf|format=text",
+            "hook.conf|exec_timeout=5|exec=sh -c 'sed s/{from}/{to}/ {input} > {output}'",
+        ] {
+            let target = Target::parse(line).unwrap();
+            assert_eq!(target.to_string(), line);
+            assert_eq!(Target::parse(&target.to_string()).unwrap(), target);
+        }
+
+        let target = Target::parse("a b|c.conf|format=css|overwrite=false").unwrap_err();
+        assert!(target.to_string().contains("'c.conf'"), "{}", target);
+        let target = Target::parse("a||b.conf|colors=1,2|format=json").unwrap();
+        assert_eq!(target.path, std::path::Path::new("a|b.conf"));
+        assert_eq!(target.format, Some(FileFormat::Json));
+        assert_eq!(target.colors, Some(vec![1, 2]));
+        assert_eq!(target.overwrite, None);
+    }
+
+    #[test]
+    fn windows_target_paths_keep_their_backslashes() {
+        let line = r"C:\Users\me\gtk-3.0\gtk.css|format=gtk";
+        let target = Target::parse(line).unwrap();
+        assert_eq!(
+            target.path,
+            std::path::Path::new(r"C:\Users\me\gtk-3.0\gtk.css")
+        );
+        assert_eq!(target.format, Some(FileFormat::Gtk));
+        assert_eq!(target.to_string(), line);
+        let target = Target::parse(r"D:\themes\a||b.css").unwrap();
+        assert_eq!(target.path, std::path::Path::new(r"D:\themes\a|b.css"));
+        assert_eq!(target.format, None);
+    }
+
+    #[test]
+    fn configs_keep_their_target_hints() {
+        let config = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\nplain.conf\ngtk.css|format=gtk|overwrite=true\n",
+        )
+        .unwrap();
+        assert_eq!(config.get_target_files(), ["plain.conf", "gtk.css"]);
+        assert_eq!(config.get_targets()[1].format, Some(FileFormat::Gtk));
+        let written = write_config_to_string(config.clone());
+        assert!(
+            written.contains("gtk.css|format=gtk|overwrite=true\n"),
+            "{}",
+            written
+        );
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+    }
+
+    #[test]
+    fn unknown_target_hints_fail_on_their_line() {
+        let e = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\na.conf\nb.conf|colour=1\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(e.contains("Line 9"), "{}", e);
+        assert!(e.contains("'colour'"), "{}", e);
+        for hint in [
+            "overwrite=maybe",
+            "format=svg",
+            "colors=0,x",
+            "exec_timeout=3",
+            "novalue",
+        ] {
+            assert!(
+                Target::parse(&format!("a.conf|{}", hint)).is_err(),
+                "{}",
+                hint
+            );
+        }
+    }
+
+    #[test]
+    fn targets_can_only_pick_colors_the_rows_have() {
+        let config = |hint: &str| {
+            parse_config_contents(&format!(
+                "[mode]\nmap\n[current_color]\n#111111 #222222 #333333\n[colors]\n#444444 #555555 #666666\n[target_files]\na.conf|colors={}\n",
+                hint
+            ))
+        };
+        assert_eq!(
+            config("0,2").unwrap().get_targets()[0].colors,
+            Some(vec![0, 2])
+        );
+        assert_eq!(
+            config("1,3").unwrap_err().to_string(),
+            "Error reading config: Target a.conf picks color 3 but rows in [colors] have 3 colors"
+        );
+        let gradient = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\na.conf|colors=0\n",
+        );
+        assert!(gradient
+            .unwrap_err()
+            .to_string()
+            .contains("only applies to map mode"));
+    }
+
+    #[test]
+    fn output_dirs_round_trip_and_need_a_dir() {
+        let config = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[output_dir]\ndir = /out\nbase=/home/me\n[target_files]\na.png\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_output_dir(),
+            Some(&OutputDir {
+                dir: PathBuf::from("/out"),
+                base: Some(PathBuf::from("/home/me")),
+            })
+        );
+        let written = write_config_to_string(config.clone());
+        assert!(
+            written.contains("[output_dir]\ndir=/out\nbase=/home/me\n"),
+            "{}",
+            written
+        );
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let error = |section: &str| {
+            parse_config_contents(&format!(
+                "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[output_dir]\n{}\n",
+                section
+            ))
+            .unwrap_err()
+            .to_string()
+        };
+        assert!(error("base=/home/me").contains("needs a dir"));
+        assert!(error("root=/out").contains("Unrecognized output_dir setting 'root'"));
+        assert!(error("/out").contains("Expected key=value"));
+    }
+
+    #[test]
+    fn rows_longer_or_shorter_than_the_first_are_rejected() {
+        let contents = "[mode]\nmap\n[colors]\n#aaaaaa#bbbbbb\n#cccccc\n";
+        assert_eq!(
+            config_error(contents),
+            "Line 5: This row has 1 colors, but rows in [colors] have 2"
+        );
+        let contents = "[mode]\nmap\n[colors]\n#aaaaaa#bbbbbb\n\n#cccccc#dddddd#eeeeee\n";
+        assert_eq!(
+            config_error(contents),
+            "Line 6: This row has 3 colors, but rows in [colors] have 2"
+        );
+    }
+
+    #[test]
+    fn rows_have_to_fit_the_current_color() {
+        let contents = map_config("#111111#222222", &["#aaaaaa#bbbbbb#cccccc"]);
+        assert_eq!(
+            config_error(&contents),
+            "Line 6: This row has 3 colors, but current_color has 2"
+        );
+    }
+
+    #[test]
+    fn a_current_color_after_the_rows_has_to_fit_them() {
+        let contents = "[mode]\nmap\n[colors]\n#aaaaaa#bbbbbb\n[current_color]\n#111111\n";
+        assert_eq!(
+            config_error(contents),
+            "Line 6: current_color has 1 colors, but rows in [colors] have 2"
+        );
+    }
+
+    #[test]
+    fn rows_of_one_length_parse() {
+        let contents = map_config(
+            "#111111#222222",
+            &["#aaaaaa#bbbbbb", "-#cccccc", "2#dddddd-"],
+        );
+        let Config::MapConfig(config) = parse_config_contents(&contents).unwrap() else {
+            panic!("expected a map config");
+        };
+        assert_eq!(config.get_rows().len(), 3);
+        assert_eq!(config.get_weight(2), 2);
+    }
+
+    #[test]
+    fn an_overlay_has_to_fit_the_current_color_of_the_base() {
+        let base =
+            parse_config_contents(&map_config("#111111#222222", &["#aaaaaa#bbbbbb"])).unwrap();
+        let Err(TranError::ConfigError(reason)) =
+            Config::merge(base.clone(), "[colors]\n#cccccc#dddddd#eeeeee\n")
+        else {
+            panic!("expected a config error");
+        };
+        assert_eq!(
+            reason,
+            "Rows in [colors] have 3 colors, but current_color has 2"
+        );
+
+        let merged = Config::merge(
+            base,
+            "[colors]\n#cccccc#dddddd#eeeeee\n[current_color]\n#111111#222222#333333\n",
+        )
+        .unwrap();
+        assert_eq!(merged.get_current_colors().len(), 3);
+    }
+
+    #[test]
+    fn placeholders_round_trip() {
+        let contents = map_config("#111111#222222#333333", &["-#aaaaaa-", "#bbbbbb--"]);
+        let config = parse_config_contents(&contents).unwrap();
+        let written = write_config_to_string(config.clone());
+        assert!(written.contains("-#aaaaaa-\n"), "{}", written);
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn placeholders_leave_their_slots_untouched() {
+        let dir = crate::test_util::TempDir::new("placeholders");
+        let original = "a=#111111 b=#222222 c=#333333\n";
+        let target = dir.write("target.txt", original);
+        let config_path = dir.write(
+            "config",
+            format!(
+                "{}[target_files]\n{}\n",
+                map_config("#111111#222222#333333", &["-#aaaaaa-"]),
+                target.display()
+            ),
+        );
+        let mut options = crate::run::RunOptions::new(&config_path);
+        options.state_dir = Some(dir.path().join("state"));
+        let summary = crate::run::run(&options).unwrap();
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+
+        let recolored = std::fs::read_to_string(&target).unwrap();
+        assert_eq!(recolored, "a=#111111 b=#aaaaaa c=#333333\n");
+        let config = parse_config(&config_path).unwrap();
+        let current: Vec<String> = config
+            .get_current_colors()
+            .iter()
+            .map(|c| c.to_hex_lower())
+            .collect();
+        assert_eq!(current, ["#111111", "#aaaaaa", "#333333"]);
+    }
+
+    /// A config the round trip property draws, kept as parts rather than text so failures can
+    /// be shrunk part by part.
+    #[derive(Clone, Debug)]
+    struct Drawn {
+        map: bool,
+        current: Vec<(u8, u8, u8)>,
+        rows: Vec<DrawnRow>,
+        targets: Vec<(String, Vec<&'static str>)>,
+        overwrite: bool,
+    }
+
+    #[derive(Clone, Debug)]
+    struct DrawnRow {
+        weight: usize,
+        tags: Vec<&'static str>,
+        colors: Vec<Option<(u8, u8, u8)>>,
+    }
+
+    const TAGS: [&str; 4] = ["dark", "light", "warm_1", "high-contrast"];
+    const HINTS: [&str; 6] = [
+        "format=css",
+        "format=png",
+        "overwrite=false",
+        "literals=true",
+        "colors=0,1",
+        "collision=skip",
+    ];
+    // Characters a path can hold, some of which mean something elsewhere in the config
+    const PATH_CHARS: [char; 14] = [
+        'a', 'Z', '0', ' ', '|', '[', ']', '#', '-', '.', '/', '=', ':', 'é',
+    ];
+
+    fn below(rng: &mut SystemRng, bound: usize) -> usize {
+        rng.below(bound as u64) as usize
+    }
+
+    fn pick<T: Copy>(rng: &mut SystemRng, from: &[T]) -> T {
+        from[below(rng, from.len())]
+    }
+
+    fn draw_color(rng: &mut SystemRng) -> (u8, u8, u8) {
+        let [r, g, b, ..] = rng.next_u64().to_le_bytes();
+        (r, g, b)
+    }
+
+    fn draw(rng: &mut SystemRng) -> Drawn {
+        let map = below(rng, 2) == 0;
+        let width = if map { 1 + below(rng, 5) } else { 1 };
+        let current = (0..width).map(|_| draw_color(rng)).collect();
+        let mut rows = Vec::new();
+        for _ in 0..1 + below(rng, 5) {
+            let weight = pick(rng, &[1, 1, 2, 17]);
+            let tags = (0..below(rng, 3)).map(|_| pick(rng, &TAGS)).collect();
+            let colors = (0..width)
+                .map(|_| match map && below(rng, 4) == 0 {
+                    true => None,
+                    false => Some(draw_color(rng)),
+                })
+                .collect();
+            rows.push(DrawnRow {
+                weight,
+                tags,
+                colors,
+            });
+        }
+        let mut targets = Vec::new();
+        for _ in 0..below(rng, 4) {
+            let path = (0..1 + below(rng, 12))
+                .map(|_| pick(rng, &PATH_CHARS))
+                .collect();
+            let hints = (0..below(rng, 3)).map(|_| pick(rng, &HINTS)).collect();
+            targets.push((path, hints));
+        }
+        Drawn {
+            map,
+            current,
+            rows,
+            targets,
+            overwrite: below(rng, 2) == 0,
+        }
+    }
+
+    fn hex((r, g, b): (u8, u8, u8)) -> String {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+
+    impl Drawn {
+        fn text(&self) -> String {
+            let mut text = format!(
+                "[mode]\n{}\n[current_color]\n",
+                if self.map { "map" } else { "gradient" }
+            );
+            text.extend(self.current.iter().copied().map(hex));
+            text.push_str("\n[colors]\n");
+            for row in &self.rows {
+                if !row.tags.is_empty() {
+                    text.push_str(&format!("{}:", row.tags.join(",")));
+                }
+                if row.weight != 1 {
+                    text.push_str(&row.weight.to_string());
+                }
+                for color in &row.colors {
+                    text.push_str(&color.map_or("-".to_string(), hex));
+                }
+                text.push('\n');
             }
-            writeln!(&mut writer)?;
+            text.push_str(&format!(
+                "[overwrite]\n{}\n[target_files]\n",
+                self.overwrite
+            ));
+            for (path, hints) in &self.targets {
+                let mut target = Target::new(path);
+                for hint in hints {
+                    let (key, value) = hint.split_once('=').unwrap();
+                    match key {
+                        "format" => target.format = Some(value.try_into().unwrap()),
+                        "overwrite" => target.overwrite = Some(value == "true"),
+                        "literals" => target.literals = Some(value == "true"),
+                        "collision" => target.collision = Some(value.try_into().unwrap()),
+                        _ => target.colors = Some(vec![0, 1]),
+                    }
+                }
+                text.push_str(&format!("{}\n", target));
+            }
+            text
+        }
 
-            writeln!(&mut writer, "[colors]")?;
-            for color_row in config.get_colors() {
-                for color in color_row {
-                    write!(&mut writer, "{}", color)?;
+        /// Smaller configs to try in place of this one, the simplest changes first.
+        fn shrinks(&self) -> Vec<Drawn> {
+            let mut shrinks = Vec::new();
+            let mut with = |change: &dyn Fn(&mut Drawn)| {
+                let mut smaller = self.clone();
+                change(&mut smaller);
+                shrinks.push(smaller);
+            };
+            for i in 0..self.targets.len() {
+                with(&|d| {
+                    d.targets.remove(i);
+                });
+            }
+            for i in (0..self.rows.len()).filter(|_| self.rows.len() > 1) {
+                with(&|d| {
+                    d.rows.remove(i);
+                });
+            }
+            for i in (0..self.current.len()).filter(|_| self.current.len() > 1) {
+                with(&|d| {
+                    d.current.remove(i);
+                    for row in &mut d.rows {
+                        row.colors.remove(i);
+                    }
+                });
+            }
+            for (i, row) in self.rows.iter().enumerate() {
+                if !row.tags.is_empty() {
+                    with(&|d| d.rows[i].tags.clear());
+                }
+                if row.weight != 1 {
+                    with(&|d| d.rows[i].weight = 1);
+                }
+                for slot in 0..row.colors.len() {
+                    if row.colors[slot] != Some((0, 0, 0)) {
+                        with(&|d| d.rows[i].colors[slot] = Some((0, 0, 0)));
+                    }
+                }
+            }
+            for (i, (path, hints)) in self.targets.iter().enumerate() {
+                for hint in 0..hints.len() {
+                    with(&|d| {
+                        d.targets[i].1.remove(hint);
+                    });
+                }
+                for (at, _) in path.char_indices().filter(|_| path.chars().count() > 1) {
+                    with(&|d| {
+                        d.targets[i].0.remove(at);
+                    });
                 }
-                writeln!(&mut writer)?;
             }
+            if self.overwrite {
+                with(&|d| d.overwrite = false);
+            }
+            shrinks
+        }
+    }
 
-            writeln!(&mut writer, "[target_files]")?;
-            for target in config.get_target_files() {
-                writeln!(&mut writer, "{}", target)?;
+    /// What goes wrong writing the config `drawn` stands for and reading it back, `None` when
+    /// nothing does or the text isn't a valid config to begin with.
+    fn round_trip_failure(drawn: &Drawn) -> Option<String> {
+        let config = parse_config_contents(&drawn.text()).ok()?;
+        let written = write_config_to_string(config.clone());
+        match parse_config_contents(&written) {
+            Ok(read) if read == config => None,
+            Ok(read) => Some(format!(
+                "written as\n{}\nreads back as {:?}\ninstead of {:?}",
+                written, read, config
+            )),
+            Err(e) => Some(format!("written as\n{}\nfails to read: {}", written, e)),
+        }
+    }
+
+    fn shrink(mut drawn: Drawn) -> Drawn {
+        while let Some(smaller) = drawn
+            .shrinks()
+            .into_iter()
+            .find(|d| round_trip_failure(d).is_some())
+        {
+            drawn = smaller;
+        }
+        drawn
+    }
+
+    #[test]
+    fn drawn_configs_round_trip() {
+        let mut valid = 0;
+        for seed in 1..=500 {
+            let drawn = draw(&mut SystemRng::from_seed(seed));
+            valid += parse_config_contents(&drawn.text()).is_ok() as usize;
+            if round_trip_failure(&drawn).is_some() {
+                let smallest = shrink(drawn);
+                panic!(
+                    "seed {}, shrunk to\n{}\n{}",
+                    seed,
+                    smallest.text(),
+                    round_trip_failure(&smallest).unwrap()
+                );
             }
         }
+        // The property says little if most drawn configs didn't parse
+        assert!(valid > 400, "only {} of 500 drawn configs are valid", valid);
     }
 
-    writer.flush()?;
+    #[test]
+    fn canonicalizing_twice_changes_nothing() {
+        for seed in 1..=100 {
+            let text = draw(&mut SystemRng::from_seed(seed)).text();
+            if let Ok(canonical) = canonicalize(&text) {
+                assert_eq!(
+                    canonicalize(&canonical).unwrap(),
+                    canonical,
+                    "seed {}",
+                    seed
+                );
+            }
+        }
+    }
 
-    Ok(())
+    #[test]
+    fn shrinking_finds_the_smallest_failure() {
+        // A stand-in failure, any target whose path holds a '|'
+        let mut drawn = draw(&mut SystemRng::from_seed(7));
+        drawn.targets = vec![
+            ("abc".to_string(), vec!["format=css"]),
+            (
+                "x|y z".to_string(),
+                vec!["overwrite=false", "literals=true"],
+            ),
+        ];
+        let fails = |d: &Drawn| d.targets.iter().any(|(path, _)| path.contains('|'));
+        while let Some(smaller) = drawn.shrinks().into_iter().find(|d| fails(d)) {
+            drawn = smaller;
+        }
+        assert_eq!(drawn.targets, vec![("|".to_string(), vec![])]);
+        assert_eq!(drawn.rows.len(), 1);
+        assert_eq!(drawn.current.len(), 1);
+    }
 }
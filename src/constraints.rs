@@ -0,0 +1,352 @@
+//! Rules the colors of a selection have to keep between each other, from the `[constraints]`
+//! section of the config. Every line names a measure, two colors and the least the measure may
+//! be between them:
+//!
+//! ```text
+//! [constraints]
+//! contrast color0 color7 >= 4.5
+//! delta_e color1..6 color0 >= 20
+//! enforce=true
+//! ```
+//!
+//! `colorN` is the color at position N of a map row, the picked color being `color0` in
+//! gradient mode, `colorA..B` every one from A to B and a hex color stands for itself. With
+//! `enforce` selections breaking a rule are never picked.
+
+use crate::{
+    config::{Color, Config},
+    errors::TranError,
+    json::Json,
+    selection::Selection,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Measure {
+    /// WCAG contrast ratio, see `Color::contrast_ratio`.
+    Contrast,
+    /// CIE76 difference, see `Color::delta_e`.
+    DeltaE,
+}
+
+impl Measure {
+    pub fn between(&self, a: &Color, b: &Color) -> f64 {
+        match self {
+            Measure::Contrast => a.contrast_ratio(b),
+            Measure::DeltaE => a.delta_e(b),
+        }
+    }
+}
+
+/// One side of a rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operand {
+    /// The colors at positions `start` to `end` of the selection.
+    Positions {
+        start: usize,
+        end: usize,
+    },
+    Color(Color),
+}
+
+impl Operand {
+    fn parse(value: &str) -> Option<Self> {
+        if value.starts_with('#') {
+            return Color::try_from_hex_str(value).ok().map(Operand::Color);
+        }
+        let positions = value.strip_prefix("color")?;
+        let (start, end) = match positions.split_once("..") {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None => (positions.parse().ok()?, positions.parse().ok()?),
+        };
+        (start <= end).then_some(Operand::Positions { start, end })
+    }
+
+    /// The colors of `colors` the operand stands for, with their positions.
+    fn resolve(&self, colors: &[Color]) -> Vec<(Option<usize>, Color)> {
+        match self {
+            Operand::Positions { start, end } => (*start..=*end)
+                .filter_map(|i| Some((Some(i), *colors.get(i)?)))
+                .collect(),
+            Operand::Color(color) => vec![(None, *color)],
+        }
+    }
+
+    fn last_position(&self) -> Option<usize> {
+        match self {
+            Operand::Positions { end, .. } => Some(*end),
+            Operand::Color(_) => None,
+        }
+    }
+}
+
+/// A line of `[constraints]`: `measure` has to be at least `min` between every color of `left`
+/// and every other color of `right`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constraint {
+    source: String,
+    pub measure: Measure,
+    pub left: Operand,
+    pub right: Operand,
+    pub min: f64,
+}
+
+// Parsing only accepts finite minimums
+impl Eq for Constraint {}
+
+impl Constraint {
+    pub fn parse(line: &str) -> Result<Self, TranError> {
+        let line = line.trim();
+        let invalid = |reason: &str| {
+            TranError::ConfigError(format!("Invalid constraint '{}': {}", line, reason))
+        };
+        let [measure, left, right, ">=", min] = line.split_whitespace().collect::<Vec<_>>()[..]
+        else {
+            return Err(invalid("expected '<measure> <color> <color> >= <minimum>'"));
+        };
+        let measure = match measure {
+            "contrast" => Measure::Contrast,
+            "delta_e" => Measure::DeltaE,
+            _ => return Err(invalid("valid measures are 'contrast' and 'delta_e'")),
+        };
+        let operand = |value: &str| {
+            Operand::parse(value).ok_or_else(|| {
+                invalid(&format!(
+                    "expected colorN, colorA..B or a hex color, found '{}'",
+                    value
+                ))
+            })
+        };
+        let min = min
+            .parse::<f64>()
+            .ok()
+            .filter(|min| min.is_finite())
+            .ok_or_else(|| invalid(&format!("'{}' is not a number", min)))?;
+        Ok(Constraint {
+            source: line.to_string(),
+            measure,
+            left: operand(left)?,
+            right: operand(right)?,
+            min,
+        })
+    }
+
+    /// The highest position the rule refers to, if it refers to any.
+    pub fn last_position(&self) -> Option<usize> {
+        self.left.last_position().max(self.right.last_position())
+    }
+
+    /// Every pair of `colors` the rule fails on.
+    pub fn violations(&self, colors: &[Color]) -> Vec<Violation> {
+        let right = self.right.resolve(colors);
+        let mut violations = Vec::new();
+        for (left_position, left) in self.left.resolve(colors) {
+            for (right_position, right) in right.iter() {
+                // A color always has the least contrast with itself
+                if left_position.is_some() && left_position == *right_position {
+                    continue;
+                }
+                let value = self.measure.between(&left, right);
+                if value < self.min {
+                    violations.push(Violation {
+                        constraint: self.source.clone(),
+                        left,
+                        right: *right,
+                        value,
+                    });
+                }
+            }
+        }
+        violations
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// A pair of colors breaking a rule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub constraint: String,
+    pub left: Color,
+    pub right: Color,
+    pub value: f64,
+}
+
+impl Violation {
+    pub fn to_json(&self) -> Json {
+        Json::object()
+            .with("constraint", self.constraint.as_str())
+            .with("left", self.left.to_string())
+            .with("right", self.right.to_string())
+            .with("value", self.value)
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} and {} break '{}' at {:.2}",
+            self.left, self.right, self.constraint, self.value
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Constraints {
+    rules: Vec<Constraint>,
+    /// Whether selections breaking a rule are passed over.
+    pub enforce: bool,
+}
+
+impl Constraints {
+    pub fn push_line(&mut self, line: &str) -> Result<(), TranError> {
+        if let Some((key, value)) = line.split_once('=').filter(|(key, _)| !key.contains('>')) {
+            let (key, value) = (key.trim(), value.trim());
+            if key != "enforce" {
+                return Err(TranError::ConfigError(format!(
+                    "Unrecognized constraints setting '{}', the only setting is 'enforce'",
+                    key
+                )));
+            }
+            self.enforce = value.parse().map_err(|_| {
+                TranError::ConfigError(format!(
+                    "Expected true or false for enforce, found '{}'",
+                    value
+                ))
+            })?;
+            return Ok(());
+        }
+        self.rules.push(Constraint::parse(line)?);
+        Ok(())
+    }
+
+    pub fn get_rules(&self) -> &[Constraint] {
+        &self.rules
+    }
+
+    /// Every pair of `colors` breaking a rule, rule by rule.
+    pub fn violations(&self, colors: &[Color]) -> Vec<Violation> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.violations(colors))
+            .collect()
+    }
+
+    /// Whether a selection of `colors` can be picked, always when the rules aren't enforced.
+    pub fn allows(&self, colors: &[Color]) -> bool {
+        !self.enforce || self.violations(colors).is_empty()
+    }
+}
+
+/// Every selection of `config` with the pairs of it breaking `[constraints]`, in config order.
+/// Nothing breaks a config without constraints.
+pub fn check(config: &Config) -> Vec<(Selection, Vec<Violation>)> {
+    config
+        .get_selections()
+        .into_iter()
+        .map(|selection| {
+            let violations = config
+                .get_constraints()
+                .map(|constraints| constraints.violations(selection.colors()))
+                .unwrap_or_default();
+            (selection, violations)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config_contents, selection::Fixed};
+
+    fn color(hex: &str) -> Color {
+        Color::try_from_hex_str(hex).unwrap()
+    }
+
+    fn map_config(rows: &[&str], constraints: &str) -> Result<Config, TranError> {
+        parse_config_contents(&format!(
+            "[mode]\nmap\n[current_color]\n#000000#ffffff\n[colors]\n{}\n[constraints]\n{}\n",
+            rows.join("\n"),
+            constraints
+        ))
+    }
+
+    #[test]
+    fn rules_parse_their_measure_operands_and_minimum() {
+        let rule = Constraint::parse(" delta_e color1..6 #2e3440 >= 20 ").unwrap();
+        assert_eq!(rule.measure, Measure::DeltaE);
+        assert_eq!(rule.left, Operand::Positions { start: 1, end: 6 });
+        assert_eq!(rule.right, Operand::Color(color("#2e3440")));
+        assert_eq!(rule.min, 20.0);
+        assert_eq!(rule.last_position(), Some(6));
+        assert_eq!(rule.to_string(), "delta_e color1..6 #2e3440 >= 20");
+
+        let error = |line: &str| Constraint::parse(line).unwrap_err().to_string();
+        assert!(error("contrast color0 color1 > 4.5").contains("expected '<measure>"));
+        assert!(error("brightness color0 color1 >= 4.5").contains("valid measures"));
+        assert!(error("contrast color3..1 color1 >= 4.5").contains("found 'color3..1'"));
+        assert!(error("contrast color0 #12 >= 4.5").contains("found '#12'"));
+        assert!(error("contrast color0 color1 >= inf").contains("'inf' is not a number"));
+    }
+
+    #[test]
+    fn colors_are_not_held_against_themselves() {
+        let rule = Constraint::parse("contrast color0..2 color0..2 >= 4.5").unwrap();
+        let colors = [color("#000000"), color("#ffffff"), color("#111111")];
+        let violations = rule.violations(&colors);
+        // Black and #111111 both ways, never a color with itself
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].left, color("#000000"));
+        assert_eq!(violations[0].right, color("#111111"));
+        assert!(violations[0]
+            .to_string()
+            .starts_with("#000000 and #111111 break 'contrast color0..2 color0..2 >= 4.5' at 1."));
+        assert!((Measure::DeltaE.between(&colors[0], &colors[1]) - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn enforced_rules_keep_breaking_rows_from_being_picked() {
+        let rows = ["#000000#111111", "#000000#eeeeee"];
+        let config = map_config(&rows, "contrast color0 color1 >= 4.5").unwrap();
+        let checked = check(&config);
+        assert_eq!(checked.len(), 2);
+        assert_eq!(checked[0].1.len(), 1);
+        assert!(checked[1].1.is_empty());
+        // Not enforced, the first row is still picked
+        let picked = config.select_next(&mut Fixed(vec![0])).unwrap();
+        assert_eq!(picked.to_string(), "#000000#111111");
+
+        let config = map_config(&rows, "contrast color0 color1 >= 4.5\nenforce=true").unwrap();
+        for draw in [0, 1, 2, 3] {
+            let picked = config.select_next(&mut Fixed(vec![draw])).unwrap();
+            assert_eq!(picked.to_string(), "#000000#eeeeee");
+        }
+        let written = crate::config::write_config_to_string(config.clone());
+        assert!(written.contains("[constraints]\ncontrast color0 color1 >= 4.5\nenforce=true\n"));
+        assert_eq!(parse_config_contents(&written).unwrap(), config);
+
+        let config = map_config(&rows[..1], "contrast color0 color1 >= 4.5\nenforce=true");
+        assert!(config
+            .unwrap()
+            .select_next(&mut Fixed(vec![0]))
+            .unwrap_err()
+            .to_string()
+            .contains("every row in [colors] breaks [constraints]"));
+    }
+
+    #[test]
+    fn rules_have_to_fit_the_selections() {
+        let error = map_config(&["#000000#111111"], "contrast color0 color2 >= 4.5")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("refers to a color past the 2 colors of a selection"));
+        let error = map_config(&["#000000#111111"], "strict=true")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("the only setting is 'enforce'"));
+    }
+}
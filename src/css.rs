@@ -0,0 +1,378 @@
+use std::ops::Range;
+
+use crate::{
+    config::Color,
+    errors::TranError,
+    text::{hex_tokens, replace_tokens, replacements, TextOptions, TextReport},
+    ColorTransform,
+};
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_'
+}
+
+/// The `--name` starting at `at`, if one does and isn't the tail of a longer word.
+fn property_at(contents: &str, at: usize) -> Option<&str> {
+    let bytes = contents.as_bytes();
+    if !contents[at..].starts_with("--") || (at > 0 && is_name_byte(bytes[at - 1])) {
+        return None;
+    }
+    let len = bytes[at + 2..]
+        .iter()
+        .take_while(|b| is_name_byte(**b))
+        .count();
+    // `<!--` and `-->` of comments are no properties
+    (len > 0).then(|| &contents[at..at + 2 + len])
+}
+
+/// A custom property definition, as in `--accent: #2e3440`.
+struct Definition<'a> {
+    name: &'a str,
+    /// The value, up to the end of the declaration.
+    value: Range<usize>,
+}
+
+/// The custom properties `contents` defines, in `:root` blocks, `<style>` elements or `style`
+/// attributes alike.
+fn definitions(contents: &str) -> Vec<Definition<'_>> {
+    let mut definitions = Vec::new();
+    let mut at = 0;
+    while let Some(i) = contents[at..].find("--") {
+        let start = at + i;
+        let Some(name) = property_at(contents, start) else {
+            at = start + 2;
+            continue;
+        };
+        at = start + name.len();
+        let rest = contents[at..].trim_start_matches([' ', '\t']);
+        if !rest.starts_with(':') {
+            continue;
+        }
+        let value_start = contents.len() - rest.len() + 1;
+        let value_end = contents[value_start..]
+            .find([';', '}', '"', '\'', '\n'])
+            .map_or(contents.len(), |end| value_start + end);
+        definitions.push(Definition {
+            name,
+            value: value_start..value_end,
+        });
+        at = value_end;
+    }
+    definitions
+}
+
+/// A declaration inside a block, as in `color: #2e3440;`.
+pub(crate) struct Declaration {
+    /// The property, `color` here.
+    pub(crate) name: Range<usize>,
+    /// The value up to its `;` or the `}` closing the block, in pieces around comments.
+    pub(crate) value: Vec<Range<usize>>,
+}
+
+/// `range` of `contents` without the whitespace around it.
+fn trimmed(contents: &str, range: Range<usize>) -> Range<usize> {
+    let text = &contents[range.clone()];
+    let start = range.start + text.len() - text.trim_start().len();
+    start..range.start + text.trim_end().len()
+}
+
+/// The declarations inside the blocks of a stylesheet. Comments and strings are stepped over,
+/// `//` comments too with `line_comments` as rasi has them, and a statement opening a block is
+/// a selector whatever colons it holds. `depth` is how many blocks `contents` starts inside,
+/// 1 to read a line on its own as a block would hold it.
+pub(crate) fn declarations(contents: &str, line_comments: bool, depth: usize) -> Vec<Declaration> {
+    let bytes = contents.as_bytes();
+    let mut declarations = Vec::new();
+    let mut depth = depth;
+    let mut parens = 0usize;
+    // Where the statement being read starts, then the declaration it turned out to be
+    let mut start = 0;
+    let mut current: Option<Declaration> = None;
+    let mut piece = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let comment_end = match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => Some(
+                contents[i + 2..]
+                    .find("*/")
+                    .map_or(contents.len(), |end| i + 2 + end + 2),
+            ),
+            (b'/', Some(b'/')) if line_comments => Some(
+                contents[i..]
+                    .find('\n')
+                    .map_or(contents.len(), |end| i + end),
+            ),
+            _ => None,
+        };
+        if let Some(end) = comment_end {
+            match &mut current {
+                Some(declaration) if piece < i => declaration.value.push(piece..i),
+                Some(_) => {}
+                None if contents[start..i].trim().is_empty() => start = end,
+                None => {}
+            }
+            piece = end;
+            i = end;
+            continue;
+        }
+        match bytes[i] {
+            quote @ (b'"' | b'\'') => {
+                let mut end = i + 1;
+                while end < bytes.len() && bytes[end] != quote && bytes[end] != b'\n' {
+                    end += 1 + usize::from(bytes[end] == b'\\');
+                }
+                i = (end + 1).min(bytes.len());
+                continue;
+            }
+            b'(' => parens += 1,
+            b')' => parens = parens.saturating_sub(1),
+            b':' if depth > 0 && parens == 0 && current.is_none() => {
+                current = Some(Declaration {
+                    name: trimmed(contents, start..i),
+                    value: Vec::new(),
+                });
+                piece = i + 1;
+            }
+            b'{' => {
+                depth += 1;
+                current = None;
+                start = i + 1;
+                parens = 0;
+            }
+            b';' | b'}' => {
+                if let Some(mut declaration) = current.take() {
+                    if piece < i {
+                        declaration.value.push(piece..i);
+                    }
+                    declarations.push(declaration);
+                }
+                if bytes[i] == b'}' {
+                    depth = depth.saturating_sub(1);
+                }
+                start = i + 1;
+                parens = 0;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    declarations
+}
+
+/// A color a stylesheet takes from outside itself, a `var()` of a property it defines no color
+/// for or `currentColor`. No map entry can match these, the color is wherever it is defined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Indirect {
+    /// `var(--name)` or `currentColor`.
+    pub usage: String,
+    /// 1-based line of the first use.
+    pub line: usize,
+}
+
+impl std::fmt::Display for Indirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at line {}", self.usage, self.line)
+    }
+}
+
+/// The properties `var()` reads in `value`.
+fn var_names(value: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut at = 0;
+    while let Some(i) = value[at..].find("var(") {
+        at += i + 4;
+        let start = value.len() - value[at..].trim_start().len();
+        if let Some(name) = property_at(value, start) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Uses of colors `contents` doesn't define, in file order and once each. A property whose
+/// value reads another one is resolved through it, one level deep.
+pub fn indirect(contents: &str) -> Vec<Indirect> {
+    let definitions = definitions(contents);
+    let has_color = |name: &str| {
+        definitions
+            .iter()
+            .any(|d| d.name == name && !hex_tokens(&contents[d.value.clone()]).is_empty())
+    };
+    let resolves = |name: &str| {
+        has_color(name)
+            || definitions.iter().any(|d| {
+                d.name == name
+                    && var_names(&contents[d.value.clone()])
+                        .into_iter()
+                        .any(has_color)
+            })
+    };
+
+    let mut found: Vec<Indirect> = Vec::new();
+    for (number, line) in contents.split_inclusive('\n').enumerate() {
+        let mut uses: Vec<(usize, String)> = Vec::new();
+        let mut at = 0;
+        while let Some(i) = line[at..].find("var(") {
+            at += i + 4;
+            let start = line.len() - line[at..].trim_start().len();
+            if let Some(name) = property_at(line, start).filter(|name| !resolves(name)) {
+                uses.push((start, format!("var({})", name)));
+            }
+        }
+        let lower = line.to_ascii_lowercase();
+        for (i, _) in lower.match_indices("currentcolor") {
+            let end = i + "currentcolor".len();
+            let bounded = (i == 0 || !is_name_byte(lower.as_bytes()[i - 1]))
+                && lower.as_bytes().get(end).is_none_or(|b| !is_name_byte(*b));
+            if bounded {
+                uses.push((i, "currentColor".to_string()));
+            }
+        }
+        uses.sort();
+        for (_, usage) in uses {
+            if !found.iter().any(|f| f.usage == usage) {
+                found.push(Indirect {
+                    usage,
+                    line: number + 1,
+                });
+            }
+        }
+    }
+    found
+}
+
+/// Whether `recolor_css` rewrites colors of `line`.
+pub fn owns_line(line: &str, literals: bool) -> bool {
+    literals || !definitions(line).is_empty()
+}
+
+/// The byte ranges of `contents` whose colors `recolor_css` rewrites.
+fn owned(contents: &str, literals: bool) -> Vec<Range<usize>> {
+    match literals {
+        true => std::iter::once(0..contents.len()).collect(),
+        false => definitions(contents).into_iter().map(|d| d.value).collect(),
+    }
+}
+
+/// The colors `recolor_css` would rewrite with `options`, in file order.
+pub fn colors(contents: &str, literals: bool, options: &TextOptions) -> Vec<Color> {
+    owned(contents, literals)
+        .into_iter()
+        .flat_map(|range| options.tokens(&contents[range]))
+        .map(|t| t.color)
+        .collect()
+}
+
+/// Rewrites the colors custom properties are defined as, leaving every other byte as it was,
+/// so each `var()` using them follows. With `literals` colors outside definitions are
+/// rewritten too.
+pub fn recolor_css(
+    contents: &str,
+    transform: &ColorTransform,
+    literals: bool,
+    options: &TextOptions,
+) -> Result<(String, TextReport), TranError> {
+    let replacements = replacements(transform, &colors(contents, literals, options))?;
+    let mut output = String::with_capacity(contents.len());
+    let mut report = TextReport::default();
+
+    let names = definitions(contents);
+    let mut last = 0;
+    for range in owned(contents, literals) {
+        output.push_str(&contents[last..range.start]);
+        let (value, changes) = replace_tokens(&contents[range.clone()], &replacements, options);
+        output.push_str(&value);
+        report.changes += changes;
+        last = range.end;
+    }
+    output.push_str(&contents[last..]);
+
+    for definition in names {
+        let replaced = options
+            .tokens(&contents[definition.value])
+            .iter()
+            .any(|token| replacements.iter().any(|(old, _)| *old == token.color));
+        if replaced && !report.names.iter().any(|n| n == definition.name) {
+            report.names.push(definition.name.to_string());
+        }
+    }
+
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMap;
+
+    const THEME: &str = ":root {\n  --accent: #88c0d0;\n  --link: var(--accent);\n}\na { color: var(--link); border-color: #88c0d0; }\n<div style=\"--bg: #88c0d0\">\n";
+
+    fn map() -> [ColorMap<'static>; 1] {
+        [ColorMap::new("#bf616a", "#88c0d0")]
+    }
+
+    #[test]
+    fn only_definitions_are_rewritten_unless_literals_are_asked_for() {
+        let map = map();
+        let transform = ColorTransform::Map(&map);
+        let (css, report) = recolor_css(THEME, &transform, false, &TextOptions::default()).unwrap();
+        assert_eq!(report.changes, 2);
+        assert_eq!(report.names, ["--accent", "--bg"]);
+        assert_eq!(
+            css,
+            THEME
+                .replacen("#88c0d0", "#bf616a", 1)
+                .replace("--bg: #88c0d0", "--bg: #bf616a")
+        );
+
+        let (css, report) = recolor_css(THEME, &transform, true, &TextOptions::default()).unwrap();
+        assert_eq!(report.changes, 3);
+        assert!(!css.contains("#88c0d0"), "{}", css);
+    }
+
+    #[test]
+    fn comments_and_words_ending_in_dashes_are_no_properties() {
+        let contents = "<!-- --x: #111111 -->\nfoo--bar: #222222;\n--ok:#333333;\n";
+        assert_eq!(
+            colors(contents, false, &TextOptions::default()),
+            [
+                Color::from_bytes(0x11, 0x11, 0x11),
+                Color::from_bytes(0x33, 0x33, 0x33)
+            ]
+        );
+        assert!(owns_line("--ok: #333333;", false));
+        assert!(!owns_line("color: #333333;", false));
+        assert!(owns_line("color: #333333;", true));
+    }
+
+    #[test]
+    fn colors_from_outside_the_file_are_reported_once() {
+        let contents = format!(
+            "{}b {{ color: var(--fg); fill: currentColor; }}\nc {{ color: var(--fg); stroke: CURRENTCOLOR; }}\n",
+            THEME
+        );
+        let found = indirect(&contents);
+        let usages: Vec<&str> = found.iter().map(|f| f.usage.as_str()).collect();
+        assert_eq!(usages, ["var(--fg)", "currentColor"]);
+        assert_eq!(found[0].line, 7);
+        assert_eq!(found[0].to_string(), "var(--fg) at line 7");
+    }
+
+    #[test]
+    fn declarations_skip_selectors_comments_and_strings() {
+        let contents = "a:hover { color: /* old */ #111111; content: \"x;y\" }";
+        let found = declarations(contents, false, 0);
+        let names: Vec<&str> = found.iter().map(|d| &contents[d.name.clone()]).collect();
+        assert_eq!(names, ["color", "content"]);
+        let value: Vec<&str> = found[0]
+            .value
+            .iter()
+            .map(|r| &contents[r.clone()])
+            .collect();
+        assert_eq!(value, [" ", " #111111"]);
+        assert_eq!(contents[found[1].value[0].clone()].trim(), "\"x;y\"");
+
+        let line = "color: #111111; // note: none";
+        assert_eq!(declarations(line, true, 1).len(), 1);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{
+    config::Color,
+    errors::TranError,
+    space::{delinearize, linearize},
+};
+
+// Linear sRGB to LMS cone responses and back, as used by Viénot, Brettel and Mollon (1999)
+const LMS_FROM_RGB: [[f64; 3]; 3] = [
+    [0.31399022, 0.63951294, 0.04649755],
+    [0.15537241, 0.75789446, 0.08670142],
+    [0.01775239, 0.10944209, 0.87256922],
+];
+const RGB_FROM_LMS: [[f64; 3]; 3] = [
+    [5.47221206, -4.6419601, 0.16963708],
+    [-1.1252419, 2.29317094, -0.1678952],
+    [0.02980165, -0.19318073, 1.16364789],
+];
+
+/// A color vision deficiency whose missing cone `Simulate` projects away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorVisionDeficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl TryFrom<&str> for ColorVisionDeficiency {
+    type Error = TranError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "protan" | "protanopia" => Ok(Self::Protanopia),
+            "deutan" | "deuteranopia" => Ok(Self::Deuteranopia),
+            "tritan" | "tritanopia" => Ok(Self::Tritanopia),
+            _ => Err(TranError::UnsupportedError(format!(
+                "Unrecognized color vision deficiency '{}', valid types are 'protan', 'deutan' and 'tritan'",
+                value
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for ColorVisionDeficiency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Protanopia => write!(f, "protan"),
+            Self::Deuteranopia => write!(f, "deutan"),
+            Self::Tritanopia => write!(f, "tritan"),
+        }
+    }
+}
+
+fn apply(matrix: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+impl ColorVisionDeficiency {
+    /// How `color` looks with this deficiency. The missing cone response is rebuilt from the
+    /// other two on the plane through black, white and sRGB blue, red for tritanopia, so those
+    /// colors look the same to everyone.
+    pub fn simulate(&self, color: &Color) -> Color {
+        let (r, g, b) = color.bytes();
+        let mut lms = apply(&LMS_FROM_RGB, [linearize(r), linearize(g), linearize(b)]);
+        match self {
+            Self::Protanopia => lms[0] = 1.05118294 * lms[1] - 0.05116099 * lms[2],
+            Self::Deuteranopia => lms[1] = 0.9513092 * lms[0] + 0.04866992 * lms[2],
+            Self::Tritanopia => lms[2] = -0.86744736 * lms[0] + 1.86727089 * lms[1],
+        }
+        let [r, g, b] = apply(&RGB_FROM_LMS, lms);
+        Color::from_bytes(delinearize(r), delinearize(g), delinearize(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorTransform;
+
+    fn simulated(deficiency: ColorVisionDeficiency, rgb: (u8, u8, u8)) -> (u8, u8, u8) {
+        deficiency
+            .simulate(&Color::from_bytes(rgb.0, rgb.1, rgb.2))
+            .bytes()
+    }
+
+    #[test]
+    fn simulations_match_the_reference_values() {
+        use ColorVisionDeficiency::*;
+
+        // The 1999 matrices of Viénot, Brettel and Mollon on the primaries and two nord colors
+        let cases = [
+            (Protanopia, (255, 0, 0), (115, 115, 0)),
+            (Protanopia, (0, 255, 0), (235, 235, 14)),
+            (Deuteranopia, (255, 0, 0), (156, 156, 0)),
+            (Deuteranopia, (0, 255, 0), (214, 214, 46)),
+            (Tritanopia, (0, 255, 0), (100, 240, 240)),
+            (Protanopia, (0x88, 0xc0, 0xd0), (184, 184, 208)),
+            (Deuteranopia, (0xbf, 0x61, 0x6a), (138, 138, 102)),
+        ];
+        for (deficiency, color, expected) in cases {
+            assert_eq!(
+                simulated(deficiency, color),
+                expected,
+                "{} of {:?}",
+                deficiency,
+                color
+            );
+        }
+    }
+
+    #[test]
+    fn colors_on_the_projection_plane_are_kept() {
+        use ColorVisionDeficiency::*;
+
+        for deficiency in [Protanopia, Deuteranopia, Tritanopia] {
+            for grey in [0, 64, 128, 255] {
+                assert_eq!(
+                    simulated(deficiency, (grey, grey, grey)),
+                    (grey, grey, grey)
+                );
+            }
+        }
+        assert_eq!(simulated(Protanopia, (0, 0, 255)), (0, 0, 255));
+        assert_eq!(simulated(Deuteranopia, (0, 0, 255)), (0, 0, 255));
+        assert_eq!(simulated(Tritanopia, (255, 0, 0)), (255, 0, 0));
+    }
+
+    #[test]
+    fn srgb_is_linearized_as_the_standard_says() {
+        assert_eq!(linearize(0), 0.0);
+        assert!((linearize(128) - 0.2158605).abs() < 1e-6);
+        assert!((linearize(188) - 0.5028865).abs() < 1e-6);
+        assert!((linearize(10) - 10.0 / 255.0 / 12.92).abs() < 1e-12);
+        assert_eq!(delinearize(0.5), 188);
+        assert_eq!(delinearize(1.5), 255);
+        assert_eq!(delinearize(-0.1), 0);
+        for channel in 0..=255 {
+            assert_eq!(delinearize(linearize(channel)), channel);
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_palettes_are_simulated() {
+        let mut png =
+            crate::png::build::PngBuilder::palette(&[(255, 0, 0), (0, 0, 255)], &[vec![0, 1]])
+                .build();
+        let transform = ColorTransform::Simulate(ColorVisionDeficiency::Protanopia);
+        assert_eq!(
+            crate::png::recolor_png_bytes(&mut png, &transform).unwrap(),
+            1
+        );
+        let check = crate::png::check::check_png(&png);
+        let plte = check.chunks.iter().find(|c| c.name() == "PLTE").unwrap();
+        assert_eq!(plte.data, [115, 115, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn text_literals_are_simulated() {
+        let transform = ColorTransform::Simulate(ColorVisionDeficiency::Protanopia);
+        let (text, changes) =
+            crate::recolor_text("a=#ff0000\nb=#ffffff\nc=#FF0000\n", &transform).unwrap();
+        assert_eq!(
+            (text.as_str(), changes),
+            ("a=#737300\nb=#ffffff\nc=#737300\n", 2)
+        );
+        assert_eq!(
+            ColorVisionDeficiency::try_from("deutan").unwrap(),
+            ColorVisionDeficiency::Deuteranopia
+        );
+        assert!(ColorVisionDeficiency::try_from("achromat").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Coalescing of bursts of events, for reacting to files rewritten over and over without
+//! reacting every time. Events are grouped per key, a target path say, and a key is due once
+//! it went a whole window without events. Whatever is due fires together, and never sooner
+//! than a minimum interval after the last firing, so a build loop rewriting a stylesheet
+//! every second doesn't keep a bar restarting.
+//!
+//! Nothing here reads a clock, times are handed in as the time since any fixed start.
+//! `tran daemon` keeps one for the targets it watches and one for its hooks.
+
+use std::time::Duration;
+
+/// A key fired after `events` events were coalesced into it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fired<K> {
+    pub key: K,
+    pub events: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Debouncer<K> {
+    window: Duration,
+    min_interval: Duration,
+    /// The keys with events since they last fired, when the last one came and how many.
+    pending: Vec<(K, Duration, usize)>,
+    last_fired: Option<Duration>,
+}
+
+impl<K: PartialEq> Debouncer<K> {
+    pub fn new(window: Duration, min_interval: Duration) -> Self {
+        Debouncer {
+            window,
+            min_interval,
+            pending: Vec::new(),
+            last_fired: None,
+        }
+    }
+
+    /// Records an event for `key` at `now`, starting its window over.
+    pub fn event(&mut self, key: K, now: Duration) {
+        match self
+            .pending
+            .iter_mut()
+            .find(|(pending, _, _)| *pending == key)
+        {
+            Some((_, last, events)) => {
+                *last = now;
+                *events += 1;
+            }
+            None => self.pending.push((key, now, 1)),
+        }
+    }
+
+    /// When the next key is due, to sleep until then. `None` when nothing is pending.
+    pub fn next_due(&self) -> Option<Duration> {
+        let quiet = self
+            .pending
+            .iter()
+            .map(|(_, last, _)| *last + self.window)
+            .min()?;
+        Some(match self.last_fired {
+            Some(fired) => quiet.max(fired + self.min_interval),
+            None => quiet,
+        })
+    }
+
+    /// Takes the keys due at `now`, in the order their first events came. Keys still in their
+    /// window stay pending, and nothing is due before the minimum interval is over.
+    pub fn due(&mut self, now: Duration) -> Vec<Fired<K>> {
+        if self
+            .last_fired
+            .is_some_and(|fired| now < fired + self.min_interval)
+        {
+            return Vec::new();
+        }
+        let (due, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, last, _)| now >= *last + self.window);
+        self.pending = pending;
+        if !due.is_empty() {
+            self.last_fired = Some(now);
+        }
+        due.into_iter()
+            .map(|(key, _, events)| Fired { key, events })
+            .collect()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mock clock, in seconds since the start.
+    fn at(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn fired<K>(key: K, events: usize) -> Fired<K> {
+        Fired { key, events }
+    }
+
+    #[test]
+    fn a_burst_fires_once_after_the_window() {
+        let mut debouncer = Debouncer::new(at(2), at(0));
+        for second in 0..5 {
+            debouncer.event("style.css", at(second));
+            assert_eq!(debouncer.due(at(second)), []);
+        }
+        assert_eq!(debouncer.next_due(), Some(at(6)));
+        assert_eq!(debouncer.due(at(5)), []);
+        assert_eq!(debouncer.due(at(6)), [fired("style.css", 5)]);
+        assert!(debouncer.is_idle());
+        assert_eq!(debouncer.next_due(), None);
+    }
+
+    #[test]
+    fn keys_wait_for_their_own_windows() {
+        let mut debouncer = Debouncer::new(at(2), at(0));
+        debouncer.event("a", at(0));
+        debouncer.event("b", at(1));
+        debouncer.event("a", at(1));
+        assert_eq!(debouncer.due(at(2)), []);
+        assert_eq!(debouncer.due(at(3)), [fired("a", 2), fired("b", 1)]);
+
+        debouncer.event("a", at(10));
+        debouncer.event("b", at(11));
+        assert_eq!(debouncer.due(at(12)), [fired("a", 1)]);
+        assert_eq!(debouncer.next_due(), Some(at(13)));
+        assert_eq!(debouncer.due(at(13)), [fired("b", 1)]);
+    }
+
+    #[test]
+    fn firings_keep_the_minimum_interval() {
+        let mut debouncer = Debouncer::new(at(1), at(10));
+        debouncer.event("hook", at(0));
+        assert_eq!(debouncer.due(at(1)), [fired("hook", 1)]);
+
+        // A build loop writing every second only gets a firing every ten
+        for second in 2..8 {
+            debouncer.event("hook", at(second));
+        }
+        assert_eq!(debouncer.next_due(), Some(at(11)));
+        assert_eq!(debouncer.due(at(9)), []);
+        assert_eq!(debouncer.due(at(10)), []);
+        assert_eq!(debouncer.due(at(11)), [fired("hook", 6)]);
+
+        debouncer.event("hook", at(30));
+        assert_eq!(debouncer.next_due(), Some(at(31)));
+        assert_eq!(debouncer.due(at(31)), [fired("hook", 1)]);
+    }
+
+    #[test]
+    fn events_in_the_minimum_interval_are_coalesced() {
+        let mut debouncer = Debouncer::new(at(0), at(5));
+        debouncer.event(1, at(0));
+        assert_eq!(debouncer.due(at(0)), [fired(1, 1)]);
+        debouncer.event(1, at(1));
+        debouncer.event(2, at(2));
+        debouncer.event(1, at(3));
+        assert_eq!(debouncer.due(at(4)), []);
+        assert_eq!(debouncer.due(at(5)), [fired(1, 2), fired(2, 1)]);
+    }
+
+    #[test]
+    fn nothing_pending_fires_nothing() {
+        let mut debouncer: Debouncer<&str> = Debouncer::new(at(1), at(1));
+        assert!(debouncer.is_idle());
+        assert_eq!(debouncer.due(at(100)), []);
+        // Firing nothing doesn't start the minimum interval
+        debouncer.event("a", at(100));
+        assert_eq!(debouncer.due(at(101)), [fired("a", 1)]);
+    }
+}
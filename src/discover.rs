@@ -0,0 +1,490 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+pub use crate::text::glob_match;
+use crate::{
+    config::{Color, Config, Traversal},
+    errors::TranError,
+    recolor::{is_backup, read_head},
+    FileFormat,
+};
+
+/// Files larger than this are left out unless told otherwise.
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+// Text with a NUL byte this early on is taken for a binary
+const BINARY_SNIFF_LEN: usize = 8192;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoverOptions {
+    /// Globs matched against the path relative to the searched directory and against the file
+    /// name, `*` standing for any run of characters and `?` for one.
+    pub exclude: Vec<String>,
+    pub max_size: u64,
+    pub traversal: Traversal,
+}
+
+impl Default for DiscoverOptions {
+    fn default() -> Self {
+        DiscoverOptions {
+            exclude: Vec::new(),
+            max_size: DEFAULT_MAX_SIZE,
+            traversal: Traversal::default(),
+        }
+    }
+}
+
+/// A line of a `.gitignore` or `.ignore` file: globs of `*` and `?` per path component, `**`
+/// for any number of them, `!` to take a path back and a trailing `/` for directories only.
+/// Character classes aren't supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct IgnoreRule {
+    components: Vec<String>,
+    /// Matched against the path from the ignore file down, not just the name.
+    anchored: bool,
+    directories_only: bool,
+    negated: bool,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (directories_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let components: Vec<String> = line
+            .trim_start_matches('/')
+            .split('/')
+            .map(str::to_string)
+            .collect();
+        if components.iter().all(String::is_empty) {
+            return None;
+        }
+        Some(IgnoreRule {
+            components,
+            anchored,
+            directories_only,
+            negated,
+        })
+    }
+
+    /// Whether the rule matches `relative`, the components of a path below the ignore file.
+    fn matches(&self, relative: &[&str], is_dir: bool) -> bool {
+        if self.directories_only && !is_dir {
+            return false;
+        }
+        match self.anchored {
+            true => components_match(&self.components, relative),
+            false => relative
+                .last()
+                .is_some_and(|name| glob_match(&self.components[0], name)),
+        }
+    }
+}
+
+fn components_match(pattern: &[String], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(any), _) if any == "**" => {
+            (0..=path.len()).any(|skip| components_match(&pattern[1..], &path[skip..]))
+        }
+        (Some(component), Some(name)) => {
+            glob_match(component, name) && components_match(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+/// The rules of the ignore files of one directory, `.ignore` after `.gitignore` so it wins.
+struct IgnoreFile {
+    dir: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreFile {
+    fn read(dir: &Path) -> Option<Self> {
+        let rules: Vec<IgnoreRule> = [".gitignore", ".ignore"]
+            .iter()
+            .filter_map(|name| std::fs::read_to_string(dir.join(name)).ok())
+            .flat_map(|contents| {
+                contents
+                    .lines()
+                    .filter_map(IgnoreRule::parse)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        (!rules.is_empty()).then(|| IgnoreFile {
+            dir: dir.to_path_buf(),
+            rules,
+        })
+    }
+}
+
+/// Whether `path` is ignored by `files`, outermost first. The last rule matching wins, so a
+/// deeper file overrides the ones above it.
+fn ignored(files: &[Rc<IgnoreFile>], path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for file in files {
+        let Ok(relative) = path.strip_prefix(&file.dir) else {
+            continue;
+        };
+        let relative: Vec<&str> = relative
+            .components()
+            .filter_map(|c| c.as_os_str().to_str())
+            .collect();
+        for rule in file.rules.iter() {
+            if rule.matches(&relative, is_dir) {
+                ignored = !rule.negated;
+            }
+        }
+    }
+    ignored
+}
+
+/// Like `glob_match` on `path`, a `/` standing for the separators of the platform, so
+/// `*/alacritty/*` also matches `C:\Users\me\alacritty\alacritty.toml` on Windows.
+pub fn path_glob_match(pattern: &str, path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        glob_match(&pattern.replace('\\', "/"), &path.replace('\\', "/"))
+    } else {
+        glob_match(pattern, &path)
+    }
+}
+
+fn excluded(options: &DiscoverOptions, relative: &Path) -> bool {
+    let name = relative.file_name().unwrap_or_default().to_string_lossy();
+    options
+        .exclude
+        .iter()
+        .any(|pattern| path_glob_match(pattern, relative) || glob_match(pattern, &name))
+}
+
+/// Every file below `dir` not excluded and small enough, in a stable order, leaving out what
+/// `options.traversal` skips, backups unless it takes them, and the directories of `skipped`.
+/// Symlinks are not followed so a link back up the tree can't loop.
+fn walk(
+    dir: &Path,
+    options: &DiscoverOptions,
+    skipped: &[PathBuf],
+) -> Result<Vec<PathBuf>, TranError> {
+    let traversal = options.traversal;
+    let mut files = Vec::new();
+    // Each directory with the ignore files that apply to it
+    let mut pending = vec![(dir.to_path_buf(), Vec::new())];
+    while let Some((current, mut ignore_files)) = pending.pop() {
+        if traversal.respect_gitignore {
+            if let Some(file) = IgnoreFile::read(&current) {
+                ignore_files.push(Rc::new(file));
+            }
+        }
+        let mut entries: Vec<_> = std::fs::read_dir(&current)
+            .map_err(|e| TranError::read(&current, e))?
+            .filter_map(Result::ok)
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        // Pushed in reverse so directories are walked in name order too
+        for entry in entries.into_iter().rev() {
+            let path = entry.path();
+            if excluded(options, path.strip_prefix(dir).unwrap_or(&path)) {
+                continue;
+            }
+            let Ok(kind) = entry.file_type() else {
+                continue;
+            };
+            if traversal.respect_gitignore && ignored(&ignore_files, &path, kind.is_dir()) {
+                continue;
+            }
+            if kind.is_dir() {
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if (traversal.hidden || !hidden) && !skipped.contains(&path) {
+                    pending.push((path, ignore_files.clone()));
+                }
+            } else if kind.is_file()
+                && (traversal.backups || !is_backup(&entry.file_name()))
+                && entry
+                    .metadata()
+                    .is_ok_and(|meta| meta.len() <= options.max_size)
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The colors of `path` the way a run would see them, or `None` for binaries and files that
+/// can't be read.
+fn file_colors(path: &Path) -> Option<Vec<Color>> {
+    let head = read_head(path).ok()?;
+    if FileFormat::detect(path, &head) == FileFormat::Png {
+        #[cfg(not(feature = "png"))]
+        return None;
+        #[cfg(feature = "png")]
+        {
+            let data = std::fs::read(path).ok()?;
+            return crate::png::palette_colors(&data).ok();
+        }
+    }
+    let data = std::fs::read(path).ok()?;
+    if data[..data.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+        return None;
+    }
+    let text = std::str::from_utf8(&data).ok()?;
+    Some(
+        crate::text::hex_tokens(text)
+            .iter()
+            .map(|t| t.color)
+            .collect(),
+    )
+}
+
+/// Files below `dir` holding any configured or current color that aren't targets yet, as
+/// canonical paths.
+pub fn discover(
+    config: &Config,
+    dir: &Path,
+    options: &DiscoverOptions,
+) -> Result<Vec<PathBuf>, TranError> {
+    let mut wanted: HashSet<Color> = config.get_current_colors().into_iter().collect();
+    for selection in config.get_selections() {
+        wanted.extend(selection.colors().iter().copied());
+    }
+    let targets: HashSet<PathBuf> = config
+        .get_targets()
+        .iter()
+        .filter_map(|target| std::fs::canonicalize(&target.path).ok())
+        .collect();
+
+    // The state directory holds backups, which are full of the old colors
+    let skipped: Vec<PathBuf> = crate::state::get_state_dir()
+        .ok()
+        .and_then(|state| std::fs::canonicalize(state).ok())
+        .into_iter()
+        .collect();
+    // Compared with the canonical state directory, which only works from a canonical start
+    let dir = std::fs::canonicalize(dir).map_err(|e| TranError::read(dir, e))?;
+    let files = walk(&dir, options, &skipped)?;
+    let matches = |path: &PathBuf| {
+        file_colors(path).is_some_and(|colors| colors.iter().any(|c| wanted.contains(c)))
+    };
+    #[cfg(feature = "parallel")]
+    let found = crate::parallel::map(&files, matches);
+    #[cfg(not(feature = "parallel"))]
+    let found: Vec<bool> = files.iter().map(matches).collect();
+
+    let mut candidates = Vec::new();
+    for (path, found) in files.iter().zip(found) {
+        let Ok(path) = std::fs::canonicalize(path) else {
+            continue;
+        };
+        // A target line can't hold a newline, so such a file could never be added
+        let listable = path.to_str().is_some_and(|p| !p.contains('\n'));
+        if found && listable && !targets.contains(&path) && !candidates.contains(&path) {
+            candidates.push(path);
+        }
+    }
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config_contents, test_util::TempDir};
+
+    fn config(targets: &[&Path]) -> Config {
+        let targets: Vec<String> = targets.iter().map(|t| t.display().to_string()).collect();
+        parse_config_contents(&format!(
+            "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n[target_files]\n{}\n",
+            targets.join("\n")
+        ))
+        .unwrap()
+    }
+
+    /// The names of `found` below `dir`.
+    fn relative(dir: &TempDir, found: Vec<PathBuf>) -> Vec<String> {
+        let dir = std::fs::canonicalize(dir.path()).unwrap();
+        found
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_files_holding_configured_colors_are_found() {
+        let dir = TempDir::new("discover");
+        let target = dir.write("target.css", "a { color: #2e3440; }\n");
+        dir.write("current.css", "a { color: #2e3440; }\n");
+        dir.write("nested/configured.conf", "fg=#88C0D0\n");
+        dir.write("unrelated.conf", "fg=#123456\n");
+        dir.write("short.conf", "fg=#2e344\n");
+        dir.write("binary.dat", b"\0\0#2e3440");
+        dir.write(
+            "large.conf",
+            format!("fg=#2e3440\n{}", "x".repeat(DEFAULT_MAX_SIZE as usize)),
+        );
+        dir.write("vendor/lib.css", "a { color: #2e3440; }\n");
+        dir.write(".hidden/theme.css", "a { color: #2e3440; }\n");
+        dir.write("theme.css.bak", "a { color: #2e3440; }\n");
+        #[cfg(feature = "png")]
+        dir.write(
+            "icon.png",
+            crate::png::build::PngBuilder::palette(&[(0x88, 0xc0, 0xd0)], &[vec![0]]).build(),
+        );
+
+        let options = DiscoverOptions {
+            exclude: vec!["vendor/*".to_string()],
+            ..DiscoverOptions::default()
+        };
+        let found = discover(&config(&[&target]), dir.path(), &options).unwrap();
+        let expected = match cfg!(feature = "png") {
+            true => vec!["current.css", "icon.png", "nested/configured.conf"],
+            false => vec!["current.css", "nested/configured.conf"],
+        };
+        assert_eq!(relative(&dir, found), expected);
+    }
+
+    #[test]
+    fn targets_are_known_by_their_canonical_path() {
+        let dir = TempDir::new("discover-targets");
+        dir.write("sub/theme.css", "a { color: #2e3440; }\n");
+        let roundabout = dir.path().join("sub/../sub/./theme.css");
+        let found = discover(
+            &config(&[&roundabout]),
+            dir.path(),
+            &DiscoverOptions::default(),
+        );
+        assert!(found.unwrap().is_empty());
+    }
+
+    #[test]
+    fn traversal_options_take_hidden_directories_and_backups() {
+        let dir = TempDir::new("discover-traversal");
+        dir.write(".config/theme.css", "a { color: #2e3440; }\n");
+        dir.write("theme.css~", "a { color: #2e3440; }\n");
+        let options = DiscoverOptions {
+            traversal: Traversal {
+                hidden: true,
+                backups: true,
+                ..Traversal::default()
+            },
+            max_size: 8,
+            ..DiscoverOptions::default()
+        };
+        assert!(discover(&config(&[]), dir.path(), &options)
+            .unwrap()
+            .is_empty());
+        let options = DiscoverOptions {
+            max_size: DEFAULT_MAX_SIZE,
+            ..options
+        };
+        let found = discover(&config(&[]), dir.path(), &options).unwrap();
+        assert_eq!(relative(&dir, found), [".config/theme.css", "theme.css~"]);
+    }
+
+    #[test]
+    fn ignore_files_leave_out_what_they_ignore() {
+        let dir = TempDir::new("discover-ignore");
+        let theme = "a { color: #2e3440; }\n";
+        dir.write(
+            ".gitignore",
+            "# generated\nbuild/\n*.log\n!keep.log\n/top.css\n",
+        );
+        dir.write("sub/.ignore", "deep/**/skip.css\n");
+        for name in [
+            "build/a.css",
+            "x.log",
+            "keep.log",
+            "top.css",
+            "sub/top.css",
+            "sub/deep/skip.css",
+            "sub/deep/a/b/skip.css",
+            "sub/deep/kept.css",
+        ] {
+            dir.write(name, theme);
+        }
+
+        let found = discover(&config(&[]), dir.path(), &DiscoverOptions::default()).unwrap();
+        assert_eq!(found.len(), 8);
+        let options = DiscoverOptions {
+            traversal: Traversal {
+                respect_gitignore: true,
+                ..Traversal::default()
+            },
+            ..DiscoverOptions::default()
+        };
+        let found = discover(&config(&[]), dir.path(), &options).unwrap();
+        assert_eq!(
+            relative(&dir, found),
+            ["keep.log", "sub/deep/kept.css", "sub/top.css"]
+        );
+    }
+
+    #[test]
+    fn ignore_rules_parse_as_git_reads_them() {
+        assert_eq!(IgnoreRule::parse("# comment"), None);
+        assert_eq!(IgnoreRule::parse("/"), None);
+        let rule = IgnoreRule::parse("!logs/").unwrap();
+        assert!(rule.negated && rule.directories_only && !rule.anchored);
+        assert!(rule.matches(&["a", "logs"], true));
+        assert!(!rule.matches(&["a", "logs"], false));
+        let rule = IgnoreRule::parse("\\!bang").unwrap();
+        assert!(!rule.negated);
+        assert!(rule.matches(&["!bang"], false));
+        let rule = IgnoreRule::parse("a/*.css").unwrap();
+        assert!(rule.matches(&["a", "x.css"], false));
+        assert!(!rule.matches(&["b", "a", "x.css"], false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_with_bars_are_found_and_listed_escaped() {
+        let dir = TempDir::new("discover-bars");
+        dir.write("a|b.css", "a { color: #2e3440; }\n");
+        let found = discover(&config(&[]), dir.path(), &DiscoverOptions::default()).unwrap();
+        assert_eq!(relative(&dir, found.clone()), ["a|b.css"]);
+
+        let line = crate::config::Target::new(&found[0]).to_string();
+        assert!(line.ends_with("a||b.css"), "{}", line);
+        let listed = config(&[Path::new(&line)]);
+        assert_eq!(listed.get_targets()[0].path, found[0]);
+        assert!(discover(&listed, dir.path(), &DiscoverOptions::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn exclude_globs_match_paths_and_names() {
+        assert!(path_glob_match(
+            "*/alacritty/*",
+            Path::new("home/alacritty/alacritty.toml")
+        ));
+        assert!(!path_glob_match(
+            "*/alacritty/*",
+            Path::new("alacritty.toml")
+        ));
+        let options = DiscoverOptions {
+            exclude: vec!["*.min.css".to_string()],
+            ..DiscoverOptions::default()
+        };
+        assert!(excluded(&options, Path::new("deep/down/site.min.css")));
+        assert!(!excluded(&options, Path::new("deep/down/site.css")));
+    }
+}
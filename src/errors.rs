@@ -1,57 +1,302 @@
+use std::path::PathBuf;
+
+use crate::config::{Mode, Section};
+
+/// Why a string isn't a hex color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HexError {
+    Empty,
+    /// The first character that isn't a hex digit.
+    InvalidDigit(char),
+    /// How many digits there were, neither three nor six.
+    Length(usize),
+    /// Bare digits where colors are listed together, which could be taken for a weight.
+    MissingPrefix,
+}
+
+impl std::fmt::Display for HexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HexError::Empty => write!(f, "there are no digits"),
+            HexError::InvalidDigit(c) => write!(f, "'{}' is not a hex digit", c),
+            HexError::Length(len) => write!(f, "it has {} digits", len),
+            HexError::MissingPrefix => write!(f, "it needs a '#' or '0x' in front"),
+        }
+    }
+}
+
+#[non_exhaustive]
 pub enum TranError {
     ConfigError(String),
-    FileReadError(String),
-    FileNotFoundError(String),
-    WritingConfigError(String),
+    FileReadError {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    FileWriteError {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    FileNotFoundError(PathBuf),
+    WritingConfigError {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    InvalidHexColor {
+        input: String,
+        reason: HexError,
+    },
+    /// A config without a section it can't do without, or without `[mode]` before the colors
+    /// that depend on it.
+    MissingSection(Section),
+    /// Colors read for one mode in a config that turned out to be in another.
+    InconsistentMode {
+        expected: Mode,
+        found: Mode,
+    },
     PngFormatError(String),
     UnsupportedError(String),
+    /// A target resolving outside of every directory in `[allowed_roots]`.
+    OutsideAllowedRoots(PathBuf),
+    /// A copy that would be written over an existing file under `CollisionPolicy::Error`.
+    OutputExists(PathBuf),
+    /// An exec command that could not run, failed or timed out.
+    ExecError(String),
+    InFile {
+        path: PathBuf,
+        source: Box<TranError>,
+    },
+    /// Work stopped as a `Cancellation` asked, before anything more was written.
+    Cancelled,
 }
 
-impl std::fmt::Display for TranError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl TranError {
+    pub fn read(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        TranError::FileReadError {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    pub fn write(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        TranError::FileWriteError {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    /// What a build without the `png` feature reports for a png.
+    #[cfg(not(feature = "png"))]
+    pub fn without_png() -> Self {
+        TranError::UnsupportedError("built without png support".to_string())
+    }
+
+    /// Attaches `path` unless the error already names it.
+    pub fn in_file(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        if self.path() == Some(path.as_path()) {
+            return self;
+        }
+        TranError::InFile {
+            path,
+            source: Box::new(self),
+        }
+    }
+
+    pub fn path(&self) -> Option<&std::path::Path> {
         match self {
-            TranError::ConfigError(reason) => write!(f, "Error reading config: {}", reason),
-            TranError::FileReadError(contents) => write!(f, "Error reading file {}", contents),
-            TranError::FileNotFoundError(file_name) => {
-                write!(f, "Could not find file {}", file_name)
-            }
-            TranError::WritingConfigError(contents) => {
-                write!(f, "Could not write config file {}", contents)
+            TranError::FileReadError { path, .. } | TranError::FileWriteError { path, .. } => {
+                path.as_deref()
             }
-            TranError::PngFormatError(reason) => write!(f, "Error reading png file: {}", reason),
-            TranError::UnsupportedError(reason) => write!(f, "{}", reason),
+            TranError::FileNotFoundError(path)
+            | TranError::OutsideAllowedRoots(path)
+            | TranError::OutputExists(path)
+            | TranError::WritingConfigError { path, .. }
+            | TranError::InFile { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Whether this is `Cancelled`, for a file or not.
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            TranError::Cancelled => true,
+            TranError::InFile { source, .. } => source.is_cancelled(),
+            _ => false,
+        }
+    }
+
+    /// The io error kind for io-derived variants.
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        match self {
+            TranError::FileReadError { source, .. }
+            | TranError::FileWriteError { source, .. }
+            | TranError::WritingConfigError { source, .. } => Some(source.kind()),
+            TranError::FileNotFoundError(_) => Some(std::io::ErrorKind::NotFound),
+            TranError::InFile { source, .. } => source.io_kind(),
+            _ => None,
         }
     }
 }
 
-impl std::fmt::Debug for TranError {
+impl std::fmt::Display for TranError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TranError::ConfigError(reason) => write!(f, "Error reading config: {}", reason),
-            TranError::FileReadError(contents) => write!(f, "Error reading file {}", contents),
-            TranError::FileNotFoundError(file_name) => {
-                write!(f, "Could not find file {}", file_name)
+            TranError::FileReadError { path, source } => match path {
+                Some(path) => write!(f, "Error reading file {}: {}", path.display(), source),
+                None => write!(f, "Error reading file: {}", source),
+            },
+            TranError::FileWriteError { path, source } => match path {
+                Some(path) => write!(f, "Error writing file {}: {}", path.display(), source),
+                None => write!(f, "Error writing file: {}", source),
+            },
+            TranError::FileNotFoundError(path) => {
+                write!(f, "Could not find file {}", path.display())
+            }
+            TranError::WritingConfigError { path, source } => {
+                write!(
+                    f,
+                    "Could not write config file {}: {}",
+                    path.display(),
+                    source
+                )
             }
-            TranError::WritingConfigError(contents) => {
-                write!(f, "Could not write config file {}", contents)
+            TranError::InvalidHexColor { input, reason } => {
+                write!(
+                    f,
+                    "Error reading config: Could not interpret '{}' as a hex color, {}. Expected six hex digits or three to be doubled, after an optional '#' or '0x', as in #2e3440, 0x2E3440 or #abc",
+                    input, reason
+                )
             }
+            TranError::MissingSection(Section::Mode) => write!(
+                f,
+                "Error reading config: Missing a [mode] section before the colors, tran can't tell how to read them"
+            ),
+            TranError::MissingSection(section) => {
+                write!(f, "Error reading config: Missing a [{}] section", section)
+            }
+            TranError::InconsistentMode { expected, found } => write!(
+                f,
+                "Error reading config: Found {} mode settings in a config in {} mode",
+                found, expected
+            ),
             TranError::PngFormatError(reason) => write!(f, "Error reading png file: {}", reason),
             TranError::UnsupportedError(reason) => write!(f, "{}", reason),
+            TranError::OutsideAllowedRoots(path) => write!(
+                f,
+                "WARNING: skipped {}, it is outside every directory in [allowed_roots]",
+                path.display()
+            ),
+            TranError::OutputExists(path) => write!(
+                f,
+                "{} already exists, set [collision] to overwrite, skip or number to write it anyway",
+                path.display()
+            ),
+            TranError::ExecError(reason) => write!(f, "{}", reason),
+            TranError::InFile { path, source } => write!(f, "{}: {}", path.display(), source),
+            TranError::Cancelled => write!(f, "The run was cancelled"),
         }
     }
 }
 
-impl std::error::Error for TranError {}
-
-impl From<std::io::Error> for TranError {
-    fn from(value: std::io::Error) -> Self {
-        TranError::FileReadError(value.to_string())
+impl std::fmt::Debug for TranError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
     }
 }
 
+impl std::error::Error for TranError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TranError::FileReadError { source, .. }
+            | TranError::FileWriteError { source, .. }
+            | TranError::WritingConfigError { source, .. } => Some(source),
+            TranError::InFile { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<std::num::ParseIntError> for TranError {
     fn from(value: std::num::ParseIntError) -> Self {
         TranError::ConfigError(value.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Color;
+    use std::error::Error;
+
+    fn denied() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied")
+    }
+
+    #[test]
+    fn io_errors_keep_their_kind_and_source() {
+        let error = TranError::read("/etc/theme", denied());
+        assert_eq!(error.io_kind(), Some(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(error.path(), Some(std::path::Path::new("/etc/theme")));
+        let source = error.source().unwrap();
+        let io = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(error.to_string(), "Error reading file /etc/theme: denied");
+    }
+
+    #[test]
+    fn wrapped_errors_chain_to_the_io_error() {
+        let error = TranError::FileReadError {
+            path: None,
+            source: denied(),
+        }
+        .in_file("/a.png");
+        assert!(matches!(error, TranError::InFile { .. }));
+        assert_eq!(error.to_string(), "/a.png: Error reading file: denied");
+        let inner = error.source().unwrap();
+        assert!(inner.downcast_ref::<TranError>().is_some());
+        let io = inner.source().unwrap().downcast_ref::<std::io::Error>();
+        assert_eq!(
+            io.map(|e| e.kind()),
+            Some(std::io::ErrorKind::PermissionDenied)
+        );
+        assert_eq!(error.io_kind(), Some(std::io::ErrorKind::PermissionDenied));
+    }
+
+    #[test]
+    fn errors_naming_the_file_are_not_wrapped_again() {
+        let error = TranError::write("/a.css", denied()).in_file("/a.css");
+        assert!(matches!(error, TranError::FileWriteError { .. }));
+        let error = TranError::Cancelled.in_file("/a.css").in_file("/a.css");
+        let TranError::InFile { source, .. } = &error else {
+            panic!("expected a wrapped error");
+        };
+        assert!(matches!(**source, TranError::Cancelled));
+        assert!(error.is_cancelled());
+    }
+
+    #[test]
+    fn bad_colors_can_be_matched() {
+        let reason = |input: &str| match Color::try_from_hex_str(input) {
+            Err(TranError::InvalidHexColor { reason, .. }) => reason,
+            other => panic!("expected an invalid color, got {:?}", other.map(|_| ())),
+        };
+        assert_eq!(reason("#12345"), HexError::Length(5));
+        assert_eq!(reason("#12345g"), HexError::InvalidDigit('g'));
+        assert_eq!(reason("#"), HexError::Empty);
+        assert!(TranError::ConfigError(String::new()).source().is_none());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn a_missing_config_names_its_path() {
+        let path = std::env::temp_dir().join("tran-no-such-config");
+        let error = crate::config::parse_config(&path).unwrap_err();
+        assert_eq!(error.io_kind(), Some(std::io::ErrorKind::NotFound));
+        assert!(
+            error.to_string().contains("tran-no-such-config"),
+            "{}",
+            error
+        );
+    }
+}
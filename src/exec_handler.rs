@@ -0,0 +1,433 @@
+//! Recolors files tran has no handler for by running a command from the config, as in
+//! `photo.jpg|exec=recolor-jpeg {input} {output} {from} {to}`.
+//!
+//! The command is split into arguments here and run without a shell, so colors and paths are
+//! always passed as single arguments, whatever they contain.
+
+use std::time::Duration;
+#[cfg(feature = "fs")]
+use std::{
+    ffi::OsString,
+    path::Path,
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+#[cfg(feature = "fs")]
+use crate::config::Color;
+use crate::errors::TranError;
+#[cfg(feature = "fs")]
+use crate::{temp::TempFile, ColorMap, ColorTransform};
+
+const PLACEHOLDERS: [&str; 5] = ["input", "output", "from", "to", "mappings"];
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A command line recoloring `{input}` into `{output}`. With `{mappings}` it runs once and
+/// reads every mapping from a file of `from to` lines, otherwise it runs once per mapping with
+/// `{from}` and `{to}` filled in.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ExecCommand {
+    pub args: Vec<String>,
+    /// How long a single run may take before it is killed.
+    pub timeout: Duration,
+}
+
+impl ExecCommand {
+    /// Splits `command` into arguments the way a shell would, single and double quotes and
+    /// backslashes included, and checks its placeholders.
+    pub fn parse(command: &str) -> Result<Self, TranError> {
+        let args = split_args(command)?;
+        if args.is_empty() {
+            return Err(TranError::ConfigError(
+                "An exec command can't be empty".to_string(),
+            ));
+        }
+        let mut used = Vec::new();
+        for arg in args.iter() {
+            let mut rest = arg.as_str();
+            while let Some(start) = rest.find('{') {
+                let after = &rest[start + 1..];
+                let Some(end) = after.find('}') else {
+                    break;
+                };
+                if !PLACEHOLDERS.contains(&&after[..end]) {
+                    return Err(TranError::ConfigError(format!(
+                        "Unknown placeholder '{{{}}}' in exec command, valid placeholders are {}",
+                        &after[..end],
+                        PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                    )));
+                }
+                used.push(&after[..end]);
+                rest = &after[end + 1..];
+            }
+        }
+        let uses = |placeholder: &str| used.contains(&placeholder);
+        if !uses("input") || !uses("output") {
+            return Err(TranError::ConfigError(
+                "An exec command needs both {input} and {output}".to_string(),
+            ));
+        }
+        let per_mapping = uses("from") && uses("to");
+        if !(per_mapping || uses("mappings")) {
+            return Err(TranError::ConfigError(
+                "An exec command needs {from} and {to}, or {mappings}".to_string(),
+            ));
+        }
+        Ok(ExecCommand {
+            args,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    #[cfg(feature = "fs")]
+    fn takes_mappings_file(&self) -> bool {
+        self.args.iter().any(|arg| arg.contains("{mappings}"))
+    }
+}
+
+impl std::fmt::Display for ExecCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|arg| quote(arg)).collect();
+        write!(f, "{}", args.join(" "))
+    }
+}
+
+/// `arg` quoted so `split_args` reads it back as one argument.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '\'' | '"' | '\\' | '|'));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn split_args(command: &str) -> Result<Vec<String>, TranError> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => args.extend(arg.take()),
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(unclosed(command)),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(unclosed(command)),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(unclosed(command)),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars.next().ok_or_else(|| unclosed(command))?;
+                arg.get_or_insert_with(String::new).push(escaped);
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+fn unclosed(command: &str) -> TranError {
+    TranError::ConfigError(format!("Unclosed quote in exec command '{}'", command))
+}
+
+/// The pairs of colors `transform` takes from and to. A gradient hands over the color it
+/// replaces, the command gets no say in the shades around it. A chain hands over its stages
+/// composed.
+#[cfg(feature = "fs")]
+fn mappings(transform: &ColorTransform) -> Result<Vec<(Color, Color)>, TranError> {
+    match transform {
+        ColorTransform::Map(map) => map
+            .iter()
+            .filter(|m| !m.keeps_color())
+            .map(ColorMap::colors)
+            .collect(),
+        ColorTransform::Gradient {
+            primary, current, ..
+        } => Ok(vec![(
+            Color::try_from_hex_str(current)?,
+            Color::try_from_hex_str(primary)?,
+        )]),
+        ColorTransform::Simulate(_) => Err(TranError::UnsupportedError(
+            "An exec command only takes color mappings, not simulations".to_string(),
+        )),
+        ColorTransform::Chain(stages) => {
+            crate::text::chain(stages, &[], |stage, _| mappings(stage))
+        }
+    }
+}
+
+/// Runs `command` once with its placeholders filled, failing when it exits with anything but
+/// success or outlives its timeout.
+#[cfg(feature = "fs")]
+fn run_once(
+    command: &ExecCommand,
+    input: &Path,
+    output: &Path,
+    mapping: Option<&(Color, Color)>,
+    mappings_file: Option<&Path>,
+) -> Result<(), TranError> {
+    let args: Vec<OsString> = command
+        .args
+        .iter()
+        .map(|arg| {
+            let mut filled = OsString::new();
+            let mut rest = arg.as_str();
+            while let Some(start) = rest.find('{') {
+                filled.push(&rest[..start]);
+                let after = &rest[start..];
+                let Some(end) = after.find('}') else {
+                    rest = after;
+                    break;
+                };
+                match (&after[1..end], mapping) {
+                    ("input", _) => filled.push(input),
+                    ("output", _) => filled.push(output),
+                    ("from", Some((from, _))) => filled.push(from.to_string()),
+                    ("to", Some((_, to))) => filled.push(to.to_string()),
+                    ("mappings", _) => filled.push(mappings_file.unwrap_or(Path::new(""))),
+                    _ => filled.push(&after[..=end]),
+                }
+                rest = &after[end + 1..];
+            }
+            filled.push(rest);
+            filled
+        })
+        .collect();
+
+    let program = &command.args[0];
+    // The command's output would get mixed into tran's own, --json included
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(std::io::stderr())
+        .spawn()
+        .map_err(|e| TranError::ExecError(format!("Could not run {}: {}", program, e)))?;
+    let deadline = Instant::now() + command.timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(TranError::ExecError(format!(
+                    "{} took longer than {} seconds and was stopped",
+                    program,
+                    command.timeout.as_secs()
+                )));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => {
+                return Err(TranError::ExecError(format!(
+                    "Could not wait for {}: {}",
+                    program, e
+                )))
+            }
+        }
+    };
+    if !status.success() {
+        return Err(TranError::ExecError(format!(
+            "{} failed with {}",
+            program, status
+        )));
+    }
+    if !output.is_file() {
+        return Err(TranError::ExecError(format!(
+            "{} did not write {}",
+            program,
+            output.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Recolors `source` into `output` with `command`, which may be `source` itself. The command
+/// only ever writes to scratch files next to `output`, which replaces it once every run
+/// succeeded. Returns the number of mappings handed to the command.
+#[cfg(feature = "fs")]
+pub fn recolor(
+    source: &Path,
+    output: &Path,
+    command: &ExecCommand,
+    transform: &ColorTransform,
+) -> Result<usize, TranError> {
+    let mappings = mappings(transform)?;
+    let scratch = [TempFile::next_to(output), TempFile::next_to(output)];
+    let mappings_file = TempFile::next_to(output);
+
+    // The scratch files are removed when dropped, the one moved over `output` included
+    match run_all(source, command, &mappings, &scratch, mappings_file.path())? {
+        Some(done) => std::fs::rename(done, output).map_err(|e| TranError::write(output, e))?,
+        None if source != output => {
+            std::fs::copy(source, output).map_err(|e| TranError::write(output, e))?;
+        }
+        None => {}
+    }
+    Ok(mappings.len())
+}
+
+/// Runs `command` for `mappings`, returning the scratch file holding the result, or `None`
+/// when there was nothing to map.
+#[cfg(feature = "fs")]
+fn run_all<'a>(
+    source: &Path,
+    command: &ExecCommand,
+    mappings: &[(Color, Color)],
+    scratch: &'a [TempFile; 2],
+    mappings_file: &Path,
+) -> Result<Option<&'a Path>, TranError> {
+    if mappings.is_empty() {
+        return Ok(None);
+    }
+    if command.takes_mappings_file() {
+        let lines: String = mappings
+            .iter()
+            .map(|(from, to)| format!("{} {}\n", from, to))
+            .collect();
+        crate::perms::write(mappings_file, lines)
+            .map_err(|e| TranError::write(mappings_file, e))?;
+        run_once(
+            command,
+            source,
+            scratch[0].path(),
+            None,
+            Some(mappings_file),
+        )?;
+        return Ok(Some(scratch[0].path()));
+    }
+    // Every mapping after the first works on what the one before it wrote
+    let mut done = None;
+    for (i, mapping) in mappings.iter().enumerate() {
+        let next = scratch[i % 2].path();
+        let _ = std::fs::remove_file(next);
+        run_once(command, done.unwrap_or(source), next, Some(mapping), None)?;
+        done = Some(next);
+    }
+    Ok(done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_split_like_a_shell_and_read_back() {
+        let command =
+            ExecCommand::parse(r#"magick {input} -fill "{to}" -opaque '{from}' out\ put {output}"#)
+                .unwrap();
+        assert_eq!(
+            command.args,
+            ["magick", "{input}", "-fill", "{to}", "-opaque", "{from}", "out put", "{output}"]
+        );
+        assert_eq!(ExecCommand::parse(&command.to_string()).unwrap(), command);
+
+        let error = |command: &str| ExecCommand::parse(command).unwrap_err().to_string();
+        assert!(error("").contains("can't be empty"));
+        assert!(
+            error("cmd {input} {output} {from} {size}").contains("Unknown placeholder '{size}'")
+        );
+        assert!(error("cmd {input} {from} {to}").contains("both {input} and {output}"));
+        assert!(error("cmd {input} {output} {from}").contains("or {mappings}"));
+        assert!(error("cmd '{input} {output} {mappings}").contains("Unclosed quote"));
+    }
+}
+
+#[cfg(all(test, feature = "fs", unix))]
+mod run_tests {
+    use super::*;
+    use crate::{test_util::TempDir, ColorMap};
+
+    fn map() -> [ColorMap<'static>; 2] {
+        [
+            ColorMap::new("#88c0d0", "#2e3440"),
+            ColorMap::new("#bf616a", "#88c0d0"),
+        ]
+    }
+
+    #[test]
+    fn mappings_run_one_after_the_other() {
+        let dir = TempDir::new("exec-chain");
+        let source = dir.write("photo.txt", "#2e3440 #88c0d0\n");
+        let output = dir.path().join("photo_out.txt");
+        let command = ExecCommand::parse(
+            r#"sh -c 'sed "s/$2/$3/g" "$0" > "$1"' {input} {output} {from} {to}"#,
+        )
+        .unwrap();
+        let map = map();
+        let runs = recolor(&source, &output, &command, &ColorTransform::Map(&map)).unwrap();
+        assert_eq!(runs, 2);
+        // The second mapping sees what the first wrote
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "#bf616a #bf616a\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&source).unwrap(),
+            "#2e3440 #88c0d0\n"
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn a_mappings_file_takes_every_mapping_at_once() {
+        let dir = TempDir::new("exec-mappings");
+        let source = dir.write("photo.txt", "");
+        let command =
+            ExecCommand::parse(r#"sh -c 'cat "$2" > "$1"' {input} {output} {mappings}"#).unwrap();
+        let map = map();
+        recolor(&source, &source, &command, &ColorTransform::Map(&map)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&source).unwrap(),
+            "#2e3440 #88c0d0\n#88c0d0 #bf616a\n"
+        );
+    }
+
+    #[test]
+    fn failing_and_slow_commands_leave_the_output_alone() {
+        let dir = TempDir::new("exec-failing");
+        let source = dir.write("photo.txt", "#2e3440\n");
+        let map = map();
+        let transform = ColorTransform::Map(&map);
+
+        let failing = ExecCommand::parse("false {input} {output} {mappings}").unwrap();
+        let Err(TranError::ExecError(reason)) = recolor(&source, &source, &failing, &transform)
+        else {
+            panic!("expected the command to fail");
+        };
+        assert!(reason.starts_with("false failed with"), "{}", reason);
+
+        let mut slow = ExecCommand::parse("sh -c 'sleep 5' {input} {output} {mappings}").unwrap();
+        slow.timeout = Duration::from_millis(50);
+        let Err(TranError::ExecError(reason)) = recolor(&source, &source, &slow, &transform) else {
+            panic!("expected the command to be stopped");
+        };
+        assert!(reason.contains("was stopped"), "{}", reason);
+
+        assert_eq!(std::fs::read_to_string(&source).unwrap(), "#2e3440\n");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::{
+    config::{Color, Config},
+    errors::TranError,
+    json::Json,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    Css,
+    Json,
+    /// `export` lines to `eval` in a POSIX shell, see `export_shell`.
+    Sh,
+    /// Raw terminal escape sequences, see `terminal_sequences`.
+    Sequences,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Shell {
+    Posix,
+    Fish,
+}
+
+pub const DEFAULT_SHELL_PREFIX: &str = "TRAN_";
+// Gradients export this many shades from the primary down to the background
+const SHELL_RAMP_LEN: usize = 16;
+
+impl TryFrom<&str> for ExportFormat {
+    type Error = TranError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "css" => Ok(ExportFormat::Css),
+            "json" => Ok(ExportFormat::Json),
+            "sh" => Ok(ExportFormat::Sh),
+            "sequences" => Ok(ExportFormat::Sequences),
+            _ => Err(TranError::UnsupportedError(format!(
+                "Unrecognized export format '{}', valid formats are 'css', 'json', 'sh' and 'sequences'",
+                value
+            ))),
+        }
+    }
+}
+
+/// Named colors of the active scheme, in the order they are exported.
+pub fn scheme(config: &Config) -> Vec<(String, Color)> {
+    let colors = config.get_current_colors();
+    let mut scheme: Vec<(String, Color)> = colors
+        .iter()
+        .enumerate()
+        .map(|(i, color)| (format!("color-{}", i), *color))
+        .collect();
+
+    if let Some(primary) = colors.first() {
+        scheme.push(("primary".to_string(), *primary));
+    }
+    // Gradients are always applied against a black background, see `run::apply`
+    if let Config::GradientConfig(_) = config {
+        scheme.push(("background".to_string(), Color::black()));
+    }
+
+    scheme
+}
+
+const RAMP_STEPS: usize = 8;
+
+fn swatch(html: &mut String, color: &Color, label: &str) {
+    // Labels stay readable whatever the swatch color
+    let text = if color.is_dark() {
+        Color::white()
+    } else {
+        Color::black()
+    };
+    html.push_str(&format!(
+        "<div class=\"swatch\" style=\"background:{};color:{}\">{}</div>",
+        color, text, label
+    ));
+}
+
+/// A standalone page showing every configured color or map row as swatches with their weight
+/// and tags, the current one highlighted, and for gradients the ramp from each color to the
+/// background.
+pub fn preview_html(config: &Config) -> String {
+    let current = config.get_current_colors();
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>tran preview</title>\n",
+        "<style>\n",
+        "body{font-family:monospace;background:#202020;color:#e0e0e0;margin:2em}\n",
+        ".row{display:flex;align-items:center;gap:4px;margin:8px 0;padding:4px;border:2px solid transparent}\n",
+        ".current{border-color:#e0e0e0}\n",
+        ".swatch{width:6em;height:3em;display:flex;align-items:center;justify-content:center;font-size:0.8em}\n",
+        ".ramp .swatch{width:3em}\n",
+        ".info{width:8em}\n",
+        "</style>\n</head>\n<body>\n",
+    ));
+    html.push_str(&format!("<h1>{} mode</h1>\n", config.get_mode()));
+
+    for (i, selection) in config.get_selections().iter().enumerate() {
+        let weight = match config {
+            Config::GradientConfig(gc) => gc.get_weight(i),
+            Config::MapConfig(mc) => mc.get_weight(i),
+        };
+        let is_current = selection.colors() == current.as_slice();
+        html.push_str(if is_current {
+            "<div class=\"row current\">"
+        } else {
+            "<div class=\"row\">"
+        });
+        let tags = config.get_tags(i);
+        html.push_str(&format!(
+            "<div class=\"info\">weight {}{}{}</div>",
+            weight,
+            if tags.is_empty() {
+                String::new()
+            } else {
+                format!("<br>{}", tags.join(", "))
+            },
+            if is_current { "<br>current" } else { "" }
+        ));
+        for color in selection.colors() {
+            swatch(&mut html, color, &color.to_hex(config.get_hex_case()));
+        }
+        html.push_str("</div>\n");
+
+        if let (Config::GradientConfig(_), Some(color)) = (config, selection.colors().first()) {
+            html.push_str("<div class=\"row ramp\"><div class=\"info\"></div>");
+            for step in color.ramp_in(&Color::black(), RAMP_STEPS, config.get_gradient_space()) {
+                swatch(&mut html, &step, "");
+            }
+            html.push_str("</div>\n");
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+pub fn export(config: &Config, format: ExportFormat) -> String {
+    let scheme = scheme(config);
+    let case = config.get_hex_case();
+    match format {
+        ExportFormat::Css => {
+            let mut css = String::from(":root {\n");
+            for (name, color) in scheme {
+                css.push_str(&format!("  --tran-{}: {};\n", name, color.to_hex(case)));
+            }
+            css.push_str("}\n");
+            css
+        }
+        ExportFormat::Json => {
+            let mut json = Json::object();
+            for (name, color) in scheme {
+                json = json.with(&name, color.to_hex(case));
+            }
+            format!("{}\n", json)
+        }
+        ExportFormat::Sh => export_shell(config, DEFAULT_SHELL_PREFIX, Shell::Posix)
+            .expect("the default prefix is a valid variable name"),
+        ExportFormat::Sequences => terminal_sequences(config),
+    }
+}
+
+// Terminals keep a palette of 256 colors, OSC 4 can't set more
+const PALETTE_LEN: usize = 256;
+
+/// `color` as an XParseColor spec, which every terminal taking OSC colors understands.
+fn osc_color(color: &Color) -> String {
+    let (red, green, blue) = color.bytes();
+    format!("rgb:{:02x}/{:02x}/{:02x}", red, green, blue)
+}
+
+/// The OSC 4 palette, OSC 10 foreground and OSC 11 background sequences of the active scheme,
+/// in the form `~/.cache/wal/sequences` has for shells to `cat` on startup. The palette is the
+/// map row or a ramp from the gradient primary to the background. Map rows have `color0` as
+/// background and `color7` as foreground, or black or white, whichever reads on the
+/// background, for rows of fewer colors. Gradients put the primary on black.
+pub fn terminal_sequences(config: &Config) -> String {
+    let colors = config.get_current_colors();
+    let (palette, foreground, background) = match (config, colors.first()) {
+        (Config::GradientConfig(_), Some(primary)) => (
+            std::iter::once(*primary)
+                .chain(primary.ramp_in(
+                    &Color::black(),
+                    SHELL_RAMP_LEN - 1,
+                    config.get_gradient_space(),
+                ))
+                .collect(),
+            *primary,
+            Color::black(),
+        ),
+        (_, Some(background)) => {
+            let foreground = colors
+                .get(7)
+                .copied()
+                .unwrap_or(match background.is_dark() {
+                    true => Color::white(),
+                    false => Color::black(),
+                });
+            (colors.clone(), foreground, *background)
+        }
+        (_, None) => (Vec::new(), Color::white(), Color::black()),
+    };
+
+    let mut sequences = String::new();
+    for (i, color) in palette.iter().take(PALETTE_LEN).enumerate() {
+        sequences.push_str(&format!("\x1b]4;{};{}\x1b\\", i, osc_color(color)));
+    }
+    sequences.push_str(&format!("\x1b]10;{}\x1b\\", osc_color(&foreground)));
+    sequences.push_str(&format!("\x1b]11;{}\x1b\\", osc_color(&background)));
+    sequences
+}
+
+fn quote(value: &str, shell: Shell) -> String {
+    match shell {
+        Shell::Posix => format!("'{}'", value.replace('\'', "'\\''")),
+        Shell::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+    }
+}
+
+/// Lines setting an environment variable per color for `shell`, named `prefix` followed by
+/// `COLOR0` and up for the map row or a ramp from the gradient primary to the background, then
+/// `PRIMARY` and, for gradients, `BACKGROUND`.
+pub fn export_shell(config: &Config, prefix: &str, shell: Shell) -> Result<String, TranError> {
+    let valid = prefix
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(TranError::UnsupportedError(format!(
+            "'{}' can't start a variable name, use letters, digits and underscores",
+            prefix
+        )));
+    }
+
+    let colors = config.get_current_colors();
+    let mut variables: Vec<(String, Color)> = match (config, colors.first()) {
+        (Config::GradientConfig(_), Some(primary)) => std::iter::once(*primary)
+            .chain(primary.ramp_in(
+                &Color::black(),
+                SHELL_RAMP_LEN - 1,
+                config.get_gradient_space(),
+            ))
+            .enumerate()
+            .map(|(i, color)| (format!("COLOR{}", i), color))
+            .collect(),
+        _ => colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| (format!("COLOR{}", i), *color))
+            .collect(),
+    };
+    if let Some(primary) = colors.first() {
+        variables.push(("PRIMARY".to_string(), *primary));
+    }
+    if let Config::GradientConfig(_) = config {
+        variables.push(("BACKGROUND".to_string(), Color::black()));
+    }
+
+    let mut output = String::new();
+    for (name, color) in variables {
+        let value = quote(&color.to_hex(config.get_hex_case()), shell);
+        match shell {
+            Shell::Posix => output.push_str(&format!("export {}{}={}\n", prefix, name, value)),
+            Shell::Fish => output.push_str(&format!("set -x {}{} {}\n", prefix, name, value)),
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config_contents;
+
+    fn gradient() -> Config {
+        parse_config_contents("[mode]\ngradient\n[current_color]\n#88c0d0\n[colors]\n#bf616a\n")
+            .unwrap()
+    }
+
+    fn map() -> Config {
+        parse_config_contents(
+            "[mode]\nmap\n[current_color]\n#2e3440#88c0d0#ECEFF4\n[colors]\n#3b4252#81a1c1#e5e9f0\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn css_of_a_gradient() {
+        assert_eq!(
+            export(&gradient(), ExportFormat::Css),
+            ":root {\n  --tran-color-0: #88c0d0;\n  --tran-primary: #88c0d0;\n  --tran-background: #000000;\n}\n"
+        );
+    }
+
+    #[test]
+    fn css_of_a_map() {
+        assert_eq!(
+            export(&map(), ExportFormat::Css),
+            concat!(
+                ":root {\n",
+                "  --tran-color-0: #2e3440;\n",
+                "  --tran-color-1: #88c0d0;\n",
+                "  --tran-color-2: #eceff4;\n",
+                "  --tran-primary: #2e3440;\n",
+                "}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn json_of_both_modes() {
+        assert_eq!(
+            export(&gradient(), ExportFormat::Json),
+            "{\"color-0\":\"#88c0d0\",\"primary\":\"#88c0d0\",\"background\":\"#000000\"}\n"
+        );
+        assert_eq!(
+            export(&map(), ExportFormat::Json),
+            "{\"color-0\":\"#2e3440\",\"color-1\":\"#88c0d0\",\"color-2\":\"#eceff4\",\"primary\":\"#2e3440\"}\n"
+        );
+    }
+
+    #[test]
+    fn terminal_sequences_of_a_map() {
+        assert_eq!(
+            export(&map(), ExportFormat::Sequences),
+            concat!(
+                "\x1b]4;0;rgb:2e/34/40\x1b\\",
+                "\x1b]4;1;rgb:88/c0/d0\x1b\\",
+                "\x1b]4;2;rgb:ec/ef/f4\x1b\\",
+                // A row without color7 takes white on its dark color0
+                "\x1b]10;rgb:ff/ff/ff\x1b\\",
+                "\x1b]11;rgb:2e/34/40\x1b\\",
+            )
+        );
+    }
+
+    #[test]
+    fn terminal_sequences_of_a_gradient() {
+        let sequences = terminal_sequences(&gradient());
+        assert_eq!(sequences.matches("\x1b]4;").count(), SHELL_RAMP_LEN);
+        assert!(sequences.starts_with("\x1b]4;0;rgb:88/c0/d0\x1b\\"));
+        assert!(sequences.ends_with("\x1b]10;rgb:88/c0/d0\x1b\\\x1b]11;rgb:00/00/00\x1b\\"));
+        assert_eq!(
+            ExportFormat::try_from("sequences").unwrap(),
+            ExportFormat::Sequences
+        );
+    }
+
+    #[test]
+    fn formats_are_named() {
+        assert_eq!(ExportFormat::try_from("css").unwrap(), ExportFormat::Css);
+        assert_eq!(ExportFormat::try_from("json").unwrap(), ExportFormat::Json);
+        assert!(ExportFormat::try_from("yaml").is_err());
+    }
+
+    #[test]
+    fn preview_of_a_map() {
+        let config = parse_config_contents(
+            "[mode]\nmap\n[current_color]\n#2e3440#eceff4\n[colors]\n2#2e3440#eceff4\nlight:#eceff4#2e3440\n",
+        )
+        .unwrap();
+        assert_eq!(
+            preview_html(&config),
+            include_str!("../tests/golden/preview_map.html")
+        );
+    }
+
+    #[test]
+    fn preview_of_a_gradient() {
+        let config = parse_config_contents(
+            "[mode]\ngradient\n[current_color]\n#88c0d0\n[colors]\n#88c0d0\n3#bf616a\n",
+        )
+        .unwrap();
+        assert_eq!(
+            preview_html(&config),
+            include_str!("../tests/golden/preview_gradient.html")
+        );
+    }
+
+    #[test]
+    fn shell_snippets_of_a_map() {
+        let config = parse_config_contents(
+            "[mode]\nmap\n[current_color]\n#2e3440#eceff4\n[colors]\n#eceff4#2e3440\n",
+        )
+        .unwrap();
+        assert_eq!(
+            export_shell(&config, "C_", Shell::Posix).unwrap(),
+            "export C_COLOR0='#2e3440'\nexport C_COLOR1='#eceff4'\nexport C_PRIMARY='#2e3440'\n"
+        );
+        assert_eq!(
+            export_shell(&config, "C_", Shell::Fish).unwrap(),
+            "set -x C_COLOR0 '#2e3440'\nset -x C_COLOR1 '#eceff4'\nset -x C_PRIMARY '#2e3440'\n"
+        );
+    }
+
+    #[test]
+    fn shell_snippets_of_a_gradient() {
+        assert_eq!(
+            export_shell(&gradient(), DEFAULT_SHELL_PREFIX, Shell::Posix).unwrap(),
+            include_str!("../tests/golden/gradient.sh")
+        );
+        assert_eq!(
+            export_shell(&gradient(), DEFAULT_SHELL_PREFIX, Shell::Fish).unwrap(),
+            include_str!("../tests/golden/gradient.fish")
+        );
+        assert_eq!(
+            export(&gradient(), ExportFormat::Sh),
+            include_str!("../tests/golden/gradient.sh")
+        );
+    }
+
+    #[test]
+    fn shell_values_are_quoted_safely() {
+        assert_eq!(quote("#88c0d0", Shell::Posix), "'#88c0d0'");
+        assert_eq!(quote("it's $HOME", Shell::Posix), "'it'\\''s $HOME'");
+        assert_eq!(quote("it's \\", Shell::Fish), "'it\\'s \\\\'");
+    }
+
+    #[test]
+    fn prefixes_have_to_start_a_variable_name() {
+        for prefix in ["", "1X", "A-B", "A B", "$(rm)"] {
+            assert!(
+                export_shell(&gradient(), prefix, Shell::Posix).is_err(),
+                "{}",
+                prefix
+            );
+        }
+        assert!(export_shell(&gradient(), "_my_1", Shell::Fish).is_ok());
+    }
+}
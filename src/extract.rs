@@ -0,0 +1,234 @@
+use std::cmp::Reverse;
+
+use crate::{
+    config::Color,
+    errors::TranError,
+    space::{to_hsl, GradientSpace},
+};
+
+/// Colors in a row made by `scheme_row`, laid out like a terminal palette.
+pub const SCHEME_LEN: usize = 16;
+const ACCENTS: usize = 6;
+
+fn channel(color: &Color, channel: usize) -> u8 {
+    let (r, g, b) = color.bytes();
+    [r, g, b][channel]
+}
+
+/// The channel `colors` spread out the most along and how far.
+fn widest(colors: &[(Color, usize)]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let values = colors.iter().map(|(color, _)| channel(color, c));
+            let extent = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (c, extent)
+        })
+        .max_by_key(|&(c, extent)| (extent, Reverse(c)))
+        .unwrap_or((0, 0))
+}
+
+fn mean(colors: &[(Color, usize)]) -> Color {
+    let total: usize = colors.iter().map(|(_, count)| count).sum::<usize>().max(1);
+    let average = |c: usize| {
+        let sum: usize = colors
+            .iter()
+            .map(|(color, count)| channel(color, c) as usize * count)
+            .sum();
+        ((sum + total / 2) / total) as u8
+    };
+    Color::from_bytes(average(0), average(1), average(2))
+}
+
+/// Median cut of `counts`, as `png::color_counts` gives them, into at most `n` colors, most
+/// pixels first. Every step splits the bucket with the widest channel in the middle of that
+/// channel, ties going the same way every time so an image always gives the same colors.
+pub fn dominant_colors(counts: &[(Color, usize)], n: usize) -> Vec<Color> {
+    if counts.is_empty() || n == 0 {
+        return Vec::new();
+    }
+    let mut buckets = vec![counts.to_vec()];
+    while buckets.len() < n {
+        let Some((index, (c, _))) = buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bucket)| bucket.len() > 1)
+            .map(|(i, bucket)| (i, widest(bucket)))
+            .max_by_key(|&(i, (_, extent))| (extent, Reverse(i)))
+        else {
+            break;
+        };
+        let mut bucket = buckets.remove(index);
+        bucket.sort_by_key(|(color, _)| (channel(color, c), color.bytes()));
+        // Halving the range rather than the pixels keeps a big cluster from being cut in two
+        let low = channel(&bucket[0].0, c) as u16;
+        let high = channel(&bucket[bucket.len() - 1].0, c) as u16;
+        let split = bucket
+            .iter()
+            .position(|(color, _)| channel(color, c) as u16 * 2 > low + high)
+            .unwrap_or(1)
+            .clamp(1, bucket.len() - 1);
+        let upper = bucket.split_off(split);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+
+    let mut colors: Vec<(Color, usize)> = buckets
+        .iter()
+        .map(|bucket| (mean(bucket), bucket.iter().map(|(_, count)| count).sum()))
+        .collect();
+    colors.sort_by_key(|&(color, count)| (Reverse(count), color.bytes()));
+    colors.into_iter().map(|(color, _)| color).collect()
+}
+
+/// A row of `SCHEME_LEN` colors: 0 the darkest as background, 1 to 6 the most saturated by
+/// hue, 7 the lightest as foreground and 8 to 15 lighter copies of the first eight. Missing
+/// accents repeat the ones there are.
+pub fn scheme_row(colors: &[Color]) -> Vec<Color> {
+    let space = GradientSpace::Oklab;
+    let mut colors = colors.to_vec();
+    colors.sort_by(|a, b| {
+        space
+            .lightness(a)
+            .total_cmp(&space.lightness(b))
+            .then(a.bytes().cmp(&b.bytes()))
+    });
+    colors.dedup();
+    let background = colors.first().copied().unwrap_or(Color::black());
+    let foreground = match colors.len() {
+        0 | 1 => Color::white(),
+        len => colors[len - 1],
+    };
+
+    let chroma = |c: &Color| {
+        let (r, g, b) = c.bytes();
+        r.max(g).max(b) - r.min(g).min(b)
+    };
+    let mut accents: Vec<Color> = colors
+        .iter()
+        .skip(1)
+        .take(colors.len().saturating_sub(2))
+        .copied()
+        .collect();
+    accents.sort_by_key(|c| (Reverse(chroma(c)), c.bytes()));
+    accents.truncate(ACCENTS);
+    accents.sort_by(|a, b| to_hsl(a)[0].total_cmp(&to_hsl(b)[0]));
+    if accents.is_empty() {
+        accents.push(foreground);
+    }
+
+    let mut row = vec![background];
+    row.extend((0..ACCENTS).map(|i| accents[i % accents.len()]));
+    row.push(foreground);
+    let brights: Vec<Color> = row
+        .iter()
+        .map(|c| space.mix(c, &Color::white(), 0.25))
+        .collect();
+    row.extend(brights);
+    row
+}
+
+/// The scheme row `tran from-image` derives from a png.
+pub fn from_png(file: &[u8]) -> Result<Vec<Color>, TranError> {
+    #[cfg(not(feature = "png"))]
+    {
+        let _ = file;
+        Err(TranError::without_png())
+    }
+    #[cfg(feature = "png")]
+    {
+        let counts = crate::png::color_counts(file)?;
+        Ok(scheme_row(&dominant_colors(&counts, ACCENTS + 2)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Background, six accents and foreground, darkest to lightest at either end
+    const SCHEME: [(u8, u8, u8); 8] = [
+        (0x1d, 0x1f, 0x21),
+        (0xcc, 0x66, 0x66),
+        (0xb5, 0xbd, 0x68),
+        (0xf0, 0xc6, 0x74),
+        (0x81, 0xa2, 0xbe),
+        (0xb2, 0x94, 0xbb),
+        (0x8a, 0xbe, 0xb7),
+        (0xea, 0xea, 0xea),
+    ];
+
+    fn color((r, g, b): (u8, u8, u8)) -> Color {
+        Color::from_bytes(r, g, b)
+    }
+
+    #[test]
+    fn dominant_colors_come_most_pixels_first() {
+        let counts: Vec<(Color, usize)> = SCHEME
+            .iter()
+            .enumerate()
+            .map(|(i, rgb)| (color(*rgb), 10 + i))
+            .collect();
+        let dominant = dominant_colors(&counts, SCHEME.len());
+        let expected: Vec<Color> = SCHEME.iter().rev().map(|rgb| color(*rgb)).collect();
+        assert_eq!(dominant, expected);
+        assert_eq!(dominant_colors(&counts, 1).len(), 1);
+        assert!(dominant_colors(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn close_colors_are_merged_into_their_mean() {
+        let counts = [
+            (color((100, 0, 0)), 1),
+            (color((102, 0, 0)), 1),
+            (color((0, 0, 200)), 3),
+        ];
+        assert_eq!(
+            dominant_colors(&counts, 2),
+            [color((0, 0, 200)), color((101, 0, 0))]
+        );
+    }
+
+    #[test]
+    fn rows_start_dark_and_end_light() {
+        let colors: Vec<Color> = SCHEME.iter().map(|rgb| color(*rgb)).collect();
+        let row = scheme_row(&colors);
+        assert_eq!(row.len(), SCHEME_LEN);
+        assert_eq!(row[0], color(SCHEME[0]));
+        assert_eq!(row[7], color(SCHEME[7]));
+        for accent in &colors[1..7] {
+            assert!(row[1..7].contains(accent), "{} is missing", accent);
+        }
+        for (base, bright) in row[..8].iter().zip(&row[8..]) {
+            let lightness = |c: &Color| GradientSpace::Oklab.lightness(c);
+            assert!(lightness(bright) > lightness(base));
+        }
+        assert_eq!(scheme_row(&[])[0], Color::black());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn every_dominant_color_of_an_image_is_in_its_row() {
+        use crate::png::build::PngBuilder;
+
+        // Blocks of each color, bigger the later they come
+        let pixels: Vec<(u8, u8, u8)> = SCHEME
+            .iter()
+            .enumerate()
+            .flat_map(|(i, rgb)| std::iter::repeat_n(*rgb, 4 * (i + 1)))
+            .collect();
+        let grid: Vec<Vec<(u8, u8, u8)>> = pixels.chunks(12).map(<[_]>::to_vec).collect();
+        let rgb = PngBuilder::rgb(&grid).build();
+        let indices: Vec<Vec<u8>> = (0..SCHEME.len() as u8).map(|i| vec![i; 3]).collect();
+        let indexed = PngBuilder::palette(&SCHEME, &indices).build();
+
+        for file in [rgb, indexed] {
+            let row = from_png(&file).unwrap();
+            assert_eq!(row.len(), SCHEME_LEN);
+            for rgb in SCHEME {
+                assert!(row[..8].contains(&color(rgb)), "{:?} is missing", rgb);
+            }
+            assert_eq!(row[0], color(SCHEME[0]));
+            assert_eq!(from_png(&file).unwrap(), row);
+        }
+    }
+}
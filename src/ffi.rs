@@ -0,0 +1,239 @@
+//! C interface to the byte and string transforms, declared in `include/tran.h`.
+//!
+//! Buffers handed out through `out` belong to the caller, who gives them back with `tran_free`.
+//! Everything passed in stays owned by the caller and is only read during the call.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+use crate::{errors::TranError, ColorMap, ColorTransform};
+
+/// What a call returned, one code per `TranError` variant.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranErrorCode {
+    Ok = 0,
+    ConfigError = 1,
+    FileReadError = 2,
+    FileWriteError = 3,
+    FileNotFoundError = 4,
+    WritingConfigError = 5,
+    InvalidHexColor = 6,
+    PngFormatError = 7,
+    UnsupportedError = 8,
+    OutsideAllowedRoots = 9,
+    /// A null pointer or a string that isn't UTF-8.
+    InvalidArgument = 10,
+    /// tran panicked, which is a bug.
+    Panic = 11,
+    OutputExists = 12,
+    ExecError = 13,
+    MissingSection = 14,
+    InconsistentMode = 15,
+    Cancelled = 16,
+}
+
+impl From<&TranError> for TranErrorCode {
+    fn from(e: &TranError) -> Self {
+        match e {
+            TranError::ConfigError(_) => Self::ConfigError,
+            TranError::FileReadError { .. } => Self::FileReadError,
+            TranError::FileWriteError { .. } => Self::FileWriteError,
+            TranError::FileNotFoundError(_) => Self::FileNotFoundError,
+            TranError::WritingConfigError { .. } => Self::WritingConfigError,
+            TranError::InvalidHexColor { .. } => Self::InvalidHexColor,
+            TranError::PngFormatError(_) => Self::PngFormatError,
+            TranError::UnsupportedError(_) => Self::UnsupportedError,
+            TranError::OutsideAllowedRoots(_) => Self::OutsideAllowedRoots,
+            TranError::OutputExists(_) => Self::OutputExists,
+            TranError::ExecError(_) => Self::ExecError,
+            TranError::MissingSection(_) => Self::MissingSection,
+            TranError::InconsistentMode { .. } => Self::InconsistentMode,
+            TranError::InFile { source, .. } => source.as_ref().into(),
+            TranError::Cancelled => Self::Cancelled,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // Messages come from paths and config lines, which could hold a NUL
+    let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning its error or panic into a code and the last error message.
+fn guard(f: impl FnOnce() -> Result<(), (TranErrorCode, String)>) -> TranErrorCode {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => TranErrorCode::Ok,
+        Ok(Err((code, message))) => {
+            set_last_error(message);
+            code
+        }
+        Err(_) => {
+            set_last_error("tran panicked".to_string());
+            TranErrorCode::Panic
+        }
+    }
+}
+
+fn tran_error(e: TranError) -> (TranErrorCode, String) {
+    ((&e).into(), e.to_string())
+}
+
+fn invalid(message: &str) -> (TranErrorCode, String) {
+    (TranErrorCode::InvalidArgument, message.to_string())
+}
+
+// Every buffer handed out starts with its length, so `tran_free` needs nothing but the pointer
+const HEADER: usize = std::mem::size_of::<usize>();
+
+/// Copies `data` into a new buffer the caller frees with `tran_free`, followed by a NUL so
+/// text can be read as a C string.
+fn hand_out(data: &[u8]) -> *mut u8 {
+    let mut buffer = Vec::with_capacity(HEADER + data.len() + 1);
+    buffer.extend_from_slice(&(data.len() + 1).to_ne_bytes());
+    buffer.extend_from_slice(data);
+    buffer.push(0);
+    let buffer = Box::into_raw(buffer.into_boxed_slice()) as *mut u8;
+    // SAFETY: the buffer is at least HEADER bytes long
+    unsafe { buffer.add(HEADER) }
+}
+
+/// Reads `n` pairs of hex colors into owned strings.
+///
+/// # Safety
+///
+/// `from_hex` and `to_hex` must each point to `n` NUL terminated strings.
+unsafe fn colors(
+    from_hex: *const *const c_char,
+    to_hex: *const *const c_char,
+    n: usize,
+) -> Result<Vec<(String, String)>, (TranErrorCode, String)> {
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    if from_hex.is_null() || to_hex.is_null() {
+        return Err(invalid("Color arrays must not be null"));
+    }
+    let string = |s: *const c_char| -> Result<String, (TranErrorCode, String)> {
+        if s.is_null() {
+            return Err(invalid("Colors must not be null"));
+        }
+        CStr::from_ptr(s)
+            .to_str()
+            .map(str::to_string)
+            .map_err(|_| invalid("Colors must be UTF-8"))
+    };
+    (0..n)
+        .map(|i| Ok((string(*from_hex.add(i))?, string(*to_hex.add(i))?)))
+        .collect()
+}
+
+/// Recolors the png of `len` bytes at `data`, taking `from_hex[i]` to `to_hex[i]`. On success
+/// the new png is written to `*out` and its length to `*out_len`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `from_hex` and `to_hex` to `n` NUL terminated
+/// strings each, and `out` and `out_len` must be writable.
+#[cfg(feature = "png")]
+#[no_mangle]
+pub unsafe extern "C" fn tran_recolor_png_bytes(
+    data: *const u8,
+    len: usize,
+    from_hex: *const *const c_char,
+    to_hex: *const *const c_char,
+    n: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> TranErrorCode {
+    guard(|| {
+        if data.is_null() || out.is_null() || out_len.is_null() {
+            return Err(invalid("data, out and out_len must not be null"));
+        }
+        let pairs = colors(from_hex, to_hex, n)?;
+        let map: Vec<ColorMap> = pairs
+            .iter()
+            .map(|(from, to)| ColorMap::new(to, from))
+            .collect();
+        let mut png = std::slice::from_raw_parts(data, len).to_vec();
+        crate::png::recolor_png_bytes(&mut png, &ColorTransform::Map(&map)).map_err(tran_error)?;
+        *out = hand_out(&png);
+        *out_len = png.len();
+        Ok(())
+    })
+}
+
+/// Recolors the NUL terminated `text`, taking `from_hex[i]` to `to_hex[i]`. On success the new
+/// text is written to `*out` as a NUL terminated string and its length, without the NUL, to
+/// `*out_len`.
+///
+/// # Safety
+///
+/// `text` must be NUL terminated, `from_hex` and `to_hex` must point to `n` NUL terminated
+/// strings each, and `out` and `out_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn tran_recolor_text(
+    text: *const c_char,
+    from_hex: *const *const c_char,
+    to_hex: *const *const c_char,
+    n: usize,
+    out: *mut *mut c_char,
+    out_len: *mut usize,
+) -> TranErrorCode {
+    guard(|| {
+        if text.is_null() || out.is_null() || out_len.is_null() {
+            return Err(invalid("text, out and out_len must not be null"));
+        }
+        let text = CStr::from_ptr(text)
+            .to_str()
+            .map_err(|_| invalid("text must be UTF-8"))?;
+        let pairs = colors(from_hex, to_hex, n)?;
+        let map: Vec<ColorMap> = pairs
+            .iter()
+            .map(|(from, to)| ColorMap::new(to, from))
+            .collect();
+        let (updated, _) =
+            crate::recolor_text(text, &ColorTransform::Map(&map)).map_err(tran_error)?;
+        *out = hand_out(updated.as_bytes()) as *mut c_char;
+        *out_len = updated.len();
+        Ok(())
+    })
+}
+
+/// The message of the last error on this thread, or null if there wasn't one. It stays valid
+/// until the next failing call on the same thread and must not be freed.
+#[no_mangle]
+pub extern "C" fn tran_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Frees a buffer tran handed out. Null is ignored.
+///
+/// # Safety
+///
+/// `ptr` must be null or a buffer from `out` of a tran call, not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn tran_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let start = (ptr as *mut u8).sub(HEADER);
+    let mut len = [0; HEADER];
+    std::ptr::copy_nonoverlapping(start, len.as_mut_ptr(), HEADER);
+    let len = HEADER + usize::from_ne_bytes(len);
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        start, len,
+    )));
+}
@@ -0,0 +1,134 @@
+//! Entry points for the fuzz targets in `fuzz/`, only built with the `fuzzing` feature.
+
+use crate::{ColorMap, ColorTransform};
+
+pub fn parse_config(data: &[u8]) {
+    if let Ok(contents) = std::str::from_utf8(data) {
+        let _ = crate::config::parse_config_contents(contents);
+    }
+}
+
+pub fn text_matcher(data: &[u8]) {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    for token in crate::text::hex_tokens(text) {
+        assert!(text[token.start..token.end].starts_with('#'));
+    }
+    let map = [
+        ColorMap::new("#88c0d0", "#2e3440"),
+        ColorMap::new("#2e3440", "#88c0d0"),
+    ];
+    let _ = crate::recolor_text(text, &ColorTransform::Map(&map));
+}
+
+pub fn png_chunks(data: &[u8]) {
+    let mut file = data.to_vec();
+    let _ = crate::png::recolor_png_bytes(
+        &mut file,
+        &ColorTransform::Gradient {
+            primary: "#88c0d0",
+            background: "#000000",
+            current: "#2e3440",
+            space: crate::space::GradientSpace::Oklab,
+            luminance: crate::pixel::LuminanceBand::default(),
+            stops: &[],
+        },
+    );
+
+    let mut file = data.to_vec();
+    let map = [ColorMap::new("#88c0d0", "#2e3440")];
+    let _ = crate::png::recolor_png_bytes(&mut file, &ColorTransform::Map(&map));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::{RngLike, SystemRng};
+
+    fn corpus(target: &str) -> Vec<Vec<u8>> {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/corpus")
+            .join(target);
+        let mut files: Vec<Vec<u8>> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+            .collect();
+        assert!(!files.is_empty());
+        files.sort();
+        files
+    }
+
+    /// Flips, drops, repeats and overwrites bytes of `seed`, the way a fuzzer starts out.
+    fn mutate(seed: &[u8], rng: &mut SystemRng) -> Vec<u8> {
+        let mut data = seed.to_vec();
+        for _ in 0..1 + rng.below(4) {
+            let at = rng.below(data.len().max(1) as u64) as usize;
+            match rng.below(5) {
+                0 if at < data.len() => data[at] ^= 1 << rng.below(8),
+                1 if at < data.len() => {
+                    data.remove(at);
+                }
+                2 => data.truncate(at),
+                3 if at < data.len() => {
+                    let byte = data[at];
+                    data.insert(at, byte);
+                }
+                _ if at < data.len() => data[at] = [0, 0xff, b'#', b'\n', b'['][at % 5],
+                _ => data.push(rng.next_u64() as u8),
+            }
+        }
+        data
+    }
+
+    fn survives(target: &str, entry: fn(&[u8])) {
+        let mut rng = SystemRng::from_seed(405);
+        for seed in corpus(target) {
+            entry(&seed);
+            for _ in 0..2000 {
+                entry(&mutate(&seed, &mut rng));
+            }
+        }
+    }
+
+    #[test]
+    fn mutated_configs_never_panic() {
+        survives("parse_config", parse_config);
+    }
+
+    #[test]
+    fn mutated_text_never_panics() {
+        survives("text_matcher", text_matcher);
+    }
+
+    #[test]
+    fn mutated_pngs_never_panic() {
+        survives("png_chunks", png_chunks);
+    }
+
+    #[test]
+    fn giant_chunk_lengths_are_refused() {
+        let mut png = corpus("png_chunks").remove(0);
+        // The length of the PLTE chunk
+        png[33..37].copy_from_slice(&0xffff_fff0u32.to_be_bytes());
+        let map = [ColorMap::new("#88c0d0", "#2e3440")];
+        assert!(crate::png::recolor_png_bytes(&mut png, &ColorTransform::Map(&map)).is_err());
+    }
+
+    #[test]
+    fn empty_configs_are_errors() {
+        for contents in ["", "\n", "  \n\t"] {
+            assert!(crate::config::parse_config_contents(contents).is_err());
+        }
+    }
+
+    #[test]
+    fn empty_colors_are_errors() {
+        let map = [ColorMap::new("", "#2e3440")];
+        assert!(crate::recolor_text("#2e3440", &ColorTransform::Map(&map)).is_err());
+        let config = crate::config::parse_config_contents("[mode]\ngradient\n[colors]\n");
+        assert!(config.map_or(true, |config| config
+            .select_next(&mut SystemRng::from_seed(1))
+            .is_err()));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    config::Color,
+    errors::TranError,
+    text::{replace_tokens, replacements, TextOptions, TextReport},
+    ColorTransform,
+};
+
+const DEFINE_COLOR: &str = "@define-color";
+
+fn defined_name(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let keyword = line.get(..DEFINE_COLOR.len())?;
+    if !keyword.eq_ignore_ascii_case(DEFINE_COLOR) {
+        return None;
+    }
+    line[DEFINE_COLOR.len()..].split_whitespace().next()
+}
+
+/// Whether `recolor_gtk` rewrites the colors of `line`.
+pub fn owns_line(line: &str, literals: bool) -> bool {
+    literals || defined_name(line).is_some()
+}
+
+/// The colors `recolor_gtk` would rewrite with `options`, in file order.
+pub fn colors(contents: &str, literals: bool, options: &TextOptions) -> Vec<Color> {
+    contents
+        .split_inclusive('\n')
+        .filter(|line| owns_line(line, literals))
+        .flat_map(|line| options.tokens(line))
+        .map(|t| t.color)
+        .collect()
+}
+
+/// Rewrites the colors of `@define-color` lines, leaving every other byte as it was.
+/// With `literals` colors on any other line are rewritten too.
+pub fn recolor_gtk(
+    contents: &str,
+    transform: &ColorTransform,
+    literals: bool,
+    options: &TextOptions,
+) -> Result<(String, TextReport), TranError> {
+    let replacements = replacements(transform, &colors(contents, literals, options))?;
+    let mut output = String::with_capacity(contents.len());
+    let mut report = TextReport::default();
+
+    for line in contents.split_inclusive('\n') {
+        if !owns_line(line, literals) {
+            output.push_str(line);
+            continue;
+        }
+
+        let (line, changes) = replace_tokens(line, &replacements, options);
+        output.push_str(&line);
+        report.changes += changes;
+        if let (Some(name), true) = (defined_name(&line), changes > 0) {
+            report.names.push(name.trim_end_matches(';').to_string());
+        }
+    }
+
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMap;
+
+    const GTK_CSS: &str = "/* Nord */
+@define-color accent_color #2e3440;
+@define-color accent_bg_color #2E3440;
+@DEFINE-COLOR window_bg_color   #eceff4 ;
+@define-color borders alpha(@accent_color, 0.5);
+
+headerbar {
+  background-image: linear-gradient(to bottom, #2e3440, #3b4252);
+  color: @accent_color;
+}
+";
+
+    fn recolor(literals: bool) -> (String, TextReport) {
+        let map = [ColorMap::new("#88c0d0", "#2e3440")];
+        recolor_gtk(
+            GTK_CSS,
+            &ColorTransform::Map(&map),
+            literals,
+            &TextOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn only_define_lines_are_rewritten() {
+        let (output, report) = recolor(false);
+        let expected = GTK_CSS
+            .replace("accent_color #2e3440", "accent_color #88c0d0")
+            .replace("accent_bg_color #2E3440", "accent_bg_color #88C0D0");
+        assert_eq!(output, expected);
+        assert_eq!(report.changes, 2);
+        assert_eq!(report.names, ["accent_color", "accent_bg_color"]);
+    }
+
+    #[test]
+    fn literals_are_rewritten_when_asked() {
+        let (output, report) = recolor(true);
+        assert!(output.contains("linear-gradient(to bottom, #88c0d0, #3b4252)"));
+        assert_eq!(report.changes, 3);
+        assert_eq!(report.names, ["accent_color", "accent_bg_color"]);
+    }
+
+    #[test]
+    fn defines_are_found_whatever_their_case() {
+        assert_eq!(
+            defined_name("@DEFINE-COLOR window_bg #fff;"),
+            Some("window_bg")
+        );
+        assert_eq!(
+            defined_name("  @define-color fg_color #fff;"),
+            Some("fg_color")
+        );
+        assert_eq!(defined_name("@define-colour fg #fff;"), None);
+        assert_eq!(defined_name("color: #fff;"), None);
+        assert_eq!(
+            colors(GTK_CSS, false, &TextOptions::default())
+                .iter()
+                .map(Color::to_hex_lower)
+                .collect::<Vec<_>>(),
+            ["#2e3440", "#2e3440", "#eceff4"]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{
+    config::Color,
+    errors::TranError,
+    text::{replace_tokens, replacements, TextOptions, TextReport},
+    ColorTransform,
+};
+
+/// The variable of a `set $name <value>` line.
+fn variable(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix("set")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    rest.split_whitespace()
+        .next()
+        .filter(|name| name.starts_with('$'))
+}
+
+fn is_client_line(line: &str) -> bool {
+    line.trim_start().starts_with("client.")
+}
+
+/// Whether `recolor_i3` rewrites the colors of `line`.
+pub fn owns_line(line: &str, clients: bool) -> bool {
+    variable(line).is_some() || (clients && is_client_line(line))
+}
+
+/// The colors `recolor_i3` would rewrite with `options`, in file order.
+pub fn colors(contents: &str, clients: bool, options: &TextOptions) -> Vec<Color> {
+    contents
+        .split_inclusive('\n')
+        .filter(|line| owns_line(line, clients))
+        .flat_map(|line| options.tokens(line))
+        .map(|t| t.color)
+        .collect()
+}
+
+/// Rewrites the colors of `set $name` lines, and with `clients` also those of `client.*` lines.
+/// Spacing, comments and every other line are left as they were.
+pub fn recolor_i3(
+    contents: &str,
+    transform: &ColorTransform,
+    clients: bool,
+    options: &TextOptions,
+) -> Result<(String, TextReport), TranError> {
+    let replacements = replacements(transform, &colors(contents, clients, options))?;
+    let mut output = String::with_capacity(contents.len());
+    let mut report = TextReport::default();
+
+    for line in contents.split_inclusive('\n') {
+        if !owns_line(line, clients) {
+            output.push_str(line);
+            continue;
+        }
+
+        let (line, changes) = replace_tokens(line, &replacements, options);
+        output.push_str(&line);
+        report.changes += changes;
+        if let (Some(name), true) = (variable(&line), changes > 0) {
+            report.names.push(name.to_string());
+        }
+    }
+
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMap;
+
+    const SWAY_CONFIG: &str = "# Colors
+set $bg     #2e3440 # the background
+set\t$fg #d8dee9
+set $mod Mod4
+setting $nope #2e3440
+
+bar {
+    colors {
+        background $bg
+        statusline #2e3440
+    }
+}
+client.focused          #2e3440 #2e3440 $fg #88c0d0
+";
+
+    fn recolor(clients: bool) -> (String, TextReport) {
+        let map = [ColorMap::new("#bf616a", "#2e3440")];
+        recolor_i3(
+            SWAY_CONFIG,
+            &ColorTransform::Map(&map),
+            clients,
+            &TextOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn only_set_lines_are_rewritten() {
+        let (output, report) = recolor(false);
+        assert_eq!(
+            output,
+            SWAY_CONFIG.replace("$bg     #2e3440", "$bg     #bf616a")
+        );
+        assert_eq!(report.changes, 1);
+        assert_eq!(report.names, ["$bg"]);
+    }
+
+    #[test]
+    fn client_lines_are_rewritten_when_asked() {
+        let (output, report) = recolor(true);
+        assert!(output.contains("client.focused          #bf616a #bf616a $fg #88c0d0\n"));
+        assert!(output.contains("statusline #2e3440"));
+        assert_eq!(report.changes, 3);
+        assert_eq!(report.names, ["$bg"]);
+    }
+
+    #[test]
+    fn variables_need_set_and_a_dollar() {
+        assert_eq!(variable("set $bg #2e3440"), Some("$bg"));
+        assert_eq!(variable("  set\t$fg #fff"), Some("$fg"));
+        assert_eq!(variable("setting $bg #2e3440"), None);
+        assert_eq!(variable("set bg #2e3440"), None);
+        assert!(is_client_line("  client.urgent #fff"));
+        assert!(!is_client_line("set $client #fff"));
+    }
+}
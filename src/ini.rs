@@ -0,0 +1,201 @@
+//! Ini-style configs, as dunst's `dunstrc` and mako's `config`, which have no extension and are
+//! picked with `format=ini`. Only the values of color keys are rewritten, and only before the
+//! first section or in sections that theme every notification, so the colors rules give the
+//! notifications they match are left as they are.
+
+use std::ops::Range;
+
+use crate::{
+    config::Color,
+    errors::TranError,
+    text::{glob_match, hex_tokens, replace_tokens, replacements, TextOptions, TextReport},
+    ColorTransform,
+};
+
+/// The color keys of dunst and mako.
+pub const DEFAULT_KEYS: [&str; 9] = [
+    "background",
+    "foreground",
+    "frame_color",
+    "highlight",
+    "separator_color",
+    "background-color",
+    "text-color",
+    "border-color",
+    "progress-color",
+];
+/// dunst's global and urgency sections and mako's urgency criteria.
+pub const DEFAULT_SECTIONS: [&str; 3] = ["global", "urgency_*", "urgency=*"];
+
+/// Which keys of which sections the ini handler rewrites, from `[handlers]`. Both are globs,
+/// see `glob_match`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IniOptions {
+    pub keys: Vec<String>,
+    pub sections: Vec<String>,
+}
+
+impl Default for IniOptions {
+    fn default() -> Self {
+        IniOptions {
+            keys: DEFAULT_KEYS.iter().map(|key| key.to_string()).collect(),
+            sections: DEFAULT_SECTIONS
+                .iter()
+                .map(|section| section.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl IniOptions {
+    /// Every key of every section, to bring back whatever a run rewrote.
+    pub fn everything() -> Self {
+        IniOptions {
+            keys: vec!["*".to_string()],
+            sections: vec!["*".to_string()],
+        }
+    }
+
+    /// Whether the values of `key` are rewritten in `section`, `None` before the first one.
+    pub fn owns(&self, section: Option<&str>, key: &str) -> bool {
+        let matches =
+            |globs: &[String], name: &str| globs.iter().any(|glob| glob_match(glob, name));
+        section.is_none_or(|section| matches(&self.sections, section)) && matches(&self.keys, key)
+    }
+}
+
+/// The key and value of a `key = value` line, comments and section headers having none.
+fn entry(line: &str) -> Option<(&str, Range<usize>)> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with(['#', ';', '[']) {
+        return None;
+    }
+    let (key, value) = line.split_once('=')?;
+    let start = key.len() + 1;
+    Some((key.trim(), start..start + value.len()))
+}
+
+/// The name of a `[section]` header line.
+fn header(line: &str) -> Option<&str> {
+    let name = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+    Some(name.trim())
+}
+
+/// A value of `contents` the handler rewrites, with the name it is reported by.
+struct Value {
+    name: String,
+    value: Range<usize>,
+}
+
+fn values(contents: &str, options: &IniOptions) -> Vec<Value> {
+    let mut values = Vec::new();
+    let mut section = None;
+    let mut at = 0;
+    for line in contents.split_inclusive('\n') {
+        if let Some(name) = header(line) {
+            section = Some(name);
+        } else if let Some((key, value)) = entry(line).filter(|(key, _)| options.owns(section, key))
+        {
+            values.push(Value {
+                name: match section {
+                    Some(section) => format!("{}.{}", section, key),
+                    None => key.to_string(),
+                },
+                value: at + value.start..at + value.end,
+            });
+        }
+        at += line.len();
+    }
+    values
+}
+
+/// Whether `recolor_ini` rewrites colors of `line`, taken to be before any section.
+pub fn owns_line(line: &str, options: &IniOptions) -> bool {
+    entry(line).is_some_and(|(key, _)| options.owns(None, key))
+}
+
+/// The colors `recolor_ini` would consider, in file order.
+pub fn colors(contents: &str, options: &IniOptions) -> Vec<Color> {
+    values(contents, options)
+        .into_iter()
+        .flat_map(|value| hex_tokens(&contents[value.value]))
+        .map(|t| t.color)
+        .collect()
+}
+
+/// Rewrites the colors of the keys and sections `options` names, leaving quotes, comments and
+/// every other byte as they were. Names in the report are the keys rewritten, as
+/// `section.key` inside a section.
+pub fn recolor_ini(
+    contents: &str,
+    transform: &ColorTransform,
+    options: &IniOptions,
+    text: &TextOptions,
+) -> Result<(String, TextReport), TranError> {
+    let replacements = replacements(transform, &colors(contents, options))?;
+    let mut output = String::with_capacity(contents.len());
+    let mut report = TextReport::default();
+
+    let mut last = 0;
+    for value in values(contents, options) {
+        let (replaced, changes) =
+            replace_tokens(&contents[value.value.clone()], &replacements, text);
+        if changes == 0 {
+            continue;
+        }
+        output.push_str(&contents[last..value.value.start]);
+        output.push_str(&replaced);
+        last = value.value.end;
+        report.changes += changes;
+        if !report.names.contains(&value.name) {
+            report.names.push(value.name);
+        }
+    }
+    output.push_str(&contents[last..]);
+
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMap;
+
+    const DUNSTRC: &str = "background = \"#2e3440\"\n[global]\n    frame_color = \"#2E3440\"\n    font = Monospace 10\n[urgency_low]\n    foreground = #2e3440\n[spotify]\n    appname = Spotify\n    background = \"#2e3440\"\n";
+
+    fn recolor(contents: &str, options: &IniOptions) -> (String, TextReport) {
+        let map = [ColorMap::new("#bf616a", "#2e3440")];
+        let transform = ColorTransform::Map(&map);
+        recolor_ini(contents, &transform, options, &TextOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn only_color_keys_of_theming_sections_are_rewritten() {
+        let (dunstrc, report) = recolor(DUNSTRC, &IniOptions::default());
+        assert_eq!(
+            dunstrc,
+            "background = \"#bf616a\"\n[global]\n    frame_color = \"#BF616A\"\n    font = Monospace 10\n[urgency_low]\n    foreground = #bf616a\n[spotify]\n    appname = Spotify\n    background = \"#2e3440\"\n"
+        );
+        assert_eq!(report.changes, 3);
+        assert_eq!(
+            report.names,
+            ["background", "global.frame_color", "urgency_low.foreground"]
+        );
+
+        let (dunstrc, report) = recolor(DUNSTRC, &IniOptions::everything());
+        assert!(!dunstrc.contains("#2e3440"), "{}", dunstrc);
+        assert_eq!(report.names.last().unwrap(), "spotify.background");
+    }
+
+    #[test]
+    fn mako_criteria_and_comments_are_read() {
+        let config = "# background-color=#2e3440\nborder-color=#2e3440\n[urgency=high]\ntext-color=#2e3440\n[app-name=firefox]\ntext-color=#2e3440\n";
+        let options = IniOptions::default();
+        assert_eq!(colors(config, &options).len(), 2);
+        assert!(owns_line("border-color = #2e3440", &options));
+        assert!(!owns_line("; border-color = #2e3440", &options));
+        assert!(!owns_line("font=#2e3440", &options));
+        assert!(options.owns(Some("urgency_critical"), "background"));
+        assert!(!options.owns(Some("app-name=firefox"), "text-color"));
+    }
+}
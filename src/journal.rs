@@ -0,0 +1,504 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cache::content_hash,
+    config::{Color, Config, HexCase},
+    errors::TranError,
+    ini::IniOptions,
+    json::Json,
+    pixel::LuminanceBand,
+    recolor::resolve_output,
+    run::{target_options, with_transform, RunSummary},
+    selection::Selection,
+    space::GradientSpace,
+    state::State,
+    text::TextOptions,
+    FileFormat, RecolorOptions,
+};
+
+/// Text files above this size are undone by applying the inverse colors instead of a backup.
+const MAX_BACKUP_SIZE: u64 = 1024 * 1024;
+const MAX_ENTRIES: usize = 32;
+const ENTRY_EXTENSION: &str = "json";
+const CONFIG_BACKUP: &str = "config";
+
+struct SnapshotFile {
+    path: PathBuf,
+    format: FileFormat,
+    literals: bool,
+    hash: Option<u64>,
+    backup: Option<Vec<u8>>,
+}
+
+/// The files a run is about to write and the config it started from, taken before the run.
+pub struct Snapshot {
+    config_path: PathBuf,
+    config: Vec<u8>,
+    text: TextOptions,
+    ini: IniOptions,
+    files: Vec<SnapshotFile>,
+}
+
+fn keeps_backup(selection: &Selection, format: FileFormat, len: usize) -> bool {
+    // Gradients derive the new colors from the file itself, so there is no inverse to apply
+    // Only text handlers can be run backwards
+    matches!(selection, Selection::Color(_))
+        || matches!(format, FileFormat::Png | FileFormat::Exec)
+        || len as u64 <= MAX_BACKUP_SIZE
+}
+
+impl Snapshot {
+    pub fn take(
+        config_path: &Path,
+        config: &Config,
+        selection: &Selection,
+        options: &RecolorOptions,
+    ) -> Result<Self, TranError> {
+        let config_contents =
+            std::fs::read(config_path).map_err(|e| TranError::read(config_path, e))?;
+        let files = with_transform(
+            selection,
+            &config.get_current_colors(),
+            config.get_gradient_space(),
+            config.get_luminance_band(),
+            config.get_stops(),
+            |transform| {
+                let mut files = Vec::new();
+                for target in config.get_targets() {
+                    let (source, options) = target_options(target, options);
+                    let Ok(data) = std::fs::read(&source) else {
+                        continue;
+                    };
+                    let (format, output) = resolve_output(&source, &data, &options, transform);
+                    let data = if output == source {
+                        Some(data)
+                    } else {
+                        std::fs::read(&output).ok()
+                    };
+                    files.push(SnapshotFile {
+                        path: output,
+                        format,
+                        literals: options.literals,
+                        hash: data.as_deref().map(content_hash),
+                        backup: data.filter(|d| keeps_backup(selection, format, d.len())),
+                    });
+                }
+                files
+            },
+        );
+
+        Ok(Snapshot {
+            config_path: config_path.to_path_buf(),
+            config: config_contents,
+            text: options.text.clone(),
+            ini: options.ini.clone(),
+            files,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Restore {
+    /// Copy the backup with this name in the entry directory back.
+    Backup(String),
+    /// The run created the file.
+    Remove,
+    /// Apply the inverse of the run's colors.
+    Inverse,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalFile {
+    pub path: PathBuf,
+    pub format: FileFormat,
+    /// Whether the run rewrote literals too, see `RecolorOptions::literals`.
+    pub literals: bool,
+    /// Hash right after the run, a different one means the file was modified since.
+    pub hash: u64,
+    pub restore: Restore,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalEntry {
+    pub id: String,
+    pub config_path: PathBuf,
+    pub selection: Selection,
+    pub previous: Vec<Color>,
+    /// The text options the run took colors with, which undoing a map takes them back with.
+    pub text: TextOptions,
+    /// The keys and sections the run rewrote in ini files.
+    pub ini: IniOptions,
+    pub files: Vec<JournalFile>,
+}
+
+fn text_options_json(options: &TextOptions) -> Json {
+    Json::object()
+        .with("hex_case", options.hex_case.to_string())
+        .with("skip_urls", options.skip_urls)
+        .with("skip_hashes", options.skip_hashes)
+        .with("exclude", options.exclude.clone())
+}
+
+fn ini_options_json(options: &IniOptions) -> Json {
+    Json::object()
+        .with("keys", options.keys.clone())
+        .with("sections", options.sections.clone())
+}
+
+fn ini_options_from_json(json: &Json) -> Option<IniOptions> {
+    let globs = |key: &str| -> Option<Vec<String>> {
+        json.get(key)?
+            .as_array()?
+            .iter()
+            .map(|glob| glob.as_str().map(str::to_string))
+            .collect()
+    };
+    Some(IniOptions {
+        keys: globs("keys")?,
+        sections: globs("sections")?,
+    })
+}
+
+fn text_options_from_json(json: &Json) -> Option<TextOptions> {
+    Some(TextOptions {
+        hex_case: HexCase::try_from(json.get("hex_case")?.as_str()?).ok()?,
+        skip_urls: json.get("skip_urls")?.as_bool()?,
+        skip_hashes: json.get("skip_hashes")?.as_bool()?,
+        exclude: json
+            .get("exclude")?
+            .as_array()?
+            .iter()
+            .map(|glob| glob.as_str().map(str::to_string))
+            .collect::<Option<_>>()?,
+    })
+}
+
+impl JournalEntry {
+    pub fn to_json(&self) -> Json {
+        let files: Vec<Json> = self
+            .files
+            .iter()
+            .map(|f| {
+                let restore = match &f.restore {
+                    Restore::Backup(name) => Json::object().with("backup", name.as_str()),
+                    Restore::Remove => Json::object().with("remove", true),
+                    Restore::Inverse => Json::object().with("inverse", true),
+                };
+                Json::object()
+                    .with("path", f.path.to_string_lossy().to_string())
+                    .with("format", f.format.to_string())
+                    .with("literals", f.literals)
+                    .with("hash", format!("{:016x}", f.hash))
+                    .with("restore", restore)
+            })
+            .collect();
+        let colors = |colors: &[Color]| -> Json {
+            colors
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .into()
+        };
+
+        Json::object()
+            .with("id", self.id.as_str())
+            .with("config", self.config_path.to_string_lossy().to_string())
+            .with("row", matches!(self.selection, Selection::Row(_)))
+            .with("new_colors", colors(self.selection.colors()))
+            .with("old_colors", colors(&self.previous))
+            .with("text_options", text_options_json(&self.text))
+            .with("ini_options", ini_options_json(&self.ini))
+            .with("files", files)
+    }
+
+    pub fn from_json(json: &Json) -> Option<Self> {
+        let colors = |key: &str| -> Option<Vec<Color>> {
+            json.get(key)?
+                .as_array()?
+                .iter()
+                .map(|c| c.as_str().and_then(|c| Color::try_from_hex_str(c).ok()))
+                .collect()
+        };
+        let new_colors = colors("new_colors")?;
+        let selection = if json.get("row")?.as_bool()? {
+            Selection::Row(new_colors)
+        } else {
+            Selection::Color(*new_colors.first()?)
+        };
+        let files = json
+            .get("files")?
+            .as_array()?
+            .iter()
+            .map(|f| {
+                let restore = f.get("restore")?;
+                let restore = if let Some(name) = restore.get("backup") {
+                    Restore::Backup(name.as_str()?.to_string())
+                } else if restore.get("remove").is_some() {
+                    Restore::Remove
+                } else {
+                    Restore::Inverse
+                };
+                Some(JournalFile {
+                    path: PathBuf::from(f.get("path")?.as_str()?),
+                    format: FileFormat::try_from(f.get("format")?.as_str()?).ok()?,
+                    // Files journaled before it was kept were undone with literals
+                    literals: f.get("literals").and_then(Json::as_bool).unwrap_or(true),
+                    hash: u64::from_str_radix(f.get("hash")?.as_str()?, 16).ok()?,
+                    restore,
+                })
+            })
+            .collect::<Option<Vec<JournalFile>>>()?;
+
+        Some(JournalEntry {
+            id: json.get("id")?.as_str()?.to_string(),
+            config_path: PathBuf::from(json.get("config")?.as_str()?),
+            selection,
+            previous: colors("old_colors")?,
+            // Runs journaled before they were kept took colors with the defaults
+            text: match json.get("text_options") {
+                Some(text) => text_options_from_json(text)?,
+                None => TextOptions::default(),
+            },
+            // and undid ini files through every key
+            ini: match json.get("ini_options") {
+                Some(ini) => ini_options_from_json(ini)?,
+                None => IniOptions::everything(),
+            },
+            files,
+        })
+    }
+}
+
+fn entry_dir(state: &State, id: &str) -> PathBuf {
+    state.get_journal_dir().join(id)
+}
+
+/// Ids sort in the order the runs happened.
+fn entry_ids(state: &State) -> Result<Vec<String>, TranError> {
+    let dir = state.get_journal_dir();
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut ids: Vec<String> = std::fs::read_dir(&dir)
+        .map_err(|e| TranError::read(&dir, e))?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            if path.extension()? != ENTRY_EXTENSION {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().to_string())
+        })
+        .collect();
+    ids.sort();
+    Ok(ids)
+}
+
+fn remove_entry(state: &State, id: &str) -> Result<(), TranError> {
+    let file = state
+        .get_journal_dir()
+        .join(format!("{}.{}", id, ENTRY_EXTENSION));
+    std::fs::remove_file(&file).map_err(|e| TranError::write(&file, e))?;
+    let dir = entry_dir(state, id);
+    if dir.is_dir() {
+        std::fs::remove_dir_all(&dir).map_err(|e| TranError::write(&dir, e))?;
+    }
+    Ok(())
+}
+
+/// Journals what the run described by `summary` changed, compared to `snapshot`.
+/// Returns `None` when the run changed nothing.
+pub fn record(
+    state: &State,
+    snapshot: Snapshot,
+    summary: &RunSummary,
+    selection: &Selection,
+) -> Result<Option<JournalEntry>, TranError> {
+    let config_after = std::fs::read(&snapshot.config_path).ok();
+    let mut entry = JournalEntry {
+        id: format!(
+            "{:020}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0)
+        ),
+        config_path: snapshot.config_path.clone(),
+        selection: selection.clone(),
+        previous: summary.colors.old.clone(),
+        text: snapshot.text.clone(),
+        ini: snapshot.ini.clone(),
+        files: Vec::new(),
+    };
+    let dir = entry_dir(state, &entry.id);
+    let mut backups = Vec::new();
+
+    for (i, file) in snapshot.files.into_iter().enumerate() {
+        let Ok(data) = std::fs::read(&file.path) else {
+            continue;
+        };
+        let hash = content_hash(&data);
+        if file.hash == Some(hash) {
+            continue;
+        }
+        let restore = match (file.hash, file.backup) {
+            (None, _) => Restore::Remove,
+            (Some(_), Some(backup)) => {
+                let name = i.to_string();
+                backups.push((dir.join(&name), backup));
+                Restore::Backup(name)
+            }
+            (Some(_), None) => Restore::Inverse,
+        };
+        entry.files.push(JournalFile {
+            path: file.path,
+            format: file.format,
+            literals: file.literals,
+            hash,
+            restore,
+        });
+    }
+
+    if entry.files.is_empty() && config_after.as_deref() == Some(snapshot.config.as_slice()) {
+        return Ok(None);
+    }
+
+    backups.push((dir.join(CONFIG_BACKUP), snapshot.config));
+    crate::perms::create_dir_all(&dir).map_err(|e| TranError::write(&dir, e))?;
+    for (path, data) in backups {
+        crate::perms::write(&path, data).map_err(|e| TranError::write(&path, e))?;
+    }
+    let file = state
+        .get_journal_dir()
+        .join(format!("{}.{}", entry.id, ENTRY_EXTENSION));
+    crate::perms::write(&file, entry.to_json().to_string())
+        .map_err(|e| TranError::write(&file, e))?;
+
+    let ids = entry_ids(state)?;
+    for id in ids.iter().take(ids.len().saturating_sub(MAX_ENTRIES)) {
+        remove_entry(state, id)?;
+    }
+
+    Ok(Some(entry))
+}
+
+pub fn last_entry(state: &State) -> Result<Option<JournalEntry>, TranError> {
+    let Some(id) = entry_ids(state)?.pop() else {
+        return Ok(None);
+    };
+    let file = state
+        .get_journal_dir()
+        .join(format!("{}.{}", id, ENTRY_EXTENSION));
+    let contents = std::fs::read_to_string(&file).map_err(|e| TranError::read(&file, e))?;
+    Json::parse(&contents)
+        .ok()
+        .and_then(|json| JournalEntry::from_json(&json))
+        .map(Some)
+        .ok_or_else(|| TranError::ConfigError(format!("Corrupt journal entry {}", file.display())))
+}
+
+/// Files of `entry` that changed since the run wrote them.
+pub fn modified_since(entry: &JournalEntry) -> Vec<PathBuf> {
+    entry
+        .files
+        .iter()
+        .filter(|f| std::fs::read(&f.path).map(|d| content_hash(&d)).ok() != Some(f.hash))
+        .map(|f| f.path.clone())
+        .collect()
+}
+
+/// Puts the contents of `backup` back in `path`. Not `fs::copy`, which would give `path` the
+/// mode of the backup.
+fn restore_backup(backup: &Path, path: &Path) -> Result<(), TranError> {
+    let data = std::fs::read(backup).map_err(|e| TranError::read(backup, e))?;
+    std::fs::write(path, data).map_err(|e| TranError::write(path, e))
+}
+
+fn restore_inverse(file: &JournalFile, entry: &JournalEntry) -> Result<(), TranError> {
+    let path = &file.path;
+    let inverse = match &entry.selection {
+        Selection::Color(_) => {
+            return Err(TranError::UnsupportedError(format!(
+                "A gradient can't be reversed without a backup of {}",
+                path.display()
+            )))
+        }
+        Selection::Row(_) => Selection::Row(entry.previous.clone()),
+    };
+    // Only maps are reversed, so the space never comes into it
+    with_transform(
+        &inverse,
+        entry.selection.colors(),
+        GradientSpace::default(),
+        LuminanceBand::default(),
+        &[],
+        |transform| {
+            let contents = std::fs::read_to_string(path).map_err(|e| TranError::read(path, e))?;
+            let (updated, _) = match file.format {
+                FileFormat::Gtk => {
+                    crate::gtk::recolor_gtk(&contents, transform, file.literals, &entry.text)
+                        .map(|(updated, report)| (updated, report.changes))?
+                }
+                FileFormat::I3 => {
+                    crate::i3::recolor_i3(&contents, transform, file.literals, &entry.text)
+                        .map(|(updated, report)| (updated, report.changes))?
+                }
+                FileFormat::Css => {
+                    crate::css::recolor_css(&contents, transform, file.literals, &entry.text)
+                        .map(|(updated, report)| (updated, report.changes))?
+                }
+                FileFormat::Json => crate::jsonc::recolor_json(&contents, transform, &entry.text)
+                    .map(|(updated, report)| (updated, report.changes))?,
+                FileFormat::Rasi => crate::rasi::recolor_rasi(&contents, transform, &entry.text)
+                    .map(|(updated, report)| (updated, report.changes))?,
+                FileFormat::Ini => {
+                    crate::ini::recolor_ini(&contents, transform, &entry.ini, &entry.text)
+                        .map(|(updated, report)| (updated, report.changes))?
+                }
+                _ => crate::recolor_text_in(&contents, transform, &entry.text)?,
+            };
+            std::fs::write(path, updated).map_err(|e| TranError::write(path, e))
+        },
+    )
+}
+
+/// Reverts the last journaled run: restores its files and the config it started from, then
+/// drops the entry so the next undo goes one run further back. Files modified since the run
+/// are refused unless `force` is set.
+pub fn undo(state: &State, force: bool) -> Result<JournalEntry, TranError> {
+    let entry = last_entry(state)?
+        .ok_or_else(|| TranError::UnsupportedError("Nothing to undo".to_string()))?;
+
+    let modified = modified_since(&entry);
+    if !modified.is_empty() && !force {
+        return Err(TranError::UnsupportedError(format!(
+            "Refusing to undo, modified since the run: {}. Use --force to undo anyway",
+            modified
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    let dir = entry_dir(state, &entry.id);
+    for file in entry.files.iter() {
+        let path = &file.path;
+        match &file.restore {
+            Restore::Backup(name) => {
+                restore_backup(&dir.join(name), path)?;
+            }
+            Restore::Remove => {
+                if path.is_file() {
+                    std::fs::remove_file(path).map_err(|e| TranError::write(path, e))?;
+                }
+            }
+            Restore::Inverse => restore_inverse(file, &entry).map_err(|e| e.in_file(path))?,
+        }
+    }
+
+    restore_backup(&dir.join(CONFIG_BACKUP), &entry.config_path)?;
+
+    remove_entry(state, &entry.id)?;
+    Ok(entry)
+}
@@ -0,0 +1,211 @@
+use std::ops::Range;
+
+use crate::{
+    config::Color,
+    errors::TranError,
+    text::{hex_tokens, replace_tokens, replacements, TextOptions, TextReport},
+    ColorTransform,
+};
+
+/// A string value of the document, never a key.
+struct Value {
+    /// Between the quotes.
+    contents: Range<usize>,
+    /// The key the value belongs to, the one of its array for array elements.
+    key: Option<Range<usize>>,
+}
+
+/// The first byte from `at` outside whitespace and `//` or `/* */` comments.
+fn skip_trivia(contents: &str, mut at: usize) -> usize {
+    let bytes = contents.as_bytes();
+    loop {
+        match (bytes.get(at), bytes.get(at + 1)) {
+            (Some(b), _) if b.is_ascii_whitespace() => at += 1,
+            (Some(b'/'), Some(b'/')) => {
+                at = contents[at..]
+                    .find('\n')
+                    .map_or(contents.len(), |end| at + end)
+            }
+            (Some(b'/'), Some(b'*')) => {
+                at = contents[at + 2..]
+                    .find("*/")
+                    .map_or(contents.len(), |end| at + 2 + end + 2)
+            }
+            _ => return at,
+        }
+    }
+}
+
+/// The end of the string opening at `at`, past its closing quote.
+fn string_end(bytes: &[u8], at: usize) -> Option<usize> {
+    let mut i = at + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// The string values of `contents`, in file order. Comments and trailing commas, as VS Code
+/// and Zed write them, are let through, and a document is read as far as its strings are
+/// complete, so whatever follows an unterminated one is left alone.
+fn values(contents: &str) -> Vec<Value> {
+    let bytes = contents.as_bytes();
+    let mut values = Vec::new();
+    // Whether each open container is an array, with the key it belongs to
+    let mut containers: Vec<(bool, Option<Range<usize>>)> = Vec::new();
+    let mut key = None;
+    let mut at = skip_trivia(contents, 0);
+    while at < bytes.len() {
+        match bytes[at] {
+            b'"' => {
+                let Some(end) = string_end(bytes, at) else {
+                    break;
+                };
+                let string = at + 1..end - 1;
+                at = skip_trivia(contents, end);
+                if bytes.get(at) == Some(&b':') {
+                    key = Some(string);
+                } else {
+                    let key = match containers.last() {
+                        Some((true, array_key)) => array_key.clone(),
+                        _ => key.clone(),
+                    };
+                    values.push(Value {
+                        contents: string,
+                        key,
+                    });
+                }
+                continue;
+            }
+            b'{' => containers.push((false, key.take())),
+            b'[' => containers.push((true, key.take())),
+            b'}' | b']' => {
+                containers.pop();
+                key = None;
+            }
+            b',' => key = None,
+            _ => {}
+        }
+        at = skip_trivia(contents, at + 1);
+    }
+    values
+}
+
+/// Whether `value` is a color and nothing else, as `#1e1e2e` or `#1e1e2e80`.
+fn is_color(value: &str) -> bool {
+    matches!(hex_tokens(value)[..], [token] if token.start == 0 && token.end == value.len())
+}
+
+fn color_values(contents: &str) -> Vec<Value> {
+    values(contents)
+        .into_iter()
+        .filter(|value| is_color(&contents[value.contents.clone()]))
+        .collect()
+}
+
+/// Whether `recolor_json` rewrites colors of `line`.
+pub fn owns_line(line: &str) -> bool {
+    !color_values(line).is_empty()
+}
+
+/// The colors `recolor_json` would rewrite with `options`, in file order.
+pub fn colors(contents: &str, options: &TextOptions) -> Vec<Color> {
+    color_values(contents)
+        .into_iter()
+        .flat_map(|value| options.tokens(&contents[value.contents]))
+        .map(|t| t.color)
+        .collect()
+}
+
+/// Rewrites the string values of a JSON or JSONC document that are a color, leaving keys,
+/// comments and every other byte as they were. Names in the report are the keys of the
+/// rewritten values.
+pub fn recolor_json(
+    contents: &str,
+    transform: &ColorTransform,
+    options: &TextOptions,
+) -> Result<(String, TextReport), TranError> {
+    let replacements = replacements(transform, &colors(contents, options))?;
+    let mut output = String::with_capacity(contents.len());
+    let mut report = TextReport::default();
+
+    let mut last = 0;
+    for value in color_values(contents) {
+        let (replaced, changes) =
+            replace_tokens(&contents[value.contents.clone()], &replacements, options);
+        if changes == 0 {
+            continue;
+        }
+        output.push_str(&contents[last..value.contents.start]);
+        output.push_str(&replaced);
+        last = value.contents.end;
+        report.changes += changes;
+        if let Some(key) = value.key {
+            let name = &contents[key];
+            if !report.names.iter().any(|n| n == name) {
+                report.names.push(name.to_string());
+            }
+        }
+    }
+    output.push_str(&contents[last..]);
+
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMap;
+
+    const SETTINGS: &str = r##"{
+  // "editor.background": "#2e3440",
+  "#2e3440": "key, not a value",
+  "workbench.colorCustomizations": {
+    "editor.background": "#2e3440", /* was "#2e3440" */
+    "terminal.ansi": ["#2e3440", "#2E3440cc",],
+    "title": "nord #2e3440",
+  },
+}
+"##;
+
+    #[test]
+    fn only_values_that_are_a_color_are_rewritten() {
+        let map = [ColorMap::new("#bf616a", "#2e3440")];
+        let (json, report) = recolor_json(
+            SETTINGS,
+            &ColorTransform::Map(&map),
+            &TextOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.changes, 3);
+        assert_eq!(report.names, ["editor.background", "terminal.ansi"]);
+        assert_eq!(
+            json,
+            SETTINGS
+                .replace(
+                    r##"    "editor.background": "#2e3440""##,
+                    r##"    "editor.background": "#bf616a""##
+                )
+                .replace(
+                    r##"["#2e3440", "#2E3440cc""##,
+                    r##"["#bf616a", "#BF616Acc""##
+                )
+        );
+    }
+
+    #[test]
+    fn unterminated_strings_end_the_document() {
+        let contents = "{\"a\": \"#111111\", \"b\": \"#222222}\n\"c\": \"#333333\"";
+        assert_eq!(
+            colors(contents, &TextOptions::default()),
+            [Color::from_bytes(0x11, 0x11, 0x11)]
+        );
+        assert!(owns_line("  \"fg\": \"#111111\","));
+        assert!(!owns_line("  \"fg\": \"#111111 bold\","));
+        assert!(!owns_line("// \"fg\": \"#111111\""));
+    }
+}
@@ -1,55 +1,141 @@
+#[cfg(feature = "fs")]
 use std::path::Path;
 
 use errors::TranError;
 
+#[cfg(feature = "fs")]
+pub mod cache;
+#[cfg(feature = "fs")]
+pub mod clean;
 pub mod config;
+pub mod constraints;
+pub mod css;
+pub mod cvd;
+pub mod debounce;
+#[cfg(feature = "fs")]
+pub mod discover;
 pub mod errors;
+pub mod exec_handler;
+pub mod export;
+pub mod extract;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod gtk;
+pub mod i3;
+pub mod ini;
+#[cfg(feature = "fs")]
+pub mod journal;
+pub mod json;
+pub mod jsonc;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "fs")]
+pub mod perms;
+pub mod pixel;
+#[cfg(feature = "fs")]
+pub mod plan;
+#[cfg(feature = "png")]
 pub mod png;
-
+pub mod prelude;
+pub mod rasi;
+pub mod recolor;
+#[cfg(feature = "fs")]
+pub mod run;
+#[cfg(feature = "fs")]
+pub mod scan;
+pub mod schedule;
+pub mod selection;
+pub mod space;
+#[cfg(feature = "fs")]
+pub mod state;
+#[cfg(feature = "fs")]
+pub mod stats;
+#[cfg(feature = "fs")]
+pub mod temp;
+#[cfg(feature = "fs")]
+pub mod term;
+#[cfg(all(test, feature = "fs"))]
+mod test_util;
+pub mod text;
+#[cfg(feature = "fs")]
+pub mod transition;
+#[cfg(feature = "fs")]
+pub mod variants;
+#[cfg(feature = "fs")]
+pub mod verify;
+#[cfg(feature = "png")]
+pub mod zlib;
+
+#[cfg(feature = "fs")]
+pub use plan::{plan, Plan};
+#[cfg(feature = "fs")]
+pub use recolor::recolor_path;
+pub use recolor::{
+    BackupPolicy, Cancellation, CollisionPolicy, FileFormat, ProgressEvent, ProgressLog,
+    RecolorOptions, RecolorReport,
+};
+#[cfg(feature = "fs")]
+pub use run::{run, RunOptions};
+
+/// A color as written in hex, as in `#2e3440`.
+pub type HexColor = str;
+
+#[deprecated(
+    since = "0.1.0",
+    note = "use `HexColor` for hex strings, `Color` is the parsed `config::Color` of the prelude"
+)]
 pub type Color = str;
 
 pub enum ColorTransform<'a, 'b> {
     Map(&'b [ColorMap<'a>]),
     Gradient {
-        primary: &'a Color,
-        background: &'a Color,
+        primary: &'a HexColor,
+        background: &'a HexColor,
+        // Color the previous run applied
+        current: &'a HexColor,
+        space: space::GradientSpace,
+        luminance: pixel::LuminanceBand,
+        /// Stops to ramp across instead of from black to `primary`.
+        stops: &'b [pixel::GradientStop],
     },
+    /// How the colors look with a color vision deficiency.
+    Simulate(cvd::ColorVisionDeficiency),
+    /// Each transform applied in turn within the one pass of a handler, each taking the colors
+    /// the ones before it left: a map after a gradient matches the shifted colors, not the ones
+    /// in the file.
+    Chain(&'b [ColorTransform<'a, 'b>]),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ColorMap<'a> {
-    new_color: &'a Color,
-    current_color: &'a Color,
+    new_color: &'a HexColor,
+    current_color: &'a HexColor,
+    /// The current and new colors, parsed once as handlers go through them per palette entry.
+    /// `None` when either isn't a hex color.
+    parsed: Option<(config::Color, config::Color)>,
 }
 
 impl<'a> ColorMap<'a> {
-    pub fn new(new_color: &'a Color, current_color: &'a Color) -> Self {
+    pub fn new(new_color: &'a HexColor, current_color: &'a HexColor) -> Self {
+        let parse = |hex: &str| config::Color::try_from_hex_str(hex).ok();
         ColorMap {
             new_color,
             current_color,
+            parsed: parse(current_color).zip(parse(new_color)),
         }
     }
-    fn new_color_bytes(&self) -> Result<(u8, u8, u8), TranError> {
-        let bytes: u32 = u32::from_str_radix(&self.new_color[1..], 16).map_err(|_| {
-            TranError::ConfigError(format!("Color hex {} is invalid", self.new_color))
-        })?;
-
-        let red: u8 = ((bytes & 0xFF0000) >> (2 * 8)) as u8;
-        let green: u8 = ((bytes & 0x00FF00) >> 8) as u8;
-        let blue: u8 = (bytes & 0x0000FF) as u8;
-
-        Ok((red, green, blue))
-    }
-    fn current_color_bytes(&self) -> Result<(u8, u8, u8), TranError> {
-        let bytes: u32 = u32::from_str_radix(&self.current_color[1..], 16).map_err(|_| {
-            TranError::ConfigError(format!("Color hex {} is invalid", self.current_color))
-        })?;
 
-        let red: u8 = ((bytes & 0xFF0000) >> (2 * 8)) as u8;
-        let green: u8 = ((bytes & 0x00FF00) >> 8) as u8;
-        let blue: u8 = (bytes & 0x0000FF) as u8;
-
-        Ok((red, green, blue))
+    /// The current and the new color, or why either can't be read.
+    pub fn colors(&self) -> Result<(config::Color, config::Color), TranError> {
+        match self.parsed {
+            Some(colors) => Ok(colors),
+            None => Ok((
+                config::Color::try_from_hex_str(self.current_color)?,
+                config::Color::try_from_hex_str(self.new_color)?,
+            )),
+        }
     }
 
     pub fn get_new_color(&self) -> &str {
@@ -59,34 +145,67 @@ impl<'a> ColorMap<'a> {
     pub fn get_current_color(&self) -> &str {
         self.current_color
     }
-}
 
-fn hex_to_bytes(hex: &str) -> Result<(u8, u8, u8), TranError> {
-    let bytes: u32 = u32::from_str_radix(&hex[1..], 16)
-        .map_err(|_| TranError::ConfigError(format!("Color hex {} is invalid", hex)))?;
+    /// Whether the color is taken to itself, as for a `-` slot of a map row.
+    pub fn keeps_color(&self) -> bool {
+        match self.parsed {
+            Some((current, new)) => current == new,
+            None => self.new_color.eq_ignore_ascii_case(self.current_color),
+        }
+    }
+}
 
-    let red: u8 = ((bytes & 0xFF0000) >> (2 * 8)) as u8;
-    let green: u8 = ((bytes & 0x00FF00) >> 8) as u8;
-    let blue: u8 = (bytes & 0x0000FF) as u8;
+/// Returns the recolored contents and the number of colors replaced.
+pub fn recolor_text(
+    contents: &str,
+    transform: &ColorTransform,
+) -> Result<(String, usize), TranError> {
+    recolor_text_in(contents, transform, &text::TextOptions::default())
+}
 
-    Ok((red, green, blue))
+/// Like `recolor_text`, taking and writing colors as `options` says.
+pub fn recolor_text_in(
+    contents: &str,
+    transform: &ColorTransform,
+    options: &text::TextOptions,
+) -> Result<(String, usize), TranError> {
+    let colors: Vec<_> = options.tokens(contents).iter().map(|t| t.color).collect();
+    Ok(text::replace_tokens(
+        contents,
+        &text::replacements(transform, &colors)?,
+        options,
+    ))
 }
 
+/// Returns the number of occurrences replaced.
+#[cfg(feature = "fs")]
 pub fn recolor_textfile<T: AsRef<Path>>(
     target: T,
-    new_color: &Color,
-    current_color: &Color,
-) -> Result<(), TranError> {
+    new_color: &HexColor,
+    current_color: &HexColor,
+) -> Result<usize, TranError> {
+    recolor_textfile_many(target, &[ColorMap::new(new_color, current_color)])
+}
+
+/// Like `recolor_textfile` for every color of `map` at once, so a color that was just
+/// replaced is never replaced again and two colors can trade places.
+#[cfg(feature = "fs")]
+pub fn recolor_textfile_many<T: AsRef<Path>>(
+    target: T,
+    map: &[ColorMap],
+) -> Result<usize, TranError> {
     if !target.as_ref().is_file() {
-        return Err(TranError::FileNotFoundError(
-            target.as_ref().to_string_lossy().to_string(),
-        ));
+        return Err(TranError::FileNotFoundError(target.as_ref().to_path_buf()));
     }
 
-    let file_contents = std::fs::read_to_string(&target)?;
-    let updated_file_contents = file_contents.replace(current_color, new_color);
+    let target = target.as_ref();
+    let file_contents = std::fs::read_to_string(target).map_err(|e| TranError::read(target, e))?;
+    let (updated_file_contents, changes) = recolor_text(&file_contents, &ColorTransform::Map(map))?;
+    if updated_file_contents == file_contents {
+        return Ok(changes);
+    }
 
-    std::fs::write(target, updated_file_contents)?;
+    std::fs::write(target, updated_file_contents).map_err(|e| TranError::write(target, e))?;
 
-    Ok(())
+    Ok(changes)
 }
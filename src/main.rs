@@ -1,162 +1,1594 @@
-use std::{fs, path::Path};
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
+use tran::prelude::*;
 use tran::{
-    config::{parse_config, write_config, Config},
-    errors::TranError,
-    png::recolor_png,
-    recolor_textfile, ColorMap, ColorTransform,
+    clean::generated_files,
+    config::{parse_config_with_local, write_config_to_string, HexCase, Target},
+    constraints,
+    cvd::ColorVisionDeficiency,
+    debounce::{Debouncer, Fired},
+    discover::{discover, DiscoverOptions},
+    export::{export, export_shell, preview_html, ExportFormat, Shell, DEFAULT_SHELL_PREFIX},
+    extract,
+    ini::IniOptions,
+    journal,
+    json::Json,
+    perms,
+    recolor::{Position, ProgressEvent, ProgressLog},
+    run, scan,
+    schedule::{Schedule, TimeZone},
+    selection::{Selection, SystemRng},
+    state::{format_timestamp, State},
+    stats,
+    term::{self, with_swatch},
+    text::TextOptions,
+    variants::{self, VariantOptions},
+    verify::{self, TargetState},
 };
 
-fn get_config_path() -> Result<String, TranError> {
-    let mut config_home = if let Ok(config_home) = std::env::var("XDG_CONFIG_HOME") {
-        config_home
-    } else if let Ok(home) = std::env::var("HOME") {
-        format!("{}/.config", home)
+fn get_config_path() -> Result<PathBuf, TranError> {
+    let config_home = if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(config_home)
+    } else if let Some(home) = std::env::var_os("HOME") {
+        Path::new(&home).join(".config")
+    } else if let Some(app_data) = std::env::var_os("APPDATA").filter(|_| cfg!(windows)) {
+        PathBuf::from(app_data)
     } else {
         return Err(TranError::ConfigError(
             "Could not determine config directory".to_string(),
         ));
     };
 
-    config_home.push_str("/tran/config");
-    Ok(config_home)
+    Ok(config_home.join("tran").join("config"))
 }
 
-fn main() -> Result<(), TranError> {
-    let config_path = get_config_path()?;
-    let config_path = std::path::Path::new(&config_path);
+/// A command line argument as a string, for those that aren't paths.
+fn utf8(arg: &OsStr) -> Result<&str, TranError> {
+    arg.to_str().ok_or_else(|| {
+        TranError::UnsupportedError(format!(
+            "The argument '{}' is not valid utf-8",
+            arg.to_string_lossy()
+        ))
+    })
+}
 
-    if !config_path.is_file() {
-        fs::write(config_path, "")?;
-        eprintln!("Created empty config file, please fill it out");
-        return Ok(());
+/// The arguments of a subcommand that takes no paths, as strings.
+fn utf8_args(args: &[OsString]) -> Result<Vec<String>, TranError> {
+    args.iter()
+        .map(|arg| utf8(arg).map(str::to_string))
+        .collect()
+}
+
+fn is_flag(arg: &OsStr) -> bool {
+    arg.as_encoded_bytes().starts_with(b"-")
+}
+
+fn main() -> ExitCode {
+    // Paths stay `OsString`, as an old file name needn't be utf-8
+    let args: Vec<OsString> = std::env::args_os()
+        .skip(1)
+        // Taken out wherever it is, every subcommand prints colors
+        .filter(|arg| match arg == "--no-color" {
+            true => {
+                term::disable();
+                false
+            }
+            false => true,
+        })
+        .collect();
+
+    let rest = &args[args.len().min(1)..];
+    let strings = || utf8_args(rest);
+    let result = match args.first().map(|arg| arg.to_str()) {
+        Some(Some("history")) => strings().and_then(|args| history(&args)).map(|_| ExitCode::SUCCESS),
+        Some(Some("export")) => export_scheme(rest).map(|_| ExitCode::SUCCESS),
+        Some(Some("undo")) => strings().and_then(|args| undo(&args)).map(|_| ExitCode::SUCCESS),
+        Some(Some("scan")) => scan_colors(rest),
+        Some(Some("variants")) => generate_variants(rest),
+        Some(Some("clean")) => strings().and_then(|args| clean(&args)),
+        Some(Some("simulate")) => simulate(rest),
+        Some(Some("from-image")) => from_image(rest).map(|_| ExitCode::SUCCESS),
+        Some(Some("preview")) => preview(rest).map(|_| ExitCode::SUCCESS),
+        Some(Some("verify")) => strings().and_then(|args| verify_targets(&args)),
+        Some(Some("discover")) => discover_targets(rest).map(|_| ExitCode::SUCCESS),
+        Some(Some("stats")) => strings().and_then(|args| color_stats(&args)),
+        Some(Some("swap")) => strings().and_then(|args| swap(&args)),
+        Some(Some("filter")) => strings().and_then(|args| filter(&args)).map(|_| ExitCode::SUCCESS),
+        Some(Some("daemon")) => strings().and_then(|args| daemon(&args)).map(|_| ExitCode::SUCCESS),
+        Some(Some("suggest")) => strings().and_then(|args| suggest(&args)).map(|_| ExitCode::SUCCESS),
+        Some(Some("check")) => strings().and_then(|args| check(&args)),
+        Some(Some("target")) => strings().and_then(|args| target(&args)),
+        Some(Some("png-check")) => png_check(rest),
+        Some(Some("adopt")) => adopt(rest),
+        Some(Some(flag)) if !flag.starts_with('-') => Err(TranError::UnsupportedError(format!(
+            "Unknown subcommand '{}', valid subcommands are 'history', 'export', 'undo', 'scan', 'variants', 'clean', 'simulate', 'from-image', 'preview', 'verify', 'discover', 'stats', 'swap', 'filter', 'daemon', 'suggest', 'check', 'target', 'png-check' and 'adopt'",
+            flag
+        ))),
+        _ => recolor(&args),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn history(args: &[String]) -> Result<(), TranError> {
+    let count = match args {
+        [] => 10,
+        [flag, n] if flag == "-n" => n.parse()?,
+        [flag, id] if flag == "--detail" => return history_detail(id.parse()?),
+        _ => {
+            return Err(TranError::UnsupportedError(
+                "Usage: tran history [-n <count> | --detail <run-id>]".to_string(),
+            ))
+        }
+    };
+
+    let state = State::open()?;
+    for run in state.recent_runs(count)? {
+        let failed = run.files.iter().filter(|f| f.error.is_some()).count();
+        println!(
+            "{} {} {} -> {} ({} ok, {} failed, run {})",
+            format_timestamp(run.timestamp),
+            run.mode,
+            run.old_colors.iter().map(with_swatch).collect::<String>(),
+            run.new_colors.iter().map(with_swatch).collect::<String>(),
+            run.files.len() - failed,
+            failed,
+            run.id
+        );
+        for file in run.files.iter() {
+            if let Some(error) = &file.error {
+                println!("    {}: {}", file.path, error);
+            }
+        }
     }
 
-    let mut config = parse_config(config_path)?;
+    Ok(())
+}
+
+/// Lists every replacement the run `id` made, as logged with `[journal]`.
+fn history_detail(id: u64) -> Result<(), TranError> {
+    let replacements = State::open()?.replacements(id)?;
+    if replacements.is_empty() {
+        println!("No replacements logged for run {}", id);
+    }
+    for replacement in replacements {
+        let at = match replacement.position {
+            Position::Text {
+                offset,
+                line,
+                column,
+            } => format!(
+                "{}:{}:{} (byte {})",
+                replacement.file.display(),
+                line,
+                column,
+                offset
+            ),
+            Position::Palette { index, offset } => format!(
+                "{} palette entry {} (byte {})",
+                replacement.file.display(),
+                index,
+                offset
+            ),
+        };
+        println!("  {} {} -> {}", at, replacement.old, replacement.new);
+    }
+    Ok(())
+}
+
+fn undo(args: &[String]) -> Result<(), TranError> {
+    let force = match args {
+        [] => false,
+        [flag] if flag == "--force" => true,
+        _ => {
+            return Err(TranError::UnsupportedError(
+                "Usage: tran undo [--force]".to_string(),
+            ))
+        }
+    };
+
+    let entry = journal::undo(&State::open()?, force)?;
+    println!(
+        "Restored {} and {} file(s)",
+        entry
+            .previous
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<String>(),
+        entry.files.len()
+    );
+    Ok(())
+}
+
+fn export_scheme(args: &[OsString]) -> Result<(), TranError> {
+    const USAGE: &str =
+        "Usage: tran export [--format css|json|sh|sequences] [--fish] [--prefix <name>] [--out <path>]";
+
+    let mut format = ExportFormat::Css;
+    let mut out = None;
+    let mut shell = Shell::Posix;
+    let mut prefix = DEFAULT_SHELL_PREFIX;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match utf8(arg)? {
+            "--fish" => shell = Shell::Fish,
+            flag => match (flag, args.next()) {
+                ("--format", Some(value)) => format = ExportFormat::try_from(utf8(value)?)?,
+                ("--out", Some(value)) => out = Some(Path::new(value)),
+                ("--prefix", Some(value)) => prefix = utf8(value)?,
+                _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+        }
+    }
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let exported = match format {
+        ExportFormat::Sh => export_shell(&config, prefix, shell)?,
+        format => export(&config, format),
+    };
+    match out {
+        Some(path) => {
+            perms::write(Path::new(path), exported).map_err(|e| TranError::write(path, e))?
+        }
+        None => print!("{}", exported),
+    }
+
+    Ok(())
+}
+
+fn filter(args: &[String]) -> Result<(), TranError> {
+    const USAGE: &str = "Usage: tran filter [--from <color> --to <color>]... [--use-config [--seed <n>]] [--format text|png|gtk|i3|css|json|rasi|ini] [--png] [--literals] [--hex-case lower|upper]";
 
-    let t = std::time::SystemTime::now();
-    match &mut config {
-        Config::GradientConfig(gc) => {
-            let colors = gc.get_colors_scaled();
-            let new_color = *colors
-                .get(
-                    t.duration_since(std::time::UNIX_EPOCH)
-                        .expect("System time is before start of unix epoch")
-                        .as_secs() as usize
-                        % colors.len(),
+    let usage = || TranError::UnsupportedError(USAGE.to_string());
+    let mut from = Vec::new();
+    let mut to = Vec::new();
+    let mut use_config = false;
+    let mut seed = None;
+    let mut format = None;
+    let mut literals = false;
+    let mut case: Option<HexCase> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => from.push(Color::try_from_hex_str(args.next().ok_or_else(usage)?)?),
+            "--to" => to.push(Color::try_from_hex_str(args.next().ok_or_else(usage)?)?),
+            "--use-config" => use_config = true,
+            "--seed" => seed = Some(args.next().ok_or_else(usage)?.parse()?),
+            "--format" => format = Some(args.next().ok_or_else(usage)?.as_str().try_into()?),
+            "--png" => format = Some(FileFormat::Png),
+            "--literals" => literals = true,
+            "--hex-case" => case = Some(args.next().ok_or_else(usage)?.as_str().try_into()?),
+            _ => {
+                return Err(TranError::UnsupportedError(format!(
+                    "Unknown option '{}'\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+    }
+    // Either mappings or the config, never both
+    if from.len() != to.len() || use_config != from.is_empty() {
+        return Err(usage());
+    }
+
+    let mut input = Vec::new();
+    std::io::stdin()
+        .lock()
+        .read_to_end(&mut input)
+        .map_err(|e| TranError::FileReadError {
+            path: None,
+            source: e,
+        })?;
+    let format = format.unwrap_or_else(|| FileFormat::detect(Path::new(""), &input));
+
+    let (output, changes) = if use_config {
+        // The colors a run would pick next, without the run moving the config on to them
+        let config = parse_config_with_local(get_config_path()?)?;
+        let mut rng = match seed {
+            Some(seed) => SystemRng::from_seed(seed),
+            None => SystemRng::new(),
+        };
+        let selection = config.select_next(&mut rng)?;
+        eprintln!(
+            "{} -> {}",
+            Selection::Row(config.get_current_colors()),
+            selection
+        );
+        run::with_transform(
+            &selection,
+            &config.get_current_colors(),
+            config.get_gradient_space(),
+            config.get_luminance_band(),
+            config.get_stops(),
+            |transform| {
+                let text = TextOptions {
+                    hex_case: case.unwrap_or(config.get_hex_case()),
+                    ..config.get_text_options().clone()
+                };
+                recolor_bytes(
+                    &input,
+                    format,
+                    transform,
+                    literals,
+                    &text,
+                    config.get_ini_options(),
                 )
-                .unwrap();
-
-            let color_string = new_color.to_string();
-            let old_color_string = gc.get_current_color().to_string();
-            let trans = ColorTransform::Gradient {
-                primary: &color_string,
-                background: "#000000",
-            };
-            for target_file in gc.get_target_files() {
-                let path = Path::new(&target_file);
-                if !path.is_file() {
-                    eprintln!("File {} could not be found", target_file);
-                    continue;
-                }
+            },
+        )?
+    } else {
+        let from: Vec<String> = from.iter().map(Color::to_string).collect();
+        let to: Vec<String> = to.iter().map(Color::to_string).collect();
+        let map: Vec<ColorMap> = to
+            .iter()
+            .zip(&from)
+            .map(|(to, from)| ColorMap::new(to, from))
+            .collect();
+        let text = TextOptions {
+            hex_case: case.unwrap_or_default(),
+            ..Default::default()
+        };
+        recolor_bytes(
+            &input,
+            format,
+            &ColorTransform::Map(&map),
+            literals,
+            &text,
+            &IniOptions::default(),
+        )?
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&output)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| TranError::FileWriteError {
+            path: None,
+            source: e,
+        })?;
+    eprintln!("{} changes", changes);
+    Ok(())
+}
+
+fn swap(args: &[String]) -> Result<ExitCode, TranError> {
+    let (a, b) = match args {
+        [a, b] => (Color::try_from_hex_str(a)?, Color::try_from_hex_str(b)?),
+        _ => {
+            return Err(TranError::UnsupportedError(
+                "Usage: tran swap <color> <color>".to_string(),
+            ))
+        }
+    };
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let options = run::recolor_options(&config, 0);
+    // Both directions go into one map, which every handler applies in a single pass
+    let summary = run::apply_map(
+        &config,
+        config.get_targets(),
+        &[a, b],
+        &Selection::Row(vec![b, a]),
+        &options,
+    );
+    for target in summary.succeeded.iter() {
+        println!("{} ({} changes)", target.output.display(), target.changes);
+    }
+    for (_, e) in summary.failed.iter() {
+        eprintln!("{}", e);
+    }
+
+    Ok(ExitCode::from(summary.exit_code()))
+}
+
+fn check(args: &[String]) -> Result<ExitCode, TranError> {
+    let json = match args {
+        [] => false,
+        [flag] if flag == "--json" => true,
+        _ => {
+            return Err(TranError::UnsupportedError(
+                "Usage: tran check [--json]".to_string(),
+            ))
+        }
+    };
 
-                if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
-                    if ext == "png" {
-                        match gc.get_overwrite() {
-                            true => recolor_png(path, path, &trans)?,
-                            false => recolor_png(
-                                path,
-                                path.with_file_name(format!(
-                                    "{}_{}",
-                                    path.file_stem()
-                                        .and_then(|p| p.to_str())
-                                        .expect("Non utf-8 file name"),
-                                    &new_color.to_string(),
-                                ))
-                                .with_extension("png"),
-                                &trans,
-                            )?,
+    let config = parse_config_with_local(get_config_path()?)?;
+    let results = constraints::check(&config);
+    if json {
+        let selections: Vec<_> = results
+            .iter()
+            .map(|(selection, violations)| {
+                Json::object()
+                    .with(
+                        "colors",
+                        selection
+                            .colors()
+                            .iter()
+                            .map(|c| c.to_string())
+                            .collect::<Vec<_>>(),
+                    )
+                    .with(
+                        "violations",
+                        violations.iter().map(|v| v.to_json()).collect::<Vec<_>>(),
+                    )
+            })
+            .collect();
+        println!("{}", Json::object().with("selections", selections));
+    } else {
+        if config.get_constraints().is_none() {
+            println!("No [constraints] to check");
+        }
+        for (selection, violations) in results.iter() {
+            match violations.is_empty() {
+                true => println!("ok     {}", selection),
+                false => println!("breaks {}", selection),
+            }
+            for violation in violations {
+                println!("       {}", violation);
+            }
+        }
+    }
+
+    Ok(
+        match results.iter().any(|(_, violations)| !violations.is_empty()) {
+            true => ExitCode::FAILURE,
+            false => ExitCode::SUCCESS,
+        },
+    )
+}
+
+/// Walks the chunks of each png, printing what is wrong with it, and with `--verbose` every
+/// chunk. Fails when any file has a violation.
+fn png_check(args: &[OsString]) -> Result<ExitCode, TranError> {
+    const USAGE: &str = "Usage: tran png-check [--verbose] <file>...";
+
+    let verbose = args.iter().any(|arg| arg == "--verbose");
+    let files: Vec<&OsString> = args.iter().filter(|arg| *arg != "--verbose").collect();
+    if files.is_empty() || files.iter().any(|file| is_flag(file)) {
+        return Err(TranError::UnsupportedError(USAGE.to_string()));
+    }
+
+    #[cfg(not(feature = "png"))]
+    {
+        let _ = (files, verbose);
+        Err(TranError::without_png())
+    }
+    #[cfg(feature = "png")]
+    {
+        let mut code = ExitCode::SUCCESS;
+        for file in files {
+            let path = Path::new(file);
+            let data = fs::read(path).map_err(|e| TranError::read(path, e))?;
+            let check = tran::png::check::check_png(&data);
+            match check.is_ok() {
+                true => println!("ok     {}", path.display()),
+                false => {
+                    println!("broken {}", path.display());
+                    code = ExitCode::FAILURE;
+                }
+            }
+            if verbose {
+                for chunk in check.chunks.iter() {
+                    println!(
+                        "       {} at {}, {} bytes, crc {}",
+                        chunk.name(),
+                        chunk.offset,
+                        chunk.data.len(),
+                        match chunk.crc_ok() {
+                            true => "ok",
+                            false => "wrong",
                         }
-                        continue;
-                    }
+                    );
                 }
+            }
+            for violation in check.violations.iter() {
+                println!("       {}", violation);
+            }
+        }
+        Ok(code)
+    }
+}
 
-                if let Err(e) = recolor_textfile(path, &color_string, &old_color_string) {
-                    eprintln!("Error recoloring {}: {}", target_file, e);
+/// Lists the targets of the config, marking the stale ones, and with `--prune-stale` removes
+/// those from `[target_files]`.
+fn target(args: &[String]) -> Result<ExitCode, TranError> {
+    const USAGE: &str =
+        "Usage: tran target [--prune-stale [--yes]] | tran target add <path>... [--adopt]";
+
+    if args.first().is_some_and(|arg| arg == "add") {
+        return target_add(&args[1..], USAGE);
+    }
+
+    let mut prune = false;
+    let mut yes = false;
+    for arg in args {
+        match arg.as_str() {
+            "--prune-stale" => prune = true,
+            "--yes" => yes = true,
+            _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+        }
+    }
+    if yes && !prune {
+        return Err(TranError::UnsupportedError(USAGE.to_string()));
+    }
+
+    // Pruned entries are written back, so the overlay is left out
+    let config_path = get_config_path()?;
+    let mut config = parse_config(&config_path)?;
+    let options = run::recolor_options(&config, 0);
+    let stale: Vec<Target> = config
+        .get_targets()
+        .iter()
+        .filter(|target| run::is_stale(&config, target, &options))
+        .cloned()
+        .collect();
+    for target in config.get_targets() {
+        match stale.contains(target) {
+            true => println!("stale      {}", target.path.display()),
+            false => println!("           {}", target.path.display()),
+        }
+    }
+    if !prune || stale.is_empty() {
+        return Ok(ExitCode::SUCCESS);
+    }
+    if !yes {
+        print!("Remove {} stale target(s)? [y/N] ", stale.len());
+        std::io::Write::flush(&mut std::io::stdout()).map_err(|e| TranError::write("stdout", e))?;
+        let mut answer = String::new();
+        std::io::stdin()
+            .read_line(&mut answer)
+            .map_err(|e| TranError::read("stdin", e))?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+    config.retain_targets(|target| !stale.contains(target));
+    write_config(config, &config_path)?;
+    eprintln!("Removed {} target(s)", stale.len());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn color_stats(args: &[String]) -> Result<ExitCode, TranError> {
+    let json = match args {
+        [] => false,
+        [flag, format] if flag == "--format" && format == "text" => false,
+        [flag, format] if flag == "--format" && format == "json" => true,
+        _ => {
+            return Err(TranError::UnsupportedError(
+                "Usage: tran stats [--format text|json]".to_string(),
+            ))
+        }
+    };
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let report = stats::stats(&config, &run::recolor_options(&config, 0));
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        // One column per configured color, then the total of the row and the file it is for
+        let mut header = String::new();
+        for color in report.colors.iter() {
+            header.push_str(&format!("{:>7} ", color.to_string()));
+        }
+        println!("{}{:>7}", header, "total");
+        for ((path, counts), total) in report.files.iter().zip(report.file_totals()) {
+            let mut row = String::new();
+            for count in counts {
+                row.push_str(&format!("{:>7} ", count));
+            }
+            println!("{}{:>7}  {}", row, total, path.display());
+        }
+        let mut totals = String::new();
+        for total in report.color_totals() {
+            totals.push_str(&format!("{:>7} ", total));
+        }
+        println!("{}{:>7}  total", totals, "");
+
+        let unused = report.unused();
+        if !unused.is_empty() {
+            println!();
+            println!("Unused, candidates for removal:");
+            for color in unused {
+                println!("  {}", color);
+            }
+        }
+        if !report.unconfigured.is_empty() {
+            println!();
+            println!("Found but not configured:");
+            for color in report.unconfigured.iter() {
+                println!("  {} {}", color.color, color.count);
+            }
+        }
+        for (_, e) in report.failed.iter() {
+            eprintln!("{}", e);
+        }
+    }
+
+    Ok(if report.failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn discover_targets(args: &[OsString]) -> Result<(), TranError> {
+    const USAGE: &str =
+        "Usage: tran discover <dir> [--add] [--exclude <glob>]... [--max-size <bytes>] [--hidden] [--respect-gitignore] [--backups]";
+
+    let mut dir = None;
+    let mut add = false;
+    let mut hidden = false;
+    let mut respect_gitignore = false;
+    let mut backups = false;
+    let mut options = DiscoverOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--add") => add = true,
+            Some("--exclude") => match args.next() {
+                Some(glob) => options.exclude.push(utf8(glob)?.to_string()),
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            Some("--max-size") => match args.next() {
+                Some(size) => options.max_size = utf8(size)?.parse()?,
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            Some("--hidden") => hidden = true,
+            Some("--respect-gitignore") => respect_gitignore = true,
+            Some("--backups") => backups = true,
+            _ if dir.is_none() && !is_flag(arg) => dir = Some(arg),
+            _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+        }
+    }
+    let dir = dir.ok_or_else(|| TranError::UnsupportedError(USAGE.to_string()))?;
+
+    let config_path = get_config_path()?;
+    let mut config = parse_config(&config_path)?;
+    options.traversal = config.get_traversal();
+    options.traversal.hidden |= hidden;
+    options.traversal.respect_gitignore |= respect_gitignore;
+    options.traversal.backups |= backups;
+    let found = discover(&config, Path::new(dir), &options)?;
+    for path in found.iter() {
+        println!("{}", path.display());
+    }
+    if add && !found.is_empty() {
+        for path in found {
+            config.push_target(Target::new(path));
+        }
+        write_config(config, &config_path)?;
+    }
+
+    Ok(())
+}
+
+fn verify_targets(args: &[String]) -> Result<ExitCode, TranError> {
+    let mut repair = false;
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            "--json" => json = true,
+            _ => {
+                return Err(TranError::UnsupportedError(
+                    "Usage: tran verify [--repair] [--json]".to_string(),
+                ))
+            }
+        }
+    }
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let options = run::recolor_options(&config, 0);
+    let mut report = verify::verify(&config, &options);
+    if repair {
+        verify::repair(&config, &options, &mut report);
+    }
+
+    print_verify_report(&report, json);
+    Ok(verify_exit_code(&report))
+}
+
+/// Takes the given targets, every one without paths, from the configured colors they hold to
+/// the current ones.
+fn adopt(args: &[OsString]) -> Result<ExitCode, TranError> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths: Vec<PathBuf> = args
+        .iter()
+        .filter(|arg| *arg != "--json")
+        .map(PathBuf::from)
+        .collect();
+    if paths.iter().any(|path| is_flag(path.as_os_str())) {
+        return Err(TranError::UnsupportedError(
+            "Usage: tran adopt [--json] [<path>...]".to_string(),
+        ));
+    }
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let report = verify::adopt(&config, &run::recolor_options(&config, 0), &paths);
+    print_verify_report(&report, json);
+    Ok(verify_exit_code(&report))
+}
+
+fn print_verify_report(report: &verify::VerifyReport, json: bool) {
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        for (path, state) in report.targets.iter() {
+            match state {
+                TargetState::Consistent => println!("consistent {}", path.display()),
+                TargetState::Unknown => println!("unknown    {}", path.display()),
+                TargetState::Indirect => println!("indirect   {}", path.display()),
+                TargetState::Stale(found) if report.repaired.contains(path) => {
+                    println!("repaired   {} (was {})", path.display(), found)
                 }
+                TargetState::Stale(found) => println!("stale      {} ({})", path.display(), found),
+            }
+            for usage in report.uses(path).unwrap_or_default() {
+                println!("           uses {}", usage);
             }
-            gc.set_current_colors(new_color);
         }
-        Config::MapConfig(mc) => {
-            let colors = mc.get_colors_scaled();
-            let new_color = colors
-                .get(
-                    t.duration_since(std::time::UNIX_EPOCH)
-                        .expect("System time is before start of unix epoch")
-                        .as_secs() as usize
-                        % colors.len(),
-                )
-                .unwrap()
-                .to_owned();
-
-            let current_color = mc.get_current_colors();
-
-            let store: Vec<(String, String)> = new_color
-                .iter()
-                .zip(current_color)
-                .map(|(new, current)| (new.to_string(), current.to_string()))
-                .collect();
-
-            let map: Vec<ColorMap> = store
-                .iter()
-                .map(|(new, current)| ColorMap::new(new, current))
-                .collect();
-            let trans = ColorTransform::Map(&map);
-
-            for target_file in mc.get_target_files() {
-                let path = Path::new(&target_file);
-                if !path.is_file() {
-                    eprintln!("File {} could not be found", target_file);
-                    continue;
+        for (_, e) in report.failed.iter() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Adds targets to `[target_files]`, each written as it would be there, and with `--adopt`
+/// brings them in line with the current colors.
+fn target_add(args: &[String], usage: &str) -> Result<ExitCode, TranError> {
+    let adopt = args.iter().any(|arg| arg == "--adopt");
+    let lines: Vec<&String> = args.iter().filter(|arg| *arg != "--adopt").collect();
+    if lines.is_empty() || lines.iter().any(|line| line.starts_with('-')) {
+        return Err(TranError::UnsupportedError(usage.to_string()));
+    }
+
+    let config_path = get_config_path()?;
+    let mut config = parse_config(&config_path)?;
+    let mut paths = Vec::new();
+    for line in lines {
+        let target = Target::parse(line)?;
+        paths.push(target.path.clone());
+        if !config.get_targets().iter().any(|t| t.path == target.path) {
+            config.push_target(target);
+        }
+    }
+    write_config(config, &config_path)?;
+    if !adopt {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let config = parse_config_with_local(&config_path)?;
+    let report = verify::adopt(&config, &run::recolor_options(&config, 0), &paths);
+    print_verify_report(&report, false);
+    Ok(verify_exit_code(&report))
+}
+
+/// Fails with stale targets left or targets that couldn't be checked.
+fn verify_exit_code(report: &verify::VerifyReport) -> ExitCode {
+    let unrepaired = report
+        .stale()
+        .any(|(path, _)| !report.repaired.contains(path));
+    if unrepaired || !report.failed.is_empty() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn preview(args: &[OsString]) -> Result<(), TranError> {
+    let out = match args {
+        [flag, out] if flag == "--html" => Path::new(out),
+        _ => {
+            return Err(TranError::UnsupportedError(
+                "Usage: tran preview --html <out.html>".to_string(),
+            ))
+        }
+    };
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    perms::write(out, preview_html(&config)).map_err(|e| TranError::write(out, e))?;
+    println!("Wrote {}", out.display());
+    Ok(())
+}
+
+fn suggest(args: &[String]) -> Result<(), TranError> {
+    const USAGE: &str =
+        "Usage: tran suggest <hex> [--analogous <count>] [--spread <degrees>] [--add]";
+
+    let mut color = None;
+    let mut count = 2;
+    let mut spread = 30.0;
+    let mut add = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--analogous" => match args.next() {
+                Some(n) => count = n.parse()?,
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            "--spread" => match args.next().and_then(|s| s.parse::<f64>().ok()) {
+                Some(degrees) if degrees.is_finite() => spread = degrees,
+                _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            "--add" => add = true,
+            _ if color.is_none() && !arg.starts_with("--") => {
+                color = Some(Color::try_from_hex_str(arg)?)
+            }
+            _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+        }
+    }
+    let color = color.ok_or_else(|| TranError::UnsupportedError(USAGE.to_string()))?;
+
+    let mut suggestions = vec![("complementary", color.complementary())];
+    suggestions.extend(
+        color
+            .analogous(count, spread)
+            .into_iter()
+            .map(|c| ("analogous", c)),
+    );
+    suggestions.extend(color.triadic().into_iter().map(|c| ("triadic", c)));
+    println!("{} input", with_swatch(&color));
+    for (kind, suggestion) in suggestions.iter() {
+        println!("{} {}", with_swatch(suggestion), kind);
+    }
+    if !add {
+        return Ok(());
+    }
+
+    let config_path = get_config_path()?;
+    let colors: Vec<Color> = suggestions.iter().map(|(_, c)| *c).collect();
+    match parse_config(&config_path)? {
+        Config::GradientConfig(mut config) => {
+            for suggestion in colors {
+                if !config.get_colors().contains(&suggestion) {
+                    config.push_color(suggestion);
                 }
+            }
+            write_config(Config::GradientConfig(config), &config_path)?;
+        }
+        Config::MapConfig(mut config) => {
+            if let Some(existing) = config
+                .get_rows()
+                .first()
+                .filter(|r| r.len() != colors.len())
+            {
+                return Err(TranError::UnsupportedError(format!(
+                    "Rows in [colors] have {} colors, suggest makes a row of {}",
+                    existing.len(),
+                    colors.len()
+                )));
+            }
+            config.push_colors(colors);
+            write_config(Config::MapConfig(config), &config_path)?;
+        }
+    }
+    eprintln!("Added the suggestions to {}", config_path.display());
 
-                if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
-                    if ext == "png" {
-                        match mc.get_overwrite() {
-                            true => recolor_png(path, path, &trans)?,
-                            false => recolor_png(
-                                path,
-                                path.with_file_name(format!(
-                                    "{}_{}",
-                                    path.file_stem()
-                                        .and_then(|p| p.to_str())
-                                        .expect("Non utf-8 file name"),
-                                    &new_color
-                                        .get(1)
-                                        .expect("No new color selectable")
-                                        .to_string(),
-                                ))
-                                .with_extension("png"),
-                                &trans,
-                            )?,
-                        }
-                        continue;
-                    }
+    Ok(())
+}
+
+fn from_image(args: &[OsString]) -> Result<(), TranError> {
+    const USAGE: &str = "Usage: tran from-image <path> [--dry-run]";
+
+    let mut path = None;
+    let mut dry_run = false;
+    for arg in args {
+        match arg.to_str() {
+            Some("--dry-run") => dry_run = true,
+            _ if path.is_none() && !is_flag(arg) => path = Some(Path::new(arg)),
+            _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+        }
+    }
+    let path = path.ok_or_else(|| TranError::UnsupportedError(USAGE.to_string()))?;
+
+    let data = fs::read(path).map_err(|e| TranError::read(path, e))?;
+    let row = extract::from_png(&data).map_err(|e| e.in_file(path))?;
+    let line: String = row.iter().map(|c| c.to_string()).collect();
+    if dry_run {
+        println!("{}", line);
+        return Ok(());
+    }
+
+    let config_path = get_config_path()?;
+    let Config::MapConfig(mut config) = parse_config(&config_path)? else {
+        return Err(TranError::UnsupportedError(
+            "from-image adds a map row, the config has to be in map mode".to_string(),
+        ));
+    };
+    if let Some(existing) = config.get_rows().first().filter(|r| r.len() != row.len()) {
+        return Err(TranError::UnsupportedError(format!(
+            "Rows in [colors] have {} colors, from-image makes rows of {}",
+            existing.len(),
+            row.len()
+        )));
+    }
+    config.push_colors(row);
+    write_config(Config::MapConfig(config), &config_path)?;
+    println!("{}", line);
+
+    Ok(())
+}
+
+fn scan_colors(args: &[OsString]) -> Result<ExitCode, TranError> {
+    const USAGE: &str = "Usage: tran scan [--format text|json] [--suggest-map <count>] [paths...]";
+
+    let mut json = false;
+    let mut suggest = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--format") => match args.next().and_then(|arg| arg.to_str()) {
+                Some("text") => json = false,
+                Some("json") => json = true,
+                _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            Some("--suggest-map") => match args.next() {
+                Some(count) => suggest = Some(utf8(count)?.parse()?),
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            _ if is_flag(arg) => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            Some(path) => paths.push(Target::parse(path)?),
+            // Hints are only read from utf-8 paths
+            None => paths.push(Target::new(arg)),
+        }
+    }
+    // Paths given on the command line are scanned with or without a config
+    let config_path = get_config_path()?;
+    let config = match paths.is_empty() || config_path.exists() {
+        true => Some(parse_config_with_local(&config_path)?),
+        false => None,
+    };
+    if paths.is_empty() {
+        paths = config
+            .iter()
+            .flat_map(|c| c.get_targets())
+            .cloned()
+            .collect();
+    }
+
+    let options = match &config {
+        Some(config) => run::recolor_options(config, 0),
+        None => RecolorOptions::default(),
+    };
+    let report = scan::scan(&paths, &options);
+    if let Some(count) = suggest {
+        print!("{}", report.suggest_map(count));
+    } else if json {
+        println!("{}", report.to_json());
+    } else {
+        for found in report.colors.iter() {
+            println!(
+                "{} {:>5} {}",
+                with_swatch(&found.color),
+                found.count,
+                found
+                    .files
+                    .iter()
+                    .map(|f| f.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        for (path, uses) in report.indirect.iter() {
+            for usage in uses {
+                println!("{} uses {}", path.display(), usage);
+            }
+        }
+    }
+    if !json {
+        for (_, e) in report.failed.iter() {
+            eprintln!("{}", e);
+        }
+    }
+
+    Ok(if report.failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn generate_variants(args: &[OsString]) -> Result<ExitCode, TranError> {
+    const USAGE: &str =
+        "Usage: tran variants --out-dir <dir> [--name <template>] [--force] [--json]";
+
+    let mut out_dir = None;
+    let mut name = None;
+    let mut force = false;
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match utf8(arg)? {
+            "--out-dir" => out_dir = args.next(),
+            "--name" => name = args.next().map(|name| utf8(name)).transpose()?,
+            "--force" => force = true,
+            "--json" => json = true,
+            _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+        }
+    }
+    let mut options =
+        VariantOptions::new(out_dir.ok_or_else(|| TranError::UnsupportedError(USAGE.to_string()))?);
+    if let Some(name) = name {
+        options.name = name.to_string();
+    }
+    options.force = force;
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let report = variants::generate(&config, &run::recolor_options(&config, 0), &options)?;
+    print_variants(&report, json);
+
+    Ok(if report.failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn simulate(args: &[OsString]) -> Result<ExitCode, TranError> {
+    const USAGE: &str = "Usage: tran simulate --type <protan|deutan|tritan> --out-dir <dir> [--name <template>] [--force] [--json]";
+
+    let mut deficiency = None;
+    let mut out_dir = None;
+    let mut name = None;
+    let mut force = false;
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match utf8(arg)? {
+            "--type" => deficiency = args.next().map(|name| utf8(name)).transpose()?,
+            "--out-dir" => out_dir = args.next(),
+            "--name" => name = args.next().map(|name| utf8(name)).transpose()?,
+            "--force" => force = true,
+            "--json" => json = true,
+            _ => return Err(TranError::UnsupportedError(USAGE.to_string())),
+        }
+    }
+    let deficiency = ColorVisionDeficiency::try_from(
+        deficiency.ok_or_else(|| TranError::UnsupportedError(USAGE.to_string()))?,
+    )?;
+    let mut options =
+        VariantOptions::new(out_dir.ok_or_else(|| TranError::UnsupportedError(USAGE.to_string()))?);
+    options.name = name.map_or(
+        variants::DEFAULT_SIMULATION_NAME.to_string(),
+        str::to_string,
+    );
+    options.force = force;
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let recolor = run::recolor_options(&config, 0);
+    let report = variants::simulate(&config, &recolor, &options, deficiency)?;
+    print_variants(&report, json);
+
+    Ok(if report.failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn print_variants(report: &variants::VariantReport, json: bool) {
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        for variant in report.variants.iter() {
+            println!(
+                "{} {} -> {} ({} changes)",
+                variant.index,
+                variant.source.display(),
+                variant.output.display(),
+                variant.changes
+            );
+        }
+        for (_, e) in report.failed.iter() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn clean(args: &[String]) -> Result<ExitCode, TranError> {
+    let mut yes = false;
+    let mut dry_run = false;
+    for arg in args {
+        match arg.as_str() {
+            "--yes" => yes = true,
+            "--dry-run" => dry_run = true,
+            _ => {
+                return Err(TranError::UnsupportedError(
+                    "Usage: tran clean [--yes] [--dry-run]".to_string(),
+                ))
+            }
+        }
+    }
+
+    let config = parse_config_with_local(get_config_path()?)?;
+    let files = generated_files(&config, &run::recolor_options(&config, 0));
+    if files.is_empty() {
+        println!("No generated files found");
+        return Ok(ExitCode::SUCCESS);
+    }
+    for file in files.iter() {
+        println!("{}", file.display());
+    }
+    if dry_run {
+        return Ok(ExitCode::SUCCESS);
+    }
+    if !yes {
+        print!("Delete {} file(s)? [y/N] ", files.len());
+        std::io::Write::flush(&mut std::io::stdout()).map_err(|e| TranError::write("stdout", e))?;
+        let mut answer = String::new();
+        std::io::stdin()
+            .read_line(&mut answer)
+            .map_err(|e| TranError::read("stdin", e))?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+
+    let mut code = ExitCode::SUCCESS;
+    for file in files.iter() {
+        if let Err(e) = fs::remove_file(file) {
+            eprintln!("{}", TranError::write(file, e));
+            code = ExitCode::FAILURE;
+        }
+    }
+    Ok(code)
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn load_schedule(path: &Path) -> Result<Schedule, TranError> {
+    parse_config_with_local(path)?
+        .get_schedule()
+        .cloned()
+        .ok_or_else(|| {
+            TranError::ConfigError("tran daemon needs a [schedule] in the config".to_string())
+        })
+}
+
+/// Recolors at every time of the config's `[schedule]`, picking the schedule up again whenever
+/// the config changes. With `--watch` targets rewritten by something else are brought back to
+/// the current colors once they stay untouched for the debounce window, and every `--hook`
+/// runs through `sh -c`, or `cmd /C` off unix, after targets were recolored, no sooner than
+/// `--min-interval` after it last ran.
+fn daemon(args: &[String]) -> Result<(), TranError> {
+    const USAGE: &str = "Usage: tran daemon [--watch] [--hook <command>]... [--debounce <seconds>] [--min-interval <seconds>]";
+    let usage = || TranError::UnsupportedError(USAGE.to_string());
+    let mut watch = false;
+    let mut hooks = Vec::new();
+    let mut window = std::time::Duration::from_secs(2);
+    let mut min_interval = std::time::Duration::from_secs(10);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watch = true,
+            "--hook" => hooks.push(args.next().ok_or_else(usage)?.clone()),
+            "--debounce" => {
+                window = std::time::Duration::from_secs(args.next().ok_or_else(usage)?.parse()?)
+            }
+            "--min-interval" => {
+                min_interval =
+                    std::time::Duration::from_secs(args.next().ok_or_else(usage)?.parse()?)
+            }
+            _ => return Err(usage()),
+        }
+    }
+
+    let config_path = get_config_path()?;
+    let zone = TimeZone::local().unwrap_or_else(|e| {
+        eprintln!("{}, scheduling in UTC", e);
+        TimeZone::utc()
+    });
+    let mut schedule = load_schedule(&config_path)?;
+    let mut seen = modified(&config_path);
+    let start = std::time::Instant::now();
+    let clock = || start.elapsed();
+    let run_options = RunOptions {
+        local_config: true,
+        ..RunOptions::new(&config_path)
+    };
+    let mut watched = watch.then(|| TargetWatch::new(window));
+    let mut hook_runs = Debouncer::new(window, min_interval);
+    let recolored = |hook_runs: &mut Debouncer<usize>| {
+        for i in 0..hooks.len() {
+            hook_runs.event(i, clock());
+        }
+    };
+    loop {
+        let next = schedule
+            .next_after(unix_now(), &zone)
+            .ok_or_else(|| TranError::ConfigError("The schedule never fires".to_string()))?;
+        eprintln!(
+            "Next run at {}",
+            format_timestamp((next + zone.offset_at(next)) as u64)
+        );
+
+        // Woken up every minute at most to notice edits to the config, every second to watch
+        // targets and whenever a hook is due
+        let mut reloaded = false;
+        while !reloaded && unix_now() < next {
+            let mut wait = std::time::Duration::from_secs((next - unix_now()).clamp(0, 60) as u64);
+            if watched.is_some() {
+                wait = wait.min(std::time::Duration::from_secs(1));
+            }
+            if let Some(due) = hook_runs.next_due() {
+                wait = wait.min(due.saturating_sub(clock()));
+            }
+            std::thread::sleep(wait);
+            if modified(&config_path) != seen {
+                seen = modified(&config_path);
+                match load_schedule(&config_path) {
+                    Ok(reloaded_schedule) => schedule = reloaded_schedule,
+                    Err(e) => eprintln!("Keeping the last schedule: {}", e),
                 }
+                reloaded = true;
+            }
+            if let Some(watched) = &mut watched {
+                if watched.poll(&run_options, clock()) {
+                    recolored(&mut hook_runs);
+                }
+            }
+            run_hooks(&hooks, &mut hook_runs, clock());
+        }
+        if reloaded {
+            continue;
+        }
 
-                for c in &map {
-                    if let Err(e) = recolor_textfile(path, c.get_new_color(), c.get_current_color())
-                    {
-                        eprintln!("Error recoloring {}: {}", target_file, e);
+        match run(&run_options) {
+            Ok(summary) => {
+                for warning in summary.warnings.iter() {
+                    eprintln!("{}", warning);
+                }
+                for (_, e) in summary.failed.iter() {
+                    eprintln!("{}", e);
+                }
+                match summary.unchanged {
+                    true => eprintln!("Already at {}, nothing to do", selected(&summary)),
+                    false => {
+                        eprintln!(
+                            "Recolored {} targets to {}",
+                            summary.succeeded.len(),
+                            selected(&summary)
+                        );
+                        recolored(&mut hook_runs);
                     }
                 }
             }
+            Err(e) => eprintln!("{}", e),
+        }
+        // The run wrote the new colors to the config, which needs no reload, and the targets
+        // it recolored aren't changes to react to
+        seen = modified(&config_path);
+        if let Some(watched) = &mut watched {
+            watched.forget_changes(&config_path);
+        }
+        run_hooks(&hooks, &mut hook_runs, clock());
+    }
+}
+
+/// Runs the hooks `hook_runs` has due at `now` with the system shell, saying how many runs each
+/// one stands for when they were coalesced.
+fn run_hooks(hooks: &[String], hook_runs: &mut Debouncer<usize>, now: std::time::Duration) {
+    for Fired { key, events } in hook_runs.due(now) {
+        let hook = &hooks[key];
+        match events {
+            1 => eprintln!("Running hook '{}'", hook),
+            events => eprintln!("Running hook '{}' once for {} coalesced runs", hook, events),
+        }
+        match shell(hook).status() {
+            Ok(status) if !status.success() => eprintln!("Hook '{}' failed with {}", hook, status),
+            Ok(_) => {}
+            Err(e) => eprintln!("Could not run hook '{}': {}", hook, e),
+        }
+    }
+}
+
+/// `command` run by `sh -c`.
+#[cfg(unix)]
+fn shell(command: &str) -> std::process::Command {
+    let mut shell = std::process::Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// `command` run by `cmd /C`.
+#[cfg(not(unix))]
+fn shell(command: &str) -> std::process::Command {
+    let mut shell = std::process::Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// The targets of the config `tran daemon --watch` looks after, with when each was last
+/// modified.
+struct TargetWatch {
+    modified: Vec<(PathBuf, Option<std::time::SystemTime>)>,
+    changes: Debouncer<PathBuf>,
+}
+
+impl TargetWatch {
+    fn new(window: std::time::Duration) -> Self {
+        TargetWatch {
+            modified: Vec::new(),
+            // Re-applying waits for the window only, hooks keep to the minimum interval
+            changes: Debouncer::new(window, std::time::Duration::ZERO),
+        }
+    }
+
+    /// Notes the targets of the config `options` run with modified since the last poll, and
+    /// brings the ones that stayed untouched for the window back to the current colors, with
+    /// the recolor options of such a run. Whether any target was recolored.
+    fn poll(&mut self, options: &RunOptions, now: std::time::Duration) -> bool {
+        let config_path = options.config_path.as_path();
+        let config = match parse_config_with_local(config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Not watching targets: {}", e);
+                return false;
+            }
+        };
+        let current = target_times(&config);
+        for (path, time) in current.iter() {
+            let known = self.modified.iter().find(|(known, _)| known == path);
+            // Targets that only just came into the config aren't changes
+            if known.is_some_and(|(_, known)| known != time) {
+                self.changes.event(path.clone(), now);
+            }
+        }
+        self.modified = current;
+
+        let due = self.changes.due(now);
+        if due.is_empty() {
+            return false;
+        }
+        for Fired { key, events } in due.iter() {
+            match events {
+                1 => eprintln!("{} changed, re-applying the current colors", key.display()),
+                events => eprintln!(
+                    "{} changed {} times, re-applying the current colors once",
+                    key.display(),
+                    events
+                ),
+            }
+        }
+        let paths: Vec<PathBuf> = due.into_iter().map(|fired| fired.key).collect();
+        let report = verify::adopt(&config, &options.recolor_options(&config, 0), &paths);
+        print_verify_report(&report, false);
+        self.forget_changes(config_path);
+        !report.repaired.is_empty()
+    }
+
+    /// Takes the targets as they are now as unchanged, after tran wrote them itself.
+    fn forget_changes(&mut self, config_path: &Path) {
+        if let Ok(config) = parse_config_with_local(config_path) {
+            self.modified = target_times(&config);
+        }
+    }
+}
+
+fn target_times(config: &Config) -> Vec<(PathBuf, Option<std::time::SystemTime>)> {
+    config
+        .get_targets()
+        .iter()
+        .map(|target| (target.path.clone(), modified(&target.path)))
+        .collect()
+}
+
+/// The colors a run picked, back to back.
+fn selected(summary: &RunSummary) -> String {
+    summary.colors.new.iter().map(with_swatch).collect()
+}
+
+fn stale_warning(path: &Path) -> String {
+    format!(
+        "STALE: {} holds none of the configured colors, no run changes it. Remove it with tran target --prune-stale",
+        path.display()
+    )
+}
 
-            mc.set_current_colors(new_color.to_owned());
+/// Prints what a run with `options` would do and keeps it for `tran --yes`.
+fn show_plan(options: &RunOptions, json: bool) -> Result<ExitCode, TranError> {
+    let plan = plan(options)?;
+    for warning in plan.warnings() {
+        eprintln!("{}", warning);
+    }
+    if let Err(e) = plan.save() {
+        eprintln!("Could not keep the plan for --yes: {}", e);
+    }
+    let selected: String = plan.selection().colors().iter().map(with_swatch).collect();
+    if json {
+        println!("{}", plan.to_json());
+    } else if plan.unchanged() {
+        eprintln!("Already at {}, nothing to do", selected);
+    } else {
+        for target in plan.targets.iter() {
+            println!("{} ({} changes)", target.output.display(), target.changes);
+            for (old, new) in target.colors.iter() {
+                println!("  {} -> {}", with_swatch(old), with_swatch(new));
+            }
+            if target.stale {
+                eprintln!("{}", stale_warning(&target.path));
+            }
         }
+        for (_, e) in plan.failed.iter() {
+            eprintln!("{}", e);
+        }
+    }
+
+    Ok(ExitCode::from(plan.exit_code()))
+}
+
+/// Prints every target as it is done, for `--progress`.
+fn print_progress(event: ProgressEvent) {
+    match event {
+        ProgressEvent::Started { .. } => {}
+        ProgressEvent::Finished {
+            path, total, done, ..
+        } => eprintln!("[{}/{}] {}", done, total, path.display()),
+        ProgressEvent::Failed {
+            path, total, done, ..
+        } => eprintln!("[{}/{}] {} failed", done, total, path.display()),
+    }
+}
+
+/// Recolors the targets of the config to its next colors, or to those `TRAN_COLOR` holds when
+/// it is set, which win over the pick of `--seed`.
+fn recolor(args: &[OsString]) -> Result<ExitCode, TranError> {
+    const USAGE: &str = "Usage: tran [--config <path>|- [--emit-config]] [--json] [--dry-run|--yes] [--seed <n>] [--only <glob>]... [--no-cache] [--no-transition] [--transactional] [--unsafe-allow-outside] [--out-dir <dir>] [--tags <tag,...>] [--progress] [--no-color]";
+
+    let mut options = RunOptions::new(get_config_path()?);
+    options.local_config = true;
+    // A color that isn't utf-8 is refused like any other the config can't read
+    options.color = match std::env::var_os("TRAN_COLOR").filter(|c| !c.is_empty()) {
+        Some(color) => Some(color.into_string().map_err(|color| {
+            TranError::UnsupportedError(format!(
+                "Can't recolor to TRAN_COLOR '{}', it is not valid utf-8",
+                color.to_string_lossy()
+            ))
+        })?),
+        None => None,
     };
+    let mut json = false;
+    let mut emit_config = false;
+    let mut yes = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match utf8(arg)? {
+            "--json" => json = true,
+            "--config" => match args.next() {
+                Some(path) if path == "-" => {
+                    let mut contents = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut contents)
+                        .map_err(|e| TranError::read("stdin", e))?;
+                    options.config_contents = Some(contents);
+                }
+                Some(path) => options.config_path = path.into(),
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            "--emit-config" => emit_config = true,
+            "--dry-run" => options.dry_run = true,
+            "--yes" => yes = true,
+            "--seed" => match args.next() {
+                Some(seed) => options.seed = Some(utf8(seed)?.parse()?),
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            "--only" => match args.next() {
+                Some(glob) => options.only.push(utf8(glob)?.to_string()),
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            "--no-cache" => options.use_cache = false,
+            "--no-transition" => options.use_transition = false,
+            "--transactional" => options.transactional = true,
+            "--unsafe-allow-outside" => options.allow_outside = true,
+            "--out-dir" => match args.next() {
+                Some(dir) => options.out_dir = Some(dir.into()),
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            "--tags" => match args.next() {
+                Some(tags) => options.tags.extend(
+                    utf8(tags)?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|tag| !tag.is_empty())
+                        .map(str::to_string),
+                ),
+                None => return Err(TranError::UnsupportedError(USAGE.to_string())),
+            },
+            "--progress" => options.progress = Some(ProgressLog::new(print_progress)),
+            arg => {
+                return Err(TranError::UnsupportedError(format!(
+                    "Unknown option '{}'\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+    }
+
+    if emit_config && (options.config_contents.is_none() || json) {
+        return Err(TranError::UnsupportedError(format!(
+            "--emit-config writes the config read with --config - to stdout, it can't go without it or with --json\n{}",
+            USAGE
+        )));
+    }
+    let config_path = &options.config_path;
+    if options.config_contents.is_none() && !config_path.is_file() {
+        if let Some(dir) = config_path.parent() {
+            perms::create_dir_all(dir).map_err(|e| TranError::write(dir, e))?;
+        }
+        perms::write(config_path, "").map_err(|e| TranError::write(config_path, e))?;
+        eprintln!("Created empty config file, please fill it out");
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    write_config(config, config_path)?;
+    if options.dry_run && yes {
+        return Err(TranError::UnsupportedError(format!(
+            "--yes applies the plan --dry-run shows, run them one after the other\n{}",
+            USAGE
+        )));
+    }
+    if options.dry_run {
+        return show_plan(&options, json);
+    }
 
-    Ok(())
+    let mut summary = match yes {
+        true => Plan::saved(&options)?.apply()?,
+        false => run(&options)?,
+    };
+    for warning in summary.warnings.iter() {
+        eprintln!("{}", warning);
+    }
+    if summary.unchanged && !json {
+        eprintln!("Already at {}, nothing to do", selected(&summary));
+    } else if json {
+        println!("{}", summary.to_json());
+    } else {
+        for target in summary.succeeded.iter() {
+            for near_match in target.near_matches.iter() {
+                eprintln!("{}: {}", target.path.display(), near_match);
+            }
+        }
+        for target in summary.stale() {
+            eprintln!("{}", stale_warning(&target.path));
+        }
+        for (_, e) in summary.failed.iter() {
+            eprintln!("{}", e);
+        }
+        if !summary.should_advance() && !summary.interrupted {
+            match options.transactional {
+                true => eprintln!("{} target(s) failed, nothing applied", summary.failed.len()),
+                false => eprintln!("Every target failed, keeping current color"),
+            }
+        }
+    }
+    if emit_config {
+        // Whatever didn't move on is emitted as it was read
+        match (summary.config.take(), &options.config_contents) {
+            (Some(config), _) => print!("{}", write_config_to_string(config)),
+            (None, Some(contents)) => print!("{}", contents),
+            (None, None) => {}
+        }
+    }
+
+    Ok(ExitCode::from(summary.exit_code()))
 }
@@ -0,0 +1,78 @@
+//! Work splitting for the `parallel` feature, on plain std threads.
+
+use std::thread;
+
+fn workers(len: usize) -> usize {
+    thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(len)
+        .max(1)
+}
+
+/// Maps `items` on as many threads as there are cores, keeping the order of the results.
+pub fn map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let chunk_size = items.len().div_ceil(workers(items.len())).max(1);
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Runs `f` over chunks of `data` whose length is a multiple of `align` and sums the results.
+pub fn for_each_chunk_mut(
+    data: &mut [u8],
+    align: usize,
+    f: impl Fn(&mut [u8]) -> usize + Sync,
+) -> usize {
+    let units = data.len() / align.max(1);
+    let chunk_size = units.div_ceil(workers(units)).max(1) * align.max(1);
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = data
+            .chunks_mut(chunk_size)
+            .map(|chunk| s.spawn(move || f(chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_keeps_the_order() {
+        let items: Vec<usize> = (0..1001).collect();
+        assert_eq!(
+            map(&items, |i| i * 2),
+            items.iter().map(|i| i * 2).collect::<Vec<_>>()
+        );
+        assert_eq!(map(&[] as &[usize], |i| *i), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn chunks_never_split_a_unit() {
+        let mut data = vec![0u8; 4 * 1013 + 3];
+        let ragged = std::sync::atomic::AtomicUsize::new(0);
+        let units = for_each_chunk_mut(&mut data, 4, |chunk| {
+            // Only the bytes past the last whole unit can be left over, in the last chunk
+            if chunk.len() % 4 != 0 {
+                assert_eq!(chunk.len() % 4, 3);
+                ragged.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+            chunk.len() / 4
+        });
+        assert_eq!(units, 1013);
+        assert!(ragged.into_inner() <= 1);
+    }
+}
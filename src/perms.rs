@@ -0,0 +1,87 @@
+//! Files and directories tran makes for itself: the config, the state directory and everything
+//! in it, backups and exports. They can hold private paths, so on Unix they are created for
+//! their owner only, files 0600 and directories 0700 with the umask taking away from that as it
+//! does from any mode. Files that already exist keep their mode, and on other platforms the
+//! defaults apply.
+
+use std::{
+    fs::{DirBuilder, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+#[cfg(unix)]
+const FILE_MODE: u32 = 0o600;
+#[cfg(unix)]
+const DIR_MODE: u32 = 0o700;
+
+fn options() -> OpenOptions {
+    #[allow(unused_mut)]
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    options.mode(FILE_MODE);
+    options
+}
+
+/// Opens `path` for writing from the start, as `File::create` does.
+pub fn create(path: &Path) -> io::Result<File> {
+    options().write(true).create(true).truncate(true).open(path)
+}
+
+/// Opens `path` for appending, creating it when it doesn't exist.
+pub fn append(path: &Path) -> io::Result<File> {
+    options().create(true).append(true).open(path)
+}
+
+/// Writes `contents` to `path`, as `fs::write` does.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    create(path)?.write_all(contents.as_ref())
+}
+
+/// Creates `path` and every missing directory above it, as `fs::create_dir_all` does.
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(DIR_MODE);
+    builder.create(path)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn new_files_and_directories_are_for_their_owner_only() {
+        let dir = TempDir::new("perms");
+        let state = dir.path().join("state/nested");
+        create_dir_all(&state).unwrap();
+        assert_eq!(mode(&state) & 0o077, 0);
+        assert_eq!(mode(&dir.path().join("state")) & 0o077, 0);
+
+        let file = state.join("runs.log");
+        write(&file, "a\n").unwrap();
+        append(&file).unwrap().write_all(b"b\n").unwrap();
+        assert_eq!(mode(&file) & 0o077, 0);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn existing_files_keep_their_mode() {
+        let dir = TempDir::new("perms-existing");
+        let config = dir.write("config", "old");
+        std::fs::set_permissions(&config, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write(&config, "new").unwrap();
+        assert_eq!(mode(&config), 0o644);
+        assert_eq!(std::fs::read_to_string(&config).unwrap(), "new");
+    }
+}
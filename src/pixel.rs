@@ -0,0 +1,387 @@
+//! Color lookups the png and text handlers share.
+
+use std::collections::HashMap;
+
+use crate::{config::Color, errors::TranError, space::GradientSpace, ColorMap};
+
+/// Old to new color lookup, so every color of a transform is parsed exactly once.
+pub type PixelMap = HashMap<(u8, u8, u8), (u8, u8, u8)>;
+
+/// Builds the lookup for `map`. The first entry for a color wins.
+pub fn pixel_map(map: &[ColorMap]) -> Result<PixelMap, TranError> {
+    let mut lookup = HashMap::with_capacity(map.len());
+    for color_map in map.iter().filter(|m| !m.keeps_color()) {
+        let (current, new) = color_map.colors()?;
+        lookup.entry(current.bytes()).or_insert(new.bytes());
+    }
+    Ok(lookup)
+}
+
+/// Recolors raw pixels of `channels` bytes each, the first three being red, green and blue.
+/// Returns the number of pixels that changed.
+pub fn recolor_pixels(pixels: &mut [u8], channels: usize, lookup: &PixelMap) -> usize {
+    let recolor = |pixels: &mut [u8]| -> usize {
+        let mut changes = 0;
+        for pixel in pixels.chunks_exact_mut(channels) {
+            if let Some(&(red, green, blue)) = lookup.get(&(pixel[0], pixel[1], pixel[2])) {
+                if (pixel[0], pixel[1], pixel[2]) != (red, green, blue) {
+                    changes += 1;
+                }
+                pixel[0] = red;
+                pixel[1] = green;
+                pixel[2] = blue;
+            }
+        }
+        changes
+    };
+
+    if channels < 3 {
+        return 0;
+    }
+
+    #[cfg(feature = "parallel")]
+    {
+        crate::parallel::for_each_chunk_mut(pixels, channels, recolor)
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        recolor(pixels)
+    }
+}
+
+/// The luma of a color, from 0 for black to 255 for white, weighting the stored channels as
+/// Rec. 709 does.
+pub fn luma(&(r, g, b): &(u8, u8, u8)) -> u8 {
+    (0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64).round() as u8
+}
+
+/// The lumas a gradient recolors, see `[gradient_options]`. Colors outside the band are left
+/// as they are and play no part in the ramp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LuminanceBand {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl Default for LuminanceBand {
+    fn default() -> Self {
+        LuminanceBand { min: 0, max: 255 }
+    }
+}
+
+impl LuminanceBand {
+    pub fn contains(&self, color: &(u8, u8, u8)) -> bool {
+        (self.min..=self.max).contains(&luma(color))
+    }
+}
+
+/// A color of a multi-stop gradient, `position` running from 0 for black to 1 for the
+/// brightest color the gradient maps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GradientStop {
+    pub position: f64,
+    pub color: Color,
+}
+
+/// The color at `t` along `stops`, mixed in `space` between the stops on either side. Before
+/// the first stop and after the last their colors hold, so a single stop is a solid fill.
+pub fn stop_color(stops: &[GradientStop], t: f64, space: GradientSpace) -> Color {
+    let Some(last) = stops.last() else {
+        return Color::black();
+    };
+    match stops.iter().position(|stop| stop.position >= t) {
+        None => last.color,
+        Some(0) => stops[0].color,
+        Some(i) => {
+            let (from, to) = (stops[i - 1], stops[i]);
+            // Two stops at one position are a hard edge
+            let span = to.position - from.position;
+            match span > 0.0 {
+                true => space.mix(&from.color, &to.color, (t - from.position) / span),
+                false => to.color,
+            }
+        }
+    }
+}
+
+/// The ramp a gradient maps the colors of `colors` inside `band` onto. With `stops`, every
+/// color takes the one at its lightness next to the brightest along them. Otherwise the
+/// brightest becomes `primary` and, in sRGB, every following one keeps its ratio to the one
+/// before it, per channel, while other spaces mix `primary` with black by that lightness.
+/// A single color without stops becomes `primary` itself. Colors outside `band` have no entry.
+pub fn gradient_map(
+    colors: &[(u8, u8, u8)],
+    primary: &str,
+    space: GradientSpace,
+    band: LuminanceBand,
+    stops: &[GradientStop],
+) -> Result<PixelMap, TranError> {
+    let mut colors: Vec<(u8, u8, u8)> = colors
+        .iter()
+        .copied()
+        .filter(|c| band.contains(c))
+        .collect();
+    colors.sort_by_key(|&(r, g, b)| std::cmp::Reverse(r as u64 + g as u64 + b as u64));
+
+    let mut lookup = PixelMap::with_capacity(colors.len());
+    if let ([only], true) = (colors.as_slice(), stops.is_empty()) {
+        lookup.insert(*only, Color::try_from_hex_str(primary)?.bytes());
+        return Ok(lookup);
+    }
+    if space != GradientSpace::Srgb || !stops.is_empty() {
+        let primary = Color::try_from_hex_str(primary)?;
+        let lightness = |&(r, g, b): &(u8, u8, u8)| space.lightness(&Color::from_bytes(r, g, b));
+        let brightest = colors.iter().map(lightness).fold(0.0, f64::max);
+        for old in colors.iter() {
+            let t = match brightest > 0.0 {
+                true => lightness(old) / brightest,
+                false => 1.0,
+            };
+            let new = match stops.is_empty() {
+                true => space.mix(&Color::black(), &primary, t),
+                false => stop_color(stops, t, space),
+            };
+            lookup.entry(*old).or_insert(new.bytes());
+        }
+        return Ok(lookup);
+    }
+    let mut previous_new = Color::try_from_hex_str(primary)?.bytes();
+    for (i, &old) in colors.iter().enumerate() {
+        if i > 0 {
+            let previous_old = colors[i - 1];
+            previous_new = (
+                (previous_new.0 as f64 * channel_ratio(old.0, previous_old.0)) as u8,
+                (previous_new.1 as f64 * channel_ratio(old.1, previous_old.1)) as u8,
+                (previous_new.2 as f64 * channel_ratio(old.2, previous_old.2)) as u8,
+            );
+        }
+        lookup.entry(old).or_insert(previous_new);
+    }
+    Ok(lookup)
+}
+
+// A channel that was 0 in the previous entry has no ratio to scale by, so it is kept as is
+fn channel_ratio(next: u8, previous: u8) -> f64 {
+    if previous == 0 {
+        1.0
+    } else {
+        next as f64 / previous as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image of odd size, so no split of it lines up with rows.
+    fn image(channels: usize) -> Vec<u8> {
+        (0..997 * 41)
+            .flat_map(|i: usize| {
+                let shade = (i / 13 % 8) as u8 * 32;
+                [shade, 255 - shade, shade / 2, 200][..channels].to_vec()
+            })
+            .collect()
+    }
+
+    fn map() -> Vec<(String, String)> {
+        (0..8)
+            .map(|i| {
+                let shade = i * 32;
+                (
+                    Color::from_bytes(255 - shade, shade, 7).to_string(),
+                    Color::from_bytes(shade, 255 - shade, shade / 2).to_string(),
+                )
+            })
+            .collect()
+    }
+
+    /// Recolors one pixel at a time, scanning the map as the palette loop used to.
+    fn naive(pixels: &mut [u8], channels: usize, map: &[ColorMap]) -> usize {
+        let mut changes = 0;
+        for pixel in pixels.chunks_exact_mut(channels) {
+            let entry = map
+                .iter()
+                .find(|m| m.colors().unwrap().0.bytes() == (pixel[0], pixel[1], pixel[2]));
+            if let Some(entry) = entry {
+                let (red, green, blue) = entry.colors().unwrap().1.bytes();
+                changes += usize::from((pixel[0], pixel[1], pixel[2]) != (red, green, blue));
+                (pixel[0], pixel[1], pixel[2]) = (red, green, blue);
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn the_lookup_recolors_as_the_naive_scan_does() {
+        let map = map();
+        let map: Vec<ColorMap> = map
+            .iter()
+            .map(|(new, old)| ColorMap::new(new, old))
+            .collect();
+        let lookup = pixel_map(&map).unwrap();
+        for channels in [3, 4] {
+            let mut expected = image(channels);
+            let expected_changes = naive(&mut expected, channels, &map);
+            let mut pixels = image(channels);
+            assert_eq!(
+                recolor_pixels(&mut pixels, channels, &lookup),
+                expected_changes
+            );
+            assert!(pixels == expected, "{} channels differ", channels);
+            assert!(expected_changes > 0);
+        }
+    }
+
+    /// The bytes are pinned, so builds with and without `parallel` are held to the same output.
+    #[test]
+    fn recolored_pixels_are_the_same_with_and_without_parallel() {
+        let map = map();
+        let map: Vec<ColorMap> = map
+            .iter()
+            .map(|(new, old)| ColorMap::new(new, old))
+            .collect();
+        let lookup = pixel_map(&map).unwrap();
+        let mut pixels = image(4);
+        let changes = recolor_pixels(&mut pixels, 4, &lookup);
+        let checksum = pixels.iter().fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+        });
+        assert_eq!((changes, checksum), (40877, 0x1cf32cc2719ab5ed));
+    }
+
+    #[test]
+    fn the_first_entry_for_a_color_wins() {
+        let map = [
+            ColorMap::new("#111111", "#2e3440"),
+            ColorMap::new("#222222", "#2e3440"),
+            ColorMap::new("#333333", "#333333"),
+        ];
+        let lookup = pixel_map(&map).unwrap();
+        assert_eq!(lookup.len(), 1);
+        assert_eq!(lookup[&(0x2e, 0x34, 0x40)], (0x11, 0x11, 0x11));
+        assert!(pixel_map(&[ColorMap::new("#zzzzzz", "#2e3440")]).is_err());
+    }
+
+    #[test]
+    fn maps_compare_colors_not_how_they_are_written() {
+        let short = ColorMap::new("#ABC", "#aabbcc");
+        assert!(short.keeps_color());
+        assert_eq!(
+            short.colors().unwrap(),
+            (
+                Color::from_bytes(0xaa, 0xbb, 0xcc),
+                Color::from_bytes(0xaa, 0xbb, 0xcc)
+            )
+        );
+        assert!(pixel_map(&[short]).unwrap().is_empty());
+
+        let lookup = pixel_map(&[ColorMap::new("0x111", "#2E3440")]).unwrap();
+        assert_eq!(lookup[&(0x2e, 0x34, 0x40)], (0x11, 0x11, 0x11));
+
+        let broken = ColorMap::new("#2e3440", "#zzzzzz");
+        assert!(!broken.keeps_color());
+        assert!(matches!(
+            broken.colors(),
+            Err(TranError::InvalidHexColor { .. })
+        ));
+    }
+
+    #[test]
+    fn grey_pixels_are_left_alone() {
+        let mut pixels = vec![0x2e, 0x34];
+        let lookup = pixel_map(&[ColorMap::new("#111111", "#2e3440")]).unwrap();
+        assert_eq!(recolor_pixels(&mut pixels, 2, &lookup), 0);
+        assert_eq!(pixels, [0x2e, 0x34]);
+    }
+
+    #[test]
+    fn oklab_gradients_ramp_from_black_in_oklab() {
+        let colors = [(200, 200, 200), (100, 100, 100), (50, 50, 50)];
+        let lookup = gradient_map(
+            &colors,
+            "#88c0d0",
+            GradientSpace::Oklab,
+            LuminanceBand::default(),
+            &[],
+        )
+        .unwrap();
+        let primary = Color::try_from_hex_str("#88c0d0").unwrap();
+        assert_eq!(lookup[&(200, 200, 200)], primary.bytes());
+        let lightness =
+            |(r, g, b): (u8, u8, u8)| crate::space::to_oklab(&Color::from_bytes(r, g, b))[0];
+        for old in &colors[1..] {
+            let t = lightness(*old) / lightness(colors[0]);
+            let expected = GradientSpace::Oklab.mix(&Color::black(), &primary, t);
+            assert_eq!(lookup[old], expected.bytes());
+        }
+        let srgb = gradient_map(
+            &colors,
+            "#88c0d0",
+            GradientSpace::Srgb,
+            LuminanceBand::default(),
+            &[],
+        )
+        .unwrap();
+        assert_ne!(lookup[&(100, 100, 100)], srgb[&(100, 100, 100)]);
+    }
+
+    #[test]
+    fn gradients_leave_colors_outside_the_band() {
+        let colors = [(250, 250, 250), (200, 200, 200), (100, 100, 100), (5, 5, 5)];
+        let band = LuminanceBand { min: 10, max: 240 };
+        let lookup = gradient_map(&colors, "#88c0d0", GradientSpace::Srgb, band, &[]).unwrap();
+        assert_eq!(lookup.len(), 2);
+        assert!(!lookup.contains_key(&(250, 250, 250)));
+        assert!(!lookup.contains_key(&(5, 5, 5)));
+        // The brightest color inside the band takes the primary, not the one above it
+        assert_eq!(lookup[&(200, 200, 200)], (0x88, 0xc0, 0xd0));
+        assert_eq!(lookup[&(100, 100, 100)], (0x44, 0x60, 0x68));
+
+        assert!(band.contains(&(10, 10, 10)));
+        assert!(band.contains(&(240, 240, 240)));
+        // Lumas weigh the channels, so a blue as bright as a grey by their sum falls out
+        assert!(!band.contains(&(0, 0, 100)));
+        assert!(band.contains(&(33, 33, 33)));
+    }
+
+    #[test]
+    fn stops_color_the_ramp_by_lightness() {
+        let (black, red, white) = (
+            Color::black(),
+            Color::from_bytes(255, 0, 0),
+            Color::from_bytes(255, 255, 255),
+        );
+        let stop = |position, color| GradientStop { position, color };
+        let stops = [stop(0.25, black), stop(0.75, red), stop(0.75, white)];
+        let at = |t| stop_color(&stops, t, GradientSpace::Srgb);
+        assert_eq!(at(0.0), black);
+        assert_eq!(at(0.25), black);
+        assert_eq!(at(0.5), GradientSpace::Srgb.mix(&black, &red, 0.5));
+        // Two stops at one position make a hard edge
+        assert_eq!(at(0.75), red);
+        assert_eq!(at(0.76), white);
+        assert_eq!(stop_color(&[], 0.5, GradientSpace::Srgb), black);
+
+        let stops = [stop(0.0, black), stop(1.0, red)];
+        let lookup = gradient_map(
+            &[(200, 200, 200), (0, 0, 0)],
+            "#88c0d0",
+            GradientSpace::Srgb,
+            LuminanceBand::default(),
+            &stops,
+        )
+        .unwrap();
+        assert_eq!(lookup[&(200, 200, 200)], red.bytes());
+        assert_eq!(lookup[&(0, 0, 0)], black.bytes());
+        // A single color with stops still takes its place along them
+        let lone = gradient_map(
+            &[(9, 9, 9)],
+            "#88c0d0",
+            GradientSpace::Srgb,
+            LuminanceBand::default(),
+            &stops,
+        )
+        .unwrap();
+        assert_eq!(lone[&(9, 9, 9)], red.bytes());
+    }
+}
@@ -0,0 +1,413 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cache::content_hash,
+    config::{write_config_to_string, Color},
+    errors::TranError,
+    json::Json,
+    recolor::FileFormat,
+    run::{finish, prepare, preview, synced, target_options, with_transform, Prepared},
+    run::{RunOptions, RunSummary},
+    scan::scan_file,
+    selection::Selection,
+    state::State,
+    text::replacements,
+};
+
+const PLAN_FILE: &str = "plan.json";
+
+/// What a run would do to one target.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedTarget {
+    pub path: PathBuf,
+    /// The file written, `path` itself when overwriting.
+    pub output: PathBuf,
+    pub handler: FileFormat,
+    /// The `(old, new)` pairs applied to the colors found in the target. For an `exec`
+    /// target, whose colors tran can't see, every pair the command is handed.
+    pub colors: Vec<(Color, Color)>,
+    /// How many colors would be replaced.
+    pub changes: usize,
+    /// See `TargetResult::stale`.
+    pub stale: bool,
+    /// The content hash of `path` and whether `output` existed when planned, see `Plan::apply`.
+    fingerprint: (Option<u64>, bool),
+}
+
+fn fingerprint(path: &Path, output: &Path) -> (Option<u64>, bool) {
+    let hash = std::fs::read(path).ok().map(|data| content_hash(&data));
+    (hash, output.exists())
+}
+
+/// A run worked out without writing anything, to be looked at and then applied as it is.
+pub struct Plan {
+    prepared: Prepared,
+    pub targets: Vec<PlannedTarget>,
+    /// Targets that couldn't be planned, which would fail the run the same way.
+    pub failed: Vec<(PathBuf, TranError)>,
+}
+
+/// Works out what a run with `options` would do to every target, picking the colors as the
+/// run would. `options.dry_run` doesn't matter, nothing is ever written. An interrupted
+/// transition has to be finished first, its steps can't be planned.
+pub fn plan(options: &RunOptions) -> Result<Plan, TranError> {
+    let prepared = prepare(options)?;
+    if let Some(pending) = &prepared.pending {
+        return Err(TranError::UnsupportedError(format!(
+            "A transition to {} was interrupted, finish it with tran before planning",
+            pending
+        )));
+    }
+    if prepared.unchanged() {
+        return Ok(Plan {
+            prepared,
+            targets: Vec::new(),
+            failed: Vec::new(),
+        });
+    }
+
+    let config = &prepared.targets;
+    let summary = preview(config, &prepared.selection, &prepared.recolor);
+    let targets = with_transform(
+        &prepared.selection,
+        &config.get_current_colors(),
+        config.get_gradient_space(),
+        config.get_luminance_band(),
+        config.get_stops(),
+        |transform| {
+            summary
+                .succeeded
+                .into_iter()
+                .map(|result| {
+                    let target = config
+                        .get_targets()
+                        .iter()
+                        .find(|target| target.path == result.path)
+                        .expect("every result is of a target");
+                    let (path, options) = target_options(target, &prepared.recolor);
+                    let found = scan_file(&path, &options).ok();
+                    let colors = synced(
+                        config,
+                        target,
+                        &options,
+                        &prepared.selection,
+                        transform,
+                        true,
+                        |transform| replacements(transform, found.as_deref().unwrap_or_default()),
+                    )
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|(old, _)| found.as_ref().is_none_or(|found| found.contains(old)))
+                    .collect();
+                    PlannedTarget {
+                        fingerprint: fingerprint(&result.path, &result.output),
+                        path: result.path,
+                        output: result.output,
+                        handler: result.handler,
+                        colors,
+                        changes: result.changes,
+                        stale: result.stale,
+                    }
+                })
+                .collect()
+        },
+    );
+    Ok(Plan {
+        prepared,
+        targets,
+        failed: summary.failed,
+    })
+}
+
+impl Plan {
+    pub fn selection(&self) -> &Selection {
+        &self.prepared.selection
+    }
+
+    pub fn old_colors(&self) -> Vec<Color> {
+        self.prepared.config.get_current_colors()
+    }
+
+    /// Things noticed while planning, such as `[allowed_roots]` being ignored.
+    pub fn warnings(&self) -> &[String] {
+        &self.prepared.warnings
+    }
+
+    /// 0 when every target can be recolored, 2 when some can't and 1 when none can.
+    pub fn exit_code(&self) -> u8 {
+        if self.failed.is_empty() {
+            0
+        } else if self.targets.is_empty() {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Whether the colors picked are the current ones, so applying does nothing.
+    pub fn unchanged(&self) -> bool {
+        self.prepared.unchanged()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let colors = |colors: &[Color]| -> Json {
+            colors
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .into()
+        };
+        let targets: Vec<Json> = self
+            .targets
+            .iter()
+            .map(|t| {
+                let pairs: Vec<Json> = t
+                    .colors
+                    .iter()
+                    .map(|(old, new)| {
+                        Json::object()
+                            .with("old", old.to_string())
+                            .with("new", new.to_string())
+                    })
+                    .collect();
+                let (hash, output_exists) = t.fingerprint;
+                Json::object()
+                    .with("path", t.path.to_string_lossy().to_string())
+                    .with("output", t.output.to_string_lossy().to_string())
+                    .with("handler", t.handler.to_string())
+                    .with("colors", pairs)
+                    .with("changes", t.changes)
+                    .with("stale", t.stale)
+                    .with(
+                        "hash",
+                        hash.map_or(Json::Null, |hash| format!("{:016x}", hash).into()),
+                    )
+                    .with("output_exists", output_exists)
+            })
+            .collect();
+        let failed: Vec<Json> = self
+            .failed
+            .iter()
+            .map(|(path, e)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("error", e.to_string())
+            })
+            .collect();
+        let config = content_hash(write_config_to_string(self.prepared.config.clone()).as_bytes());
+
+        Json::object()
+            .with("mode", self.prepared.config.get_mode().to_string())
+            .with("old_colors", colors(&self.old_colors()))
+            .with("new_colors", colors(self.selection().colors()))
+            .with("selection", self.selection().to_string())
+            .with("config", format!("{:016x}", config))
+            .with("targets", targets)
+            .with("failed", failed)
+            .with("unchanged", self.unchanged())
+    }
+
+    /// Runs the plan: recolors the targets, advances the config and journals the run as
+    /// `run` does. It goes straight to the new colors, the steps of a `[transition]` would
+    /// make changes the plan doesn't show. Nothing is written when any target changed since
+    /// it was planned, as those changes would differ from the plan too.
+    pub fn apply(mut self) -> Result<RunSummary, TranError> {
+        if self.unchanged() {
+            return Ok(self.prepared.unchanged_summary());
+        }
+        let changed = self
+            .targets
+            .iter()
+            .find(|t| fingerprint(&t.path, &t.output) != t.fingerprint);
+        if let Some(target) = changed {
+            return Err(TranError::UnsupportedError(format!(
+                "{} changed since it was planned, plan again",
+                target.path.display()
+            )));
+        }
+        self.prepared.options.use_transition = false;
+        finish(self.prepared)
+    }
+
+    /// Keeps the plan in the state directory, for `Plan::saved` to apply later.
+    pub fn save(&self) -> Result<(), TranError> {
+        let path = plan_path(&self.prepared.options)?;
+        crate::perms::write(&path, self.to_json().to_string())
+            .map_err(|e| TranError::write(&path, e))
+    }
+
+    /// Plans again the run `save` kept with `options`, to the colors it showed. Fails when
+    /// there is no such plan or anything it showed changed since, the config or a target.
+    /// The kept plan is used up.
+    pub fn saved(options: &RunOptions) -> Result<Plan, TranError> {
+        let path = plan_path(options)?;
+        if !path.is_file() {
+            return Err(TranError::UnsupportedError(
+                "No plan to apply, show one with tran --dry-run".to_string(),
+            ));
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| TranError::read(&path, e))?;
+        let saved = Json::parse(&contents)?;
+        let selection = saved
+            .get("selection")
+            .and_then(Json::as_str)
+            .ok_or_else(|| {
+                TranError::UnsupportedError(format!(
+                    "Could not read the plan in {}",
+                    path.display()
+                ))
+            })?;
+        let plan = plan(&RunOptions {
+            color: Some(selection.to_string()),
+            ..options.clone()
+        })?;
+        if plan.to_json() != saved {
+            return Err(TranError::UnsupportedError(
+                "The config or targets changed since the plan was shown, show it again with tran --dry-run"
+                    .to_string(),
+            ));
+        }
+        std::fs::remove_file(&path).map_err(|e| TranError::write(&path, e))?;
+        Ok(plan)
+    }
+}
+
+fn plan_path(options: &RunOptions) -> Result<PathBuf, TranError> {
+    let state = match &options.state_dir {
+        Some(dir) => State::at(dir),
+        None => State::open(),
+    }?;
+    Ok(state.get_dir().join(PLAN_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config, test_util::TempDir};
+
+    fn color(hex: &str) -> Color {
+        Color::try_from_hex_str(hex).unwrap()
+    }
+
+    /// A map from #2e3440#88c0d0 to #3b4252#bf616a over `targets`, with its state in `dir`.
+    fn options(dir: &TempDir, targets: &[&Path]) -> RunOptions {
+        let targets: Vec<String> = targets.iter().map(|t| t.display().to_string()).collect();
+        let config = dir.write(
+            "config",
+            format!(
+                "[mode]\nmap\n[current_color]\n#2e3440#88c0d0\n[colors]\n#3b4252#bf616a\n[target_files]\n{}\n",
+                targets.join("\n")
+            ),
+        );
+        let mut options = RunOptions::new(config);
+        options.state_dir = Some(dir.path().join("state"));
+        options
+    }
+
+    #[test]
+    fn applying_a_plan_does_what_it_showed() {
+        let dir = TempDir::new("plan-apply");
+        let text = dir.write("a.conf", "bg=#2e3440 fg=#88c0d0 fg2=#88c0d0\n");
+        let css = dir.write("b.css", ":root { --fg: #88c0d0; }\n");
+        let missing = dir.path().join("missing.conf");
+        let mut targets = vec![text.as_path(), css.as_path(), missing.as_path()];
+        #[cfg(feature = "png")]
+        let png = dir.write(
+            "icon.png",
+            crate::png::build::PngBuilder::palette(
+                &[(0x2e, 0x34, 0x40), (0x88, 0xc0, 0xd0)],
+                &[vec![0, 1]],
+            )
+            .build(),
+        );
+        #[cfg(feature = "png")]
+        targets.push(png.as_path());
+        let options = options(&dir, &targets);
+        let config = std::fs::read(&options.config_path).unwrap();
+
+        let plan = plan(&options).unwrap();
+        assert_eq!(plan.exit_code(), 2);
+        assert_eq!(plan.failed.len(), 1);
+        assert_eq!(plan.failed[0].0, missing);
+        assert_eq!(plan.targets.len(), targets.len() - 1);
+        assert_eq!(plan.selection().to_string(), "#3b4252#bf616a");
+        let planned = &plan.targets[0];
+        assert_eq!((planned.handler, planned.changes), (FileFormat::Text, 3));
+        assert_eq!(
+            planned.colors,
+            [
+                (color("#2e3440"), color("#3b4252")),
+                (color("#88c0d0"), color("#bf616a"))
+            ]
+        );
+        assert_eq!(plan.targets[1].colors.len(), 1);
+        // Planning writes nothing
+        assert_eq!(
+            std::fs::read_to_string(&text).unwrap(),
+            "bg=#2e3440 fg=#88c0d0 fg2=#88c0d0\n"
+        );
+        assert_eq!(std::fs::read(&options.config_path).unwrap(), config);
+
+        let planned: Vec<(PathBuf, PathBuf, FileFormat, usize)> = plan
+            .targets
+            .iter()
+            .map(|t| (t.path.clone(), t.output.clone(), t.handler, t.changes))
+            .collect();
+        let summary = plan.apply().unwrap();
+        let applied: Vec<(PathBuf, PathBuf, FileFormat, usize)> = summary
+            .succeeded
+            .iter()
+            .map(|t| (t.path.clone(), t.output.clone(), t.handler, t.changes))
+            .collect();
+        assert_eq!(applied, planned);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(&text).unwrap(),
+            "bg=#3b4252 fg=#bf616a fg2=#bf616a\n"
+        );
+        let current = parse_config(&options.config_path)
+            .unwrap()
+            .get_current_colors();
+        assert_eq!(current, [color("#3b4252"), color("#bf616a")]);
+    }
+
+    #[test]
+    fn plans_are_refused_once_a_target_changed() {
+        let dir = TempDir::new("plan-changed");
+        let text = dir.write("a.conf", "bg=#2e3440\n");
+        let options = options(&dir, &[&text]);
+        let plan = plan(&options).unwrap();
+        dir.write("a.conf", "bg=#2e3440\nfg=#88c0d0\n");
+        let error = plan.apply().unwrap_err();
+        assert!(error.to_string().contains("changed since it was planned"));
+        assert_eq!(
+            std::fs::read_to_string(&text).unwrap(),
+            "bg=#2e3440\nfg=#88c0d0\n"
+        );
+    }
+
+    #[test]
+    fn saved_plans_are_applied_once_and_only_as_shown() {
+        let dir = TempDir::new("plan-saved");
+        let text = dir.write("a.conf", "bg=#2e3440\n");
+        let options = options(&dir, &[&text]);
+        assert!(Plan::saved(&options)
+            .map(|_| ())
+            .unwrap_err()
+            .to_string()
+            .contains("No plan to apply"));
+
+        plan(&options).unwrap().save().unwrap();
+        dir.write("a.conf", "bg=#2e3440 #2e3440\n");
+        assert!(Plan::saved(&options)
+            .map(|_| ())
+            .unwrap_err()
+            .to_string()
+            .contains("changed since the plan was shown"));
+
+        plan(&options).unwrap().save().unwrap();
+        let summary = Plan::saved(&options).unwrap().apply().unwrap();
+        assert_eq!(summary.succeeded[0].changes, 2);
+        assert!(Plan::saved(&options).is_err());
+    }
+}
@@ -1,6 +1,17 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+#[cfg(feature = "fs")]
 use std::path::Path;
 
-use crate::{errors::TranError, hex_to_bytes, ColorTransform};
+pub use crate::pixel::{
+    gradient_map, pixel_map, recolor_pixels, GradientStop, LuminanceBand, PixelMap,
+};
+use crate::{config::Color, errors::TranError, recolor::Cancellation, ColorTransform};
+
+#[cfg(any(test, feature = "testing"))]
+pub mod build;
+pub mod check;
+mod image;
 
 const PNG_FORMAT_IDENTIFIER: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
 const IHDR_COLOR_TYPE_OFFSET: usize = 9;
@@ -8,8 +19,12 @@ const IHDR: u32 = 0x49484452;
 const IEND: u32 = 0x49454E44;
 const PLTE: u32 = 0x504C5445;
 const IDAT: u32 = 0x49444154;
+const TRNS: u32 = 0x74524E53;
+const HIST: u32 = 0x68495354;
+const BKGD: u32 = 0x624B4744;
+pub const MAX_PALETTE_COLORS: usize = 256;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum PngColorType {
     Grayscale,      // 0
     Rgb,            // 2
@@ -28,7 +43,7 @@ impl TryFrom<u8> for PngColorType {
             3 => Ok(PngColorType::Palette),
             4 => Ok(PngColorType::GrayscaleAlpha),
             6 => Ok(PngColorType::Rgba),
-            _ => Err(TranError::FileReadError(format!(
+            _ => Err(TranError::PngFormatError(format!(
                 "PNG Color type is invalid {}",
                 value
             ))),
@@ -45,287 +60,1821 @@ impl TryFrom<&u8> for PngColorType {
 }
 
 struct Chunk<'a> {
-    length: u32,
     chunk_type: u32,
     chunk_data: Vec<&'a mut u8>,
     crc: [&'a mut u8; 4],
 }
 
-fn read_chunk<'a>(png: &'a mut std::slice::IterMut<u8>) -> Result<Chunk<'a>, TranError> {
-    let length = [
-        png.next().cloned(),
-        png.next().cloned(),
-        png.next().cloned(),
-        png.next().cloned(),
-    ]
-    .iter()
-    .filter_map(|byte| *byte)
-    .enumerate()
-    .map(|(index, byte)| (byte as u32) << (8 * (3 - index)))
-    .reduce(|acc, byte| acc | byte)
-    .ok_or_else(|| {
-        TranError::FileReadError("Something went wrong while reducing length".to_string())
-    })?;
-
-    let chunk_type = [
-        png.next().cloned(),
-        png.next().cloned(),
-        png.next().cloned(),
-        png.next().cloned(),
-    ]
-    .iter()
-    .filter_map(|byte| *byte)
-    .enumerate()
-    .map(|(index, byte)| (byte as u32) << (8 * (3 - index)))
-    .reduce(|acc, byte| acc | byte)
-    .ok_or_else(|| {
-        TranError::FileReadError("Something went wrong while reducing chunk type".to_string())
-    })?;
+fn read_u32(png: &mut std::slice::IterMut<u8>, what: &str) -> Result<u32, TranError> {
+    let mut value: u32 = 0;
+    for _ in 0..4 {
+        let byte = png.next().ok_or_else(|| {
+            TranError::PngFormatError(format!("Ran out of bytes while reading chunk {}", what))
+        })?;
+        value = (value << 8) | *byte as u32;
+    }
+    Ok(value)
+}
+
+/// Reads the next chunk of a png `file_len` bytes long, checking its CRC.
+fn read_chunk<'a>(
+    png: &'a mut std::slice::IterMut<u8>,
+    file_len: usize,
+) -> Result<Chunk<'a>, TranError> {
+    let offset = file_len - png.len();
+    let length = read_u32(png, "length")?;
+    let chunk_type = read_u32(png, "type")?;
+
+    // Checked before allocating so a bogus length can't request gigabytes
+    if length as usize > png.len() {
+        return Err(TranError::PngFormatError(format!(
+            "Chunk {:x} declares {} bytes but only {} remain",
+            chunk_type,
+            length,
+            png.len()
+        )));
+    }
 
     let mut chunk_data: Vec<&mut u8> = Vec::with_capacity(length as usize);
     for _ in 0..length {
         chunk_data.push(
             png.next()
-                .ok_or_else(|| TranError::FileReadError("Ran out of bytes".to_string()))?,
+                .ok_or_else(|| TranError::PngFormatError("Ran out of bytes".to_string()))?,
         );
     }
 
     let crc = [
         png.next()
-            .ok_or_else(|| TranError::FileReadError("Ran out of bytes".to_string()))?,
+            .ok_or_else(|| TranError::PngFormatError("Ran out of bytes".to_string()))?,
         png.next()
-            .ok_or_else(|| TranError::FileReadError("Ran out of bytes".to_string()))?,
+            .ok_or_else(|| TranError::PngFormatError("Ran out of bytes".to_string()))?,
         png.next()
-            .ok_or_else(|| TranError::FileReadError("Ran out of bytes".to_string()))?,
+            .ok_or_else(|| TranError::PngFormatError("Ran out of bytes".to_string()))?,
         png.next()
-            .ok_or_else(|| TranError::FileReadError("Ran out of bytes".to_string()))?,
+            .ok_or_else(|| TranError::PngFormatError("Ran out of bytes".to_string()))?,
     ];
 
+    let written = u32::from_be_bytes([*crc[0], *crc[1], *crc[2], *crc[3]]);
+    let computed = crc_update(0xffffffff, chunk_type.to_be_bytes());
+    let computed = crc_update(computed, chunk_data.iter().map(|b| **b)) ^ 0xffffffff;
+    check_crc(chunk_type, offset, written, computed)?;
+
     Ok(Chunk {
-        length,
         chunk_type,
         chunk_data,
         crc,
     })
 }
 
-struct GeneratedColorMap {
-    old_colors: (u8, u8, u8),
-    new_colors: (u8, u8, u8),
+/// The four letters of a chunk type, `?` for any byte that isn't one.
+pub(crate) fn chunk_name(chunk_type: u32) -> String {
+    chunk_type
+        .to_be_bytes()
+        .iter()
+        .map(|&b| match b.is_ascii_alphabetic() {
+            true => b as char,
+            false => '?',
+        })
+        .collect()
 }
 
-pub fn recolor_png<S: AsRef<Path>, T: AsRef<Path>>(source: S, target: T, transform: &ColorTransform) -> Result<(), TranError> {
-    if !source.as_ref().is_file() {
-        return Err(TranError::FileNotFoundError(
-            source.as_ref().to_string_lossy().to_string(),
-        ));
+/// Fails when the CRC `written` after the chunk at `offset` isn't the one `computed` from its
+/// type and data, as in a file cut short or damaged on the way.
+pub(crate) fn check_crc(
+    chunk_type: u32,
+    offset: usize,
+    written: u32,
+    computed: u32,
+) -> Result<(), TranError> {
+    match written == computed {
+        true => Ok(()),
+        false => Err(TranError::PngFormatError(format!(
+            "The {} chunk at byte {} is corrupt, its CRC is {:08x} instead of {:08x}",
+            chunk_name(chunk_type),
+            offset,
+            written,
+            computed
+        ))),
+    }
+}
+
+/// Checks a png the way `tran png-check` does, failing with every violation found.
+#[cfg(feature = "fs")]
+pub fn verify_png<P: AsRef<Path>>(path: P) -> Result<(), TranError> {
+    let path = path.as_ref();
+    let file = std::fs::read(path).map_err(|e| TranError::read(path, e))?;
+    let check = check::check_png(&file);
+    match check.is_ok() {
+        true => Ok(()),
+        false => Err(TranError::PngFormatError(
+            check
+                .violations
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+        .in_file(path)),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PngOptions {
+    /// Merge palette entries the transform made identical, see `compact_palette`.
+    pub compact_palette: bool,
+    /// Convert RGB images with few enough colors to palette images so the palette pass can
+    /// recolor them, see `palettize_bytes`.
+    pub palettize_rgb: bool,
+    /// Fail with a `PngFormatError` when the output of a png passing `check::check_png` doesn't
+    /// pass it, as a broken pass would leave it. Streaming keeps a copy of both sides for it.
+    pub check_output: bool,
+    /// Checked between the passes over the image and between chunks, nothing is written once
+    /// it is cancelled.
+    pub cancel: Option<Cancellation>,
+}
+
+/// Returns the number of palette entries that changed.
+#[cfg(feature = "fs")]
+pub fn recolor_png<S: AsRef<Path>, T: AsRef<Path>>(
+    source: S,
+    target: T,
+    transform: &ColorTransform,
+) -> Result<usize, TranError> {
+    recolor_png_with(source, target, transform, &PngOptions::default())
+}
+
+#[cfg(feature = "fs")]
+pub fn recolor_png_with<S: AsRef<Path>, T: AsRef<Path>>(
+    source: S,
+    target: T,
+    transform: &ColorTransform,
+    options: &PngOptions,
+) -> Result<usize, TranError> {
+    let source = source.as_ref();
+    if !source.is_file() {
+        return Err(TranError::FileNotFoundError(source.to_path_buf()));
+    }
+
+    recolor_png_file(source, target.as_ref(), transform, options).map_err(|e| e.in_file(source))
+}
+
+#[cfg(feature = "fs")]
+fn recolor_png_file(
+    source: &Path,
+    target: &Path,
+    transform: &ColorTransform,
+    options: &PngOptions,
+) -> Result<usize, TranError> {
+    // Streaming into the file being read would truncate it first
+    let same_file = source == target
+        || std::fs::canonicalize(target).ok()
+            == Some(std::fs::canonicalize(source).map_err(|e| TranError::read(source, e))?);
+    if !options.palettize_rgb && !options.compact_palette && !same_file {
+        let reader = std::fs::File::open(source).map_err(|e| TranError::read(source, e))?;
+        let writer = std::fs::File::create(target).map_err(|e| TranError::write(target, e))?;
+        let result = recolor_png_stream(
+            std::io::BufReader::new(reader),
+            std::io::BufWriter::new(writer),
+            transform,
+            options,
+        );
+        if result.is_err() {
+            let _ = std::fs::remove_file(target);
+        }
+        return result;
+    }
+
+    let check = || options.cancel.as_ref().map_or(Ok(()), Cancellation::check);
+    let mut file = std::fs::read(source).map_err(|e| TranError::read(source, e))?;
+    let sound = options.check_output && check::check_png(&file).is_ok();
+    if options.palettize_rgb {
+        if let Some(palettized) = palettize_bytes(&file, MAX_PALETTE_COLORS)? {
+            file = palettized;
+        }
+        check()?;
+    }
+    let changes = recolor_png_bytes(&mut file, transform)?;
+    let compacted = if options.compact_palette {
+        check()?;
+        compact_palette(&file)?
+    } else {
+        None
+    };
+    check()?;
+
+    check_output(sound, compacted.as_ref().unwrap_or(&file))?;
+    if let Some(compacted) = compacted {
+        std::fs::write(target, compacted).map_err(|e| TranError::write(target, e))?;
+    } else if changes > 0 || source != target {
+        std::fs::write(target, file).map_err(|e| TranError::write(target, e))?;
+    }
+
+    Ok(changes)
+}
+
+/// Rewrites every palette index of `passes` through `map`.
+fn remap_indices(ihdr: &image::Ihdr, passes: &mut [image::Pass], map: &[u8]) {
+    let depth = ihdr.bit_depth as usize;
+    let mask = ((1u16 << depth) - 1) as u8;
+    for pass in passes.iter_mut().filter(|p| p.width > 0) {
+        let row_bytes = ihdr.row_bytes(pass.width);
+        for row in pass.data.chunks_mut(row_bytes) {
+            for x in 0..pass.width as usize {
+                let bit = x * depth;
+                let shift = 8 - depth - bit % 8;
+                let byte = &mut row[bit / 8];
+                let index = (*byte >> shift) & mask;
+                let new = map.get(index as usize).copied().unwrap_or(index);
+                *byte = (*byte & !(mask << shift)) | (new << shift);
+            }
+        }
+    }
+}
+
+/// The palette entries `recolor_png_bytes` would recolor, pure black and white are left out.
+/// For an RGB image the colors of its pixels, most common first.
+pub fn palette_colors(file: &[u8]) -> Result<Vec<Color>, TranError> {
+    let chunks = image::read_chunks(file)?;
+    let ihdr = image::Ihdr::parse(&chunks[0].data)?;
+    match PngColorType::try_from(ihdr.color_type)? {
+        PngColorType::Grayscale | PngColorType::GrayscaleAlpha => return Ok(Vec::new()),
+        PngColorType::Rgb | PngColorType::Rgba => {
+            return Ok(color_counts(file)?
+                .into_iter()
+                .map(|(color, _)| color)
+                .filter(|c| *c != Color::black() && *c != Color::white())
+                .collect())
+        }
+        PngColorType::Palette => {}
+    }
+    Ok(chunks
+        .iter()
+        .filter(|c| c.chunk_type == PLTE)
+        .flat_map(|c| c.data.chunks_exact(3))
+        .map(|rgb| Color::from_bytes(rgb[0], rgb[1], rgb[2]))
+        .filter(|c| *c != Color::black() && *c != Color::white())
+        .collect())
+}
+
+/// Every palette entry of the png with the offset of its bytes in `file`, black and white
+/// included. Empty for images without a palette.
+pub fn palette_entries(file: &[u8]) -> Result<Vec<(usize, Color)>, TranError> {
+    let mut pos = PNG_FORMAT_IDENTIFIER.len();
+    let mut entries = Vec::new();
+    for chunk in image::read_chunks(file)? {
+        let start = pos + 8;
+        if chunk.chunk_type == PLTE {
+            for (i, rgb) in chunk.data.chunks_exact(3).enumerate() {
+                entries.push((start + 3 * i, Color::from_bytes(rgb[0], rgb[1], rgb[2])));
+            }
+        }
+        pos = start + chunk.data.len() + 4;
+    }
+    Ok(entries)
+}
+
+/// How many pixels of the image have each color, most common first and ties by color, fully
+/// transparent pixels left out. Decodes the image data, so any color type or bit depth works.
+pub fn color_counts(file: &[u8]) -> Result<Vec<(Color, usize)>, TranError> {
+    let chunks = image::read_chunks(file)?;
+    let ihdr = image::Ihdr::parse(&chunks[0].data)?;
+    let color_type = PngColorType::try_from(ihdr.color_type)?;
+    let depth = ihdr.bit_depth as usize;
+    let valid = match color_type {
+        PngColorType::Palette => matches!(depth, 1 | 2 | 4 | 8),
+        PngColorType::Grayscale => matches!(depth, 1 | 2 | 4 | 8 | 16),
+        _ => matches!(depth, 8 | 16),
+    };
+    if !valid {
+        return Err(TranError::PngFormatError(format!(
+            "Invalid bit depth {} for color type {}",
+            depth, ihdr.color_type
+        )));
+    }
+
+    let palette: Vec<Color> = chunks
+        .iter()
+        .filter(|c| c.chunk_type == PLTE)
+        .flat_map(|c| c.data.chunks_exact(3))
+        .map(|rgb| Color::from_bytes(rgb[0], rgb[1], rgb[2]))
+        .collect();
+    let palette_alpha: Vec<u8> = chunks
+        .iter()
+        .find(|c| c.chunk_type == TRNS)
+        .filter(|_| color_type == PngColorType::Palette)
+        .map_or(Vec::new(), |c| c.data.clone());
+    // The single transparent color of an 8 bit RGB image, the way `palettize_bytes` reads it
+    let transparent = chunks
+        .iter()
+        .find(|c| c.chunk_type == TRNS && c.data.len() == 6)
+        .filter(|_| color_type == PngColorType::Rgb && depth == 8)
+        .map(|c| Color::from_bytes(c.data[1], c.data[3], c.data[5]));
+
+    let sample = |row: &[u8], i: usize| -> u8 {
+        match depth {
+            8 => row[i],
+            16 => row[2 * i],
+            _ => {
+                let bit = i * depth;
+                let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1u16 << depth) - 1) as u8;
+                match color_type {
+                    PngColorType::Palette => value,
+                    _ => (value as u16 * 255 / ((1u16 << depth) - 1)) as u8,
+                }
+            }
+        }
+    };
+
+    let channels = ihdr.channels();
+    let mut counts: HashMap<Color, usize> = HashMap::new();
+    for pass in image::decode(&ihdr, &chunks)?
+        .iter()
+        .filter(|p| p.width > 0)
+    {
+        for row in pass.data.chunks(ihdr.row_bytes(pass.width)) {
+            for x in 0..pass.width as usize {
+                let s = |channel: usize| sample(row, x * channels + channel);
+                let color = match color_type {
+                    PngColorType::Palette => {
+                        let index = s(0) as usize;
+                        if palette_alpha.get(index) == Some(&0) {
+                            continue;
+                        }
+                        match palette.get(index) {
+                            Some(color) => *color,
+                            None => continue,
+                        }
+                    }
+                    PngColorType::Grayscale => Color::from_bytes(s(0), s(0), s(0)),
+                    PngColorType::GrayscaleAlpha if s(1) == 0 => continue,
+                    PngColorType::GrayscaleAlpha => Color::from_bytes(s(0), s(0), s(0)),
+                    PngColorType::Rgba if s(3) == 0 => continue,
+                    PngColorType::Rgb | PngColorType::Rgba => Color::from_bytes(s(0), s(1), s(2)),
+                };
+                if Some(color) == transparent {
+                    continue;
+                }
+                *counts.entry(color).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut counts: Vec<(Color, usize)> = counts.into_iter().collect();
+    counts.sort_by_key(|&(color, count)| (std::cmp::Reverse(count), color.bytes()));
+    Ok(counts)
+}
+
+/// Merges identical palette entries, alpha included, points the image data at the entries
+/// that are kept and shrinks PLTE, tRNS and hIST to match. Returns `None` when the palette
+/// has no duplicates or the image is not palette based.
+pub fn compact_palette(file: &[u8]) -> Result<Option<Vec<u8>>, TranError> {
+    let mut chunks = image::read_chunks(file)?;
+    let ihdr = image::Ihdr::parse(&chunks[0].data)?;
+    if ihdr.color_type != 3 {
+        return Ok(None);
+    }
+    if !matches!(ihdr.bit_depth, 1 | 2 | 4 | 8) {
+        return Err(TranError::PngFormatError(format!(
+            "Palette images can't have a bit depth of {}",
+            ihdr.bit_depth
+        )));
+    }
+    let Some(plte) = chunks.iter().position(|c| c.chunk_type == PLTE) else {
+        return Ok(None);
+    };
+    let trns = chunks.iter().position(|c| c.chunk_type == TRNS);
+    let hist = chunks.iter().position(|c| c.chunk_type == HIST);
+
+    let entries: Vec<(u8, u8, u8, u8)> = chunks[plte]
+        .data
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(i, rgb)| {
+            let alpha = trns
+                .and_then(|t| chunks[t].data.get(i).copied())
+                .unwrap_or(255);
+            (rgb[0], rgb[1], rgb[2], alpha)
+        })
+        .collect();
+
+    let mut kept: Vec<(u8, u8, u8, u8)> = Vec::with_capacity(entries.len());
+    let mut map = Vec::with_capacity(entries.len());
+    for entry in entries.iter() {
+        match kept.iter().position(|k| k == entry) {
+            Some(index) => map.push(index as u8),
+            None => {
+                map.push(kept.len() as u8);
+                kept.push(*entry);
+            }
+        }
+    }
+    if kept.len() == entries.len() {
+        return Ok(None);
+    }
+
+    let mut passes = image::decode(&ihdr, &chunks)?;
+    remap_indices(&ihdr, &mut passes, &map);
+    image::replace_image_data(&mut chunks, image::encode(&ihdr, &passes));
+
+    chunks[plte].data = kept.iter().flat_map(|&(r, g, b, _)| [r, g, b]).collect();
+    if let Some(t) = trns {
+        let mut alpha: Vec<u8> = kept.iter().map(|k| k.3).collect();
+        while alpha.last() == Some(&255) {
+            alpha.pop();
+        }
+        chunks[t].data = alpha;
     }
+    if let Some(h) = hist {
+        let mut counts = vec![0u16; kept.len()];
+        for (i, pair) in chunks[h].data.chunks_exact(2).enumerate() {
+            if let Some(&new) = map.get(i) {
+                let count = &mut counts[new as usize];
+                *count = count.saturating_add(u16::from_be_bytes([pair[0], pair[1]]));
+            }
+        }
+        chunks[h].data = counts.iter().flat_map(|c| c.to_be_bytes()).collect();
+    }
+    // An empty tRNS is not allowed
+    chunks.retain(|c| c.chunk_type != TRNS || !c.data.is_empty());
 
-    let mut file = std::fs::read(&source)?;
+    Ok(Some(image::write_chunks(&chunks, image::trailer(file))))
+}
+
+/// Writes `source` to `target` as a palette image, returning the number of palette entries.
+#[cfg(feature = "fs")]
+pub fn palettize<S: AsRef<Path>, T: AsRef<Path>>(
+    source: S,
+    target: T,
+    max_colors: usize,
+) -> Result<usize, TranError> {
+    let (source, target) = (source.as_ref(), target.as_ref());
+    let file = std::fs::read(source).map_err(|e| TranError::read(source, e))?;
+    let palettized = palettize_bytes(&file, max_colors)
+        .and_then(|p| {
+            p.ok_or_else(|| {
+                TranError::PngFormatError("Only 8 bit RGB images can be palettized".to_string())
+            })
+        })
+        .map_err(|e| e.in_file(source))?;
+    let entries = image::read_chunks(&palettized)?
+        .iter()
+        .find(|c| c.chunk_type == PLTE)
+        .map_or(0, |c| c.data.len() / 3);
+    std::fs::write(target, palettized).map_err(|e| TranError::write(target, e))?;
+    Ok(entries)
+}
+
+/// Converts an 8 bit RGB png to a palette png with one entry per distinct color, failing when
+/// there are more than `max_colors` of them. Returns `None` for any other kind of image.
+pub fn palettize_bytes(file: &[u8], max_colors: usize) -> Result<Option<Vec<u8>>, TranError> {
+    let mut chunks = image::read_chunks(file)?;
+    let ihdr = image::Ihdr::parse(&chunks[0].data)?;
+    if ihdr.color_type != 2 || ihdr.bit_depth != 8 {
+        return Ok(None);
+    }
+    let max_colors = max_colors.min(MAX_PALETTE_COLORS);
+
+    // The single transparent color of an RGB image, stored as 16 bit samples
+    let transparent = chunks
+        .iter()
+        .find(|c| c.chunk_type == TRNS && c.data.len() == 6)
+        .map(|c| (c.data[1], c.data[3], c.data[5]));
+
+    let passes = image::decode(&ihdr, &chunks)?;
+    let mut palette: HashMap<(u8, u8, u8), u8> = HashMap::new();
+    let mut colors: Vec<(u8, u8, u8)> = Vec::new();
+    // Putting the transparent color first keeps tRNS a single byte
+    if let Some(color) = transparent {
+        palette.insert(color, 0);
+        colors.push(color);
+    }
+    let mut distinct = colors.len();
+    for pass in passes.iter() {
+        for pixel in pass.data.chunks_exact(3) {
+            let color = (pixel[0], pixel[1], pixel[2]);
+            if palette.contains_key(&color) {
+                continue;
+            }
+            distinct += 1;
+            if colors.len() < max_colors {
+                palette.insert(color, colors.len() as u8);
+                colors.push(color);
+            } else {
+                palette.insert(color, 0);
+            }
+        }
+    }
+    if distinct > max_colors {
+        return Err(TranError::PngFormatError(format!(
+            "Image has {} distinct colors, more than the {} a palette can hold",
+            distinct, max_colors
+        )));
+    }
+
+    let indexed = image::Ihdr {
+        color_type: 3,
+        ..ihdr
+    };
+    let passes: Vec<image::Pass> = passes
+        .iter()
+        .map(|pass| image::Pass {
+            width: pass.width,
+            height: pass.height,
+            data: pass
+                .data
+                .chunks_exact(3)
+                .map(|p| palette[&(p[0], p[1], p[2])])
+                .collect(),
+        })
+        .collect();
+
+    chunks[0].data[IHDR_COLOR_TYPE_OFFSET] = 3;
+    // Background colors are stored differently for palette images
+    chunks.retain(|c| c.chunk_type != TRNS && c.chunk_type != BKGD);
+    image::replace_image_data(&mut chunks, image::encode(&indexed, &passes));
+    let first_idat = chunks
+        .iter()
+        .position(|c| c.chunk_type == IDAT)
+        .unwrap_or(1);
+    chunks.insert(
+        first_idat,
+        image::OwnedChunk {
+            chunk_type: PLTE,
+            data: colors.iter().flat_map(|&(r, g, b)| [r, g, b]).collect(),
+        },
+    );
+    if transparent.is_some() {
+        chunks.insert(
+            first_idat + 1,
+            image::OwnedChunk {
+                chunk_type: TRNS,
+                data: vec![0],
+            },
+        );
+    }
+
+    Ok(Some(image::write_chunks(&chunks, image::trailer(file))))
+}
+
+/// Recolors an in-memory png, returning the number of palette entries that changed. Palette
+/// images are edited in place, RGB and RGBA ones are re-encoded and count the distinct colors
+/// that changed.
+pub fn recolor_png_bytes(
+    file: &mut Vec<u8>,
+    transform: &ColorTransform,
+) -> Result<usize, TranError> {
+    let changes = match color_type(file) {
+        Some(PngColorType::Rgb | PngColorType::Rgba) => {
+            let (recolored, changes) = recolor_truecolor(file, transform)?;
+            if changes > 0 {
+                *file = recolored;
+            }
+            changes
+        }
+        _ => recolor_palette_in_place(file, transform)?,
+    };
+    Ok(changes)
+}
+
+/// Fails when `output` doesn't pass `check::check_png` though the png it was made from was
+/// `sound`.
+fn check_output(sound: bool, output: &[u8]) -> Result<(), TranError> {
+    let check = check::check_png(output);
+    match !sound || check.is_ok() {
+        true => Ok(()),
+        false => Err(TranError::PngFormatError(format!(
+            "Recoloring broke the png: {}",
+            check
+                .violations
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+fn recolor_palette_in_place(
+    file: &mut [u8],
+    transform: &ColorTransform,
+) -> Result<usize, TranError> {
+    let file_len = file.len();
     let mut png = file.iter_mut();
 
     for png_format_identifier_byte in PNG_FORMAT_IDENTIFIER {
         if let Some(read_byte) = png.next() {
             if *read_byte != png_format_identifier_byte {
-                return Err(TranError::FileReadError(format!(
-                    "{} is not a png as {:x} != {:x}",
-                    source.as_ref().to_string_lossy(),
-                    *read_byte,
-                    png_format_identifier_byte
+                return Err(TranError::PngFormatError(format!(
+                    "Not a png as {:x} != {:x}",
+                    *read_byte, png_format_identifier_byte
                 )));
             }
         } else {
-            return Err(TranError::FileReadError(format!(
-                "{} is not a png as png next failed",
-                source.as_ref().to_string_lossy()
-            )));
+            return Err(TranError::PngFormatError(
+                "Not a png as the file ended before the signature".to_string(),
+            ));
         }
     }
 
-    let ihdr = read_chunk(&mut png)?;
+    let ihdr = read_chunk(&mut png, file_len)?;
     if ihdr.chunk_type != IHDR {
-        return Err(TranError::FileReadError(format!(
-            "{} is not a png as it does not contain IHDR chunk {:x} != {:x}",
-            source.as_ref().to_string_lossy(),
-            ihdr.chunk_type,
-            IHDR
+        return Err(TranError::PngFormatError(format!(
+            "Not a png as it does not start with an IHDR chunk {:x} != {:x}",
+            ihdr.chunk_type, IHDR
         )));
     }
 
-    let color_type: PngColorType = (**ihdr
-        .chunk_data
-        .get(IHDR_COLOR_TYPE_OFFSET)
-        .ok_or_else(|| TranError::FileReadError("No color type".to_string()))?)
-    .try_into()?;
+    let ihdr_data: Vec<u8> = ihdr.chunk_data.iter().map(|b| **b).collect();
+    let color_type: PngColorType = image::Ihdr::parse(&ihdr_data)?.color_type.try_into()?;
 
     if let PngColorType::Grayscale | PngColorType::GrayscaleAlpha = color_type {
-        return Ok(());
+        return Ok(0);
     }
 
     if let PngColorType::Rgb | PngColorType::Rgba = color_type {
-        return Err(TranError::FileReadError(
-            "Can't decompress png of type RGB".to_string(),
+        return Err(TranError::PngFormatError(
+            "RGB pngs are re-encoded, not recolored in place".to_string(),
         ));
     }
 
+    let mut changes = 0;
     match color_type {
-        PngColorType::Palette => {
-            loop {
-                let mut chunk = read_chunk(&mut png)?;
-                if chunk.chunk_type == PLTE {
-                    let mut pixels = chunk.chunk_data.iter_mut();
-                    let mut colors = Vec::with_capacity((chunk.length / 3) as usize);
-                    for _ in 0..chunk.length / 3 {
-                        let red = pixels.next().ok_or_else(|| {
-                            TranError::FileReadError("Could not read red pixel".to_string())
-                        })?;
-                        let green = pixels.next().ok_or_else(|| {
-                            TranError::FileReadError("Could not read green pixel".to_string())
-                        })?;
-                        let blue = pixels.next().ok_or_else(|| {
-                            TranError::FileReadError("Could not read blue pixel".to_string())
-                        })?;
-
-                        if (**red == 0 && **green == 0 && **blue == 0)
-                            || (**red == 255 && **green == 255 && **blue == 255)
-                        {
-                            continue;
-                        }
+        PngColorType::Palette => loop {
+            let mut chunk = read_chunk(&mut png, file_len)?;
+            if chunk.chunk_type == PLTE {
+                let mut data: Vec<u8> = chunk.chunk_data.iter().map(|b| **b).collect();
+                changes += recolor_palette(&mut data, transform)?;
+                for (byte, new) in chunk.chunk_data.iter_mut().zip(&data) {
+                    **byte = *new;
+                }
+                let new_crc = chunk_crc(chunk.chunk_type, &data);
+                for (byte, new) in chunk.crc.iter_mut().zip(new_crc.to_be_bytes()) {
+                    **byte = new;
+                }
+            }
+            if chunk.chunk_type == IEND {
+                break;
+            }
+        },
+        _ => unreachable!(),
+    }
 
-                        colors.push((red, green, blue));
-                    }
+    Ok(changes)
+}
 
-                    match transform {
-                        ColorTransform::Map(map) => {
-                            for trans in map.iter() {
-                                for color in colors.iter_mut() {
-                                    if **color.0 == trans.current_color_bytes()?.0
-                                        && **color.1 == trans.current_color_bytes()?.1
-                                        && **color.2 == trans.current_color_bytes()?.2
-                                    {
-                                        **color.0 = trans.new_color_bytes()?.0;
-                                        **color.1 = trans.new_color_bytes()?.1;
-                                        **color.2 = trans.new_color_bytes()?.2;
-                                    }
-                                }
-                            }
-                        }
-                        ColorTransform::Gradient {
-                            primary,
-                            background: _,
-                        } => {
-                            colors.sort_unstable_by(|a, b| {
-                                (**b.0 as u64 + **b.1 as u64 + **b.2 as u64)
-                                    .cmp(&(**a.0 as u64 + **a.1 as u64 + **a.2 as u64))
-                            });
-                            let mut map: Vec<GeneratedColorMap> = Vec::with_capacity(colors.len());
-                            let first_color = colors.get(0).ok_or_else(|| {
-                                TranError::PngFormatError("No colors".to_string())
-                            })?;
-                            map.push(GeneratedColorMap {
-                                new_colors: hex_to_bytes(primary)?,
-                                old_colors: (**first_color.0, **first_color.1, **first_color.2),
-                            });
-
-                            for i in 1..colors.len() {
-                                let previous_new = map
-                                    .get(i - 1)
-                                    .ok_or_else(|| {
-                                        TranError::PngFormatError("No colors".to_string())
-                                    })?
-                                    .new_colors;
-                                let previous_old = colors.get(i - 1).ok_or_else(|| {
-                                    TranError::PngFormatError("No colors".to_string())
-                                })?;
-                                let next_old = colors.get(i).ok_or_else(|| {
-                                    TranError::PngFormatError("No colors".to_string())
-                                })?;
-
-                                let red_diff = (**next_old.0 as f64) / (**previous_old.0 as f64);
-                                let grenn_diff = (**next_old.1 as f64) / (**previous_old.1 as f64);
-                                let blue_diff = (**next_old.2 as f64) / (**previous_old.2 as f64);
-
-                                let next_new = (
-                                    ((previous_new.0 as f64) * red_diff) as u8,
-                                    ((previous_new.1 as f64) * grenn_diff) as u8,
-                                    ((previous_new.2 as f64) * blue_diff) as u8,
-                                );
-                                map.push(GeneratedColorMap {
-                                    new_colors: next_new,
-                                    old_colors: (**next_old.0, **next_old.1, **next_old.2),
-                                });
-                            }
-
-                            for trans in map.iter() {
-                                for color in colors.iter_mut() {
-                                    if **color.0 == trans.old_colors.0
-                                        && **color.1 == trans.old_colors.1
-                                        && **color.2 == trans.old_colors.2
-                                    {
-                                        **color.0 = trans.new_colors.0;
-                                        **color.1 = trans.new_colors.1;
-                                        **color.2 = trans.new_colors.2;
-                                    }
-                                }
-                            }
+/// The color type of `file`, when it starts with a png signature and an IHDR chunk holding one.
+fn color_type(file: &[u8]) -> Option<PngColorType> {
+    let header = file.get(..PNG_FORMAT_IDENTIFIER.len() + 8)?;
+    let ihdr = u32::from_be_bytes(header[12..16].try_into().ok()?);
+    if header[..8] != PNG_FORMAT_IDENTIFIER || ihdr != IHDR {
+        return None;
+    }
+    file.get(header.len() + IHDR_COLOR_TYPE_OFFSET)?
+        .try_into()
+        .ok()
+}
+
+/// Recolors the pixels of an 8 or 16 bit RGB or RGBA png, leaving pure black and white alone
+/// as the palette pass does, and the color tRNS makes transparent. 16 bit samples are looked
+/// up by their high byte, as `color_counts` reads them, and recolored ones are written back as
+/// the 16 bit value of the new color. Alpha is carried through, and fully transparent pixels
+/// play no part in the lookup so a gradient isn't ramped across colors nobody sees. Returns the
+/// png re-encoded, in its bit depth and color type, and the number of distinct colors that
+/// changed.
+fn recolor_truecolor(
+    file: &[u8],
+    transform: &ColorTransform,
+) -> Result<(Vec<u8>, usize), TranError> {
+    let mut chunks = image::read_chunks(file)?;
+    let ihdr = image::Ihdr::parse(&chunks[0].data)?;
+    let depth = ihdr.bit_depth as usize / 8;
+    // The samples of the single transparent color, tRNS holding them at 16 bits
+    let transparent: Option<Vec<u8>> = chunks
+        .iter()
+        .find(|c| c.chunk_type == TRNS && c.data.len() == 6)
+        .map(|c| {
+            c.data
+                .iter()
+                .skip(2 - depth)
+                .step_by(2 / depth)
+                .copied()
+                .collect()
+        });
+
+    let channels = ihdr.channels();
+    let color = |pixel: &[u8]| (pixel[0], pixel[depth], pixel[2 * depth]);
+    let mut passes = image::decode(&ihdr, &chunks)?;
+    let mut seen = std::collections::HashSet::new();
+    let mut before = Vec::new();
+    for pass in passes.iter() {
+        for pixel in pass.data.chunks_exact(channels * depth) {
+            if channels == 4 && pixel[3 * depth..].iter().all(|&alpha| alpha == 0) {
+                continue;
+            }
+            let color = color(pixel);
+            if color != (0, 0, 0)
+                && color != (255, 255, 255)
+                && transparent.as_deref() != Some(&pixel[..3 * depth])
+                && seen.insert(color)
+            {
+                before.push(color);
+            }
+        }
+    }
+    let mut lookup = palette_lookup(transform, &before)?;
+    lookup.retain(|old, new| old != new && seen.contains(old));
+    if lookup.is_empty() {
+        return Ok((file.to_vec(), 0));
+    }
+
+    for pass in passes.iter_mut() {
+        match depth {
+            1 => {
+                recolor_pixels(&mut pass.data, channels, &lookup);
+            }
+            _ => {
+                for pixel in pass.data.chunks_exact_mut(channels * depth) {
+                    if transparent.as_deref() == Some(&pixel[..3 * depth]) {
+                        continue;
+                    }
+                    if let Some(&(red, green, blue)) = lookup.get(&color(pixel)) {
+                        for (sample, value) in pixel.chunks_exact_mut(depth).zip([red, green, blue])
+                        {
+                            sample.fill(value);
                         }
                     }
-
-                    // Recalculate CRC
-                    let mut crc_data: Vec<&mut u8> = Vec::with_capacity(4 + chunk.chunk_data.len());
-                    let mut chunk_type = (
-                        (((chunk.chunk_type & 0xFF000000) >> (3 * 8)) as u8),
-                        (((chunk.chunk_type & 0x00FF0000) >> (2 * 8)) as u8),
-                        (((chunk.chunk_type & 0x0000FF00) >> 8) as u8),
-                        ((chunk.chunk_type & 0x000000FF) as u8),
-                    );
-                    crc_data.push(&mut chunk_type.0);
-                    crc_data.push(&mut chunk_type.1);
-                    crc_data.push(&mut chunk_type.2);
-                    crc_data.push(&mut chunk_type.3);
-
-                    crc_data.extend(chunk.chunk_data);
-
-                    let new_crc = crc(crc_data.as_slice());
-                    *chunk.crc[0] = ((new_crc & (0xFF000000)) >> (3 * 8)) as u8;
-                    *chunk.crc[1] = ((new_crc & (0x00FF0000)) >> (2 * 8)) as u8;
-                    *chunk.crc[2] = ((new_crc & (0x0000FF00)) >> 8) as u8;
-                    *chunk.crc[3] = (new_crc & (0x000000FF)) as u8;
                 }
-                if chunk.chunk_type == IEND {
-                    break;
+            }
+        }
+    }
+    image::replace_image_data(&mut chunks, image::encode(&ihdr, &passes));
+    Ok((
+        image::write_chunks(&chunks, image::trailer(file)),
+        lookup.len(),
+    ))
+}
+
+/// Recolors the entries of PLTE chunk data, leaving pure black and white alone. Returns the
+/// number of entries that changed, none for a palette of only black and white.
+fn recolor_palette(data: &mut [u8], transform: &ColorTransform) -> Result<usize, TranError> {
+    let mut entries: Vec<&mut [u8]> = data
+        .chunks_exact_mut(3)
+        .filter(|entry| **entry != [0, 0, 0] && **entry != [255, 255, 255])
+        .collect();
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    let before: Vec<(u8, u8, u8)> = entries.iter().map(|e| (e[0], e[1], e[2])).collect();
+
+    let lookup = palette_lookup(transform, &before)?;
+    let mut changes = 0;
+    for (entry, old) in entries.iter_mut().zip(before) {
+        if let Some(&(r, g, b)) = lookup.get(&old) {
+            if (r, g, b) != old {
+                changes += 1;
+            }
+            entry.copy_from_slice(&[r, g, b]);
+        }
+    }
+    Ok(changes)
+}
+
+/// What `transform` turns each of the palette entries `before` into. A chain follows every entry
+/// through its stages, each looking up what the stages before it left.
+fn palette_lookup(
+    transform: &ColorTransform,
+    before: &[(u8, u8, u8)],
+) -> Result<PixelMap, TranError> {
+    Ok(match transform {
+        ColorTransform::Map(map) => pixel_map(map)?,
+        ColorTransform::Simulate(deficiency) => before
+            .iter()
+            .map(|&(r, g, b)| {
+                (
+                    (r, g, b),
+                    deficiency.simulate(&Color::from_bytes(r, g, b)).bytes(),
+                )
+            })
+            .collect(),
+        ColorTransform::Gradient {
+            primary,
+            space,
+            luminance,
+            stops,
+            ..
+        } => gradient_map(before, primary, *space, *luminance, stops)?,
+        ColorTransform::Chain(stages) => {
+            let mut after = before.to_vec();
+            for stage in stages.iter() {
+                let lookup = palette_lookup(stage, &after)?;
+                for color in after.iter_mut() {
+                    if let Some(&new) = lookup.get(color) {
+                        *color = new;
+                    }
                 }
             }
+            before.iter().copied().zip(after).collect()
         }
-        PngColorType::Rgb | PngColorType::Rgba => {
-            todo!()
+    })
+}
+
+fn read_error(e: std::io::Error, what: &str) -> TranError {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        TranError::PngFormatError(format!("Ran out of bytes while reading {}", what))
+    } else {
+        TranError::FileReadError {
+            path: None,
+            source: e,
         }
-        _ => unreachable!(),
     }
+}
 
-    std::fs::write(&target, file)?;
+fn write_error(e: std::io::Error) -> TranError {
+    TranError::FileWriteError {
+        path: None,
+        source: e,
+    }
+}
 
-    Ok(())
+/// Reads the data of a chunk of `length` bytes, growing the buffer only as bytes arrive so a
+/// bogus length can't request gigabytes.
+fn read_chunk_data<R: Read>(reader: &mut R, length: u32) -> Result<Vec<u8>, TranError> {
+    let mut data = Vec::new();
+    reader
+        .take(length as u64)
+        .read_to_end(&mut data)
+        .map_err(|e| read_error(e, "chunk data"))?;
+    if data.len() != length as usize {
+        return Err(TranError::PngFormatError(
+            "Ran out of bytes while reading chunk data".to_string(),
+        ));
+    }
+    Ok(data)
 }
 
-fn crc(buf: &[&mut u8]) -> u32 {
-    let mut crc_table: [u32; 256] = [0; 256];
+/// Like `recolor_png_bytes`, but streams the png from `reader` to `writer`. Chunks are copied
+/// through as they are read, only IHDR and PLTE are held in memory, except for RGB and RGBA
+/// images whose image data is re-encoded. Returns the number of palette entries that changed.
+pub fn recolor_png_io<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    transform: &ColorTransform,
+) -> Result<usize, TranError> {
+    recolor_png_stream(reader, writer, transform, &PngOptions::default())
+}
+
+/// `recolor_png_io`, checking the cancellation of `options` before every chunk. With
+/// `check_output` a copy of what is read and written is kept to check the output.
+fn recolor_png_stream<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    transform: &ColorTransform,
+    options: &PngOptions,
+) -> Result<usize, TranError> {
+    let cancel = options.cancel.as_ref();
+    if !options.check_output {
+        return stream_chunks(reader, writer, transform, cancel);
+    }
+    let (mut input, mut output) = (Vec::new(), Vec::new());
+    let changes = stream_chunks(
+        Tee {
+            inner: reader,
+            copy: &mut input,
+        },
+        Tee {
+            inner: writer,
+            copy: &mut output,
+        },
+        transform,
+        cancel,
+    )?;
+    check_output(check::check_png(&input).is_ok(), &output)?;
+    Ok(changes)
+}
+
+/// A reader or writer keeping a copy of the bytes going through it.
+struct Tee<'a, T> {
+    inner: T,
+    copy: &'a mut Vec<u8>,
+}
+
+impl<R: Read> Read for Tee<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Tee<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.copy.extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
 
-    for n in 0..256 {
-        let mut c: u32 = n;
-        for _ in 0..8 {
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn stream_chunks<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    transform: &ColorTransform,
+    cancel: Option<&Cancellation>,
+) -> Result<usize, TranError> {
+    let mut signature = [0; 8];
+    reader
+        .read_exact(&mut signature)
+        .map_err(|e| read_error(e, "the signature"))?;
+    if signature != PNG_FORMAT_IDENTIFIER {
+        return Err(TranError::PngFormatError(
+            "Not a png as the signature doesn't match".to_string(),
+        ));
+    }
+    writer.write_all(&signature).map_err(write_error)?;
+
+    let mut color_type = None;
+    let mut changes = 0;
+    let mut offset = PNG_FORMAT_IDENTIFIER.len();
+    loop {
+        cancel.map_or(Ok(()), Cancellation::check)?;
+        let mut header = [0; 8];
+        reader
+            .read_exact(&mut header)
+            .map_err(|e| read_error(e, "chunk length"))?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk_type = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+
+        let computed = if color_type.is_none() {
+            if chunk_type != IHDR {
+                return Err(TranError::PngFormatError(format!(
+                    "Not a png as it does not start with an IHDR chunk {:x} != {:x}",
+                    chunk_type, IHDR
+                )));
+            }
+            let data = read_chunk_data(&mut reader, length)?;
+            let found: PngColorType = image::Ihdr::parse(&data)?.color_type.try_into()?;
+            if let PngColorType::Rgb | PngColorType::Rgba = found {
+                // The image data is re-encoded, so the whole file is needed
+                let mut file = [&signature[..], &header, &data].concat();
+                reader
+                    .read_to_end(&mut file)
+                    .map_err(|e| read_error(e, "the image"))?;
+                cancel.map_or(Ok(()), Cancellation::check)?;
+                let changes = recolor_png_bytes(&mut file, transform)?;
+                // The signature is written already
+                writer
+                    .write_all(&file[PNG_FORMAT_IDENTIFIER.len()..])
+                    .map_err(write_error)?;
+                writer.flush().map_err(write_error)?;
+                return Ok(changes);
+            }
+            color_type = Some(found);
+            writer.write_all(&header).map_err(write_error)?;
+            writer.write_all(&data).map_err(write_error)?;
+            chunk_crc(chunk_type, &data)
+        } else if chunk_type == PLTE && color_type == Some(PngColorType::Palette) {
+            let mut data = read_chunk_data(&mut reader, length)?;
+            let mut crc = [0; 4];
+            reader
+                .read_exact(&mut crc)
+                .map_err(|e| read_error(e, "chunk crc"))?;
+            check_crc(
+                chunk_type,
+                offset,
+                u32::from_be_bytes(crc),
+                chunk_crc(chunk_type, &data),
+            )?;
+            offset += 12 + length as usize;
+            changes += recolor_palette(&mut data, transform)?;
+            writer.write_all(&header).map_err(write_error)?;
+            writer.write_all(&data).map_err(write_error)?;
+            writer
+                .write_all(&chunk_crc(chunk_type, &data).to_be_bytes())
+                .map_err(write_error)?;
+            continue;
+        } else {
+            writer.write_all(&header).map_err(write_error)?;
+            copy_chunk_data(&mut reader, &mut writer, chunk_type, length)?
+        };
+
+        let mut crc = [0; 4];
+        reader
+            .read_exact(&mut crc)
+            .map_err(|e| read_error(e, "chunk crc"))?;
+        check_crc(chunk_type, offset, u32::from_be_bytes(crc), computed)?;
+        offset += 12 + length as usize;
+        writer.write_all(&crc).map_err(write_error)?;
+        if chunk_type == IEND {
+            break;
+        }
+    }
+
+    // Whatever trails IEND is kept, as the in-memory passes do
+    std::io::copy(&mut reader, &mut writer).map_err(|e| read_error(e, "the trailer"))?;
+    writer.flush().map_err(write_error)?;
+    Ok(changes)
+}
+
+/// Copies the `length` bytes of chunk data from `reader` to `writer`, returning the CRC they
+/// give with `chunk_type`.
+fn copy_chunk_data<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    chunk_type: u32,
+    length: u32,
+) -> Result<u32, TranError> {
+    let mut crc = crc_update(0xffffffff, chunk_type.to_be_bytes());
+    let mut buffer = [0; 8192];
+    let size = buffer.len();
+    let mut left = length as usize;
+    while left > 0 {
+        let read = match reader.read(&mut buffer[..left.min(size)]) {
+            Ok(0) => {
+                return Err(TranError::PngFormatError(
+                    "Ran out of bytes while reading chunk data".to_string(),
+                ))
+            }
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(read_error(e, "chunk data")),
+        };
+        crc = crc_update(crc, buffer[..read].iter().copied());
+        writer.write_all(&buffer[..read]).map_err(write_error)?;
+        left -= read;
+    }
+    Ok(crc ^ 0xffffffff)
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut crc_table: [u32; 256] = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
             if (c & 1) != 0 {
                 c = 0xEDB88320 ^ (c >> 1);
             } else {
                 c >>= 1;
             }
+            k += 1;
+        }
+        crc_table[n] = c;
+        n += 1;
+    }
+    crc_table
+}
+
+fn crc_update(c: u32, bytes: impl IntoIterator<Item = u8>) -> u32 {
+    bytes.into_iter().fold(c, |c, byte| {
+        CRC_TABLE[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+/// CRC of a chunk, which covers its type and data but not its length.
+pub(crate) fn chunk_crc(chunk_type: u32, data: &[u8]) -> u32 {
+    let c = crc_update(0xffffffff, chunk_type.to_be_bytes());
+    crc_update(c, data.iter().copied()) ^ 0xffffffff
+}
+
+/// Appends a complete chunk to `out`.
+pub(crate) fn write_chunk(out: &mut Vec<u8>, chunk_type: u32, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(&chunk_type.to_be_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(&chunk_crc(chunk_type, data).to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build::PngBuilder, *};
+    use crate::ColorMap;
+
+    const NORD: [(u8, u8, u8); 5] = [
+        (0x00, 0x00, 0x00),
+        (0xff, 0xff, 0xff),
+        (0x2e, 0x34, 0x40),
+        (0x88, 0xc0, 0xd0),
+        (0x4c, 0x56, 0x6a),
+    ];
+
+    fn indices() -> Vec<Vec<u8>> {
+        (0..16)
+            .map(|y| (0..16).map(|x| ((x / 4 + y / 4) % 5) as u8).collect())
+            .collect()
+    }
+
+    fn rgb() -> Vec<Vec<(u8, u8, u8)>> {
+        indices()
+            .iter()
+            .map(|row| row.iter().map(|i| NORD[*i as usize]).collect())
+            .collect()
+    }
+
+    fn rgba() -> Vec<Vec<(u8, u8, u8, u8)>> {
+        rgb()
+            .iter()
+            .enumerate()
+            .map(|(y, row)| {
+                row.iter()
+                    .map(|&(r, g, b)| (r, g, b, if y < 4 { 0 } else { 255 }))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn fixtures() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            ("palette", PngBuilder::palette(&NORD, &indices()).build()),
+            ("rgb", PngBuilder::rgb(&rgb()).build()),
+            ("rgba", PngBuilder::rgba(&rgba()).build()),
+        ]
+    }
+
+    fn map() -> [ColorMap<'static>; 1] {
+        [ColorMap::new("#bf616a", "#88c0d0")]
+    }
+
+    /// What a viewer shows of an 8 bit png, pixel by pixel in the order of its passes.
+    fn render(file: &[u8]) -> Vec<(u8, u8, u8, u8)> {
+        let chunks = image::read_chunks(file).unwrap();
+        let ihdr = image::Ihdr::parse(&chunks[0].data).unwrap();
+        let data = |chunk_type| {
+            chunks
+                .iter()
+                .find(|c| c.chunk_type == chunk_type)
+                .map_or(&[][..], |c| c.data.as_slice())
+        };
+        let (plte, trns) = (data(PLTE), data(TRNS));
+        let pixels: Vec<u8> = image::decode(&ihdr, &chunks)
+            .unwrap()
+            .into_iter()
+            .flat_map(|pass| pass.data)
+            .collect();
+        match ihdr.color_type {
+            3 => pixels
+                .iter()
+                .map(|&i| {
+                    let i = i as usize;
+                    let rgb = &plte[3 * i..3 * i + 3];
+                    (rgb[0], rgb[1], rgb[2], trns.get(i).copied().unwrap_or(255))
+                })
+                .collect(),
+            2 => pixels
+                .chunks_exact(3)
+                .map(|p| (p[0], p[1], p[2], 255))
+                .collect(),
+            _ => pixels
+                .chunks_exact(4)
+                .map(|p| (p[0], p[1], p[2], p[3]))
+                .collect(),
         }
-        crc_table[n as usize] = c;
     }
 
-    let mut c: u32 = 0xffffffff;
-    for i in 0..buf.len() {
-        c = crc_table[((c ^ (*(buf[i])) as u32) & 0xff) as usize] ^ (c >> 8);
+    fn palette_len(file: &[u8]) -> usize {
+        image::read_chunks(file)
+            .unwrap()
+            .iter()
+            .find(|c| c.chunk_type == PLTE)
+            .map_or(0, |c| c.data.len() / 3)
+    }
+
+    #[test]
+    fn two_colors_swap_in_every_kind_of_png() {
+        let map = [
+            ColorMap::new("#88c0d0", "#2e3440"),
+            ColorMap::new("#2e3440", "#88c0d0"),
+        ];
+        let transform = ColorTransform::Map(&map);
+        let swapped = |(r, g, b, a): (u8, u8, u8, u8)| match (r, g, b) {
+            (0x2e, 0x34, 0x40) => (0x88, 0xc0, 0xd0, a),
+            (0x88, 0xc0, 0xd0) => (0x2e, 0x34, 0x40, a),
+            _ => (r, g, b, a),
+        };
+        for (name, png) in fixtures() {
+            let mut recolored = png.clone();
+            recolor_png_bytes(&mut recolored, &transform).unwrap();
+            let expected: Vec<_> = render(&png).into_iter().map(swapped).collect();
+            assert!(render(&recolored) == expected, "{} differs", name);
+        }
+    }
+
+    #[test]
+    fn chained_maps_follow_every_entry_through_their_stages() {
+        let first = [ColorMap::new("#88c0d0", "#2e3440")];
+        let second = [
+            ColorMap::new("#bf616a", "#88c0d0"),
+            ColorMap::new("#a3be8c", "#4c566a"),
+        ];
+        let stages = [ColorTransform::Map(&first), ColorTransform::Map(&second)];
+        let file = PngBuilder::palette(&NORD[2..], &[vec![0, 1, 2]]).build();
+        let mut recolored = file.clone();
+        let changes = recolor_png_bytes(&mut recolored, &ColorTransform::Chain(&stages)).unwrap();
+        assert_eq!(changes, 3);
+        assert_eq!(
+            render(&recolored),
+            [
+                (0xbf, 0x61, 0x6a, 255),
+                (0xbf, 0x61, 0x6a, 255),
+                (0xa3, 0xbe, 0x8c, 255)
+            ]
+        );
+    }
+
+    #[test]
+    fn gradients_leave_black_and_white_and_take_a_lone_color_to_primary() {
+        let gradient = ColorTransform::Gradient {
+            primary: "#bf616a",
+            background: "#000000",
+            current: "#000000",
+            space: crate::space::GradientSpace::Srgb,
+            luminance: crate::pixel::LuminanceBand::default(),
+            stops: &[],
+        };
+        let grid = vec![vec![0, 1, 1, 0]];
+        let file = PngBuilder::palette(&NORD[..2], &grid).build();
+        let mut recolored = file.clone();
+        assert_eq!(recolor_png_bytes(&mut recolored, &gradient).unwrap(), 0);
+        assert_eq!(recolored, file);
+
+        let file = PngBuilder::palette(&NORD[..3], &[vec![0, 1, 2]]).build();
+        let mut recolored = file.clone();
+        assert_eq!(recolor_png_bytes(&mut recolored, &gradient).unwrap(), 1);
+        assert_eq!(
+            render(&recolored),
+            [
+                (0x00, 0x00, 0x00, 255),
+                (0xff, 0xff, 0xff, 255),
+                (0xbf, 0x61, 0x6a, 255)
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_entries_are_merged() {
+        let palette = [NORD[2], NORD[3], NORD[2], NORD[4], NORD[3]];
+        let grid: Vec<Vec<u8>> = (0..5)
+            .map(|y| (0..7).map(|x| (x + y) % 5).collect())
+            .collect();
+        let file = PngBuilder::palette(&palette, &grid).build();
+        let compacted = compact_palette(&file).unwrap().unwrap();
+        assert_eq!(palette_len(&compacted), 3);
+        assert!(check::check_png(&compacted).is_ok());
+        assert_eq!(render(&compacted), render(&file));
+        assert_eq!(compact_palette(&compacted).unwrap(), None);
+    }
+
+    #[test]
+    fn entries_apart_in_alpha_stay_apart() {
+        let palette = [NORD[2], NORD[2], NORD[2], NORD[3]];
+        let grid = vec![vec![0, 1, 2, 3]];
+        let file = PngBuilder::palette(&palette, &grid)
+            .transparency(&[0, 255, 0])
+            .build();
+        let compacted = compact_palette(&file).unwrap().unwrap();
+        assert_eq!(palette_len(&compacted), 3);
+        assert_eq!(render(&compacted), render(&file));
+
+        // Once only opaque entries are left there is no tRNS to keep
+        let file = PngBuilder::palette(&[NORD[2], NORD[2]], &[vec![0, 1]])
+            .transparency(&[255, 255])
+            .build();
+        let compacted = compact_palette(&file).unwrap().unwrap();
+        assert!(check::check_png(&compacted)
+            .chunks
+            .iter()
+            .all(|c| c.chunk_type != TRNS));
+    }
+
+    #[test]
+    fn histograms_add_up_merged_entries() {
+        let file = PngBuilder::palette(&[NORD[2], NORD[2], NORD[3]], &[vec![0, 1, 2]]).build();
+        let mut chunks = image::read_chunks(&file).unwrap();
+        chunks.insert(
+            2,
+            image::OwnedChunk {
+                chunk_type: HIST,
+                data: vec![0, 1, 0, 2, 0, 3],
+            },
+        );
+        let file = image::write_chunks(&chunks, &[]);
+        let compacted = compact_palette(&file).unwrap().unwrap();
+        let hist = image::read_chunks(&compacted)
+            .unwrap()
+            .into_iter()
+            .find(|c| c.chunk_type == HIST)
+            .unwrap();
+        assert_eq!(hist.data, [0, 3, 0, 3]);
+    }
+
+    #[test]
+    fn pngs_without_duplicates_are_left_alone() {
+        let file = PngBuilder::palette(&NORD, &indices()).build();
+        assert_eq!(compact_palette(&file).unwrap(), None);
+        let file = PngBuilder::rgb(&rgb()).build();
+        assert_eq!(compact_palette(&file).unwrap(), None);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn compaction_merges_what_the_map_made_identical() {
+        let dir = crate::test_util::TempDir::new("png-compact");
+        let file = PngBuilder::palette(&NORD, &indices()).build();
+        let source = dir.write("icon.png", &file);
+        let target = dir.path().join("out.png");
+        // #88c0d0 becomes #2e3440, which the palette already has
+        let map = [ColorMap::new("#2e3440", "#88c0d0")];
+        let options = PngOptions {
+            compact_palette: true,
+            ..PngOptions::default()
+        };
+        recolor_png_with(&source, &target, &ColorTransform::Map(&map), &options).unwrap();
+        let written = std::fs::read(&target).unwrap();
+        assert_eq!(palette_len(&written), NORD.len() - 1);
+
+        let mut plain = file.clone();
+        recolor_png_bytes(&mut plain, &ColorTransform::Map(&map)).unwrap();
+        assert_eq!(palette_len(&plain), NORD.len());
+        assert_eq!(render(&written), render(&plain));
+    }
+
+    #[test]
+    fn flat_rgb_images_palettize_to_the_same_pixels() {
+        let file = PngBuilder::rgb(&rgb()).build();
+        let palettized = palettize_bytes(&file, MAX_PALETTE_COLORS).unwrap().unwrap();
+        assert!(check::check_png(&palettized).is_ok());
+        let chunks = image::read_chunks(&palettized).unwrap();
+        assert_eq!(image::Ihdr::parse(&chunks[0].data).unwrap().color_type, 3);
+        assert_eq!(palette_len(&palettized), NORD.len());
+        assert_eq!(render(&palettized), render(&file));
+
+        let interlaced = PngBuilder::rgb(&rgb()).interlaced().build();
+        let palettized = palettize_bytes(&interlaced, MAX_PALETTE_COLORS)
+            .unwrap()
+            .unwrap();
+        assert_eq!(render(&palettized), render(&interlaced));
+    }
+
+    #[test]
+    fn too_many_colors_are_counted() {
+        let file = PngBuilder::rgb(&rgb()).build();
+        let Err(TranError::PngFormatError(reason)) = palettize_bytes(&file, 3) else {
+            panic!("expected a png error");
+        };
+        assert_eq!(
+            reason,
+            "Image has 5 distinct colors, more than the 3 a palette can hold"
+        );
+        let many: Vec<Vec<(u8, u8, u8)>> = (0..2)
+            .map(|y| (0..200).map(|x| (x as u8, y as u8, 0)).collect())
+            .collect();
+        let file = PngBuilder::rgb(&many).build();
+        assert!(palettize_bytes(&file, 1000).is_err());
+    }
+
+    #[test]
+    fn only_rgb_images_palettize() {
+        let file = PngBuilder::rgba(&rgba()).build();
+        assert_eq!(palettize_bytes(&file, MAX_PALETTE_COLORS).unwrap(), None);
+        let file = PngBuilder::palette(&NORD, &indices()).build();
+        assert_eq!(palettize_bytes(&file, MAX_PALETTE_COLORS).unwrap(), None);
+    }
+
+    #[test]
+    fn the_transparent_color_of_rgb_becomes_entry_zero() {
+        let file = PngBuilder::rgb(&rgb()).build();
+        let mut chunks = image::read_chunks(&file).unwrap();
+        // #2e3440 as 16 bit samples
+        chunks.insert(
+            1,
+            image::OwnedChunk {
+                chunk_type: TRNS,
+                data: vec![0, 0x2e, 0, 0x34, 0, 0x40],
+            },
+        );
+        let file = image::write_chunks(&chunks, &[]);
+        let palettized = palettize_bytes(&file, MAX_PALETTE_COLORS).unwrap().unwrap();
+        let pixels = render(&palettized);
+        assert!(pixels.contains(&(0x2e, 0x34, 0x40, 0)));
+        assert!(pixels
+            .iter()
+            .all(|&(r, g, b, a)| (a == 0) == ((r, g, b) == (0x2e, 0x34, 0x40))));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn rgb_images_can_take_the_palette_route() {
+        let dir = crate::test_util::TempDir::new("png-palettize");
+        let file = PngBuilder::rgb(&rgb()).build();
+        let source = dir.write("icon.png", &file);
+        let target = dir.path().join("out.png");
+        let options = PngOptions {
+            palettize_rgb: true,
+            ..PngOptions::default()
+        };
+        let changes =
+            recolor_png_with(&source, &target, &ColorTransform::Map(&map()), &options).unwrap();
+        assert_eq!(changes, 1);
+        let written = std::fs::read(&target).unwrap();
+        assert_eq!(palette_len(&written), NORD.len());
+        assert_eq!(render(&written), render(&bytes_recolored(&file)));
+
+        let copy = dir.path().join("palettized.png");
+        assert_eq!(palettize(&source, &copy, 256).unwrap(), NORD.len());
+        assert_eq!(render(&std::fs::read(&copy).unwrap()), render(&file));
+    }
+
+    fn bytes_recolored(file: &[u8]) -> Vec<u8> {
+        let mut file = file.to_vec();
+        recolor_png_bytes(&mut file, &ColorTransform::Map(&map())).unwrap();
+        file
+    }
+
+    #[test]
+    fn damaged_chunks_are_caught_by_their_crc() {
+        // IHDR takes bytes 8 to 33, the chunk after it starts at 33 with its data at 41
+        for (file, name) in [
+            (PngBuilder::palette(&NORD, &indices()).build(), "PLTE"),
+            (PngBuilder::rgb(&rgb()).build(), "IDAT"),
+        ] {
+            let mut damaged = file.clone();
+            damaged[41] ^= 0xff;
+            let expected = format!("The {} chunk at byte 33 is corrupt", name);
+            let errors = [
+                recolor_png_bytes(&mut damaged.clone(), &ColorTransform::Map(&map())).map(|_| ()),
+                recolor_png_io(
+                    std::io::Cursor::new(&damaged),
+                    &mut Vec::new(),
+                    &ColorTransform::Map(&map()),
+                )
+                .map(|_| ()),
+                image::read_chunks(&damaged).map(|_| ()),
+            ];
+            for error in errors {
+                let error = error.unwrap_err().to_string();
+                assert!(error.contains(&expected), "{}", error);
+            }
+            assert!(image::read_chunks(&file).is_ok());
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn verify_png_names_the_file_and_its_violations() {
+        let dir = crate::test_util::TempDir::new("png-verify");
+        let file = PngBuilder::palette(&NORD, &indices()).build();
+        let good = dir.write("good.png", &file);
+        assert!(verify_png(&good).is_ok());
+
+        let mut damaged = file;
+        damaged[41] ^= 0xff;
+        let bad = dir.write("bad.png", &damaged);
+        let error = verify_png(&bad).unwrap_err();
+        assert!(error.to_string().contains("bad.png"), "{}", error);
+        assert!(verify_png(dir.path().join("missing.png")).is_err());
+    }
+
+    #[test]
+    fn streaming_matches_the_bytes_api() {
+        for (name, file) in fixtures() {
+            let mut out = Vec::new();
+            let changes = recolor_png_io(
+                std::io::Cursor::new(&file),
+                &mut out,
+                &ColorTransform::Map(&map()),
+            )
+            .unwrap();
+            assert_eq!(changes, 1, "{}", name);
+            assert_eq!(out, bytes_recolored(&file), "{}", name);
+            assert!(check::check_png(&out).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn checked_streams_match_unchecked_ones() {
+        let options = PngOptions {
+            check_output: true,
+            ..PngOptions::default()
+        };
+        for (name, file) in fixtures() {
+            let mut out = Vec::new();
+            let transform = ColorTransform::Map(&map());
+            recolor_png_stream(std::io::Cursor::new(&file), &mut out, &transform, &options)
+                .unwrap();
+            assert_eq!(out, bytes_recolored(&file), "{}", name);
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn path_based_output_matches_the_bytes_api() {
+        let dir = crate::test_util::TempDir::new("png-paths");
+        for (name, file) in fixtures() {
+            let source = dir.write(&format!("{}.png", name), &file);
+            let target = dir.path().join(format!("{}_out.png", name));
+            recolor_png(&source, &target, &ColorTransform::Map(&map())).unwrap();
+            let written = std::fs::read(&target).unwrap();
+            assert_eq!(written, bytes_recolored(&file), "{}", name);
+            assert!(check::check_png(&written).is_ok(), "{}", name);
+
+            // Recoloring in place reads the whole file, and checks it when asked to
+            let checked = PngOptions {
+                check_output: true,
+                ..PngOptions::default()
+            };
+            recolor_png_with(&source, &source, &ColorTransform::Map(&map()), &checked).unwrap();
+            assert_eq!(std::fs::read(&source).unwrap(), written, "{}", name);
+        }
+    }
+
+    #[test]
+    fn truecolor_pixels_take_the_map() {
+        for (name, file) in fixtures().into_iter().skip(1) {
+            let counts = color_counts(&bytes_recolored(&file)).unwrap();
+            let count = |color: &str| {
+                let color = Color::try_from_hex_str(color).unwrap();
+                counts
+                    .iter()
+                    .find(|(c, _)| *c == color)
+                    .map_or(0, |(_, n)| *n)
+            };
+            assert_eq!(count("#88c0d0"), 0, "{}", name);
+            assert!(count("#bf616a") > 0, "{}", name);
+            assert!(count("#2e3440") > 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn rgba_keeps_alpha() {
+        let file = PngBuilder::rgba(&rgba()).build();
+        let recolored = bytes_recolored(&file);
+        let chunks = image::read_chunks(&recolored).unwrap();
+        let ihdr = image::Ihdr::parse(&chunks[0].data).unwrap();
+        assert_eq!((ihdr.color_type, ihdr.bit_depth), (6, 8));
+        let alpha = |file: &[u8]| -> Vec<u8> {
+            let chunks = image::read_chunks(file).unwrap();
+            let ihdr = image::Ihdr::parse(&chunks[0].data).unwrap();
+            image::decode(&ihdr, &chunks).unwrap()[0]
+                .data
+                .chunks_exact(4)
+                .map(|p| p[3])
+                .collect()
+        };
+        assert_eq!(alpha(&recolored), alpha(&file));
+    }
+
+    #[test]
+    fn sixteen_bit_pixels_take_the_map_at_their_depth() {
+        let pixels = |file: &[u8]| -> (u8, Vec<u8>) {
+            let chunks = image::read_chunks(file).unwrap();
+            let ihdr = image::Ihdr::parse(&chunks[0].data).unwrap();
+            (
+                ihdr.bit_depth,
+                image::decode(&ihdr, &chunks).unwrap()[0].data.clone(),
+            )
+        };
+        let swap = |(r, g, b)| match (r, g, b) {
+            (0x88, 0xc0, 0xd0) => (0xbf, 0x61, 0x6a),
+            color => color,
+        };
+        let recolored_rgb: Vec<Vec<_>> = rgb()
+            .into_iter()
+            .map(|row| row.into_iter().map(swap).collect())
+            .collect();
+        let recolored_rgba: Vec<Vec<_>> = rgba()
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|(r, g, b, a)| {
+                        let (r, g, b) = swap((r, g, b));
+                        (r, g, b, a)
+                    })
+                    .collect()
+            })
+            .collect();
+        for (name, file, expected) in [
+            (
+                "rgb16",
+                PngBuilder::rgb(&rgb()).sixteen_bit().build(),
+                PngBuilder::rgb(&recolored_rgb).sixteen_bit().build(),
+            ),
+            (
+                "rgba16",
+                PngBuilder::rgba(&rgba()).sixteen_bit().build(),
+                PngBuilder::rgba(&recolored_rgba).sixteen_bit().build(),
+            ),
+        ] {
+            let recolored = bytes_recolored(&file);
+            assert_eq!(pixels(&recolored), pixels(&expected), "{}", name);
+            assert_eq!(pixels(&recolored).0, 16, "{}", name);
+            assert!(check::check_png(&recolored).is_ok(), "{}", name);
+        }
+
+        // Samples between two 8 bit values are looked up by their high byte
+        let mut file = PngBuilder::rgb(&[vec![(0x88, 0xc0, 0xd0)]])
+            .sixteen_bit()
+            .build();
+        let mut chunks = image::read_chunks(&file).unwrap();
+        let ihdr = image::Ihdr::parse(&chunks[0].data).unwrap();
+        let mut passes = image::decode(&ihdr, &chunks).unwrap();
+        passes[0].data = vec![0x88, 0x01, 0xc0, 0x02, 0xd0, 0x03];
+        image::replace_image_data(&mut chunks, image::encode(&ihdr, &passes));
+        file = image::write_chunks(&chunks, &[]);
+        let recolored = bytes_recolored(&file);
+        assert_eq!(pixels(&recolored).1, [0xbf, 0xbf, 0x61, 0x61, 0x6a, 0x6a]);
+    }
+
+    /// Where the pixels of an interlaced `width` by `height` image sit in the plain one, in the
+    /// order of its passes.
+    fn adam7_order(width: u32, height: u32) -> Vec<usize> {
+        let mut order = Vec::new();
+        for (x0, y0, dx, dy) in image::ADAM7 {
+            for y in (y0..height).step_by(dy as usize) {
+                for x in (x0..width).step_by(dx as usize) {
+                    order.push((y * width + x) as usize);
+                }
+            }
+        }
+        order
+    }
+
+    #[test]
+    fn interlaced_images_recolor_as_their_plain_ones() {
+        let small: Vec<Vec<u8>> = (0..5)
+            .map(|y| (0..3).map(|x| (x + y) % 5).collect())
+            .collect();
+        for grid in [indices(), small] {
+            let (width, height) = (grid[0].len() as u32, grid.len() as u32);
+            let rgb: Vec<Vec<(u8, u8, u8)>> = grid
+                .iter()
+                .map(|row| row.iter().map(|i| NORD[*i as usize]).collect())
+                .collect();
+            let fixtures = [
+                (
+                    PngBuilder::palette(&NORD, &grid).build(),
+                    PngBuilder::palette(&NORD, &grid).interlaced().build(),
+                ),
+                (
+                    PngBuilder::rgb(&rgb).build(),
+                    PngBuilder::rgb(&rgb).interlaced().build(),
+                ),
+            ];
+            for (plain, interlaced) in fixtures {
+                assert!(check::check_png(&interlaced).is_ok());
+                let recolored = bytes_recolored(&interlaced);
+                let chunks = image::read_chunks(&recolored).unwrap();
+                assert_eq!(image::Ihdr::parse(&chunks[0].data).unwrap().interlace, 1);
+                let plain = render(&bytes_recolored(&plain));
+                let expected: Vec<_> = adam7_order(width, height)
+                    .into_iter()
+                    .map(|i| plain[i])
+                    .collect();
+                assert_eq!(render(&recolored), expected, "{}x{}", width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_rgba_pixels_play_no_part_in_a_gradient() {
+        let gradient = ColorTransform::Gradient {
+            primary: "#bf616a",
+            background: "#000000",
+            current: "#000000",
+            space: crate::space::GradientSpace::Srgb,
+            luminance: crate::pixel::LuminanceBand::default(),
+            stops: &[],
+        };
+        let pixels = [(0x2e, 0x34, 0x40, 255), (0x88, 0xc0, 0xd0, 0)];
+        let mut file = PngBuilder::rgba(&[pixels.to_vec()]).build();
+        assert_eq!(recolor_png_bytes(&mut file, &gradient).unwrap(), 1);
+        let chunks = image::read_chunks(&file).unwrap();
+        let ihdr = image::Ihdr::parse(&chunks[0].data).unwrap();
+        assert_eq!(
+            image::decode(&ihdr, &chunks).unwrap()[0].data,
+            [0xbf, 0x61, 0x6a, 255, 0x88, 0xc0, 0xd0, 0]
+        );
+    }
+
+    #[test]
+    fn broken_output_of_a_sound_png_is_an_error() {
+        let file = PngBuilder::rgb(&rgb()).build();
+        let broken = &file[..file.len() - 1];
+        match check_output(true, broken) {
+            Err(TranError::PngFormatError(message)) => {
+                assert!(
+                    message.starts_with("Recoloring broke the png: "),
+                    "{}",
+                    message
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+        // A png broken to begin with can't be broken by the passes
+        assert!(check_output(false, broken).is_ok());
+        assert!(check_output(true, &file).is_ok());
+    }
+
+    #[test]
+    fn streaming_keeps_a_copy_of_both_sides() {
+        let file = PngBuilder::palette(&NORD, &indices()).build();
+        let mut copy = Vec::new();
+        let mut out = Vec::new();
+        std::io::copy(
+            &mut Tee {
+                inner: &file[..],
+                copy: &mut copy,
+            },
+            &mut Tee {
+                inner: &mut out,
+                copy: &mut Vec::new(),
+            },
+        )
+        .unwrap();
+        assert_eq!((copy.as_slice(), out.as_slice()), (&file[..], &file[..]));
+    }
+
+    /// Hands out a few bytes per read, noting how much had been written by then.
+    struct Trickle<'a> {
+        file: &'a [u8],
+        read: usize,
+        written: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+        // Bytes read and written before each read
+        log: Vec<(usize, usize)>,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.log.push((self.read, self.written.borrow().len()));
+            let n = buf.len().min(3).min(self.file.len() - self.read);
+            buf[..n].copy_from_slice(&self.file[self.read..self.read + n]);
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chunks_are_written_as_they_are_read() {
+        for (name, file) in fixtures() {
+            let written = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+            let mut reader = Trickle {
+                file: &file,
+                read: 0,
+                written: written.clone(),
+                log: Vec::new(),
+            };
+            recolor_png_io(
+                &mut reader,
+                Shared(written.clone()),
+                &ColorTransform::Map(&map()),
+            )
+            .unwrap();
+            assert_eq!(*written.borrow(), bytes_recolored(&file), "{}", name);
+
+            // Truecolor image data is re-encoded as a whole, palette images stream, the chunks
+            // before IEND out before its bytes are asked for
+            if name != "palette" {
+                continue;
+            }
+            let iend = check::check_png(&file).chunks.last().unwrap().offset;
+            let (_, out) = reader.log.iter().find(|(read, _)| *read >= iend).unwrap();
+            assert!(*out >= iend, "{}: {} of {} written", name, out, iend);
+        }
+    }
+
+    #[test]
+    fn bytes_after_iend_are_kept() {
+        for (name, file) in fixtures() {
+            let mut with_trailer = file.clone();
+            with_trailer.extend_from_slice(b"TRAILER");
+            let recolored = bytes_recolored(&with_trailer);
+            assert!(recolored.ends_with(b"TRAILER"), "{}", name);
+
+            let mut out = Vec::new();
+            recolor_png_io(
+                std::io::Cursor::new(&with_trailer),
+                &mut out,
+                &ColorTransform::Map(&map()),
+            )
+            .unwrap();
+            assert_eq!(out, recolored, "{}", name);
+        }
     }
-    c ^ 0xffffffff
 }
@@ -0,0 +1,331 @@
+//! Builds small, valid pngs in memory, for tests and fixtures.
+//!
+//! ```
+//! use tran::png::build::PngBuilder;
+//!
+//! let palette = [(0x2e, 0x34, 0x40), (0x88, 0xc0, 0xd0)];
+//! let png = PngBuilder::palette(&palette, &[vec![0, 1], vec![1, 0]])
+//!     .idat_chunks(2)
+//!     .build();
+//! assert!(png.starts_with(b"\x89PNG"));
+//! ```
+
+use super::{image::ADAM7, write_chunk, IDAT, IEND, IHDR, PLTE, PNG_FORMAT_IDENTIFIER, TRNS};
+use crate::zlib::{compress, Compression};
+
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PngBuilder {
+    width: u32,
+    height: u32,
+    color_type: u8,
+    bit_depth: u8,
+    palette: Vec<(u8, u8, u8)>,
+    transparency: Vec<u8>,
+    // Unfiltered scanlines, without the filter type byte
+    rows: Vec<Vec<u8>>,
+    compression: Compression,
+    idat_chunks: usize,
+    interlaced: bool,
+}
+
+impl PngBuilder {
+    fn new(color_type: u8, rows: Vec<Vec<u8>>, width: usize) -> Self {
+        PngBuilder {
+            width: width as u32,
+            height: rows.len() as u32,
+            color_type,
+            bit_depth: 8,
+            palette: Vec::new(),
+            transparency: Vec::new(),
+            rows,
+            compression: Compression::default(),
+            idat_chunks: 1,
+            interlaced: false,
+        }
+    }
+
+    /// An 8 bit palette image, `grid` holding one row of palette indices per scanline.
+    /// Every row must be as long as the first.
+    pub fn palette(palette: &[(u8, u8, u8)], grid: &[Vec<u8>]) -> Self {
+        let width = grid.first().map_or(0, Vec::len);
+        assert!(grid.iter().all(|row| row.len() == width), "ragged grid");
+        PngBuilder {
+            palette: palette.to_vec(),
+            ..PngBuilder::new(COLOR_TYPE_PALETTE, grid.to_vec(), width)
+        }
+    }
+
+    /// An 8 bit truecolor image.
+    pub fn rgb(grid: &[Vec<(u8, u8, u8)>]) -> Self {
+        let width = grid.first().map_or(0, Vec::len);
+        assert!(grid.iter().all(|row| row.len() == width), "ragged grid");
+        let rows = grid
+            .iter()
+            .map(|row| row.iter().flat_map(|&(r, g, b)| [r, g, b]).collect())
+            .collect();
+        PngBuilder::new(COLOR_TYPE_RGB, rows, width)
+    }
+
+    /// An 8 bit truecolor image with alpha.
+    pub fn rgba(grid: &[Vec<(u8, u8, u8, u8)>]) -> Self {
+        let width = grid.first().map_or(0, Vec::len);
+        assert!(grid.iter().all(|row| row.len() == width), "ragged grid");
+        let rows = grid
+            .iter()
+            .map(|row| row.iter().flat_map(|&(r, g, b, a)| [r, g, b, a]).collect())
+            .collect();
+        PngBuilder::new(COLOR_TYPE_RGBA, rows, width)
+    }
+
+    /// Alpha values for the first palette entries, written as a tRNS chunk.
+    pub fn transparency(mut self, alpha: &[u8]) -> Self {
+        self.transparency = alpha.to_vec();
+        self
+    }
+
+    /// Use stored deflate blocks instead of compressing.
+    pub fn stored(mut self) -> Self {
+        self.compression = Compression::Stored;
+        self
+    }
+
+    /// Split the image data over `count` IDAT chunks.
+    pub fn idat_chunks(mut self, count: usize) -> Self {
+        self.idat_chunks = count.max(1);
+        self
+    }
+
+    /// Store every sample of a truecolor image at 16 bits, `v` as `v * 257` so it reads back
+    /// as the same color.
+    pub fn sixteen_bit(mut self) -> Self {
+        assert_ne!(self.color_type, COLOR_TYPE_PALETTE, "palettes are 8 bit");
+        self.rows = self
+            .rows
+            .iter()
+            .map(|row| row.iter().flat_map(|&v| [v, v]).collect())
+            .collect();
+        self.bit_depth = 16;
+        self
+    }
+
+    /// Store the scanlines as the seven Adam7 passes.
+    pub fn interlaced(mut self) -> Self {
+        self.interlaced = true;
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = PNG_FORMAT_IDENTIFIER.to_vec();
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        // Bit depth, color type, compression, filter and interlace method
+        ihdr.extend_from_slice(&[self.bit_depth, self.color_type, 0, 0, self.interlaced as u8]);
+        write_chunk(&mut out, IHDR, &ihdr);
+
+        if self.color_type == COLOR_TYPE_PALETTE {
+            let plte: Vec<u8> = self
+                .palette
+                .iter()
+                .flat_map(|&(r, g, b)| [r, g, b])
+                .collect();
+            write_chunk(&mut out, PLTE, &plte);
+        }
+        if !self.transparency.is_empty() {
+            write_chunk(&mut out, TRNS, &self.transparency);
+        }
+
+        // Filter type 0 for every scanline
+        let raw: Vec<u8> = match self.interlaced {
+            false => self
+                .rows
+                .iter()
+                .flat_map(|row| std::iter::once(0).chain(row.iter().copied()))
+                .collect(),
+            true => self.adam7(),
+        };
+        let data = compress(&raw, self.compression);
+        let chunk_size = data.len().div_ceil(self.idat_chunks).max(1);
+        for chunk in data.chunks(chunk_size) {
+            write_chunk(&mut out, IDAT, chunk);
+        }
+
+        write_chunk(&mut out, IEND, &[]);
+        out
+    }
+
+    /// The scanlines of every non-empty Adam7 pass, each behind filter type 0.
+    fn adam7(&self) -> Vec<u8> {
+        let pixel = self.rows.first().map_or(0, Vec::len) / (self.width.max(1) as usize);
+        let mut raw = Vec::new();
+        for (x0, y0, dx, dy) in ADAM7 {
+            let xs: Vec<usize> = (x0..self.width)
+                .step_by(dx as usize)
+                .map(|x| x as usize)
+                .collect();
+            if xs.is_empty() {
+                continue;
+            }
+            for row in self.rows.iter().skip(y0 as usize).step_by(dy as usize) {
+                raw.push(0);
+                for x in xs.iter() {
+                    raw.extend_from_slice(&row[x * pixel..(x + 1) * pixel]);
+                }
+            }
+        }
+        raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{
+        check::check_png,
+        image::{decode, read_chunks, Ihdr},
+    };
+
+    const PALETTE: [(u8, u8, u8); 3] = [(0x2e, 0x34, 0x40), (0x88, 0xc0, 0xd0), (0xff, 0xff, 0xff)];
+
+    fn grid() -> Vec<Vec<u8>> {
+        (0..9)
+            .map(|y| (0..11).map(|x| ((x + y) % 3) as u8).collect())
+            .collect()
+    }
+
+    fn variants() -> Vec<(&'static str, PngBuilder)> {
+        vec![
+            ("palette", PngBuilder::palette(&PALETTE, &grid())),
+            ("stored", PngBuilder::palette(&PALETTE, &grid()).stored()),
+            (
+                "split",
+                PngBuilder::palette(&PALETTE, &grid()).idat_chunks(3),
+            ),
+            (
+                "transparent",
+                PngBuilder::palette(&PALETTE, &grid()).transparency(&[0, 128]),
+            ),
+            (
+                "rgb",
+                PngBuilder::rgb(
+                    &grid()
+                        .iter()
+                        .map(|row| row.iter().map(|&i| PALETTE[i as usize]).collect())
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            (
+                "rgba",
+                PngBuilder::rgba(&[vec![(1, 2, 3, 4), (5, 6, 7, 8)], vec![(9, 10, 11, 12); 2]]),
+            ),
+            (
+                "rgba16",
+                PngBuilder::rgba(&[vec![(1, 2, 3, 4), (5, 6, 7, 8)], vec![(9, 10, 11, 12); 2]])
+                    .sixteen_bit(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn built_pngs_pass_the_check() {
+        for (name, builder) in variants() {
+            let check = check_png(&builder.build()).violations;
+            assert!(check.is_empty(), "{}: {:?}", name, check);
+        }
+    }
+
+    #[test]
+    fn built_pngs_decode_to_their_grid() {
+        for (name, builder) in variants() {
+            let chunks = read_chunks(&builder.build()).unwrap();
+            let ihdr = Ihdr::parse(&chunks[0].data).unwrap();
+            let passes = decode(&ihdr, &chunks).unwrap();
+            assert_eq!(passes.len(), 1, "{}", name);
+            assert_eq!(passes[0].data, builder.rows.concat(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn built_pngs_have_the_chunks_asked_for() {
+        let names = |png: Vec<u8>| -> Vec<String> {
+            check_png(&png).chunks.iter().map(|c| c.name()).collect()
+        };
+        let split = PngBuilder::palette(&PALETTE, &grid())
+            .idat_chunks(3)
+            .build();
+        assert_eq!(
+            names(split),
+            ["IHDR", "PLTE", "IDAT", "IDAT", "IDAT", "IEND"]
+        );
+        let transparent = PngBuilder::palette(&PALETTE, &grid())
+            .transparency(&[0])
+            .build();
+        assert_eq!(names(transparent), ["IHDR", "PLTE", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(
+            names(PngBuilder::rgb(&[vec![(0, 0, 0)]]).build()),
+            ["IHDR", "IDAT", "IEND"]
+        );
+    }
+
+    #[test]
+    fn built_pngs_are_recolored() {
+        let map = [crate::ColorMap::new("#bf616a", "#2e3440")];
+        for (name, builder) in variants() {
+            let mut png = builder.build();
+            let result = crate::png::recolor_png_bytes(&mut png, &crate::ColorTransform::Map(&map));
+            assert!(result.is_ok(), "{}: {:?}", name, result);
+        }
+    }
+
+    /// Checks the CRCs and inflates the image data with python's zlib, a decoder that shares
+    /// nothing with this crate. Skipped where there is no python.
+    #[test]
+    fn built_pngs_inflate_with_another_zlib() {
+        const SCRIPT: &str = r#"
+import sys, struct, zlib
+data = sys.stdin.buffer.read()
+assert data[:8] == b"\x89PNG\r\n\x1a\n"
+at, idat = 8, b""
+while at < len(data):
+    length, kind = struct.unpack(">I4s", data[at:at + 8])
+    body = data[at + 8:at + 8 + length]
+    crc, = struct.unpack(">I", data[at + 8 + length:at + 12 + length])
+    assert zlib.crc32(kind + body) == crc, kind
+    if kind == b"IDAT":
+        idat += body
+    at += 12 + length
+sys.stdout.buffer.write(zlib.decompress(idat))
+"#;
+        for (name, builder) in variants() {
+            let child = std::process::Command::new("python3")
+                .args(["-c", SCRIPT])
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn();
+            let Ok(mut child) = child else {
+                eprintln!("no python3, skipping");
+                return;
+            };
+            use std::io::Write;
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(&builder.build())
+                .unwrap();
+            let output = child.wait_with_output().unwrap();
+            assert!(output.status.success(), "{}", name);
+            let raw: Vec<u8> = builder
+                .rows
+                .iter()
+                .flat_map(|row| std::iter::once(0).chain(row.iter().copied()))
+                .collect();
+            assert_eq!(output.stdout, raw, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! A lightweight integrity check of a png, walking its chunks as the recolor passes do:
+//! signature, lengths, CRCs, the order of the critical chunks and nothing after `IEND`. Used by
+//! `tran png-check`, and on what the recolor passes write when `PngOptions::check_output` asks.
+
+use super::{chunk_crc, chunk_name, IDAT, IEND, IHDR, PLTE, PNG_FORMAT_IDENTIFIER};
+
+/// A chunk of a png, borrowed from the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawChunk<'a> {
+    /// Where its length starts in the file.
+    pub offset: usize,
+    pub chunk_type: u32,
+    pub data: &'a [u8],
+    /// The CRC as written, see `crc_ok`.
+    pub crc: u32,
+}
+
+impl RawChunk<'_> {
+    /// The chunk type as its four letters.
+    pub fn name(&self) -> String {
+        chunk_name(self.chunk_type)
+    }
+
+    pub fn crc_ok(&self) -> bool {
+        chunk_crc(self.chunk_type, self.data) == self.crc
+    }
+
+    /// Whether a decoder has to understand the chunk, from the case of its first letter.
+    pub fn is_critical(&self) -> bool {
+        self.chunk_type.to_be_bytes()[0].is_ascii_uppercase()
+    }
+}
+
+/// The chunks of a png after its signature, up to and including `IEND`. A chunk running past
+/// the end of the file ends the walk with an error, leaving `offset` where it starts.
+#[derive(Clone, Debug)]
+pub struct Chunks<'a> {
+    file: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Chunks<'a> {
+    /// Walks `file`, whose signature isn't checked.
+    pub fn new(file: &'a [u8]) -> Self {
+        Chunks {
+            file,
+            offset: PNG_FORMAT_IDENTIFIER.len().min(file.len()),
+            done: false,
+        }
+    }
+
+    /// Where the next chunk starts, past `IEND` once the walk is over.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<RawChunk<'a>, Violation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.file.len() {
+            return None;
+        }
+        let at = self.offset;
+        let rest = &self.file[at..];
+        let word = |i: usize| {
+            rest.get(i..i + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let (Some(length), Some(chunk_type)) = (word(0), word(4)) else {
+            self.done = true;
+            return Some(Err(Violation::Truncated { offset: at }));
+        };
+        let end = 8 + length as usize;
+        let (Some(data), Some(crc)) = (rest.get(8..end), word(end)) else {
+            self.done = true;
+            return Some(Err(Violation::Truncated { offset: at }));
+        };
+        self.offset += end + 4;
+        self.done = chunk_type == IEND;
+        Some(Ok(RawChunk {
+            offset: at,
+            chunk_type,
+            data,
+            crc,
+        }))
+    }
+}
+
+/// Something wrong with a png.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    Signature,
+    /// The chunk starting at `offset` runs past the end of the file.
+    Truncated {
+        offset: usize,
+    },
+    BadCrc {
+        chunk: String,
+        offset: usize,
+    },
+    /// A chunk type with anything but letters.
+    BadType {
+        offset: usize,
+    },
+    IhdrNotFirst,
+    /// An `IHDR` or `PLTE` after the first.
+    Repeated {
+        chunk: String,
+        offset: usize,
+    },
+    PlteAfterIdat {
+        offset: usize,
+    },
+    /// `IDAT` chunks with others between them.
+    SplitIdat {
+        offset: usize,
+    },
+    NoIdat,
+    /// A palette image without a `PLTE`.
+    NoPlte,
+    NoIend,
+    /// Bytes after `IEND`.
+    Trailing {
+        offset: usize,
+        bytes: usize,
+    },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::Signature => write!(f, "Does not start with the png signature"),
+            Violation::Truncated { offset } => {
+                write!(f, "The chunk at {} runs past the end of the file", offset)
+            }
+            Violation::BadCrc { chunk, offset } => {
+                write!(f, "The {} chunk at {} has a wrong CRC", chunk, offset)
+            }
+            Violation::BadType { offset } => {
+                write!(f, "The chunk at {} has an invalid type", offset)
+            }
+            Violation::IhdrNotFirst => write!(f, "The first chunk is not IHDR"),
+            Violation::Repeated { chunk, offset } => {
+                write!(f, "A second {} chunk at {}", chunk, offset)
+            }
+            Violation::PlteAfterIdat { offset } => {
+                write!(f, "The PLTE chunk at {} comes after image data", offset)
+            }
+            Violation::SplitIdat { offset } => {
+                write!(
+                    f,
+                    "The IDAT chunk at {} is apart from the ones before",
+                    offset
+                )
+            }
+            Violation::NoIdat => write!(f, "There is no IDAT chunk"),
+            Violation::NoPlte => write!(f, "A palette image without a PLTE chunk"),
+            Violation::NoIend => write!(f, "There is no IEND chunk"),
+            Violation::Trailing { offset, bytes } => {
+                write!(f, "{} bytes after IEND at {}", bytes, offset)
+            }
+        }
+    }
+}
+
+/// The chunks of a png and what is wrong with it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PngCheck<'a> {
+    pub chunks: Vec<RawChunk<'a>>,
+    pub violations: Vec<Violation>,
+}
+
+impl PngCheck<'_> {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Walks every chunk of `file`, checking it against the rules above. The walk goes on past a
+/// violation whenever the lengths still tell where the next chunk starts.
+pub fn check_png(file: &[u8]) -> PngCheck<'_> {
+    let mut check = PngCheck::default();
+    if !file.starts_with(&PNG_FORMAT_IDENTIFIER) {
+        check.violations.push(Violation::Signature);
+        return check;
+    }
+
+    let mut chunks = Chunks::new(file);
+    for chunk in chunks.by_ref() {
+        match chunk {
+            Ok(chunk) => check.chunks.push(chunk),
+            Err(violation) => check.violations.push(violation),
+        }
+    }
+
+    let violations = &mut check.violations;
+    let mut seen_idat = false;
+    let mut after_idat = false;
+    let seen = |chunk: &RawChunk| {
+        check
+            .chunks
+            .iter()
+            .any(|c| c.chunk_type == chunk.chunk_type && c.offset < chunk.offset)
+    };
+    for (i, chunk) in check.chunks.iter().enumerate() {
+        if !chunk
+            .chunk_type
+            .to_be_bytes()
+            .iter()
+            .all(u8::is_ascii_alphabetic)
+        {
+            violations.push(Violation::BadType {
+                offset: chunk.offset,
+            });
+        }
+        if !chunk.crc_ok() {
+            violations.push(Violation::BadCrc {
+                chunk: chunk.name(),
+                offset: chunk.offset,
+            });
+        }
+        if i == 0 && chunk.chunk_type != IHDR {
+            violations.push(Violation::IhdrNotFirst);
+        }
+        if [IHDR, PLTE].contains(&chunk.chunk_type) && seen(chunk) {
+            violations.push(Violation::Repeated {
+                chunk: chunk.name(),
+                offset: chunk.offset,
+            });
+        }
+        match chunk.chunk_type {
+            PLTE if seen_idat => violations.push(Violation::PlteAfterIdat {
+                offset: chunk.offset,
+            }),
+            IDAT if after_idat => violations.push(Violation::SplitIdat {
+                offset: chunk.offset,
+            }),
+            _ => {}
+        }
+        match chunk.chunk_type {
+            IDAT => seen_idat = true,
+            _ => after_idat = seen_idat,
+        }
+    }
+
+    let has = |chunk_type| check.chunks.iter().any(|c| c.chunk_type == chunk_type);
+    if !has(IDAT) {
+        violations.push(Violation::NoIdat);
+    }
+    let palette = check
+        .chunks
+        .first()
+        .filter(|c| c.chunk_type == IHDR)
+        .and_then(|c| c.data.get(super::IHDR_COLOR_TYPE_OFFSET))
+        == Some(&3);
+    if palette && !has(PLTE) {
+        violations.push(Violation::NoPlte);
+    }
+    match check.chunks.last() {
+        Some(last) if last.chunk_type == IEND => {
+            if chunks.offset() < file.len() {
+                violations.push(Violation::Trailing {
+                    offset: chunks.offset(),
+                    bytes: file.len() - chunks.offset(),
+                });
+            }
+        }
+        _ => violations.push(Violation::NoIend),
+    }
+    check
+}
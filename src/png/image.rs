@@ -0,0 +1,496 @@
+//! Decoding and re-encoding of png image data, owned rather than edited in place.
+
+use super::{check_crc, chunk_crc, write_chunk, IDAT, IEND, IHDR, PNG_FORMAT_IDENTIFIER};
+use crate::{
+    errors::TranError,
+    zlib::{compress, decompress, Compression},
+};
+
+// Starting column, starting row, column step and row step of the seven Adam7 passes
+pub(super) const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedChunk {
+    pub chunk_type: u32,
+    pub data: Vec<u8>,
+}
+
+/// Splits a png into its chunks, checking the signature, that every length fits and every CRC.
+pub fn read_chunks(file: &[u8]) -> Result<Vec<OwnedChunk>, TranError> {
+    if !file.starts_with(&PNG_FORMAT_IDENTIFIER) {
+        return Err(TranError::PngFormatError(
+            "Not a png as it does not start with the png signature".to_string(),
+        ));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = PNG_FORMAT_IDENTIFIER.len();
+    while pos < file.len() {
+        let header = file
+            .get(pos..pos + 8)
+            .ok_or_else(|| TranError::PngFormatError("Ran out of bytes".to_string()))?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk_type = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let data = file.get(pos + 8..pos + 8 + length).ok_or_else(|| {
+            TranError::PngFormatError(format!(
+                "Chunk {:x} declares {} bytes but only {} remain",
+                chunk_type,
+                length,
+                file.len() - pos - 8
+            ))
+        })?;
+        let Some(crc) = file.get(pos + 8 + length..pos + 12 + length) else {
+            return Err(TranError::PngFormatError("Ran out of bytes".to_string()));
+        };
+        check_crc(
+            chunk_type,
+            pos,
+            u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]),
+            chunk_crc(chunk_type, data),
+        )?;
+        chunks.push(OwnedChunk {
+            chunk_type,
+            data: data.to_vec(),
+        });
+        pos += 12 + length;
+        if chunk_type == IEND {
+            break;
+        }
+    }
+
+    if chunks.first().map(|c| c.chunk_type) != Some(IHDR) {
+        return Err(TranError::PngFormatError(
+            "Not a png as it does not start with an IHDR chunk".to_string(),
+        ));
+    }
+    Ok(chunks)
+}
+
+/// Writes `chunks` back into a png, followed by `trailer`, see `trailer`.
+pub fn write_chunks(chunks: &[OwnedChunk], trailer: &[u8]) -> Vec<u8> {
+    let mut out = PNG_FORMAT_IDENTIFIER.to_vec();
+    for chunk in chunks {
+        write_chunk(&mut out, chunk.chunk_type, &chunk.data);
+    }
+    out.extend_from_slice(trailer);
+    out
+}
+
+/// Whatever follows IEND in `file`, kept by every pass that rewrites a png as the in-place
+/// ones keep it.
+pub fn trailer(file: &[u8]) -> &[u8] {
+    let mut chunks = super::check::Chunks::new(file);
+    chunks.by_ref().for_each(drop);
+    &file[chunks.offset().min(file.len())..]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression: u8,
+    pub filter: u8,
+    pub interlace: u8,
+}
+
+// The spec caps both dimensions at 2^31 - 1
+const MAX_DIMENSION: u32 = i32::MAX as u32;
+
+/// The name of a color type and the bit depths the spec allows for it.
+fn color_type_depths(color_type: u8) -> Option<(&'static str, &'static [u8])> {
+    match color_type {
+        0 => Some(("grayscale", &[1, 2, 4, 8, 16])),
+        2 => Some(("RGB", &[8, 16])),
+        3 => Some(("palette", &[1, 2, 4, 8])),
+        4 => Some(("grayscale with alpha", &[8, 16])),
+        6 => Some(("RGBA", &[8, 16])),
+        _ => None,
+    }
+}
+
+impl Ihdr {
+    /// Reads the IHDR chunk data, checking every field against the spec.
+    pub fn parse(data: &[u8]) -> Result<Self, TranError> {
+        if data.len() != 13 {
+            return Err(TranError::PngFormatError(format!(
+                "IHDR is {} bytes instead of 13",
+                data.len()
+            )));
+        }
+        let ihdr = Ihdr {
+            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression: data[10],
+            filter: data[11],
+            interlace: data[12],
+        };
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+
+    fn validate(&self) -> Result<(), TranError> {
+        let invalid = |reason: String| Err(TranError::PngFormatError(format!("IHDR {}", reason)));
+        for (name, value) in [("width", self.width), ("height", self.height)] {
+            if value == 0 || value > MAX_DIMENSION {
+                return invalid(format!(
+                    "{} is {}, it has to be from 1 to {}",
+                    name, value, MAX_DIMENSION
+                ));
+            }
+        }
+        let Some((name, depths)) = color_type_depths(self.color_type) else {
+            return invalid(format!(
+                "color type {} is not one of 0, 2, 3, 4 or 6",
+                self.color_type
+            ));
+        };
+        if !depths.contains(&self.bit_depth) {
+            let depths: Vec<String> = depths.iter().map(|d| d.to_string()).collect();
+            return invalid(format!(
+                "bit depth {} is not allowed for color type {} ({}), which takes {}",
+                self.bit_depth,
+                self.color_type,
+                name,
+                depths.join(", ")
+            ));
+        }
+        for (name, value, max) in [
+            ("compression method", self.compression, 0),
+            ("filter method", self.filter, 0),
+            ("interlace method", self.interlace, 1),
+        ] {
+            if value > max {
+                return invalid(format!("{} {} is unknown", name, value));
+            }
+        }
+        // Every size is computed in usize further on, so it has to fit
+        let rows = (self.width as usize)
+            .checked_mul(self.bits_per_pixel())
+            .map(|bits| bits.div_ceil(8))
+            .and_then(|row| row.checked_add(1))
+            .and_then(|row| row.checked_mul(self.height as usize));
+        if rows.is_none() {
+            return invalid(format!(
+                "dimensions {}x{} are too large for this platform",
+                self.width, self.height
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// Distance to the corresponding byte of the previous pixel, as the filters use it.
+    pub fn filter_distance(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    pub fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Width and height of every pass, a single one unless the image is interlaced.
+    pub fn passes(&self) -> Vec<(u32, u32)> {
+        if self.interlace == 0 {
+            return vec![(self.width, self.height)];
+        }
+        ADAM7
+            .iter()
+            .map(|&(x, y, dx, dy)| {
+                (
+                    (self.width + dx - 1 - x) / dx,
+                    (self.height + dy - 1 - y) / dy,
+                )
+            })
+            .collect()
+    }
+
+    /// Length of the decompressed image data, filter bytes included.
+    pub fn raw_len(&self) -> usize {
+        self.passes()
+            .iter()
+            .filter(|(w, h)| *w > 0 && *h > 0)
+            .map(|&(w, h)| h as usize * (1 + self.row_bytes(w)))
+            .sum()
+    }
+}
+
+/// Unfiltered scanlines of one pass, stored back to back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pass {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Inflates the concatenated IDAT data of `chunks` and undoes the scanline filters.
+pub fn decode(ihdr: &Ihdr, chunks: &[OwnedChunk]) -> Result<Vec<Pass>, TranError> {
+    let compressed: Vec<u8> = chunks
+        .iter()
+        .filter(|c| c.chunk_type == IDAT)
+        .flat_map(|c| c.data.iter().copied())
+        .collect();
+    let raw = decompress(&compressed, ihdr.raw_len())?;
+    if raw.len() != ihdr.raw_len() {
+        return Err(TranError::PngFormatError(format!(
+            "Image data is {} bytes, expected {}",
+            raw.len(),
+            ihdr.raw_len()
+        )));
+    }
+
+    let distance = ihdr.filter_distance();
+    let mut rest = raw.as_slice();
+    let mut passes = Vec::new();
+    for (width, height) in ihdr.passes() {
+        let row_bytes = ihdr.row_bytes(width);
+        let mut data = Vec::with_capacity(row_bytes * height as usize);
+        if width > 0 {
+            for row in 0..height as usize {
+                let (line, tail) = rest.split_at(1 + row_bytes);
+                rest = tail;
+                let start = data.len();
+                for (i, byte) in line[1..].iter().enumerate() {
+                    let a = if i >= distance {
+                        data[start + i - distance]
+                    } else {
+                        0
+                    };
+                    let b = if row > 0 {
+                        data[start + i - row_bytes]
+                    } else {
+                        0
+                    };
+                    let c = if row > 0 && i >= distance {
+                        data[start + i - row_bytes - distance]
+                    } else {
+                        0
+                    };
+                    let value = match line[0] {
+                        0 => *byte,
+                        1 => byte.wrapping_add(a),
+                        2 => byte.wrapping_add(b),
+                        3 => byte.wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                        4 => byte.wrapping_add(paeth(a, b, c)),
+                        filter => {
+                            return Err(TranError::PngFormatError(format!(
+                                "Unknown filter type {}",
+                                filter
+                            )))
+                        }
+                    };
+                    data.push(value);
+                }
+            }
+        }
+        passes.push(Pass {
+            width,
+            height,
+            data,
+        });
+    }
+    Ok(passes)
+}
+
+/// Filters every scanline with the Sub filter, or None where nothing precedes, and deflates.
+pub fn encode(ihdr: &Ihdr, passes: &[Pass]) -> Vec<u8> {
+    let distance = ihdr.filter_distance();
+    let mut raw = Vec::with_capacity(ihdr.raw_len());
+    for pass in passes.iter().filter(|p| p.width > 0) {
+        let row_bytes = ihdr.row_bytes(pass.width);
+        for line in pass.data.chunks(row_bytes) {
+            raw.push(1);
+            for (i, byte) in line.iter().enumerate() {
+                let a = if i >= distance { line[i - distance] } else { 0 };
+                raw.push(byte.wrapping_sub(a));
+            }
+        }
+    }
+    compress(&raw, Compression::Fixed)
+}
+
+/// Replaces the IDAT chunks of `chunks` by `data`, split into as many chunks as there were, of
+/// even length. An image in a single IDAT stays in one.
+pub fn replace_image_data(chunks: &mut Vec<OwnedChunk>, data: Vec<u8>) {
+    let first = chunks.iter().position(|c| c.chunk_type == IDAT);
+    let count = chunks
+        .iter()
+        .filter(|c| c.chunk_type == IDAT)
+        .count()
+        .max(1);
+    chunks.retain(|c| c.chunk_type != IDAT);
+    let at = first.unwrap_or(chunks.len().saturating_sub(1));
+    let idats = (0..count).map(|i| OwnedChunk {
+        chunk_type: IDAT,
+        data: data[i * data.len() / count..(i + 1) * data.len() / count].to_vec(),
+    });
+    chunks.splice(at..at, idats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::build::PngBuilder, *};
+    use crate::{ColorMap, ColorTransform};
+
+    fn idat_count(file: &[u8]) -> usize {
+        read_chunks(file)
+            .unwrap()
+            .iter()
+            .filter(|c| c.chunk_type == IDAT)
+            .count()
+    }
+
+    fn concat_data(idats: &[&OwnedChunk]) -> Vec<u8> {
+        idats.iter().flat_map(|c| c.data.iter().copied()).collect()
+    }
+
+    fn stripes() -> Vec<Vec<(u8, u8, u8)>> {
+        (0..32)
+            .map(|y| {
+                (0..32)
+                    .map(|x| [(0x2e, 0x34, 0x40), (0x88, 0xc0, 0xd0)][(x + y) % 2])
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn image_data_keeps_its_number_of_chunks() {
+        for count in [1, 2, 3, 5] {
+            let file = PngBuilder::rgb(&stripes()).idat_chunks(count).build();
+            let mut chunks = read_chunks(&file).unwrap();
+            let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+            replace_image_data(&mut chunks, data.clone());
+
+            let idats: Vec<&OwnedChunk> = chunks.iter().filter(|c| c.chunk_type == IDAT).collect();
+            assert_eq!(idats.len(), count);
+            assert_eq!(concat_data(&idats), data);
+            let lengths: Vec<usize> = idats.iter().map(|c| c.data.len()).collect();
+            assert!(lengths.iter().max().unwrap() - lengths.iter().min().unwrap() <= 1);
+            assert_eq!(chunks.first().unwrap().chunk_type, IHDR);
+            assert_eq!(chunks.last().unwrap().chunk_type, IEND);
+        }
+    }
+
+    #[test]
+    fn short_image_data_still_fills_every_chunk() {
+        let file = PngBuilder::rgb(&stripes()).idat_chunks(3).build();
+        let mut chunks = read_chunks(&file).unwrap();
+        replace_image_data(&mut chunks, vec![1, 2]);
+        let idats: Vec<&OwnedChunk> = chunks.iter().filter(|c| c.chunk_type == IDAT).collect();
+        assert_eq!(idats.len(), 3);
+        assert_eq!(concat_data(&idats), vec![1, 2]);
+    }
+
+    #[test]
+    fn recoloring_keeps_the_idat_split() {
+        let map = [ColorMap::new("#bf616a", "#88c0d0")];
+        for count in [1, 3] {
+            let mut file = PngBuilder::rgb(&stripes()).idat_chunks(count).build();
+            assert_eq!(idat_count(&file), count);
+            let changes =
+                crate::png::recolor_png_bytes(&mut file, &ColorTransform::Map(&map)).unwrap();
+            assert_eq!(changes, 1);
+            assert_eq!(idat_count(&file), count);
+            assert!(super::super::check::check_png(&file).is_ok());
+        }
+    }
+
+    fn ihdr(width: u32, height: u32, fields: [u8; 5]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend(fields);
+        data
+    }
+
+    #[test]
+    fn ihdr_fields_are_checked_against_the_spec() {
+        let reason = |data: &[u8]| match Ihdr::parse(data) {
+            Err(TranError::PngFormatError(reason)) => reason,
+            other => panic!("expected a format error, got {:?}", other),
+        };
+        let parsed = Ihdr::parse(&ihdr(3, 2, [8, 6, 0, 0, 1])).unwrap();
+        assert_eq!((parsed.width, parsed.height, parsed.interlace), (3, 2, 1));
+        for (color_type, depth) in [(0, 1), (0, 16), (2, 16), (3, 4), (4, 8), (6, 16)] {
+            assert!(Ihdr::parse(&ihdr(1, 1, [depth, color_type, 0, 0, 0])).is_ok());
+        }
+
+        assert_eq!(
+            reason(&ihdr(0, 1, [8, 2, 0, 0, 0])),
+            "IHDR width is 0, it has to be from 1 to 2147483647"
+        );
+        assert_eq!(
+            reason(&ihdr(1, 1 << 31, [8, 2, 0, 0, 0])),
+            "IHDR height is 2147483648, it has to be from 1 to 2147483647"
+        );
+        assert_eq!(
+            reason(&ihdr(1, 1, [8, 5, 0, 0, 0])),
+            "IHDR color type 5 is not one of 0, 2, 3, 4 or 6"
+        );
+        assert_eq!(
+            reason(&ihdr(1, 1, [16, 3, 0, 0, 0])),
+            "IHDR bit depth 16 is not allowed for color type 3 (palette), which takes 1, 2, 4, 8"
+        );
+        assert_eq!(
+            reason(&ihdr(1, 1, [4, 2, 0, 0, 0])),
+            "IHDR bit depth 4 is not allowed for color type 2 (RGB), which takes 8, 16"
+        );
+        assert_eq!(
+            reason(&ihdr(1, 1, [8, 2, 1, 0, 0])),
+            "IHDR compression method 1 is unknown"
+        );
+        assert_eq!(
+            reason(&ihdr(1, 1, [8, 2, 0, 1, 0])),
+            "IHDR filter method 1 is unknown"
+        );
+        assert_eq!(
+            reason(&ihdr(1, 1, [8, 2, 0, 0, 2])),
+            "IHDR interlace method 2 is unknown"
+        );
+        assert!(
+            reason(&ihdr(MAX_DIMENSION, MAX_DIMENSION, [16, 6, 0, 0, 0]))
+                .contains("too large for this platform")
+        );
+        assert!(reason(&ihdr(1, 1, [8, 2, 0, 0, 0])[..12]).contains("12"));
+    }
+}
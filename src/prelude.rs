@@ -0,0 +1,31 @@
+//! The types and functions most uses of tran need, for a single glob import.
+//!
+//! ```
+//! use tran::prelude::*;
+//!
+//! let map = [ColorMap::new("#88c0d0", "#2e3440")];
+//! let (recolored, changes) = recolor_text("background = #2e3440", &ColorTransform::Map(&map))?;
+//! assert_eq!(recolored, "background = #88c0d0");
+//! assert_eq!(changes, 1);
+//!
+//! let color = Color::try_from_hex_str("#2e3440")?;
+//! assert!(color.is_dark());
+//! # Ok::<(), TranError>(())
+//! ```
+//!
+//! `Color` is the parsed color of `config`, colors still written in hex are `HexColor`.
+
+#[cfg(feature = "fs")]
+pub use crate::{
+    config::{parse_config, write_config},
+    plan::{plan, Plan},
+    recolor::recolor_path,
+    recolor_textfile, recolor_textfile_many,
+    run::{run, RunOptions, RunSummary},
+};
+pub use crate::{
+    config::{Color, Config},
+    errors::TranError,
+    recolor::{recolor_bytes, FileFormat, RecolorOptions, RecolorReport},
+    recolor_text, recolor_text_in, ColorMap, ColorTransform, HexColor,
+};
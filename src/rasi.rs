@@ -0,0 +1,262 @@
+//! Rofi themes. Colors are only rewritten in the values of properties, never in comments,
+//! selectors or `@import` lines, in every hex notation rofi reads: `#rgb`, `#rgba`, `#rrggbb`,
+//! `#rrggbbaa` and `argb:aarrggbb`. Alpha is kept as written.
+
+use std::ops::Range;
+
+use crate::{
+    config::{Color, HexCase},
+    css::declarations,
+    errors::TranError,
+    text::{replacements, HexToken, TextOptions, TextReport},
+    ColorTransform,
+};
+
+const ARGB: &str = "argb:";
+
+/// How a color literal is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Notation {
+    /// `#rgb` or `#rgba`, a digit a channel.
+    Short,
+    /// `#rrggbb` or `#rrggbbaa`.
+    Long,
+    /// `argb:aarrggbb`, alpha first.
+    Argb,
+}
+
+struct Literal {
+    token: HexToken,
+    notation: Notation,
+    /// The digits of the red, green and blue channels.
+    digits: Range<usize>,
+    /// The digits of the alpha channel, empty without one.
+    alpha: Range<usize>,
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_'
+}
+
+fn hex_run(bytes: &[u8], at: usize) -> usize {
+    bytes[at..]
+        .iter()
+        .take_while(|b| b.is_ascii_hexdigit())
+        .count()
+}
+
+/// The color literals of `text`, a property value.
+fn literals(text: &str) -> Vec<Literal> {
+    let bytes = text.as_bytes();
+    let mut literals = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let bounded = |end: usize| bytes.get(end).is_none_or(|b| !is_name_byte(*b));
+        let starts_word = i == 0 || !is_name_byte(bytes[i - 1]);
+        let argb = text[i..]
+            .get(..ARGB.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(ARGB));
+        let literal = if bytes[i] == b'#' {
+            let len = hex_run(bytes, i + 1);
+            let at = i + 1;
+            match len {
+                3 | 4 if bounded(at + len) => Some((Notation::Short, at..at + 3, at + 3..at + len)),
+                6 | 8 if bounded(at + len) => Some((Notation::Long, at..at + 6, at + 6..at + len)),
+                _ => None,
+            }
+            .map(|literal| (literal, at + len))
+        } else if argb && starts_word {
+            let at = i + ARGB.len();
+            let len = hex_run(bytes, at);
+            (len == 8 && bounded(at + len))
+                .then(|| ((Notation::Argb, at + 2..at + 8, at..at + 2), at + len))
+        } else {
+            None
+        };
+        let Some(((notation, digits, alpha), end)) = literal else {
+            i += 1;
+            continue;
+        };
+        let hex: String = match notation {
+            Notation::Short => text[digits.clone()].chars().flat_map(|c| [c, c]).collect(),
+            _ => text[digits.clone()].to_string(),
+        };
+        if let Ok(color) = Color::try_from_hex_str(&hex) {
+            literals.push(Literal {
+                token: HexToken {
+                    start: i,
+                    end,
+                    color,
+                },
+                notation,
+                digits,
+                alpha,
+            });
+        }
+        i = end;
+    }
+    literals
+}
+
+/// `literal` of `text` written in `new`, in the notation, case and alpha it was written in. A
+/// shorthand color turns into a long one when `new` has no shorthand.
+fn rewrite(text: &str, literal: &Literal, new: &Color, options: &TextOptions) -> String {
+    let digits = &text[literal.digits.clone()];
+    let alpha = &text[literal.alpha.clone()];
+    let case = match (
+        digits.bytes().any(|b| b.is_ascii_uppercase()),
+        digits.bytes().any(|b| b.is_ascii_lowercase()),
+    ) {
+        (true, false) => HexCase::Upper,
+        (false, true) => HexCase::Lower,
+        _ => options.hex_case,
+    };
+    let hex = new.to_hex(case);
+    match literal.notation {
+        Notation::Long => format!("{}{}", hex, alpha),
+        Notation::Argb => format!(
+            "{}{}{}",
+            &text[literal.token.start..literal.alpha.start],
+            alpha,
+            &hex[1..]
+        ),
+        Notation::Short => {
+            let pairs = hex.as_bytes()[1..].chunks(2);
+            match pairs.clone().all(|pair| pair[0] == pair[1]) {
+                true => format!(
+                    "#{}{}",
+                    pairs.map(|pair| pair[0] as char).collect::<String>(),
+                    alpha
+                ),
+                false => format!("{}{}", hex, alpha.repeat(2)),
+            }
+        }
+    }
+}
+
+/// The pieces of `contents` holding property values.
+fn values(contents: &str, depth: usize) -> Vec<Range<usize>> {
+    declarations(contents, true, depth)
+        .into_iter()
+        .flat_map(|declaration| declaration.value)
+        .collect()
+}
+
+/// Whether `recolor_rasi` rewrites colors of `line`, read as the inside of a block.
+pub fn owns_line(line: &str) -> bool {
+    values(line, 1)
+        .into_iter()
+        .any(|value| !literals(&line[value]).is_empty())
+}
+
+/// The colors `recolor_rasi` would rewrite with `options`, in file order.
+pub fn colors(contents: &str, options: &TextOptions) -> Vec<Color> {
+    values(contents, 0)
+        .into_iter()
+        .flat_map(|value| {
+            let text = &contents[value];
+            literals(text)
+                .into_iter()
+                .filter(|literal| options.allows(text, &literal.token))
+                .map(|literal| literal.token.color)
+        })
+        .collect()
+}
+
+/// Rewrites the colors in the property values of a rofi theme, leaving every other byte as it
+/// was. Names in the report are the properties rewritten, the variables of `* { }` among them.
+pub fn recolor_rasi(
+    contents: &str,
+    transform: &ColorTransform,
+    options: &TextOptions,
+) -> Result<(String, TextReport), TranError> {
+    let replacements = replacements(transform, &colors(contents, options))?;
+    let mut output = String::with_capacity(contents.len());
+    let mut report = TextReport::default();
+
+    let mut last = 0;
+    for declaration in declarations(contents, true, 0) {
+        let mut changed = false;
+        for value in declaration.value {
+            let text = &contents[value.clone()];
+            for literal in literals(text) {
+                if !options.allows(text, &literal.token) {
+                    continue;
+                }
+                let Some((_, new)) = replacements
+                    .iter()
+                    .find(|(old, _)| *old == literal.token.color)
+                else {
+                    continue;
+                };
+                output.push_str(&contents[last..value.start + literal.token.start]);
+                output.push_str(&rewrite(text, &literal, new, options));
+                last = value.start + literal.token.end;
+                report.changes += 1;
+                changed = true;
+            }
+        }
+        let name = &contents[declaration.name];
+        if changed && !report.names.iter().any(|n| n == name) {
+            report.names.push(name.to_string());
+        }
+    }
+    output.push_str(&contents[last..]);
+
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMap;
+
+    fn recolor(contents: &str) -> (String, TextReport) {
+        let map = [
+            ColorMap::new("#bf616a", "#2e3440"),
+            ColorMap::new("#778899", "#112233"),
+        ];
+        recolor_rasi(
+            contents,
+            &ColorTransform::Map(&map),
+            &TextOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn every_notation_keeps_its_alpha_and_case() {
+        let (theme, report) = recolor(
+            "* {\n  bg: #2e3440;\n  bg-alt: #2E3440CC;\n  fg: argb:802e3440;\n  dim: #123;\n  faint: #1238;\n}\n",
+        );
+        assert_eq!(
+            theme,
+            "* {\n  bg: #bf616a;\n  bg-alt: #BF616ACC;\n  fg: argb:80bf616a;\n  dim: #789;\n  faint: #7898;\n}\n"
+        );
+        assert_eq!(report.changes, 5);
+        assert_eq!(report.names, ["bg", "bg-alt", "fg", "dim", "faint"]);
+
+        // Shorthands without a shorthand for the new color are written out
+        let map = [ColorMap::new("#bf616a", "#112233")];
+        let (theme, _) = recolor_rasi(
+            "a { b: #123a; }",
+            &ColorTransform::Map(&map),
+            &TextOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(theme, "a { b: #bf616aaa; }");
+    }
+
+    #[test]
+    fn comments_selectors_and_imports_are_left_alone() {
+        let theme = "@import \"#2e3440.rasi\"\n/* bg: #2e3440; */\n#2e3440 { // #2e3440\n  border: 2px solid #2e3440;\n}\n";
+        let (recolored, report) = recolor(theme);
+        assert_eq!(recolored, theme.replace("solid #2e3440", "solid #bf616a"));
+        assert_eq!(report.changes, 1);
+        assert_eq!(colors(theme, &TextOptions::default()).len(), 1);
+
+        assert!(owns_line("  background-color: argb:ff2e3440;"));
+        assert!(!owns_line("  // background-color: #2e3440;"));
+        assert!(!owns_line("  font: \"mono 12\";"));
+    }
+}
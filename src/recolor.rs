@@ -0,0 +1,1846 @@
+#[cfg(feature = "fs")]
+use std::io::Read;
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+#[cfg(feature = "png")]
+use crate::png::PngOptions;
+use crate::{
+    config::Color,
+    errors::TranError,
+    exec_handler::ExecCommand,
+    ini::IniOptions,
+    json::Json,
+    text::{NearMatch, TextOptions},
+    ColorTransform,
+};
+
+const PNG_MAGIC: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+/// Endings editors and merge tools give the copies they leave behind.
+pub const BACKUP_SUFFIXES: [&str; 3] = [".bak", ".orig", "~"];
+pub const DEFAULT_OUTPUT_NAME: &str = "{stem}_{color}.{ext}";
+const OUTPUT_NAME_FIELDS: [&str; 6] = ["stem", "ext", "color", "colors", "index", "date"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileFormat {
+    Text,
+    Png,
+    Gtk,
+    I3,
+    /// Stylesheets and svgs keeping their colors in custom properties.
+    Css,
+    /// JSON and JSONC documents, such as VS Code and Zed settings and themes.
+    Json,
+    /// Rofi themes, see `rasi`.
+    Rasi,
+    /// Ini-style configs such as dunst's and mako's, see `ini`.
+    Ini,
+    /// Handed to the `exec` command of the target.
+    Exec,
+}
+
+/// How a file is handled, worked out from its name and first bytes alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dispatch {
+    pub format: FileFormat,
+    /// The format the extension names, when the content says `format` instead.
+    pub mismatch: Option<FileFormat>,
+    /// The name ends in one of `BACKUP_SUFFIXES`, such files are skipped unless asked for.
+    pub backup: bool,
+}
+
+impl Dispatch {
+    /// The warning for a file whose extension and content disagree.
+    pub fn mismatch_warning(&self, path: &Path) -> Option<String> {
+        self.mismatch.map(|named| {
+            format!(
+                "WARNING: {} is named as {} but holds {}, recoloring it as {}",
+                path.display(),
+                named,
+                self.format,
+                self.format
+            )
+        })
+    }
+}
+
+/// The dispatch of a file named `name` starting with `head`. The extension is read after
+/// taking off a backup suffix and without case, and the content wins over it whenever there is
+/// content to go by:
+///
+/// | name              | content   | format | mismatch | backup |
+/// |-------------------|-----------|--------|----------|--------|
+/// | `ICON.PNG`        | png       | png    |          |        |
+/// | `ICON.PNG`        | empty     | png    |          |        |
+/// | `logo.png.bak`    | png       | png    |          | yes    |
+/// | `archive.tar.png` | not png   | text   | png      |        |
+/// | `wallpaper`       | png       | png    |          |        |
+/// | `colors.conf~`    | text      | text   |          | yes    |
+/// | `theme.rasi`      | text      | rasi   |          |        |
+/// | `theme.rasi`      | png       | png    | rasi     |        |
+pub fn dispatch(name: &OsStr, head: &[u8]) -> Dispatch {
+    let name = name.to_string_lossy();
+    let stripped = strip_backup_suffix(&name);
+    let named = match Path::new(stripped.unwrap_or(&name))
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        // Windows keeps `.PNG` as the camera wrote it
+        Some(ext) if ext.eq_ignore_ascii_case("png") => Some(FileFormat::Png),
+        Some(ext) if ext.eq_ignore_ascii_case("rasi") => Some(FileFormat::Rasi),
+        _ => None,
+    };
+    let found = match head {
+        [] => None,
+        head if head.starts_with(&PNG_MAGIC) => Some(FileFormat::Png),
+        _ => Some(FileFormat::Text),
+    };
+    let format = match (found, named) {
+        // Text is all content can tell of the formats named by their extension
+        (Some(FileFormat::Text), Some(named)) if named != FileFormat::Png => named,
+        _ => found.or(named).unwrap_or(FileFormat::Text),
+    };
+    Dispatch {
+        format,
+        mismatch: named.filter(|&named| named != format),
+        backup: stripped.is_some(),
+    }
+}
+
+fn strip_backup_suffix(name: &str) -> Option<&str> {
+    BACKUP_SUFFIXES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix).filter(|rest| !rest.is_empty()))
+}
+
+/// Whether `name` ends in one of `BACKUP_SUFFIXES`.
+pub fn is_backup(name: &OsStr) -> bool {
+    strip_backup_suffix(&name.to_string_lossy()).is_some()
+}
+
+impl FileFormat {
+    /// The format `dispatch` picks for `path`.
+    pub fn detect(path: &Path, head: &[u8]) -> FileFormat {
+        dispatch(path.file_name().unwrap_or_default(), head).format
+    }
+
+    /// Whether the handler can write its result somewhere other than the source.
+    pub fn supports_output_copy(&self) -> bool {
+        match self {
+            FileFormat::Text
+            | FileFormat::Gtk
+            | FileFormat::I3
+            | FileFormat::Css
+            | FileFormat::Json
+            | FileFormat::Rasi
+            | FileFormat::Ini => false,
+            FileFormat::Png | FileFormat::Exec => true,
+        }
+    }
+}
+
+impl TryFrom<&str> for FileFormat {
+    type Error = TranError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "text" => Ok(FileFormat::Text),
+            "png" => Ok(FileFormat::Png),
+            "gtk" => Ok(FileFormat::Gtk),
+            "i3" => Ok(FileFormat::I3),
+            "css" => Ok(FileFormat::Css),
+            "json" => Ok(FileFormat::Json),
+            "rasi" => Ok(FileFormat::Rasi),
+            "ini" => Ok(FileFormat::Ini),
+            _ => Err(TranError::ConfigError(format!(
+                "Unrecognized file format '{}', valid formats are 'text', 'png', 'gtk', 'i3', 'css', 'json', 'rasi' and 'ini'",
+                value
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileFormat::Text => write!(f, "text"),
+            FileFormat::Png => write!(f, "png"),
+            FileFormat::Gtk => write!(f, "gtk"),
+            FileFormat::I3 => write!(f, "i3"),
+            FileFormat::Css => write!(f, "css"),
+            FileFormat::Json => write!(f, "json"),
+            FileFormat::Rasi => write!(f, "rasi"),
+            FileFormat::Ini => write!(f, "ini"),
+            FileFormat::Exec => write!(f, "exec"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum BackupPolicy {
+    #[default]
+    None,
+    Directory(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecolorOptions {
+    pub overwrite: bool,
+    pub output_name: String,
+    pub backup: BackupPolicy,
+    pub format: Option<FileFormat>,
+    /// Structured handlers (gtk, i3, css) also rewrite colors outside the lines they own,
+    /// literals for gtk and css and `client.*` lines for i3.
+    pub literals: bool,
+    #[cfg(feature = "png")]
+    pub png: PngOptions,
+    /// Directories every file read or written has to be in once symlinks are resolved, empty
+    /// allows any.
+    pub allowed_roots: Vec<PathBuf>,
+    /// Where copies go instead of next to their sources, ignored when overwriting.
+    pub output_dir: Option<OutputDir>,
+    /// What happens when a copy would be written over a file that is already there.
+    pub collision: CollisionPolicy,
+    /// The command `FileFormat::Exec` runs.
+    pub exec: Option<ExecCommand>,
+    /// Gets every color the text and png handlers replace.
+    pub journal: Option<ReplacementLog>,
+    /// Which colors text handlers take and the case they write them in.
+    pub text: TextOptions,
+    /// Which keys and sections the ini handler rewrites.
+    pub ini: IniOptions,
+    /// Told as a run starts and finishes every target.
+    pub progress: Option<ProgressLog>,
+    /// Stops a run between targets, see `Cancellation`.
+    pub cancel: Option<Cancellation>,
+}
+
+impl Default for RecolorOptions {
+    fn default() -> Self {
+        RecolorOptions {
+            overwrite: false,
+            output_name: DEFAULT_OUTPUT_NAME.to_string(),
+            backup: BackupPolicy::None,
+            format: None,
+            literals: false,
+            #[cfg(feature = "png")]
+            png: PngOptions::default(),
+            allowed_roots: Vec::new(),
+            output_dir: None,
+            collision: CollisionPolicy::default(),
+            exec: None,
+            journal: None,
+            text: TextOptions::default(),
+            ini: IniOptions::default(),
+            progress: None,
+            cancel: None,
+        }
+    }
+}
+
+/// Where in a file a replaced color was.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Position {
+    /// A hex color in text, `line` and `column` counting from 1 and `column` in bytes.
+    Text {
+        offset: usize,
+        line: usize,
+        column: usize,
+    },
+    /// An entry of a png palette, `offset` being where its bytes start.
+    Palette { index: usize, offset: usize },
+}
+
+/// One color a handler replaced in `file`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replacement {
+    pub file: PathBuf,
+    pub position: Position,
+    pub old: Color,
+    pub new: Color,
+}
+
+impl Replacement {
+    pub fn to_json(&self) -> Json {
+        let json = Json::object().with("file", self.file.to_string_lossy().as_ref());
+        let json = match self.position {
+            Position::Text {
+                offset,
+                line,
+                column,
+            } => json
+                .with("offset", offset)
+                .with("line", line)
+                .with("column", column),
+            Position::Palette { index, offset } => {
+                json.with("palette_index", index).with("offset", offset)
+            }
+        };
+        json.with("old", self.old.to_string())
+            .with("new", self.new.to_string())
+    }
+
+    pub fn from_json(json: &Json) -> Option<Self> {
+        let number = |key: &str| json.get(key)?.as_u64().map(|n| n as usize);
+        let color = |key: &str| Color::try_from_hex_str(json.get(key)?.as_str()?).ok();
+        let offset = number("offset")?;
+        let position = match number("palette_index") {
+            Some(index) => Position::Palette { index, offset },
+            None => Position::Text {
+                offset,
+                line: number("line")?,
+                column: number("column")?,
+            },
+        };
+        Some(Replacement {
+            file: PathBuf::from(json.get("file")?.as_str()?),
+            position,
+            old: color("old")?,
+            new: color("new")?,
+        })
+    }
+}
+
+/// Receives the replacements of a run as they are made, from several threads with the
+/// `parallel` feature.
+#[derive(Clone)]
+pub struct ReplacementLog(Arc<dyn Fn(&Replacement) + Send + Sync>);
+
+impl ReplacementLog {
+    pub fn new(log: impl Fn(&Replacement) + Send + Sync + 'static) -> Self {
+        ReplacementLog(Arc::new(log))
+    }
+
+    pub fn log(&self, replacement: &Replacement) {
+        (self.0)(replacement)
+    }
+}
+
+impl std::fmt::Debug for ReplacementLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ReplacementLog")
+    }
+}
+
+// Two logs are the same when they are clones of one another
+impl PartialEq for ReplacementLog {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ReplacementLog {}
+
+/// Where a run is in its targets. `index` is the place of the target in the config and
+/// `total` how many targets the run recolors. With the `parallel` feature targets finish out
+/// of order, `done` counts those finished so far, this one included.
+#[derive(Debug)]
+pub enum ProgressEvent<'a> {
+    Started {
+        path: &'a Path,
+        index: usize,
+        total: usize,
+    },
+    Finished {
+        path: &'a Path,
+        index: usize,
+        total: usize,
+        done: usize,
+        changes: usize,
+    },
+    Failed {
+        path: &'a Path,
+        index: usize,
+        total: usize,
+        done: usize,
+        error: &'a TranError,
+    },
+}
+
+/// Receives the progress of a run, from several threads with the `parallel` feature.
+#[derive(Clone)]
+pub struct ProgressLog(Arc<dyn Fn(ProgressEvent) + Send + Sync>);
+
+impl ProgressLog {
+    pub fn new(log: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        ProgressLog(Arc::new(log))
+    }
+
+    pub fn log(&self, event: ProgressEvent) {
+        (self.0)(event)
+    }
+}
+
+impl std::fmt::Debug for ProgressLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressLog")
+    }
+}
+
+impl PartialEq for ProgressLog {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressLog {}
+
+/// Asks a run to stop, from another thread or a signal handler. A run checks between targets
+/// and between the passes over a png, so the target being written when it is cancelled is
+/// either finished or left as it was, never half written. Clones cancel one another.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Cancellation::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// `TranError::Cancelled` once cancelled.
+    pub fn check(&self) -> Result<(), TranError> {
+        match self.is_cancelled() {
+            true => Err(TranError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+impl From<Arc<AtomicBool>> for Cancellation {
+    fn from(flag: Arc<AtomicBool>) -> Self {
+        Cancellation(flag)
+    }
+}
+
+impl PartialEq for Cancellation {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Cancellation {}
+
+/// Logs the hex colors that differ between `old` and `new`. Handlers replace a color with one
+/// of the same length, so tokens of both line up.
+#[cfg(feature = "fs")]
+fn log_text(log: &ReplacementLog, file: &Path, old: &str, new: &str) {
+    let old_tokens = crate::text::hex_tokens(old);
+    let new_tokens = crate::text::hex_tokens(new);
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut counted = 0;
+    for (before, after) in old_tokens.iter().zip(new_tokens.iter()) {
+        if before.start != after.start || before.color == after.color {
+            continue;
+        }
+        for (i, byte) in old.as_bytes()[counted..before.start].iter().enumerate() {
+            if *byte == b'\n' {
+                line += 1;
+                line_start = counted + i + 1;
+            }
+        }
+        counted = before.start;
+        log.log(&Replacement {
+            file: file.to_path_buf(),
+            position: Position::Text {
+                offset: before.start,
+                line,
+                column: before.start - line_start + 1,
+            },
+            old: before.color,
+            new: after.color,
+        });
+    }
+}
+
+/// Logs the palette entries that differ between the pngs `old` and `new`. A palette that
+/// changed size, as when an rgb png was palettized, can't be compared entry by entry.
+#[cfg(all(feature = "fs", feature = "png"))]
+fn log_palette(log: &ReplacementLog, file: &Path, old: &[u8], new: &[u8]) {
+    let (Ok(old), Ok(new)) = (
+        crate::png::palette_entries(old),
+        crate::png::palette_entries(new),
+    ) else {
+        return;
+    };
+    if old.len() != new.len() {
+        return;
+    }
+    for (index, ((offset, before), (_, after))) in old.iter().zip(new.iter()).enumerate() {
+        if before != after {
+            log.log(&Replacement {
+                file: file.to_path_buf(),
+                position: Position::Palette {
+                    index,
+                    offset: *offset,
+                },
+                old: *before,
+                new: *after,
+            });
+        }
+    }
+}
+
+/// What to do when the output name of a copy is taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CollisionPolicy {
+    #[default]
+    Error,
+    Overwrite,
+    /// Leave the file there and count the target as done.
+    Skip,
+    /// Append `-1`, `-2`, … to the stem until the name is free.
+    Number,
+}
+
+impl TryFrom<&str> for CollisionPolicy {
+    type Error = TranError;
+
+    fn try_from(value: &str) -> Result<Self, TranError> {
+        match value {
+            "error" => Ok(CollisionPolicy::Error),
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "skip" => Ok(CollisionPolicy::Skip),
+            "number" => Ok(CollisionPolicy::Number),
+            _ => Err(TranError::ConfigError(format!(
+                "Unrecognized collision policy '{}', valid policies are 'error', 'overwrite', 'skip' and 'number'",
+                value
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollisionPolicy::Error => write!(f, "error"),
+            CollisionPolicy::Overwrite => write!(f, "overwrite"),
+            CollisionPolicy::Skip => write!(f, "skip"),
+            CollisionPolicy::Number => write!(f, "number"),
+        }
+    }
+}
+
+/// `path` with `-n` appended to its stem.
+#[cfg(feature = "fs")]
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{}", n));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// The path a copy meant for `output` is written to under `policy`, `None` when it is skipped.
+/// With `reserve` the path is created empty, so targets recolored in parallel never pick the
+/// same number.
+#[cfg(feature = "fs")]
+pub fn claim_output(
+    output: &Path,
+    policy: CollisionPolicy,
+    reserve: bool,
+) -> Result<Option<PathBuf>, TranError> {
+    let claim = |path: &Path| -> std::io::Result<()> {
+        if reserve {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map(drop)
+        } else if path.exists() {
+            Err(std::io::ErrorKind::AlreadyExists.into())
+        } else {
+            Ok(())
+        }
+    };
+    let taken = |e: &std::io::Error| e.kind() == std::io::ErrorKind::AlreadyExists;
+    match policy {
+        CollisionPolicy::Overwrite => Ok(Some(output.to_path_buf())),
+        CollisionPolicy::Error | CollisionPolicy::Skip => match claim(output) {
+            Ok(()) => Ok(Some(output.to_path_buf())),
+            Err(e) if taken(&e) && policy == CollisionPolicy::Skip => Ok(None),
+            Err(e) if taken(&e) => Err(TranError::OutputExists(output.to_path_buf())),
+            Err(e) => Err(TranError::write(output, e)),
+        },
+        CollisionPolicy::Number => {
+            for n in 0.. {
+                let candidate = match n {
+                    0 => output.to_path_buf(),
+                    n => numbered(output, n),
+                };
+                match claim(&candidate) {
+                    Ok(()) => return Ok(Some(candidate)),
+                    Err(e) if taken(&e) => continue,
+                    Err(e) => return Err(TranError::write(&candidate, e)),
+                }
+            }
+            unreachable!("every number was taken")
+        }
+    }
+}
+
+/// A directory copies are written under, mirroring where their sources are relative to `base`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputDir {
+    pub dir: PathBuf,
+    /// Sources outside `base` are mirrored from the root. `None` until `around` picks the
+    /// common directory of the targets.
+    pub base: Option<PathBuf>,
+}
+
+/// The drive or share of a Windows path as a directory name, `C:` becoming `C`.
+fn drive_name(prefix: std::path::PrefixComponent) -> OsString {
+    let name: String = prefix
+        .as_os_str()
+        .to_string_lossy()
+        .chars()
+        .filter(|c| !matches!(c, '\\' | '/' | ':' | '?' | '.'))
+        .collect();
+    OsString::from(name)
+}
+
+/// `path` made absolute against the working directory, without touching the filesystem.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl OutputDir {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        OutputDir {
+            dir: dir.into(),
+            base: None,
+        }
+    }
+
+    /// This directory with `base` set to the deepest directory holding every one of
+    /// `sources`, unless a base was configured.
+    pub fn around<'a>(&self, sources: impl IntoIterator<Item = &'a Path>) -> Self {
+        if self.base.is_some() {
+            return self.clone();
+        }
+        let mut base: Option<PathBuf> = None;
+        for source in sources {
+            let parent = absolute(source)
+                .parent()
+                .map_or_else(PathBuf::new, Path::to_path_buf);
+            base = Some(match base {
+                None => parent,
+                Some(base) => base
+                    .components()
+                    .zip(parent.components())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a)
+                    .collect(),
+            });
+        }
+        OutputDir {
+            dir: self.dir.clone(),
+            base,
+        }
+    }
+
+    /// Where `path`, an output next to its source, goes under this directory.
+    pub fn mirror(&self, path: &Path) -> PathBuf {
+        let path = absolute(path);
+        let relative = self
+            .base
+            .as_deref()
+            .and_then(|base| path.strip_prefix(absolute(base)).ok())
+            .map_or_else(
+                || {
+                    // The drive is kept as a directory, so C:\a and D:\a don't meet
+                    path.components()
+                        .filter_map(|c| match c {
+                            std::path::Component::Prefix(prefix) => Some(drive_name(prefix)),
+                            std::path::Component::Normal(part) => Some(part.to_os_string()),
+                            _ => None,
+                        })
+                        .collect()
+                },
+                Path::to_path_buf,
+            );
+        self.dir.join(relative)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecolorReport {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub format: FileFormat,
+    pub backup: Option<PathBuf>,
+    pub changes: usize,
+    /// Named colors the handler updated, for handlers that know about names.
+    pub names: Vec<String>,
+    /// Colors close to mapped colors that were not found, for text handlers.
+    pub near_matches: Vec<NearMatch>,
+}
+
+#[cfg(feature = "fs")]
+pub(crate) fn read_head(path: &Path) -> Result<Vec<u8>, TranError> {
+    let mut head = Vec::with_capacity(PNG_MAGIC.len());
+    std::fs::File::open(path)
+        .and_then(|f| f.take(PNG_MAGIC.len() as u64).read_to_end(&mut head))
+        .map_err(|e| TranError::read(path, e))?;
+    Ok(head)
+}
+
+/// Color used for the `{color}` placeholder: the gradient primary, or the first new color of a map.
+/// A chain takes the one of its last stage.
+pub fn output_color(transform: &ColorTransform) -> String {
+    match transform {
+        ColorTransform::Gradient { primary, .. } => primary.to_string(),
+        ColorTransform::Simulate(deficiency) => deficiency.to_string(),
+        ColorTransform::Map(map) => map
+            .first()
+            .map(|c| c.get_new_color().to_string())
+            .unwrap_or_default(),
+        ColorTransform::Chain(stages) => stages.last().map(output_color).unwrap_or_default(),
+    }
+}
+
+/// Every new color of `transform` back to back, for the `{colors}` placeholder.
+pub fn output_colors(transform: &ColorTransform) -> String {
+    match transform {
+        ColorTransform::Gradient { primary, .. } => primary.to_string(),
+        ColorTransform::Simulate(deficiency) => deficiency.to_string(),
+        ColorTransform::Map(map) => map.iter().map(|c| c.get_new_color()).collect(),
+        ColorTransform::Chain(stages) => stages.iter().map(output_colors).collect(),
+    }
+}
+
+/// Checks that `template` only uses placeholders an output name can be built from.
+pub fn validate_output_name(template: &str) -> Result<(), TranError> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let end = after.find('}').ok_or_else(|| {
+            TranError::ConfigError(format!(
+                "Unclosed placeholder in output name '{}'",
+                template
+            ))
+        })?;
+        if !OUTPUT_NAME_FIELDS.contains(&&after[..end]) {
+            return Err(TranError::ConfigError(format!(
+                "Unknown placeholder '{{{}}}' in output name, valid placeholders are {}",
+                &after[..end],
+                OUTPUT_NAME_FIELDS.map(|f| format!("{{{}}}", f)).join(", ")
+            )));
+        }
+        rest = &after[end + 1..];
+    }
+    Ok(())
+}
+
+/// Fills `{index}`, the position of the selection in the config, and `{date}` in `template`.
+/// They depend on the run rather than the file, so they are filled before targets are.
+pub fn fill_run_fields(template: &str, index: usize, date: &str) -> String {
+    template
+        .replace("{index}", &index.to_string())
+        .replace("{date}", date)
+}
+
+/// Expands `{stem}`, `{ext}`, `{color}` and `{colors}` in `template` next to `source`.
+/// Works on `OsStr` so file names that aren't valid utf-8 are carried through untouched.
+pub fn output_path(source: &Path, template: &str, transform: &ColorTransform) -> PathBuf {
+    let stem = source.file_stem().unwrap_or_else(|| OsStr::new(""));
+    let ext = source.extension().unwrap_or_else(|| OsStr::new(""));
+
+    // A source without an extension would otherwise end in a dangling '.'
+    let template = if ext.is_empty() {
+        template.replace(".{ext}", "")
+    } else {
+        template.to_string()
+    };
+
+    let mut name = OsString::new();
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find('{') {
+        name.push(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(end) = after.find('}') {
+            match &after[1..end] {
+                "stem" => name.push(stem),
+                "ext" => name.push(ext),
+                "color" => name.push(output_color(transform)),
+                "colors" => name.push(output_colors(transform)),
+                _ => name.push(&after[..=end]),
+            }
+            rest = &after[end + 1..];
+        } else {
+            name.push(after);
+            rest = "";
+        }
+    }
+    name.push(rest);
+
+    source.with_file_name(name)
+}
+
+/// Recolors `data` in memory with the handler for `format`, returning the result and the
+/// number of changes. `literals`, `text` and `ini` are as in `RecolorOptions`.
+pub fn recolor_bytes(
+    data: &[u8],
+    format: FileFormat,
+    transform: &ColorTransform,
+    literals: bool,
+    text: &TextOptions,
+    ini: &IniOptions,
+) -> Result<(Vec<u8>, usize), TranError> {
+    if format == FileFormat::Exec {
+        return Err(TranError::UnsupportedError(
+            "An exec command only recolors files".to_string(),
+        ));
+    }
+    if format == FileFormat::Png {
+        #[cfg(feature = "png")]
+        {
+            let mut png = data.to_vec();
+            let changes = crate::png::recolor_png_bytes(&mut png, transform)?;
+            return Ok((png, changes));
+        }
+        #[cfg(not(feature = "png"))]
+        return Err(TranError::without_png());
+    }
+    let contents = std::str::from_utf8(data).map_err(|e| TranError::FileReadError {
+        path: None,
+        source: std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+    })?;
+    let (updated, changes) = match format {
+        FileFormat::Gtk => {
+            let (updated, report) = crate::gtk::recolor_gtk(contents, transform, literals, text)?;
+            (updated, report.changes)
+        }
+        FileFormat::I3 => {
+            let (updated, report) = crate::i3::recolor_i3(contents, transform, literals, text)?;
+            (updated, report.changes)
+        }
+        FileFormat::Css => {
+            let (updated, report) = crate::css::recolor_css(contents, transform, literals, text)?;
+            (updated, report.changes)
+        }
+        FileFormat::Json => {
+            let (updated, report) = crate::jsonc::recolor_json(contents, transform, text)?;
+            (updated, report.changes)
+        }
+        FileFormat::Rasi => {
+            let (updated, report) = crate::rasi::recolor_rasi(contents, transform, text)?;
+            (updated, report.changes)
+        }
+        FileFormat::Ini => {
+            let (updated, report) = crate::ini::recolor_ini(contents, transform, ini, text)?;
+            (updated, report.changes)
+        }
+        _ => crate::recolor_text_in(contents, transform, text)?,
+    };
+    Ok((updated.into_bytes(), changes))
+}
+
+/// The handler and output `source` gets under `options`, `head` being its first bytes.
+pub fn resolve_output(
+    source: &Path,
+    head: &[u8],
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+) -> (FileFormat, PathBuf) {
+    let format = options
+        .format
+        .unwrap_or_else(|| FileFormat::detect(source, head));
+    let output = if options.overwrite || !format.supports_output_copy() {
+        source.to_path_buf()
+    } else {
+        let output = output_path(source, &options.output_name, transform);
+        match &options.output_dir {
+            Some(dir) => dir.mirror(&output),
+            None => output,
+        }
+    };
+    (format, output)
+}
+
+#[cfg(feature = "fs")]
+fn backup(source: &Path, policy: &BackupPolicy) -> Result<Option<PathBuf>, TranError> {
+    match policy {
+        BackupPolicy::None => Ok(None),
+        BackupPolicy::Directory(dir) => {
+            crate::perms::create_dir_all(dir).map_err(|e| TranError::write(dir, e))?;
+            let absolute = std::fs::canonicalize(source).map_err(|e| TranError::read(source, e))?;
+            let mut name = OsString::new();
+            for (i, component) in absolute
+                .components()
+                .filter_map(|c| match c {
+                    std::path::Component::Prefix(prefix) => Some(drive_name(prefix)),
+                    std::path::Component::Normal(part) => Some(part.to_os_string()),
+                    _ => None,
+                })
+                .enumerate()
+            {
+                if i > 0 {
+                    name.push("%");
+                }
+                name.push(component);
+            }
+            let target = dir.join(name);
+            // Not `fs::copy`, which would give the backup the mode of the source
+            std::fs::File::open(source)
+                .map_err(|e| TranError::read(source, e))
+                .and_then(|mut file| {
+                    crate::perms::create(&target)
+                        .and_then(|mut backup| std::io::copy(&mut file, &mut backup))
+                        .map_err(|e| TranError::write(&target, e))
+                })?;
+            Ok(Some(target))
+        }
+    }
+}
+
+/// Fails unless `path` resolves to somewhere under one of `roots`. An output that doesn't exist
+/// yet is judged by its directory.
+#[cfg(feature = "fs")]
+fn check_roots(path: &Path, roots: &[PathBuf]) -> Result<(), TranError> {
+    if roots.is_empty() {
+        return Ok(());
+    }
+    // Directories of an output under `output_dir` may not exist yet either
+    let absolute = absolute(path);
+    let resolved = absolute.ancestors().find_map(|existing| {
+        let rest = absolute.strip_prefix(existing).ok()?;
+        let plain = rest
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+        plain
+            .then(|| std::fs::canonicalize(existing).ok())
+            .flatten()
+            .map(|existing| existing.join(rest))
+    });
+    let Some(resolved) = resolved else {
+        return Err(TranError::OutsideAllowedRoots(path.to_path_buf()));
+    };
+    // Components are compared whole, so /home/me/dots doesn't allow /home/me/dotsfoo
+    let allowed = roots
+        .iter()
+        .filter_map(|root| std::fs::canonicalize(root).ok())
+        .any(|root| resolved.starts_with(root));
+    if allowed {
+        Ok(())
+    } else {
+        Err(TranError::OutsideAllowedRoots(path.to_path_buf()))
+    }
+}
+
+#[cfg(feature = "fs")]
+pub fn recolor_path(
+    source: &Path,
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+) -> Result<RecolorReport, TranError> {
+    recolor_path_inner(source, None, options, transform, true).map_err(|e| e.in_file(source))
+}
+
+/// Like `recolor_path`, but writes the result to `output` whatever the handler, so `source`
+/// is never modified.
+#[cfg(feature = "fs")]
+pub fn recolor_path_to(
+    source: &Path,
+    output: &Path,
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+) -> Result<RecolorReport, TranError> {
+    recolor_path_inner(source, Some(output), options, transform, true)
+        .map_err(|e| e.in_file(source))
+}
+
+/// Like `recolor_path`, but nothing is written. The report says what a run would change.
+#[cfg(feature = "fs")]
+pub fn recolor_path_dry(
+    source: &Path,
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+) -> Result<RecolorReport, TranError> {
+    recolor_path_inner(source, None, options, transform, false).map_err(|e| e.in_file(source))
+}
+
+#[cfg(feature = "fs")]
+fn recolor_path_inner(
+    source: &Path,
+    output: Option<&Path>,
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+    write: bool,
+) -> Result<RecolorReport, TranError> {
+    if !source.is_file() {
+        return Err(TranError::FileNotFoundError(source.to_path_buf()));
+    }
+
+    let head = match options.format {
+        Some(_) => Vec::new(),
+        None => read_head(source)?,
+    };
+    let (format, resolved) = resolve_output(source, &head, options, transform);
+    // A named output is the caller's choice, the collision policy is for generated names
+    let claimed = output.is_none() && resolved != source;
+    let output = output.map_or(resolved, Path::to_path_buf);
+    check_roots(source, &options.allowed_roots)?;
+    check_roots(&output, &options.allowed_roots)?;
+    #[cfg(not(feature = "png"))]
+    if format == FileFormat::Png {
+        return Err(TranError::without_png());
+    }
+
+    let backup = if write && output == source {
+        backup(source, &options.backup)?
+    } else {
+        None
+    };
+    if write && output != source {
+        if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| TranError::write(dir, e))?;
+        }
+    }
+    let output = match claimed {
+        true => match claim_output(&output, options.collision, write)? {
+            Some(output) => output,
+            None => {
+                return Ok(RecolorReport {
+                    source: source.to_path_buf(),
+                    output,
+                    format,
+                    backup,
+                    changes: 0,
+                    names: Vec::new(),
+                    near_matches: Vec::new(),
+                })
+            }
+        },
+        false => output,
+    };
+    let reserved = claimed && write && options.collision != CollisionPolicy::Overwrite;
+
+    let (changes, names, near_matches) =
+        match handle(source, &output, format, options, transform, write) {
+            Err(e) if reserved => {
+                let _ = std::fs::remove_file(&output);
+                return Err(e);
+            }
+            result => result?,
+        };
+    // A copy is as private as the file it was made from
+    if write && output != source && output.is_file() {
+        let permissions = std::fs::metadata(source)
+            .map_err(|e| TranError::read(source, e))?
+            .permissions();
+        std::fs::set_permissions(&output, permissions).map_err(|e| TranError::write(&output, e))?;
+    }
+
+    Ok(RecolorReport {
+        source: source.to_path_buf(),
+        output,
+        format,
+        backup,
+        changes,
+        names,
+        near_matches,
+    })
+}
+
+/// The png options of `options`, checking its cancellation.
+#[cfg(all(feature = "fs", feature = "png"))]
+fn png_options(options: &RecolorOptions) -> PngOptions {
+    PngOptions {
+        cancel: options.cancel.clone(),
+        ..options.png.clone()
+    }
+}
+
+/// Runs the handler for `format`, writing to `output` when `write` is set. Returns the changes,
+/// the names and the near matches of the report.
+#[cfg(feature = "fs")]
+fn handle(
+    source: &Path,
+    output: &Path,
+    format: FileFormat,
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+    write: bool,
+) -> Result<(usize, Vec<String>, Vec<NearMatch>), TranError> {
+    let mut names = Vec::new();
+    let mut near_matches = Vec::new();
+    let changes = match format {
+        #[cfg(feature = "png")]
+        FileFormat::Png if write => match &options.journal {
+            Some(log) => {
+                let before = std::fs::read(source).map_err(|e| TranError::read(source, e))?;
+                let changes =
+                    crate::png::recolor_png_with(source, output, transform, &png_options(options))?;
+                let after = std::fs::read(output).map_err(|e| TranError::read(output, e))?;
+                log_palette(log, source, &before, &after);
+                changes
+            }
+            None => crate::png::recolor_png_with(source, output, transform, &png_options(options))?,
+        },
+        #[cfg(feature = "png")]
+        FileFormat::Png => {
+            let mut file = std::fs::read(source).map_err(|e| TranError::read(source, e))?;
+            if options.png.palettize_rgb {
+                if let Some(palettized) =
+                    crate::png::palettize_bytes(&file, crate::png::MAX_PALETTE_COLORS)?
+                {
+                    file = palettized;
+                }
+            }
+            crate::png::recolor_png_bytes(&mut file, transform)?
+        }
+        // Refused before any backup was made
+        #[cfg(not(feature = "png"))]
+        FileFormat::Png => 0,
+        FileFormat::Gtk
+        | FileFormat::I3
+        | FileFormat::Css
+        | FileFormat::Json
+        | FileFormat::Rasi
+        | FileFormat::Ini => {
+            let contents =
+                std::fs::read_to_string(source).map_err(|e| TranError::read(source, e))?;
+            let (updated, report) = match format {
+                FileFormat::Gtk => {
+                    crate::gtk::recolor_gtk(&contents, transform, options.literals, &options.text)?
+                }
+                FileFormat::Css => {
+                    crate::css::recolor_css(&contents, transform, options.literals, &options.text)?
+                }
+                FileFormat::Json => {
+                    crate::jsonc::recolor_json(&contents, transform, &options.text)?
+                }
+                FileFormat::Rasi => crate::rasi::recolor_rasi(&contents, transform, &options.text)?,
+                FileFormat::Ini => {
+                    crate::ini::recolor_ini(&contents, transform, &options.ini, &options.text)?
+                }
+                _ => crate::i3::recolor_i3(&contents, transform, options.literals, &options.text)?,
+            };
+            if write && (updated != contents || output != source) {
+                if let Some(log) = &options.journal {
+                    log_text(log, source, &contents, &updated);
+                }
+                std::fs::write(output, updated).map_err(|e| TranError::write(output, e))?;
+            }
+            near_matches = match format {
+                FileFormat::Gtk => mapped_near_matches(&contents, transform, |line| {
+                    crate::gtk::owns_line(line, options.literals)
+                })?,
+                FileFormat::Css => mapped_near_matches(&contents, transform, |line| {
+                    crate::css::owns_line(line, options.literals)
+                })?,
+                FileFormat::Json => {
+                    mapped_near_matches(&contents, transform, crate::jsonc::owns_line)?
+                }
+                FileFormat::Rasi => {
+                    mapped_near_matches(&contents, transform, crate::rasi::owns_line)?
+                }
+                FileFormat::Ini => mapped_near_matches(&contents, transform, |line| {
+                    crate::ini::owns_line(line, &options.ini)
+                })?,
+                _ => mapped_near_matches(&contents, transform, |line| {
+                    crate::i3::owns_line(line, options.literals)
+                })?,
+            };
+            names = report.names;
+            report.changes
+        }
+        FileFormat::Exec => {
+            let command = options.exec.as_ref().ok_or_else(|| {
+                TranError::UnsupportedError("The exec format needs an exec command".to_string())
+            })?;
+            // What the command changes is up to it, a dry run can't tell
+            match write {
+                true => crate::exec_handler::recolor(source, output, command, transform)?,
+                false => 0,
+            }
+        }
+        FileFormat::Text => {
+            let contents =
+                std::fs::read_to_string(source).map_err(|e| TranError::read(source, e))?;
+            let (updated, changes) = crate::recolor_text_in(&contents, transform, &options.text)?;
+            if write && (updated != contents || output != source) {
+                if let Some(log) = &options.journal {
+                    log_text(log, source, &contents, &updated);
+                }
+                std::fs::write(output, updated).map_err(|e| TranError::write(output, e))?;
+            }
+            near_matches = mapped_near_matches(&contents, transform, |_| true)?;
+            changes
+        }
+    };
+    Ok((changes, names, near_matches))
+}
+
+/// Near matches of the colors a map looks for, other transforms take whatever colors they find.
+#[cfg(feature = "fs")]
+fn mapped_near_matches(
+    contents: &str,
+    transform: &ColorTransform,
+    owned: impl Fn(&str) -> bool,
+) -> Result<Vec<NearMatch>, TranError> {
+    if !matches!(transform, ColorTransform::Map(_)) {
+        return Ok(Vec::new());
+    }
+    let wanted: Vec<_> = crate::text::replacements(transform, &[])?
+        .into_iter()
+        .map(|(old, _)| old)
+        .collect();
+    Ok(crate::text::near_matches(contents, &wanted, owned))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMap;
+
+    fn map() -> [ColorMap<'static>; 1] {
+        [ColorMap::new("#bf616a", "#2e3440")]
+    }
+
+    #[test]
+    fn bytes_are_recolored_without_a_filesystem() {
+        let map = map();
+        let transform = ColorTransform::Map(&map);
+        let text = TextOptions::default();
+        let ini = IniOptions::default();
+        let (css, changes) = recolor_bytes(
+            b":root { --a: #2e3440; }",
+            FileFormat::Css,
+            &transform,
+            false,
+            &text,
+            &ini,
+        )
+        .unwrap();
+        assert_eq!(
+            (css.as_slice(), changes),
+            (&b":root { --a: #bf616a; }"[..], 1)
+        );
+        assert!(recolor_bytes(
+            b"\xff#2e3440",
+            FileFormat::Text,
+            &transform,
+            false,
+            &text,
+            &ini
+        )
+        .is_err());
+        assert!(recolor_bytes(b"", FileFormat::Exec, &transform, false, &text, &ini).is_err());
+
+        #[cfg(feature = "png")]
+        {
+            let png =
+                crate::png::build::PngBuilder::palette(&[(0x2e, 0x34, 0x40)], &[vec![0]]).build();
+            let (recolored, changes) =
+                recolor_bytes(&png, FileFormat::Png, &transform, false, &text, &ini).unwrap();
+            assert_eq!(changes, 1);
+            assert!(crate::png::check::check_png(&recolored).is_ok());
+        }
+    }
+
+    #[test]
+    fn dispatch_follows_content_then_name() {
+        let text = b"fg=#2e3440";
+        let expect = |format, mismatch, backup| Dispatch {
+            format,
+            mismatch,
+            backup,
+        };
+        let cases: [(&str, &[u8], Dispatch); 6] = [
+            ("ICON.PNG", &PNG_MAGIC, expect(FileFormat::Png, None, false)),
+            ("ICON.PNG", b"", expect(FileFormat::Png, None, false)),
+            (
+                "logo.png.bak",
+                &PNG_MAGIC,
+                expect(FileFormat::Png, None, true),
+            ),
+            (
+                "archive.tar.png",
+                text,
+                expect(FileFormat::Text, Some(FileFormat::Png), false),
+            ),
+            (
+                "wallpaper",
+                &PNG_MAGIC,
+                expect(FileFormat::Png, None, false),
+            ),
+            ("colors.conf~", text, expect(FileFormat::Text, None, true)),
+        ];
+        for (name, head, expected) in cases {
+            assert_eq!(dispatch(OsStr::new(name), head), expected, "{}", name);
+        }
+
+        let rasi = dispatch(OsStr::new("theme.rasi"), text);
+        assert_eq!(rasi, expect(FileFormat::Rasi, None, false));
+        let png = dispatch(OsStr::new("theme.rasi"), &PNG_MAGIC);
+        assert_eq!(png, expect(FileFormat::Png, Some(FileFormat::Rasi), false));
+
+        let found = dispatch(OsStr::new("archive.tar.png"), text);
+        assert_eq!(
+            found
+                .mismatch_warning(Path::new("archive.tar.png"))
+                .unwrap(),
+            "WARNING: archive.tar.png is named as png but holds text, recoloring it as text"
+        );
+        // A lone suffix is a name, not a backup of one
+        assert!(!is_backup(OsStr::new("~")));
+        assert!(is_backup(OsStr::new("kitty.conf.orig")));
+    }
+
+    #[cfg(not(feature = "png"))]
+    #[test]
+    fn pngs_are_refused_without_png_support() {
+        let map = map();
+        let png = b"\x89PNG\r\n\x1a\n";
+        let Err(TranError::UnsupportedError(reason)) = recolor_bytes(
+            png,
+            FileFormat::Png,
+            &ColorTransform::Map(&map),
+            false,
+            &TextOptions::default(),
+            &IniOptions::default(),
+        ) else {
+            panic!("expected an unsupported error");
+        };
+        assert_eq!(reason, "built without png support");
+    }
+
+    #[test]
+    fn handlers_keep_the_casing_of_each_occurrence() {
+        let map = map();
+        let transform = ColorTransform::Map(&map);
+        let text = TextOptions::default();
+        let ini = IniOptions::default();
+        let recolor = |data: &str, format, literals| {
+            let (bytes, _) =
+                recolor_bytes(data.as_bytes(), format, &transform, literals, &text, &ini).unwrap();
+            String::from_utf8(bytes).unwrap()
+        };
+        assert_eq!(
+            recolor(
+                ":root { --a: #2E3440; --b: #2e3440; }",
+                FileFormat::Css,
+                false
+            ),
+            ":root { --a: #BF616A; --b: #bf616a; }"
+        );
+        assert_eq!(
+            recolor(
+                "<svg><path fill=\"#2E3440\"/><path stroke=\"#2e3440\"/></svg>",
+                FileFormat::Css,
+                true
+            ),
+            "<svg><path fill=\"#BF616A\"/><path stroke=\"#bf616a\"/></svg>"
+        );
+        assert_eq!(
+            recolor(
+                "*.background: #2E3440\n*.foreground: #2e3440\n",
+                FileFormat::Text,
+                false
+            ),
+            "*.background: #BF616A\n*.foreground: #bf616a\n"
+        );
+    }
+
+    #[test]
+    fn output_names_fill_the_template() {
+        let map = map();
+        let transform = ColorTransform::Map(&map);
+        let name =
+            |source: &str, template: &str| output_path(Path::new(source), template, &transform);
+        assert_eq!(
+            name("/icons/logo.png", DEFAULT_OUTPUT_NAME),
+            Path::new("/icons/logo_#bf616a.png")
+        );
+        assert_eq!(name("logo.png", "{ext}-{stem}"), Path::new("png-logo"));
+        assert_eq!(
+            name("/icons/logo", DEFAULT_OUTPUT_NAME),
+            Path::new("/icons/logo_#bf616a")
+        );
+        assert_eq!(
+            name("/icons/logo.tar.png", "{stem}.{colors}.{ext}"),
+            Path::new("/icons/logo.tar.#bf616a.png")
+        );
+        // Unknown or unclosed placeholders are kept as they are
+        assert_eq!(name("a.png", "{size}_{stem"), Path::new("{size}_{stem"));
+    }
+
+    #[test]
+    fn every_placeholder_is_filled() {
+        let map = [
+            ColorMap::new("#88c0d0", "#2e3440"),
+            ColorMap::new("#bf616a", "#eceff4"),
+        ];
+        let template = fill_run_fields(
+            "{stem}-{ext}-{color}-{colors}-{index}-{date}",
+            2,
+            "2026-10-14",
+        );
+        assert_eq!(
+            output_path(
+                Path::new("/icons/logo.png"),
+                &template,
+                &ColorTransform::Map(&map)
+            ),
+            Path::new("/icons/logo-png-#88c0d0-#88c0d0#bf616a-2-2026-10-14")
+        );
+    }
+
+    #[test]
+    fn single_color_map_rows_name_their_outputs() {
+        let map = [ColorMap::new("#88c0d0", "#2e3440")];
+        let transform = ColorTransform::Map(&map);
+        assert_eq!(output_color(&transform), "#88c0d0");
+        assert_eq!(output_colors(&transform), "#88c0d0");
+        assert_eq!(
+            output_path(Path::new("logo.png"), DEFAULT_OUTPUT_NAME, &transform),
+            Path::new("logo_#88c0d0.png")
+        );
+        assert_eq!(output_color(&ColorTransform::Map(&[])), "");
+    }
+
+    #[test]
+    fn output_dirs_mirror_sources_under_their_common_base() {
+        let around = OutputDir::new("/out").around([
+            Path::new("/home/me/icons/a.png"),
+            Path::new("/home/me/icons/dark/b.png"),
+            Path::new("/home/me/walls/c.png"),
+        ]);
+        assert_eq!(around.base.as_deref(), Some(Path::new("/home/me")));
+        assert_eq!(
+            around.mirror(Path::new("/home/me/icons/dark/b_#bf616a.png")),
+            Path::new("/out/icons/dark/b_#bf616a.png")
+        );
+        // Outputs outside the base are mirrored from the root
+        assert_eq!(
+            around.mirror(Path::new("/srv/d.png")),
+            Path::new("/out/srv/d.png")
+        );
+
+        let configured = OutputDir {
+            dir: PathBuf::from("/out"),
+            base: Some(PathBuf::from("/home/me/icons")),
+        };
+        assert_eq!(
+            configured.around([Path::new("/elsewhere/e.png")]),
+            configured
+        );
+        assert_eq!(
+            configured.mirror(Path::new("/home/me/icons/a_#bf616a.png")),
+            Path::new("/out/a_#bf616a.png")
+        );
+    }
+
+    #[test]
+    fn output_names_are_validated() {
+        assert!(validate_output_name(DEFAULT_OUTPUT_NAME).is_ok());
+        assert!(validate_output_name("{stem}-{index}-{date}.{ext}").is_ok());
+        let Err(TranError::ConfigError(reason)) = validate_output_name("{stem}_{size}") else {
+            panic!("expected a config error");
+        };
+        assert!(
+            reason.starts_with("Unknown placeholder '{size}'"),
+            "{}",
+            reason
+        );
+        assert!(validate_output_name("{stem").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_names_keep_names_that_are_not_utf8() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let map = [ColorMap::new("#bf616a", "#2e3440")];
+        let source = Path::new(OsStr::from_bytes(b"/icons/old-\xff.png"));
+        let output = output_path(source, DEFAULT_OUTPUT_NAME, &ColorTransform::Map(&map));
+        assert_eq!(output.parent(), Some(Path::new("/icons")));
+        assert_eq!(
+            output.file_name().unwrap().to_os_string().into_vec(),
+            b"old-\xff_#bf616a.png"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod path_tests {
+    use super::*;
+    use crate::{test_util::TempDir, ColorMap};
+
+    fn map() -> [ColorMap<'static>; 1] {
+        [ColorMap::new("#bf616a", "#2e3440")]
+    }
+
+    #[test]
+    fn text_is_recolored_in_place() {
+        let dir = TempDir::new("recolor-text");
+        let source = dir.write("colors.conf", "fg=#2e3440\nbg=#2E3440\n");
+        let map = map();
+        let report = recolor_path(
+            &source,
+            &RecolorOptions::default(),
+            &ColorTransform::Map(&map),
+        )
+        .unwrap();
+        assert_eq!(report.format, FileFormat::Text);
+        assert_eq!(report.output, source);
+        assert_eq!(report.changes, 2);
+        assert_eq!(report.backup, None);
+        let recolored = std::fs::read_to_string(&source).unwrap();
+        assert_eq!(recolored, "fg=#bf616a\nbg=#BF616A\n");
+    }
+
+    #[test]
+    fn a_backup_is_made_before_overwriting() {
+        let dir = TempDir::new("recolor-backup");
+        let source = dir.write("colors.conf", "fg=#2e3440\n");
+        let options = RecolorOptions {
+            backup: BackupPolicy::Directory(dir.path().join("backups")),
+            ..RecolorOptions::default()
+        };
+        let map = map();
+        let report = recolor_path(&source, &options, &ColorTransform::Map(&map)).unwrap();
+        let backup = report.backup.unwrap();
+        assert!(backup.starts_with(dir.path().join("backups")));
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), "fg=#2e3440\n");
+        assert_eq!(std::fs::read_to_string(&source).unwrap(), "fg=#bf616a\n");
+    }
+
+    #[cfg(all(feature = "png", unix))]
+    #[test]
+    fn copies_keep_the_mode_of_the_source_and_backups_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let dir = TempDir::new("recolor-modes");
+        let png = crate::png::build::PngBuilder::palette(&[(0x2e, 0x34, 0x40)], &[vec![0]]).build();
+        let source = dir.write("logo.png", &png);
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let map = map();
+        let report = recolor_path(
+            &source,
+            &RecolorOptions::default(),
+            &ColorTransform::Map(&map),
+        )
+        .unwrap();
+        assert_ne!(report.output, source);
+        assert_eq!(mode(&report.output), 0o640);
+
+        let text = dir.write("colors.conf", "fg=#2e3440\n");
+        std::fs::set_permissions(&text, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let options = RecolorOptions {
+            backup: BackupPolicy::Directory(dir.path().join("backups")),
+            ..RecolorOptions::default()
+        };
+        let report = recolor_path(&text, &options, &ColorTransform::Map(&map)).unwrap();
+        assert_eq!(mode(&report.backup.unwrap()) & 0o077, 0);
+        assert_eq!(mode(&dir.path().join("backups")) & 0o077, 0);
+        assert_eq!(mode(&text), 0o644);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn pngs_are_found_by_their_bytes_and_copied() {
+        let dir = TempDir::new("recolor-png");
+        let png = crate::png::build::PngBuilder::palette(
+            &[(0x2e, 0x34, 0x40), (0xff, 0xff, 0xff)],
+            &[vec![0, 1], vec![1, 0]],
+        )
+        .build();
+        let source = dir.write("wallpaper", &png);
+        let map = map();
+        let report = recolor_path(
+            &source,
+            &RecolorOptions::default(),
+            &ColorTransform::Map(&map),
+        )
+        .unwrap();
+        assert_eq!(report.format, FileFormat::Png);
+        assert_eq!(report.output, dir.path().join("wallpaper_#bf616a"));
+        assert_eq!(report.changes, 1);
+        assert_eq!(std::fs::read(&source).unwrap(), png);
+        assert_ne!(std::fs::read(&report.output).unwrap(), png);
+
+        let overwrite = RecolorOptions {
+            overwrite: true,
+            ..RecolorOptions::default()
+        };
+        let report = recolor_path(&source, &overwrite, &ColorTransform::Map(&map)).unwrap();
+        assert_eq!(report.output, source);
+        assert_ne!(std::fs::read(&source).unwrap(), png);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn copies_are_written_under_the_output_dir() {
+        let dir = TempDir::new("recolor-output-dir");
+        let png = crate::png::build::PngBuilder::palette(&[(0x2e, 0x34, 0x40)], &[vec![0]]).build();
+        let source = dir.write("icons/dark/logo.png", &png);
+        let options = RecolorOptions {
+            output_dir: Some(OutputDir {
+                dir: dir.path().join("out"),
+                base: Some(dir.path().join("icons")),
+            }),
+            ..RecolorOptions::default()
+        };
+        let map = map();
+        let report = recolor_path(&source, &options, &ColorTransform::Map(&map)).unwrap();
+        assert_eq!(report.output, dir.path().join("out/dark/logo_#bf616a.png"));
+        assert_ne!(std::fs::read(&report.output).unwrap(), png);
+        assert_eq!(std::fs::read(&source).unwrap(), png);
+        assert!(!dir.path().join("icons/dark/logo_#bf616a.png").exists());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn taken_copy_names_follow_the_collision_policy() {
+        let dir = TempDir::new("recolor-collision");
+        let png = crate::png::build::PngBuilder::palette(&[(0x2e, 0x34, 0x40)], &[vec![0]]).build();
+        let source = dir.write("logo.png", &png);
+        let taken = dir.write("logo_#bf616a.png", "mine");
+        let map = map();
+        let recolor = |collision| {
+            let options = RecolorOptions {
+                collision,
+                ..RecolorOptions::default()
+            };
+            recolor_path(&source, &options, &ColorTransform::Map(&map))
+        };
+
+        let Err(TranError::InFile {
+            path,
+            source: error,
+        }) = recolor(CollisionPolicy::Error)
+        else {
+            panic!("expected the taken name to be refused");
+        };
+        assert_eq!(path, source);
+        assert!(matches!(*error, TranError::OutputExists(ref path) if *path == taken));
+        let skipped = recolor(CollisionPolicy::Skip).unwrap();
+        assert_eq!(
+            (skipped.output.as_path(), skipped.changes),
+            (taken.as_path(), 0)
+        );
+        assert_eq!(std::fs::read_to_string(&taken).unwrap(), "mine");
+
+        let first = recolor(CollisionPolicy::Number).unwrap();
+        let second = recolor(CollisionPolicy::Number).unwrap();
+        assert_eq!(first.output, dir.path().join("logo_#bf616a-1.png"));
+        assert_eq!(second.output, dir.path().join("logo_#bf616a-2.png"));
+        assert_eq!(
+            std::fs::read(&first.output).unwrap(),
+            std::fs::read(&second.output).unwrap()
+        );
+        assert_eq!(std::fs::read_to_string(&taken).unwrap(), "mine");
+
+        let overwritten = recolor(CollisionPolicy::Overwrite).unwrap();
+        assert_eq!(overwritten.output, taken);
+        assert_eq!(
+            std::fs::read(&taken).unwrap(),
+            std::fs::read(&first.output).unwrap()
+        );
+    }
+
+    #[test]
+    fn a_format_hint_wins_over_detection() {
+        let dir = TempDir::new("recolor-hint");
+        let source = dir.write("theme.png", ":root { --accent: #2e3440; }\n");
+        let options = RecolorOptions {
+            format: Some(FileFormat::Css),
+            ..RecolorOptions::default()
+        };
+        let map = map();
+        let report = recolor_path(&source, &options, &ColorTransform::Map(&map)).unwrap();
+        assert_eq!(report.format, FileFormat::Css);
+        assert_eq!(
+            std::fs::read_to_string(&source).unwrap(),
+            ":root { --accent: #bf616a; }\n"
+        );
+    }
+
+    #[test]
+    fn many_colors_are_recolored_at_once() {
+        let dir = TempDir::new("recolor-many");
+        let source = dir.write("colors.conf", "#2e3440#88c0d0\n");
+        let map = [
+            ColorMap::new("#88c0d0", "#2e3440"),
+            ColorMap::new("#2e3440", "#88c0d0"),
+        ];
+        assert_eq!(crate::recolor_textfile_many(&source, &map).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&source).unwrap(),
+            "#88c0d0#2e3440\n"
+        );
+    }
+
+    fn outside(result: Result<(), TranError>) -> bool {
+        matches!(result, Err(TranError::OutsideAllowedRoots(_)))
+    }
+
+    #[test]
+    fn only_paths_under_an_allowed_root_pass() {
+        let dir = TempDir::new("recolor-roots");
+        let root = dir.path().join("dots");
+        let inside = dir.write("dots/gtk/gtk.css", "");
+        let sibling = dir.write("dotsfoo/gtk.css", "");
+        let roots = [root.clone()];
+
+        assert!(check_roots(&sibling, &[]).is_ok());
+        assert!(check_roots(&inside, &roots).is_ok());
+        // Outputs may go into directories that don't exist yet
+        assert!(check_roots(&root.join("new/dir/out.png"), &roots).is_ok());
+        assert!(outside(check_roots(&sibling, &roots)));
+        assert!(outside(check_roots(
+            &root.join("../dotsfoo/gtk.css"),
+            &roots
+        )));
+        assert!(outside(check_roots(
+            &root.join("gtk/../../dotsfoo/gtk.css"),
+            &roots
+        )));
+        assert!(check_roots(&root.join("gtk/../gtk/gtk.css"), &roots).is_ok());
+        assert!(outside(check_roots(
+            &root.join("new/../../dotsfoo/x"),
+            &roots
+        )));
+        // A root that doesn't exist allows nothing
+        assert!(outside(check_roots(&inside, &[dir.path().join("gone")])));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_an_allowed_root_are_caught() {
+        let dir = TempDir::new("recolor-roots-symlink");
+        let root = dir.path().join("dots");
+        let secret = dir.write("elsewhere/secret.conf", "");
+        std::fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink(&secret, root.join("link.conf")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("elsewhere"), root.join("linked")).unwrap();
+        let roots = [root.clone()];
+        assert!(outside(check_roots(&root.join("link.conf"), &roots)));
+        assert!(outside(check_roots(
+            &root.join("linked/secret.conf"),
+            &roots
+        )));
+        assert!(outside(check_roots(&root.join("linked/new.conf"), &roots)));
+
+        // A root reached through a symlink still holds what is under it
+        let alias = dir.path().join("alias");
+        std::os::unix::fs::symlink(&root, &alias).unwrap();
+        let inside = dir.write("dots/a.conf", "");
+        assert!(check_roots(&inside, std::slice::from_ref(&alias)).is_ok());
+        assert!(check_roots(&alias.join("a.conf"), &roots).is_ok());
+    }
+
+    #[test]
+    fn roots_differing_in_case_follow_the_filesystem() {
+        let dir = TempDir::new("recolor-roots-case");
+        let inside = dir.write("Dots/a.conf", "");
+        let lower = dir.path().join("dots");
+        // Whether the filesystem tells the two apart decides whether the root is the same
+        let insensitive = lower.exists();
+        assert_eq!(check_roots(&inside, &[lower]).is_ok(), insensitive);
+        assert!(check_roots(&inside, &[dir.path().join("Dots")]).is_ok());
+    }
+
+    #[cfg(not(feature = "png"))]
+    #[test]
+    fn png_targets_are_refused_without_png_support() {
+        let dir = TempDir::new("recolor-without-png");
+        let source = dir.write("icon.png", b"\x89PNG\r\n\x1a\n");
+        let map = map();
+        let error = recolor_path(
+            &source,
+            &RecolorOptions::default(),
+            &ColorTransform::Map(&map),
+        )
+        .unwrap_err();
+        assert_eq!(error.path(), Some(source.as_path()));
+        assert!(
+            error.to_string().ends_with("built without png support"),
+            "{}",
+            error
+        );
+        // Text targets are still recolored
+        let text = dir.write("colors.conf", "fg=#2e3440\n");
+        let report = recolor_path(
+            &text,
+            &RecolorOptions::default(),
+            &ColorTransform::Map(&map),
+        );
+        assert_eq!(report.unwrap().changes, 1);
+    }
+
+    #[test]
+    fn missing_sources_are_reported() {
+        let dir = TempDir::new("recolor-missing");
+        let source = dir.path().join("gone.conf");
+        let map = map();
+        let error = recolor_path(
+            &source,
+            &RecolorOptions::default(),
+            &ColorTransform::Map(&map),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("gone.conf"), "{}", error);
+
+        let error = crate::recolor_textfile(&source, "#bf616a", "#2e3440").unwrap_err();
+        assert!(error.to_string().contains("gone.conf"), "{}", error);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn corrupt_pngs_are_reported_with_their_path() {
+        let dir = TempDir::new("recolor-corrupt");
+        let mut png =
+            crate::png::build::PngBuilder::palette(&[(0x2e, 0x34, 0x40)], &[vec![0]]).build();
+        png.truncate(png.len() / 2);
+        let source = dir.write("broken.png", &png);
+        let map = map();
+        let transform = ColorTransform::Map(&map);
+
+        let error =
+            crate::png::recolor_png(&source, dir.path().join("out.png"), &transform).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("broken.png"), "{}", message);
+        let error = recolor_path(&source, &RecolorOptions::default(), &transform).unwrap_err();
+        assert_eq!(error.path(), Some(source.as_path()));
+        assert!(
+            error.to_string().starts_with(&source.display().to_string()),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn config_errors_name_the_config() {
+        let dir = TempDir::new("recolor-config");
+        let path = dir.write("config", "[mode]\ngradient\n[colors]\n#12345\n");
+        let error = crate::config::parse_config(&path).unwrap_err();
+        assert_eq!(error.path(), Some(path.as_path()));
+        let message = error.to_string();
+        assert!(
+            message.starts_with(&path.display().to_string()),
+            "{}",
+            message
+        );
+        assert!(message.contains("'#12345'"), "{}", message);
+    }
+}
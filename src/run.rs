@@ -0,0 +1,1901 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    cache::{content_hash, Cache, CacheEntry},
+    config::{
+        parse_config, parse_config_contents, parse_config_with_local, write_config, Color, Config,
+        Stop, SyncMode, Target,
+    },
+    discover::path_glob_match,
+    errors::TranError,
+    export::terminal_sequences,
+    journal::{self, Snapshot},
+    json::Json,
+    pixel::{GradientStop, LuminanceBand},
+    recolor::{
+        claim_output, dispatch, fill_run_fields, read_head, recolor_path_dry, recolor_path_to,
+        resolve_output, Cancellation, OutputDir, ProgressEvent, ProgressLog, DEFAULT_OUTPUT_NAME,
+    },
+    recolor_path,
+    scan::{self, scan_file},
+    selection::{Selection, SystemRng},
+    space::GradientSpace,
+    state::{new_run_id, today, RunRecord, State},
+    temp::{self, TempFile},
+    text::NearMatch,
+    transition,
+    verify::{classify, TargetState},
+    ColorMap, ColorTransform, FileFormat, RecolorOptions, RecolorReport,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetResult {
+    pub path: PathBuf,
+    pub output: PathBuf,
+    pub handler: FileFormat,
+    pub changes: usize,
+    pub names: Vec<String>,
+    pub near_matches: Vec<NearMatch>,
+    /// Skipped because the cache showed the file was already in the new colors.
+    pub cached: bool,
+    /// It holds none of the colors of the config, the current ones or any other, so no run
+    /// will ever change it, see `is_stale`.
+    pub stale: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectionInfo {
+    pub mode: String,
+    pub old: Vec<Color>,
+    pub new: Vec<Color>,
+}
+
+#[derive(Debug)]
+pub struct RunSummary {
+    pub succeeded: Vec<TargetResult>,
+    pub failed: Vec<(PathBuf, TranError)>,
+    pub colors: SelectionInfo,
+    /// Things that went wrong around the targets, such as a journal that couldn't be written.
+    pub warnings: Vec<String>,
+    /// The colors picked were the current ones, so nothing was written.
+    pub unchanged: bool,
+    /// The config in the new colors, when it came from `RunOptions::config_contents` and has
+    /// nowhere to be written back to.
+    pub config: Option<Config>,
+    /// The run was cancelled before every target was recolored. Targets it never got to are in
+    /// neither `succeeded` nor `failed`, and the config wasn't advanced.
+    pub interrupted: bool,
+}
+
+impl RunSummary {
+    /// `current_color` only moves forward when at least one target took the new colors,
+    /// otherwise the config would claim a state none of the files are in.
+    pub fn should_advance(&self) -> bool {
+        !self.interrupted && (!self.succeeded.is_empty() || self.failed.is_empty())
+    }
+
+    /// Targets that took none of the new colors as they hold none of the config, see
+    /// `TargetResult::stale`.
+    pub fn stale(&self) -> impl Iterator<Item = &TargetResult> {
+        self.succeeded.iter().filter(|t| t.stale)
+    }
+
+    /// 0 when everything succeeded, 2 for a partial failure and 1 when every target failed. A
+    /// cancelled run counts as failing the targets it never got to.
+    pub fn exit_code(&self) -> u8 {
+        if self.failed.is_empty() && !self.interrupted {
+            0
+        } else if self.succeeded.is_empty() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let colors = |colors: &[Color]| -> Json {
+            colors
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .into()
+        };
+        let succeeded: Vec<Json> = self
+            .succeeded
+            .iter()
+            .map(|t| {
+                Json::object()
+                    .with("path", t.path.to_string_lossy().to_string())
+                    .with("output", t.output.to_string_lossy().to_string())
+                    .with("handler", t.handler.to_string())
+                    .with("changes", t.changes)
+                    .with("names", t.names.clone())
+                    .with(
+                        "near_matches",
+                        t.near_matches
+                            .iter()
+                            .map(|m| {
+                                Json::object()
+                                    .with("wanted", m.wanted.to_string())
+                                    .with("found", m.found.to_string())
+                                    .with("distance", m.distance as u64)
+                                    .with("line", m.line)
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .with("cached", t.cached)
+                    .with("stale", t.stale)
+            })
+            .collect();
+        let failed: Vec<Json> = self
+            .failed
+            .iter()
+            .map(|(path, e)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("error", e.to_string())
+            })
+            .collect();
+
+        Json::object()
+            .with("mode", self.colors.mode.as_str())
+            .with("old_colors", colors(&self.colors.old))
+            .with("new_colors", colors(&self.colors.new))
+            .with("succeeded", succeeded)
+            .with("failed", failed)
+            .with(
+                "stale",
+                self.stale()
+                    .map(|t| t.path.to_string_lossy().to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .with("warnings", self.warnings.clone())
+            .with("advanced", self.should_advance())
+            .with("unchanged", self.unchanged)
+            .with("interrupted", self.interrupted)
+    }
+}
+
+/// What a whole run does, the flags of a plain `tran`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunOptions {
+    pub config_path: PathBuf,
+    /// Where the cache, journal and history are kept, `None` for the usual state directory.
+    pub state_dir: Option<PathBuf>,
+    /// Picks the colors and reports what every target would get, without writing anything.
+    pub dry_run: bool,
+    /// Seeds the pick of the next colors, so a run can be repeated.
+    pub seed: Option<u64>,
+    /// Globs a target's path has to match to be recolored, empty recolors every target. The
+    /// config still moves on to the new colors, leaving the others for `tran verify --repair`.
+    pub only: Vec<String>,
+    pub use_cache: bool,
+    pub use_transition: bool,
+    /// See `apply_transactional`.
+    pub transactional: bool,
+    /// Ignores `[allowed_roots]`.
+    pub allow_outside: bool,
+    /// Writes copies under this directory instead of the one in `[output_dir]`.
+    pub out_dir: Option<PathBuf>,
+    /// Merges the local overlay next to the config over it, see `parse_config_with_local`.
+    pub local_config: bool,
+    /// Colors to recolor to instead of picking them, see `Config::parse_selection`. They win
+    /// over `seed`, only an interrupted transition is finished first.
+    pub color: Option<String>,
+    /// The config itself, as piped to `tran --config -`, read instead of `config_path` and
+    /// without a local overlay. Such a config isn't kept anywhere, so nothing is written back
+    /// or journaled and transitions go straight to the new colors. The config in them is
+    /// `RunSummary::config`.
+    pub config_contents: Option<String>,
+    /// Picks only among entries of `[colors]` with every one of these tags, see
+    /// `Config::select_tagged`. Colors given in `color` aren't narrowed.
+    pub tags: Vec<String>,
+    /// Told as the run starts and finishes every target, see `ProgressEvent`.
+    pub progress: Option<ProgressLog>,
+    /// Stops the run between targets. A cancelled run journals what it did but leaves the
+    /// config in the old colors, see `RunSummary::interrupted`.
+    pub cancel: Option<Cancellation>,
+}
+
+impl RunOptions {
+    pub fn new(config_path: impl Into<PathBuf>) -> Self {
+        RunOptions {
+            config_path: config_path.into(),
+            state_dir: None,
+            dry_run: false,
+            seed: None,
+            only: Vec::new(),
+            use_cache: true,
+            use_transition: true,
+            transactional: false,
+            allow_outside: false,
+            out_dir: None,
+            local_config: false,
+            color: None,
+            config_contents: None,
+            tags: Vec::new(),
+            progress: None,
+            cancel: None,
+        }
+    }
+
+    /// The recolor options a run with these options takes for the `index`th selection of
+    /// `config`, as the daemon also recolors the targets it watches with.
+    pub fn recolor_options(&self, config: &Config, index: usize) -> RecolorOptions {
+        RecolorOptions {
+            allowed_roots: match self.allow_outside {
+                true => Vec::new(),
+                false => config.get_allowed_roots().to_vec(),
+            },
+            output_dir: output_dir(config, self.out_dir.as_deref()),
+            progress: self.progress.clone(),
+            cancel: self.cancel.clone(),
+            ..recolor_options(config, index)
+        }
+    }
+}
+
+/// The recolor options `config` asks for, as a run without flags picking its `index`th selection
+/// uses them. Commands reading or writing targets outside of a run start from these.
+pub fn recolor_options(config: &Config, index: usize) -> RecolorOptions {
+    RecolorOptions {
+        overwrite: config.get_overwrite(),
+        output_name: fill_run_fields(
+            config.get_output_name().unwrap_or(DEFAULT_OUTPUT_NAME),
+            index,
+            &today(),
+        ),
+        allowed_roots: config.get_allowed_roots().to_vec(),
+        output_dir: output_dir(config, None),
+        collision: config.get_collision(),
+        text: config.get_text_options().clone(),
+        ini: config.get_ini_options().clone(),
+        ..Default::default()
+    }
+}
+
+/// Where copies of the targets of `config` go, `dir` replacing the configured directory. The
+/// base is the common directory of every target, so `--only` doesn't move the copies.
+pub fn output_dir(config: &Config, dir: Option<&Path>) -> Option<OutputDir> {
+    let output_dir = match (dir, config.get_output_dir()) {
+        (Some(dir), Some(configured)) => OutputDir {
+            dir: dir.to_path_buf(),
+            ..configured.clone()
+        },
+        (Some(dir), None) => OutputDir::new(dir),
+        (None, configured) => configured?.clone(),
+    };
+    Some(
+        output_dir.around(
+            config
+                .get_targets()
+                .iter()
+                .map(|target| target.path.as_path()),
+        ),
+    )
+}
+
+/// The colors the last runs in the mode of `config` took, oldest first, as far back as its
+/// `[recency_penalty]` looks. Without a penalty, a state directory or a readable history there
+/// is nothing to penalize.
+fn recent_colors(config: &Config, state: Option<&State>) -> Vec<Vec<Color>> {
+    let (Some(penalty), Some(state)) = (config.get_recency_penalty(), state) else {
+        return Vec::new();
+    };
+    state
+        .recent_runs(penalty.runs)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|run| run.mode == config.get_mode())
+        .map(|run| run.new_colors)
+        .collect()
+}
+
+/// What a run settles before it touches any target: the config, the colors it moves to and
+/// how every target is recolored. `run` and `Plan::apply` both carry on from here.
+pub(crate) struct Prepared {
+    pub(crate) options: RunOptions,
+    pub(crate) config: Config,
+    /// Whether the config is kept at `config_path`, to be advanced and restored by `tran undo`.
+    pub(crate) kept: bool,
+    pub(crate) state: Result<State, TranError>,
+    /// An interrupted transition, which `selection` then finishes.
+    pub(crate) pending: Option<Selection>,
+    pub(crate) selection: Selection,
+    pub(crate) run_id: u64,
+    /// The options of every target, without a journal until one is opened for the writes.
+    pub(crate) recolor: RecolorOptions,
+    /// The config with only the targets the run recolors.
+    pub(crate) targets: Config,
+    pub(crate) warnings: Vec<String>,
+}
+
+impl Prepared {
+    /// Whether the colors picked are the current ones. Recoloring to them would rewrite every
+    /// file as it is, and the config.
+    pub(crate) fn unchanged(&self) -> bool {
+        self.pending.is_none() && self.selection.colors() == self.config.get_current_colors()
+    }
+
+    pub(crate) fn unchanged_summary(self) -> RunSummary {
+        let mut summary = empty_summary(
+            &self.config,
+            &self.config.get_current_colors(),
+            &self.selection,
+        );
+        summary.unchanged = true;
+        summary.warnings = self.warnings;
+        summary
+    }
+}
+
+pub(crate) fn prepare(options: &RunOptions) -> Result<Prepared, TranError> {
+    let config_path = options.config_path.as_path();
+    let config = match (&options.config_contents, options.local_config) {
+        (Some(contents), _) => parse_config_contents(contents)?,
+        (None, true) => parse_config_with_local(config_path)?,
+        (None, false) => parse_config(config_path)?,
+    };
+    let kept = options.config_contents.is_none();
+    let mut warnings = Vec::new();
+
+    let state = match &options.state_dir {
+        Some(dir) => State::at(dir),
+        None => State::open(),
+    };
+    // An interrupted transition is finished before anything else is picked
+    let pending = match &state {
+        Ok(state) if kept => transition::pending(state)?,
+        _ => None,
+    };
+    let selection = match (&pending, &options.color) {
+        (Some(selection), _) => selection.clone(),
+        (None, Some(colors)) => config.parse_selection(colors)?,
+        (None, None) => {
+            let mut rng = match options.seed {
+                Some(seed) => SystemRng::from_seed(seed),
+                None => SystemRng::new(),
+            };
+            let recent = recent_colors(&config, state.as_ref().ok());
+            let (selection, fix) = config.select_tagged(&mut rng, &recent, &options.tags)?;
+            warnings.extend(fix.map(|fix| fix.to_string()));
+            selection
+        }
+    };
+    let index = config
+        .get_selections()
+        .iter()
+        .position(|s| *s == selection)
+        .unwrap_or(0);
+    let recolor = options.recolor_options(&config, index);
+    if options.allow_outside && !config.get_allowed_roots().is_empty() {
+        warnings.push(
+            "WARNING: ignoring [allowed_roots], targets anywhere will be recolored".to_string(),
+        );
+    }
+
+    let mut targets = config.clone();
+    if !options.only.is_empty() {
+        targets.retain_targets(|target| {
+            options
+                .only
+                .iter()
+                .any(|glob| path_glob_match(glob, &target.path))
+        });
+    }
+    check_dispatch(&mut targets, &mut warnings);
+
+    Ok(Prepared {
+        options: options.clone(),
+        config,
+        kept,
+        state,
+        pending,
+        selection,
+        run_id: new_run_id(),
+        recolor,
+        targets,
+        warnings,
+    })
+}
+
+/// Leaves out the backups among the targets unless `[discover_options]` takes them, and warns
+/// about targets whose extension and content disagree. Targets with a format or a command
+/// aren't dispatched, so they are left as they are.
+fn check_dispatch(targets: &mut Config, warnings: &mut Vec<String>) {
+    let backups = targets.get_traversal().backups;
+    targets.retain_targets(|target| {
+        if target.format.is_some() || target.exec.is_some() {
+            return true;
+        }
+        let head = read_head(&target.path).unwrap_or_default();
+        let dispatch = dispatch(target.path.file_name().unwrap_or_default(), &head);
+        if dispatch.backup && !backups {
+            warnings.push(format!(
+                "Skipping {}, a backup, set backups=true in [discover_options] to recolor it",
+                target.path.display()
+            ));
+            return false;
+        }
+        warnings.extend(dispatch.mismatch_warning(&target.path));
+        true
+    });
+}
+
+/// Removes what killed runs left next to the config and the targets, see `temp::sweep`.
+fn sweep_temp(config_path: &Path, targets: &Config, warnings: &mut Vec<String>) {
+    let mut dirs: Vec<&Path> = std::iter::once(config_path)
+        .chain(targets.get_targets().iter().map(|t| t.path.as_path()))
+        .filter_map(Path::parent)
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    let swept: Vec<PathBuf> = dirs
+        .into_iter()
+        .flat_map(|dir| temp::sweep(dir, temp::STALE_AFTER))
+        .collect();
+    if !swept.is_empty() {
+        warnings.push(format!(
+            "Removed {} temporary files left by an interrupted run: {}",
+            swept.len(),
+            swept
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+}
+
+/// A whole run: picks the next colors of the config at `options.config_path`, recolors its
+/// targets and, when any of them took the new colors, writes those back to the config and
+/// records the run to be undone and listed in the history.
+pub fn run(options: &RunOptions) -> Result<RunSummary, TranError> {
+    let prepared = prepare(options)?;
+    if prepared.unchanged() {
+        return Ok(prepared.unchanged_summary());
+    }
+    if options.dry_run {
+        let mut summary = preview(&prepared.targets, &prepared.selection, &prepared.recolor);
+        summary.warnings = prepared.warnings;
+        return Ok(summary);
+    }
+    finish(prepared)
+}
+
+/// Recolors the targets of a prepared run, then advances the config and journals the run.
+pub(crate) fn finish(prepared: Prepared) -> Result<RunSummary, TranError> {
+    let Prepared {
+        options,
+        config,
+        kept,
+        state,
+        pending,
+        selection,
+        run_id,
+        mut recolor,
+        targets,
+        mut warnings,
+    } = prepared;
+    let config_path = options.config_path.as_path();
+    sweep_temp(config_path, &targets, &mut warnings);
+
+    if let (Ok(state), true) = (&state, config.get_journal() && kept) {
+        match state.replacement_log(run_id) {
+            Ok(log) => recolor.journal = Some(log),
+            Err(e) => warnings.push(format!("Could not open replacements log: {}", e)),
+        }
+    }
+    let snapshot = kept.then(|| Snapshot::take(config_path, &targets, &selection, &recolor));
+    let fade = config
+        .get_transition()
+        .filter(|_| options.use_transition && kept);
+    let mut summary = match (&state, fade, options.use_cache) {
+        (Ok(state), _, _) if pending.is_some() => {
+            warnings.push(format!("Finishing interrupted transition to {}", selection));
+            transition::resume(state, &targets, &recolor)?
+        }
+        // All or nothing can't hold across the steps of a transition, so it goes straight there
+        _ if options.transactional => apply_transactional(&targets, &selection, &recolor),
+        (Ok(state), Some(fade), _) => transition::apply(
+            state,
+            &targets,
+            &selection,
+            &recolor,
+            fade,
+            std::thread::sleep,
+        )?,
+        (Ok(state), None, true) => {
+            let mut cache = Cache::open(state);
+            let summary = apply_cached(&targets, &selection, &recolor, &mut cache);
+            if let Err(e) = cache.save() {
+                warnings.push(format!("Could not save target cache: {}", e));
+            }
+            summary
+        }
+        _ => apply(&targets, &selection, &recolor),
+    };
+
+    if summary.interrupted {
+        warnings.push("The run was cancelled, the config keeps its colors".to_string());
+    }
+    // Terminals started from here on take the scheme every target is in
+    let sequences = match (
+        config.get_sequences_file(),
+        summary.failed.is_empty() && !summary.interrupted,
+    ) {
+        (Some(path), true) => {
+            let mut current = config.clone();
+            current.set_selection(selection.clone())?;
+            Some((path.to_path_buf(), terminal_sequences(&current)))
+        }
+        _ => None,
+    };
+    if summary.should_advance() {
+        // Only the new colors are written, to the base, so nothing of the overlay is shared
+        let mut written = match options.local_config && kept {
+            true => parse_config(config_path)?,
+            false => config,
+        };
+        written.set_selection(selection.clone())?;
+        match kept {
+            true => write_config(written, config_path)?,
+            false => summary.config = Some(written),
+        }
+    }
+    if let Some((path, sequences)) = sequences {
+        if let Err(e) = write_atomic(&path, &sequences) {
+            warnings.push(format!("Could not write sequences file: {}", e));
+        }
+    }
+
+    match (&state, snapshot) {
+        (Ok(state), Some(Ok(snapshot))) => {
+            if let Err(e) = journal::record(state, snapshot, &summary, &selection) {
+                warnings.push(format!("Could not journal run, it can't be undone: {}", e));
+            }
+        }
+        (_, Some(Err(e))) => {
+            warnings.push(format!("Could not journal run, it can't be undone: {}", e))
+        }
+        _ => {}
+    }
+    let mut record = RunRecord::from_summary(&summary);
+    record.id = run_id;
+    if let Err(e) = state.and_then(|state| state.append_run(&record)) {
+        warnings.push(format!("Could not record run history: {}", e));
+    }
+
+    summary.warnings = warnings;
+    Ok(summary)
+}
+
+/// Recolors every target of `config` from its current colors to `selection`, carrying on
+/// past individual failures. The config itself is left untouched.
+pub fn apply(config: &Config, selection: &Selection, options: &RecolorOptions) -> RunSummary {
+    apply_inner(config, selection, options, None)
+}
+
+/// Like `apply`, but skips targets `cache` knows are already in the colors of `selection` and
+/// records the new state of every target that succeeded.
+pub fn apply_cached(
+    config: &Config,
+    selection: &Selection,
+    options: &RecolorOptions,
+    cache: &mut Cache,
+) -> RunSummary {
+    let summary = apply_inner(config, selection, options, Some(cache));
+    let colors = selection.to_string();
+    for target in summary.succeeded.iter() {
+        match std::fs::read(&target.path) {
+            Ok(data) => cache.insert(
+                target.path.clone(),
+                CacheEntry {
+                    hash: content_hash(&data),
+                    colors: colors.clone(),
+                },
+            ),
+            Err(_) => cache.remove(&target.path),
+        }
+    }
+    for (path, _) in summary.failed.iter() {
+        cache.remove(path);
+    }
+    summary
+}
+
+fn apply_inner(
+    config: &Config,
+    selection: &Selection,
+    options: &RecolorOptions,
+    cache: Option<&Cache>,
+) -> RunSummary {
+    let old = config.get_current_colors();
+    with_transform(
+        selection,
+        &old,
+        config.get_gradient_space(),
+        config.get_luminance_band(),
+        config.get_stops(),
+        |transform| {
+            apply_transform(
+                config,
+                config.get_targets(),
+                &old,
+                selection,
+                options,
+                Pass::Write(cache),
+                transform,
+            )
+        },
+    )
+}
+
+/// Like `apply`, but nothing is written. The summary says what `apply` would do.
+pub fn preview(config: &Config, selection: &Selection, options: &RecolorOptions) -> RunSummary {
+    let old = config.get_current_colors();
+    with_transform(
+        selection,
+        &old,
+        config.get_gradient_space(),
+        config.get_luminance_band(),
+        config.get_stops(),
+        |transform| {
+            apply_transform(
+                config,
+                config.get_targets(),
+                &old,
+                selection,
+                options,
+                Pass::Dry,
+                transform,
+            )
+        },
+    )
+}
+
+/// Like `apply`, but only recolors `targets` and takes them from `old` rather than the
+/// current colors of `config`. Position `i` of `old` and `selection` is color `i` of a row, as
+/// in the steps of a transition.
+pub fn apply_from(
+    config: &Config,
+    targets: &[Target],
+    old: &[Color],
+    selection: &Selection,
+    options: &RecolorOptions,
+) -> RunSummary {
+    with_transform(
+        selection,
+        old,
+        config.get_gradient_space(),
+        config.get_luminance_band(),
+        config.get_stops(),
+        |transform| {
+            apply_transform(
+                config,
+                targets,
+                old,
+                selection,
+                options,
+                Pass::Write(None),
+                transform,
+            )
+        },
+    )
+}
+
+/// Like `apply_from`, but `old` and `selection` are any colors rather than rows of the config,
+/// as `tran swap` trades two of them. The map is recolored as given, never narrowed to the
+/// colors a target takes.
+pub fn apply_map(
+    config: &Config,
+    targets: &[Target],
+    old: &[Color],
+    selection: &Selection,
+    options: &RecolorOptions,
+) -> RunSummary {
+    with_transform(
+        selection,
+        old,
+        config.get_gradient_space(),
+        config.get_luminance_band(),
+        config.get_stops(),
+        |transform| {
+            apply_transform(
+                config,
+                targets,
+                old,
+                selection,
+                options,
+                Pass::Explicit,
+                transform,
+            )
+        },
+    )
+}
+
+/// Calls `f` with the transform that takes `old` to the colors of `selection`, gradients
+/// ramping in `space` across `stops`, `primary` in them being the color of `selection`.
+pub fn with_transform<R>(
+    selection: &Selection,
+    old: &[Color],
+    space: GradientSpace,
+    luminance: LuminanceBand,
+    stops: &[Stop],
+    f: impl FnOnce(&ColorTransform) -> R,
+) -> R {
+    let new_colors: Vec<String> = selection.colors().iter().map(|c| c.to_string()).collect();
+    let old_colors: Vec<String> = old.iter().map(|c| c.to_string()).collect();
+    let map: Vec<ColorMap> = new_colors
+        .iter()
+        .zip(&old_colors)
+        .map(|(new, current)| ColorMap::new(new, current))
+        .collect();
+    let primary = selection
+        .colors()
+        .first()
+        .copied()
+        .unwrap_or(Color::black());
+    let stops: Vec<GradientStop> = stops
+        .iter()
+        .map(|stop| GradientStop {
+            position: stop.position,
+            color: stop.color.unwrap_or(primary),
+        })
+        .collect();
+    let transform = match selection {
+        Selection::Color(_) => ColorTransform::Gradient {
+            primary: new_colors.first().map_or("", String::as_str),
+            background: "#000000",
+            current: old_colors.first().map_or("", String::as_str),
+            space,
+            luminance,
+            stops: &stops,
+        },
+        Selection::Row(_) => ColorTransform::Map(&map),
+    };
+    f(&transform)
+}
+
+/// Calls `f` with `transform` narrowed to the colors of the map row `target` takes.
+pub fn for_target<R>(
+    target: &Target,
+    transform: &ColorTransform,
+    f: impl FnOnce(&ColorTransform) -> R,
+) -> R {
+    match (transform, &target.colors) {
+        (ColorTransform::Map(map), Some(colors)) => {
+            let map: Vec<ColorMap> = colors.iter().filter_map(|&i| map.get(i).copied()).collect();
+            f(&ColorTransform::Map(&map))
+        }
+        _ => f(transform),
+    }
+}
+
+/// Calls `f` with the transform `target` is recolored with, `options` being its own. When the
+/// positions of `transform` are those of the config `rows`, a target holding other configured
+/// colors than the current ones under `SyncMode::Detect`, see `verify::classify`, is taken from
+/// those, and otherwise `transform` is narrowed as `for_target` does. Any other `transform` is
+/// used as it is.
+pub fn synced<R>(
+    config: &Config,
+    target: &Target,
+    options: &RecolorOptions,
+    selection: &Selection,
+    transform: &ColorTransform,
+    rows: bool,
+    f: impl FnOnce(&ColorTransform) -> R,
+) -> R {
+    let found = match config.get_sync_mode() {
+        SyncMode::Detect if rows => scan_file(&target.path, options).ok(),
+        _ => None,
+    };
+    match found.map(|found| classify(config, &found)) {
+        Some(TargetState::Stale(found)) => with_transform(
+            selection,
+            found.colors(),
+            config.get_gradient_space(),
+            config.get_luminance_band(),
+            config.get_stops(),
+            |transform| for_target(target, transform, f),
+        ),
+        _ if !rows => f(transform),
+        _ => for_target(target, transform, f),
+    }
+}
+
+/// The path of `target` and the options it is recolored with.
+pub fn target_options(target: &Target, options: &RecolorOptions) -> (PathBuf, RecolorOptions) {
+    (target.path.clone(), target.options(options))
+}
+
+fn empty_summary(config: &Config, old: &[Color], selection: &Selection) -> RunSummary {
+    RunSummary {
+        succeeded: Vec::new(),
+        failed: Vec::new(),
+        colors: SelectionInfo {
+            mode: config.get_mode().to_string(),
+            old: old.to_vec(),
+            new: selection.colors().to_vec(),
+        },
+        warnings: Vec::new(),
+        unchanged: false,
+        config: None,
+        interrupted: false,
+    }
+}
+
+fn target_result(report: RecolorReport, cached: bool) -> TargetResult {
+    TargetResult {
+        path: report.source,
+        output: report.output,
+        handler: report.format,
+        changes: report.changes,
+        names: report.names,
+        near_matches: report.near_matches,
+        cached,
+        stale: false,
+    }
+}
+
+/// Whether `target` holds none of the colors a map row of `config` gives it, the current row
+/// included. Gradients ramp whatever colors a file has, so their targets are never stale, and
+/// neither is one that can't be read.
+pub fn is_stale(config: &Config, target: &Target, options: &RecolorOptions) -> bool {
+    let Config::MapConfig(_) = config else {
+        return false;
+    };
+    let current = Selection::Row(config.get_current_colors());
+    let mut colors: Vec<Color> = Vec::new();
+    for selection in config.get_selections().iter().chain([&current]) {
+        for (i, color) in selection.colors().iter().enumerate() {
+            let taken = target
+                .colors
+                .as_ref()
+                .is_none_or(|taken| taken.contains(&i));
+            if taken && !colors.contains(color) {
+                colors.push(*color);
+            }
+        }
+    }
+    let (path, options) = target_options(target, options);
+    scan::holds_any(&path, &options, &colors).is_ok_and(|holds| !holds)
+}
+
+/// Marks the targets of `summary` that are stale. Only those without changes are scanned, any
+/// change is to a color of the config.
+fn mark_stale(
+    config: &Config,
+    targets: &[Target],
+    options: &RecolorOptions,
+    summary: &mut RunSummary,
+) {
+    for result in summary.succeeded.iter_mut() {
+        if result.changes > 0 || result.cached {
+            continue;
+        }
+        if let Some(target) = targets.iter().find(|target| target.path == result.path) {
+            result.stale = is_stale(config, target, options);
+        }
+    }
+}
+
+/// How `apply_transform` treats every target.
+#[derive(Clone, Copy)]
+enum Pass<'a> {
+    /// Recolors it, unless the cache shows it already is in the new colors.
+    Write(Option<&'a Cache>),
+    /// Only reports what recoloring it would change.
+    Dry,
+    /// Recolors it with a map whose positions aren't those of the rows of the config.
+    Explicit,
+}
+
+fn apply_transform(
+    config: &Config,
+    targets: &[Target],
+    old: &[Color],
+    selection: &Selection,
+    options: &RecolorOptions,
+    pass: Pass,
+    transform: &ColorTransform,
+) -> RunSummary {
+    let mut summary = empty_summary(config, old, selection);
+
+    // A file listed twice is recolored once, as its first entry says, so no two targets write
+    // the same file at once
+    let mut seen = HashSet::new();
+    let mut targets_at: Vec<(usize, &Target)> = Vec::new();
+    for target in targets {
+        let canonical = std::fs::canonicalize(&target.path).unwrap_or_else(|_| target.path.clone());
+        match seen.insert(canonical) {
+            true => targets_at.push((targets_at.len(), target)),
+            false => summary.warnings.push(format!(
+                "Skipping {}, it is listed more than once in [target_files]",
+                target.path.display()
+            )),
+        }
+    }
+
+    let colors = selection.to_string();
+    let total = targets_at.len();
+    let done = AtomicUsize::new(0);
+    let recolor_target = |(index, target): &(usize, &Target)| {
+        let index = *index;
+        let (path, options) = target_options(target, options);
+        let path = path.as_path();
+        if let Some(cancel) = &options.cancel {
+            cancel.check().map_err(|e| (path.to_path_buf(), e))?;
+        }
+        progress(&options, ProgressEvent::Started { path, index, total });
+        let result = synced(
+            config,
+            target,
+            &options,
+            selection,
+            transform,
+            !matches!(pass, Pass::Explicit),
+            |transform| {
+                let recolor = match pass {
+                    Pass::Write(cache) => {
+                        if let Some(report) = cache
+                            .and_then(|cache| cached(cache, path, &options, transform, &colors))
+                        {
+                            return Ok((report, true));
+                        }
+                        recolor_path
+                    }
+                    Pass::Dry => recolor_path_dry,
+                    Pass::Explicit => recolor_path,
+                };
+                recolor(path, &options, transform)
+                    .map(|report| (report, false))
+                    .map_err(|e| (path.to_path_buf(), e))
+            },
+        );
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        match &result {
+            Ok((report, _)) => progress(
+                &options,
+                ProgressEvent::Finished {
+                    path,
+                    index,
+                    total,
+                    done,
+                    changes: report.changes,
+                },
+            ),
+            Err((_, error)) if !error.is_cancelled() => progress(
+                &options,
+                ProgressEvent::Failed {
+                    path,
+                    index,
+                    total,
+                    done,
+                    error,
+                },
+            ),
+            Err(_) => {}
+        }
+        result
+    };
+
+    #[cfg(feature = "parallel")]
+    let results = crate::parallel::map(&targets_at, recolor_target);
+    #[cfg(not(feature = "parallel"))]
+    let results: Vec<_> = targets_at.iter().map(recolor_target).collect();
+
+    for result in results {
+        match result {
+            Ok((report, cached)) => summary.succeeded.push(target_result(report, cached)),
+            // Cancelled targets were left as they were, as the ones never started
+            Err((_, e)) if e.is_cancelled() => summary.interrupted = true,
+            Err(failure) => summary.failed.push(failure),
+        }
+    }
+    mark_stale(config, targets, options, &mut summary);
+
+    summary
+}
+
+fn progress(options: &RecolorOptions, event: ProgressEvent) {
+    if let Some(log) = &options.progress {
+        log.log(event);
+    }
+}
+
+/// Like `apply`, but all or nothing. Every target is first recolored into a staged copy next to
+/// its output and only once all of them succeeded are the copies renamed into place, so a
+/// failure leaves every file as it was. Staging takes room for a second copy of each target,
+/// on the same filesystem so the renames never copy.
+pub fn apply_transactional(
+    config: &Config,
+    selection: &Selection,
+    options: &RecolorOptions,
+) -> RunSummary {
+    let old = config.get_current_colors();
+    with_transform(
+        selection,
+        &old,
+        config.get_gradient_space(),
+        config.get_luminance_band(),
+        config.get_stops(),
+        |transform| {
+            let mut summary = empty_summary(config, &old, selection);
+            let mut staged = Vec::new();
+            let total = config.get_targets().len();
+            for (index, target) in config.get_targets().iter().enumerate() {
+                let (path, options) = target_options(target, options);
+                let result = options
+                    .cancel
+                    .as_ref()
+                    .map_or(Ok(()), Cancellation::check)
+                    .and_then(|()| {
+                        progress(
+                            &options,
+                            ProgressEvent::Started {
+                                path: &path,
+                                index,
+                                total,
+                            },
+                        );
+                        synced(
+                            config,
+                            target,
+                            &options,
+                            selection,
+                            transform,
+                            true,
+                            |transform| stage(&path, &options, transform),
+                        )
+                    });
+                match result {
+                    Ok(copy) => staged.push(copy),
+                    // Nothing was moved into place yet, dropping the staged copies undoes the run
+                    Err(e) if e.is_cancelled() => {
+                        summary.failed.clear();
+                        summary.interrupted = true;
+                        return summary;
+                    }
+                    Err(error) => {
+                        progress(
+                            &options,
+                            ProgressEvent::Failed {
+                                path: &path,
+                                index,
+                                total,
+                                done: index + 1,
+                                error: &error,
+                            },
+                        );
+                        summary.failed.push((path, error));
+                    }
+                }
+            }
+
+            // Dropping the staged copies removes them
+            if !summary.failed.is_empty() {
+                return summary;
+            }
+            for (index, (copy, report)) in staged.into_iter().enumerate() {
+                let committed = match copy {
+                    Some(copy) => commit(copy, &report.output),
+                    None => Ok(()),
+                };
+                match committed {
+                    Ok(()) => {
+                        progress(
+                            options,
+                            ProgressEvent::Finished {
+                                path: &report.source,
+                                index,
+                                total,
+                                done: index + 1,
+                                changes: report.changes,
+                            },
+                        );
+                        summary.succeeded.push(target_result(report, false));
+                    }
+                    Err(error) => {
+                        progress(
+                            options,
+                            ProgressEvent::Failed {
+                                path: &report.source,
+                                index,
+                                total,
+                                done: index + 1,
+                                error: &error,
+                            },
+                        );
+                        summary.failed.push((report.source, error));
+                    }
+                }
+            }
+            mark_stale(config, config.get_targets(), options, &mut summary);
+            summary
+        },
+    )
+}
+
+/// Recolors `path` into its staged copy, returning the copy and a report pointing at the
+/// output the copy replaces. There is no copy when the collision policy skips the target.
+fn stage(
+    path: &Path,
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+) -> Result<(Option<TempFile>, RecolorReport), TranError> {
+    if !path.is_file() {
+        return Err(TranError::FileNotFoundError(path.to_path_buf()));
+    }
+    let head = match options.format {
+        Some(_) => Vec::new(),
+        None => read_head(path)?,
+    };
+    let (_, output) = resolve_output(path, &head, options, transform);
+    let output = match output != path {
+        true => match claim_output(&output, options.collision, false)? {
+            Some(output) => output,
+            None => return recolor_path_dry(path, options, transform).map(|report| (None, report)),
+        },
+        false => output,
+    };
+    let copy = TempFile::next_to(&output);
+    let mut report = recolor_path_to(path, copy.path(), options, transform)?;
+    report.output = output;
+    Ok((Some(copy), report))
+}
+
+/// Writes `contents` to `path` through a staged copy next to it, so whatever reads the file
+/// never finds half of it.
+fn write_atomic(path: &Path, contents: &str) -> Result<(), TranError> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        crate::perms::create_dir_all(parent).map_err(|e| TranError::write(parent, e))?;
+    }
+    let copy = TempFile::next_to(path);
+    crate::perms::write(copy.path(), contents).map_err(|e| TranError::write(copy.path(), e))?;
+    commit(copy, path)
+}
+
+/// Moves a staged copy over `output`, keeping the permissions `output` had.
+fn commit(copy: TempFile, output: &Path) -> Result<(), TranError> {
+    if let Ok(metadata) = std::fs::metadata(output) {
+        std::fs::set_permissions(copy.path(), metadata.permissions())
+            .map_err(|e| TranError::write(copy.path(), e))?;
+    }
+    copy.persist(output)
+}
+
+/// The report of a target the cache shows is already in `colors`. Copies only count when the
+/// output is still there.
+fn cached(
+    cache: &Cache,
+    path: &Path,
+    options: &RecolorOptions,
+    transform: &ColorTransform,
+    colors: &str,
+) -> Option<RecolorReport> {
+    let data = std::fs::read(path).ok()?;
+    if !cache.is_current(path, &data, colors) {
+        return None;
+    }
+
+    let (format, output) = resolve_output(path, &data, options, transform);
+    if output != path && !output.is_file() {
+        return None;
+    }
+
+    Some(RecolorReport {
+        source: path.to_path_buf(),
+        output,
+        format,
+        backup: None,
+        changes: 0,
+        names: Vec::new(),
+        near_matches: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{recolor::Position, test_util::TempDir};
+
+    /// A gradient config from #111111 to #222222 over `targets`, with its state in `dir`.
+    fn options(dir: &TempDir, extra: &str, targets: &[&Path]) -> RunOptions {
+        let targets: Vec<String> = targets.iter().map(|t| t.display().to_string()).collect();
+        let config = dir.write(
+            "config",
+            format!(
+                "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n{}[target_files]\n{}\n",
+                extra,
+                targets.join("\n")
+            ),
+        );
+        let mut options = RunOptions::new(config);
+        options.state_dir = Some(dir.path().join("state"));
+        options
+    }
+
+    fn current_colors(options: &RunOptions) -> Vec<String> {
+        parse_config(&options.config_path)
+            .unwrap()
+            .get_current_colors()
+            .iter()
+            .map(|c| c.to_hex_lower())
+            .collect()
+    }
+
+    /// Backdates `path`, so a write to it can't go unnoticed within the resolution of mtimes.
+    fn backdate(path: &Path) -> std::time::SystemTime {
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(old))
+            .unwrap();
+        old
+    }
+
+    fn modified(path: &Path) -> std::time::SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    /// Moves the config back to #111111, as if nothing had run.
+    fn rewind(options: &RunOptions) {
+        let contents = std::fs::read_to_string(&options.config_path).unwrap();
+        std::fs::write(
+            &options.config_path,
+            contents.replacen("#222222", "#111111", 1),
+        )
+        .unwrap();
+        assert_eq!(current_colors(options), ["#111111"]);
+    }
+
+    #[test]
+    fn targets_the_cache_knows_are_not_rewritten() {
+        let dir = TempDir::new("run-cache");
+        let target = dir.write("a.conf", "fg=#111111\n");
+        let mut options = options(&dir, "", &[&target]);
+        options.color = Some("#222222".to_string());
+        let summary = run(&options).unwrap();
+        assert!(!summary.succeeded[0].cached);
+
+        rewind(&options);
+        let old = backdate(&target);
+        let summary = run(&options).unwrap();
+        assert!(summary.succeeded[0].cached);
+        assert_eq!(modified(&target), old);
+
+        // A file changed since is recolored again
+        rewind(&options);
+        std::fs::write(&target, "fg=#111111 bg=#111111\n").unwrap();
+        let summary = run(&options).unwrap();
+        assert!(!summary.succeeded[0].cached);
+        assert_eq!(summary.succeeded[0].changes, 2);
+
+        rewind(&options);
+        options.use_cache = false;
+        let summary = run(&options).unwrap();
+        assert!(!summary.succeeded[0].cached);
+    }
+
+    #[test]
+    fn targets_that_come_out_the_same_are_not_written() {
+        let dir = TempDir::new("run-same");
+        let target = dir.write("a.conf", "fg=none\n");
+        let options = options(&dir, "", &[&target]);
+        let old = backdate(&target);
+        let summary = run(&options).unwrap();
+        assert_eq!(summary.succeeded[0].changes, 0);
+        assert_eq!(modified(&target), old);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=none\n");
+    }
+
+    #[test]
+    fn a_run_carries_on_past_failed_targets() {
+        let dir = TempDir::new("run-partial");
+        let first = dir.write("first.conf", "fg=#111111 bg=#111111\n");
+        let missing = dir.path().join("missing.conf");
+        let last = dir.write("last.css", ":root { --fg: #111111; }\n");
+        let options = options(&dir, "", &[&first, &missing, &last]);
+
+        let summary = run(&options).unwrap();
+        let succeeded: Vec<(&Path, FileFormat, usize)> = summary
+            .succeeded
+            .iter()
+            .map(|t| (t.path.as_path(), t.handler, t.changes))
+            .collect();
+        assert_eq!(
+            succeeded,
+            [
+                (first.as_path(), FileFormat::Text, 2),
+                (last.as_path(), FileFormat::Text, 1)
+            ]
+        );
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, missing);
+        assert!(summary.failed[0].1.to_string().contains("missing.conf"));
+        assert_eq!(summary.exit_code(), 2);
+        assert!(summary.should_advance());
+        assert_eq!(current_colors(&options), ["#222222"]);
+
+        let json = summary.to_json();
+        assert_eq!(json.get("advanced"), Some(&Json::Bool(true)));
+        assert_eq!(
+            json.get("failed")
+                .and_then(Json::as_array)
+                .map(<[Json]>::len),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn the_config_stays_when_every_target_fails() {
+        let dir = TempDir::new("run-failed");
+        let options = options(&dir, "", &[&dir.path().join("a"), &dir.path().join("b")]);
+        let summary = run(&options).unwrap();
+        assert!(summary.succeeded.is_empty());
+        assert_eq!(summary.failed.len(), 2);
+        assert_eq!(summary.exit_code(), 1);
+        assert!(!summary.should_advance());
+        assert_eq!(current_colors(&options), ["#111111"]);
+    }
+
+    #[test]
+    fn a_clean_run_exits_with_zero() {
+        let dir = TempDir::new("run-clean");
+        let target = dir.write("a.conf", "fg=#111111\n");
+        let options = options(&dir, "", &[&target]);
+        let summary = run(&options).unwrap();
+        assert_eq!(summary.exit_code(), 0);
+        assert_eq!(summary.colors.mode, "gradient");
+        assert_eq!(summary.colors.old[0].to_hex_lower(), "#111111");
+        assert_eq!(summary.colors.new[0].to_hex_lower(), "#222222");
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=#222222\n");
+
+        // With nothing left to pick the run changes nothing
+        let summary = run(&options).unwrap();
+        assert!(summary.unchanged);
+        assert_eq!(summary.exit_code(), 0);
+    }
+
+    #[test]
+    fn picking_the_current_colors_writes_nothing() {
+        let dir = TempDir::new("run-unchanged");
+        let target = dir.write("a.conf", "fg=#111111\n");
+        let mut options = options(&dir, "", &[&target]);
+        options.color = Some("#111111".to_string());
+        let (target_time, config_time) = (backdate(&target), backdate(&options.config_path));
+
+        let summary = run(&options).unwrap();
+        assert!(summary.unchanged);
+        assert!(summary.succeeded.is_empty());
+        assert_eq!(
+            summary.to_json().get("unchanged").and_then(Json::as_bool),
+            Some(true)
+        );
+        assert_eq!(modified(&target), target_time);
+        assert_eq!(modified(&options.config_path), config_time);
+        assert!(!dir.path().join("state").join("runs.log").exists());
+    }
+
+    #[test]
+    fn sequences_are_written_after_runs_without_failures() {
+        let dir = TempDir::new("run-sequences");
+        let target = dir.write("a.conf", "fg=#111111\n");
+        let sequences = dir.path().join("cache").join("sequences");
+        let extra = format!("[sequences_file]\n{}\n", sequences.display());
+        let options = options(&dir, &extra, &[&target]);
+        run(&options).unwrap();
+        let written = std::fs::read_to_string(&sequences).unwrap();
+        assert!(
+            written.starts_with("\x1b]4;0;rgb:22/22/22\x1b\\"),
+            "{:?}",
+            written
+        );
+
+        // A failed target leaves the terminals on the scheme before
+        std::fs::remove_file(&sequences).unwrap();
+        rewind(&options);
+        let missing = dir.path().join("missing.conf");
+        let options = self::options(&dir, &extra, &[&target, &missing]);
+        let summary = run(&options).unwrap();
+        assert_eq!(summary.failed.len(), 1);
+        assert!(!sequences.exists());
+    }
+
+    #[test]
+    fn temporary_files_of_killed_runs_are_swept() {
+        let dir = TempDir::new("run-sweep");
+        let target = dir.write("colors.conf", "fg=#111111\n");
+        let orphan = dir.write(".tran-tmp-4294967295-0.conf", "fg=#222222\n");
+        backdate(&orphan);
+        let summary = run(&options(&dir, "", &[&target])).unwrap();
+        assert!(!orphan.exists());
+        assert!(summary
+            .warnings
+            .iter()
+            .any(|w| w.starts_with("Removed 1 temporary files")));
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(".tran-tmp-")
+            })
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+    }
+
+    #[test]
+    fn progress_is_told_for_every_target() {
+        let dir = TempDir::new("run-progress");
+        let first = dir.write("first.conf", "fg=#111111\n");
+        let missing = dir.path().join("missing.conf");
+        let mut options = options(&dir, "", &[&first, &missing]);
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = events.clone();
+        options.progress = Some(ProgressLog::new(move |event| {
+            let line = match event {
+                ProgressEvent::Started { index, total, .. } => {
+                    format!("started {}/{}", index, total)
+                }
+                ProgressEvent::Finished { index, changes, .. } => {
+                    format!("finished {} with {}", index, changes)
+                }
+                ProgressEvent::Failed { index, .. } => format!("failed {}", index),
+            };
+            log.lock().unwrap().push(line);
+        }));
+        run(&options).unwrap();
+        let mut events = events.lock().unwrap().clone();
+        events.sort();
+        assert_eq!(
+            events,
+            [
+                "failed 1",
+                "finished 0 with 1",
+                "started 0/2",
+                "started 1/2"
+            ]
+        );
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    #[test]
+    fn cancelled_runs_stop_between_targets_and_keep_the_colors() {
+        let dir = TempDir::new("run-cancel");
+        let first = dir.write("first.conf", "fg=#111111\n");
+        let second = dir.write("second.conf", "fg=#111111\n");
+        let mut options = options(&dir, "", &[&first, &second]);
+        let cancel = Cancellation::new();
+        let on_finish = cancel.clone();
+        options.cancel = Some(cancel);
+        options.progress = Some(ProgressLog::new(move |event| {
+            if let ProgressEvent::Finished { .. } = event {
+                on_finish.cancel();
+            }
+        }));
+        let summary = run(&options).unwrap();
+        assert!(summary.interrupted);
+        assert_eq!(summary.succeeded.len(), 1);
+        assert!(summary.failed.is_empty());
+        assert_eq!(summary.exit_code(), 2);
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "fg=#222222\n");
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "fg=#111111\n");
+        assert_eq!(current_colors(&options), ["#111111"]);
+        assert!(summary
+            .warnings
+            .iter()
+            .any(|w| w == "The run was cancelled, the config keeps its colors"));
+    }
+
+    #[test]
+    fn runs_pick_only_among_the_tags_asked_for() {
+        let dir = TempDir::new("run-tags");
+        let target = dir.write("colors.conf", "fg=#111111\n");
+        let config = dir.write(
+            "config",
+            format!(
+                "[mode]\ngradient\n[current_color]\n#111111\n[colors]\ndark:#222222\nlight:#eeeeee\n[target_files]\n{}\n",
+                target.display()
+            ),
+        );
+        let mut options = RunOptions::new(config);
+        options.state_dir = Some(dir.path().join("state"));
+        options.tags = vec!["light".to_string()];
+        run(&options).unwrap();
+        assert_eq!(current_colors(&options), ["#eeeeee"]);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=#eeeeee\n");
+    }
+
+    #[test]
+    fn backups_are_skipped_unless_taken() {
+        let dir = TempDir::new("run-backups");
+        let target = dir.write("colors.conf", "fg=#111111\n");
+        let backup = dir.write("colors.conf.bak", "fg=#111111\n");
+        let summary = run(&options(&dir, "", &[&target, &backup])).unwrap();
+        assert_eq!(summary.succeeded.len(), 1);
+        assert!(summary
+            .warnings
+            .iter()
+            .any(|w| w.starts_with("Skipping") && w.contains("colors.conf.bak")));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "fg=#111111\n");
+
+        let dir = TempDir::new("run-backups-taken");
+        let backup = dir.write("colors.conf~", "fg=#111111\n");
+        let extra = "[discover_options]\nbackups=true\n";
+        let summary = run(&options(&dir, extra, &[&backup])).unwrap();
+        assert_eq!(summary.succeeded.len(), 1);
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "fg=#222222\n");
+    }
+
+    #[test]
+    fn targets_named_for_another_format_are_warned_about() {
+        let dir = TempDir::new("run-mismatch");
+        let target = dir.write("logo.png", "fg=#111111\n");
+        let summary = run(&options(&dir, "", &[&target])).unwrap();
+        assert_eq!(summary.succeeded.len(), 1);
+        assert!(summary
+            .warnings
+            .iter()
+            .any(|w| w.contains("is named as png but holds text")));
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=#222222\n");
+    }
+
+    #[test]
+    fn detected_targets_are_taken_from_the_colors_they_hold() {
+        for (mode, expected) in [
+            ("", "fg=#bf616a\n"),
+            ("[sync_mode]\ndetect\n", "fg=#88c0d0\n"),
+        ] {
+            let dir = TempDir::new("run-sync");
+            let behind = dir.write("behind.conf", "fg=#bf616a\n");
+            let config = dir.write(
+                "config",
+                format!(
+                    "[mode]\nmap\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n#bf616a\n{}[target_files]\n{}\n",
+                    mode,
+                    behind.display()
+                ),
+            );
+            let mut options = RunOptions::new(config);
+            options.state_dir = Some(dir.path().join("state"));
+            options.color = Some("#88c0d0".to_string());
+            run(&options).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&behind).unwrap(),
+                expected,
+                "{}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn maps_of_other_colors_are_not_taken_from_the_row_a_detected_target_holds() {
+        let dir = TempDir::new("run-sync-map");
+        let behind = dir.write("behind.conf", "fg=#bf616a\nbg=#d08770\n");
+        let config = parse_config_contents(&format!(
+            "[mode]\nmap\n[current_color]\n#2e3440 #3b4252\n[colors]\n#88c0d0 #8fbcbb\n#bf616a #d08770\n[sync_mode]\ndetect\n[target_files]\n{}\n",
+            behind.display()
+        ))
+        .unwrap();
+        let [old, new] = ["#d08770", "#ffffff"].map(|c| Color::try_from_hex_str(c).unwrap());
+        let summary = apply_map(
+            &config,
+            config.get_targets(),
+            &[old],
+            &Selection::Row(vec![new]),
+            &recolor_options(&config, 0),
+        );
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        assert_eq!(
+            std::fs::read_to_string(&behind).unwrap(),
+            "fg=#bf616a\nbg=#ffffff\n"
+        );
+    }
+
+    #[test]
+    fn targets_holding_no_color_of_the_config_are_stale() {
+        let dir = TempDir::new("run-stale");
+        let stale = dir.write("stale.conf", "fg=#123456\n");
+        let other_row = dir.write("other.conf", "fg=#bf616a\n");
+        let current = dir.write("current.conf", "fg=#2e3440\n");
+        let config = dir.write(
+            "config",
+            format!(
+                "[mode]\nmap\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n#bf616a\n[target_files]\n{}\n{}\n{}\n",
+                stale.display(),
+                other_row.display(),
+                current.display()
+            ),
+        );
+        let mut options = RunOptions::new(config);
+        options.state_dir = Some(dir.path().join("state"));
+        options.color = Some("#88c0d0".to_string());
+        let summary = run(&options).unwrap();
+        let flagged: Vec<&Path> = summary.stale().map(|t| t.path.as_path()).collect();
+        assert_eq!(flagged, [stale.as_path()]);
+        let json = summary.to_json();
+        assert_eq!(
+            json.get("stale")
+                .and_then(Json::as_array)
+                .map(<[Json]>::len),
+            Some(1)
+        );
+
+        // Gradients ramp whatever a file holds
+        let config = crate::config::parse_config_contents(&format!(
+            "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n[target_files]\n{}\n",
+            stale.display()
+        ))
+        .unwrap();
+        let target = &config.get_targets()[0];
+        assert!(!is_stale(&config, target, &RecolorOptions::default()));
+    }
+
+    #[test]
+    fn map_rows_of_one_color_name_outputs_after_it() {
+        let config = crate::config::parse_config_contents(
+            "[mode]\nmap\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n#bf616a\n[output_name]\n{stem}.{color}.{index}.{ext}\n",
+        )
+        .unwrap();
+        let template = config.get_output_name().unwrap();
+        let names: Vec<PathBuf> = config
+            .get_selections()
+            .iter()
+            .enumerate()
+            .map(|(index, selection)| {
+                with_transform(
+                    selection,
+                    &config.get_current_colors(),
+                    config.get_gradient_space(),
+                    config.get_luminance_band(),
+                    config.get_stops(),
+                    |transform| {
+                        let template = crate::recolor::fill_run_fields(template, index, "");
+                        crate::recolor::output_path(Path::new("icon.png"), &template, transform)
+                    },
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                Path::new("icon.#88c0d0.0.png"),
+                Path::new("icon.#bf616a.1.png")
+            ]
+        );
+    }
+
+    #[test]
+    fn run_flags_override_the_recolor_options_of_the_config() {
+        let dir = TempDir::new("run-recolor-options");
+        let mut options = options(&dir, "[overwrite]\nfalse\n[allowed_roots]\n/nowhere\n", &[]);
+        let config = parse_config(&options.config_path).unwrap();
+        let recolor = options.recolor_options(&config, 0);
+        assert!(!recolor.overwrite);
+        assert_eq!(recolor.allowed_roots, [Path::new("/nowhere")]);
+        assert!(recolor.output_dir.is_none());
+
+        options.allow_outside = true;
+        options.out_dir = Some(dir.path().join("out"));
+        let recolor = options.recolor_options(&config, 0);
+        assert!(!recolor.overwrite);
+        assert!(recolor.allowed_roots.is_empty());
+        assert_eq!(recolor.output_dir.unwrap().dir, dir.path().join("out"));
+    }
+
+    #[test]
+    fn gradient_runs_ramp_the_targets_and_advance_the_config() {
+        let dir = TempDir::new("run-gradient");
+        let target = dir.write("a.css", ":root { --fg: #111111; --bg: #0c0c0c; }\n");
+        let mut options = options(&dir, "", &[&target]);
+        options.seed = Some(7);
+        let summary = run(&options).unwrap();
+        assert_eq!(summary.colors.mode, "gradient");
+        assert_eq!(summary.succeeded.len(), 1);
+        assert!(summary.failed.is_empty());
+        assert_eq!(current_colors(&options), ["#222222"]);
+        let text = std::fs::read_to_string(&target).unwrap();
+        assert!(text.contains("--fg: #222222;"), "{}", text);
+        assert!(!text.contains("#0c0c0c"), "{}", text);
+    }
+
+    #[test]
+    fn map_runs_recolor_every_color_of_a_row() {
+        let dir = TempDir::new("run-map");
+        let target = dir.write("a.conf", "fg=#111111 bg=#000000\n");
+        let config = dir.write(
+            "config",
+            format!(
+                "[mode]\nmap\n[current_color]\n#111111 #000000\n[colors]\n#eeeeee #333333\n[target_files]\n{}\n",
+                target.display()
+            ),
+        );
+        let mut options = RunOptions::new(config);
+        options.state_dir = Some(dir.path().join("state"));
+        let summary = run(&options).unwrap();
+        assert_eq!(summary.colors.mode, "map");
+        assert_eq!(summary.succeeded[0].changes, 2);
+        assert_eq!(
+            std::fs::read_to_string(&target).unwrap(),
+            "fg=#eeeeee bg=#333333\n"
+        );
+        assert_eq!(current_colors(&options), ["#eeeeee", "#333333"]);
+    }
+
+    #[test]
+    fn dry_runs_write_nothing() {
+        let dir = TempDir::new("run-dry");
+        let target = dir.write("a.conf", "fg=#111111\n");
+        let mut options = options(&dir, "", &[&target]);
+        options.dry_run = true;
+        let old = backdate(&target);
+        let summary = run(&options).unwrap();
+        assert_eq!(summary.succeeded[0].changes, 1);
+        assert_eq!(modified(&target), old);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=#111111\n");
+        assert_eq!(current_colors(&options), ["#111111"]);
+    }
+
+    #[test]
+    fn only_recolors_the_matching_targets() {
+        let dir = TempDir::new("run-only");
+        let kept = dir.write("a.conf", "fg=#111111\n");
+        let recolored = dir.write("b.css", "fg=#111111\n");
+        let mut options = options(&dir, "", &[&kept, &recolored]);
+        options.only = vec!["*.css".to_string()];
+        let summary = run(&options).unwrap();
+        let paths: Vec<&Path> = summary.succeeded.iter().map(|t| t.path.as_path()).collect();
+        assert_eq!(paths, [recolored.as_path()]);
+        assert_eq!(std::fs::read_to_string(&kept).unwrap(), "fg=#111111\n");
+        assert_eq!(std::fs::read_to_string(&recolored).unwrap(), "fg=#222222\n");
+        assert_eq!(current_colors(&options), ["#222222"]);
+    }
+
+    #[test]
+    fn seeded_runs_pick_the_same_colors() {
+        let picks: Vec<String> = (0..2)
+            .map(|i| {
+                let dir = TempDir::new(&format!("run-seed-{}", i));
+                let target = dir.write("a.conf", "fg=#111111\n");
+                let mut options = options(&dir, "#333333\n#444444\n#555555\n", &[&target]);
+                options.seed = Some(42);
+                run(&options).unwrap();
+                current_colors(&options).join(" ")
+            })
+            .collect();
+        assert_eq!(picks[0], picks[1]);
+    }
+
+    #[test]
+    fn targets_picking_colors_keep_the_rest_of_the_row() {
+        let dir = TempDir::new("run-target-colors");
+        let line = "#000000 #111111 #222222\n";
+        let full = dir.write("full.conf", line);
+        let picked = dir.write("picked.css", line);
+        let config = dir.write(
+            "config",
+            format!(
+                "[mode]\nmap\n[current_color]\n#000000 #111111 #222222\n[colors]\n#aaaaaa #bbbbbb #cccccc\n[target_files]\n{}\n{}|colors=0,2\n",
+                full.display(),
+                picked.display()
+            ),
+        );
+        let mut options = RunOptions::new(config);
+        options.state_dir = Some(dir.path().join("state"));
+        run(&options).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&full).unwrap(),
+            "#aaaaaa #bbbbbb #cccccc\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&picked).unwrap(),
+            "#aaaaaa #111111 #cccccc\n"
+        );
+    }
+
+    #[test]
+    fn swapped_colors_are_not_narrowed_to_the_colors_a_target_picks() {
+        let dir = TempDir::new("run-swap-picked");
+        let picked = dir.write("picked.conf", "#000000 #111111 #222222\n");
+        let config = parse_config_contents(&format!(
+            "[mode]\nmap\n[current_color]\n#000000 #111111 #222222\n[colors]\n#aaaaaa #bbbbbb #cccccc\n[target_files]\n{}|colors=0,2\n",
+            picked.display()
+        ))
+        .unwrap();
+        let [a, b] = ["#000000", "#222222"].map(|c| Color::try_from_hex_str(c).unwrap());
+        let summary = apply_map(
+            &config,
+            config.get_targets(),
+            &[a, b],
+            &Selection::Row(vec![b, a]),
+            &recolor_options(&config, 0),
+        );
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        assert_eq!(
+            std::fs::read_to_string(&picked).unwrap(),
+            "#222222 #111111 #000000\n"
+        );
+    }
+
+    #[test]
+    fn a_file_listed_twice_is_recolored_once() {
+        let dir = TempDir::new("run-listed-twice");
+        let twice = dir.write("twice.conf", "#000000 #111111\n");
+        let config = parse_config_contents(&format!(
+            "[mode]\nmap\n[current_color]\n#000000 #111111\n[colors]\n#111111 #000000\n[target_files]\n{}\n{}\n",
+            twice.display(),
+            twice.parent().unwrap().join(".").join("twice.conf").display()
+        ))
+        .unwrap();
+        let [a, b] = ["#000000", "#111111"].map(|c| Color::try_from_hex_str(c).unwrap());
+        let summary = apply_map(
+            &config,
+            config.get_targets(),
+            &[a, b],
+            &Selection::Row(vec![b, a]),
+            &recolor_options(&config, 0),
+        );
+        // Swapped twice it would be back where it was
+        assert_eq!(
+            std::fs::read_to_string(&twice).unwrap(),
+            "#111111 #000000\n"
+        );
+        assert_eq!(summary.succeeded.len(), 1);
+        assert_eq!(summary.warnings.len(), 1, "{:?}", summary.warnings);
+    }
+
+    #[test]
+    fn journaled_runs_log_every_replacement() {
+        let dir = TempDir::new("run-journal");
+        let target = dir.write("a.conf", "fg=#111111\nbg=#000000 #111111\n");
+        let journaled = options(&dir, "[journal]\ntrue\n", &[&target]);
+        run(&journaled).unwrap();
+
+        let state = State::at(journaled.state_dir.as_ref().unwrap()).unwrap();
+        let id = state.recent_runs(1).unwrap()[0].id;
+        let replacements = state.replacements(id).unwrap();
+        let positions: Vec<&Position> = replacements.iter().map(|r| &r.position).collect();
+        assert_eq!(
+            positions,
+            [
+                &Position::Text {
+                    offset: 3,
+                    line: 1,
+                    column: 4
+                },
+                &Position::Text {
+                    offset: 22,
+                    line: 2,
+                    column: 12
+                },
+            ]
+        );
+        for replacement in replacements.iter() {
+            assert_eq!(replacement.file, target);
+            assert_eq!(replacement.old.to_hex_lower(), "#111111");
+            assert_eq!(replacement.new.to_hex_lower(), "#222222");
+        }
+        assert_eq!(state.replacements(id + 1).unwrap(), []);
+
+        // Without [journal] nothing more is logged
+        let unlogged = TempDir::new("run-journal-off");
+        let target = unlogged.write("a.conf", "fg=#111111\n");
+        let plain = options(&unlogged, "", &[&target]);
+        run(&plain).unwrap();
+        let state = State::at(plain.state_dir.as_ref().unwrap()).unwrap();
+        assert!(!state.get_replacements_log_path().exists());
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{Color, Target},
+    css::Indirect,
+    errors::TranError,
+    json::Json,
+    recolor::read_head,
+    run::target_options,
+    FileFormat, RecolorOptions,
+};
+
+/// A color found while scanning and where it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorUse {
+    pub color: Color,
+    /// Occurrences in text files plus palette entries in pngs.
+    pub count: usize,
+    /// Files the color appears in, in the order they were scanned.
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// Most frequent first.
+    pub colors: Vec<ColorUse>,
+    /// Colors css targets take from outside themselves, by file.
+    pub indirect: Vec<(PathBuf, Vec<Indirect>)>,
+    pub failed: Vec<(PathBuf, TranError)>,
+}
+
+impl ScanReport {
+    pub fn to_json(&self) -> Json {
+        let colors: Vec<Json> = self
+            .colors
+            .iter()
+            .map(|c| {
+                Json::object()
+                    .with("color", c.color.to_string())
+                    .with("count", c.count)
+                    .with(
+                        "files",
+                        c.files
+                            .iter()
+                            .map(|f| f.to_string_lossy().to_string())
+                            .collect::<Vec<_>>(),
+                    )
+            })
+            .collect();
+        let indirect: Vec<Json> = self
+            .indirect
+            .iter()
+            .map(|(path, uses)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("uses", uses_to_json(uses))
+            })
+            .collect();
+        let failed: Vec<Json> = self
+            .failed
+            .iter()
+            .map(|(path, e)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("error", e.to_string())
+            })
+            .collect();
+
+        Json::object()
+            .with("colors", colors)
+            .with("indirect", indirect)
+            .with("failed", failed)
+    }
+
+    /// A map row of the `count` most frequent colors, as `[current_color]` and `[colors]`
+    /// sections ready to paste into a config.
+    pub fn suggest_map(&self, count: usize) -> String {
+        let row: String = self
+            .colors
+            .iter()
+            .take(count)
+            .map(|c| c.color.to_string())
+            .collect();
+        format!("[current_color]\n{}\n[colors]\n{}\n", row, row)
+    }
+}
+
+pub(crate) fn uses_to_json(uses: &[Indirect]) -> Vec<Json> {
+    uses.iter()
+        .map(|u| {
+            Json::object()
+                .with("usage", u.usage.as_str())
+                .with("line", u.line)
+        })
+        .collect()
+}
+
+fn format_of(path: &Path, options: &RecolorOptions) -> Result<FileFormat, TranError> {
+    if !path.is_file() {
+        return Err(TranError::FileNotFoundError(path.to_path_buf()));
+    }
+    match options.format {
+        Some(format) => Ok(format),
+        None => Ok(FileFormat::detect(path, &read_head(path)?)),
+    }
+}
+
+/// The colors a run would find in `path`, using the same handler it would pick.
+pub(crate) fn scan_file(path: &Path, options: &RecolorOptions) -> Result<Vec<Color>, TranError> {
+    let format = format_of(path, options)?;
+    if format == FileFormat::Exec {
+        return Err(TranError::UnsupportedError(format!(
+            "Can't scan {}, an exec command recolors it",
+            path.display()
+        )));
+    }
+    if format == FileFormat::Png {
+        #[cfg(not(feature = "png"))]
+        return Err(TranError::without_png());
+        #[cfg(feature = "png")]
+        {
+            let data = std::fs::read(path).map_err(|e| TranError::read(path, e))?;
+            return crate::png::palette_colors(&data);
+        }
+    }
+
+    let contents = std::fs::read_to_string(path).map_err(|e| TranError::read(path, e))?;
+    Ok(match format {
+        FileFormat::Gtk => crate::gtk::colors(&contents, options.literals, &options.text),
+        FileFormat::I3 => crate::i3::colors(&contents, options.literals, &options.text),
+        FileFormat::Css => crate::css::colors(&contents, options.literals, &options.text),
+        FileFormat::Json => crate::jsonc::colors(&contents, &options.text),
+        FileFormat::Rasi => crate::rasi::colors(&contents, &options.text),
+        FileFormat::Ini => crate::ini::colors(&contents, &options.ini),
+        _ => options
+            .text
+            .tokens(&contents)
+            .iter()
+            .map(|t| t.color)
+            .collect(),
+    })
+}
+
+/// Whether `path` holds any of `colors` where its handler recolors, scanning no further than
+/// the first. What an exec command does with a file can't be told, so it always might.
+pub(crate) fn holds_any(
+    path: &Path,
+    options: &RecolorOptions,
+    colors: &[Color],
+) -> Result<bool, TranError> {
+    match format_of(path, options)? {
+        FileFormat::Exec => Ok(true),
+        FileFormat::Text => {
+            let contents = std::fs::read_to_string(path).map_err(|e| TranError::read(path, e))?;
+            let holds = crate::text::hex_tokens_iter(&contents).any(|t| colors.contains(&t.color));
+            Ok(holds)
+        }
+        _ => Ok(scan_file(path, options)?
+            .iter()
+            .any(|color| colors.contains(color))),
+    }
+}
+
+/// The colors a css target takes from outside itself, none for any other handler.
+pub(crate) fn scan_indirect(
+    path: &Path,
+    options: &RecolorOptions,
+) -> Result<Vec<Indirect>, TranError> {
+    if format_of(path, options)? != FileFormat::Css {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(path).map_err(|e| TranError::read(path, e))?;
+    Ok(crate::css::indirect(&contents))
+}
+
+/// Counts the colors of every target, with its settings applied, carrying on past files that
+/// can't be scanned.
+pub fn scan(targets: &[Target], options: &RecolorOptions) -> ScanReport {
+    let mut report = ScanReport::default();
+    let mut indices: HashMap<Color, usize> = HashMap::new();
+    for target in targets {
+        let (path, options) = target_options(target, options);
+        let colors = match scan_file(&path, &options) {
+            Ok(colors) => colors,
+            Err(e) => {
+                report.failed.push((path.clone(), e.in_file(&path)));
+                continue;
+            }
+        };
+        match scan_indirect(&path, &options) {
+            Ok(uses) if uses.is_empty() => {}
+            Ok(uses) => report.indirect.push((path.clone(), uses)),
+            Err(e) => report.failed.push((path.clone(), e.in_file(&path))),
+        }
+        for color in colors {
+            let index = *indices.entry(color).or_insert_with(|| {
+                report.colors.push(ColorUse {
+                    color,
+                    count: 0,
+                    files: Vec::new(),
+                });
+                report.colors.len() - 1
+            });
+            let found = &mut report.colors[index];
+            found.count += 1;
+            if !found.files.contains(&path) {
+                found.files.push(path.clone());
+            }
+        }
+    }
+
+    // Stable, so ties keep the order they were first found in
+    report.colors.sort_by_key(|c| std::cmp::Reverse(c.count));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn hex(color: &str) -> Color {
+        Color::try_from_hex_str(color).unwrap()
+    }
+
+    /// Two text files sharing #2e3440 and a palette png holding it and #88c0d0.
+    #[cfg(feature = "png")]
+    fn targets(dir: &TempDir) -> Vec<PathBuf> {
+        let png = crate::png::build::PngBuilder::palette(
+            &[(0x2e, 0x34, 0x40), (0x88, 0xc0, 0xd0)],
+            &[vec![0, 1], vec![1, 0]],
+        )
+        .build();
+        vec![
+            dir.write("a.css", "a { color: #2e3440; background: #ECEFF4; }\n"),
+            dir.write("b.conf", "fg=#2e3440\nbg=#2E3440\nhl=#88c0d0\n"),
+            dir.write("icon.png", &png),
+        ]
+    }
+
+    fn lines(paths: &[PathBuf]) -> Vec<Target> {
+        paths.iter().map(Target::new).collect()
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn colors_are_counted_across_files_most_frequent_first() {
+        let dir = TempDir::new("scan-counts");
+        let paths = targets(&dir);
+        let report = scan(&lines(&paths), &RecolorOptions::default());
+        assert!(report.failed.is_empty());
+        let counts: Vec<(Color, usize, &[PathBuf])> = report
+            .colors
+            .iter()
+            .map(|c| (c.color, c.count, c.files.as_slice()))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (hex("#2e3440"), 4, &paths[..]),
+                (hex("#88c0d0"), 2, &paths[1..]),
+                (hex("#eceff4"), 1, &paths[..1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn targets_that_fail_are_reported_and_skipped() {
+        let dir = TempDir::new("scan-failed");
+        let found = dir.write("a.conf", "fg=#2e3440\n");
+        let missing = dir.path().join("missing.conf");
+        let report = scan(
+            &lines(&[missing.clone(), found]),
+            &RecolorOptions::default(),
+        );
+        assert_eq!(report.colors.len(), 1);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, missing);
+        assert!(report.failed[0].1.to_string().contains("missing.conf"));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn reports_convert_to_json() {
+        let dir = TempDir::new("scan-json");
+        let paths = targets(&dir);
+        let mut targets = lines(&paths);
+        targets.push(Target::new(dir.path().join("missing.conf")));
+        let json = scan(&targets, &RecolorOptions::default()).to_json();
+        let json = Json::parse(&json.to_string()).unwrap();
+
+        let colors = json.get("colors").and_then(Json::as_array).unwrap();
+        assert_eq!(colors.len(), 3);
+        assert_eq!(
+            colors[0].get("color").and_then(Json::as_str),
+            Some("#2e3440")
+        );
+        assert_eq!(colors[0].get("count").and_then(Json::as_u64), Some(4));
+        let files: Vec<&str> = colors[1]
+            .get("files")
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter()
+            .filter_map(Json::as_str)
+            .collect();
+        let paths: Vec<String> = targets[1..3]
+            .iter()
+            .map(|t| t.path.display().to_string())
+            .collect();
+        assert_eq!(files, paths);
+        let failed = json.get("failed").and_then(Json::as_array).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(
+            failed[0].get("path").and_then(Json::as_str),
+            Some(targets[3].path.display().to_string().as_str())
+        );
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn suggested_maps_take_the_most_frequent_colors() {
+        let dir = TempDir::new("scan-suggest");
+        let report = scan(&lines(&targets(&dir)), &RecolorOptions::default());
+        assert_eq!(
+            report.suggest_map(2),
+            "[current_color]\n#2e3440#88c0d0\n[colors]\n#2e3440#88c0d0\n"
+        );
+        // Asking for more colors than were found suggests all of them
+        assert_eq!(
+            report.suggest_map(10),
+            "[current_color]\n#2e3440#88c0d0#eceff4\n[colors]\n#2e3440#88c0d0#eceff4\n"
+        );
+    }
+}
@@ -0,0 +1,812 @@
+//! When `tran daemon` recolors, from the `[schedule]` section of the config. Every line is
+//! either a `HH:MM` time or a restricted cron expression of `minute hour day-of-week`, all in
+//! local time:
+//!
+//! ```text
+//! [schedule]
+//! 09:00
+//! 21:00
+//! */30 8-18 1-5
+//! ```
+
+use crate::errors::TranError;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// One line of `[schedule]`, the minutes, hours and days of the week it fires on as bit sets.
+/// Days count from Sunday.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ScheduleEntry {
+    source: String,
+    minutes: u64,
+    hours: u32,
+    days: u8,
+}
+
+impl ScheduleEntry {
+    pub fn parse(line: &str) -> Result<Self, TranError> {
+        let line = line.trim();
+        let invalid = |reason: &str| {
+            TranError::ConfigError(format!("Invalid schedule '{}': {}", line, reason))
+        };
+        let (minutes, hours, days) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [time] => {
+                let (hour, minute) = time
+                    .split_once(':')
+                    .ok_or_else(|| invalid("expected HH:MM or 'minute hour day-of-week'"))?;
+                let number = |value: &str, max: u32| {
+                    value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|n| (1..=2).contains(&value.len()) && *n <= max)
+                        .ok_or_else(|| {
+                            invalid("expected HH:MM with hours 0 to 23 and minutes 0 to 59")
+                        })
+                };
+                (
+                    1 << number(minute, 59)?,
+                    1 << number(hour, 23)?,
+                    u8::MAX >> 1,
+                )
+            }
+            [minute, hour, day] => {
+                let days = field(day, 0, 7).map_err(|reason| invalid(&reason))?;
+                // Both 0 and 7 are Sunday
+                let days = ((days | (days >> 7)) & 0x7f) as u8;
+                (
+                    field(minute, 0, 59).map_err(|reason| invalid(&reason))?,
+                    field(hour, 0, 23).map_err(|reason| invalid(&reason))? as u32,
+                    days,
+                )
+            }
+            _ => return Err(invalid("expected HH:MM or 'minute hour day-of-week'")),
+        };
+        Ok(ScheduleEntry {
+            source: line.to_string(),
+            minutes,
+            hours,
+            days,
+        })
+    }
+
+    fn fires_on(&self, day_of_week: i64) -> bool {
+        self.days & (1 << day_of_week) != 0
+    }
+}
+
+impl std::fmt::Display for ScheduleEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// The values a cron field of `*`, numbers, `a-b` ranges and `/n` steps, separated by commas,
+/// takes between `min` and `max`.
+fn field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut values = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u64>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("'{}' is not a step", step)),
+            },
+            None => (part, 1),
+        };
+        let number = |value: &str| match value.parse::<u64>() {
+            Ok(n) if (min..=max).contains(&n) => Ok(n),
+            _ => Err(format!("'{}' is not between {} and {}", value, min, max)),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/15` runs from 5 to the end, as in cron
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start > end {
+            return Err(format!("the range '{}' runs backwards", range));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            values |= 1 << value;
+        }
+    }
+    Ok(values)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Schedule {
+    entries: Vec<ScheduleEntry>,
+}
+
+impl Schedule {
+    pub fn push_line(&mut self, line: &str) -> Result<(), TranError> {
+        self.entries.push(ScheduleEntry::parse(line)?);
+        Ok(())
+    }
+
+    pub fn get_entries(&self) -> &[ScheduleEntry] {
+        &self.entries
+    }
+
+    /// The first instant, in seconds since the unix epoch, after `now` that any entry fires
+    /// on in `zone`. A time skipped when the clocks go forward fires as long after the jump as
+    /// it was after the hour, one repeated when they go back only fires the first time.
+    pub fn next_after(&self, now: i64, zone: &TimeZone) -> Option<i64> {
+        let today = (now + zone.offset_at(now)).div_euclid(DAY);
+        // A week and a day covers every day of the week even when the offset changes
+        for day in today..today + 8 {
+            // The epoch was a Thursday
+            let day_of_week = (day + 4).rem_euclid(7);
+            let mut first: Option<i64> = None;
+            for entry in self.entries.iter().filter(|e| e.fires_on(day_of_week)) {
+                let times = (0..24)
+                    .filter(|hour| entry.hours & (1 << hour) != 0)
+                    .flat_map(|hour| {
+                        (0..60)
+                            .filter(|minute| entry.minutes & (1 << minute) != 0)
+                            .map(move |minute| hour * 3600 + minute * 60)
+                    });
+                for time in times {
+                    let instant = zone.resolve(day * DAY + time);
+                    if instant > now && first.is_none_or(|first| instant < first) {
+                        first = Some(instant);
+                    }
+                }
+            }
+            if first.is_some() {
+                return first;
+            }
+        }
+        None
+    }
+}
+
+/// The offsets from UTC a time zone had over time, enough of a zone file to tell local time.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TimeZone {
+    /// The offset in seconds before the first transition.
+    initial: i64,
+    /// When the offset changed, in seconds since the unix epoch, and what it changed to.
+    transitions: Vec<(i64, i64)>,
+    /// The rule the offsets follow after the last transition, see `Rule`.
+    rule: Option<Rule>,
+}
+
+/// A POSIX TZ rule like `CET-1CEST,M3.5.0,M10.5.0/3`, as version 2 and later zone files end
+/// with for the years past their transitions. Offsets are in seconds east of UTC, the other
+/// way around from how the rule writes them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Rule {
+    standard: i64,
+    daylight: Option<Daylight>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Daylight {
+    offset: i64,
+    /// The day daylight saving time starts and the local standard time it starts at.
+    start: (RuleDay, i64),
+    /// The day it ends and the local daylight saving time it ends at.
+    end: (RuleDay, i64),
+}
+
+/// A day of the year in a rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum RuleDay {
+    /// `Jn`, 1 to 365, never counting February 29th.
+    Julian(i64),
+    /// `n`, 0 to 365, counting February 29th.
+    Zero(i64),
+    /// `Mm.w.d`, day `d` of week `w` of month `m`, days counting from Sunday and week 5 being
+    /// the last.
+    Month(i64, i64, i64),
+}
+
+impl Rule {
+    fn parse(tz: &str) -> Option<Self> {
+        let rest = skip_zone_name(tz)?;
+        let (standard, rest) = rule_time(rest)?;
+        if rest.is_empty() {
+            return Some(Rule {
+                standard: -standard,
+                daylight: None,
+            });
+        }
+        let rest = skip_zone_name(rest)?;
+        let (offset, rest) = match rest.is_empty() || rest.starts_with(',') {
+            true => (-standard + 3600, rest),
+            false => rule_time(rest).map(|(offset, rest)| (-offset, rest))?,
+        };
+        // Without dates the rule is the one of the United States
+        let (start, end) = match rest {
+            "" => ("M3.2.0", "M11.1.0"),
+            rest => rest.strip_prefix(',')?.split_once(',')?,
+        };
+        Some(Rule {
+            standard: -standard,
+            daylight: Some(Daylight {
+                offset,
+                start: rule_date(start)?,
+                end: rule_date(end)?,
+            }),
+        })
+    }
+
+    fn offset_at(&self, utc: i64) -> i64 {
+        let Some(daylight) = self.daylight else {
+            return self.standard;
+        };
+        let year = year_of((utc + self.standard).div_euclid(DAY));
+        let start = daylight.start.0.day(year) * DAY + daylight.start.1 - self.standard;
+        let end = daylight.end.0.day(year) * DAY + daylight.end.1 - daylight.offset;
+        // South of the equator daylight saving time spans the turn of the year
+        let in_daylight = match start < end {
+            true => start <= utc && utc < end,
+            false => utc < end || start <= utc,
+        };
+        match in_daylight {
+            true => daylight.offset,
+            false => self.standard,
+        }
+    }
+}
+
+impl RuleDay {
+    /// The day since the unix epoch this is in `year`.
+    fn day(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, 1, 1);
+        match *self {
+            RuleDay::Julian(n) => {
+                let leap = days_from_civil(year, 3, 1) - days_from_civil(year, 2, 28) == 2;
+                first + n - 1 + (leap && n >= 60) as i64
+            }
+            RuleDay::Zero(n) => first + n,
+            RuleDay::Month(month, week, weekday) => {
+                let first = days_from_civil(year, month, 1);
+                let next = match month {
+                    12 => days_from_civil(year + 1, 1, 1),
+                    month => days_from_civil(year, month + 1, 1),
+                };
+                // The epoch was a Thursday
+                let first_weekday = first + (weekday - (first + 4)).rem_euclid(7);
+                let mut day = first_weekday + (week - 1) * 7;
+                while day >= next {
+                    day -= 7;
+                }
+                day
+            }
+        }
+    }
+}
+
+/// What follows the name of a zone at the start of `tz`, three letters or more or anything
+/// between `<` and `>`.
+fn skip_zone_name(tz: &str) -> Option<&str> {
+    if let Some(rest) = tz.strip_prefix('<') {
+        return rest.split_once('>').map(|(_, rest)| rest);
+    }
+    let len = tz
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(tz.len());
+    (len >= 3).then(|| &tz[len..])
+}
+
+/// A `[+-]hh[:mm[:ss]]` at the start of `tz` in seconds, and what follows it.
+fn rule_time(tz: &str) -> Option<(i64, &str)> {
+    let (sign, tz) = match tz.strip_prefix('-') {
+        Some(tz) => (-1, tz),
+        None => (1, tz.strip_prefix('+').unwrap_or(tz)),
+    };
+    let len = tz
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(tz.len());
+    let mut seconds = 0;
+    for (i, part) in tz[..len].split(':').enumerate() {
+        let unit = [3600, 60, 1].get(i)?;
+        seconds += unit * part.parse::<i64>().ok().filter(|_| part.len() <= 3)?;
+    }
+    Some((sign * seconds, &tz[len..]))
+}
+
+/// A `day[/time]` of a rule, at 02:00 when it has no time.
+fn rule_date(date: &str) -> Option<(RuleDay, i64)> {
+    let (day, time) = match date.split_once('/') {
+        Some((day, time)) => match rule_time(time)? {
+            (time, "") => (day, time),
+            _ => return None,
+        },
+        None => (date, 2 * 3600),
+    };
+    let number = |n: &str, range: std::ops::RangeInclusive<i64>| {
+        n.parse().ok().filter(|n| range.contains(n))
+    };
+    let day = if let Some(n) = day.strip_prefix('J') {
+        RuleDay::Julian(number(n, 1..=365)?)
+    } else if let Some(month) = day.strip_prefix('M') {
+        match month.split('.').collect::<Vec<_>>()[..] {
+            [month, week, weekday] => RuleDay::Month(
+                number(month, 1..=12)?,
+                number(week, 1..=5)?,
+                number(weekday, 0..=6)?,
+            ),
+            _ => return None,
+        }
+    } else {
+        RuleDay::Zero(number(day, 0..=365)?)
+    };
+    Some((day, time))
+}
+
+/// The day since the unix epoch of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year the day since the unix epoch `day` is in.
+fn year_of(day: i64) -> i64 {
+    // A guess the loops correct, leap years make it a little late
+    let mut year = 1970 + day.div_euclid(365);
+    while days_from_civil(year + 1, 1, 1) <= day {
+        year += 1;
+    }
+    while days_from_civil(year, 1, 1) > day {
+        year -= 1;
+    }
+    year
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        TimeZone::default()
+    }
+
+    pub fn new(initial: i64, mut transitions: Vec<(i64, i64)>) -> Self {
+        transitions.sort();
+        TimeZone {
+            initial,
+            transitions,
+            rule: None,
+        }
+    }
+
+    /// The zone of a POSIX TZ rule like `EST5EDT,M3.2.0,M11.1.0`.
+    pub fn from_posix(tz: &str) -> Result<Self, TranError> {
+        let rule = Rule::parse(tz).ok_or_else(|| {
+            TranError::UnsupportedError(format!("'{}' is not a valid TZ rule", tz))
+        })?;
+        Ok(TimeZone {
+            initial: rule.standard,
+            transitions: Vec::new(),
+            rule: Some(rule),
+        })
+    }
+
+    /// The offset from UTC at the instant `utc`. Past the last transition of the zone the
+    /// offset follows its TZ rule, or stays the last one without a rule.
+    pub fn offset_at(&self, utc: i64) -> i64 {
+        match (
+            self.transitions.partition_point(|(at, _)| *at <= utc),
+            self.rule,
+        ) {
+            (i, Some(rule)) if i == self.transitions.len() => rule.offset_at(utc),
+            (0, _) => self.initial,
+            (i, _) => self.transitions[i - 1].1,
+        }
+    }
+
+    /// The first instant the clock shows `local`, or when it shows it skipped, the instant it
+    /// would have been without the jump.
+    fn resolve(&self, local: i64) -> i64 {
+        // No zone changes its offset twice in a day, so the offsets a day away are every
+        // offset `local` could be in
+        let before = self.offset_at(local - DAY);
+        let after = self.offset_at(local + DAY);
+        [before, after]
+            .into_iter()
+            .map(|offset| local - offset)
+            .filter(|utc| self.offset_at(*utc) == local - utc)
+            .min()
+            .unwrap_or(local - before)
+    }
+
+    /// Reads a TZif zone file, as in `/etc/localtime`, with the TZ rule version 2 and later
+    /// end with.
+    pub fn from_tzif(data: &[u8]) -> Result<Self, TranError> {
+        let invalid = || TranError::UnsupportedError("Not a valid zone file".to_string());
+        let header = |at: usize| -> Option<[usize; 6]> {
+            let header = data.get(at..at + 44)?;
+            if &header[..4] != b"TZif" {
+                return None;
+            }
+            let count = |i: usize| {
+                let bytes = &header[20 + 4 * i..24 + 4 * i];
+                u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+            };
+            Some([count(0), count(1), count(2), count(3), count(4), count(5)])
+        };
+        let [utc_count, std_count, leap_count, time_count, type_count, char_count] =
+            header(0).ok_or_else(invalid)?;
+        let mut at = 0;
+        let mut time_size = 4;
+        // Version 2 and later repeat the data with 64 bit times after the first block
+        if data[4] >= b'2' {
+            at = 44
+                + time_count * 5
+                + type_count * 6
+                + char_count
+                + leap_count * 8
+                + std_count
+                + utc_count;
+            time_size = 8;
+        }
+        let [utc_count, std_count, leap_count, time_count, type_count, char_count] =
+            header(at).ok_or_else(invalid)?;
+        let body = data.get(at + 44..).ok_or_else(invalid)?;
+        let times = body.get(..time_count * time_size).ok_or_else(invalid)?;
+        let indices = body
+            .get(times.len()..times.len() + time_count)
+            .ok_or_else(invalid)?;
+        let types_at = times.len() + indices.len();
+        let types = body
+            .get(types_at..types_at + type_count * 6)
+            .ok_or_else(invalid)?;
+        let offset = |index: usize| -> Option<i64> {
+            let info = types.get(index * 6..index * 6 + 4)?;
+            Some(i32::from_be_bytes([info[0], info[1], info[2], info[3]]) as i64)
+        };
+        let transitions = times
+            .chunks_exact(time_size)
+            .zip(indices)
+            .map(|(time, index)| {
+                let time = match time_size {
+                    8 => i64::from_be_bytes(time.try_into().ok()?),
+                    _ => i32::from_be_bytes(time.try_into().ok()?) as i64,
+                };
+                Some((time, offset(*index as usize)?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let mut zone = TimeZone::new(offset(0).ok_or_else(invalid)?, transitions);
+
+        if time_size == 8 {
+            let footer_at =
+                types_at + types.len() + char_count + leap_count * 12 + std_count + utc_count;
+            // The rule is on a line of its own, the line is empty when there is none
+            let footer = body.get(footer_at..).ok_or_else(invalid)?;
+            let footer = std::str::from_utf8(footer).map_err(|_| invalid())?;
+            let rule = footer
+                .strip_prefix('\n')
+                .and_then(|footer| footer.split_once('\n'))
+                .map(|(rule, _)| rule)
+                .ok_or_else(invalid)?;
+            if !rule.is_empty() {
+                zone.rule = Some(Rule::parse(rule).ok_or_else(invalid)?);
+            }
+        }
+        Ok(zone)
+    }
+
+    /// The zone of `TZ` when set, `/etc/localtime` otherwise, UTC when neither names one.
+    #[cfg(feature = "fs")]
+    pub fn local() -> Result<Self, TranError> {
+        let path = match std::env::var("TZ") {
+            Ok(tz) if tz.trim_start_matches(':').is_empty() || tz == "UTC" => {
+                return Ok(TimeZone::utc())
+            }
+            Ok(tz) => {
+                let tz = tz.trim_start_matches(':');
+                match tz.starts_with('/') {
+                    true => std::path::PathBuf::from(tz),
+                    false => std::path::Path::new("/usr/share/zoneinfo").join(tz),
+                }
+            }
+            Err(_) => std::path::PathBuf::from("/etc/localtime"),
+        };
+        if !path.is_file() {
+            return match std::env::var("TZ") {
+                // TZ can hold a rule rather than name a zone
+                Ok(tz) => TimeZone::from_posix(tz.trim_start_matches(':')).map_err(|_| {
+                    TranError::UnsupportedError(format!(
+                        "TZ is neither a zone in {} nor a valid rule",
+                        path.display()
+                    ))
+                }),
+                Err(_) => Ok(TimeZone::utc()),
+            };
+        }
+        let data = std::fs::read(&path).map_err(|e| TranError::read(&path, e))?;
+        TimeZone::from_tzif(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The instant of a UTC date and time.
+    fn utc(year: i64, month: i64, day: i64, hour: i64, minute: i64) -> i64 {
+        days_from_civil(year, month, day) * DAY + hour * 3600 + minute * 60
+    }
+
+    fn schedule(lines: &[&str]) -> Schedule {
+        let mut schedule = Schedule::default();
+        for line in lines {
+            schedule.push_line(line).unwrap();
+        }
+        schedule
+    }
+
+    fn berlin() -> TimeZone {
+        TimeZone::from_posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap()
+    }
+
+    /// A version 2 zone file with `transitions` in its table and `rule` in its footer.
+    fn tzif(transitions: &[(i64, i32)], rule: &str) -> Vec<u8> {
+        let mut offsets: Vec<i32> = vec![transitions.first().map_or(0, |t| t.1)];
+        for (_, offset) in transitions {
+            if !offsets.contains(offset) {
+                offsets.push(*offset);
+            }
+        }
+        let header = |time_count: usize, type_count: usize| {
+            let mut header = b"TZif2".to_vec();
+            header.extend_from_slice(&[0; 15]);
+            for count in [0, 0, 0, time_count, type_count, 4] {
+                header.extend_from_slice(&(count as u32).to_be_bytes());
+            }
+            header
+        };
+        let types = |offsets: &[i32]| {
+            let mut types = Vec::new();
+            for offset in offsets {
+                types.extend_from_slice(&offset.to_be_bytes());
+                types.extend_from_slice(&[0, 0]);
+            }
+            types.extend_from_slice(b"ZZZ\0");
+            types
+        };
+        // An empty first block, as zic writes with -b slim
+        let mut file = header(0, 1);
+        file.extend(types(&[0]));
+        file.extend(header(transitions.len(), offsets.len()));
+        for (at, _) in transitions {
+            file.extend_from_slice(&at.to_be_bytes());
+        }
+        for (_, offset) in transitions {
+            file.push(offsets.iter().position(|o| o == offset).unwrap() as u8);
+        }
+        file.extend(types(&offsets));
+        file.extend(format!("\n{}\n", rule).into_bytes());
+        file
+    }
+
+    #[test]
+    fn civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for day in [-800_000, -1, 0, 59, 11016, 11017, 20_000, 2_000_000] {
+            let year = year_of(day);
+            assert!(days_from_civil(year, 1, 1) <= day && day < days_from_civil(year + 1, 1, 1));
+        }
+    }
+
+    #[test]
+    fn rule_days() {
+        // The last Sunday of March and October 2026
+        assert_eq!(
+            RuleDay::Month(3, 5, 0).day(2026),
+            days_from_civil(2026, 3, 29)
+        );
+        assert_eq!(
+            RuleDay::Month(10, 5, 0).day(2026),
+            days_from_civil(2026, 10, 25)
+        );
+        // The second Sunday of March, the first of November
+        assert_eq!(
+            RuleDay::Month(3, 2, 0).day(2026),
+            days_from_civil(2026, 3, 8)
+        );
+        assert_eq!(
+            RuleDay::Month(11, 1, 0).day(2026),
+            days_from_civil(2026, 11, 1)
+        );
+        // March 1st, with and without a February 29th before it
+        assert_eq!(RuleDay::Julian(60).day(2024), days_from_civil(2024, 3, 1));
+        assert_eq!(RuleDay::Julian(60).day(2026), days_from_civil(2026, 3, 1));
+        assert_eq!(RuleDay::Zero(59).day(2024), days_from_civil(2024, 2, 29));
+    }
+
+    #[test]
+    fn rules_parse() {
+        assert_eq!(
+            Rule::parse("CET-1CEST,M3.5.0,M10.5.0/3"),
+            Some(Rule {
+                standard: 3600,
+                daylight: Some(Daylight {
+                    offset: 7200,
+                    start: (RuleDay::Month(3, 5, 0), 7200),
+                    end: (RuleDay::Month(10, 5, 0), 3 * 3600),
+                }),
+            })
+        );
+        assert_eq!(
+            Rule::parse("<+0530>-5:30"),
+            Some(Rule {
+                standard: 5 * 3600 + 30 * 60,
+                daylight: None,
+            })
+        );
+        assert_eq!(
+            Rule::parse("EST5EDT").unwrap().daylight.unwrap().offset,
+            -4 * 3600
+        );
+        assert_eq!(Rule::parse("UTC0"), Some(Rule::parse("<UTC>0").unwrap()));
+        for invalid in [
+            "",
+            "C1",
+            "CET",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,M13.5.0,M10.5.0",
+        ] {
+            assert_eq!(Rule::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn rule_offsets_around_both_edges() {
+        let zone = berlin();
+        // Clocks go from 02:00 CET to 03:00 CEST at 01:00 UTC, and back at 01:00 UTC
+        assert_eq!(zone.offset_at(utc(2026, 3, 29, 1, 0) - 1), 3600);
+        assert_eq!(zone.offset_at(utc(2026, 3, 29, 1, 0)), 7200);
+        assert_eq!(zone.offset_at(utc(2026, 10, 25, 1, 0) - 1), 7200);
+        assert_eq!(zone.offset_at(utc(2026, 10, 25, 1, 0)), 3600);
+        assert_eq!(zone.offset_at(utc(2026, 1, 1, 0, 0)), 3600);
+
+        let sydney = TimeZone::from_posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(utc(2026, 1, 15, 0, 0)), 11 * 3600);
+        assert_eq!(sydney.offset_at(utc(2026, 7, 15, 0, 0)), 10 * 3600);
+        assert_eq!(sydney.offset_at(utc(2026, 12, 31, 23, 0)), 11 * 3600);
+    }
+
+    #[test]
+    fn the_footer_takes_over_after_the_last_transition() {
+        let transitions = [
+            (utc(1996, 3, 31, 1, 0), 7200),
+            (utc(1996, 10, 27, 1, 0), 3600),
+        ];
+        let zone = TimeZone::from_tzif(&tzif(&transitions, "CET-1CEST,M3.5.0,M10.5.0/3")).unwrap();
+        assert_eq!(zone.offset_at(utc(1996, 6, 1, 0, 0)), 7200);
+        assert_eq!(zone.offset_at(utc(2026, 1, 15, 0, 0)), 3600);
+        assert_eq!(zone.offset_at(utc(2026, 7, 15, 0, 0)), 7200);
+
+        // Without a rule the last offset stays
+        let zone = TimeZone::from_tzif(&tzif(&transitions, "")).unwrap();
+        assert_eq!(zone.offset_at(utc(2026, 7, 15, 0, 0)), 3600);
+
+        assert!(TimeZone::from_tzif(&tzif(&transitions, "CET-1CEST,M3")).is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn system_zone_files_read_with_their_footer() {
+        // Only where the zone database is installed
+        let Ok(data) = std::fs::read("/usr/share/zoneinfo/Europe/Berlin") else {
+            return;
+        };
+        let zone = TimeZone::from_tzif(&data).unwrap();
+        for year in [2026, 2040, 2100] {
+            assert_eq!(zone.offset_at(utc(year, 1, 15, 0, 0)), 3600, "{}", year);
+            assert_eq!(zone.offset_at(utc(year, 7, 15, 0, 0)), 7200, "{}", year);
+        }
+    }
+
+    #[test]
+    fn next_times_wrap_past_midnight() {
+        let schedule = schedule(&["09:00", "21:00"]);
+        let zone = TimeZone::utc();
+        let now = utc(2026, 10, 14, 10, 0);
+        assert_eq!(
+            schedule.next_after(now, &zone),
+            Some(utc(2026, 10, 14, 21, 0))
+        );
+        let now = utc(2026, 10, 14, 21, 0);
+        assert_eq!(
+            schedule.next_after(now, &zone),
+            Some(utc(2026, 10, 15, 9, 0))
+        );
+        let now = utc(2026, 12, 31, 22, 0);
+        assert_eq!(schedule.next_after(now, &zone), Some(utc(2027, 1, 1, 9, 0)));
+    }
+
+    #[test]
+    fn next_times_keep_to_the_days_of_the_week() {
+        let schedule = schedule(&["*/30 8-18 1-5"]);
+        let zone = TimeZone::utc();
+        // Wednesday, then Friday evening on to Monday morning
+        let now = utc(2026, 10, 14, 10, 10);
+        assert_eq!(
+            schedule.next_after(now, &zone),
+            Some(utc(2026, 10, 14, 10, 30))
+        );
+        let now = utc(2026, 10, 16, 18, 30);
+        assert_eq!(
+            schedule.next_after(now, &zone),
+            Some(utc(2026, 10, 19, 8, 0))
+        );
+        // 7 is Sunday as well as 0
+        let sundays = self::schedule(&["0 12 7"]);
+        assert_eq!(
+            sundays.next_after(now, &zone),
+            Some(utc(2026, 10, 18, 12, 0))
+        );
+    }
+
+    #[test]
+    fn next_times_are_in_local_time() {
+        let schedule = schedule(&["09:00"]);
+        let now = utc(2026, 7, 1, 12, 0);
+        assert_eq!(
+            schedule.next_after(now, &berlin()),
+            Some(utc(2026, 7, 2, 7, 0))
+        );
+        let now = utc(2026, 1, 1, 12, 0);
+        assert_eq!(
+            schedule.next_after(now, &berlin()),
+            Some(utc(2026, 1, 2, 8, 0))
+        );
+    }
+
+    #[test]
+    fn a_skipped_time_fires_after_the_jump() {
+        // 02:30 doesn't happen on March 29th, it fires at 03:30 CEST instead
+        let schedule = schedule(&["02:30"]);
+        let now = utc(2026, 3, 28, 12, 0);
+        let next = schedule.next_after(now, &berlin()).unwrap();
+        assert_eq!(next, utc(2026, 3, 29, 1, 30));
+        assert_eq!(
+            schedule.next_after(next, &berlin()),
+            Some(utc(2026, 3, 30, 0, 30))
+        );
+    }
+
+    #[test]
+    fn a_repeated_time_fires_once() {
+        // 02:30 happens at 00:30 UTC in CEST, then again at 01:30 UTC in CET
+        let schedule = schedule(&["02:30"]);
+        let now = utc(2026, 10, 24, 12, 0);
+        let next = schedule.next_after(now, &berlin()).unwrap();
+        assert_eq!(next, utc(2026, 10, 25, 0, 30));
+        assert_eq!(
+            schedule.next_after(next, &berlin()),
+            Some(utc(2026, 10, 26, 1, 30))
+        );
+    }
+
+    #[test]
+    fn invalid_entries_are_config_errors() {
+        for (line, reason) in [
+            (
+                "24:00",
+                "expected HH:MM with hours 0 to 23 and minutes 0 to 59",
+            ),
+            ("9", "expected HH:MM or 'minute hour day-of-week'"),
+            ("*/0 * *", "'0' is not a step"),
+            ("0 25 *", "'25' is not between 0 and 23"),
+            ("0 18-8 *", "the range '18-8' runs backwards"),
+            ("0 0", "expected HH:MM or 'minute hour day-of-week'"),
+        ] {
+            match ScheduleEntry::parse(line) {
+                Err(TranError::ConfigError(message)) => {
+                    assert_eq!(message, format!("Invalid schedule '{}': {}", line, reason))
+                }
+                other => panic!("{} parsed as {:?}", line, other),
+            }
+        }
+    }
+}
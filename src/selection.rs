@@ -0,0 +1,162 @@
+use crate::config::Color;
+
+pub trait RngLike {
+    fn next_u64(&mut self) -> u64;
+
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        self.next_u64() % bound
+    }
+}
+
+// xorshift64*, good enough for picking a color
+pub struct SystemRng {
+    state: u64,
+}
+
+impl SystemRng {
+    #[cfg(feature = "fs")]
+    pub fn new() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        SystemRng::from_seed(nanos ^ ((std::process::id() as u64) << 32))
+    }
+
+    pub fn from_seed(seed: u64) -> Self {
+        SystemRng {
+            state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed },
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Default for SystemRng {
+    fn default() -> Self {
+        SystemRng::new()
+    }
+}
+
+impl RngLike for SystemRng {
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    Color(Color),
+    Row(Vec<Color>),
+}
+
+impl Selection {
+    pub fn colors(&self) -> &[Color] {
+        match self {
+            Selection::Color(color) => std::slice::from_ref(color),
+            Selection::Row(row) => row,
+        }
+    }
+}
+
+impl std::fmt::Display for Selection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for color in self.colors() {
+            write!(f, "{}", color)?;
+        }
+        Ok(())
+    }
+}
+
+/// Picks one of `candidates` with probability proportional to its weight.
+/// Returns `None` when no candidate has a non-zero weight.
+pub fn pick_weighted<'a, T, R: RngLike>(
+    candidates: &[(&'a T, usize)],
+    rng: &mut R,
+) -> Option<&'a T> {
+    let total: u64 = candidates
+        .iter()
+        .fold(0u64, |acc, (_, w)| acc.saturating_add(*w as u64));
+    if total == 0 {
+        return None;
+    }
+
+    let mut target = rng.below(total);
+    for (candidate, weight) in candidates {
+        let weight = *weight as u64;
+        if target < weight {
+            return Some(candidate);
+        }
+        target -= weight;
+    }
+
+    None
+}
+
+/// Hands out its values in turn, then zeros.
+#[cfg(test)]
+pub(crate) struct Fixed(pub Vec<u64>);
+
+#[cfg(test)]
+impl RngLike for Fixed {
+    fn next_u64(&mut self) -> u64 {
+        match self.0.is_empty() {
+            true => 0,
+            false => self.0.remove(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_follow_the_weights() {
+        let (a, b, c) = ('a', 'b', 'c');
+        let candidates = [(&a, 1), (&b, 3), (&c, 2)];
+        let picks: Vec<char> = (0..6)
+            .map(|i| *pick_weighted(&candidates, &mut Fixed(vec![i])).unwrap())
+            .collect();
+        assert_eq!(picks, ['a', 'b', 'b', 'b', 'c', 'c']);
+        assert_eq!(pick_weighted(&candidates, &mut Fixed(vec![6])), Some(&'a'));
+    }
+
+    #[test]
+    fn weightless_candidates_are_never_picked() {
+        let (a, b) = ('a', 'b');
+        let candidates = [(&a, 0), (&b, 1)];
+        for i in 0..10 {
+            assert_eq!(pick_weighted(&candidates, &mut Fixed(vec![i])), Some(&'b'));
+        }
+        assert_eq!(pick_weighted(&[(&a, 0)], &mut Fixed(vec![])), None);
+        assert_eq!(pick_weighted::<char, _>(&[], &mut Fixed(vec![])), None);
+    }
+
+    #[test]
+    fn huge_weights_do_not_overflow() {
+        let (a, b) = ('a', 'b');
+        let candidates = [(&a, usize::MAX), (&b, usize::MAX)];
+        for value in [0, u64::MAX / 2, u64::MAX] {
+            assert!(pick_weighted(&candidates, &mut Fixed(vec![value])).is_some());
+        }
+    }
+
+    #[test]
+    fn seeded_rngs_repeat() {
+        let draws = |seed| {
+            let mut rng = SystemRng::from_seed(seed);
+            (0..4).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(7), draws(7));
+        assert_ne!(draws(7), draws(8));
+        assert_ne!(draws(0), [0; 4]);
+    }
+}
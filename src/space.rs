@@ -0,0 +1,297 @@
+use crate::{config::Color, errors::TranError};
+
+/// The color space gradients and transitions interpolate in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GradientSpace {
+    /// Straight per-channel interpolation of the stored values.
+    #[default]
+    Srgb,
+    /// Björn Ottosson's perceptual space, keeps ramps from dipping into grays.
+    Oklab,
+    Hsl,
+}
+
+impl TryFrom<&str> for GradientSpace {
+    type Error = TranError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "srgb" => Ok(Self::Srgb),
+            "oklab" => Ok(Self::Oklab),
+            "hsl" => Ok(Self::Hsl),
+            _ => Err(TranError::ConfigError(format!(
+                "Unrecognized gradient space '{}', valid spaces are 'srgb', 'oklab' and 'hsl'",
+                value
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for GradientSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Srgb => write!(f, "srgb"),
+            Self::Oklab => write!(f, "oklab"),
+            Self::Hsl => write!(f, "hsl"),
+        }
+    }
+}
+
+/// sRGB channel to linear light between 0 and 1.
+pub fn linearize(channel: u8) -> f64 {
+    let c = channel as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light back to an sRGB channel, clamping what falls outside the gamut.
+pub fn delinearize(linear: f64) -> u8 {
+    let c = linear.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+/// `[L, a, b]` of `color`, L running from 0 for black to 1 for white.
+pub fn to_oklab(color: &Color) -> [f64; 3] {
+    let (r, g, b) = color.bytes();
+    let (r, g, b) = (linearize(r), linearize(g), linearize(b));
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+/// CIELAB `[L, a, b]` of `color` under the D65 white point, L running from 0 for black to 100
+/// for white.
+pub fn to_lab(color: &Color) -> [f64; 3] {
+    let (r, g, b) = color.bytes();
+    let (r, g, b) = (linearize(r), linearize(g), linearize(b));
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+    let f = |t: f64| match t > 216.0 / 24389.0 {
+        true => t.cbrt(),
+        false => (24389.0 / 27.0 * t + 16.0) / 116.0,
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// The sRGB color of `[L, a, b]`, clamped into the gamut.
+pub fn from_oklab([lightness, a, b]: [f64; 3]) -> Color {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    Color::from_bytes(
+        delinearize(4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s),
+        delinearize(-1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s),
+        delinearize(-0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s),
+    )
+}
+
+/// `[hue, saturation, lightness]` of `color`, hue in degrees and the others between 0 and 1.
+pub fn to_hsl(color: &Color) -> [f64; 3] {
+    let (r, g, b) = color.bytes();
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return [0.0, 0.0, lightness];
+    }
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    [hue * 60.0, saturation, lightness]
+}
+
+pub fn from_hsl([hue, saturation, lightness]: [f64; 3]) -> Color {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let offset = lightness - chroma / 2.0;
+    let channel = |c: f64| ((c + offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+    Color::from_bytes(channel(r), channel(g), channel(b))
+}
+
+impl GradientSpace {
+    /// The color `t` of the way from `from` to `to`, exactly `from` at 0 and `to` at 1.
+    pub fn mix(&self, from: &Color, to: &Color, t: f64) -> Color {
+        if t <= 0.0 {
+            return *from;
+        }
+        if t >= 1.0 {
+            return *to;
+        }
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        match self {
+            Self::Srgb => {
+                let channel = |a: u8, b: u8| lerp(a as f64, b as f64).round() as u8;
+                let ((r1, g1, b1), (r2, g2, b2)) = (from.bytes(), to.bytes());
+                Color::from_bytes(channel(r1, r2), channel(g1, g2), channel(b1, b2))
+            }
+            Self::Oklab => {
+                let (a, b) = (to_oklab(from), to_oklab(to));
+                from_oklab([lerp(a[0], b[0]), lerp(a[1], b[1]), lerp(a[2], b[2])])
+            }
+            Self::Hsl => {
+                // A gray has no hue of its own and black and white no saturation either, so
+                // they take on those of the other end instead of fading through red
+                let adopt = |mut this: [f64; 3], other: [f64; 3]| {
+                    if this[1] == 0.0 {
+                        this[0] = other[0];
+                        if this[2] == 0.0 || this[2] == 1.0 {
+                            this[1] = other[1];
+                        }
+                    }
+                    this
+                };
+                let (a, b) = (to_hsl(from), to_hsl(to));
+                let (a, b) = (adopt(a, b), adopt(b, a));
+                // The short way around the hue circle
+                let mut turn = b[0] - a[0];
+                if turn > 180.0 {
+                    turn -= 360.0;
+                } else if turn < -180.0 {
+                    turn += 360.0;
+                }
+                from_hsl([a[0] + turn * t, lerp(a[1], b[1]), lerp(a[2], b[2])])
+            }
+        }
+    }
+
+    /// Lightness of `color` the way this space measures it, between 0 and 1.
+    pub fn lightness(&self, color: &Color) -> f64 {
+        match self {
+            Self::Srgb => {
+                let (r, g, b) = color.bytes();
+                (r as f64 + g as f64 + b as f64) / (3.0 * 255.0)
+            }
+            Self::Oklab => to_oklab(color)[0],
+            Self::Hsl => to_hsl(color)[2],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f64; 3], b: [f64; 3]) -> bool {
+        (0..3).all(|i| (a[i] - b[i]).abs() < 1e-3)
+    }
+
+    #[test]
+    fn oklab_matches_the_reference_values() {
+        // From Ottosson's "A perceptual color space for image processing"
+        let cases = [
+            (Color::white(), [1.0, 0.0, 0.0]),
+            (Color::black(), [0.0, 0.0, 0.0]),
+            (Color::from_bytes(255, 0, 0), [0.62796, 0.22486, 0.12585]),
+            (Color::from_bytes(0, 255, 0), [0.86644, -0.23389, 0.1795]),
+            (Color::from_bytes(0, 0, 255), [0.45201, -0.03246, -0.31153]),
+        ];
+        for (color, lab) in cases {
+            assert!(
+                close(to_oklab(&color), lab),
+                "{} is {:?}",
+                color,
+                to_oklab(&color)
+            );
+            assert_eq!(from_oklab(lab), color);
+        }
+    }
+
+    #[test]
+    fn oklab_round_trips_and_clamps() {
+        for value in (0..=255).step_by(17) {
+            for color in [
+                Color::from_bytes(value, 255 - value, 128),
+                Color::from_bytes(value, value, value),
+            ] {
+                assert_eq!(from_oklab(to_oklab(&color)), color);
+            }
+        }
+        // Far outside the gamut
+        assert_eq!(from_oklab([1.0, 0.5, 0.5]).bytes().0, 255);
+        assert_eq!(from_oklab([-1.0, 0.0, 0.0]), Color::black());
+    }
+
+    #[test]
+    fn oklab_midpoints_keep_their_lightness() {
+        let (red, green) = (Color::from_bytes(255, 0, 0), Color::from_bytes(0, 255, 0));
+        let srgb = GradientSpace::Srgb.mix(&red, &green, 0.5);
+        let oklab = GradientSpace::Oklab.mix(&red, &green, 0.5);
+        assert_eq!(srgb, Color::from_bytes(128, 128, 0));
+        // The straight ramp dips into a muddy olive, the perceptual one stays as light as its ends
+        let lightness = |c: &Color| to_oklab(c)[0];
+        assert!(lightness(&oklab) > lightness(&srgb) + 0.1);
+        let ends = (lightness(&red) + lightness(&green)) / 2.0;
+        assert!((lightness(&oklab) - ends).abs() < 0.01);
+    }
+
+    #[test]
+    fn ramps_end_where_they_are_asked_to() {
+        let (from, to) = (
+            Color::from_bytes(0x2e, 0x34, 0x40),
+            Color::from_bytes(0x88, 0xc0, 0xd0),
+        );
+        for space in [
+            GradientSpace::Srgb,
+            GradientSpace::Oklab,
+            GradientSpace::Hsl,
+        ] {
+            assert_eq!(space.mix(&from, &to, 0.0), from);
+            assert_eq!(space.mix(&from, &to, 1.0), to);
+            let ramp = from.ramp_in(&to, 4, space);
+            assert_eq!(ramp.len(), 4);
+            assert_eq!(ramp[3], to);
+        }
+    }
+
+    #[test]
+    fn spaces_are_read_by_name() {
+        assert_eq!(GradientSpace::default(), GradientSpace::Srgb);
+        for space in [
+            GradientSpace::Srgb,
+            GradientSpace::Oklab,
+            GradientSpace::Hsl,
+        ] {
+            assert_eq!(
+                GradientSpace::try_from(space.to_string().as_str()).unwrap(),
+                space
+            );
+        }
+        assert!(GradientSpace::try_from("lab").is_err());
+        let config = crate::config::parse_config_contents(
+            "[mode]\ngradient\n[colors]\n#88c0d0\n[gradient_space]\noklab\n",
+        )
+        .unwrap();
+        assert_eq!(config.get_gradient_space(), GradientSpace::Oklab);
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    config::Color,
+    errors::TranError,
+    json::Json,
+    recolor::{Replacement, ReplacementLog},
+    run::RunSummary,
+};
+
+const RUNS_LOG: &str = "runs.log";
+const REPLACEMENTS_LOG: &str = "replacements.jsonl";
+const RUNS_LOG_ROTATED: &str = "runs.log.1";
+const MAX_RUNS_LOG_SIZE: u64 = 256 * 1024;
+
+pub fn get_state_dir() -> Result<PathBuf, TranError> {
+    if let Ok(state_dir) = std::env::var("TRAN_STATE_DIR") {
+        return Ok(PathBuf::from(state_dir));
+    }
+
+    let mut state_home = if let Ok(state_home) = std::env::var("XDG_STATE_HOME") {
+        PathBuf::from(state_home)
+    } else if let Ok(home) = std::env::var("HOME") {
+        Path::new(&home).join(".local").join("state")
+    } else if let Some(local) = std::env::var_os("LOCALAPPDATA").filter(|_| cfg!(windows)) {
+        PathBuf::from(local)
+    } else {
+        return Err(TranError::ConfigError(
+            "Could not determine state directory".to_string(),
+        ));
+    };
+
+    state_home.push("tran");
+    Ok(state_home)
+}
+
+pub struct State {
+    dir: PathBuf,
+}
+
+impl State {
+    pub fn open() -> Result<Self, TranError> {
+        State::at(get_state_dir()?)
+    }
+
+    pub fn at<P: Into<PathBuf>>(dir: P) -> Result<Self, TranError> {
+        let dir = dir.into();
+        crate::perms::create_dir_all(&dir).map_err(|e| TranError::write(&dir, e))?;
+        Ok(State { dir })
+    }
+
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn get_backups_dir(&self) -> PathBuf {
+        self.dir.join("backups")
+    }
+
+    pub fn get_cache_dir(&self) -> PathBuf {
+        self.dir.join("cache")
+    }
+
+    pub fn get_journal_dir(&self) -> PathBuf {
+        self.dir.join("journal")
+    }
+
+    pub fn get_runs_log_path(&self) -> PathBuf {
+        self.dir.join(RUNS_LOG)
+    }
+
+    pub fn get_replacements_log_path(&self) -> PathBuf {
+        self.dir.join(REPLACEMENTS_LOG)
+    }
+
+    /// A log appending every replacement it gets to the replacements log as a JSON line of
+    /// the run `run_id`. Lines that fail to write are dropped, the run goes on without them.
+    pub fn replacement_log(&self, run_id: u64) -> Result<ReplacementLog, TranError> {
+        let path = self.get_replacements_log_path();
+        let file = crate::perms::append(&path).map_err(|e| TranError::write(&path, e))?;
+        let file = Mutex::new(file);
+        Ok(ReplacementLog::new(move |replacement| {
+            let line = replacement.to_json().with("run", run_id);
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }))
+    }
+
+    /// The replacements logged for the run `run_id`, in the order they were made.
+    pub fn replacements(&self, run_id: u64) -> Result<Vec<Replacement>, TranError> {
+        let path = self.get_replacements_log_path();
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| TranError::read(&path, e))?;
+        Ok(contents
+            .lines()
+            .filter_map(|line| Json::parse(line).ok())
+            .filter(|json| json.get("run").and_then(Json::as_u64) == Some(run_id))
+            .filter_map(|json| Replacement::from_json(&json))
+            .collect())
+    }
+
+    pub fn append_run(&self, run: &RunRecord) -> Result<(), TranError> {
+        let log = self.get_runs_log_path();
+        if log
+            .metadata()
+            .map(|m| m.len() >= MAX_RUNS_LOG_SIZE)
+            .unwrap_or(false)
+        {
+            std::fs::rename(&log, self.dir.join(RUNS_LOG_ROTATED))
+                .map_err(|e| TranError::write(&log, e))?;
+        }
+
+        crate::perms::append(&log)
+            .and_then(|mut f| writeln!(f, "{}", run.to_json()))
+            .map_err(|e| TranError::write(&log, e))?;
+
+        Ok(())
+    }
+
+    /// Returns up to `count` runs, oldest first. Lines that fail to parse are skipped.
+    pub fn recent_runs(&self, count: usize) -> Result<Vec<RunRecord>, TranError> {
+        let mut runs = Vec::new();
+        for name in [RUNS_LOG_ROTATED, RUNS_LOG] {
+            let path = self.dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path).map_err(|e| TranError::read(&path, e))?;
+            for line in contents.lines() {
+                if let Some(run) = Json::parse(line)
+                    .ok()
+                    .and_then(|j| RunRecord::from_json(&j))
+                {
+                    runs.push(run);
+                }
+            }
+        }
+
+        let skip = runs.len().saturating_sub(count);
+        Ok(runs.split_off(skip))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileRecord {
+    pub path: String,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunRecord {
+    /// Milliseconds since the unix epoch when the run started, what its replacements are
+    /// logged under.
+    pub id: u64,
+    pub timestamp: u64,
+    pub mode: String,
+    pub old_colors: Vec<Color>,
+    pub new_colors: Vec<Color>,
+    pub files: Vec<FileRecord>,
+}
+
+impl RunRecord {
+    pub fn new(mode: &str, old_colors: Vec<Color>, new_colors: Vec<Color>) -> Self {
+        let id = new_run_id();
+        RunRecord {
+            id,
+            timestamp: id / 1000,
+            mode: mode.to_string(),
+            old_colors,
+            new_colors,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn from_summary(summary: &RunSummary) -> Self {
+        let mut run = RunRecord::new(
+            &summary.colors.mode,
+            summary.colors.old.clone(),
+            summary.colors.new.clone(),
+        );
+        for target in summary.succeeded.iter() {
+            run.files.push(FileRecord {
+                path: target.path.to_string_lossy().to_string(),
+                error: None,
+            });
+        }
+        for (path, e) in summary.failed.iter() {
+            run.files.push(FileRecord {
+                path: path.to_string_lossy().to_string(),
+                error: Some(e.to_string()),
+            });
+        }
+        run
+    }
+
+    pub fn to_json(&self) -> Json {
+        let colors = |colors: &[Color]| -> Json {
+            colors
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .into()
+        };
+        let files: Vec<Json> = self
+            .files
+            .iter()
+            .map(|f| {
+                Json::object()
+                    .with("path", f.path.as_str())
+                    .with("error", f.error.clone())
+            })
+            .collect();
+
+        Json::object()
+            .with("id", self.id)
+            .with("timestamp", self.timestamp)
+            .with("mode", self.mode.as_str())
+            .with("old_colors", colors(&self.old_colors))
+            .with("new_colors", colors(&self.new_colors))
+            .with("files", files)
+    }
+
+    pub fn from_json(json: &Json) -> Option<Self> {
+        let colors = |key: &str| -> Option<Vec<Color>> {
+            json.get(key)?
+                .as_array()?
+                .iter()
+                .map(|c| c.as_str().and_then(|c| Color::try_from_hex_str(c).ok()))
+                .collect()
+        };
+        let files = json
+            .get("files")?
+            .as_array()?
+            .iter()
+            .map(|f| {
+                Some(FileRecord {
+                    path: f.get("path")?.as_str()?.to_string(),
+                    error: f.get("error").and_then(|e| e.as_str()).map(String::from),
+                })
+            })
+            .collect::<Option<Vec<FileRecord>>>()?;
+
+        let timestamp = json.get("timestamp")?.as_u64()?;
+        Some(RunRecord {
+            // Runs recorded before ids were kept get one from their timestamp
+            id: json
+                .get("id")
+                .and_then(Json::as_u64)
+                .unwrap_or(timestamp * 1000),
+            timestamp,
+            mode: json.get("mode")?.as_str()?.to_string(),
+            old_colors: colors("old_colors")?,
+            new_colors: colors("new_colors")?,
+            files,
+        })
+    }
+}
+
+/// An id for a run starting now.
+pub fn new_run_id() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The current date as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_timestamp(secs)[..10].to_string()
+}
+
+/// Formats seconds since the unix epoch as `YYYY-MM-DD HH:MM:SS` (UTC).
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn record(timestamp: u64, new: &str) -> RunRecord {
+        let mut run = RunRecord::new(
+            "gradient",
+            vec![Color::try_from_hex_str("#111111").unwrap()],
+            vec![Color::try_from_hex_str(new).unwrap()],
+        );
+        run.id = timestamp * 1000;
+        run.timestamp = timestamp;
+        run.files = vec![
+            FileRecord {
+                path: "/a.css".to_string(),
+                error: None,
+            },
+            FileRecord {
+                path: "/b \"quoted\".css".to_string(),
+                error: Some("Could not read /b".to_string()),
+            },
+        ];
+        run
+    }
+
+    #[test]
+    fn runs_round_trip_through_the_log() {
+        let dir = TempDir::new("state-runs");
+        let state = State::at(dir.path().join("tran")).unwrap();
+        assert_eq!(state.recent_runs(10).unwrap(), []);
+
+        let runs = [
+            record(1, "#222222"),
+            record(2, "#333333"),
+            record(3, "#444444"),
+        ];
+        for run in runs.iter() {
+            state.append_run(run).unwrap();
+        }
+        assert_eq!(state.recent_runs(10).unwrap(), runs);
+        assert_eq!(state.recent_runs(2).unwrap(), runs[1..]);
+        assert_eq!(state.recent_runs(0).unwrap(), []);
+    }
+
+    #[test]
+    fn lines_that_do_not_parse_are_skipped() {
+        let dir = TempDir::new("state-garbage");
+        let state = State::at(dir.path()).unwrap();
+        state.append_run(&record(1, "#222222")).unwrap();
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(state.get_runs_log_path())
+            .unwrap();
+        writeln!(log, "not json\n{{\"timestamp\": 5}}").unwrap();
+        state.append_run(&record(2, "#333333")).unwrap();
+        let timestamps: Vec<u64> = state
+            .recent_runs(10)
+            .unwrap()
+            .iter()
+            .map(|run| run.timestamp)
+            .collect();
+        assert_eq!(timestamps, [1, 2]);
+    }
+
+    #[test]
+    fn a_full_log_is_rotated() {
+        let dir = TempDir::new("state-rotate");
+        let state = State::at(dir.path()).unwrap();
+        let old = record(1, "#222222").to_json().to_string();
+        let lines = MAX_RUNS_LOG_SIZE as usize / old.len() + 1;
+        std::fs::write(
+            state.get_runs_log_path(),
+            format!("{}\n", old).repeat(lines),
+        )
+        .unwrap();
+
+        state.append_run(&record(2, "#333333")).unwrap();
+        let rotated = std::fs::read_to_string(dir.path().join(RUNS_LOG_ROTATED)).unwrap();
+        assert_eq!(rotated.lines().count(), lines);
+        let log = std::fs::read_to_string(state.get_runs_log_path()).unwrap();
+        assert_eq!(log.lines().count(), 1);
+
+        // Both halves are read, oldest first
+        let runs = state.recent_runs(usize::MAX).unwrap();
+        assert_eq!(runs.len(), lines + 1);
+        assert_eq!(runs.last().unwrap().timestamp, 2);
+    }
+
+    #[test]
+    fn records_without_an_id_take_one_from_their_timestamp() {
+        let mut json = record(7, "#222222").to_json();
+        if let Json::Object(fields) = &mut json {
+            fields.retain(|(key, _)| key != "id");
+        }
+        assert_eq!(RunRecord::from_json(&json).unwrap().id, 7000);
+    }
+
+    #[test]
+    fn timestamps_format_as_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13:20");
+        assert_eq!(format_timestamp(4_102_444_799), "2099-12-31 23:59:59");
+    }
+}
@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+
+use crate::{
+    config::{Color, Config},
+    errors::TranError,
+    json::Json,
+    run::target_options,
+    scan::{scan_file, ColorUse},
+    RecolorOptions,
+};
+
+#[derive(Debug, Default)]
+pub struct StatsReport {
+    /// Every configured and current color once, in config order.
+    pub colors: Vec<Color>,
+    /// Occurrences of each of `colors` per target, in the same order.
+    pub files: Vec<(PathBuf, Vec<usize>)>,
+    /// Colors found in targets that the config doesn't have, most frequent first.
+    pub unconfigured: Vec<ColorUse>,
+    pub failed: Vec<(PathBuf, TranError)>,
+}
+
+impl StatsReport {
+    pub fn color_totals(&self) -> Vec<usize> {
+        (0..self.colors.len())
+            .map(|i| self.files.iter().map(|(_, counts)| counts[i]).sum())
+            .collect()
+    }
+
+    pub fn file_totals(&self) -> Vec<usize> {
+        self.files
+            .iter()
+            .map(|(_, counts)| counts.iter().sum())
+            .collect()
+    }
+
+    /// Configured colors no target has, candidates for removal.
+    pub fn unused(&self) -> Vec<Color> {
+        self.colors
+            .iter()
+            .zip(self.color_totals())
+            .filter(|(_, total)| *total == 0)
+            .map(|(color, _)| *color)
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let colors: Vec<Json> = self
+            .colors
+            .iter()
+            .zip(self.color_totals())
+            .map(|(color, total)| {
+                Json::object()
+                    .with("color", color.to_string())
+                    .with("total", total)
+            })
+            .collect();
+        let files: Vec<Json> = self
+            .files
+            .iter()
+            .zip(self.file_totals())
+            .map(|((path, counts), total)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("counts", counts.clone())
+                    .with("total", total)
+            })
+            .collect();
+        let unconfigured: Vec<Json> = self
+            .unconfigured
+            .iter()
+            .map(|c| {
+                Json::object()
+                    .with("color", c.color.to_string())
+                    .with("count", c.count)
+            })
+            .collect();
+        let failed: Vec<Json> = self
+            .failed
+            .iter()
+            .map(|(path, e)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("error", e.to_string())
+            })
+            .collect();
+
+        Json::object()
+            .with("colors", colors)
+            .with("files", files)
+            .with(
+                "unused",
+                self.unused()
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .with("unconfigured", unconfigured)
+            .with("failed", failed)
+    }
+}
+
+/// Counts every configured color in every target of `config`, carrying on past targets that
+/// can't be scanned.
+pub fn stats(config: &Config, options: &RecolorOptions) -> StatsReport {
+    let mut report = StatsReport::default();
+    for color in config.get_current_colors().into_iter().chain(
+        config
+            .get_selections()
+            .iter()
+            .flat_map(|s| s.colors().to_vec()),
+    ) {
+        if !report.colors.contains(&color) {
+            report.colors.push(color);
+        }
+    }
+
+    for target in config.get_targets() {
+        let (path, options) = target_options(target, options);
+        let found = match scan_file(&path, &options) {
+            Ok(found) => found,
+            Err(e) => {
+                report.failed.push((path.clone(), e.in_file(&path)));
+                continue;
+            }
+        };
+        let mut counts = vec![0; report.colors.len()];
+        for color in found {
+            match report.colors.iter().position(|c| *c == color) {
+                Some(i) => counts[i] += 1,
+                None => match report.unconfigured.iter_mut().find(|c| c.color == color) {
+                    Some(other) => {
+                        other.count += 1;
+                        if !other.files.contains(&path) {
+                            other.files.push(path.clone());
+                        }
+                    }
+                    None => report.unconfigured.push(ColorUse {
+                        color,
+                        count: 1,
+                        files: vec![path.clone()],
+                    }),
+                },
+            }
+        }
+        report.files.push((path, counts));
+    }
+
+    // Stable, so ties keep the order they were first found in
+    report
+        .unconfigured
+        .sort_by_key(|c| std::cmp::Reverse(c.count));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config_contents, test_util::TempDir};
+
+    fn color(hex: &str) -> Color {
+        Color::try_from_hex_str(hex).unwrap()
+    }
+
+    #[test]
+    fn every_configured_color_is_counted_per_target() {
+        let dir = TempDir::new("stats");
+        let a = dir.write(
+            "a.conf",
+            "fg=#2e3440\nbg=#2E3440\naccent=#88c0d0\nx=#123456\n",
+        );
+        let b = dir.write("b.conf", "fg=#88c0d0\ny=#abcdef\nz=#123456\nw=#123456\n");
+        let missing = dir.path().join("missing.conf");
+        let config = parse_config_contents(&format!(
+            "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n#bf616a\n#2e3440\n[target_files]\n{}\n{}\n{}\n",
+            a.display(),
+            b.display(),
+            missing.display()
+        ))
+        .unwrap();
+        let report = stats(&config, &RecolorOptions::default());
+
+        assert_eq!(
+            report.colors,
+            [color("#2e3440"), color("#88c0d0"), color("#bf616a")]
+        );
+        assert_eq!(
+            report.files,
+            [(a, vec![2, 1, 0]), (b.clone(), vec![0, 1, 0])]
+        );
+        assert_eq!(report.color_totals(), [2, 2, 0]);
+        assert_eq!(report.file_totals(), [3, 1]);
+        assert_eq!(report.unused(), [color("#bf616a")]);
+
+        let unconfigured: Vec<(Color, usize, usize)> = report
+            .unconfigured
+            .iter()
+            .map(|c| (c.color, c.count, c.files.len()))
+            .collect();
+        assert_eq!(
+            unconfigured,
+            [(color("#123456"), 3, 2), (color("#abcdef"), 1, 1)]
+        );
+        assert_eq!(report.unconfigured[1].files, [b]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, missing);
+
+        let json = report.to_json().to_string();
+        assert!(json.contains("\"unused\":[\"#bf616a\"]"), "{}", json);
+        assert!(
+            json.contains("{\"color\":\"#2e3440\",\"total\":2}"),
+            "{}",
+            json
+        );
+    }
+}
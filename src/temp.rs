@@ -0,0 +1,183 @@
+//! The temporary files tran writes before moving them over what they replace: staged targets,
+//! atomic writes and the scratch files of `exec` commands. They are named
+//! `.tran-tmp-<pid>-<n>`, with the extension of the file they stand in for so commands still
+//! see the format, and removed when dropped, on errors and panics as on success. Files left
+//! behind by a run that was killed are swept by `sweep` the next time tran runs next to them.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use crate::errors::TranError;
+
+const PREFIX: &str = ".tran-tmp-";
+/// Temporary files this much older than their last write are taken for orphans, unless the run
+/// that made them is still going.
+pub const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary file, removed when dropped unless it was moved into place with `persist`.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// A new temporary file name next to `path`. Nothing is created until it is written to.
+    pub fn next_to(path: &Path) -> TempFile {
+        let mut name = OsString::from(format!(
+            "{}{}-{}",
+            PREFIX,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        TempFile {
+            path: path.with_file_name(name),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the file over `target`, which then holds it for good.
+    pub fn persist(self, target: &Path) -> Result<(), TranError> {
+        std::fs::rename(&self.path, target).map_err(|e| TranError::write(target, e))?;
+        std::mem::forget(self);
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The pid of the run that made the temporary file `name`, `None` for any other name.
+fn owner(name: &str) -> Option<u32> {
+    let rest = name.strip_prefix(PREFIX)?;
+    let (pid, rest) = rest.split_once('-')?;
+    let n = rest.split_once('.').map_or(rest, |(n, _)| n);
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(pid) || !digits(n) {
+        return None;
+    }
+    pid.parse().ok()
+}
+
+/// Whether the process `pid` is running. Only Linux can tell, elsewhere age alone decides.
+fn is_alive(pid: u32) -> bool {
+    pid == std::process::id()
+        || (cfg!(target_os = "linux") && Path::new("/proc").join(pid.to_string()).exists())
+}
+
+/// Removes the temporary files in `dir` that were last written more than `older_than` ago by a
+/// run no longer going, returning them. Files tran didn't name are never touched, nor are
+/// directories.
+pub fn sweep(dir: &Path, older_than: Duration) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let now = SystemTime::now();
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let Some(pid) = entry.file_name().to_str().and_then(owner) else {
+                return false;
+            };
+            let age = entry
+                .metadata()
+                .ok()
+                .filter(|meta| meta.is_file())
+                .and_then(|meta| meta.modified().ok())
+                .and_then(|modified| now.duration_since(modified).ok());
+            age.is_some_and(|age| age > older_than) && !is_alive(pid)
+        })
+        .map(|entry| entry.path())
+        .filter(|path| std::fs::remove_file(path).is_ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn backdate(path: &Path) {
+        let old = SystemTime::now() - 2 * STALE_AFTER;
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(old))
+            .unwrap();
+    }
+
+    #[test]
+    fn temp_files_keep_the_extension_and_go_unless_persisted() {
+        let dir = TempDir::new("temp-drop");
+        let target = dir.path().join("theme.css");
+        let temp = TempFile::next_to(&target);
+        let name = temp
+            .path()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(owner(&name), Some(std::process::id()));
+        assert!(name.ends_with(".css"), "{}", name);
+        assert_ne!(temp.path(), TempFile::next_to(&target).path());
+
+        std::fs::write(temp.path(), "a").unwrap();
+        let path = temp.path().to_path_buf();
+        drop(temp);
+        assert!(!path.exists());
+
+        let temp = TempFile::next_to(&target);
+        std::fs::write(temp.path(), "b").unwrap();
+        let path = temp.path().to_path_buf();
+        temp.persist(&target).unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "b");
+    }
+
+    #[test]
+    fn only_names_tran_gives_have_an_owner() {
+        assert_eq!(owner(".tran-tmp-12-3"), Some(12));
+        assert_eq!(owner(".tran-tmp-12-3.png"), Some(12));
+        for name in [
+            ".tran-tmp-12",
+            ".tran-tmp--3",
+            ".tran-tmp-x-3",
+            "tran-tmp-12-3",
+        ] {
+            assert_eq!(owner(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn only_old_files_of_finished_runs_are_swept() {
+        let dir = TempDir::new("temp-sweep");
+        // No pid runs this high, so the run that made it is taken as killed
+        let orphan = dir.write(".tran-tmp-4294967295-0.conf", "");
+        let fresh = dir.write(".tran-tmp-4294967295-1.conf", "");
+        let own = dir.write(&format!(".tran-tmp-{}-0", std::process::id()), "");
+        let other = dir.write(".tran-tmp-notes", "");
+        for path in [&orphan, &own, &other] {
+            backdate(path);
+        }
+        assert_eq!(sweep(dir.path(), STALE_AFTER), [orphan.as_path()]);
+        assert!(!orphan.exists());
+        assert!(fresh.exists() && own.exists() && other.exists());
+        assert!(sweep(&dir.path().join("missing"), STALE_AFTER).is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+//! Colors shown as colors in what tran prints: a truecolor block in front of each hex value.
+//! Blocks are only drawn when stdout is a terminal announcing truecolor through `COLORTERM`,
+//! and never with `NO_COLOR` set or after `disable`.
+
+use std::{
+    ffi::OsStr,
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::config::Color;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns swatches off for the rest of the process, as `--no-color` does.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether `swatch` draws anything.
+pub fn enabled() -> bool {
+    draws(
+        std::env::var_os("NO_COLOR").as_deref(),
+        std::env::var("COLORTERM").ok().as_deref(),
+        std::io::stdout().is_terminal(),
+        DISABLED.load(Ordering::Relaxed),
+    )
+}
+
+/// Whether swatches are drawn given `NO_COLOR`, `COLORTERM`, whether stdout is a terminal and
+/// whether they were disabled. An empty `NO_COLOR` counts as unset.
+fn draws(no_color: Option<&OsStr>, colorterm: Option<&str>, tty: bool, disabled: bool) -> bool {
+    let no_color = no_color.is_some_and(|value| !value.is_empty());
+    let truecolor = matches!(colorterm, Some("truecolor" | "24bit"));
+    !disabled && !no_color && truecolor && tty
+}
+
+/// The escape sequences drawing a block of `color` followed by a space.
+pub fn swatch_sequence(color: &Color) -> String {
+    let (r, g, b) = color.bytes();
+    format!("\x1b[48;2;{};{};{}m    \x1b[0m ", r, g, b)
+}
+
+/// A block of `color` when the terminal can show it, nothing otherwise.
+pub fn swatch(color: &Color) -> String {
+    swatch_when(color, enabled())
+}
+
+fn swatch_when(color: &Color, draw: bool) -> String {
+    match draw {
+        true => swatch_sequence(color),
+        false => String::new(),
+    }
+}
+
+/// `color` with its swatch in front.
+pub fn with_swatch(color: &Color) -> String {
+    format!("{}{}", swatch(color), color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swatches_are_a_block_of_the_color() {
+        let color = Color::from_bytes(0x2e, 0x34, 0x40);
+        assert_eq!(swatch_sequence(&color), "\x1b[48;2;46;52;64m    \x1b[0m ");
+        assert_eq!(swatch_when(&color, false), "");
+        assert_eq!(swatch_when(&color, true), swatch_sequence(&color));
+    }
+
+    #[test]
+    fn swatches_are_drawn_only_on_truecolor_terminals() {
+        let unset = None;
+        let set = Some(OsStr::new("1"));
+        let empty = Some(OsStr::new(""));
+        let cases = [
+            (unset, Some("truecolor"), true, false, true),
+            (unset, Some("24bit"), true, false, true),
+            (empty, Some("truecolor"), true, false, true),
+            (set, Some("truecolor"), true, false, false),
+            (unset, Some("256color"), true, false, false),
+            (unset, Some(""), true, false, false),
+            (unset, None, true, false, false),
+            (unset, Some("truecolor"), false, false, false),
+            // --no-color
+            (unset, Some("truecolor"), true, true, false),
+        ];
+        for (no_color, colorterm, tty, disabled, expected) in cases {
+            assert_eq!(
+                draws(no_color, colorterm, tty, disabled),
+                expected,
+                "{:?} {:?} {} {}",
+                no_color,
+                colorterm,
+                tty,
+                disabled
+            );
+        }
+    }
+}
@@ -0,0 +1,562 @@
+use crate::{
+    config::{Color, HexCase},
+    errors::TranError,
+    ColorMap, ColorTransform,
+};
+
+/// A `#rrggbb` or `#rrggbbaa` color found in a text. Hex runs of any other length are not colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HexToken {
+    pub start: usize,
+    pub end: usize,
+    pub color: Color,
+}
+
+// A sha1 hash, as git prints them in full
+const HASH_LEN: usize = 40;
+
+/// Which hex tokens of a text are taken for colors and the case replacements are written in,
+/// from `[text_options]` and `[hex_case]`. Tokens are judged by the word around them, the run
+/// of characters up to whitespace, a quote, a parenthesis, a comma or a semicolon.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextOptions {
+    pub hex_case: HexCase,
+    /// Leaves tokens in words with `://`, as in `https://example.com/#2e3440`.
+    pub skip_urls: bool,
+    /// Leaves tokens in words holding a run of at least 40 hex digits, as a commit hash is.
+    pub skip_hashes: bool,
+    /// Globs leaving tokens alone whose word matches, see `glob_match`.
+    pub exclude: Vec<String>,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            hex_case: HexCase::default(),
+            skip_urls: true,
+            skip_hashes: true,
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl TextOptions {
+    /// Whether `token` of `text` is taken for a color.
+    pub fn allows(&self, text: &str, token: &HexToken) -> bool {
+        let is_delimiter =
+            |c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '(' | ')' | ',' | ';');
+        let start = text[..token.start].rfind(is_delimiter).map_or(0, |i| {
+            i + text[i..].chars().next().map_or(1, char::len_utf8)
+        });
+        let end = text[token.end..]
+            .find(is_delimiter)
+            .map_or(text.len(), |i| token.end + i);
+        let word = &text[start..end];
+        let longest_hex_run = word
+            .split(|c: char| !c.is_ascii_hexdigit())
+            .map(str::len)
+            .max()
+            .unwrap_or(0);
+        let skipped = (self.skip_urls && word.contains("://"))
+            || (self.skip_hashes && longest_hex_run >= HASH_LEN)
+            || self.exclude.iter().any(|pattern| glob_match(pattern, word));
+        !skipped
+    }
+
+    /// The tokens of `text` taken for colors.
+    pub fn tokens(&self, text: &str) -> Vec<HexToken> {
+        hex_tokens(text)
+            .into_iter()
+            .filter(|token| self.allows(text, token))
+            .collect()
+    }
+}
+
+/// Whether `text` matches the glob `pattern` as a whole.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was and how much of the text it has taken so far
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub fn hex_tokens(text: &str) -> Vec<HexToken> {
+    hex_tokens_iter(text).collect()
+}
+
+/// The tokens of `hex_tokens` as they are found, for callers that can stop early.
+pub fn hex_tokens_iter(text: &str) -> impl Iterator<Item = HexToken> + '_ {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        while i < bytes.len() {
+            if bytes[i] != b'#' {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            let digits = bytes[i + 1..]
+                .iter()
+                .take_while(|b| b.is_ascii_hexdigit())
+                .count();
+            i += 1 + digits;
+            if digits == 6 || digits == 8 {
+                if let Ok(color) = Color::try_from_hex_str(&text[start + 1..start + 7]) {
+                    return Some(HexToken {
+                        start,
+                        end: i,
+                        color,
+                    });
+                }
+            }
+        }
+        None
+    })
+}
+
+/// Colors at most this far from one a map looks for are reported as likely typos.
+pub const NEAR_MATCH_DISTANCE: u32 = 8;
+
+/// A color close to one a map looks for but never found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NearMatch {
+    pub wanted: Color,
+    pub found: Color,
+    pub distance: u32,
+    /// 1-based line of the first occurrence.
+    pub line: usize,
+}
+
+impl std::fmt::Display for NearMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} not found, found {} (distance {}) at line {}, did you mean this?",
+            self.wanted, self.found, self.distance, self.line
+        )
+    }
+}
+
+/// Colors of the lines `owned` accepts that are close to a `wanted` color with no exact match,
+/// closest first.
+pub fn near_matches(text: &str, wanted: &[Color], owned: impl Fn(&str) -> bool) -> Vec<NearMatch> {
+    let mut found: Vec<(Color, usize)> = Vec::new();
+    for (number, line) in text.split_inclusive('\n').enumerate() {
+        if !owned(line) {
+            continue;
+        }
+        for token in hex_tokens(line) {
+            if !found.iter().any(|(color, _)| *color == token.color) {
+                found.push((token.color, number + 1));
+            }
+        }
+    }
+
+    let mut matches = Vec::new();
+    for wanted in wanted {
+        if found.iter().any(|(color, _)| color == wanted) {
+            continue;
+        }
+        for (color, line) in found.iter() {
+            let distance = wanted.distance(color);
+            if distance <= NEAR_MATCH_DISTANCE {
+                matches.push(NearMatch {
+                    wanted: *wanted,
+                    found: *color,
+                    distance,
+                    line: *line,
+                });
+            }
+        }
+    }
+    matches.sort_by_key(|m| m.distance);
+    matches
+}
+
+/// Result of a handler that only rewrites colors bound to a name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextReport {
+    pub changes: usize,
+    /// Names whose colors were rewritten, in file order.
+    pub names: Vec<String>,
+}
+
+/// The `(old, new)` pairs `transform` swaps in text, parsed once. A gradient ramps `colors`,
+/// the colors found in the text, the same way it ramps a png palette, leaving black, white and
+/// colors outside its luminance band.
+pub fn replacements(
+    transform: &ColorTransform,
+    colors: &[Color],
+) -> Result<Vec<(Color, Color)>, TranError> {
+    match transform {
+        ColorTransform::Gradient {
+            primary,
+            space,
+            luminance,
+            stops,
+            ..
+        } => {
+            let mut ramp: Vec<(u8, u8, u8)> = Vec::new();
+            for color in colors {
+                if *color != Color::black()
+                    && *color != Color::white()
+                    && !ramp.contains(&color.bytes())
+                {
+                    ramp.push(color.bytes());
+                }
+            }
+            let lookup = crate::pixel::gradient_map(&ramp, primary, *space, *luminance, stops)?;
+            Ok(ramp
+                .iter()
+                .filter_map(|old| Some((*old, *lookup.get(old)?)))
+                .filter(|(old, new)| old != new)
+                .map(|(old, new)| {
+                    (
+                        Color::from_bytes(old.0, old.1, old.2),
+                        Color::from_bytes(new.0, new.1, new.2),
+                    )
+                })
+                .collect())
+        }
+        ColorTransform::Simulate(deficiency) => {
+            let mut pairs: Vec<(Color, Color)> = Vec::new();
+            for color in colors {
+                let simulated = deficiency.simulate(color);
+                if simulated != *color && !pairs.iter().any(|(old, _)| old == color) {
+                    pairs.push((*color, simulated));
+                }
+            }
+            Ok(pairs)
+        }
+        ColorTransform::Map(map) => map
+            .iter()
+            .filter(|c| !c.get_current_color().is_empty() && !c.keeps_color())
+            .map(ColorMap::colors)
+            .collect(),
+        ColorTransform::Chain(stages) => chain(stages, colors, replacements),
+    }
+}
+
+/// The pairs of `stages` applied one after the other to `colors`, `pairs` giving those of a
+/// single stage for the colors the stages before it left. A color a stage brings in that no
+/// earlier one touched is followed through the later stages too, so a chain starting with a map
+/// needs no colors to be found.
+pub(crate) fn chain(
+    stages: &[ColorTransform],
+    colors: &[Color],
+    pairs: impl Fn(&ColorTransform, &[Color]) -> Result<Vec<(Color, Color)>, TranError>,
+) -> Result<Vec<(Color, Color)>, TranError> {
+    // Each color as it was and as the stages so far left it
+    let mut paths: Vec<(Color, Color)> = Vec::new();
+    for color in colors {
+        if !paths.iter().any(|(old, _)| old == color) {
+            paths.push((*color, *color));
+        }
+    }
+    for stage in stages {
+        let current: Vec<Color> = paths.iter().map(|(_, current)| *current).collect();
+        let stage = pairs(stage, &current)?;
+        for (_, current) in paths.iter_mut() {
+            if let Some((_, new)) = stage.iter().find(|(old, _)| old == current) {
+                *current = *new;
+            }
+        }
+        for (old, new) in stage {
+            if !paths.iter().any(|(path, _)| *path == old) {
+                paths.push((old, new));
+            }
+        }
+    }
+    Ok(paths.into_iter().filter(|(old, new)| old != new).collect())
+}
+
+/// Rewrites every token of `text` that has a replacement, case-insensitively and in a single
+/// pass so a color replaced earlier is never picked up again. An alpha suffix is kept, and an
+/// occurrence all in one case gets its replacement in that case, any other in the one of
+/// `options`. Tokens `options` doesn't take for colors are left alone.
+pub fn replace_tokens(
+    text: &str,
+    replacements: &[(Color, Color)],
+    options: &TextOptions,
+) -> (String, usize) {
+    let mut output = String::with_capacity(text.len());
+    let mut changes = 0;
+    let mut last = 0;
+    for token in options.tokens(text) {
+        if let Some((_, new)) = replacements.iter().find(|(old, _)| *old == token.color) {
+            output.push_str(&text[last..token.start]);
+            let digits = &text[token.start + 1..token.start + 7];
+            let upper = digits.bytes().any(|b| b.is_ascii_uppercase());
+            let lower = digits.bytes().any(|b| b.is_ascii_lowercase());
+            let case = match (upper, lower) {
+                (true, false) => HexCase::Upper,
+                (false, true) => HexCase::Lower,
+                _ => options.hex_case,
+            };
+            output.push_str(&new.to_hex(case));
+            output.push_str(&text[token.start + 7..token.end]);
+            last = token.end;
+            changes += 1;
+        }
+    }
+    output.push_str(&text[last..]);
+    (output, changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pixel::LuminanceBand, space::GradientSpace};
+
+    const SHADES: [&str; 5] = ["#f0e0d0", "#c0b0a0", "#908070", "#605040", "#302010"];
+
+    fn gradient(primary: &str) -> ColorTransform<'_, 'static> {
+        ColorTransform::Gradient {
+            primary,
+            background: "#000000",
+            current: "#000000",
+            space: GradientSpace::Srgb,
+            luminance: LuminanceBand::default(),
+            stops: &[],
+        }
+    }
+
+    fn stylesheet() -> String {
+        let mut css = String::from(".a { color: #000000; background: #ffffff; }\n");
+        for (i, shade) in SHADES.iter().enumerate() {
+            css.push_str(&format!(".s{} {{ color: {}; }}\n", i, shade));
+        }
+        css
+    }
+
+    #[test]
+    fn chains_take_the_colors_the_stages_before_left() {
+        let first = [crate::ColorMap::new("#222222", "#111111")];
+        let second = [crate::ColorMap::new("#333333", "#222222")];
+        let stages = [ColorTransform::Map(&first), ColorTransform::Map(&second)];
+        let chain = ColorTransform::Chain(&stages);
+        let (text, changes) = crate::recolor_text("a=#111111 b=#222222", &chain).unwrap();
+        assert_eq!((text.as_str(), changes), ("a=#333333 b=#333333", 2));
+        let pairs = replacements(&chain, &[]).unwrap();
+        assert!(pairs.contains(&(
+            Color::try_from_hex_str("111111").unwrap(),
+            Color::try_from_hex_str("333333").unwrap()
+        )));
+
+        // A map after a gradient matches the shade the gradient left
+        let map = [crate::ColorMap::new("#bf616a", "#88c0d0")];
+        let stages = [gradient("#88c0d0"), ColorTransform::Map(&map)];
+        let (css, _) = crate::recolor_text(&stylesheet(), &ColorTransform::Chain(&stages)).unwrap();
+        assert!(css.contains(".s0 { color: #bf616a; }"), "{}", css);
+        assert!(
+            css.contains("color: #000000; background: #ffffff;"),
+            "{}",
+            css
+        );
+    }
+
+    #[test]
+    fn gradients_ramp_every_shade_of_a_text() {
+        let transform = gradient("#88c0d0");
+        let (css, changes) = crate::recolor_text(&stylesheet(), &transform).unwrap();
+        assert_eq!(changes, SHADES.len());
+
+        let colors: Vec<Color> = hex_tokens(&css).iter().map(|t| t.color).collect();
+        assert_eq!(colors[..2], [Color::black(), Color::white()]);
+        let ramped = &colors[2..];
+        assert_eq!(ramped[0], Color::try_from_hex_str("88c0d0").unwrap());
+        let brightness = |c: &Color| c.bytes().0 as u32 + c.bytes().1 as u32 + c.bytes().2 as u32;
+        assert!(
+            ramped
+                .windows(2)
+                .all(|pair| brightness(&pair[0]) > brightness(&pair[1])),
+            "{}",
+            css
+        );
+
+        let ramp: Vec<(u8, u8, u8)> = SHADES
+            .iter()
+            .map(|s| Color::try_from_hex_str(&s[1..]).unwrap().bytes())
+            .collect();
+        let lookup = crate::pixel::gradient_map(
+            &ramp,
+            "#88c0d0",
+            GradientSpace::Srgb,
+            LuminanceBand::default(),
+            &[],
+        )
+        .unwrap();
+        for (old, new) in ramp.iter().zip(ramped) {
+            assert_eq!(lookup[old], new.bytes());
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn texts_ramp_as_png_palettes_do() {
+        use crate::png::{build::PngBuilder, check::check_png};
+
+        let palette: Vec<(u8, u8, u8)> = SHADES
+            .iter()
+            .map(|s| Color::try_from_hex_str(&s[1..]).unwrap().bytes())
+            .collect();
+        let mut png = PngBuilder::palette(&palette, &[vec![0, 1, 2, 3, 4]]).build();
+        let transform = gradient("#bf616a");
+        crate::png::recolor_png_bytes(&mut png, &transform).unwrap();
+        let check = check_png(&png);
+        let plte = check.chunks.iter().find(|c| c.name() == "PLTE").unwrap();
+        let from_png: Vec<Color> = plte
+            .data
+            .chunks(3)
+            .map(|rgb| Color::from_bytes(rgb[0], rgb[1], rgb[2]))
+            .collect();
+
+        let (css, _) = crate::recolor_text(&stylesheet(), &transform).unwrap();
+        let from_text: Vec<Color> = hex_tokens(&css).iter().skip(2).map(|t| t.color).collect();
+        assert_eq!(from_text, from_png);
+    }
+
+    fn color(hex: &str) -> Color {
+        Color::try_from_hex_str(hex.trim_start_matches('#')).unwrap()
+    }
+
+    #[test]
+    fn an_off_by_one_color_is_suggested() {
+        let text = "bg=#eceff4\n\nfg=#2e343f\nborder=#2e343f\n";
+        let matches = near_matches(text, &[color("#2e3440")], |_| true);
+        assert_eq!(
+            matches,
+            [NearMatch {
+                wanted: color("#2e3440"),
+                found: color("#2e343f"),
+                distance: 1,
+                line: 3,
+            }]
+        );
+        assert_eq!(
+            matches[0].to_string(),
+            "#2e3440 not found, found #2e343f (distance 1) at line 3, did you mean this?"
+        );
+    }
+
+    #[test]
+    fn found_and_far_colors_are_not_suggested() {
+        let wanted = [color("#2e3440"), color("#88c0d0")];
+        let text = "a=#2E3440\nb=#2e343f\nc=#88c0e0\nd=#88c0d5\nf=#80c0d8\n";
+        let matches = near_matches(text, &wanted, |_| true);
+        // #2e3440 is there, only in another case, and the others are 16 away
+        assert_eq!(
+            matches
+                .iter()
+                .map(|m| (m.found, m.distance))
+                .collect::<Vec<_>>(),
+            [(color("#88c0d5"), 5)]
+        );
+    }
+
+    #[test]
+    fn lines_the_handler_does_not_own_are_not_searched() {
+        let text = "@define-color fg #2e343f;\nbutton { color: #2e3441; }\n";
+        let matches = near_matches(text, &[color("#2e3440")], |line| {
+            line.starts_with("@define-color")
+        });
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].found, matches[0].line), (color("#2e343f"), 1));
+    }
+
+    #[test]
+    fn each_occurrence_keeps_its_casing() {
+        let map = [crate::ColorMap::new("#bf616a", "#abcdef")];
+        let transform = ColorTransform::Map(&map);
+        let text = "a=#ABCDEF\nb=#abcdef\nc=#abcdefCC\nd=#ABCDEFcc\ne=#aBcDeF\n";
+        let (lower, changes) = crate::recolor_text(text, &transform).unwrap();
+        assert_eq!(changes, 5);
+        assert_eq!(
+            lower,
+            "a=#BF616A\nb=#bf616a\nc=#bf616aCC\nd=#BF616Acc\ne=#bf616a\n"
+        );
+
+        // Only an occurrence mixing cases takes the one asked for
+        let options = TextOptions {
+            hex_case: HexCase::Upper,
+            ..TextOptions::default()
+        };
+        let (upper, _) = crate::recolor_text_in(text, &transform, &options).unwrap();
+        assert_eq!(
+            upper,
+            "a=#BF616A\nb=#bf616a\nc=#bf616aCC\nd=#BF616Acc\ne=#BF616A\n"
+        );
+    }
+
+    #[test]
+    fn urls_hashes_and_excluded_words_are_left_alone() {
+        let map = [crate::ColorMap::new("#123456", "#abcdef")];
+        let transform = ColorTransform::Map(&map);
+        let hash = format!("commit={}", "abcdef".repeat(7));
+        let text = format!(
+            "url=https://example.com/#abcdef\n{}#abcdef\nicon-#abcdef.svg\nfg=#abcdef\n",
+            hash
+        );
+        let options = TextOptions {
+            exclude: vec!["icon-*".to_string()],
+            ..TextOptions::default()
+        };
+        let (recolored, changes) = crate::recolor_text_in(&text, &transform, &options).unwrap();
+        assert_eq!(changes, 1);
+        assert!(
+            recolored.ends_with("icon-#abcdef.svg\nfg=#123456\n"),
+            "{}",
+            recolored
+        );
+
+        let everything = TextOptions {
+            skip_urls: false,
+            skip_hashes: false,
+            ..TextOptions::default()
+        };
+        let (_, changes) = crate::recolor_text_in(&text, &transform, &everything).unwrap();
+        assert_eq!(changes, 4);
+    }
+
+    #[test]
+    fn globs_match_whole_words() {
+        assert!(glob_match("icon-*", "icon-#abcdef.svg"));
+        assert!(glob_match("*.svg", "icon-#abcdef.svg"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*b*b", "abab"));
+        assert!(!glob_match("icon-*", "my-icon-#abcdef"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn two_colors_swap_in_one_pass() {
+        let map = [
+            crate::ColorMap::new("#eceff4", "#2e3440"),
+            crate::ColorMap::new("#2e3440", "#eceff4"),
+        ];
+        let (text, changes) = crate::recolor_text(
+            "#2e3440#eceff4 fg=#ECEFF4,bg=#2e3440\n",
+            &ColorTransform::Map(&map),
+        )
+        .unwrap();
+        assert_eq!(changes, 4);
+        assert_eq!(text, "#eceff4#2e3440 fg=#2E3440,bg=#eceff4\n");
+    }
+}
@@ -0,0 +1,387 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    config::{Color, Config, Target, Transition},
+    errors::TranError,
+    json::Json,
+    recolor::read_head,
+    run::{apply_from, target_options, RunSummary},
+    selection::Selection,
+    space::GradientSpace,
+    state::State,
+    FileFormat, RecolorOptions,
+};
+
+const PROGRESS_FILE: &str = "transition.json";
+
+/// The selection every step of a transition from `from` to `to` applies, the last being `to`.
+pub fn steps(from: &[Color], to: &Selection, steps: usize, space: GradientSpace) -> Vec<Selection> {
+    let ramps: Vec<Vec<Color>> = to
+        .colors()
+        .iter()
+        .zip(from)
+        .map(|(to, from)| from.ramp_in(to, steps, space))
+        .collect();
+    let mut selections: Vec<Selection> = (0..steps.saturating_sub(1))
+        .map(|i| match to {
+            Selection::Color(_) => Selection::Color(ramps[0][i]),
+            Selection::Row(_) => Selection::Row(ramps.iter().map(|ramp| ramp[i]).collect()),
+        })
+        .collect();
+    selections.push(to.clone());
+    selections
+}
+
+/// How far a transition got, kept in the state directory until its last step is done so an
+/// interrupted transition can be finished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Progress {
+    from: Vec<Color>,
+    at: Vec<Color>,
+    to: Selection,
+    skip_png: bool,
+}
+
+impl Progress {
+    fn path(state: &State) -> PathBuf {
+        state.get_dir().join(PROGRESS_FILE)
+    }
+
+    fn to_json(&self) -> Json {
+        let colors = |colors: &[Color]| -> Json {
+            colors
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .into()
+        };
+        let mode = match self.to {
+            Selection::Color(_) => "gradient",
+            Selection::Row(_) => "map",
+        };
+        Json::object()
+            .with("mode", mode)
+            .with("from", colors(&self.from))
+            .with("at", colors(&self.at))
+            .with("to", colors(self.to.colors()))
+            .with("skip_png", self.skip_png)
+    }
+
+    fn from_json(json: &Json) -> Option<Self> {
+        let colors = |key: &str| -> Option<Vec<Color>> {
+            json.get(key)?
+                .as_array()?
+                .iter()
+                .map(|c| c.as_str().and_then(|c| Color::try_from_hex_str(c).ok()))
+                .collect()
+        };
+        let to = colors("to")?;
+        let to = match json.get("mode")?.as_str()? {
+            "gradient" => Selection::Color(*to.first()?),
+            _ => Selection::Row(to),
+        };
+        Some(Progress {
+            from: colors("from")?,
+            at: colors("at")?,
+            to,
+            skip_png: json.get("skip_png")?.as_bool()?,
+        })
+    }
+
+    fn save(&self, state: &State) -> Result<(), TranError> {
+        let path = Progress::path(state);
+        crate::perms::write(&path, self.to_json().to_string())
+            .map_err(|e| TranError::write(&path, e))
+    }
+
+    fn load(state: &State) -> Result<Option<Self>, TranError> {
+        let path = Progress::path(state);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| TranError::read(&path, e))?;
+        Progress::from_json(&Json::parse(&contents)?)
+            .map(Some)
+            .ok_or_else(|| {
+                TranError::UnsupportedError(format!(
+                    "Could not read transition progress in {}",
+                    path.display()
+                ))
+            })
+    }
+
+    fn clear(state: &State) -> Result<(), TranError> {
+        let path = Progress::path(state);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(TranError::write(&path, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_png(target: &Target, options: &RecolorOptions) -> bool {
+    let (path, options) = target_options(target, options);
+    match options.format {
+        Some(format) => format == FileFormat::Png,
+        None => {
+            read_head(&path).is_ok_and(|head| FileFormat::detect(&path, &head) == FileFormat::Png)
+        }
+    }
+}
+
+/// Splits the targets into those recolored on every step and the pngs only recolored on the
+/// last one.
+fn split_targets(
+    config: &Config,
+    options: &RecolorOptions,
+    skip_png: bool,
+) -> (Vec<Target>, Vec<Target>) {
+    config
+        .get_targets()
+        .iter()
+        .cloned()
+        .partition(|target| !(skip_png && is_png(target, options)))
+}
+
+fn merge(mut summary: RunSummary, other: RunSummary) -> RunSummary {
+    summary.succeeded.extend(other.succeeded);
+    summary.failed.extend(other.failed);
+    summary.interrupted |= other.interrupted;
+    summary
+}
+
+/// Fades every target from the current colors of `config` to `selection`, calling `wait`
+/// between steps. Progress is saved to `state` after every step, the summary returned is the
+/// one of the last step but from the colors the transition started at. The config itself is
+/// left untouched. A cancellation is only checked between steps, as targets a step left
+/// behind couldn't be told from the others when it is finished. The transition is then left
+/// for `resume`.
+pub fn apply(
+    state: &State,
+    config: &Config,
+    selection: &Selection,
+    options: &RecolorOptions,
+    transition: &Transition,
+    mut wait: impl FnMut(Duration),
+) -> Result<RunSummary, TranError> {
+    let from = config.get_current_colors();
+    let (every_step, last_step) = split_targets(config, options, transition.skip_png);
+    let mut progress = Progress {
+        from: from.clone(),
+        at: from.clone(),
+        to: selection.clone(),
+        skip_png: transition.skip_png,
+    };
+    progress.save(state)?;
+    let cancel = options.cancel.as_ref();
+    let options = &RecolorOptions {
+        cancel: None,
+        ..options.clone()
+    };
+
+    let steps = steps(
+        &from,
+        selection,
+        transition.steps,
+        config.get_gradient_space(),
+    );
+    let mut summary: Option<RunSummary> = None;
+    for (i, step) in steps.iter().enumerate() {
+        if i > 0 {
+            wait(transition.interval());
+        }
+        if let (Some(mut summary), true) =
+            (summary.take(), cancel.is_some_and(|c| c.is_cancelled()))
+        {
+            summary.colors.old = from;
+            summary.interrupted = true;
+            return Ok(summary);
+        }
+        let mut result = apply_from(config, &every_step, &progress.at, step, options);
+        if i + 1 == steps.len() && !last_step.is_empty() {
+            result = merge(result, apply_from(config, &last_step, &from, step, options));
+        }
+        progress.at = step.colors().to_vec();
+        progress.save(state)?;
+        summary = Some(result);
+    }
+
+    Progress::clear(state)?;
+    let mut summary = summary.ok_or_else(|| {
+        TranError::UnsupportedError("A transition needs at least one step".to_string())
+    })?;
+    summary.colors.old = from;
+    Ok(summary)
+}
+
+/// The selection an interrupted transition was heading for, if there is one.
+pub fn pending(state: &State) -> Result<Option<Selection>, TranError> {
+    Ok(Progress::load(state)?.map(|progress| progress.to))
+}
+
+/// Finishes an interrupted transition, taking every target straight to the colors it was
+/// heading for. It isn't cancelled, stopping halfway would leave targets `resume` can't
+/// finish.
+pub fn resume(
+    state: &State,
+    config: &Config,
+    options: &RecolorOptions,
+) -> Result<RunSummary, TranError> {
+    let options = &RecolorOptions {
+        cancel: None,
+        ..options.clone()
+    };
+    let progress = Progress::load(state)?.ok_or_else(|| {
+        TranError::UnsupportedError("No interrupted transition to finish".to_string())
+    })?;
+    let (every_step, last_step) = split_targets(config, options, progress.skip_png);
+    let mut summary = merge(
+        apply_from(config, &every_step, &progress.at, &progress.to, options),
+        apply_from(config, &last_step, &progress.from, &progress.to, options),
+    );
+    Progress::clear(state)?;
+    summary.colors.old = progress.from;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config_contents, recolor::Cancellation, test_util::TempDir};
+
+    fn color(hex: &str) -> Color {
+        Color::try_from_hex_str(hex).unwrap()
+    }
+
+    fn config(targets: &[&std::path::Path]) -> Config {
+        let targets: Vec<String> = targets.iter().map(|t| t.display().to_string()).collect();
+        parse_config_contents(&format!(
+            "[mode]\ngradient\n[current_color]\n#333333\n[colors]\n#999999\n[target_files]\n{}\n",
+            targets.join("\n")
+        ))
+        .unwrap()
+    }
+
+    fn three_steps() -> Transition {
+        Transition {
+            duration: 3,
+            steps: 3,
+            skip_png: false,
+        }
+    }
+
+    #[test]
+    fn steps_ramp_to_the_selection() {
+        let steps = steps(
+            &[color("333333")],
+            &Selection::Color(color("999999")),
+            3,
+            GradientSpace::Srgb,
+        );
+        assert_eq!(
+            steps,
+            [
+                Selection::Color(color("555555")),
+                Selection::Color(color("777777")),
+                Selection::Color(color("999999")),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_step_is_applied_in_turn() {
+        let dir = TempDir::new("transition");
+        let target = dir.write("colors.conf", "fg=#333333\n");
+        let state = State::at(dir.path().join("state")).unwrap();
+        let mut seen = Vec::new();
+        let mut waits = Vec::new();
+        let summary = apply(
+            &state,
+            &config(&[&target]),
+            &Selection::Color(color("999999")),
+            &RecolorOptions::default(),
+            &three_steps(),
+            |wait| {
+                waits.push(wait);
+                seen.push(std::fs::read_to_string(&target).unwrap());
+            },
+        )
+        .unwrap();
+
+        assert_eq!(seen, ["fg=#555555\n", "fg=#777777\n"]);
+        assert_eq!(waits, [Duration::from_secs(1); 2]);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=#999999\n");
+        assert_eq!(summary.colors.old, [color("333333")]);
+        assert!(!summary.interrupted);
+        assert_eq!(pending(&state).unwrap(), None);
+    }
+
+    #[test]
+    fn an_interrupted_transition_can_be_finished() {
+        let dir = TempDir::new("transition-cancel");
+        let target = dir.write("colors.conf", "fg=#333333\n");
+        let state = State::at(dir.path().join("state")).unwrap();
+        let config = config(&[&target]);
+        let cancel = Cancellation::new();
+        let options = RecolorOptions {
+            cancel: Some(cancel.clone()),
+            ..RecolorOptions::default()
+        };
+        let summary = apply(
+            &state,
+            &config,
+            &Selection::Color(color("999999")),
+            &options,
+            &three_steps(),
+            |_| cancel.cancel(),
+        )
+        .unwrap();
+
+        assert!(summary.interrupted);
+        assert_eq!(summary.colors.old, [color("333333")]);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=#555555\n");
+        assert_eq!(
+            pending(&state).unwrap(),
+            Some(Selection::Color(color("999999")))
+        );
+
+        let summary = resume(&state, &config, &RecolorOptions::default()).unwrap();
+        assert_eq!(summary.colors.old, [color("333333")]);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "fg=#999999\n");
+        assert_eq!(pending(&state).unwrap(), None);
+        assert!(resume(&state, &config, &RecolorOptions::default()).is_err());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn pngs_can_wait_for_the_last_step() {
+        let dir = TempDir::new("transition-png");
+        let text = dir.write("colors.conf", "fg=#333333\n");
+        let png = crate::png::build::PngBuilder::palette(&[(0x33, 0x33, 0x33)], &[vec![0]]).build();
+        let icon = dir.write("icon.png", &png);
+        let state = State::at(dir.path().join("state")).unwrap();
+        let options = RecolorOptions {
+            overwrite: true,
+            ..RecolorOptions::default()
+        };
+        let transition = Transition {
+            skip_png: true,
+            ..three_steps()
+        };
+        let mut pngs = Vec::new();
+        apply(
+            &state,
+            &config(&[&text, &icon]),
+            &Selection::Color(color("999999")),
+            &options,
+            &transition,
+            |_| pngs.push(std::fs::read(&icon).unwrap()),
+        )
+        .unwrap();
+
+        assert_eq!(pngs, [png.clone(), png.clone()]);
+        let recolored = std::fs::read(&icon).unwrap();
+        assert_ne!(recolored, png);
+        assert!(recolored.windows(3).any(|rgb| rgb == [0x99, 0x99, 0x99]));
+    }
+}
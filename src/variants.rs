@@ -0,0 +1,309 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{Color, Config},
+    cvd::ColorVisionDeficiency,
+    errors::TranError,
+    json::Json,
+    recolor::{fill_run_fields, output_path, recolor_path_to, validate_output_name},
+    run::{target_options, with_transform},
+    state::today,
+    ColorTransform, RecolorOptions,
+};
+
+pub const DEFAULT_VARIANT_NAME: &str = "{stem}_{index}.{ext}";
+pub const DEFAULT_SIMULATION_NAME: &str = "{stem}.{ext}";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VariantOptions {
+    pub out_dir: PathBuf,
+    /// An output name template, see `recolor::validate_output_name`.
+    pub name: String,
+    /// Replace outputs that already exist instead of failing.
+    pub force: bool,
+}
+
+impl VariantOptions {
+    pub fn new(out_dir: impl Into<PathBuf>) -> Self {
+        VariantOptions {
+            out_dir: out_dir.into(),
+            name: DEFAULT_VARIANT_NAME.to_string(),
+            force: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant {
+    pub index: usize,
+    pub colors: Vec<Color>,
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub changes: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct VariantReport {
+    pub variants: Vec<Variant>,
+    pub failed: Vec<(PathBuf, TranError)>,
+}
+
+impl VariantReport {
+    pub fn to_json(&self) -> Json {
+        let variants: Vec<Json> = self
+            .variants
+            .iter()
+            .map(|v| {
+                Json::object()
+                    .with("index", v.index)
+                    .with(
+                        "colors",
+                        v.colors.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+                    )
+                    .with("source", v.source.to_string_lossy().to_string())
+                    .with("output", v.output.to_string_lossy().to_string())
+                    .with("changes", v.changes)
+            })
+            .collect();
+        let failed: Vec<Json> = self
+            .failed
+            .iter()
+            .map(|(path, e)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("error", e.to_string())
+            })
+            .collect();
+
+        Json::object()
+            .with("variants", variants)
+            .with("failed", failed)
+    }
+}
+
+/// Where the variant `index` of `source` goes.
+fn variant_path(
+    source: &Path,
+    options: &VariantOptions,
+    index: usize,
+    date: &str,
+    transform: &ColorTransform,
+) -> PathBuf {
+    let template = fill_run_fields(&options.name, index, date);
+    let name = output_path(source, &template, transform);
+    options
+        .out_dir
+        .join(name.file_name().unwrap_or(source.as_os_str()))
+}
+
+/// Writes a copy of every target for every configured color or map row into the output
+/// directory, recolored from the current colors. Neither the targets nor the config change.
+pub fn generate(
+    config: &Config,
+    recolor: &RecolorOptions,
+    options: &VariantOptions,
+) -> Result<VariantReport, TranError> {
+    validate_output_name(&options.name)?;
+    std::fs::create_dir_all(&options.out_dir).map_err(|e| TranError::write(&options.out_dir, e))?;
+
+    let mut copies = Copies::new(config, recolor, options);
+    let current = config.get_current_colors();
+    for (index, selection) in config.get_selections().iter().enumerate() {
+        with_transform(
+            selection,
+            &current,
+            config.get_gradient_space(),
+            config.get_luminance_band(),
+            config.get_stops(),
+            |transform| copies.write(index, selection.colors(), transform),
+        );
+    }
+
+    Ok(copies.report)
+}
+
+/// Writes a copy of every target into the output directory as it looks with `deficiency`.
+/// Neither the targets nor the config change.
+pub fn simulate(
+    config: &Config,
+    recolor: &RecolorOptions,
+    options: &VariantOptions,
+    deficiency: ColorVisionDeficiency,
+) -> Result<VariantReport, TranError> {
+    validate_output_name(&options.name)?;
+    std::fs::create_dir_all(&options.out_dir).map_err(|e| TranError::write(&options.out_dir, e))?;
+
+    let mut copies = Copies::new(config, recolor, options);
+    let colors: Vec<Color> = config
+        .get_current_colors()
+        .iter()
+        .map(|c| deficiency.simulate(c))
+        .collect();
+    copies.write(0, &colors, &ColorTransform::Simulate(deficiency));
+
+    Ok(copies.report)
+}
+
+/// Writes copies of the targets of a config, refusing to write over its sources or over
+/// earlier copies.
+struct Copies<'a> {
+    config: &'a Config,
+    recolor: &'a RecolorOptions,
+    options: &'a VariantOptions,
+    date: String,
+    written: HashSet<PathBuf>,
+    report: VariantReport,
+}
+
+impl<'a> Copies<'a> {
+    fn new(config: &'a Config, recolor: &'a RecolorOptions, options: &'a VariantOptions) -> Self {
+        Copies {
+            config,
+            recolor,
+            options,
+            date: today(),
+            written: HashSet::new(),
+            report: VariantReport::default(),
+        }
+    }
+
+    fn write(&mut self, index: usize, colors: &[Color], transform: &ColorTransform) {
+        for target in self.config.get_targets() {
+            let (source, recolor) = target_options(target, self.recolor);
+            let output = variant_path(&source, self.options, index, &self.date, transform);
+            let collision = if output == source {
+                Some(format!("{} would overwrite its source", output.display()))
+            } else if !self.written.insert(output.clone()) {
+                // Two targets with the same name would otherwise overwrite each other
+                Some(format!(
+                    "{} was already written by another target",
+                    output.display()
+                ))
+            } else if output.exists() && !self.options.force {
+                Some(format!(
+                    "{} already exists, use --force to replace it",
+                    output.display()
+                ))
+            } else {
+                None
+            };
+            if let Some(reason) = collision {
+                self.report
+                    .failed
+                    .push((source.clone(), TranError::UnsupportedError(reason)));
+                continue;
+            }
+            match recolor_path_to(&source, &output, &recolor, transform) {
+                Ok(result) => self.report.variants.push(Variant {
+                    index,
+                    colors: colors.to_vec(),
+                    source,
+                    output,
+                    changes: result.changes,
+                }),
+                Err(e) => self.report.failed.push((source, e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config_contents, test_util::TempDir};
+
+    fn config(targets: &[PathBuf]) -> Config {
+        let targets: Vec<String> = targets.iter().map(|t| t.display().to_string()).collect();
+        parse_config_contents(&format!(
+            "[mode]\nmap\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n#bf616a\n[target_files]\n{}\n",
+            targets.join("\n")
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn every_color_gets_a_copy_of_every_target() {
+        let dir = TempDir::new("variants");
+        let svg = dir.write("logo.svg", "<svg fill=\"#2e3440\"/>\n");
+        let css = dir.write("site.css", "a { color: #2e3440; }\n");
+        let config = config(&[svg, css]);
+        let options = VariantOptions::new(dir.path().join("out"));
+        let report = generate(&config, &RecolorOptions::default(), &options).unwrap();
+
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        let outputs: Vec<(usize, &Path)> = report
+            .variants
+            .iter()
+            .map(|v| (v.index, v.output.strip_prefix(dir.path()).unwrap()))
+            .collect();
+        assert_eq!(
+            outputs,
+            [
+                (0, Path::new("out/logo_0.svg")),
+                (0, Path::new("out/site_0.css")),
+                (1, Path::new("out/logo_1.svg")),
+                (1, Path::new("out/site_1.css")),
+            ]
+        );
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("out/logo_0.svg"), "<svg fill=\"#88c0d0\"/>\n");
+        assert_eq!(read("out/site_1.css"), "a { color: #bf616a; }\n");
+        assert_eq!(read("logo.svg"), "<svg fill=\"#2e3440\"/>\n");
+        assert_eq!(config.get_current_colors()[0].to_hex_lower(), "#2e3440");
+    }
+
+    #[test]
+    fn names_can_carry_the_color() {
+        let dir = TempDir::new("variants-color");
+        let svg = dir.write("logo.svg", "<svg fill=\"#2e3440\"/>\n");
+        let options = VariantOptions {
+            name: "{stem}_{color}.{ext}".to_string(),
+            ..VariantOptions::new(dir.path())
+        };
+        let report = generate(&config(&[svg]), &RecolorOptions::default(), &options).unwrap();
+        let names: Vec<_> = report
+            .variants
+            .iter()
+            .map(|v| v.output.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["logo_#88c0d0.svg", "logo_#bf616a.svg"]);
+
+        let options = VariantOptions {
+            name: "{stem}_{size}".to_string(),
+            ..VariantOptions::new(dir.path())
+        };
+        assert!(generate(&config(&[]), &RecolorOptions::default(), &options).is_err());
+    }
+
+    #[test]
+    fn collisions_fail_unless_forced() {
+        let dir = TempDir::new("variants-force");
+        let svg = dir.write("logo.svg", "<svg fill=\"#2e3440\"/>\n");
+        dir.write("out/logo_0.svg", "kept\n");
+        let config = config(&[svg]);
+        let mut options = VariantOptions::new(dir.path().join("out"));
+
+        let report = generate(&config, &RecolorOptions::default(), &options).unwrap();
+        assert_eq!(report.variants.len(), 1);
+        assert_eq!(report.failed.len(), 1);
+        let message = report.failed[0].1.to_string();
+        assert!(message.contains("use --force"), "{}", message);
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("out/logo_0.svg"), "kept\n");
+
+        options.force = true;
+        let report = generate(&config, &RecolorOptions::default(), &options).unwrap();
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        assert_eq!(read("out/logo_0.svg"), "<svg fill=\"#88c0d0\"/>\n");
+
+        // A name without the index makes every color write over the one before
+        options.name = "{stem}.{ext}".to_string();
+        let report = generate(&config, &RecolorOptions::default(), &options).unwrap();
+        assert_eq!(report.failed.len(), 1);
+        let message = report.failed[0].1.to_string();
+        assert!(message.contains("already written"), "{}", message);
+    }
+}
@@ -0,0 +1,344 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::{Color, Config, Target},
+    css::Indirect,
+    errors::TranError,
+    json::Json,
+    recolor_path,
+    run::{target_options, with_transform},
+    scan::{scan_file, scan_indirect, uses_to_json},
+    selection::Selection,
+    RecolorOptions,
+};
+
+/// Where the colors of a target stand next to `current_color`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TargetState {
+    /// It has the current colors.
+    Consistent,
+    /// It has the colors of another configured selection instead.
+    Stale(Selection),
+    /// It has none of the configured colors.
+    Unknown,
+    /// It has none of the configured colors, only ones it takes from outside itself, see
+    /// `VerifyReport::indirect`.
+    Indirect,
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub targets: Vec<(PathBuf, TargetState)>,
+    /// Colors css targets take from outside themselves, by file. They can't fit any
+    /// selection, so they count as neither a match nor a miss.
+    pub indirect: Vec<(PathBuf, Vec<Indirect>)>,
+    /// Stale targets taken back to the current colors by `repair`.
+    pub repaired: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, TranError)>,
+}
+
+impl VerifyReport {
+    pub fn stale(&self) -> impl Iterator<Item = (&PathBuf, &Selection)> {
+        self.targets.iter().filter_map(|(path, state)| match state {
+            TargetState::Stale(selection) => Some((path, selection)),
+            _ => None,
+        })
+    }
+
+    /// The colors `path` takes from outside itself, if it takes any.
+    pub fn uses(&self, path: &PathBuf) -> Option<&[Indirect]> {
+        self.indirect
+            .iter()
+            .find(|(indirect, _)| indirect == path)
+            .map(|(_, uses)| uses.as_slice())
+    }
+
+    pub fn to_json(&self) -> Json {
+        let targets: Vec<Json> = self
+            .targets
+            .iter()
+            .map(|(path, state)| {
+                let json = Json::object().with("path", path.to_string_lossy().to_string());
+                match state {
+                    TargetState::Consistent => json.with("state", "consistent"),
+                    TargetState::Unknown => json.with("state", "unknown"),
+                    TargetState::Indirect => json.with("state", "indirect"),
+                    TargetState::Stale(selection) => json.with("state", "stale").with(
+                        "found",
+                        selection
+                            .colors()
+                            .iter()
+                            .map(|c| c.to_string())
+                            .collect::<Vec<_>>(),
+                    ),
+                }
+                .with("repaired", self.repaired.contains(path))
+                .with(
+                    "indirect",
+                    self.uses(path).map_or_else(Vec::new, uses_to_json),
+                )
+            })
+            .collect();
+        let failed: Vec<Json> = self
+            .failed
+            .iter()
+            .map(|(path, e)| {
+                Json::object()
+                    .with("path", path.to_string_lossy().to_string())
+                    .with("error", e.to_string())
+            })
+            .collect();
+
+        Json::object()
+            .with("targets", targets)
+            .with("failed", failed)
+    }
+}
+
+fn current_selection(config: &Config) -> Selection {
+    match config {
+        Config::GradientConfig(gc) => Selection::Color(*gc.get_current_color()),
+        Config::MapConfig(mc) => Selection::Row(mc.get_current_colors().to_vec()),
+    }
+}
+
+/// Which selection `found`, the colors of a target, holds the most colors of. The current one
+/// wins ties, so a file is only stale when another selection fits it strictly better.
+pub fn classify(config: &Config, found: &[Color]) -> TargetState {
+    let current = current_selection(config);
+    let matches = |selection: &Selection| {
+        selection
+            .colors()
+            .iter()
+            .filter(|c| found.contains(c))
+            .count()
+    };
+    let current_matches = matches(&current);
+    let best = config
+        .get_selections()
+        .into_iter()
+        .filter(|selection| *selection != current)
+        .map(|selection| (matches(&selection), selection))
+        .filter(|(count, _)| *count > current_matches)
+        .max_by_key(|(count, _)| *count);
+    match best {
+        Some((_, selection)) => TargetState::Stale(selection),
+        None if current_matches > 0 => TargetState::Consistent,
+        None => TargetState::Unknown,
+    }
+}
+
+/// Sorts every target of `config` into consistent, stale and unknown, without changing any.
+/// Targets are checked as they are, which only follows the runs in overwrite mode.
+pub fn verify(config: &Config, options: &RecolorOptions) -> VerifyReport {
+    verify_matching(config, options, |_| true)
+}
+
+fn verify_matching(
+    config: &Config,
+    options: &RecolorOptions,
+    keep: impl Fn(&Target) -> bool,
+) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    for target in config.get_targets().iter().filter(|target| keep(target)) {
+        let (path, options) = target_options(target, options);
+        let scanned = scan_file(&path, &options)
+            .and_then(|found| Ok((found, scan_indirect(&path, &options)?)));
+        match scanned {
+            Ok((found, uses)) => {
+                let state = match classify(config, &found) {
+                    TargetState::Unknown if !uses.is_empty() => TargetState::Indirect,
+                    state => state,
+                };
+                if !uses.is_empty() {
+                    report.indirect.push((path.clone(), uses));
+                }
+                report.targets.push((path, state));
+            }
+            Err(e) => report.failed.push((path.clone(), e.in_file(&path))),
+        }
+    }
+    report
+}
+
+/// Takes every stale target of `report` from the colors found in it to the current ones, in
+/// place.
+pub fn repair(config: &Config, options: &RecolorOptions, report: &mut VerifyReport) {
+    let current = current_selection(config);
+    let stale: Vec<(PathBuf, RecolorOptions, Selection)> = config
+        .get_targets()
+        .iter()
+        .map(|target| {
+            // Repairs happen in place whatever the target says
+            let (path, options) = target_options(target, options);
+            let options = RecolorOptions {
+                overwrite: true,
+                ..options
+            };
+            (path, options)
+        })
+        .filter_map(|(path, options)| {
+            let (_, found) = report.stale().find(|(stale, _)| **stale == path)?;
+            let found = found.clone();
+            Some((path, options, found))
+        })
+        .collect();
+    for (path, options, found) in stale {
+        let result = with_transform(
+            &current,
+            found.colors(),
+            config.get_gradient_space(),
+            config.get_luminance_band(),
+            config.get_stops(),
+            |transform| recolor_path(&path, &options, transform),
+        );
+        match result {
+            Ok(_) => report.repaired.push(path),
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+}
+
+/// Whether `target` is the one at `path`, as written or once both are resolved.
+fn is_at(target: &Target, path: &Path) -> bool {
+    target.path == path
+        || std::fs::canonicalize(&target.path)
+            .is_ok_and(|target| std::fs::canonicalize(path).is_ok_and(|path| path == target))
+}
+
+/// Brings the targets at `paths`, every target when empty, in line with the current colors
+/// without advancing the config: a target holding another configured selection is taken from
+/// it as `repair` does. Targets holding none of the configured colors are reported unknown and
+/// left as they are, and a path no target is at fails.
+pub fn adopt(config: &Config, options: &RecolorOptions, paths: &[PathBuf]) -> VerifyReport {
+    let mut report = verify_matching(config, options, |target| {
+        paths.is_empty() || paths.iter().any(|path| is_at(target, path))
+    });
+    for path in paths {
+        if !config
+            .get_targets()
+            .iter()
+            .any(|target| is_at(target, path))
+        {
+            report.failed.push((
+                path.clone(),
+                TranError::UnsupportedError(format!(
+                    "{} is not a target, add it with 'tran target add'",
+                    path.display()
+                )),
+            ));
+        }
+    }
+    repair(config, options, &mut report);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::parse_config_contents, test_util::TempDir};
+
+    fn color(hex: &str) -> Color {
+        Color::try_from_hex_str(hex).unwrap()
+    }
+
+    fn config(targets: &[&std::path::Path]) -> Config {
+        let targets: Vec<String> = targets.iter().map(|t| t.display().to_string()).collect();
+        parse_config_contents(&format!(
+            "[mode]\nmap\n[current_color]\n#2e3440#eceff4\n[colors]\n#2e3440#eceff4\n#bf616a#88c0d0\n[target_files]\n{}\n",
+            targets.join("\n")
+        ))
+        .unwrap()
+    }
+
+    /// A target already recolored to the current colors, one still holding the colors of
+    /// another row and one holding none of the configured colors.
+    fn targets(dir: &TempDir) -> [PathBuf; 3] {
+        [
+            dir.write("recolored.conf", "bg=#2e3440\nfg=#eceff4\n"),
+            dir.write("original.conf", "bg=#bf616a\nfg=#88c0d0\n"),
+            dir.write("foreign.conf", "bg=#123456\n"),
+        ]
+    }
+
+    #[test]
+    fn targets_are_sorted_by_the_colors_they_hold() {
+        let dir = TempDir::new("verify");
+        let [recolored, original, foreign] = targets(&dir);
+        let missing = dir.path().join("missing.conf");
+        let config = config(&[&recolored, &original, &foreign, &missing]);
+        let report = verify(&config, &RecolorOptions::default());
+
+        let stale_row = Selection::Row(vec![color("#bf616a"), color("#88c0d0")]);
+        assert_eq!(
+            report.targets,
+            [
+                (recolored, TargetState::Consistent),
+                (original.clone(), TargetState::Stale(stale_row.clone())),
+                (foreign, TargetState::Unknown),
+            ]
+        );
+        assert_eq!(
+            report.stale().collect::<Vec<_>>(),
+            [(&original, &stale_row)]
+        );
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, missing);
+        assert!(report.repaired.is_empty());
+    }
+
+    #[test]
+    fn ties_go_to_the_current_colors() {
+        let config = config(&[]);
+        assert_eq!(
+            classify(&config, &[color("#2e3440"), color("#bf616a")]),
+            TargetState::Consistent
+        );
+        assert_eq!(
+            classify(&config, &[color("#88c0d0")]),
+            TargetState::Stale(Selection::Row(vec![color("#bf616a"), color("#88c0d0")]))
+        );
+        assert_eq!(classify(&config, &[]), TargetState::Unknown);
+    }
+
+    #[test]
+    fn repair_takes_only_stale_targets_to_the_current_colors() {
+        let dir = TempDir::new("verify-repair");
+        let [recolored, original, foreign] = targets(&dir);
+        let config = config(&[&recolored, &original, &foreign]);
+        let mut report = verify(&config, &RecolorOptions::default());
+        repair(&config, &RecolorOptions::default(), &mut report);
+
+        assert_eq!(report.repaired, [original.as_path()]);
+        assert!(report.failed.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(&original).unwrap(),
+            "bg=#2e3440\nfg=#eceff4\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&recolored).unwrap(),
+            "bg=#2e3440\nfg=#eceff4\n"
+        );
+        assert_eq!(std::fs::read_to_string(&foreign).unwrap(), "bg=#123456\n");
+
+        let report = verify(&config, &RecolorOptions::default());
+        assert_eq!(report.stale().count(), 0);
+    }
+
+    #[test]
+    fn reports_convert_to_json() {
+        let dir = TempDir::new("verify-json");
+        let [_, original, _] = targets(&dir);
+        let config = config(&[&original]);
+        let mut report = verify(&config, &RecolorOptions::default());
+        assert_eq!(
+            report.to_json().to_string(),
+            format!(
+                "{{\"targets\":[{{\"path\":\"{}\",\"state\":\"stale\",\"found\":[\"#bf616a\",\"#88c0d0\"],\"repaired\":false,\"indirect\":[]}}],\"failed\":[]}}",
+                original.display()
+            )
+        );
+        repair(&config, &RecolorOptions::default(), &mut report);
+        assert!(report.to_json().to_string().contains("\"repaired\":true"));
+    }
+}
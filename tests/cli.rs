@@ -0,0 +1,967 @@
+//! End to end runs of the `tran` binary.
+#![cfg(feature = "fs")]
+
+mod common;
+
+use common::Home;
+
+#[cfg(unix)]
+#[test]
+fn paths_that_are_not_utf8_are_scanned() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let home = Home::new("non-utf8");
+    let name = std::ffi::OsStr::from_bytes(b"icon-\xff\xfe.css");
+    let path = home.write(name, "a { color: #2e3440; }\n");
+    let run = home.tran([std::ffi::OsStr::new("scan"), path.as_os_str()]);
+    run.success();
+    assert!(run.stdout().contains("#2e3440"), "{}", run.stdout());
+    assert!(run.stdout().contains("icon-"), "{}", run.stdout());
+
+    // Options that aren't paths still have to be utf-8
+    let run = home.tran([
+        std::ffi::OsStr::new("scan"),
+        std::ffi::OsStr::new("--format"),
+        name,
+    ]);
+    run.failure();
+
+    // And so are the config and output paths of a run
+    let target = home.write("theme.css", "a { color: #111111; }\n");
+    let config = home.write(
+        std::ffi::OsStr::from_bytes(b"tran-\xff.conf"),
+        format!(
+            "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\n{}\n",
+            target.display()
+        ),
+    );
+    let out_dir = home.path(std::ffi::OsStr::from_bytes(b"out-\xfe"));
+    home.tran([
+        std::ffi::OsStr::new("--config"),
+        config.as_os_str(),
+        std::ffi::OsStr::new("--out-dir"),
+        out_dir.as_os_str(),
+    ])
+    .success();
+    assert_eq!(home.read("theme.css"), "a { color: #222222; }\n");
+}
+
+#[test]
+fn swatches_are_left_out_when_stdout_is_no_terminal() {
+    let home = Home::new("swatches");
+    let path = home.write("theme.css", "a { color: #2e3440; }\n");
+    let mut command = home.command([std::ffi::OsStr::new("scan"), path.as_os_str()]);
+    command.env_remove("NO_COLOR").env("COLORTERM", "truecolor");
+    let output = command.output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("#2e3440"), "{}", stdout);
+    assert!(!stdout.contains('\x1b'), "{:?}", stdout);
+}
+
+#[test]
+fn runs_are_logged_to_the_state_dir_and_listed_by_history() {
+    let home = Home::new("history");
+    let target = home.write("theme.css", "a { color: #111111; }\n");
+    home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\n{}\n{}\n",
+        target.display(),
+        home.path("missing.css").display()
+    ));
+    home.tran(Vec::<&str>::new());
+
+    let log = home.read("state/runs.log");
+    assert_eq!(log.lines().count(), 1, "{}", log);
+    assert!(log.contains("\"new_colors\":[\"#222222\"]"), "{}", log);
+    assert!(!home.path(".local/state/tran").exists());
+
+    let run = home.tran(["history"]);
+    run.success();
+    let stdout = run.stdout();
+    assert!(
+        stdout.contains("gradient #111111 -> #222222 (1 ok, 1 failed"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("missing.css"), "{}", stdout);
+
+    let run = home.tran(["history", "-n", "0"]);
+    assert_eq!(run.success().stdout(), "");
+    home.tran(["history", "--bogus"]).failure();
+}
+
+#[test]
+fn the_state_dir_falls_back_to_xdg_state_home_and_home() {
+    let home = Home::new("state-dir");
+    let target = home.write("theme.css", "a { color: #111111; }\n");
+    home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n#333333\n[target_files]\n{}\n",
+        target.display()
+    ));
+
+    let mut command = home.command(Vec::<&str>::new());
+    command
+        .env_remove("TRAN_STATE_DIR")
+        .env_remove("XDG_STATE_HOME");
+    assert!(command.status().unwrap().success());
+    assert!(home.path(".local/state/tran/runs.log").is_file());
+
+    let mut command = home.command(Vec::<&str>::new());
+    command
+        .env_remove("TRAN_STATE_DIR")
+        .env("XDG_STATE_HOME", home.path("xdg"));
+    assert!(command.status().unwrap().success());
+    assert!(home.path("xdg/tran/runs.log").is_file());
+}
+
+#[test]
+fn export_prints_or_writes_the_current_scheme() {
+    let home = Home::new("export");
+    home.config("[mode]\ngradient\n[current_color]\n#88c0d0\n[colors]\n#bf616a\n");
+
+    let run = home.tran(["export", "--format", "json"]);
+    assert_eq!(
+        run.success().stdout(),
+        "{\"color-0\":\"#88c0d0\",\"primary\":\"#88c0d0\",\"background\":\"#000000\"}\n"
+    );
+    home.tran(["export", "--out", "colors.css"]).success();
+    assert!(home
+        .read("colors.css")
+        .starts_with(":root {\n  --tran-color-0: #88c0d0;\n"));
+    home.tran(["export", "--format", "yaml"]).failure();
+}
+
+#[test]
+fn a_format_hint_picks_the_gtk_handler() {
+    let home = Home::new("gtk");
+    let theme = home.write(
+        "gtk.css",
+        "@define-color accent_color #2e3440;\nbutton { color: #2e3440; }\n",
+    );
+    home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n[target_files]\n{}|format=gtk\n",
+        theme.display()
+    ));
+    home.tran(Vec::<&str>::new()).success();
+    assert_eq!(
+        home.read("gtk.css"),
+        "@define-color accent_color #88c0d0;\nbutton { color: #2e3440; }\n"
+    );
+}
+
+/// A palette png of the nord colors #000000, #ffffff, #2e3440, #88c0d0 and #4c566a.
+const PALETTE_PNG: &[u8] = include_bytes!("../fuzz/corpus/png_chunks/palette.png");
+
+#[cfg(feature = "png")]
+#[test]
+fn undo_restores_targets_and_config_byte_for_byte() {
+    let home = Home::new("undo");
+    let text = home.write(
+        "theme.css",
+        "a { color: #2E3440; }\nb { color: #2e3440; }\n",
+    );
+    let png = home.write("icon.png", PALETTE_PNG);
+    let config = home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#bf616a\n[journal]\ntrue\n[target_files]\n{}\n{}\n",
+        text.display(),
+        png.display()
+    ));
+    let config_before = std::fs::read(&config).unwrap();
+    let text_before = std::fs::read(&text).unwrap();
+
+    home.tran(Vec::<&str>::new()).success();
+    assert_ne!(std::fs::read(&text).unwrap(), text_before);
+    assert_ne!(std::fs::read(&config).unwrap(), config_before);
+    let copy = home.path("icon_#bf616a.png");
+    assert!(copy.is_file());
+
+    let run = home.tran(["undo"]);
+    assert!(
+        run.success()
+            .stdout()
+            .starts_with("Restored #2e3440 and 2 file(s)"),
+        "{}",
+        run.stdout()
+    );
+    assert_eq!(std::fs::read(&text).unwrap(), text_before);
+    assert_eq!(std::fs::read(&png).unwrap(), PALETTE_PNG);
+    assert_eq!(std::fs::read(&config).unwrap(), config_before);
+    assert!(!copy.exists());
+
+    let run = home.tran(["undo"]);
+    assert!(
+        run.failure().stderr().contains("Nothing to undo"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn undo_refuses_files_modified_since_the_run() {
+    let home = Home::new("undo-modified");
+    let text = home.write("theme.conf", "fg=#2e3440\n");
+    let config = home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#bf616a\n[target_files]\n{}\n",
+        text.display()
+    ));
+    let config_before = std::fs::read(&config).unwrap();
+    home.tran(Vec::<&str>::new()).success();
+    std::fs::write(&text, "fg=#bf616a\nedited=yes\n").unwrap();
+
+    let run = home.tran(["undo"]);
+    assert!(
+        run.failure().stderr().contains("modified since the run"),
+        "{}",
+        run.stderr()
+    );
+    assert_eq!(home.read("theme.conf"), "fg=#bf616a\nedited=yes\n");
+
+    home.tran(["undo", "--force"]).success();
+    assert_eq!(home.read("theme.conf"), "fg=#2e3440\n");
+    assert_eq!(std::fs::read(&config).unwrap(), config_before);
+}
+
+#[test]
+fn undo_inverts_maps_of_files_too_large_to_back_up() {
+    // Each file is undone with the options the run recolored it with
+    for (name, hints, extra, line, after) in [
+        (
+            "large.conf",
+            "",
+            "",
+            "fg=#2e3440 bg=#88c0d0 other=#123456\n",
+            "fg=#bf616a bg=#a3be8c other=#123456\n",
+        ),
+        // Without literals only custom properties are recolored, and undone
+        (
+            "large.css",
+            "|format=css",
+            "",
+            ":root { --fg: #2e3440; }\na { color: #bf616a; }\n",
+            ":root { --fg: #bf616a; }\na { color: #bf616a; }\n",
+        ),
+        // Colors in urls are taken back, words left out stay as they were
+        (
+            "links.conf",
+            "",
+            "[text_options]\nskip_urls=false\nexclude=keep=*\n",
+            "link=https://x.org/#2e3440 keep=#bf616a\n",
+            "link=https://x.org/#bf616a keep=#bf616a\n",
+        ),
+        // Sections the run left alone are left alone by undo too
+        (
+            "dunstrc",
+            "|format=ini",
+            "",
+            "[global]\nbackground=#2e3440\n[rule]\nbackground=#bf616a\n",
+            "[global]\nbackground=#bf616a\n[rule]\nbackground=#bf616a\n",
+        ),
+    ] {
+        let home = Home::new("undo-inverse");
+        let contents = line.repeat(1024 * 1024 / line.len() + 1);
+        let text = home.write(name, &contents);
+        home.config(&format!(
+            "[mode]\nmap\n[current_color]\n#2e3440#88c0d0\n[colors]\n#bf616a#a3be8c\n{}[target_files]\n{}{}\n",
+            extra,
+            text.display(),
+            hints
+        ));
+        home.tran(Vec::<&str>::new()).success();
+        assert!(home.read(name).starts_with(after), "{}", name);
+        let journaled: String = std::fs::read_dir(home.path("state/journal"))
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .map(|entry| std::fs::read_to_string(entry.path()).unwrap())
+            .collect();
+        assert!(journaled.contains("\"inverse\":true"), "{}", journaled);
+
+        home.tran(["undo"]).success();
+        assert!(home.read(name) == contents, "{}", name);
+    }
+}
+
+#[test]
+fn scan_lists_the_colors_of_the_configured_targets() {
+    let home = Home::new("scan");
+    let a = home.write("a.css", "a { color: #2e3440; background: #eceff4; }\n");
+    let b = home.write("b.conf", "fg=#2e3440\n");
+    home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#bf616a\n[target_files]\n{}\n{}\n",
+        a.display(),
+        b.display()
+    ));
+
+    let run = home.tran(["scan"]);
+    let stdout = run.success().stdout();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            format!("#2e3440     2 {}, {}", a.display(), b.display()),
+            format!("#eceff4     1 {}", a.display()),
+        ]
+    );
+
+    let run = home.tran(["scan", "--format", "json"]);
+    let stdout = run.success().stdout();
+    assert!(
+        stdout.starts_with("{\"colors\":[{\"color\":\"#2e3440\",\"count\":2,"),
+        "{}",
+        stdout
+    );
+
+    let run = home.tran(["scan", "--suggest-map", "1", b.to_str().unwrap()]);
+    assert_eq!(
+        run.success().stdout(),
+        "[current_color]\n#2e3440\n[colors]\n#2e3440\n"
+    );
+
+    let run = home.tran(["scan", "--format", "yaml"]);
+    assert!(
+        run.failure().stderr().contains("Usage: tran scan"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn colors_one_off_from_a_map_are_suggested() {
+    let home = Home::new("near-match");
+    let theme = home.write("theme.conf", "bg=#eceff4\nfg=#2e343f\n");
+    home.config(&format!(
+        "[mode]\nmap\n[current_color]\n#2e3440\n[colors]\n#bf616a\n[target_files]\n{}\n",
+        theme.display()
+    ));
+    let run = home.tran(Vec::<&str>::new());
+    assert!(
+        run.stderr().contains(&format!(
+            "{}: #2e3440 not found, found #2e343f (distance 1) at line 2, did you mean this?",
+            theme.display()
+        )),
+        "{}",
+        run.stderr()
+    );
+    assert_eq!(home.read("theme.conf"), "bg=#eceff4\nfg=#2e343f\n");
+}
+
+#[test]
+fn clean_removes_only_generated_files() {
+    let home = Home::new("clean");
+    let icon = home.write("icon.png", PALETTE_PNG);
+    home.write("icon_#88c0d0.png", PALETTE_PNG);
+    home.write("icon_#88c0d1.png", PALETTE_PNG);
+    home.write("icon_#88c0d0.png.orig", PALETTE_PNG);
+    home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n[target_files]\n{}\n",
+        icon.display()
+    ));
+
+    let run = home.tran(["clean", "--dry-run"]);
+    run.success();
+    assert_eq!(
+        run.stdout(),
+        format!("{}\n", home.path("icon_#88c0d0.png").display())
+    );
+    assert!(home.path("icon_#88c0d0.png").exists());
+
+    // Anything but yes keeps them
+    home.tran_with_input(["clean"], b"n\n").success();
+    assert!(home.path("icon_#88c0d0.png").exists());
+
+    home.tran(["clean", "--yes"]).success();
+    assert!(!home.path("icon_#88c0d0.png").exists());
+    for kept in ["icon.png", "icon_#88c0d1.png", "icon_#88c0d0.png.orig"] {
+        assert!(home.path(kept).exists(), "{}", kept);
+    }
+}
+
+#[test]
+fn simulate_writes_copies_and_leaves_the_originals() {
+    let home = Home::new("simulate");
+    let theme = home.write("theme.conf", "fg=#ff0000\n");
+    let config = format!(
+        "[mode]\ngradient\n[current_color]\n#ff0000\n[colors]\n#00ff00\n[target_files]\n{}\n",
+        theme.display()
+    );
+    home.config(&config);
+
+    home.tran(["simulate", "--type", "protan", "--out-dir", "preview"])
+        .success();
+    assert_eq!(home.read("preview/theme.conf"), "fg=#737300\n");
+    assert_eq!(home.read("theme.conf"), "fg=#ff0000\n");
+    assert_eq!(std::fs::read_to_string(home.config_path()).unwrap(), config);
+
+    home.tran(["simulate", "--type", "protan", "--out-dir", "preview"])
+        .failure();
+    home.tran(["simulate", "--type", "mono", "--out-dir", "preview"])
+        .failure();
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn from_image_prints_or_appends_a_row() {
+    let home = Home::new("from-image");
+    home.write("wallpaper.png", PALETTE_PNG);
+    let run = home.tran(["from-image", "wallpaper.png", "--dry-run"]);
+    run.success();
+    let row = run.stdout().trim().to_string();
+    assert_eq!(row.len(), 16 * 7, "{}", row);
+    assert!(row.starts_with("#000000"), "{}", row);
+    for color in ["#88c0d0", "#2e3440", "#4c566a", "#ffffff"] {
+        assert!(row.contains(color), "{} is missing from {}", color, row);
+    }
+
+    home.config("[mode]\ngradient\n[colors]\n#88c0d0\n");
+    home.tran(["from-image", "wallpaper.png"]).failure();
+    home.config(&format!(
+        "[mode]\nmap\n[colors]\n{}\n",
+        "#111111".repeat(16)
+    ));
+    home.tran(["from-image", "wallpaper.png"]).success();
+    let config = std::fs::read_to_string(home.config_path()).unwrap();
+    assert!(config.contains(&row), "{}", config);
+}
+
+#[test]
+fn preview_writes_the_page_of_the_config() {
+    let home = Home::new("preview");
+    home.config("[mode]\ngradient\n[current_color]\n#88c0d0\n[colors]\n#88c0d0\n3#bf616a\n");
+    home.tran(["preview", "--html", "preview.html"]).success();
+    assert_eq!(
+        home.read("preview.html"),
+        include_str!("golden/preview_gradient.html")
+    );
+    home.tran(["preview"]).failure();
+}
+
+#[test]
+fn shell_snippets_can_be_evaluated() {
+    let home = Home::new("export-sh");
+    home.config("[mode]\nmap\n[current_color]\n#2e3440#eceff4\n[colors]\n#eceff4#2e3440\n");
+    let run = home.tran(["export", "--format", "sh", "--prefix", "C_"]);
+    run.success();
+    let Ok(output) = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            "{}printf %s \"$C_COLOR1 $C_PRIMARY\"",
+            run.stdout()
+        ))
+        .output()
+    else {
+        return;
+    };
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "#eceff4 #2e3440");
+
+    let run = home.tran(["export", "--format", "sh", "--fish"]);
+    run.success();
+    assert!(run.stdout().starts_with("set -x TRAN_COLOR0 '#2e3440'\n"));
+    home.tran(["export", "--format", "sh", "--prefix", "1"])
+        .failure();
+}
+
+#[test]
+fn verify_fails_on_stale_targets_until_repaired() {
+    let home = Home::new("verify");
+    let recolored = home.write("recolored.conf", "bg=#2e3440\nfg=#eceff4\n");
+    let original = home.write("original.conf", "bg=#bf616a\nfg=#88c0d0\n");
+    let foreign = home.write("foreign.conf", "bg=#123456\n");
+    home.config(&format!(
+        "[mode]\nmap\n[current_color]\n#2e3440#eceff4\n[colors]\n#2e3440#eceff4\n#bf616a#88c0d0\n[target_files]\n{}\n{}\n{}\n",
+        recolored.display(),
+        original.display(),
+        foreign.display()
+    ));
+
+    let run = home.tran(["verify"]);
+    assert_eq!(
+        run.failure().stdout(),
+        format!(
+            "consistent {}\nstale      {} (#bf616a#88c0d0)\nunknown    {}\n",
+            recolored.display(),
+            original.display(),
+            foreign.display()
+        )
+    );
+    assert_eq!(home.read("original.conf"), "bg=#bf616a\nfg=#88c0d0\n");
+
+    let run = home.tran(["verify", "--repair"]);
+    assert!(
+        run.success().stdout().contains(&format!(
+            "repaired   {} (was #bf616a#88c0d0)",
+            original.display()
+        )),
+        "{}",
+        run.stdout()
+    );
+    assert_eq!(home.read("original.conf"), "bg=#2e3440\nfg=#eceff4\n");
+    assert_eq!(home.read("foreign.conf"), "bg=#123456\n");
+
+    let run = home.tran(["verify", "--json"]);
+    assert!(run.success().stdout().contains("\"state\":\"consistent\""));
+    home.tran(["verify", "--fix"]).failure();
+}
+
+#[test]
+fn commands_find_colors_as_the_text_options_of_the_config_say() {
+    // Only with the config's skip_urls=false is the color in the url found
+    for (args, mode, colors, held, read, expected) in [
+        (
+            &["scan"][..],
+            "gradient",
+            "#00ff00",
+            "#ff0000",
+            "",
+            "#ff0000",
+        ),
+        (
+            &["variants", "--out-dir", "out"],
+            "gradient",
+            "#00ff00",
+            "#ff0000",
+            "out/link_0.conf",
+            "link=https://x.org/#00ff00\n",
+        ),
+        (
+            &["simulate", "--type", "protan", "--out-dir", "preview"],
+            "gradient",
+            "#00ff00",
+            "#ff0000",
+            "preview/link.conf",
+            "link=https://x.org/#737300\n",
+        ),
+        (
+            &["verify", "--repair"],
+            "gradient",
+            "#ff0000\n#00ff00",
+            "#00ff00",
+            "link.conf",
+            "link=https://x.org/#ff0000\n",
+        ),
+        (
+            &["stats", "--format", "json"],
+            "gradient",
+            "#00ff00",
+            "#ff0000",
+            "",
+            "\"total\":1",
+        ),
+        (
+            &["adopt", "link.conf"],
+            "map",
+            "#00ff00",
+            "#00ff00",
+            "link.conf",
+            "link=https://x.org/#ff0000\n",
+        ),
+        (
+            &["target", "add", "link.conf", "--adopt"],
+            "map",
+            "#00ff00",
+            "#00ff00",
+            "link.conf",
+            "link=https://x.org/#ff0000\n",
+        ),
+    ] {
+        let home = Home::new("text-options");
+        let link = home.write("link.conf", format!("link=https://x.org/{}\n", held));
+        home.config(&format!(
+            "[mode]\n{}\n[current_color]\n#ff0000\n[colors]\n{}\n[text_options]\nskip_urls=false\n[target_files]\n{}\n",
+            mode,
+            colors,
+            link.display()
+        ));
+        let run = home.tran(args);
+        let output = match read {
+            "" => run.success().stdout(),
+            read => {
+                run.success();
+                home.read(read)
+            }
+        };
+        assert!(output.contains(expected), "{:?}: {}", args, output);
+    }
+}
+
+#[test]
+fn discover_lists_and_adds_candidates() {
+    let home = Home::new("discover");
+    home.write("themes/a.css", "a { color: #2e3440; }\n");
+    home.write("themes/b.css", "b { color: #123456; }\n");
+    home.config("[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n");
+    let found = std::fs::canonicalize(home.path("themes/a.css")).unwrap();
+
+    let run = home.tran(["discover", "themes"]);
+    run.success();
+    assert_eq!(run.stdout(), format!("{}\n", found.display()));
+
+    home.tran(["discover", "themes", "--add"]).success();
+    let config = std::fs::read_to_string(home.config_path()).unwrap();
+    assert!(
+        config.contains(&format!("[target_files]\n{}\n", found.display())),
+        "{}",
+        config
+    );
+    // Known targets aren't found again
+    let run = home.tran(["discover", "themes"]);
+    assert!(!run.stdout().contains("a.css"), "{}", run.stdout());
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn swap_trades_two_colors_and_keeps_the_config() {
+    let home = Home::new("swap");
+    let theme = home.write("theme.conf", "pair=#2e3440#88c0d0\nfg=#88c0d0\n");
+    let icon = home.write("icon.png", PALETTE_PNG);
+    let config = format!(
+        "[mode]\ngradient\n[current_color]\n#bf616a\n[colors]\n#a3be8c\n[overwrite]\ntrue\n[target_files]\n{}\n{}\n",
+        theme.display(),
+        icon.display()
+    );
+    home.config(&config);
+
+    home.tran(["swap", "#2e3440", "#88c0d0"]).success();
+    assert_eq!(home.read("theme.conf"), "pair=#88c0d0#2e3440\nfg=#2e3440\n");
+    let swapped = std::fs::read(&icon).unwrap();
+    assert_ne!(swapped, PALETTE_PNG);
+    assert_eq!(std::fs::read_to_string(home.config_path()).unwrap(), config);
+
+    // Swapping back restores them
+    home.tran(["swap", "#88c0d0", "#2e3440"]).success();
+    assert_eq!(home.read("theme.conf"), "pair=#2e3440#88c0d0\nfg=#88c0d0\n");
+    assert_eq!(std::fs::read(&icon).unwrap(), PALETTE_PNG);
+    home.tran(["swap", "#88c0d0"]).failure();
+}
+
+#[test]
+fn a_transactional_run_failing_on_its_last_target_applies_nothing() {
+    let home = Home::new("transactional");
+    let first = home.write("first.css", "a { color: #111111; }\n");
+    let second = home.write("second.css", "b { color: #111111; }\n");
+    let missing = home.path("missing.css");
+    let config = format!(
+        "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\n{}\n{}\n{}\n",
+        first.display(),
+        second.display(),
+        missing.display()
+    );
+    home.config(&config);
+
+    let run = home.tran(["--transactional"]);
+    assert_eq!(run.code(), Some(1), "{}", run.stderr());
+    assert!(
+        run.stderr().contains("1 target(s) failed, nothing applied"),
+        "{}",
+        run.stderr()
+    );
+    assert!(
+        !run.stderr().contains("Every target failed"),
+        "{}",
+        run.stderr()
+    );
+    assert_eq!(home.read("first.css"), "a { color: #111111; }\n");
+    assert_eq!(home.read("second.css"), "b { color: #111111; }\n");
+    assert_eq!(std::fs::read_to_string(home.config_path()).unwrap(), config);
+
+    // Without it the targets that could be recolored are
+    let run = home.tran(Vec::<&str>::new());
+    assert_eq!(run.code(), Some(2), "{}", run.stderr());
+    assert_eq!(home.read("first.css"), "a { color: #222222; }\n");
+}
+
+#[test]
+fn targets_outside_the_allowed_roots_are_skipped() {
+    let home = Home::new("allowed-roots");
+    let inside = home.write("dots/a.conf", "fg=#2e3440\n");
+    let outside = home.write("other/b.conf", "fg=#2e3440\n");
+    let config = |color: &str| {
+        format!(
+            "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n{}\n[allowed_roots]\n{}\n[target_files]\n{}\n{}\n",
+            color,
+            home.path("dots").display(),
+            inside.display(),
+            outside.display()
+        )
+    };
+    home.config(&config("#88c0d0"));
+
+    let run = home.tran(Vec::<&str>::new());
+    assert_eq!(run.code(), Some(2), "{}", run.stderr());
+    assert!(run.stderr().contains("b.conf"), "{}", run.stderr());
+    assert_eq!(home.read("dots/a.conf"), "fg=#88c0d0\n");
+    assert_eq!(home.read("other/b.conf"), "fg=#2e3440\n");
+
+    home.config(&config("#bf616a"));
+    let run = home.tran(["--unsafe-allow-outside"]);
+    run.success();
+    assert_eq!(home.read("other/b.conf"), "fg=#bf616a\n");
+    assert!(
+        run.stderr().contains("WARNING: ignoring [allowed_roots]"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn filter_recolors_stdin_to_stdout() {
+    let home = Home::new("filter");
+    let run = home.tran_with_input(
+        ["filter", "--from", "#2e3440", "--to", "#88c0d0"],
+        b"bg=#2e3440\nfg=#eceff4\n",
+    );
+    run.success();
+    assert_eq!(run.stdout(), "bg=#88c0d0\nfg=#eceff4\n");
+    assert!(run.stderr().contains("1 changes"), "{}", run.stderr());
+
+    let config = "[mode]\ngradient\n[current_color]\n#2e3440\n[colors]\n#bf616a\n";
+    home.config(config);
+    let run = home.tran_with_input(["filter", "--use-config", "--seed", "1"], b"bg=#2e3440\n");
+    run.success();
+    assert_eq!(run.stdout(), "bg=#bf616a\n");
+    // Filtering previews the next pick without moving the config on to it
+    assert_eq!(std::fs::read_to_string(home.config_path()).unwrap(), config);
+
+    // Ini files are read with the keys the config names
+    home.config(&format!("{}[handlers]\nini_keys=accent\n", config));
+    let run = home.tran_with_input(
+        ["filter", "--use-config", "--seed", "1", "--format", "ini"],
+        b"[global]\naccent=#2e3440\nbackground=#2e3440\n",
+    );
+    run.success();
+    assert_eq!(
+        run.stdout(),
+        "[global]\naccent=#bf616a\nbackground=#2e3440\n"
+    );
+
+    home.tran_with_input(["filter", "--from", "#2e3440"], b"")
+        .failure();
+    home.tran_with_input(
+        [
+            "filter",
+            "--use-config",
+            "--from",
+            "#2e3440",
+            "--to",
+            "#88c0d0",
+        ],
+        b"",
+    )
+    .failure();
+}
+
+#[test]
+fn suggest_prints_and_adds_related_colors() {
+    let home = Home::new("suggest");
+    let run = home.tran(["suggest", "#ff0000", "--analogous", "1", "--spread", "60"]);
+    run.success();
+    assert_eq!(
+        run.stdout(),
+        "#ff0000 input\n#00ffff complementary\n#ff00ff analogous\n#00ff00 triadic\n#0000ff triadic\n"
+    );
+
+    home.config("[mode]\ngradient\n[current_color]\n#ff0000\n[colors]\n#00ff00\n");
+    home.tran(["suggest", "#ff0000", "--analogous", "0", "--add"])
+        .success();
+    let config = std::fs::read_to_string(home.config_path()).unwrap();
+    let colors: Vec<&str> = config
+        .split("[colors]\n")
+        .nth(1)
+        .unwrap()
+        .lines()
+        .take_while(|line| !line.starts_with('['))
+        .collect();
+    // Colors already in the config aren't added twice
+    assert_eq!(colors, ["#00ff00", "#00ffff", "#0000ff"]);
+
+    home.config("[mode]\nmap\n[current_color]\n#ff0000#00ff00\n[colors]\n#00ff00#ff0000\n");
+    let run = home.tran(["suggest", "#ff0000", "--add"]);
+    run.failure();
+    assert!(
+        run.stderr()
+            .contains("Rows in [colors] have 2 colors, suggest makes a row of 5"),
+        "{}",
+        run.stderr()
+    );
+    home.tran(["suggest"]).failure();
+}
+
+#[test]
+fn tran_color_sets_the_colors_of_a_run() {
+    let home = Home::new("tran-color");
+    let target = home.write("theme.css", "a { color: #111111; }\n");
+    home.config(&format!(
+        "[mode]\ngradient\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\n{}\n",
+        target.display()
+    ));
+
+    let mut command = home.command(Vec::<&str>::new());
+    command.env("TRAN_COLOR", "#abcdef");
+    assert!(command.status().unwrap().success());
+    assert_eq!(home.read("theme.css"), "a { color: #abcdef; }\n");
+    assert!(std::fs::read_to_string(home.config_path())
+        .unwrap()
+        .contains("[current_color]\n#abcdef\n"));
+
+    // It wins over the color a seed would pick
+    let mut command = home.command(["--seed", "1"]);
+    command.env("TRAN_COLOR", "#fedcba");
+    assert!(command.status().unwrap().success());
+    assert_eq!(home.read("theme.css"), "a { color: #fedcba; }\n");
+    let mut command = home.command(Vec::<&str>::new());
+    command.env("TRAN_COLOR", "#abcdef");
+    assert!(command.status().unwrap().success());
+
+    let mut command = home.command(Vec::<&str>::new());
+    command.env("TRAN_COLOR", "#123456#654321");
+    let run = command.output().unwrap();
+    assert!(!run.status.success());
+    assert!(String::from_utf8_lossy(&run.stderr).contains("gradient mode takes one"));
+    assert_eq!(home.read("theme.css"), "a { color: #abcdef; }\n");
+
+    // A value that is not utf-8 fails the run rather than being ignored
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut command = home.command(Vec::<&str>::new());
+        command.env("TRAN_COLOR", std::ffi::OsStr::from_bytes(b"#12345\xff"));
+        let run = command.output().unwrap();
+        assert!(!run.status.success());
+        assert!(String::from_utf8_lossy(&run.stderr).contains("not valid utf-8"));
+        assert_eq!(home.read("theme.css"), "a { color: #abcdef; }\n");
+    }
+
+    // In map mode it is a row as long as the current one, `-` keeping a slot
+    let home = Home::new("tran-color-map");
+    let target = home.write("theme.css", "a { color: #111111; background: #222222; }\n");
+    home.config(&format!(
+        "[mode]\nmap\n[current_color]\n#111111 #222222\n[colors]\n#333333 #444444\n[target_files]\n{}\n",
+        target.display()
+    ));
+    let mut command = home.command(Vec::<&str>::new());
+    command.env("TRAN_COLOR", "#abcdef -");
+    assert!(command.status().unwrap().success());
+    assert_eq!(
+        home.read("theme.css"),
+        "a { color: #abcdef; background: #222222; }\n"
+    );
+
+    let mut command = home.command(Vec::<&str>::new());
+    command.env("TRAN_COLOR", "#123456");
+    let run = command.output().unwrap();
+    assert!(!run.status.success());
+    assert!(String::from_utf8_lossy(&run.stderr)
+        .contains("it has 1 colors but rows in map mode have 2"));
+    assert_eq!(
+        home.read("theme.css"),
+        "a { color: #abcdef; background: #222222; }\n"
+    );
+}
+
+#[test]
+fn configs_piped_in_are_emitted_advanced() {
+    let home = Home::new("config-stdin");
+    let target = home.write("theme.css", "a { color: #111111; }\n");
+    let config = format!(
+        "[mode]\ngradient\n[overwrite]\nfalse\n[current_color]\n#111111\n[colors]\n#222222\n[target_files]\n{}\n",
+        target.display()
+    );
+
+    let run = home.tran_with_input(["--config", "-", "--emit-config"], config.as_bytes());
+    run.success();
+    assert_eq!(run.stdout(), config.replace("#111111", "#222222"));
+    assert_eq!(home.read("theme.css"), "a { color: #222222; }\n");
+    assert!(!home.config_path().exists());
+
+    // Nothing to pick, the config comes back as it went in
+    let advanced = run.stdout();
+    let run = home.tran_with_input(["--config", "-", "--emit-config"], advanced.as_bytes());
+    run.success();
+    assert_eq!(run.stdout(), advanced);
+
+    let run = home.tran_with_input(["--emit-config"], config.as_bytes());
+    run.failure();
+    assert!(run
+        .stderr()
+        .contains("--emit-config writes the config read with --config -"));
+}
+
+#[test]
+fn stale_targets_are_listed_and_pruned() {
+    let home = Home::new("prune-stale");
+    let stale = home.write("stale.css", "a { color: #123456; }\n");
+    let kept = home.write("kept.css", "a { color: #2e3440; }\n");
+    // Its only color is under a key the config adds to the ini handler
+    let dunstrc = home.write("dunstrc", "[global]\naccent=#2e3440\n");
+    home.config(&format!(
+        "[mode]\nmap\n[current_color]\n#2e3440\n[colors]\n#88c0d0\n[handlers]\nini_keys=accent\n[target_files]\n{}\n{}\n{}|format=ini\n",
+        stale.display(),
+        kept.display(),
+        dunstrc.display()
+    ));
+
+    let run = home.tran(["target"]);
+    run.success();
+    assert_eq!(
+        run.stdout(),
+        format!(
+            "stale      {}\n           {}\n           {}\n",
+            stale.display(),
+            kept.display(),
+            dunstrc.display()
+        )
+    );
+
+    let run = home.tran(Vec::<&str>::new());
+    run.success();
+    assert!(run.stderr().contains("STALE: "), "{}", run.stderr());
+
+    // Declined, nothing is removed
+    let run = home.tran_with_input(["target", "--prune-stale"], b"n\n");
+    run.success();
+    assert!(std::fs::read_to_string(home.config_path())
+        .unwrap()
+        .contains("stale.css"));
+
+    let run = home.tran(["target", "--prune-stale", "--yes"]);
+    run.success();
+    assert!(run.stderr().contains("Removed 1 target(s)"));
+    let config = std::fs::read_to_string(home.config_path()).unwrap();
+    assert!(!config.contains("stale.css"), "{}", config);
+    assert!(config.contains("kept.css"), "{}", config);
+    assert!(config.contains("dunstrc"), "{}", config);
+}
+
+#[test]
+fn targets_are_adopted_into_the_current_colors() {
+    let home = Home::new("adopt");
+    let behind = home.write("behind.css", "a { color: #bf616a; }\n");
+    let added = home.write("added.css", "a { color: #bf616a; }\n");
+    let config = home.config(&format!(
+        "[mode]\nmap\n[current_color]\n#2e3440\n[colors]\n#bf616a\n[target_files]\n{}\n",
+        behind.display()
+    ));
+
+    home.tran([std::ffi::OsStr::new("adopt"), behind.as_os_str()])
+        .success();
+    assert_eq!(home.read("behind.css"), "a { color: #2e3440; }\n");
+    assert!(std::fs::read_to_string(&config)
+        .unwrap()
+        .contains("[current_color]\n#2e3440\n"));
+
+    // Paths that are no target are refused until added
+    home.tran([std::ffi::OsStr::new("adopt"), added.as_os_str()])
+        .failure();
+    assert_eq!(home.read("added.css"), "a { color: #bf616a; }\n");
+
+    home.tran([
+        std::ffi::OsStr::new("target"),
+        std::ffi::OsStr::new("add"),
+        added.as_os_str(),
+        std::ffi::OsStr::new("--adopt"),
+    ])
+    .success();
+    assert_eq!(home.read("added.css"), "a { color: #2e3440; }\n");
+    let contents = std::fs::read_to_string(&config).unwrap();
+    assert_eq!(contents.matches("added.css").count(), 1, "{}", contents);
+}
@@ -0,0 +1,135 @@
+//! Builds tests/ffi/check.c against include/tran.h and runs it on the cdylib, so the header
+//! and the Rust side of the C interface can't drift apart.
+#![cfg(all(unix, feature = "ffi", feature = "png"))]
+
+use std::{collections::HashMap, path::PathBuf, process::Command};
+
+use tran::ffi::TranErrorCode;
+
+/// Every code the Rust side has, named as in the header.
+fn rust_codes() -> Vec<(&'static str, TranErrorCode)> {
+    use TranErrorCode::*;
+    let codes = [
+        ("TRAN_OK", Ok),
+        ("TRAN_CONFIG_ERROR", ConfigError),
+        ("TRAN_FILE_READ_ERROR", FileReadError),
+        ("TRAN_FILE_WRITE_ERROR", FileWriteError),
+        ("TRAN_FILE_NOT_FOUND_ERROR", FileNotFoundError),
+        ("TRAN_WRITING_CONFIG_ERROR", WritingConfigError),
+        ("TRAN_INVALID_HEX_COLOR", InvalidHexColor),
+        ("TRAN_PNG_FORMAT_ERROR", PngFormatError),
+        ("TRAN_UNSUPPORTED_ERROR", UnsupportedError),
+        ("TRAN_OUTSIDE_ALLOWED_ROOTS", OutsideAllowedRoots),
+        ("TRAN_INVALID_ARGUMENT", InvalidArgument),
+        ("TRAN_PANIC", Panic),
+        ("TRAN_OUTPUT_EXISTS", OutputExists),
+        ("TRAN_EXEC_ERROR", ExecError),
+        ("TRAN_MISSING_SECTION", MissingSection),
+        ("TRAN_INCONSISTENT_MODE", InconsistentMode),
+        ("TRAN_CANCELLED", Cancelled),
+    ];
+    // A new code fails to build here until it is listed above and in check.c
+    for (_, code) in codes {
+        match code {
+            Ok | ConfigError | FileReadError | FileWriteError | FileNotFoundError
+            | WritingConfigError | InvalidHexColor | PngFormatError | UnsupportedError
+            | OutsideAllowedRoots | InvalidArgument | Panic | OutputExists | ExecError
+            | MissingSection | InconsistentMode | Cancelled => {}
+        }
+    }
+    codes.to_vec()
+}
+
+/// The output of check.c, compiled with `cc` and linked to the cdylib of this build.
+fn run_check() -> String {
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // Test binaries are in target/<profile>/deps, next to the cdylib
+    let deps = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let library = deps.join(format!(
+        "{}tran{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ));
+    assert!(library.is_file(), "no cdylib at {}", library.display());
+
+    let program = deps.join(format!("ffi-check-{}", std::process::id()));
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg(manifest.join("tests/ffi/check.c"))
+        .arg(&library)
+        .arg(format!("-Wl,-rpath,{}", deps.display()))
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("cc has to be installed to check the C interface");
+    assert!(status.success(), "check.c doesn't build against tran.h");
+
+    let output = Command::new(&program).output().unwrap();
+    let _ = std::fs::remove_file(&program);
+    assert!(
+        output.status.success(),
+        "check.c failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The words of the line of `output` starting with `name`.
+fn line<'a>(output: &'a str, name: &str) -> Vec<&'a str> {
+    let line = output
+        .lines()
+        .find(|line| line.split(' ').next() == Some(name))
+        .unwrap_or_else(|| panic!("no {} line in\n{}", name, output));
+    line.split(' ').skip(1).collect()
+}
+
+#[test]
+fn header_matches_the_rust_side() {
+    let output = run_check();
+
+    let header: HashMap<&str, i32> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix("code "))
+        .map(|line| {
+            let (name, value) = line.split_once(' ').unwrap();
+            (name, value.parse().unwrap())
+        })
+        .collect();
+    let rust = rust_codes();
+    assert_eq!(header.len(), rust.len());
+    for (name, code) in rust {
+        assert_eq!(header.get(name), Some(&(code as i32)), "{}", name);
+    }
+
+    // code, out_len, strlen(out), the length prefix tran_free reads, then the text
+    let text = line(&output, "text");
+    let ok = (TranErrorCode::Ok as i32).to_string();
+    assert_eq!(text[0], ok);
+    assert_eq!(text[1], "12");
+    assert_eq!(text[2], "12");
+    // Counts the NUL after the text, which freeing has to take back too
+    assert_eq!(text[3], "13");
+    assert_eq!(text[4], "bg=#bf616a;");
+
+    let bad = line(&output, "bad_color");
+    assert_eq!(bad[0], (TranErrorCode::InvalidHexColor as i32).to_string());
+    assert_eq!(bad[1], "1", "nothing is handed out on errors");
+
+    let null = line(&output, "null_text");
+    assert_eq!(null[0], (TranErrorCode::InvalidArgument as i32).to_string());
+    assert_eq!(
+        null[1..].join(" "),
+        "text, out and out_len must not be null"
+    );
+
+    let png = line(&output, "not_png");
+    assert_eq!(png[0], (TranErrorCode::PngFormatError as i32).to_string());
+    assert_eq!(png[1], "1");
+}
@@ -0,0 +1,63 @@
+/* Calls tran through include/tran.h, run by tests/ffi.rs against the cdylib. Prints what the
+ * Rust side checks: the value of every error code, then one line per call. */
+#include <stdio.h>
+#include <string.h>
+
+#include "tran.h"
+
+#define CODE(name) printf("code %s %d\n", #name, (int)name)
+
+/* The length tran_free reads, kept in the size_t before the buffer. */
+static size_t prefix(const void *buffer) {
+    size_t len;
+    memcpy(&len, (const unsigned char *)buffer - sizeof(size_t), sizeof(size_t));
+    return len;
+}
+
+static const char *message(void) {
+    const char *message = tran_last_error_message();
+    return message ? message : "(null)";
+}
+
+int main(void) {
+    CODE(TRAN_OK);
+    CODE(TRAN_CONFIG_ERROR);
+    CODE(TRAN_FILE_READ_ERROR);
+    CODE(TRAN_FILE_WRITE_ERROR);
+    CODE(TRAN_FILE_NOT_FOUND_ERROR);
+    CODE(TRAN_WRITING_CONFIG_ERROR);
+    CODE(TRAN_INVALID_HEX_COLOR);
+    CODE(TRAN_PNG_FORMAT_ERROR);
+    CODE(TRAN_UNSUPPORTED_ERROR);
+    CODE(TRAN_OUTSIDE_ALLOWED_ROOTS);
+    CODE(TRAN_INVALID_ARGUMENT);
+    CODE(TRAN_PANIC);
+    CODE(TRAN_OUTPUT_EXISTS);
+    CODE(TRAN_EXEC_ERROR);
+    CODE(TRAN_MISSING_SECTION);
+    CODE(TRAN_INCONSISTENT_MODE);
+    CODE(TRAN_CANCELLED);
+
+    const char *from[] = {"#2e3440"};
+    const char *to[] = {"#bf616a"};
+    char *out = NULL;
+    size_t out_len = 0;
+    tran_error_code code = tran_recolor_text("bg=#2e3440;\n", from, to, 1, &out, &out_len);
+    printf("text %d %zu %zu %zu %s", (int)code, out_len, strlen(out), prefix(out), out);
+    tran_free(out);
+    tran_free(NULL);
+
+    const char *bad[] = {"#2e34"};
+    out = NULL;
+    code = tran_recolor_text("bg=#2e3440;\n", bad, to, 1, &out, &out_len);
+    printf("bad_color %d %d %s\n", (int)code, out == NULL, message());
+
+    code = tran_recolor_text(NULL, from, to, 1, &out, &out_len);
+    printf("null_text %d %s\n", (int)code, message());
+
+    const uint8_t not_png[] = {1, 2, 3, 4, 5, 6, 7, 8, 9, 10};
+    uint8_t *png = NULL;
+    code = tran_recolor_png_bytes(not_png, sizeof not_png, from, to, 1, &png, &out_len);
+    printf("not_png %d %d %s\n", (int)code, png == NULL, message());
+    return 0;
+}
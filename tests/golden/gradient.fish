@@ -0,0 +1,18 @@
+set -x TRAN_COLOR0 '#88c0d0'
+set -x TRAN_COLOR1 '#7fb3c2'
+set -x TRAN_COLOR2 '#76a6b4'
+set -x TRAN_COLOR3 '#6d9aa6'
+set -x TRAN_COLOR4 '#648d99'
+set -x TRAN_COLOR5 '#5b808b'
+set -x TRAN_COLOR6 '#52737d'
+set -x TRAN_COLOR7 '#49666f'
+set -x TRAN_COLOR8 '#3f5a61'
+set -x TRAN_COLOR9 '#364d53'
+set -x TRAN_COLOR10 '#2d4045'
+set -x TRAN_COLOR11 '#243337'
+set -x TRAN_COLOR12 '#1b262a'
+set -x TRAN_COLOR13 '#121a1c'
+set -x TRAN_COLOR14 '#090d0e'
+set -x TRAN_COLOR15 '#000000'
+set -x TRAN_PRIMARY '#88c0d0'
+set -x TRAN_BACKGROUND '#000000'
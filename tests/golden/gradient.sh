@@ -0,0 +1,18 @@
+export TRAN_COLOR0='#88c0d0'
+export TRAN_COLOR1='#7fb3c2'
+export TRAN_COLOR2='#76a6b4'
+export TRAN_COLOR3='#6d9aa6'
+export TRAN_COLOR4='#648d99'
+export TRAN_COLOR5='#5b808b'
+export TRAN_COLOR6='#52737d'
+export TRAN_COLOR7='#49666f'
+export TRAN_COLOR8='#3f5a61'
+export TRAN_COLOR9='#364d53'
+export TRAN_COLOR10='#2d4045'
+export TRAN_COLOR11='#243337'
+export TRAN_COLOR12='#1b262a'
+export TRAN_COLOR13='#121a1c'
+export TRAN_COLOR14='#090d0e'
+export TRAN_COLOR15='#000000'
+export TRAN_PRIMARY='#88c0d0'
+export TRAN_BACKGROUND='#000000'
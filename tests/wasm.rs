@@ -0,0 +1,43 @@
+//! The core has to build for wasm32-unknown-unknown, without the `fs` feature and so without
+//! filesystem, env or clock access. Where that target isn't installed the core is checked for
+//! the host without `fs` instead, which catches everything but the clock.
+
+use std::process::Command;
+
+#[test]
+fn the_core_builds_without_fs() {
+    let wasm = Command::new("rustc")
+        .args([
+            "--print",
+            "target-libdir",
+            "--target",
+            "wasm32-unknown-unknown",
+        ])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|dir| std::path::Path::new(dir).is_dir());
+    let mut command = Command::new(option_env!("CARGO").unwrap_or("cargo"));
+    command
+        .args(["check", "--quiet", "--lib", "--no-default-features"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        // Apart from the build running this test, which holds the lock on its own
+        .env(
+            "CARGO_TARGET_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/target/core-check"),
+        );
+    match wasm {
+        Some(_) => command.args(["--target", "wasm32-unknown-unknown"]),
+        None => {
+            eprintln!("wasm32-unknown-unknown isn't installed, checking the core for the host");
+            &mut command
+        }
+    };
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}